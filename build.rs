@@ -0,0 +1,51 @@
+//! Embeds the git hash & the time of the build into the firmware, see `src/build_info.rs`. For the
+//! `firmware-update` feature it adds the linker script which checks the size of the firmware &
+//! links it for slot B if `FIRMWARE_SLOT=b` is set (see `memory.x`).
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // only the hash, marked with `-dirty` if there are uncommitted changes
+    let git_hash = Command::new("git")
+        .args([
+            "describe",
+            "--always",
+            "--dirty",
+            "--abbrev=8",
+            "--exclude=*",
+        ])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+    // reproducible builds set the time of the last commit instead
+    let build_s = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs())
+        });
+    println!("cargo:rustc-env=GIT_HASH={git_hash}");
+    println!("cargo:rustc-env=BUILD_UNIX_S={build_s}");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // not for the host tests, which are linked without the memory layout of the board
+    let bare_metal = std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("none");
+    if bare_metal && std::env::var_os("CARGO_FEATURE_FIRMWARE_UPDATE").is_some() {
+        println!("cargo:rustc-link-arg=-Tfirmware-update.x");
+        println!("cargo:rerun-if-changed=firmware-update.x");
+        match std::env::var("FIRMWARE_SLOT").as_deref() {
+            Ok("b") => println!("cargo:rustc-link-arg=--defsym=__firmware_slot_b=1"),
+            Ok("a") | Err(_) => {}
+            Ok(slot) => panic!("unknown firmware slot {slot:?}, use `a` or `b`"),
+        }
+        println!("cargo:rerun-if-env-changed=FIRMWARE_SLOT");
+    }
+}
@@ -0,0 +1,4 @@
+/* The `firmware-update` feature switches between two slots of 128K (the sectors 0 - 4 & sector 5),
+   thus the firmware together with its CRC (see `tof-host sign`) must fit into one of them. */
+ASSERT(__sidata + SIZEOF(.data) + 4 <= ORIGIN(FLASH) + 128K,
+       "the firmware doesn't fit into a slot of 128K, disable some features");
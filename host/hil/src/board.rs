@@ -0,0 +1,145 @@
+//! The board under test: the firmware runs under `probe-rs run`, which prints the defmt output,
+//! & the commands & the telemetry go over the virtual COM port of the ST-LINK.
+
+use serialport::SerialPort;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The chip of the Nucleo, as named by probe-rs.
+const CHIP: &str = "STM32F401RE";
+/// Baud rate of the virtual COM port, see `src/uart.rs` of the firmware.
+const BAUD_RATE: u32 = 115_200;
+/// Timeout of a single read from the virtual COM port.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+/// Time within which the firmware responds to a command.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct Board {
+    probe_rs: Child,
+    /// The lines of the defmt output, received by a thread reading the output of probe-rs.
+    log_lines: Receiver<String>,
+    /// All lines of the defmt output received so far.
+    log: Vec<String>,
+    /// The lines of the defmt output which have already been searched by [`Board::wait_for_log`].
+    log_seen: usize,
+    port: BufReader<Box<dyn SerialPort>>,
+    /// The incomplete line read from the port so far.
+    line: String,
+}
+
+impl Board {
+    /// Flash & run the firmware (an ELF file) & open the virtual COM port.
+    pub fn flash(firmware: &str, port: &str) -> io::Result<Self> {
+        let mut probe_rs = Command::new("probe-rs")
+            .args(["run", "--chip", CHIP, firmware])
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let output = probe_rs.stdout.take().expect("stdout is piped");
+        let (sender, log_lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(output).lines().map_while(Result::ok) {
+                eprintln!("  | {line}");
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let port = serialport::new(port, BAUD_RATE)
+            .timeout(READ_TIMEOUT)
+            .open()?;
+        Ok(Self {
+            probe_rs,
+            log_lines,
+            log: Vec::new(),
+            log_seen: 0,
+            port: BufReader::new(port),
+            line: String::new(),
+        })
+    }
+
+    /// Wait for a line of the defmt output which contains `pattern` & hasn't been returned yet.
+    pub fn wait_for_log(&mut self, pattern: &str, timeout: Duration) -> Result<String, String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(index) = self.log[self.log_seen..]
+                .iter()
+                .position(|line| line.contains(pattern))
+            {
+                self.log_seen += index + 1;
+                return Ok(self.log[self.log_seen - 1].clone());
+            }
+            self.log_seen = self.log.len();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.log_lines.recv_timeout(remaining) {
+                Ok(line) => self.log.push(line),
+                Err(_) => return Err(format!("no log message with \"{pattern}\"")),
+            }
+        }
+    }
+
+    /// The lines of the defmt output with the level `ERROR` so far.
+    pub fn errors(&mut self) -> Vec<String> {
+        self.log.extend(self.log_lines.try_iter());
+        self.log
+            .iter()
+            .filter(|line| line.trim_start().starts_with("ERROR"))
+            .cloned()
+            .collect()
+    }
+
+    /// Send a command & return its response (`OK ...` or `ERR ...`), the telemetry frames
+    /// received meanwhile are skipped.
+    pub fn command(&mut self, command: &str) -> Result<String, String> {
+        write!(self.port.get_mut(), "{command}\r\n").map_err(|e| e.to_string())?;
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Some(line) = self.read_line()? {
+                if line.starts_with("OK") || line.starts_with("ERR") {
+                    return Ok(line);
+                }
+            }
+        }
+        Err(format!("no response to \"{command}\""))
+    }
+
+    /// Wait for the next telemetry frame of the type (e.g. `D` for measurements) & return its
+    /// fields (including the type).
+    pub fn frame(&mut self, kind: &str, timeout: Duration) -> Result<Vec<String>, String> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Some(line) = self.read_line()? {
+                let fields: Vec<String> = line.split(',').map(str::to_owned).collect();
+                if fields[0] == kind {
+                    return Ok(fields);
+                }
+            }
+        }
+        Err(format!("no \"{kind}\" frame"))
+    }
+
+    /// A complete line (without the line ending) or `None` if none has been received within the
+    /// read timeout.
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        // an incomplete line stays in the buffer until the rest of it has been received
+        match self.port.read_line(&mut self.line) {
+            Ok(_) if self.line.ends_with('\n') => {
+                let line = self.line.trim_end().to_owned();
+                self.line.clear();
+                Ok(Some(line))
+            }
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+impl Drop for Board {
+    fn drop(&mut self) {
+        self.probe_rs.kill().ok();
+        self.probe_rs.wait().ok();
+    }
+}
@@ -0,0 +1,188 @@
+//! Hardware-in-the-loop tests of the whole firmware, for regression tests on a Nucleo with the TOF
+//! sensor attached.
+//!
+//! `tof-hil [--unattended] <firmware.elf> <port>` flashes the firmware with `probe-rs run` (which
+//! needs to be installed), captures its defmt output & runs the checks over the virtual COM port
+//! `<port>` (e.g. `/dev/ttyACM0`). The presence check needs somebody to move a target in front of
+//! the sensor & is skipped with `--unattended`.
+
+mod board;
+
+use board::Board;
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// Time within which the firmware has to boot after flashing it.
+const BOOT_TIMEOUT: Duration = Duration::from_secs(20);
+/// Time within which the next measurement has to arrive while ranging.
+const MEASUREMENT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Number of measurements which are checked.
+const MEASUREMENTS: usize = 10;
+/// The longest distance the sensor measures (in the long distance mode).
+const MAX_DISTANCE_MM: u32 = 4_000;
+/// Time given to the operator to move a target in front of the sensor.
+const PRESENCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A check of the behaviour of the firmware, `Err` describes the failure.
+type Check = fn(&mut Board) -> Result<(), String>;
+
+/// The checks run unattended, in this order.
+const CHECKS: &[(&str, Check)] = &[
+    ("boot", boot),
+    ("help", help),
+    ("unknown command", unknown_command),
+    ("status", status),
+    ("measurements", measurements),
+    ("stop & start", stop_start),
+];
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (unattended, args) = match args.split_first() {
+        Some((flag, rest)) if flag == "--unattended" => (true, rest),
+        _ => (false, &args[..]),
+    };
+    let [firmware, port] = args else {
+        eprintln!("usage: tof-hil [--unattended] <firmware.elf> <port>");
+        return ExitCode::FAILURE;
+    };
+    let mut board = match Board::flash(firmware, port) {
+        Ok(board) => board,
+        Err(e) => {
+            eprintln!("failed to start the firmware: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut checks = CHECKS.to_vec();
+    if !unattended {
+        checks.push(("presence event", presence_event));
+    }
+    let mut failures = 0;
+    for (name, check) in checks {
+        match check(&mut board) {
+            Ok(()) => println!("PASS {name}"),
+            Err(e) => {
+                println!("FAIL {name}: {e}");
+                failures += 1;
+            }
+        }
+    }
+    // errors are logged e.g. if the sensor fails, even though the firmware recovers
+    let errors = board.errors();
+    if errors.is_empty() {
+        println!("PASS no errors logged");
+    } else {
+        println!("FAIL {} errors logged: {:?}", errors.len(), errors);
+        failures += 1;
+    }
+
+    if failures == 0 {
+        ExitCode::SUCCESS
+    } else {
+        println!("{failures} checks failed");
+        ExitCode::FAILURE
+    }
+}
+
+fn boot(board: &mut Board) -> Result<(), String> {
+    board.wait_for_log("init done!", BOOT_TIMEOUT)?;
+    // the frames are parsed as CSV, whatever the stored setting is
+    expect_ok(board, "format csv")?;
+    Ok(())
+}
+
+fn help(board: &mut Board) -> Result<(), String> {
+    let response = expect_ok(board, "help")?;
+    for command in ["start", "stop", "status"] {
+        if !response.contains(command) {
+            return Err(format!("\"{command}\" isn't listed in \"{response}\""));
+        }
+    }
+    Ok(())
+}
+
+fn unknown_command(board: &mut Board) -> Result<(), String> {
+    let response = board.command("frobnicate")?;
+    if response.starts_with("ERR") {
+        Ok(())
+    } else {
+        Err(format!("accepted: \"{response}\""))
+    }
+}
+
+fn status(board: &mut Board) -> Result<(), String> {
+    let response = expect_ok(board, "status")?;
+    for field in ["ranging=1", "safe_mode=0", "image=ok"] {
+        if !response.split_ascii_whitespace().any(|word| word == field) {
+            return Err(format!("no \"{field}\" in \"{response}\""));
+        }
+    }
+    Ok(())
+}
+
+/// The measurements arrive in sequence & the valid ones are within the range of the sensor.
+fn measurements(board: &mut Board) -> Result<(), String> {
+    let mut previous_seq = None;
+    for _ in 0..MEASUREMENTS {
+        let frame = board.frame("D", MEASUREMENT_TIMEOUT)?;
+        let field = |index: usize| -> Result<u32, String> {
+            frame
+                .get(index)
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| format!("invalid frame {frame:?}"))
+        };
+        let (seq, distance_mm, status) = (field(1)?, field(3)?, field(4)?);
+        if previous_seq.is_some_and(|previous: u32| seq != previous.wrapping_add(1)) {
+            return Err(format!("measurement {seq} after {previous_seq:?}"));
+        }
+        previous_seq = Some(seq);
+        // status 0 is a valid measurement
+        if status == 0 && distance_mm > MAX_DISTANCE_MM {
+            return Err(format!("implausible distance {distance_mm} mm"));
+        }
+    }
+    Ok(())
+}
+
+fn stop_start(board: &mut Board) -> Result<(), String> {
+    expect_ok(board, "stop")?;
+    // a measurement may have been on its way while stopping
+    board.frame("D", Duration::from_millis(200)).ok();
+    if let Ok(frame) = board.frame("D", MEASUREMENT_TIMEOUT) {
+        return Err(format!("measurement {frame:?} after stopping"));
+    }
+    let response = expect_ok(board, "status")?;
+    if !response.contains("ranging=0") {
+        return Err(format!("still ranging: \"{response}\""));
+    }
+    expect_ok(board, "start")?;
+    board.frame("D", MEASUREMENT_TIMEOUT)?;
+    Ok(())
+}
+
+/// A target moved in front of the sensor generates a presence event, which is logged.
+fn presence_event(board: &mut Board) -> Result<(), String> {
+    println!(
+        "move a target in front of the sensor (closer than the presence threshold) & away again \
+         within {} s",
+        PRESENCE_TIMEOUT.as_secs()
+    );
+    board.wait_for_log("event: ", PRESENCE_TIMEOUT)?;
+    let response = expect_ok(board, "events 1")?;
+    if response.contains("present") || response.contains("gone") {
+        Ok(())
+    } else {
+        Err(format!("the event isn't logged: \"{response}\""))
+    }
+}
+
+/// Send the command & check that it has succeeded.
+fn expect_ok(board: &mut Board, command: &str) -> Result<String, String> {
+    let response = board.command(command)?;
+    if response.starts_with("OK") {
+        Ok(response)
+    } else {
+        Err(format!("\"{command}\" failed: \"{response}\""))
+    }
+}
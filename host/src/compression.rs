@@ -0,0 +1,159 @@
+//! Decompression of the output of `dump compressed`, the counterpart of
+//! `src/log_compression.rs` of the firmware which documents the format.
+
+use firmware::log_compression::MIN_MATCH;
+use firmware::storage::crc32;
+
+/// The compressed bytes of the `Z,<base64>,<crc>` lines of the text (other lines, e.g. other
+/// output of the firmware which has been captured as well, are skipped). Fails at the first line
+/// which has been corrupted.
+pub fn read_chunks(text: &str) -> Result<Vec<u8>, String> {
+    let mut compressed = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let Some(chunk) = line.trim_end().strip_prefix("Z,") else {
+            continue;
+        };
+        let bytes = chunk
+            .split_once(',')
+            .and_then(|(base64, crc)| {
+                let bytes = decode_base64(base64)?;
+                let crc = u32::from_str_radix(crc, 16).ok()?;
+                (crc32(&bytes) == crc).then_some(bytes)
+            })
+            .ok_or_else(|| format!("line {} is corrupt", number + 1))?;
+        compressed.extend_from_slice(&bytes);
+    }
+    Ok(compressed)
+}
+
+/// Decompress the LZSS stream.
+pub fn inflate(compressed: &[u8]) -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    let mut bytes = compressed.iter().copied();
+    while let Some(control) = bytes.next() {
+        for token in 0..8 {
+            let Some(byte) = bytes.next() else {
+                break;
+            };
+            if control & 1 << token == 0 {
+                output.push(byte);
+                continue;
+            }
+            let distance = byte as usize + 1;
+            let len = bytes.next().ok_or("truncated match")? as usize + MIN_MATCH;
+            if distance > output.len() {
+                return Err(format!("invalid match at byte {}", output.len()));
+            }
+            for _ in 0..len {
+                output.push(output[output.len() - distance]);
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Split the decompressed stream into the records of the log with their CRC, which has been
+/// removed for the compression.
+pub fn restore_records(stream: &[u8]) -> Result<Vec<u8>, String> {
+    let mut records = Vec::with_capacity(stream.len() * 6 / 5);
+    let mut offset = 0;
+    while let Some(&first) = stream.get(offset) {
+        let len = (first & 0x1F) as usize;
+        let value = stream
+            .get(offset..offset + len)
+            .filter(|_| len > 0)
+            .ok_or_else(|| format!("truncated record at byte {offset}"))?;
+        records.extend_from_slice(value);
+        records.push(crc32(value) as u8);
+        offset += len;
+    }
+    Ok(records)
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for symbol in text.bytes() {
+        let value = match symbol {
+            b'A'..=b'Z' => symbol - b'A',
+            b'a'..=b'z' => symbol - b'a' + 26,
+            b'0'..=b'9' => symbol - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firmware::log_compression::{chunk_line, Compressor};
+    use firmware::log_record::{Chain, Decoded, Record};
+
+    fn records() -> Vec<Record> {
+        (1..=300)
+            .map(|seq| Record {
+                seq,
+                timestamp_ms: seq * 100 + seq % 3,
+                utc_ms: (seq > 100).then_some(1_760_000_000_000 + seq as u64 * 100),
+                distance_mm: 500 + (seq % 7) as u16,
+                status: seq.is_multiple_of(50) as u8,
+            })
+            .collect()
+    }
+
+    /// The output of `dump compressed` of the firmware for the records.
+    fn capture(records: &[Record]) -> String {
+        let mut compressor = Compressor::new();
+        let mut capture = String::from("OK dump compressed\r\n");
+        let mut pending = records.iter();
+        while !compressor.is_done() {
+            match compressor.next_chunk() {
+                Some(chunk) => capture.push_str(&chunk_line(&chunk)),
+                None => match pending.next() {
+                    Some(record) => compressor.push(record),
+                    None => compressor.finish(),
+                },
+            }
+        }
+        capture
+    }
+
+    #[test]
+    fn inflates_the_records_compressed_by_the_firmware() {
+        let records = records();
+        let compressed = read_chunks(&capture(&records)).unwrap();
+        let stream = restore_records(&inflate(&compressed).unwrap()).unwrap();
+        let (mut chain, mut offset) = (Chain::new(), 0);
+        for record in &records {
+            let Decoded::Record(decoded, len) = chain.decode(&stream[offset..]) else {
+                panic!("no record at byte {offset}");
+            };
+            assert_eq!(decoded, *record);
+            offset += len;
+        }
+        assert_eq!(offset, stream.len());
+    }
+
+    #[test]
+    fn fails_at_a_corrupt_line() {
+        let capture = capture(&records()).replacen("Z,A", "Z,B", 1);
+        assert_eq!(read_chunks(&capture), Err("line 2 is corrupt".to_owned()));
+    }
+
+    #[test]
+    fn fails_at_a_match_before_the_start() {
+        // a single token, a match 2 bytes back
+        assert!(inflate(&[0x01, 0x01, 0x00]).is_err());
+        assert_eq!(inflate(&[0x04, b'a', b'b', 0x01, 0x00]).unwrap(), b"ababa");
+    }
+}
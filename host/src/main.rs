@@ -0,0 +1,275 @@
+//! Companion tool for the firmware which runs on the host.
+//!
+//! `tof-host decode <file>...` decodes the measurement log stored on the NOR flash & prints the
+//! measurements in the format of the `dump` command. A file is either an image of the flash with
+//! the ring of the `nor-flash` feature (e.g. read out with a programmer) or a log file of the
+//! `littlefs` feature.
+//!
+//! `tof-host inflate <capture>...` decompresses the output of `dump compressed` (e.g. captured
+//! with a terminal program) & prints the measurements in the same format.
+//!
+//! `tof-host sign <firmware.bin>` appends the CRC of the image to a binary of the firmware, which
+//! is checked at boot (see `src/image_check.rs` of the firmware).
+
+mod compression;
+
+use firmware::log_record::{Chain, Decoded};
+use std::process::ExitCode;
+
+/// Length of a sector of the ring.
+const SECTOR_LEN: usize = 4096;
+/// Length of the header of a sector.
+const HEADER_LEN: usize = 16;
+/// Marks the header of a sector, `FL` followed by the version of the layout.
+const MAGIC: &[u8] = b"FL02";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.split_first() {
+        Some((command, [file])) if command == "sign" => match sign(file) {
+            Ok(crc) => {
+                eprintln!("{file}: appended CRC {crc:08x}");
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("{file}: {e}");
+                ExitCode::FAILURE
+            }
+        },
+        Some((command, files)) if command == "decode" && !files.is_empty() => {
+            for file in files {
+                if let Err(e) = decode(file) {
+                    eprintln!("{file}: {e}");
+                    return ExitCode::FAILURE;
+                }
+            }
+            ExitCode::SUCCESS
+        }
+        Some((command, files)) if command == "inflate" && !files.is_empty() => {
+            for file in files {
+                if let Err(e) = inflate(file) {
+                    eprintln!("{file}: {e}");
+                    return ExitCode::FAILURE;
+                }
+            }
+            ExitCode::SUCCESS
+        }
+        _ => {
+            eprintln!(
+                "usage: tof-host decode <file>...\n       tof-host inflate <capture>...\n       tof-host sign <firmware.bin>"
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn decode(path: &str) -> std::io::Result<()> {
+    let data = std::fs::read(path)?;
+    let chains = if is_flash_image(&data) {
+        if let Some(device) = device_id(&data) {
+            eprintln!("{path}: device {device:08x}");
+        }
+        flash_sectors(&data)
+    } else {
+        vec![&data[..]]
+    };
+    let (mut records, mut corrupt) = (0, 0);
+    for chain in chains {
+        for decoded in decode_all(chain) {
+            match decoded {
+                Decoded::Record(record, _) => {
+                    println!("{}", record.line().trim_end());
+                    records += 1;
+                }
+                Decoded::Corrupt(_) => corrupt += 1,
+                Decoded::Incomplete | Decoded::End => {}
+            }
+        }
+    }
+    eprintln!("{path}: {records} records, {corrupt} corrupt ones skipped");
+    Ok(())
+}
+
+fn inflate(path: &str) -> Result<(), String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let compressed = compression::read_chunks(&text)?;
+    let records = compression::restore_records(&compression::inflate(&compressed)?)?;
+    let (mut count, mut corrupt) = (0, 0);
+    for decoded in decode_all(&records) {
+        match decoded {
+            Decoded::Record(record, _) => {
+                println!("{}", record.line().trim_end());
+                count += 1;
+            }
+            Decoded::Corrupt(_) => corrupt += 1,
+            Decoded::Incomplete | Decoded::End => {}
+        }
+    }
+    eprintln!(
+        "{path}: {count} records from {} compressed bytes, {corrupt} corrupt ones skipped",
+        compressed.len()
+    );
+    Ok(())
+}
+
+/// Decode all records of a chain (a sector of the flash or a log file), it ends at the erased
+/// flash or at a truncated record.
+fn decode_all(bytes: &[u8]) -> Vec<Decoded> {
+    let mut chain = Chain::new();
+    let mut decoded = Vec::new();
+    let mut offset = 0;
+    loop {
+        let result = chain.decode(&bytes[offset..]);
+        decoded.push(result);
+        match result {
+            Decoded::Record(_, len) | Decoded::Corrupt(len) => offset += len,
+            Decoded::Incomplete | Decoded::End => return decoded,
+        }
+    }
+}
+
+/// Append the CRC of the image to the binary. Must only be done once, the CRC would be part of
+/// the image otherwise.
+fn sign(path: &str) -> std::io::Result<u32> {
+    let mut data = std::fs::read(path)?;
+    if !data.len().is_multiple_of(4) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "the length of the image isn't a multiple of 4 bytes",
+        ));
+    }
+    let crc = crc32_mpeg2(&data);
+    data.extend_from_slice(&crc.to_le_bytes());
+    std::fs::write(path, data)?;
+    Ok(crc)
+}
+
+/// CRC-32/MPEG-2 over the little endian words, as calculated by the CRC peripheral of the STM32.
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for word in data.chunks_exact(4) {
+        crc ^= u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+        for _ in 0..32 {
+            crc = if crc & 0x8000_0000 != 0 {
+                crc << 1 ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn is_flash_image(data: &[u8]) -> bool {
+    data.len().is_multiple_of(SECTOR_LEN)
+        && data
+            .chunks(SECTOR_LEN)
+            .any(|sector| sector.starts_with(MAGIC))
+}
+
+/// The device ID of the board which wrote the newest sector, `None` for sectors written by an
+/// older firmware.
+fn device_id(data: &[u8]) -> Option<u32> {
+    data.chunks(SECTOR_LEN)
+        .filter(|sector| sector.starts_with(MAGIC))
+        .max_by_key(|sector| u32::from_le_bytes([sector[4], sector[5], sector[6], sector[7]]))
+        .map(|sector| u32::from_le_bytes([sector[8], sector[9], sector[10], sector[11]]))
+        .filter(|device| *device != u32::MAX)
+}
+
+/// The records of each sector of the ring, from the oldest to the newest sector.
+fn flash_sectors(data: &[u8]) -> Vec<&[u8]> {
+    let mut sectors: Vec<(u32, &[u8])> = data
+        .chunks(SECTOR_LEN)
+        .filter(|sector| sector.starts_with(MAGIC))
+        .map(|sector| {
+            let seq = u32::from_le_bytes([sector[4], sector[5], sector[6], sector[7]]);
+            (seq, &sector[HEADER_LEN..])
+        })
+        .collect();
+    sectors.sort_by_key(|(seq, _)| *seq);
+    sectors.into_iter().map(|(_, records)| records).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firmware::log_record::Record;
+
+    fn record(seq: u32) -> Record {
+        Record {
+            seq,
+            timestamp_ms: seq * 50,
+            utc_ms: seq
+                .is_multiple_of(2)
+                .then_some(1_760_000_000_000 + seq as u64 * 50),
+            distance_mm: 1_000 - seq as u16,
+            status: seq.is_multiple_of(5) as u8,
+        }
+    }
+
+    /// A sector of the ring with the records as encoded by the firmware.
+    fn sector(seq: u32, device: u32, records: &[Record]) -> Vec<u8> {
+        let mut sector = vec![0xFF; SECTOR_LEN];
+        sector[..4].copy_from_slice(MAGIC);
+        sector[4..8].copy_from_slice(&seq.to_le_bytes());
+        sector[8..12].copy_from_slice(&device.to_le_bytes());
+        let (mut chain, mut offset) = (Chain::new(), HEADER_LEN);
+        for record in records {
+            let encoded = chain.encode(record);
+            sector[offset..offset + encoded.len()].copy_from_slice(&encoded);
+            offset += encoded.len();
+        }
+        sector
+    }
+
+    fn decoded_records(chain: &[u8]) -> Vec<Record> {
+        decode_all(chain)
+            .into_iter()
+            .filter_map(|decoded| match decoded {
+                Decoded::Record(record, _) => Some(record),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_the_sectors_of_a_flash_image_in_order() {
+        let (older, newer): (Vec<_>, Vec<_>) = (1..=40).map(record).partition(|r| r.seq <= 20);
+        let mut image = sector(8, 0x5f3a91c2, &newer);
+        image.extend(sector(7, 0x5f3a91c2, &older));
+        image.extend(vec![0xFF; SECTOR_LEN]);
+        assert!(is_flash_image(&image));
+        assert_eq!(device_id(&image), Some(0x5f3a91c2));
+
+        let chains = flash_sectors(&image);
+        assert_eq!(chains.len(), 2);
+        assert_eq!(decoded_records(chains[0]), older);
+        assert_eq!(decoded_records(chains[1]), newer);
+    }
+
+    #[test]
+    fn skips_the_corrupt_records_of_a_log_file() {
+        let mut chain = Chain::new();
+        let mut file = Vec::new();
+        let mut starts = Vec::new();
+        for seq in 1..=3 {
+            starts.push(file.len());
+            file.extend_from_slice(&chain.encode(&record(seq)));
+        }
+        // e.g. interrupted by a loss of power
+        file[starts[1] + 2] ^= 0x10;
+        let decoded = decode_all(&file);
+        assert!(matches!(decoded[..], [
+            Decoded::Record(first, _),
+            Decoded::Corrupt(_),
+            Decoded::Record(_, _),
+            Decoded::Incomplete,
+        ] if first == record(1)));
+
+        // the last record has been written partially
+        let decoded = decode_all(&file[..file.len() - 1]);
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[2], Decoded::Incomplete);
+    }
+}
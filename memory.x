@@ -1,6 +1,10 @@
 MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 512K
+  /* the last two sectors (2 x 128K at 0x08040000) are reserved for the EEPROM emulation, with
+     the `firmware-update` feature the firmware runs from slot A (sectors 0 - 4) or slot B
+     (sector 5 at 0x08020000, `FIRMWARE_SLOT=b`, see `build.rs`) of 128K each */
+  FLASH : ORIGIN = DEFINED(__firmware_slot_b) ? 0x08020000 : 0x08000000,
+          LENGTH = DEFINED(__firmware_slot_b) ? 128K : 256K
   RAM : ORIGIN = 0x20000000, LENGTH = 96K
 }
@@ -0,0 +1,88 @@
+//! How the measurements of the TOF sensor are acquired, switched at runtime with the `acquisition`
+//! command so that both can be compared with the same firmware.
+//!
+//! By default each measurement is read when the sensor raises its data ready interrupt (GPIO1 on
+//! `PA0`). When polled, the data ready flag of the sensor is read every [`POLL_INTERVAL_MS`]
+//! instead, which doesn't need the interrupt line to be wired but costs I2C traffic & adds up to
+//! the interval to the latency. The interrupt line is ignored while polling.
+//!
+//! Timed, a hardware timer starts each measurement at an exact cadence, see [`crate::cadence`].
+//! With the `trigger-input` feature the measurements can also be triggered by an external pulse
+//! each, see [`crate::trigger`], & with the `second-tof` feature both sensors can take turns, see
+//! [`crate::interleave`]. These start a single measurement at a time, which is read on the data
+//! ready interrupt.
+
+/// Interval at which the data ready flag is read while polling.
+pub const POLL_INTERVAL_MS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Acquisition {
+    /// Read the measurements on the data ready interrupt.
+    Interrupt,
+    /// Poll the data ready flag of the sensor.
+    Polled,
+    /// Start a single measurement every inter-measurement period with TIM11.
+    Timed,
+    /// Start a single measurement on each pulse of the trigger input.
+    #[cfg(feature = "trigger-input")]
+    Triggered,
+    /// Like [`Self::Timed`], but the TOF sensors take turns.
+    #[cfg(feature = "second-tof")]
+    Interleaved,
+}
+
+impl Acquisition {
+    pub const ALL: &'static [Self] = &[
+        Self::Interrupt,
+        Self::Polled,
+        Self::Timed,
+        #[cfg(feature = "trigger-input")]
+        Self::Triggered,
+        #[cfg(feature = "second-tof")]
+        Self::Interleaved,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Acquisition::Interrupt => "interrupt",
+            Acquisition::Polled => "polled",
+            Acquisition::Timed => "timed",
+            #[cfg(feature = "trigger-input")]
+            Acquisition::Triggered => "triggered",
+            #[cfg(feature = "second-tof")]
+            Acquisition::Interleaved => "interleaved",
+        }
+    }
+
+    /// Whether the firmware starts each measurement, the sensor stops after it.
+    pub fn is_single_shot(&self) -> bool {
+        match self {
+            Acquisition::Interrupt | Acquisition::Polled => false,
+            Acquisition::Timed => true,
+            #[cfg(feature = "trigger-input")]
+            Acquisition::Triggered => true,
+            #[cfg(feature = "second-tof")]
+            Acquisition::Interleaved => true,
+        }
+    }
+
+    /// Whether the measurements are started by the [`crate::cadence`].
+    pub fn uses_cadence(&self) -> bool {
+        *self == Acquisition::Timed || self.is_interleaved()
+    }
+
+    pub fn is_interleaved(&self) -> bool {
+        match self {
+            #[cfg(feature = "second-tof")]
+            Acquisition::Interleaved => true,
+            _ => false,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|acquisition| acquisition.name() == name)
+    }
+}
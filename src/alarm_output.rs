@@ -0,0 +1,154 @@
+//! A debounced alarm output on `PB0`, e.g. for driving a relay or external logic.
+//!
+//! The alarm is asserted once the filtered distance has been within the configured window for the
+//! dwell time and deasserted once it has been outside of it for the hold time (see
+//! [`crate::config::alarm_output`]). Invalid measurements count as outside of the window.
+//!
+//! With [`crate::config::alarm_output::LATCHING`] the alarm stays asserted once it has been
+//! asserted, until it's cleared with `alarm clear` or a press of the user button. It can only be
+//! cleared once the distance has left the window, the hold time still applies then.
+//!
+//! While the TOF sensor is lost the alarm is held at
+//! [`crate::config::alarm_output::FAILSAFE_ASSERTED`], from the next measurement on it follows the
+//! distance again.
+//!
+//! Outside of the active hours (see [`crate::schedule`]) the alarm is muted, i.e. deasserted.
+
+use crate::config::alarm_output as config;
+use crate::filter::Median3;
+use crate::telemetry::Measurement;
+use stm32f4xx_hal::gpio::{DynamicPin, PinState};
+use vl53l1x_uld::RangeStatus;
+
+pub struct AlarmOutput {
+    pin: DynamicPin<'B', 0>,
+    asserted: bool,
+    /// Median filter of the valid distances, which suppresses single outliers.
+    filter: Median3,
+    /// Whether the filtered distance is currently within the window.
+    in_window: bool,
+    /// Time at which the filtered distance entered or left the window.
+    changed_ms: u32,
+    /// Upper limit of the window, can be changed at runtime.
+    max_mm: u16,
+    /// Whether the asserted alarm waits to be cleared.
+    latched: bool,
+    /// Whether the TOF sensor is lost, see [`Self::failsafe`].
+    failsafe: bool,
+    /// Whether the alarm is kept deasserted as it's outside of the active hours.
+    muted: bool,
+}
+
+impl AlarmOutput {
+    pub fn new(mut pin: DynamicPin<'B', 0>) -> Self {
+        let deasserted = Self::level(false);
+        if config::OPEN_DRAIN {
+            pin.make_open_drain_output_in_state(deasserted);
+        } else {
+            pin.make_push_pull_output_in_state(deasserted);
+        }
+        Self {
+            pin,
+            asserted: false,
+            filter: Median3::new(u16::MAX),
+            in_window: false,
+            changed_ms: 0,
+            max_mm: config::MAX_MM,
+            latched: false,
+            failsafe: false,
+            muted: false,
+        }
+    }
+
+    /// Change the upper limit of the window. The alarm is never asserted if it's below the lower
+    /// limit.
+    pub fn set_max_mm(&mut self, max_mm: u16) {
+        self.max_mm = max_mm;
+    }
+
+    /// The upper limit of the window.
+    pub fn max_mm(&self) -> u16 {
+        self.max_mm
+    }
+
+    /// Hand a new measurement to the alarm.
+    pub fn update(&mut self, measurement: &Measurement) {
+        self.failsafe = false;
+        let in_window = if measurement.status == RangeStatus::Valid {
+            let distance_mm = self.filter.update(measurement.distance_mm);
+            (config::MIN_MM..=self.max_mm).contains(&distance_mm)
+        } else {
+            false
+        };
+        if in_window != self.in_window {
+            self.in_window = in_window;
+            self.changed_ms = measurement.timestamp_ms;
+        }
+    }
+
+    /// Hold the alarm at its failsafe state as the TOF sensor is lost, until the next measurement.
+    /// The alarm changes with the next [`Self::tick`].
+    pub fn failsafe(&mut self) {
+        self.failsafe = true;
+    }
+
+    /// Mute (or unmute) the alarm, which changes with the next [`Self::tick`]. A latched alarm is
+    /// cleared.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.asserted
+    }
+
+    pub fn is_latched(&self) -> bool {
+        self.latched
+    }
+
+    /// Clear the latched alarm, returns `false` if the distance is still within the window. The
+    /// alarm is deasserted by the next [`Self::tick`].
+    pub fn clear(&mut self) -> bool {
+        if self.latched && self.in_window {
+            return false;
+        }
+        self.latched = false;
+        true
+    }
+
+    /// Assert or deassert the alarm once the dwell or hold time has passed. Must be called
+    /// periodically. Returns whether the alarm has been asserted or deasserted.
+    pub fn tick(&mut self, now_ms: u32) -> bool {
+        let elapsed = now_ms.wrapping_sub(self.changed_ms);
+        let asserted = match (self.asserted, self.in_window) {
+            _ if self.muted => false,
+            _ if self.failsafe => config::FAILSAFE_ASSERTED,
+            (false, true) => elapsed >= config::DWELL_MS,
+            (true, false) => self.latched || elapsed < config::HOLD_MS,
+            (asserted, _) => asserted,
+        };
+        if asserted != self.asserted {
+            defmt::info!("alarm {}", if asserted { "asserted" } else { "deasserted" });
+            self.asserted = asserted;
+            self.latched = asserted && config::LATCHING;
+            // can't fail, the pin is always an output
+            let result = match Self::level(asserted) {
+                PinState::High => self.pin.set_high(),
+                PinState::Low => self.pin.set_low(),
+            };
+            result.ok();
+            return true;
+        }
+        false
+    }
+
+    /// Output level for the alarm state: open-drain outputs are active-low, push-pull outputs
+    /// active-high.
+    const fn level(asserted: bool) -> PinState {
+        if asserted != config::OPEN_DRAIN {
+            PinState::High
+        } else {
+            PinState::Low
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Monitoring of the ambient light measured by the TOF sensor (its ambient rate, published with
+//! each measurement), which lowers the accuracy & the range of the sensor, e.g. in direct
+//! sunlight.
+//!
+//! The ambient light is high once the rate has been above [`config::HIGH_KCPS`] for
+//! [`SAMPLES`] measurements in a row, & normal again once it has been below 3/4 of it for as
+//! long, so that the changes (which are logged as events) don't flap. If
+//! [`config::SHORT_MODE`] is enabled the sensor is switched to the short distance mode (which is
+//! less sensitive to ambient light) while the ambient light is high.
+
+use crate::config::ambient as config;
+
+/// Number of measurements in a row which are needed for a change.
+pub const SAMPLES: u8 = 5;
+/// The rate below which the ambient light is normal again.
+const NORMAL_KCPS: u16 = (config::HIGH_KCPS as u32 * 3 / 4) as u16;
+
+pub struct AmbientMonitor {
+    high: bool,
+    /// Number of measurements in a row which contradict the current state.
+    count: u8,
+}
+
+impl AmbientMonitor {
+    pub fn new() -> Self {
+        Self {
+            high: false,
+            count: 0,
+        }
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.high
+    }
+
+    /// Update with the ambient rate of a measurement, returns the new state if it has changed.
+    pub fn update(&mut self, ambient_kcps: u16) -> Option<bool> {
+        let contradicts = if self.high {
+            ambient_kcps < NORMAL_KCPS
+        } else {
+            ambient_kcps > config::HIGH_KCPS
+        };
+        if !contradicts {
+            self.count = 0;
+            return None;
+        }
+        self.count += 1;
+        if self.count < SAMPLES {
+            return None;
+        }
+        self.count = 0;
+        self.high = !self.high;
+        Some(self.high)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update_with(monitor: &mut AmbientMonitor, ambient_kcps: u16) -> Vec<Option<bool>> {
+        (0..SAMPLES).map(|_| monitor.update(ambient_kcps)).collect()
+    }
+
+    #[test]
+    fn high_ambient_needs_several_samples() {
+        let mut monitor = AmbientMonitor::new();
+        let changes = update_with(&mut monitor, config::HIGH_KCPS + 1);
+        assert_eq!(changes.last(), Some(&Some(true)));
+        assert!(changes[..changes.len() - 1].iter().all(Option::is_none));
+        assert!(monitor.is_high());
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut monitor = AmbientMonitor::new();
+        update_with(&mut monitor, config::HIGH_KCPS + 1);
+        // between the limits the state is kept
+        assert!(update_with(&mut monitor, NORMAL_KCPS)
+            .iter()
+            .all(Option::is_none));
+        assert_eq!(
+            update_with(&mut monitor, NORMAL_KCPS - 1).last(),
+            Some(&Some(false))
+        );
+    }
+
+    #[test]
+    fn single_outliers_are_ignored() {
+        let mut monitor = AmbientMonitor::new();
+        for _ in 0..3 * SAMPLES {
+            monitor.update(config::HIGH_KCPS + 1);
+            assert_eq!(monitor.update(0), None);
+        }
+        assert!(!monitor.is_high());
+    }
+}
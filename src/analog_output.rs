@@ -0,0 +1,81 @@
+//! A pseudo-analog output of the distance on `PA1` (TIM5 CH2): a high-frequency PWM signal whose
+//! duty cycle is proportional to the distance, which an external RC low-pass filter turns into a
+//! voltage of 0 - 3.3 V (e.g. for the analog input of a PLC).
+//!
+//! The output is at its minimum voltage at or below the minimum distance, at its maximum voltage at
+//! or beyond the maximum distance and linear in between (see [`crate::config::analog_output`]). It
+//! keeps its voltage for invalid measurements & is set to
+//! [`crate::config::analog_output::FAILSAFE_MV`] while the TOF sensor is lost.
+
+use crate::config::analog_output as config;
+use crate::telemetry::Measurement;
+use stm32f4xx_hal::pac::TIM5;
+use stm32f4xx_hal::timer::PwmChannel;
+use vl53l1x_uld::RangeStatus;
+
+#[cfg(feature = "motor-pid")]
+compile_error!(
+    "the features `analog-output` and `motor-pid` can't be combined as both use TIM5 & PA1"
+);
+
+/// The voltage at a duty cycle of 100 %, the supply of the microcontroller.
+pub const FULL_SCALE_MV: u32 = 3_300;
+
+pub struct AnalogOutput {
+    pwm: PwmChannel<TIM5, 1>,
+}
+
+impl AnalogOutput {
+    pub fn new(pwm: PwmChannel<TIM5, 1>) -> Self {
+        let mut output = Self { pwm };
+        output.set_mv(config::MIN_MV);
+        output.pwm.enable();
+        output
+    }
+
+    /// Set the voltage for the measurement.
+    pub fn update(&mut self, measurement: &Measurement) {
+        if measurement.status == RangeStatus::Valid {
+            self.set_mv(output_mv(measurement.distance_mm));
+        }
+    }
+
+    /// Set the failsafe voltage as the TOF sensor is lost.
+    pub fn failsafe(&mut self) {
+        self.set_mv(config::FAILSAFE_MV);
+    }
+
+    fn set_mv(&mut self, mv: u32) {
+        let max_duty = self.pwm.get_max_duty() as u32;
+        self.pwm.set_duty((mv * max_duty / FULL_SCALE_MV) as u16);
+    }
+}
+
+/// The filtered voltage for the distance.
+// the default of the voltage at the minimum distance is 0, where clippy considers the check
+// pointless
+#[allow(clippy::absurd_extreme_comparisons)]
+fn output_mv(distance_mm: u16) -> u32 {
+    let distance = distance_mm.clamp(config::MIN_MM, config::MAX_MM) as u32;
+    let range = (config::MAX_MM - config::MIN_MM) as u32;
+    let offset =
+        (distance - config::MIN_MM as u32) * config::MAX_MV.abs_diff(config::MIN_MV) / range;
+    if config::MIN_MV <= config::MAX_MV {
+        config::MIN_MV + offset
+    } else {
+        config::MIN_MV - offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voltage_is_proportional_to_the_distance() {
+        assert_eq!(output_mv(0), config::MIN_MV);
+        assert_eq!(output_mv(u16::MAX), config::MAX_MV);
+        let middle_mm = (config::MIN_MM + config::MAX_MM) / 2;
+        assert!(output_mv(middle_mm).abs_diff((config::MIN_MV + config::MAX_MV) / 2) <= 1);
+    }
+}
@@ -0,0 +1,189 @@
+//! The application modes which decide what the firmware does with the measurements.
+//!
+//! The local outputs are always updated, the mode only selects which measurements are published
+//! on the telemetry links. The health report & the rollups are sent in all modes.
+//!
+//! The mode is selected at boot, see [`Selection`]: holding the user button during the reset for
+//! a number of [`SELECTION_STEP_MS`] selects the mode with this number (the user LED flashes at
+//! each step), which is stored. Otherwise the DIP switch (or the switches of the GPIO expander)
+//! or the stored mode is used.
+
+use crate::config::app_mode as config;
+use crate::eeprom::{self, Eeprom};
+use crate::telemetry::Measurement;
+use stm32f4xx_hal::flash;
+use stm32f4xx_hal::pac::FLASH;
+use stm32f4xx_hal::watchdog::IndependentWatchdog;
+use vl53l1x_uld::RangeStatus;
+
+/// Version of the format in which the mode is stored in the EEPROM.
+const EEPROM_VERSION: u8 = 1;
+
+/// Distance the target has to move past the presence threshold before the presence changes, this
+/// avoids a flood of frames while it's close to the threshold.
+const PRESENCE_HYSTERESIS_MM: u16 = 50;
+
+/// Time for which the user button has to be held at boot per step of the selection of the mode.
+pub const SELECTION_STEP_MS: u32 = 1_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum AppMode {
+    /// Publish every measurement.
+    Streaming,
+    /// Only publish the measurements at which a target appears or disappears, see
+    /// [`PresenceDetector`].
+    Presence,
+    /// Only operate the local outputs (e.g. the buzzer), nothing is published.
+    ParkingAssist,
+    /// Only publish the periodic statistics, see [`crate::rollup`].
+    Rollup,
+    /// Like [`AppMode::Presence`], but the microcontroller stops until the sensor detects a
+    /// target on its own, see [`crate::low_power`].
+    LowPower,
+}
+
+/// Where the mode at boot comes from, in the order of precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Selection {
+    /// The user button has been held at boot, see [`AppMode::selected_by_hold`].
+    Button,
+    /// The DIP switch or the switches of the GPIO expander, see [`crate::boot_config`].
+    Switches,
+    /// The mode stored in the EEPROM.
+    Stored,
+    /// The default of the [`crate::boot_config`].
+    Default,
+}
+
+/// Detects whether a target is present, i.e. closer than [`config::PRESENCE_MM`].
+pub struct PresenceDetector {
+    present: bool,
+}
+
+impl PresenceDetector {
+    pub fn new() -> Self {
+        Self { present: false }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    /// Update the presence with the measurement. Returns whether it has changed.
+    pub fn update(&mut self, measurement: &Measurement) -> bool {
+        let valid = measurement.status == RangeStatus::Valid;
+        let present = if self.present {
+            valid && measurement.distance_mm < config::PRESENCE_MM + PRESENCE_HYSTERESIS_MM
+        } else {
+            valid && measurement.distance_mm < config::PRESENCE_MM
+        };
+        if present == self.present {
+            return false;
+        }
+        defmt::info!("target {}", if present { "present" } else { "gone" });
+        self.present = present;
+        true
+    }
+}
+
+impl AppMode {
+    /// Number of modes.
+    pub const COUNT: u8 = 5;
+
+    /// The mode after this one, all modes are cycled through.
+    pub fn next(&self) -> Self {
+        match self {
+            AppMode::Streaming => AppMode::Presence,
+            AppMode::Presence => AppMode::ParkingAssist,
+            AppMode::ParkingAssist => AppMode::Rollup,
+            AppMode::Rollup => AppMode::LowPower,
+            AppMode::LowPower => AppMode::Streaming,
+        }
+    }
+
+    /// Number of the mode (starting at 1), shown by blinking the status LED.
+    pub fn number(&self) -> u8 {
+        match self {
+            AppMode::Streaming => 1,
+            AppMode::Presence => 2,
+            AppMode::ParkingAssist => 3,
+            AppMode::Rollup => 4,
+            AppMode::LowPower => 5,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AppMode::Streaming => "streaming",
+            AppMode::Presence => "presence",
+            AppMode::ParkingAssist => "parking",
+            AppMode::Rollup => "rollup",
+            AppMode::LowPower => "low-power",
+        }
+    }
+
+    /// The mode selected by holding the user button at boot for `held_ms`: the one with the
+    /// [number](Self::number) of the completed [`SELECTION_STEP_MS`], at most the last one. `None`
+    /// for less than one step, which selects the next profile instead (see [`crate::profile`]).
+    pub fn selected_by_hold(held_ms: u32) -> Option<Self> {
+        let steps = (held_ms / SELECTION_STEP_MS).min(Self::COUNT as u32);
+        Self::from_number(steps as u8)
+    }
+
+    /// The mode with the [number](Self::number).
+    fn from_number(number: u8) -> Option<Self> {
+        match number {
+            1 => Some(AppMode::Streaming),
+            2 => Some(AppMode::Presence),
+            3 => Some(AppMode::ParkingAssist),
+            4 => Some(AppMode::Rollup),
+            5 => Some(AppMode::LowPower),
+            _ => None,
+        }
+    }
+
+    /// The mode stored in the EEPROM, `None` if none has been stored yet.
+    pub fn load(eeprom: &Eeprom, flash: &FLASH) -> Option<Self> {
+        let [number] = eeprom.read_array(flash, eeprom::keys::APP_MODE, EEPROM_VERSION)?;
+        Self::from_number(number)
+    }
+
+    /// Store the mode in the EEPROM so that it's used again after a reset.
+    pub fn store(
+        &self,
+        eeprom: &mut Eeprom,
+        flash: &mut FLASH,
+        watchdog: &mut IndependentWatchdog,
+    ) -> Result<(), flash::Error> {
+        eeprom.write(
+            flash,
+            eeprom::keys::APP_MODE,
+            EEPROM_VERSION,
+            &[self.number()],
+            watchdog,
+        )
+    }
+
+    /// Whether the measurement should be published in this mode, `presence_changed` is the result
+    /// of [`PresenceDetector::update`] with it.
+    pub fn publishes(&self, presence_changed: bool) -> bool {
+        match self {
+            AppMode::Streaming => true,
+            AppMode::Presence | AppMode::LowPower => presence_changed,
+            AppMode::ParkingAssist | AppMode::Rollup => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection_by_holding_the_button() {
+        assert_eq!(AppMode::selected_by_hold(999), None);
+        assert_eq!(AppMode::selected_by_hold(1_000), Some(AppMode::Streaming));
+        assert_eq!(AppMode::selected_by_hold(2_500), Some(AppMode::Presence));
+        assert_eq!(AppMode::selected_by_hold(60_000), Some(AppMode::LowPower));
+    }
+}
@@ -0,0 +1,177 @@
+//! Batching of the published measurements for slow links (e.g. a UART at 9600 baud): if
+//! [`config::ENABLED`], up to [`config::SIZE`] measurements are published as a single batch frame
+//! (see [`crate::telemetry::batch_frame`]) instead of a measurement frame each, which saves the
+//! repeated keys, the device & the line ending. Each sample of a batch only contains its time
+//! relative to the first one, the distance & the status.
+//!
+//! A batch is published once it's full or [`config::TIMEOUT_MS`] after its first measurement, so
+//! that a slow measurement rate doesn't delay the measurements indefinitely. The timeout is a
+//! single scheduled task which is only armed while a batch is pending, see [`Update::timeout_ms`].
+//! The measurements of a [`crate::capture`] aren't batched, a pending batch is published before
+//! them.
+
+use crate::config::batch as config;
+use crate::telemetry::Measurement;
+use vl53l1x_uld::RangeStatus;
+
+/// The largest batch, which fits into a [`crate::telemetry::Frame`] in either format with any
+/// distance.
+pub const MAX_SIZE: usize = 6;
+/// The longest timeout, so that the offsets within a batch have at most 4 digits.
+pub const MAX_TIMEOUT_MS: u32 = 9_999;
+
+/// A measurement within a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// Time since the first measurement of the batch.
+    pub offset_ms: u16,
+    pub distance_mm: u16,
+    pub status: RangeStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Batch {
+    /// Time since boot of the first measurement.
+    pub timestamp_ms: u32,
+    /// Sequence number of the first measurement.
+    pub seq: u32,
+    /// Calendar time of the first measurement, `None` if the clock hasn't been set.
+    pub utc_ms: Option<u64>,
+    pub samples: heapless::Vec<Sample, MAX_SIZE>,
+}
+
+/// The result of a change of the batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Update {
+    /// The batch which is complete & has to be published.
+    pub complete: Option<Batch>,
+    /// Arm the timeout to run after this many ms, see [`Batcher::on_timeout`].
+    pub timeout_ms: Option<u32>,
+}
+
+pub struct Batcher {
+    /// Number of measurements per batch.
+    size: usize,
+    pending: Option<Batch>,
+    /// Whether the timeout is scheduled.
+    armed: bool,
+}
+
+impl Batcher {
+    pub const fn new(size: usize) -> Self {
+        Self {
+            size,
+            pending: None,
+            armed: false,
+        }
+    }
+
+    /// Add the measurement to the pending batch. A measurement past the timeout of the pending
+    /// batch (e.g. as the timeout hasn't run yet) completes it & starts a new one.
+    pub fn add(&mut self, measurement: &Measurement) -> Update {
+        let mut complete = self.pending.take_if(|batch| {
+            measurement.timestamp_ms.wrapping_sub(batch.timestamp_ms) >= config::TIMEOUT_MS
+        });
+        let batch = self.pending.get_or_insert_with(|| Batch {
+            timestamp_ms: measurement.timestamp_ms,
+            seq: measurement.seq,
+            utc_ms: measurement.utc_ms,
+            samples: heapless::Vec::new(),
+        });
+        let offset_ms = measurement.timestamp_ms.wrapping_sub(batch.timestamp_ms);
+        // can't fail as a full batch is completed right away
+        let _ = batch.samples.push(Sample {
+            offset_ms: offset_ms as u16,
+            distance_mm: measurement.distance_mm,
+            status: measurement.status,
+        });
+        if batch.samples.len() >= self.size {
+            complete = self.pending.take();
+        }
+        Update {
+            complete,
+            timeout_ms: self.arm(config::TIMEOUT_MS - offset_ms),
+        }
+    }
+
+    /// Handle the timeout at `now_ms`: the pending batch is completed if it's old enough, the
+    /// timeout is armed again for a younger one.
+    pub fn on_timeout(&mut self, now_ms: u32) -> Update {
+        self.armed = false;
+        let complete = self
+            .pending
+            .take_if(|batch| now_ms.wrapping_sub(batch.timestamp_ms) >= config::TIMEOUT_MS);
+        let age_ms = self
+            .pending
+            .as_ref()
+            .map_or(0, |batch| now_ms.wrapping_sub(batch.timestamp_ms));
+        Update {
+            complete,
+            timeout_ms: self.arm(config::TIMEOUT_MS - age_ms),
+        }
+    }
+
+    /// Take the pending batch regardless of its size.
+    pub fn flush(&mut self) -> Option<Batch> {
+        self.pending.take()
+    }
+
+    /// The delay of the timeout if it has to be scheduled for the pending batch.
+    fn arm(&mut self, delay_ms: u32) -> Option<u32> {
+        if self.armed || self.pending.is_none() {
+            return None;
+        }
+        self.armed = true;
+        Some(delay_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test_measurement;
+
+    #[test]
+    fn completes_full_and_timed_out_batches() {
+        let mut batcher = Batcher::new(3);
+        let first = batcher.add(&Measurement {
+            seq: 1,
+            ..test_measurement(1_000, 500)
+        });
+        assert_eq!(first.complete, None);
+        assert_eq!(first.timeout_ms, Some(config::TIMEOUT_MS));
+        let mut complete = None;
+        for i in 1..3 {
+            let update = batcher.add(&Measurement {
+                seq: 1 + i,
+                ..test_measurement(1_000 + i * 10, 500)
+            });
+            // the timeout is only scheduled once
+            assert_eq!(update.timeout_ms, None);
+            complete = update.complete;
+        }
+        let batch = complete.unwrap();
+        assert_eq!((batch.timestamp_ms, batch.seq), (1_000, 1));
+        assert_eq!(batch.samples.len(), 3);
+        assert_eq!(batch.samples[1].offset_ms, 10);
+
+        // the timeout of the completed batch re-arms itself for the next one
+        let start_ms = 1_000 + config::TIMEOUT_MS - 100;
+        batcher.add(&Measurement {
+            seq: 10,
+            ..test_measurement(start_ms, 500)
+        });
+        let update = batcher.on_timeout(1_000 + config::TIMEOUT_MS);
+        assert_eq!(
+            update,
+            Update {
+                complete: None,
+                timeout_ms: Some(config::TIMEOUT_MS - 100)
+            }
+        );
+        let update = batcher.on_timeout(start_ms + config::TIMEOUT_MS);
+        assert_eq!(update.complete.map(|batch| batch.seq), Some(10));
+        assert_eq!(update.timeout_ms, None);
+        assert_eq!(batcher.flush(), None);
+    }
+}
@@ -0,0 +1,157 @@
+//! Battery operation (`battery` feature): the battery voltage is measured through a voltage divider
+//! on `PA4` (ADC1 IN4) every [`POLL_INTERVAL_MS`] & compared with two thresholds:
+//!
+//! * below [`config::LOW_MV`] the battery is low, the inter-measurement period of the TOF sensor is
+//!   extended to [`config::LOW_INTER_MEASUREMENT_MS`] to save power. The previous one is restored
+//!   once the voltage has risen above the threshold by [`config::HYSTERESIS_MV`] (e.g. on a
+//!   charger).
+//! * below [`config::CRITICAL_MV`] the battery is critical, the firmware shuts down like with the
+//!   `shutdown` command: the data loggers are stopped, the TOF sensor is parked & the
+//!   microcontroller stops until the next reset (see [`crate::shutdown`]).
+//!
+//! Each transition is logged as an event in the [`crate::event_log`]. The samples are filtered,
+//! thus a short dip (e.g. while the sensor emits) doesn't trigger a transition.
+
+use crate::config::battery as config;
+use stm32f4xx_hal::adc::config::SampleTime;
+use stm32f4xx_hal::adc::Adc;
+use stm32f4xx_hal::gpio::{Analog, PA4};
+use stm32f4xx_hal::pac::ADC1;
+
+#[cfg(feature = "threshold-pot")]
+compile_error!("the features `battery` and `threshold-pot` can't be combined as both use ADC1");
+#[cfg(feature = "motor-pid")]
+compile_error!("the features `battery` and `motor-pid` can't be combined as both use PA4");
+
+/// Interval at which the voltage is measured.
+pub const POLL_INTERVAL_MS: u32 = 1_000;
+/// Maximum value of the 12 bit ADC.
+const ADC_MAX: u32 = 4095;
+/// The reference voltage of the ADC, the supply of the microcontroller.
+const VREF_MV: u32 = 3_300;
+/// Weight of the previous filtered voltage against a new sample.
+const FILTER_WEIGHT: u32 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Level {
+    Ok,
+    Low,
+    Critical,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Ok => "ok",
+            Level::Low => "low",
+            Level::Critical => "critical",
+        }
+    }
+}
+
+/// Tracks the level of the filtered voltage, [`Level::Critical`] isn't left anymore.
+pub struct LevelTracker {
+    low_mv: u32,
+    critical_mv: u32,
+    hysteresis_mv: u32,
+    filtered_mv: Option<u32>,
+    level: Level,
+}
+
+impl LevelTracker {
+    pub const fn new(low_mv: u32, critical_mv: u32, hysteresis_mv: u32) -> Self {
+        Self {
+            low_mv,
+            critical_mv,
+            hysteresis_mv,
+            filtered_mv: None,
+            level: Level::Ok,
+        }
+    }
+
+    /// Add a sample, returns the new level if it has changed.
+    pub fn update(&mut self, sample_mv: u32) -> Option<Level> {
+        let filtered_mv = self.filtered_mv.map_or(sample_mv, |filtered_mv| {
+            (filtered_mv * FILTER_WEIGHT + sample_mv) / (FILTER_WEIGHT + 1)
+        });
+        self.filtered_mv = Some(filtered_mv);
+        let level = match self.level {
+            Level::Critical => Level::Critical,
+            _ if filtered_mv < self.critical_mv => Level::Critical,
+            _ if filtered_mv < self.low_mv => Level::Low,
+            Level::Low if filtered_mv < self.low_mv + self.hysteresis_mv => Level::Low,
+            _ => Level::Ok,
+        };
+        if level == self.level {
+            return None;
+        }
+        self.level = level;
+        Some(level)
+    }
+
+    /// The filtered voltage, `None` before the first sample.
+    pub fn voltage_mv(&self) -> Option<u32> {
+        self.filtered_mv
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+}
+
+pub struct Battery {
+    adc: Adc<ADC1>,
+    pin: PA4<Analog>,
+    tracker: LevelTracker,
+}
+
+impl Battery {
+    pub fn new(adc: Adc<ADC1>, pin: PA4<Analog>) -> Self {
+        Self {
+            adc,
+            pin,
+            tracker: LevelTracker::new(config::LOW_MV, config::CRITICAL_MV, config::HYSTERESIS_MV),
+        }
+    }
+
+    /// Measure the voltage. Returns the new level if it has changed.
+    pub fn poll(&mut self) -> Option<Level> {
+        let sample = self.adc.convert(&self.pin, SampleTime::Cycles_480) as u32;
+        let divided_mv = sample.min(ADC_MAX) * VREF_MV / ADC_MAX;
+        let voltage_mv = divided_mv * (config::DIVIDER_TOP_KOHM + config::DIVIDER_BOTTOM_KOHM)
+            / config::DIVIDER_BOTTOM_KOHM;
+        self.tracker.update(voltage_mv)
+    }
+
+    pub fn voltage_mv(&self) -> Option<u32> {
+        self.tracker.voltage_mv()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_with_hysteresis() {
+        let mut tracker = LevelTracker::new(3_500, 3_300, 100);
+        assert_eq!(tracker.update(3_700), None);
+        // filtered, a single dip doesn't count
+        assert_eq!(tracker.update(3_000), None);
+        assert_eq!(tracker.voltage_mv(), Some(3_612));
+        let low = (0..20).find_map(|_| tracker.update(3_450));
+        assert_eq!(low, Some(Level::Low));
+        assert_eq!((0..20).find_map(|_| tracker.update(3_550)), None);
+        assert_eq!((0..20).find_map(|_| tracker.update(3_650)), Some(Level::Ok));
+        assert_eq!(
+            (0..40).find_map(|_| tracker.update(3_200)),
+            Some(Level::Low)
+        );
+        assert_eq!(
+            (0..40).find_map(|_| tracker.update(3_200)),
+            Some(Level::Critical)
+        );
+        assert_eq!((0..40).find_map(|_| tracker.update(4_000)), None);
+        assert_eq!(tracker.level(), Level::Critical);
+    }
+}
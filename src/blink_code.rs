@@ -0,0 +1,76 @@
+//! Blink codes for units without any tooling attached: the class of the latest error is recorded
+//! (see [`record`]) & played on the status LED in a loop as long as the firmware is in a failed
+//! state (safe mode or a failing sensor), see [`crate::status_led::Pattern::Code`]. The number of
+//! blinks is the class.
+//!
+//! Like the log (see [`crate::log_backend`]) the class is kept in a static, thus it can be
+//! recorded from every task & during the initialisation.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// The classes of the errors, the discriminant is the number of blinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ErrorClass {
+    /// The TOF sensor doesn't answer on the I2C bus or a read failed.
+    I2c = 1,
+    /// The TOF sensor couldn't be set up.
+    SensorInit = 2,
+    /// The EEPROM, the flash log or the file system failed.
+    Storage = 3,
+    /// A setting couldn't be applied.
+    Config = 4,
+    /// The LoRa radio couldn't be set up.
+    Radio = 5,
+}
+
+impl ErrorClass {
+    pub fn blinks(self) -> u8 {
+        self as u8
+    }
+
+    fn from_blinks(blinks: u8) -> Option<Self> {
+        match blinks {
+            1 => Some(Self::I2c),
+            2 => Some(Self::SensorInit),
+            3 => Some(Self::Storage),
+            4 => Some(Self::Config),
+            5 => Some(Self::Radio),
+            _ => None,
+        }
+    }
+}
+
+/// The blinks of the latest error, 0 if none has been recorded since boot.
+static LAST: AtomicU8 = AtomicU8::new(0);
+
+/// Record an error, it replaces the previous one.
+pub fn record(class: ErrorClass) {
+    LAST.store(class.blinks(), Ordering::Relaxed);
+}
+
+/// The class of the latest error since boot.
+pub fn last() -> Option<ErrorClass> {
+    ErrorClass::from_blinks(LAST.load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_error() {
+        assert_eq!(last(), None);
+        record(ErrorClass::Storage);
+        record(ErrorClass::I2c);
+        assert_eq!(last(), Some(ErrorClass::I2c));
+        for class in [
+            ErrorClass::I2c,
+            ErrorClass::SensorInit,
+            ErrorClass::Storage,
+            ErrorClass::Config,
+            ErrorClass::Radio,
+        ] {
+            assert_eq!(ErrorClass::from_blinks(class.blinks()), Some(class));
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Support for a HC-05 (Bluetooth classic) or HM-10 (BLE) UART bridge module on USART6 (`PC6` = TX,
+//! `PC7` = RX).
+//!
+//! The module is configured once at boot (name & baud rate) using its AT command interface, after
+//! which it is used as a normal [`UartLink`](crate::uart::UartLink) carrying the telemetry &
+//! commands.
+
+use crate::config::bluetooth as config;
+use stm32f4xx_hal::gpio::{Output, PC6, PC7, PC8};
+use stm32f4xx_hal::hal::serial::Read;
+use stm32f4xx_hal::pac::USART6;
+use stm32f4xx_hal::prelude::*;
+use stm32f4xx_hal::rcc::Clocks;
+use stm32f4xx_hal::serial::{self, Serial};
+
+#[cfg(all(feature = "bluetooth-hc05", feature = "bluetooth-hm10"))]
+compile_error!("only one of the features `bluetooth-hc05` and `bluetooth-hm10` can be enabled");
+#[cfg(not(any(feature = "bluetooth-hc05", feature = "bluetooth-hm10")))]
+compile_error!(
+    "use the feature `bluetooth-hc05` or `bluetooth-hm10` to select the Bluetooth module"
+);
+
+/// Time to wait for the response of the module to a configuration command.
+const RESPONSE_TIMEOUT_MS: u32 = 500;
+
+/// Baud rate at which the module accepts AT commands when in the configuration mode.
+#[cfg(feature = "bluetooth-hc05")]
+const CONFIG_BAUD_RATE: u32 = 38_400;
+/// Baud rate at which the module accepts AT commands when in the configuration mode.
+#[cfg(feature = "bluetooth-hm10")]
+const CONFIG_BAUD_RATE: u32 = 9_600;
+
+/// Configure the module and return the UART ready to be used for the telemetry link.
+///
+/// The HC-05 is put into its AT command mode by driving its `KEY`/`EN` pin (connected to `PC8`)
+/// high during the configuration. The HM-10 accepts AT commands whenever no central is connected.
+///
+/// Failing to configure the module isn't fatal: it might already have been configured
+/// previously, thus failures are only logged.
+pub fn setup(
+    usart: USART6,
+    pins: (PC6, PC7),
+    mut key: PC8<Output>,
+    clocks: &Clocks,
+) -> Serial<USART6> {
+    key.set_high();
+    let mut serial = Serial::new(
+        usart,
+        pins,
+        serial::Config::default().baudrate(CONFIG_BAUD_RATE.bps()),
+        clocks,
+    )
+    .expect("");
+
+    let mut ok = true;
+    for command in commands() {
+        ok &= send_command(&mut serial, command.as_bytes(), clocks);
+    }
+    key.set_low();
+    if ok {
+        defmt::info!("Bluetooth module configured as '{}'", config::NAME);
+    } else {
+        defmt::warn!("failed to configure Bluetooth module, using its previous configuration");
+    }
+
+    let (usart, pins) = serial.release();
+    Serial::new(usart, pins, crate::uart::config(), clocks).expect("")
+}
+
+type CommandBuffer = heapless::String<48>;
+
+/// The AT commands needed to configure the module.
+#[cfg(feature = "bluetooth-hc05")]
+fn commands() -> [CommandBuffer; 3] {
+    use core::fmt::Write;
+    let mut name = CommandBuffer::new();
+    write!(name, "AT+NAME={}\r\n", config::NAME).expect("name too long");
+    let mut baud = CommandBuffer::new();
+    write!(baud, "AT+UART={},0,0\r\n", crate::uart::BAUD_RATE).ok();
+    // leave the AT command mode
+    let reset = CommandBuffer::from("AT+RESET\r\n");
+    [name, baud, reset]
+}
+
+/// The AT commands needed to configure the module.
+#[cfg(feature = "bluetooth-hm10")]
+fn commands() -> [CommandBuffer; 3] {
+    use core::fmt::Write;
+    // the HM-10 doesn't expect any line endings
+    let mut name = CommandBuffer::new();
+    write!(name, "AT+NAME{}", config::NAME).expect("name too long");
+    let baud = match crate::uart::BAUD_RATE {
+        9_600 => "AT+BAUD0",
+        19_200 => "AT+BAUD1",
+        38_400 => "AT+BAUD2",
+        57_600 => "AT+BAUD3",
+        _ => "AT+BAUD4", // 115200
+    };
+    let baud = CommandBuffer::from(baud);
+    let reset = CommandBuffer::from("AT+RESET");
+    [name, baud, reset]
+}
+
+/// Send a command and wait for an `OK` in the response.
+///
+/// This runs during init where no timers are available yet, thus the timeout is implemented
+/// using busy-waiting.
+fn send_command(serial: &mut Serial<USART6>, command: &[u8], clocks: &Clocks) -> bool {
+    defmt::debug!("Bluetooth: sending {=[u8]:a}", command);
+    if serial.bwrite_all(command).is_err() {
+        return false;
+    }
+
+    // poll every 10 us
+    let cycles_per_poll = clocks.sysclk().raw() / 100_000;
+    let mut response = heapless::Vec::<u8, 32>::new();
+    for _ in 0..RESPONSE_TIMEOUT_MS * 100 {
+        match serial.read() {
+            Ok(byte) => {
+                response.push(byte).ok();
+                if response.windows(2).any(|w| w == b"OK") {
+                    return true;
+                }
+            }
+            Err(_) => cortex_m::asm::delay(cycles_per_poll),
+        }
+    }
+    defmt::warn!("Bluetooth: no valid response, got {=[u8]:a}", &response[..]);
+    false
+}
@@ -0,0 +1,101 @@
+//! The pins of the core functions of the firmware: the shared I2C bus (I2C1) with the TOF sensor,
+//! the interrupt & `XSHUT` pins of the sensor, the user LED & button of the Nucleo board & the
+//! virtual COM port (USART2). They're taken from the GPIO ports with [`crate::board_pins!`], an
+//! alternative mapping is selected with a feature for shields which already occupy a default pin:
+//!
+//! | Function      | Default                   | Alternative                  | Feature                  |
+//! |---------------|---------------------------|------------------------------|--------------------------|
+//! | I2C SCL / SDA | `PB8` / `PB9` (D15 / D14) | `PB6` / `PB7` (D10 / CN7-21) | `pins-i2c-pb6-pb7`       |
+//! | TOF interrupt | `PA0` (A0)                | `PB0` (A3)                   | `pins-tof-interrupt-pb0` |
+//! | TOF `XSHUT`   | `PB2`                     | -                            | -                        |
+//! | LED (LD2)     | `PA5`                     | -                            | -                        |
+//! | Button (B1)   | `PC13`                    | -                            | -                        |
+//! | UART TX / RX  | `PA2` / `PA3`             | -                            | -                        |
+//!
+//! The alternative interrupt pin is on the same EXTI line (0), thus it's handled by the same task.
+//! The pins of the optional features are documented in their modules.
+
+use stm32f4xx_hal::gpio::{Edge, Input, Output, PA2, PA3, PA5, PB2, PC13};
+
+#[cfg(all(feature = "pins-i2c-pb6-pb7", feature = "buzzer"))]
+compile_error!("the features `pins-i2c-pb6-pb7` and `buzzer` can't be combined as both use PB6");
+#[cfg(all(feature = "pins-i2c-pb6-pb7", feature = "ultrasonic"))]
+compile_error!(
+    "the features `pins-i2c-pb6-pb7` and `ultrasonic` can't be combined as both use PB6"
+);
+#[cfg(all(
+    feature = "pins-i2c-pb6-pb7",
+    any(feature = "sd-card", feature = "nor-flash")
+))]
+compile_error!(
+    "the feature `pins-i2c-pb6-pb7` can't be combined with `sd-card` or `nor-flash` as they use PB7"
+);
+#[cfg(all(feature = "pins-tof-interrupt-pb0", feature = "alarm-output"))]
+compile_error!(
+    "the features `pins-tof-interrupt-pb0` and `alarm-output` can't be combined as both use PB0"
+);
+
+/// SCL of the shared I2C bus.
+#[cfg(not(feature = "pins-i2c-pb6-pb7"))]
+pub type I2cScl = stm32f4xx_hal::gpio::PB8;
+#[cfg(feature = "pins-i2c-pb6-pb7")]
+pub type I2cScl = stm32f4xx_hal::gpio::PB6;
+/// SDA of the shared I2C bus.
+#[cfg(not(feature = "pins-i2c-pb6-pb7"))]
+pub type I2cSda = stm32f4xx_hal::gpio::PB9;
+#[cfg(feature = "pins-i2c-pb6-pb7")]
+pub type I2cSda = stm32f4xx_hal::gpio::PB7;
+/// The data ready interrupt of the TOF sensor (`GPIO1`), on EXTI line 0.
+#[cfg(not(feature = "pins-tof-interrupt-pb0"))]
+pub type TofInterrupt = stm32f4xx_hal::gpio::PA0<Input>;
+#[cfg(feature = "pins-tof-interrupt-pb0")]
+pub type TofInterrupt = stm32f4xx_hal::gpio::PB0<Input>;
+/// The edge of the interrupt lines of the TOF sensors at which a measurement is ready, see
+/// [`crate::config::tof_interrupt`].
+pub const TOF_INTERRUPT_EDGE: Edge = if crate::config::tof_interrupt::FALLING_EDGE {
+    Edge::Falling
+} else {
+    Edge::Rising
+};
+/// The `XSHUT` pin of the TOF sensor, which is kept high while the firmware runs (the sensor is
+/// shut down while it's low).
+pub type TofShutdown = PB2<Output>;
+/// The user LED (LD2).
+pub type Led = PA5<Output>;
+/// The user button (B1), low while it's pressed.
+pub type Button = PC13<Input>;
+
+/// The pins of the core functions, in the modes in which they're used.
+pub struct Pins {
+    pub i2c: (I2cScl, I2cSda),
+    pub tof_interrupt: TofInterrupt,
+    pub tof_shutdown: TofShutdown,
+    pub led: Led,
+    pub button: Button,
+    /// TX & RX of the virtual COM port.
+    pub vcp: (PA2, PA3),
+}
+
+/// Take the [`Pins`] out of the split GPIO ports A - C (`stm32f4xx_hal::gpio::gpiox::Parts`), the
+/// other pins remain available. `XSHUT` is set high right away.
+#[macro_export]
+macro_rules! board_pins {
+    ($gpioa:ident, $gpiob:ident, $gpioc:ident) => {
+        $crate::board::Pins {
+            #[cfg(not(feature = "pins-i2c-pb6-pb7"))]
+            i2c: ($gpiob.pb8, $gpiob.pb9),
+            #[cfg(feature = "pins-i2c-pb6-pb7")]
+            i2c: ($gpiob.pb6, $gpiob.pb7),
+            #[cfg(not(feature = "pins-tof-interrupt-pb0"))]
+            tof_interrupt: $gpioa.pa0.into_pull_down_input(),
+            #[cfg(feature = "pins-tof-interrupt-pb0")]
+            tof_interrupt: $gpiob.pb0.into_pull_down_input(),
+            tof_shutdown: $gpiob
+                .pb2
+                .into_push_pull_output_in_state(::stm32f4xx_hal::gpio::PinState::High),
+            led: $gpioa.pa5.into_push_pull_output(),
+            button: $gpioc.pc13.into_pull_up_input(),
+            vcp: ($gpioa.pa2, $gpioa.pa3),
+        }
+    };
+}
@@ -0,0 +1,118 @@
+//! Configuration which is selected at boot, allowing identical boards to be deployed with different
+//! roles using the same binary.
+//!
+//! With the `dip-switch` feature it's read from a DIP switch (switches to GND, the internal
+//! pull-ups are used), with the `gpio-expander` feature from the switches on port A of the
+//! expander (`GPA0` for switch 1 etc., see [`crate::gpio_expander`]), otherwise the defaults are
+//! used:
+//!
+//! | Switch | Pin    | Setting                                                               |
+//! |--------|--------|-----------------------------------------------------------------------|
+//! | 1      | `PC4`  | on = [`AppMode::Presence`]                                            |
+//! | 2      | `PC5`  | on = [`AppMode::ParkingAssist`], [`AppMode::Streaming`] if both off,  |
+//! |        |        | [`AppMode::Rollup`] if both on                                        |
+//! | 3      | `PB10` | [`FrameFormat`]: off = CSV, on = JSON                                 |
+//! | 4      | `PA15` | I2C address of the TOF sensor: off = default (`0x29`), on = `0x2A`    |
+//! | 5      | `PC0`  | [`Preset`] applied at boot: the binary number of switches 5 (1),      |
+//! | 6      | `PC1`  | 6 (2) & 7 (4), none if all are off                                    |
+//! | 7      | `PC2`  |                                                                       |
+
+use crate::app_mode::AppMode;
+use crate::preset::Preset;
+use crate::telemetry::FrameFormat;
+#[cfg(feature = "dip-switch")]
+use stm32f4xx_hal::gpio::{Input, PA15, PB10, PC0, PC1, PC2, PC4, PC5};
+
+#[cfg(all(feature = "dip-switch", feature = "stepper"))]
+compile_error!("the features `dip-switch` and `stepper` can't be combined as both use PC0 - PC2");
+#[cfg(all(feature = "dip-switch", feature = "gpio-expander"))]
+compile_error!(
+    "the features `dip-switch` and `gpio-expander` can't be combined as both read the switches"
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct BootConfig {
+    pub app_mode: AppMode,
+    pub frame_format: FrameFormat,
+    /// Added to the default I2C address of the TOF sensor.
+    pub i2c_address_offset: u8,
+    pub preset: Option<Preset>,
+}
+
+impl Default for BootConfig {
+    fn default() -> Self {
+        Self {
+            app_mode: AppMode::Streaming,
+            frame_format: FrameFormat::Csv,
+            i2c_address_offset: 0,
+            preset: None,
+        }
+    }
+}
+
+impl BootConfig {
+    /// The configuration selected by the switches, bit `n` is set if switch `n + 1` is on.
+    pub fn from_switches(switches: u8) -> Self {
+        let on = |switch: u8| switches & 1 << (switch - 1) != 0;
+        let app_mode = match (on(1), on(2)) {
+            (false, false) => AppMode::Streaming,
+            (true, false) => AppMode::Presence,
+            (false, true) => AppMode::ParkingAssist,
+            (true, true) => AppMode::Rollup,
+        };
+        Self {
+            app_mode,
+            frame_format: if on(3) {
+                FrameFormat::Json
+            } else {
+                FrameFormat::Csv
+            },
+            i2c_address_offset: on(4) as u8,
+            preset: Preset::from_number(switches >> 4 & 0b111),
+        }
+    }
+
+    /// Read the configuration from the DIP switch, the pins must have their pull-ups enabled.
+    #[cfg(feature = "dip-switch")]
+    pub fn read(
+        mode_0: PC4<Input>,
+        mode_1: PC5<Input>,
+        json: PB10<Input>,
+        offset: PA15<Input>,
+        preset: (PC0<Input>, PC1<Input>, PC2<Input>),
+    ) -> Self {
+        // give the pull-ups time to charge the lines
+        cortex_m::asm::delay(1_000);
+        // a closed switch pulls the pin low
+        let switches = [
+            mode_0.is_low(),
+            mode_1.is_low(),
+            json.is_low(),
+            offset.is_low(),
+            preset.0.is_low(),
+            preset.1.is_low(),
+            preset.2.is_low(),
+        ];
+        Self::from_switches(
+            switches
+                .iter()
+                .enumerate()
+                .fold(0, |bits, (index, &on)| bits | (on as u8) << index),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches_select_the_configuration() {
+        assert_eq!(BootConfig::from_switches(0), BootConfig::default());
+        let config = BootConfig::from_switches(0b011_0111);
+        assert_eq!(config.app_mode, AppMode::Rollup);
+        assert_eq!(config.frame_format, FrameFormat::Json);
+        assert_eq!(config.i2c_address_offset, 0);
+        assert_eq!(config.preset, Preset::from_number(3));
+    }
+}
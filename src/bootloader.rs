@@ -0,0 +1,48 @@
+//! Entry into the bootloader in the system memory (ROM) of the STM32, so that a new firmware can be
+//! flashed over the UART (`USART1`/`USART2`) or USB (DFU) without a debug probe, see AN2606.
+//!
+//! The bootloader expects the peripherals in their reset state. Instead of de-initialising each of
+//! them the firmware marks the request in a backup register of the [`crate::clock`] & resets, the
+//! next boot then jumps to the bootloader before anything else has been set up. The request is
+//! cleared before the jump, thus a reset while in the bootloader starts the firmware again.
+
+use cortex_m::peripheral::{NVIC, SCB};
+use stm32f4xx_hal::pac::SYSCFG;
+
+/// Start of the system memory, which contains the vector table of the bootloader.
+const SYSTEM_MEMORY: u32 = 0x1FFF_0000;
+/// Value of `MEM_MODE` which maps the system memory at address 0.
+const MEM_MODE_SYSTEM_FLASH: u8 = 0b01;
+/// Time between the response to a command (e.g. `dfu`) & the reset, so that the links can send it.
+pub const RESET_DELAY_MS: u32 = 100;
+
+/// Reset so that the next boot enters the bootloader, see
+/// [`crate::clock::Clock::request_bootloader`] which has to be called before.
+pub fn reset() -> ! {
+    defmt::info!("resetting into the bootloader");
+    SCB::sys_reset()
+}
+
+/// Jump to the bootloader. Must be called right after the reset (before clocks, interrupts or the
+/// watchdog have been set up) with the clock of `SYSCFG` enabled.
+#[allow(unsafe_code)]
+pub fn jump(syscfg: &SYSCFG) -> ! {
+    defmt::info!("jumping to the bootloader");
+    // the bootloader uses its own interrupts, no pending ones of the firmware may be left over
+    let nvic = NVIC::PTR;
+    unsafe {
+        for i in 0..(*nvic).icer.len() {
+            (*nvic).icer[i].write(u32::MAX);
+            (*nvic).icpr[i].write(u32::MAX);
+        }
+    }
+    // the bootloader expects its vector table at address 0
+    syscfg
+        .memrm
+        .modify(|_, w| unsafe { w.mem_mode().bits(MEM_MODE_SYSTEM_FLASH) });
+    // the system memory starts with the initial stack pointer & the reset handler of the bootloader
+    unsafe {
+        cortex_m::interrupt::enable();
+        cortex_m::asm::bootload(SYSTEM_MEMORY as *const u32)
+    }
+}
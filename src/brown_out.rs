@@ -0,0 +1,71 @@
+//! Monitoring of the supply voltage: the brown-out reset (BOR) holds the microcontroller in reset
+//! below [`BOR_LEVEL`], the level is written to the option bytes at boot (see [`set_bor_level`]).
+//! Above it the programmable voltage detector (PVD) raises the `PVD` interrupt (EXTI line 16)
+//! whenever the supply crosses [`PVD_LEVEL_MV`] (see [`SupplyMonitor`]), which gives the firmware
+//! time to stop the data loggers before a write is cut off by the reset.
+//!
+//! The default BOR level 3 (2.7 V) keeps the TOF sensor, which needs at least 2.6 V, within its
+//! operating range.
+//!
+//! [`BOR_LEVEL`]: crate::config::brown_out::BOR_LEVEL
+//! [`PVD_LEVEL_MV`]: crate::config::brown_out::PVD_LEVEL_MV
+
+use crate::config::brown_out::{BOR_LEVEL, PVD_LEVEL_MV};
+use crate::option_bytes;
+use stm32f4xx_hal::pac::{EXTI, FLASH, PWR};
+
+/// The value of `BOR_LEV` in `FLASH_OPTCR` for a level, 0 is off.
+const fn bor_lev(level: u8) -> u8 {
+    match level {
+        0 => 0b11,
+        1 => 0b10,
+        2 => 0b01,
+        _ => 0b00,
+    }
+}
+
+/// Write [`BOR_LEVEL`] to the [`option_bytes`] if they contain another level, returns whether they
+/// have been written. The new level applies immediately.
+#[allow(unsafe_code)]
+pub fn set_bor_level(flash: &mut FLASH) -> Result<bool, option_bytes::Error> {
+    let bor_lev = bor_lev(BOR_LEVEL);
+    if flash.optcr.read().bor_lev().bits() == bor_lev {
+        return Ok(false);
+    }
+    // SAFETY: all values of the 2 bits are valid levels
+    option_bytes::program(flash, |w| unsafe { w.bor_lev().bits(bor_lev) })?;
+    Ok(true)
+}
+
+/// The PVD, which raises its interrupt on both edges.
+pub struct SupplyMonitor {
+    pwr: PWR,
+}
+
+impl SupplyMonitor {
+    /// Enable the PVD at [`PVD_LEVEL_MV`], the clock of the `PWR` has to be enabled already (by
+    /// [`crate::clock::Clock::new`]).
+    #[allow(unsafe_code)]
+    pub fn new(pwr: PWR, exti: &mut EXTI) -> Self {
+        let pls = ((PVD_LEVEL_MV - 2_200) / 100) as u8;
+        // SAFETY: the level has been checked at compile time
+        pwr.cr
+            .modify(|_, w| unsafe { w.pls().bits(pls) }.pvde().set_bit());
+        exti.rtsr.modify(|_, w| w.tr16().set_bit());
+        exti.ftsr.modify(|_, w| w.tr16().set_bit());
+        exti.imr.modify(|_, w| w.mr16().set_bit());
+        Self { pwr }
+    }
+
+    /// Whether the supply is below [`PVD_LEVEL_MV`].
+    pub fn is_low(&self) -> bool {
+        self.pwr.csr.read().pvdo().bit_is_set()
+    }
+
+    #[allow(unsafe_code)]
+    pub fn clear_interrupt(&mut self) {
+        // SAFETY: writing 1 only clears the pending bit of the PVD, the ones of the other lines
+        // aren't changed
+        unsafe { (*EXTI::ptr()).pr.write(|w| w.pr16().clear()) };
+    }
+}
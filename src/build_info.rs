@@ -0,0 +1,11 @@
+//! Identification of the firmware, so that the data of deployed devices can be traced back to the
+//! build. The git hash & the time of the build are provided by `build.rs`.
+
+/// Version of the crate.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Abbreviated hash of the commit, followed by `-dirty` if there were uncommitted changes,
+/// `unknown` if it was built outside of a git repository.
+pub const GIT_HASH: &str = env!("GIT_HASH");
+/// Time of the build in seconds since the Unix epoch (`SOURCE_DATE_EPOCH` for reproducible
+/// builds).
+pub const BUILD_UNIX_S: u32 = crate::config::parse_u32(env!("BUILD_UNIX_S"));
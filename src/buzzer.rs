@@ -0,0 +1,160 @@
+//! Parking assist using a piezo buzzer on `PB6` (TIM4 CH1).
+//!
+//! The closer the target, the faster the buzzer beeps and the higher its tone, up to a continuous
+//! tone in the near zone (see [`crate::config::buzzer`]). It is silent beyond the far zone and for
+//! invalid measurements. A zone of the [`crate::geofence`] can additionally chirp it.
+
+use crate::config::buzzer as config;
+use crate::telemetry::Measurement;
+use stm32f4xx_hal::pac::TIM4;
+use stm32f4xx_hal::prelude::*;
+use stm32f4xx_hal::timer::{Channel, Channel1, PwmHz};
+use vl53l1x_uld::RangeStatus;
+
+/// Length of a single beep.
+const BEEP_MS: u32 = 80;
+/// Length of a chirp, see [`Buzzer::chirp`].
+const CHIRP_MS: u32 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum Zone {
+    Silent,
+    Far,
+    Middle,
+    Near,
+}
+
+impl Zone {
+    fn of(measurement: &Measurement) -> Self {
+        match measurement.distance_mm {
+            _ if measurement.status != RangeStatus::Valid => Zone::Silent,
+            d if d <= config::NEAR_MM => Zone::Near,
+            d if d <= config::MIDDLE_MM => Zone::Middle,
+            d if d <= config::FAR_MM => Zone::Far,
+            _ => Zone::Silent,
+        }
+    }
+
+    /// Time between the start of two beeps, `None` for no beeps & `Some(0)` for a continuous tone.
+    fn beep_interval_ms(&self) -> Option<u32> {
+        match self {
+            Zone::Silent => None,
+            Zone::Far => Some(600),
+            Zone::Middle => Some(200),
+            Zone::Near => Some(0),
+        }
+    }
+
+    fn tone_hz(&self) -> u32 {
+        match self {
+            Zone::Silent | Zone::Far => 2_000,
+            Zone::Middle => 2_500,
+            Zone::Near => 3_000,
+        }
+    }
+}
+
+pub struct Buzzer {
+    pwm: PwmHz<TIM4, Channel1<TIM4>>,
+    zone: Zone,
+    muted: bool,
+    /// Whether the tone is currently on.
+    sounding: bool,
+    /// Time at which the last beep has started.
+    beep_started_ms: u32,
+    /// Whether a chirp starts with the next tick.
+    chirp_pending: bool,
+    /// Time at which the last chirp has started.
+    chirp_started_ms: Option<u32>,
+}
+
+impl Buzzer {
+    pub fn new(pwm: PwmHz<TIM4, Channel1<TIM4>>) -> Self {
+        let mut buzzer = Self {
+            pwm,
+            zone: Zone::Silent,
+            muted: false,
+            sounding: false,
+            beep_started_ms: 0,
+            chirp_pending: false,
+            chirp_started_ms: None,
+        };
+        buzzer.set_zone(Zone::Silent);
+        buzzer
+    }
+
+    /// Mute or unmute the buzzer.
+    pub fn set_muted(&mut self, muted: bool) {
+        defmt::info!("buzzer {}", if muted { "muted" } else { "enabled" });
+        self.muted = muted;
+        if muted {
+            self.set_sounding(false);
+        }
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Switch to the zone of the measurement.
+    pub fn update(&mut self, measurement: &Measurement) {
+        let zone = Zone::of(measurement);
+        if zone != self.zone {
+            self.set_zone(zone);
+        }
+    }
+
+    /// Silence the buzzer as the TOF sensor is lost, until the next measurement.
+    pub fn failsafe(&mut self) {
+        self.set_zone(Zone::Silent);
+    }
+
+    /// Sound the buzzer for [`CHIRP_MS`] regardless of the distance (unless it's muted).
+    pub fn chirp(&mut self) {
+        self.chirp_pending = true;
+    }
+
+    /// Generate the beeps. Must be called periodically.
+    pub fn tick(&mut self, now_ms: u32) {
+        if core::mem::take(&mut self.chirp_pending) {
+            self.chirp_started_ms = Some(now_ms);
+        }
+        let chirping = self
+            .chirp_started_ms
+            .is_some_and(|started_ms| now_ms.wrapping_sub(started_ms) < CHIRP_MS);
+        let on = match self.zone.beep_interval_ms() {
+            _ if self.muted => false,
+            _ if chirping => true,
+            None => false,
+            Some(0) => true,
+            Some(interval) => {
+                let elapsed = now_ms.wrapping_sub(self.beep_started_ms);
+                if elapsed >= interval {
+                    self.beep_started_ms = now_ms;
+                }
+                now_ms.wrapping_sub(self.beep_started_ms) < BEEP_MS
+            }
+        };
+        self.set_sounding(on);
+    }
+
+    fn set_zone(&mut self, zone: Zone) {
+        defmt::debug!("buzzer zone: {}", zone);
+        self.zone = zone;
+        self.pwm.set_period(zone.tone_hz().Hz());
+        // the maximum duty depends on the period, 50 % gives the loudest tone
+        self.pwm.set_duty(Channel::C1, self.pwm.get_max_duty() / 2);
+    }
+
+    fn set_sounding(&mut self, sounding: bool) {
+        if sounding == self.sounding {
+            return;
+        }
+        self.sounding = sounding;
+        if sounding {
+            self.pwm.enable(Channel::C1);
+        } else {
+            self.pwm.disable(Channel::C1);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! The cadence of [`Acquisition::Timed`](crate::acquisition::Acquisition::Timed): instead of the
+//! free-running inter-measurement period of the sensor, TIM11 starts each measurement (a single
+//! one, like a pulse of the [`crate::trigger`] input), which gives control applications an exact
+//! sampling rate.
+//!
+//! The timer is restarted for each period, the latency of the interrupt & of the restart would add
+//! up. Thus each start is scheduled at an absolute time of the monotonic timer (`start + n *
+//! period`) & the delay until it's corrected by the error measured at the previous start, the
+//! error stays bounded by the latency instead of drifting. A start which is late by a whole period
+//! (e.g. after a long critical section) is skipped & the schedule continues from the current time.
+//!
+//! TIM11 only has 16 bits, delays beyond [`MAX_CHUNK_US`] are split into several runs.
+
+use stm32f4xx_hal::pac::TIM11;
+use stm32f4xx_hal::prelude::*;
+use stm32f4xx_hal::timer::{CounterUs, Event};
+
+/// The shortest delay the timer is started with, the next start is late rather than in the past.
+const MIN_DELAY_US: u32 = 50;
+/// The longest run of the timer, it overflows at 65.5 ms.
+const MAX_CHUNK_US: u32 = 60_000;
+
+/// The schedule of the starts, in µs of the monotonic timer.
+#[derive(Debug, Clone, PartialEq, Eq, defmt::Format)]
+pub struct Cadence {
+    period_us: u32,
+    /// The time at which the next start is due.
+    due_us: u32,
+    /// The largest deviation of a start from its due time.
+    max_error_us: u32,
+    /// Number of skipped starts.
+    skipped: u32,
+}
+
+impl Cadence {
+    /// Schedule the first start one period after `now_us`.
+    pub fn new(period_us: u32, now_us: u32) -> Self {
+        Self {
+            period_us,
+            due_us: now_us.wrapping_add(period_us),
+            max_error_us: 0,
+            skipped: 0,
+        }
+    }
+
+    /// The delay until the first start.
+    pub fn first_delay_us(&self) -> u32 {
+        self.period_us
+    }
+
+    /// Handle a start at `now_us`, returns the delay until the next one.
+    pub fn on_start(&mut self, now_us: u32) -> u32 {
+        let error_us = now_us.wrapping_sub(self.due_us) as i32;
+        self.max_error_us = self.max_error_us.max(error_us.unsigned_abs());
+        self.due_us = self.due_us.wrapping_add(self.period_us);
+        if error_us >= self.period_us as i32 {
+            self.skipped = self.skipped.wrapping_add(1);
+            self.due_us = now_us.wrapping_add(self.period_us);
+        }
+        (self.due_us.wrapping_sub(now_us) as i32).max(MIN_DELAY_US as i32) as u32
+    }
+
+    pub fn period_us(&self) -> u32 {
+        self.period_us
+    }
+
+    pub fn max_error_us(&self) -> u32 {
+        self.max_error_us
+    }
+
+    pub fn skipped(&self) -> u32 {
+        self.skipped
+    }
+}
+
+/// TIM11 running the [`Cadence`].
+pub struct CadenceTimer {
+    timer: CounterUs<TIM11>,
+    cadence: Option<Cadence>,
+    /// The rest of the delay after the current run.
+    remaining_us: u32,
+}
+
+impl CadenceTimer {
+    pub fn new(mut timer: CounterUs<TIM11>) -> Self {
+        timer.listen(Event::Update);
+        Self {
+            timer,
+            cadence: None,
+            remaining_us: 0,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.cadence.is_some()
+    }
+
+    /// Start the cadence with the period, the first measurement starts after it.
+    pub fn start(&mut self, period_us: u32, now_us: u32) {
+        let cadence = Cadence::new(period_us, now_us);
+        self.schedule(cadence.first_delay_us());
+        self.cadence = Some(cadence);
+    }
+
+    pub fn stop(&mut self) {
+        self.timer.cancel().ok();
+        if let Some(cadence) = self.cadence.take() {
+            defmt::info!(
+                "cadence stopped: period {} us, max. error {} us, {} skipped",
+                cadence.period_us(),
+                cadence.max_error_us(),
+                cadence.skipped()
+            );
+        }
+    }
+
+    /// Handle the interrupt of TIM11 at `now_us`, returns whether a measurement is due.
+    pub fn on_interrupt(&mut self, now_us: u32) -> bool {
+        // clears the flag
+        let elapsed = self.timer.wait().is_ok();
+        if !elapsed || self.cadence.is_none() {
+            return false;
+        }
+        if self.remaining_us > 0 {
+            self.schedule(self.remaining_us);
+            return false;
+        }
+        let delay_us = self
+            .cadence
+            .as_mut()
+            .map_or(0, |cadence| cadence.on_start(now_us));
+        self.schedule(delay_us);
+        true
+    }
+
+    fn schedule(&mut self, delay_us: u32) {
+        let run_us = delay_us.min(MAX_CHUNK_US);
+        self.remaining_us = delay_us - run_us;
+        self.timer.start(run_us.micros()).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compensates_the_latency() {
+        let mut cadence = Cadence::new(10_000, u32::MAX - 4_999);
+        // due at 5_000, 30 us late: the next start is due at 15_000
+        assert_eq!(cadence.on_start(5_030), 9_970);
+        // 20 us early
+        assert_eq!(cadence.on_start(14_980), 10_020);
+        assert_eq!(cadence.max_error_us(), 30);
+        // a whole period late: skipped, the schedule continues from now
+        assert_eq!(cadence.on_start(37_000), 10_000);
+        assert_eq!(cadence.skipped(), 1);
+        // almost a period late, the next start is due right away
+        assert_eq!(cadence.on_start(56_980), MIN_DELAY_US);
+    }
+}
@@ -0,0 +1,210 @@
+//! Offset & crosstalk calibration of the TOF sensor, following the procedures of ST's ULD.
+//!
+//! For the offset a target (ideally grey, 17 % reflectance) has to be placed at a known distance
+//! in front of the sensor, [`config::TARGET_MM`] unless it has been changed with `cal target` (e.g.
+//! because the enclosure doesn't leave room for a target that close). The offset of the sensor is
+//! reset and the average of the following valid measurements is compared to the known distance,
+//! the difference is the new offset.
+//!
+//! For the crosstalk (of a cover glass) the target has to be placed at
+//! [`config::CROSS_TALK_TARGET_MM`], where the light reflected by the glass makes it appear closer
+//! than it is. The crosstalk correction is reset and the signal rate is scaled by how much too
+//! close the target appears on average.
+//!
+//! Unlike `VL53L1X::calibrate_offset` & `VL53L1X::calibrate_cross_talk` this doesn't block until
+//! all samples have been measured but uses the regular measurements, thus the firmware (e.g. the
+//! watchdog) keeps running.
+
+use crate::config::calibration as config;
+use crate::range_sensor::Reading;
+use crate::telemetry::Measurement;
+use vl53l1x_uld::RangeStatus;
+
+/// The farthest target of the offset calibration, the range of the sensor in the long distance
+/// mode.
+pub const MAX_TARGET_MM: u16 = 4_000;
+
+/// Number of measurements which are averaged.
+const SAMPLES: u32 = 50;
+/// The calibration fails if there are more invalid measurements than this, e.g. because there is
+/// no target.
+const MAX_INVALID: u32 = 50;
+
+/// Result of feeding a measurement to a calibration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Progress<T = i16> {
+    Running,
+    /// The calibration has finished, this is the new offset (or crosstalk correction) which has to
+    /// be set.
+    Done(T),
+    Failed,
+}
+
+pub struct OffsetCalibration {
+    /// Sum of the distances of the valid measurements.
+    sum: u32,
+    count: u32,
+    invalid: u32,
+    /// The distance to the target.
+    target_mm: u16,
+    /// Whether the sensor was ranging before the calibration started.
+    was_ranging: bool,
+    /// The offset before the calibration, it's restored if the calibration fails.
+    previous_offset: i16,
+}
+
+impl OffsetCalibration {
+    /// Start a calibration with a target at `target_mm`, the offset of the sensor must be reset to
+    /// 0 & it must be ranging.
+    pub fn new(was_ranging: bool, previous_offset: i16, target_mm: u16) -> Self {
+        defmt::info!("offset calibration with a target at {} mm", target_mm);
+        Self {
+            sum: 0,
+            count: 0,
+            invalid: 0,
+            target_mm,
+            was_ranging,
+            previous_offset,
+        }
+    }
+
+    pub fn previous_offset(&self) -> i16 {
+        self.previous_offset
+    }
+
+    /// Whether the sensor should keep ranging after the calibration.
+    pub fn was_ranging(&self) -> bool {
+        self.was_ranging
+    }
+
+    /// Add a measurement to the calibration.
+    pub fn add(&mut self, measurement: &Measurement) -> Progress {
+        if measurement.status != RangeStatus::Valid {
+            self.invalid += 1;
+            return if self.invalid > MAX_INVALID {
+                defmt::warn!("offset calibration failed, no valid target");
+                Progress::Failed
+            } else {
+                Progress::Running
+            };
+        }
+
+        self.sum += measurement.distance_mm as u32;
+        self.count += 1;
+        if self.count < SAMPLES {
+            return Progress::Running;
+        }
+        let average = (self.sum / self.count) as i32;
+        let offset = (self.target_mm as i32 - average).clamp(i16::MIN as i32, i16::MAX as i32);
+        defmt::info!("offset calibration done: {} mm", offset);
+        Progress::Done(offset as i16)
+    }
+}
+
+pub struct CrossTalkCalibration {
+    /// Sums of the distances & the signal rates per SPAD of the valid measurements.
+    distance_sum: u32,
+    signal_sum: u32,
+    count: u32,
+    invalid: u32,
+}
+
+impl CrossTalkCalibration {
+    /// Start a calibration, the crosstalk correction of the sensor must be reset to 0 & it must be
+    /// ranging.
+    pub fn new() -> Self {
+        defmt::info!(
+            "crosstalk calibration with a target at {} mm",
+            config::CROSS_TALK_TARGET_MM
+        );
+        Self {
+            distance_sum: 0,
+            signal_sum: 0,
+            count: 0,
+            invalid: 0,
+        }
+    }
+
+    /// Add a measurement to the calibration, the result is the crosstalk correction in counts per
+    /// second.
+    pub fn add(&mut self, reading: &Reading) -> Progress<u16> {
+        if reading.status != RangeStatus::Valid {
+            self.invalid += 1;
+            return if self.invalid > MAX_INVALID {
+                defmt::warn!("crosstalk calibration failed, no valid target");
+                Progress::Failed
+            } else {
+                Progress::Running
+            };
+        }
+
+        self.distance_sum += reading.distance_mm as u32;
+        self.signal_sum += reading.signal_per_spad_kcps as u32;
+        self.count += 1;
+        if self.count < SAMPLES {
+            return Progress::Running;
+        }
+        let distance = (self.distance_sum / self.count) as u64;
+        let signal_kcps = (self.signal_sum / self.count) as u64;
+        let target = config::CROSS_TALK_TARGET_MM as u64;
+        // the share of the signal which is reflected by the glass
+        let cross_talk_cps = signal_kcps * 1_000 * target.saturating_sub(distance) / target;
+        let cross_talk_cps = cross_talk_cps.min(u16::MAX as u64) as u16;
+        defmt::info!("crosstalk calibration done: {} cps", cross_talk_cps);
+        Progress::Done(cross_talk_cps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test_measurement;
+
+    fn reading(distance_mm: u16, status: RangeStatus) -> Reading {
+        Reading {
+            distance_mm,
+            status,
+            ambient_kcps: 0,
+            signal_per_spad_kcps: 20,
+        }
+    }
+
+    #[test]
+    fn offset_from_the_target() {
+        let mut calibration = OffsetCalibration::new(true, 0, 300);
+        for _ in 1..SAMPLES {
+            assert_eq!(
+                calibration.add(&test_measurement(0, 279)),
+                Progress::Running
+            );
+        }
+        // the average is 279 mm
+        assert_eq!(
+            calibration.add(&test_measurement(0, 290)),
+            Progress::Done(21)
+        );
+    }
+
+    #[test]
+    fn cross_talk_from_the_underranging() {
+        let target = config::CROSS_TALK_TARGET_MM;
+        let mut calibration = CrossTalkCalibration::new();
+        for _ in 1..SAMPLES {
+            let progress = calibration.add(&reading(target * 3 / 4, RangeStatus::Valid));
+            assert_eq!(progress, Progress::Running);
+        }
+        assert_eq!(
+            calibration.add(&reading(target * 3 / 4, RangeStatus::Valid)),
+            Progress::Done(5_000)
+        );
+    }
+
+    #[test]
+    fn cross_talk_without_target_fails() {
+        let mut calibration = CrossTalkCalibration::new();
+        let progress: Vec<_> = (0..=MAX_INVALID)
+            .map(|_| calibration.add(&reading(0, RangeStatus::SignalFailure)))
+            .collect();
+        assert_eq!(progress.last(), Some(&Progress::Failed));
+    }
+}
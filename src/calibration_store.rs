@@ -0,0 +1,59 @@
+//! Persists the calibration of the TOF sensor in the [`crate::eeprom`], it's applied at boot.
+
+use crate::eeprom::{self, Eeprom};
+use stm32f4xx_hal::flash;
+use stm32f4xx_hal::pac::FLASH;
+use stm32f4xx_hal::watchdog::IndependentWatchdog;
+
+/// Version of the format in which the calibration is stored.
+const EEPROM_VERSION: u8 = 1;
+
+/// The calibration of the TOF sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct CalibrationData {
+    pub offset_mm: i16,
+    /// The crosstalk correction in counts per second.
+    pub cross_talk_cps: u16,
+}
+
+impl CalibrationData {
+    /// Layout: offset & crosstalk.
+    fn encode(&self) -> [u8; 4] {
+        let mut value = [0; 4];
+        value[..2].copy_from_slice(&self.offset_mm.to_le_bytes());
+        value[2..].copy_from_slice(&self.cross_talk_cps.to_le_bytes());
+        value
+    }
+
+    fn decode(value: &[u8; 4]) -> Self {
+        Self {
+            offset_mm: i16::from_le_bytes([value[0], value[1]]),
+            cross_talk_cps: u16::from_le_bytes([value[2], value[3]]),
+        }
+    }
+}
+
+/// The stored calibration, `None` if none has been saved yet.
+pub fn load(eeprom: &Eeprom, flash: &FLASH) -> Option<CalibrationData> {
+    eeprom
+        .read_array(flash, eeprom::keys::CALIBRATION, EEPROM_VERSION)
+        .map(|value| CalibrationData::decode(&value))
+}
+
+/// Save the calibration, see [`Eeprom::write`] for the watchdog.
+pub fn save(
+    eeprom: &mut Eeprom,
+    flash: &mut FLASH,
+    data: &CalibrationData,
+    watchdog: &mut IndependentWatchdog,
+) -> Result<(), flash::Error> {
+    eeprom.write(
+        flash,
+        eeprom::keys::CALIBRATION,
+        EEPROM_VERSION,
+        &data.encode(),
+        watchdog,
+    )?;
+    defmt::info!("saved {}", data);
+    Ok(())
+}
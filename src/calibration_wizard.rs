@@ -0,0 +1,245 @@
+//! Guided calibration with the `cal` commands: `cal wizard` walks through the offset & the
+//! crosstalk calibration (see [`crate::calibration`]), each response tells what to do next.
+//!
+//! 1. Place a target at the target distance of the offset calibration (see `cal target`) & send
+//!    `cal next`, the offset is measured.
+//! 2. Place a target at [`config::CROSS_TALK_TARGET_MM`] & send `cal next`, the crosstalk is
+//!    measured.
+//! 3. Review the results, `cal save` stores them, `cal discard` (at any step) restores the previous
+//!    calibration.
+//!
+//! The response to `cal next` is only sent once the measurement is done, if it failed (no valid
+//! target) the step can be repeated. The results are applied to the sensor right away so that the
+//! measurements can be checked before they're stored.
+
+use crate::calibration::{CrossTalkCalibration, Progress};
+use crate::calibration_store::CalibrationData;
+use crate::config::calibration as config;
+use crate::range_sensor::Reading;
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Step {
+    /// Waiting for the target of the offset calibration.
+    OffsetTarget,
+    /// The offset is measured by the [`crate::calibration::OffsetCalibration`].
+    Offset,
+    /// Waiting for the target of the crosstalk calibration.
+    CrossTalkTarget,
+    CrossTalk,
+    /// Waiting for the results to be stored or discarded.
+    Review,
+}
+
+pub struct CalibrationWizard {
+    step: Step,
+    /// The calibration before the wizard, it's restored if the results are discarded.
+    previous: CalibrationData,
+    /// The distance to the target of the offset calibration.
+    offset_target_mm: u16,
+    /// The results so far, the previous calibration for the steps which haven't been done yet.
+    calibration: CalibrationData,
+    /// The running crosstalk calibration.
+    cross_talk: Option<CrossTalkCalibration>,
+    /// Whether the sensor was ranging before the running measurement.
+    was_ranging: bool,
+    /// Whether the latest measurement failed.
+    failed: bool,
+}
+
+impl CalibrationWizard {
+    pub fn new(previous: CalibrationData, offset_target_mm: u16) -> Self {
+        Self {
+            step: Step::OffsetTarget,
+            previous,
+            offset_target_mm,
+            calibration: previous,
+            cross_talk: None,
+            was_ranging: false,
+            failed: false,
+        }
+    }
+
+    pub fn step(&self) -> Step {
+        self.step
+    }
+
+    pub fn previous(&self) -> CalibrationData {
+        self.previous
+    }
+
+    /// The calibration which has to be applied to the sensor.
+    pub fn calibration(&self) -> CalibrationData {
+        self.calibration
+    }
+
+    /// Whether the sensor should keep ranging after the running measurement.
+    pub fn was_ranging(&self) -> bool {
+        self.was_ranging
+    }
+
+    pub fn is_measuring(&self) -> bool {
+        matches!(self.step, Step::Offset | Step::CrossTalk)
+    }
+
+    /// Continue with the measurement as the target has been placed (`cal next`), returns the step
+    /// of it or `None` if the wizard isn't waiting for a target. The sensor has to be prepared for
+    /// the calibration by the caller.
+    pub fn next(&mut self, was_ranging: bool) -> Option<Step> {
+        self.step = match self.step {
+            Step::OffsetTarget => Step::Offset,
+            Step::CrossTalkTarget => {
+                self.cross_talk = Some(CrossTalkCalibration::new());
+                Step::CrossTalk
+            }
+            _ => return None,
+        };
+        self.was_ranging = was_ranging;
+        Some(self.step)
+    }
+
+    /// The offset calibration has finished, with the new offset or `None` if it failed.
+    pub fn on_offset(&mut self, offset_mm: Option<i16>) {
+        if self.step != Step::Offset {
+            return;
+        }
+        self.failed = offset_mm.is_none();
+        match offset_mm {
+            Some(offset_mm) => {
+                self.calibration.offset_mm = offset_mm;
+                self.step = Step::CrossTalkTarget;
+            }
+            None => self.step = Step::OffsetTarget,
+        }
+    }
+
+    /// Add a measurement to the running crosstalk calibration, once it has finished the
+    /// [`CalibrationWizard::calibration`] has to be applied.
+    pub fn add(&mut self, reading: &Reading) -> Progress<u16> {
+        let Some(cross_talk) = self.cross_talk.as_mut() else {
+            return Progress::Running;
+        };
+        let progress = cross_talk.add(reading);
+        match progress {
+            Progress::Running => return progress,
+            Progress::Done(cross_talk_cps) => {
+                self.calibration.cross_talk_cps = cross_talk_cps;
+                self.step = Step::Review;
+            }
+            Progress::Failed => {
+                self.calibration.cross_talk_cps = self.previous.cross_talk_cps;
+                self.step = Step::CrossTalkTarget;
+            }
+        }
+        self.failed = progress == Progress::Failed;
+        self.cross_talk = None;
+        progress
+    }
+
+    /// Write the response which tells what to do next.
+    pub fn prompt(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        let target_mm = match self.step {
+            Step::OffsetTarget => self.offset_target_mm,
+            Step::CrossTalkTarget => config::CROSS_TALK_TARGET_MM,
+            Step::Offset | Step::CrossTalk => return write!(out, "OK cal measuring\r\n"),
+            Step::Review => {
+                return write!(
+                    out,
+                    "OK cal offset_mm={} cross_talk_cps={} step=3/3 send \"cal save\" to store the \
+                     calibration or \"cal discard\"\r\n",
+                    self.calibration.offset_mm,
+                    self.calibration.cross_talk_cps
+                )
+            }
+        };
+        if self.failed {
+            write!(out, "ERR cal no valid target, ")?;
+        } else if self.step == Step::OffsetTarget {
+            write!(out, "OK cal step=1/3 ")?;
+        } else {
+            write!(
+                out,
+                "OK cal offset_mm={} step=2/3 ",
+                self.calibration.offset_mm
+            )?;
+        }
+        write!(
+            out,
+            "place a target at {} mm & send \"cal next\"\r\n",
+            target_mm
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Response;
+    use vl53l1x_uld::RangeStatus;
+
+    const PREVIOUS: CalibrationData = CalibrationData {
+        offset_mm: 5,
+        cross_talk_cps: 100,
+    };
+
+    fn prompt(wizard: &CalibrationWizard) -> Response {
+        let mut response = Response::new();
+        wizard.prompt(&mut response).unwrap();
+        response
+    }
+
+    fn measure_cross_talk(wizard: &mut CalibrationWizard, status: RangeStatus) -> Progress<u16> {
+        let reading = Reading {
+            distance_mm: config::CROSS_TALK_TARGET_MM / 2,
+            status,
+            ambient_kcps: 0,
+            signal_per_spad_kcps: 10,
+        };
+        core::iter::repeat_with(|| wizard.add(&reading))
+            .find(|progress| *progress != Progress::Running)
+            .unwrap()
+    }
+
+    #[test]
+    fn offset_then_cross_talk() {
+        let mut wizard = CalibrationWizard::new(PREVIOUS, 250);
+        assert!(prompt(&wizard).starts_with("OK cal step=1/3 place a target at 250 mm"));
+        assert_eq!(wizard.next(true), Some(Step::Offset));
+        assert_eq!(wizard.next(true), None);
+        wizard.on_offset(Some(-12));
+        assert_eq!(wizard.step(), Step::CrossTalkTarget);
+        assert!(prompt(&wizard).starts_with("OK cal offset_mm=-12 step=2/3"));
+        assert_eq!(wizard.next(false), Some(Step::CrossTalk));
+        assert_eq!(
+            measure_cross_talk(&mut wizard, RangeStatus::Valid),
+            Progress::Done(5_000)
+        );
+        assert_eq!(
+            wizard.calibration(),
+            CalibrationData {
+                offset_mm: -12,
+                cross_talk_cps: 5_000
+            }
+        );
+        assert!(!wizard.was_ranging());
+        assert!(prompt(&wizard).starts_with("OK cal offset_mm=-12 cross_talk_cps=5000 step=3/3"));
+    }
+
+    #[test]
+    fn failed_steps_can_be_repeated() {
+        let mut wizard = CalibrationWizard::new(PREVIOUS, config::TARGET_MM);
+        wizard.next(true);
+        wizard.on_offset(None);
+        assert_eq!(wizard.step(), Step::OffsetTarget);
+        assert!(prompt(&wizard).starts_with("ERR cal no valid target"));
+        wizard.next(true);
+        wizard.on_offset(Some(1));
+        wizard.next(true);
+        assert_eq!(
+            measure_cross_talk(&mut wizard, RangeStatus::SignalFailure),
+            Progress::Failed
+        );
+        assert_eq!(wizard.step(), Step::CrossTalkTarget);
+        assert_eq!(wizard.calibration().cross_talk_cps, PREVIOUS.cross_talk_cps);
+    }
+}
@@ -0,0 +1,270 @@
+//! Bounded captures for scripted measurements: `capture <n> [<rate_hz>]` streams exactly the next
+//! `n` measurements, `capture <s>s [<rate_hz>]` those of the next `s` seconds, optionally at
+//! another measurement rate. The window is enclosed by a header & a footer frame with the
+//! completion status (see [`crate::telemetry::capture_start_frame`]), so that a script can
+//! reproduce a measurement without having to cut the stream itself.
+//!
+//! The window starts with the first measurement which isn't settling (see [`crate::warm_up`]), all
+//! measurements within it are published regardless of the application mode & the publish filters,
+//! those outside of it aren't published while the capture runs. The sensor starts ranging for the
+//! capture if it didn't range before & stops again afterwards, a changed rate is restored. A capture
+//! which doesn't complete within twice its expected duration times out.
+
+use crate::config::warm_up;
+use crate::settings::TofSettings;
+use crate::telemetry::Measurement;
+
+/// The highest rate of a capture, the shortest timing budget of the long distance mode is 20 ms.
+pub const MAX_RATE_HZ: u8 = 50;
+/// The longest duration of a capture.
+pub const MAX_DURATION_S: u16 = 3_600;
+/// Time added to the expected duration of a capture before it times out.
+const TIMEOUT_MARGIN_MS: u32 = 1_000;
+/// The timing budgets of the sensor which are valid in all distance modes.
+const TIMING_BUDGETS_MS: [u16; 6] = [20, 33, 50, 100, 200, 500];
+
+/// The end of a capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Limit {
+    /// After this many measurements.
+    Samples(u16),
+    /// After this many seconds since the first measurement.
+    Seconds(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Request {
+    pub limit: Limit,
+    /// The measurement rate during the capture, the current one for `None`.
+    pub rate_hz: Option<u8>,
+}
+
+impl Request {
+    /// The time after which the capture times out if the measurements arrive every `interval_ms`.
+    pub fn timeout_ms(&self, interval_ms: u32) -> u32 {
+        let interval_ms = match self.rate_hz {
+            Some(rate_hz) => 1_000 / u32::from(rate_hz),
+            None => interval_ms,
+        };
+        let expected_ms = match self.limit {
+            Limit::Samples(samples) => u32::from(samples) * interval_ms,
+            Limit::Seconds(seconds) => u32::from(seconds) * 1_000,
+        };
+        warm_up::DURATION_MS + 2 * expected_ms + TIMEOUT_MARGIN_MS
+    }
+}
+
+/// The completion status of a capture, reported in the footer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Outcome {
+    /// The whole window has been captured.
+    Complete,
+    /// The measurements stopped before the end of the window.
+    TimedOut,
+    /// Aborted with `capture abort`.
+    Aborted,
+}
+
+impl Outcome {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Complete => "complete",
+            Outcome::TimedOut => "timeout",
+            Outcome::Aborted => "aborted",
+        }
+    }
+}
+
+/// What to do with a measurement, see [`Capture::on_measurement`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// No capture is running, the measurement is handled as usual.
+    Idle,
+    /// The measurement is outside of the window & isn't published.
+    Excluded,
+    /// The measurement is in the window & is published.
+    Captured,
+    /// The measurement is the last one of the capture with this ID, which is complete.
+    Last(u16),
+    /// The measurement is past the end of the window, the capture with this ID is complete.
+    Ended(u16),
+}
+
+/// A running capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub id: u16,
+    pub request: Request,
+    /// Time of the first measurement, `None` until it has been captured.
+    start_ms: Option<u32>,
+    /// Number of captured measurements.
+    pub count: u32,
+    /// Whether the end of the window has been reached.
+    complete: bool,
+    /// Whether the sensor was ranging before the capture.
+    pub was_ranging: bool,
+    /// The settings to restore after a capture with another rate.
+    pub restore: Option<TofSettings>,
+}
+
+pub struct Capture {
+    window: Option<Window>,
+    /// The ID of the next capture, so that the frames of consecutive ones can be told apart.
+    next_id: u16,
+}
+
+impl Capture {
+    pub const fn new() -> Self {
+        Self {
+            window: None,
+            next_id: 1,
+        }
+    }
+
+    /// The running capture.
+    pub fn window(&self) -> Option<&Window> {
+        self.window.as_ref()
+    }
+
+    /// Start a capture, returns its ID.
+    pub fn start(
+        &mut self,
+        request: Request,
+        was_ranging: bool,
+        restore: Option<TofSettings>,
+    ) -> u16 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.window = Some(Window {
+            id,
+            request,
+            start_ms: None,
+            count: 0,
+            complete: false,
+            was_ranging,
+            restore,
+        });
+        id
+    }
+
+    /// End the capture with the ID, `None` if it has already ended.
+    pub fn finish(&mut self, id: u16) -> Option<Window> {
+        self.window.take_if(|window| window.id == id)
+    }
+
+    pub fn on_measurement(&mut self, measurement: &Measurement) -> Step {
+        let Some(window) = &mut self.window else {
+            return Step::Idle;
+        };
+        if window.complete || measurement.settling {
+            return Step::Excluded;
+        }
+        let start_ms = *window.start_ms.get_or_insert(measurement.timestamp_ms);
+        match window.request.limit {
+            Limit::Seconds(seconds)
+                if measurement.timestamp_ms.wrapping_sub(start_ms)
+                    >= u32::from(seconds) * 1_000 =>
+            {
+                window.complete = true;
+                return Step::Ended(window.id);
+            }
+            _ => {}
+        }
+        window.count += 1;
+        match window.request.limit {
+            Limit::Samples(samples) if window.count >= u32::from(samples) => {
+                window.complete = true;
+                Step::Last(window.id)
+            }
+            _ => Step::Captured,
+        }
+    }
+}
+
+/// The measurement settings for a capture at `rate_hz`: the timing budget is kept unless it's
+/// longer than the interval of the measurements, then it's shortened to the longest one which fits.
+pub fn settings_for_rate(settings: TofSettings, rate_hz: u8) -> TofSettings {
+    let interval_ms = 1_000 / u16::from(rate_hz);
+    let timing_budget_ms = if settings.timing_budget_ms <= interval_ms {
+        settings.timing_budget_ms
+    } else {
+        TIMING_BUDGETS_MS
+            .into_iter()
+            .rev()
+            .find(|&budget_ms| budget_ms <= interval_ms)
+            .unwrap_or(TIMING_BUDGETS_MS[0])
+    };
+    TofSettings {
+        timing_budget_ms,
+        inter_measurement_ms: interval_ms,
+        ..settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test_measurement;
+    use vl53l1x_uld::DistanceMode;
+
+    #[test]
+    fn captures_exactly_the_window() {
+        let mut capture = Capture::new();
+        assert_eq!(
+            capture.on_measurement(&test_measurement(0, 500)),
+            Step::Idle
+        );
+
+        let request = Request {
+            limit: Limit::Samples(2),
+            rate_hz: None,
+        };
+        let id = capture.start(request, false, None);
+        let steps = [(0, true), (100, false), (200, false), (300, false)].map(
+            |(timestamp_ms, settling)| {
+                capture.on_measurement(&Measurement {
+                    settling,
+                    ..test_measurement(timestamp_ms, 500)
+                })
+            },
+        );
+        assert_eq!(
+            steps,
+            [
+                Step::Excluded,
+                Step::Captured,
+                Step::Last(id),
+                Step::Excluded
+            ]
+        );
+        assert_eq!(capture.finish(id).map(|window| window.count), Some(2));
+        assert_eq!(capture.finish(id), None);
+
+        // the window starts with the first measurement
+        let request = Request {
+            limit: Limit::Seconds(1),
+            rate_hz: Some(10),
+        };
+        let id = capture.start(request, true, None);
+        let steps = [500, 1_400, 1_500]
+            .map(|timestamp_ms| capture.on_measurement(&test_measurement(timestamp_ms, 500)));
+        assert_eq!(steps, [Step::Captured, Step::Captured, Step::Ended(id)]);
+        assert_eq!(capture.window().map(|window| window.count), Some(2));
+    }
+
+    #[test]
+    fn shortens_the_timing_budget_for_the_rate() {
+        let settings = TofSettings {
+            distance_mode: DistanceMode::Long,
+            timing_budget_ms: 100,
+            inter_measurement_ms: 100,
+        };
+        let fast = settings_for_rate(settings, 25);
+        assert_eq!((fast.timing_budget_ms, fast.inter_measurement_ms), (33, 40));
+        let slow = settings_for_rate(settings, 2);
+        assert_eq!(
+            (slow.timing_budget_ms, slow.inter_measurement_ms),
+            (100, 500)
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Change-only reporting in the streaming mode: with `change <delta_mm>` a measurement is only
+//! published if its distance differs from the previously published one by more than the delta or
+//! its status has changed, which saves most of the bandwidth while the target doesn't move.
+//! `change off` publishes every measurement again.
+//!
+//! As long as nothing is published a keepalive frame is sent every
+//! [`crate::config::change::KEEPALIVE_S`], thus the host can tell "no change" from a dead link.
+//! The delta is stored with `save`, the other application modes & the local outputs aren't
+//! affected. With a [`crate::publish_interval`] the means of the intervals are filtered.
+
+use crate::telemetry::Measurement;
+use vl53l1x_uld::RangeStatus;
+
+/// Interval at which it's checked whether a keepalive frame is due.
+pub const CHECK_INTERVAL_MS: u32 = 1_000;
+
+pub struct ChangeFilter {
+    /// `None` if every measurement is published.
+    delta_mm: Option<u16>,
+    /// The distance & the status of the latest published measurement.
+    published: Option<(u16, RangeStatus)>,
+    /// Sequence number of the latest measurement, published or not.
+    latest_seq: u32,
+    /// Time since boot of the latest published frame.
+    sent_ms: u32,
+}
+
+impl ChangeFilter {
+    pub const fn new(delta_mm: Option<u16>) -> Self {
+        Self {
+            delta_mm,
+            published: None,
+            latest_seq: 0,
+            sent_ms: 0,
+        }
+    }
+
+    pub fn delta_mm(&self) -> Option<u16> {
+        self.delta_mm
+    }
+
+    /// Set the delta, the next measurement is published in any case.
+    pub fn set_delta_mm(&mut self, delta_mm: Option<u16>) {
+        self.delta_mm = delta_mm;
+        self.published = None;
+    }
+
+    /// Whether the measurement is published. The distances of invalid measurements aren't
+    /// compared, only their status.
+    pub fn passes(&mut self, measurement: &Measurement) -> bool {
+        self.latest_seq = measurement.seq;
+        let Some(delta_mm) = self.delta_mm else {
+            return true;
+        };
+        let changed = match self.published {
+            None => true,
+            Some((distance_mm, status)) => {
+                status != measurement.status
+                    || (status == RangeStatus::Valid
+                        && measurement.distance_mm.abs_diff(distance_mm) > delta_mm)
+            }
+        };
+        if changed {
+            self.published = Some((measurement.distance_mm, measurement.status));
+            self.sent_ms = measurement.timestamp_ms;
+        }
+        changed
+    }
+
+    /// Whether a keepalive frame is due at `now_ms`: the filter is on & nothing has been published
+    /// for `interval_ms`. Returns the sequence number of the latest measurement for it, the next
+    /// one is due an interval later.
+    pub fn keepalive(&mut self, now_ms: u32, interval_ms: u32) -> Option<u32> {
+        if self.delta_mm.is_none() || now_ms.wrapping_sub(self.sent_ms) < interval_ms {
+            return None;
+        }
+        self.sent_ms = now_ms;
+        Some(self.latest_seq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test_measurement;
+
+    #[test]
+    fn publishes_only_the_changes() {
+        let mut filter = ChangeFilter::new(Some(10));
+        assert!(filter.passes(&Measurement {
+            seq: 1,
+            ..test_measurement(100, 500)
+        }));
+        assert!(!filter.passes(&Measurement {
+            seq: 2,
+            ..test_measurement(200, 510)
+        }));
+        // compared with the published one, not with the previous one
+        assert!(filter.passes(&Measurement {
+            seq: 3,
+            ..test_measurement(300, 511)
+        }));
+        assert!(filter.passes(&Measurement {
+            seq: 4,
+            status: RangeStatus::SignalFailure,
+            ..test_measurement(400, 0)
+        }));
+        assert!(!filter.passes(&Measurement {
+            seq: 5,
+            status: RangeStatus::SignalFailure,
+            ..test_measurement(500, 800)
+        }));
+        assert!(filter.passes(&Measurement {
+            seq: 6,
+            ..test_measurement(600, 800)
+        }));
+
+        // the latest published frame has been sent at 600 ms
+        assert_eq!(filter.keepalive(1_500, 1_000), None);
+        assert!(!filter.passes(&Measurement {
+            seq: 7,
+            ..test_measurement(700, 805)
+        }));
+        assert_eq!(filter.keepalive(1_600, 1_000), Some(7));
+        assert_eq!(filter.keepalive(2_500, 1_000), None);
+
+        filter.set_delta_mm(None);
+        assert!(filter.passes(&Measurement {
+            seq: 8,
+            ..test_measurement(800, 805)
+        }));
+        assert_eq!(filter.keepalive(5_000, 1_000), None);
+    }
+}
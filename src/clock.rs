@@ -0,0 +1,246 @@
+//! The real-time clock (RTC) of the microcontroller: the calendar time, which is set by the host
+//! with the `time set` command, and values which are kept in its backup registers so that they
+//! survive a reset.
+//!
+//! The RTC is clocked by the 32.768 kHz crystal (LSE, X2) of the Nucleo board. The backup domain
+//! is only reset by a loss of power (`VBAT` is connected to `VDD` on the Nucleo board), thus the
+//! calendar has to be set again afterwards. A magic value in the first backup register tells
+//! whether the registers are intact. Unlike the flash they can be written as often as needed.
+
+use crate::rollup;
+use stm32f4xx_hal::pac::{EXTI, PWR, RTC};
+use stm32f4xx_hal::prelude::*;
+use stm32f4xx_hal::rtc::{self, Lse, Rtc};
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+/// Marks intact registers, `BK` followed by the version of the layout.
+const MAGIC: u32 = u32::from_le_bytes(*b"BK01");
+
+/// The backup registers in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    Magic = 0,
+    /// Uptime in seconds, see [`crate::reset_log`].
+    Uptime = 1,
+    /// The sequence number of the latest measurement.
+    Sequence = 2,
+    /// [`BOOTLOADER_REQUEST`] if the next boot enters the [`crate::bootloader`].
+    Bootloader = 3,
+    /// [`SLOT_B_REQUEST`] if the next boot starts slot B of the [`crate::firmware_update`],
+    /// [`SLOT_A_REQUEST`] if it stays in slot A.
+    Slot = 4,
+    /// Number of resets in a row by the [`crate::sensor_supervisor`].
+    SensorResets = 5,
+    /// The number of the stage of the [`crate::post`] in progress, 0 once the boot has finished.
+    BootStage = 6,
+    /// The first of the [`rollup::BACKUP_REGISTERS`] of the accumulator of the rollup in progress.
+    Rollup = 7,
+}
+
+/// Marks a pending request to enter the bootloader.
+const BOOTLOADER_REQUEST: u32 = u32::from_le_bytes(*b"DFU!");
+/// Marks a pending request to start slot B.
+#[cfg(feature = "firmware-update")]
+const SLOT_B_REQUEST: u32 = u32::from_le_bytes(*b"SLTB");
+/// Marks a pending request to stay in slot A.
+#[cfg(feature = "firmware-update")]
+const SLOT_A_REQUEST: u32 = u32::from_le_bytes(*b"SLTA");
+
+pub struct Clock {
+    rtc: Rtc<Lse>,
+    /// Whether the registers contained the values from before the reset at boot.
+    intact: bool,
+}
+
+impl Clock {
+    /// Start the RTC (unless it's already running) & enable the access to the backup registers,
+    /// which are cleared if they aren't intact.
+    ///
+    /// Starting the crystal after a loss of power takes up to 2 s, the firmware doesn't boot
+    /// without it.
+    pub fn new(rtc: RTC, pwr: &mut PWR) -> Self {
+        // this resets the backup domain if the crystal isn't running yet
+        let rtc = Rtc::new(rtc, pwr);
+        let mut clock = Self { rtc, intact: false };
+        clock.intact = clock.read(Register::Magic) == MAGIC;
+        if !clock.intact {
+            defmt::info!("backup registers not intact, clearing them");
+            clock.write(Register::Uptime, 0);
+            clock.write(Register::Sequence, 0);
+            clock.write(Register::Bootloader, 0);
+            clock.write(Register::Slot, 0);
+            clock.write(Register::SensorResets, 0);
+            clock.write(Register::BootStage, 0);
+            clock.set_rollup([0; rollup::BACKUP_REGISTERS]);
+            clock.write(Register::Magic, MAGIC);
+        }
+        clock
+    }
+
+    /// The current calendar time (UTC) in milliseconds since the Unix epoch, `None` if it hasn't
+    /// been set yet.
+    pub fn now_ms(&mut self) -> Option<u64> {
+        if !self.is_set() {
+            return None;
+        }
+        let now = self.rtc.get_datetime().assume_utc();
+        Some(now.unix_timestamp() as u64 * 1000 + now.millisecond() as u64)
+    }
+
+    /// Set the calendar time (UTC) in seconds since the Unix epoch. Only the years 1970 to 2069
+    /// are supported.
+    pub fn set(&mut self, unix_s: u32) -> Result<(), rtc::Error> {
+        let now = OffsetDateTime::from_unix_timestamp(unix_s.into())
+            .map_err(|_| rtc::Error::InvalidInputData)?;
+        self.rtc
+            .set_datetime(&PrimitiveDateTime::new(now.date(), now.time()))?;
+        defmt::info!("calendar time set to {}", unix_s);
+        Ok(())
+    }
+
+    /// Whether the calendar time has been set, i.e. the year is no longer the one after a reset of
+    /// the backup domain.
+    fn is_set(&self) -> bool {
+        self.rtc.regs.isr.read().inits().bit_is_set()
+    }
+
+    /// The uptime from before the reset, `None` if the registers weren't intact. Must be read
+    /// before it's overwritten with [`Self::set_uptime`].
+    pub fn previous_uptime_s(&self) -> Option<u32> {
+        self.intact.then(|| self.read(Register::Uptime))
+    }
+
+    /// Remember the uptime in case of a reset. Must be called periodically.
+    pub fn set_uptime(&mut self, uptime_s: u32) {
+        self.write(Register::Uptime, uptime_s);
+    }
+
+    /// The sequence number of the latest measurement before the reset, `None` if the registers
+    /// weren't intact.
+    pub fn previous_sequence(&self) -> Option<u32> {
+        self.intact.then(|| self.read(Register::Sequence))
+    }
+
+    pub fn set_sequence(&mut self, seq: u32) {
+        self.write(Register::Sequence, seq);
+    }
+
+    /// The accumulator of the rollup in progress before the reset (see
+    /// [`rollup::RollupAccumulator::backup`]), `None` if the registers weren't intact.
+    pub fn previous_rollup(&self) -> Option<[u32; rollup::BACKUP_REGISTERS]> {
+        self.intact
+            .then(|| core::array::from_fn(|i| self.read_at(Register::Rollup as usize + i)))
+    }
+
+    pub fn set_rollup(&mut self, registers: [u32; rollup::BACKUP_REGISTERS]) {
+        for (i, value) in registers.into_iter().enumerate() {
+            self.write_at(Register::Rollup as usize + i, value);
+        }
+    }
+
+    /// Number of resets in a row by the [`crate::sensor_supervisor`], 0 after a loss of power.
+    pub fn sensor_resets(&self) -> u32 {
+        self.read(Register::SensorResets)
+    }
+
+    pub fn set_sensor_resets(&mut self, resets: u32) {
+        self.write(Register::SensorResets, resets);
+    }
+
+    /// The number of the stage of the [`crate::post`] in progress, that of the previous boot
+    /// (0 if it has finished) until it's overwritten with [`Self::set_boot_stage`].
+    pub fn boot_stage(&self) -> u32 {
+        self.read(Register::BootStage)
+    }
+
+    pub fn set_boot_stage(&mut self, stage: u32) {
+        self.write(Register::BootStage, stage);
+    }
+
+    /// Enter the [`crate::bootloader`] at the next boot.
+    pub fn request_bootloader(&mut self) {
+        self.write(Register::Bootloader, BOOTLOADER_REQUEST);
+    }
+
+    /// Whether the bootloader has been requested before the reset, the request is cleared.
+    pub fn take_bootloader_request(&mut self) -> bool {
+        let requested = self.read(Register::Bootloader) == BOOTLOADER_REQUEST;
+        if requested {
+            self.write(Register::Bootloader, 0);
+        }
+        requested
+    }
+
+    /// Start slot B of the [`crate::firmware_update`] at the next boot.
+    #[cfg(feature = "firmware-update")]
+    pub fn request_slot_b(&mut self) {
+        self.write(Register::Slot, SLOT_B_REQUEST);
+    }
+
+    /// Whether slot B has been requested before the reset, the request is cleared.
+    #[cfg(feature = "firmware-update")]
+    pub fn take_slot_b_request(&mut self) -> bool {
+        let requested = self.read(Register::Slot) == SLOT_B_REQUEST;
+        if requested {
+            self.write(Register::Slot, 0);
+        }
+        requested
+    }
+
+    /// Stay in slot A of the [`crate::firmware_update`] at the next boot, e.g. to update slot B.
+    #[cfg(feature = "firmware-update")]
+    pub fn request_slot_a(&mut self) {
+        self.write(Register::Slot, SLOT_A_REQUEST);
+    }
+
+    /// Whether slot A has been requested before the reset, the request is cleared.
+    #[cfg(feature = "firmware-update")]
+    pub fn take_slot_a_request(&mut self) -> bool {
+        let requested = self.read(Register::Slot) == SLOT_A_REQUEST;
+        if requested {
+            self.write(Register::Slot, 0);
+        }
+        requested
+    }
+
+    /// Wake the microcontroller every `interval_s` (e.g. from the stop mode, see
+    /// [`crate::shutdown`]), the wakeup has to be cleared with [`Self::clear_wakeup`].
+    pub fn start_wakeup(&mut self, interval_s: u32, exti: &mut EXTI) {
+        self.rtc.enable_wakeup(interval_s.secs());
+        self.rtc.listen(exti, rtc::Event::Wakeup);
+    }
+
+    /// Route the wakeup timer to its interrupt, it's started with [`Self::set_wakeup`].
+    pub fn listen_wakeup(&mut self, exti: &mut EXTI) {
+        self.rtc.listen(exti, rtc::Event::Wakeup);
+    }
+
+    /// Wake the microcontroller every `interval_s` (e.g. from the stop mode, see
+    /// [`crate::low_power`]) or not at all (`None`).
+    pub fn set_wakeup(&mut self, interval_s: Option<u32>) {
+        match interval_s {
+            Some(interval_s) => self.rtc.enable_wakeup(interval_s.secs()),
+            None => self.rtc.disable_wakeup(),
+        }
+    }
+
+    pub fn clear_wakeup(&mut self) {
+        self.rtc.clear_interrupt(rtc::Event::Wakeup);
+    }
+
+    fn read(&self, register: Register) -> u32 {
+        self.read_at(register as usize)
+    }
+
+    fn write(&mut self, register: Register, value: u32) {
+        self.write_at(register as usize, value);
+    }
+
+    fn read_at(&self, index: usize) -> u32 {
+        self.rtc.regs.bkpr[index].read().bkp().bits()
+    }
+
+    fn write_at(&mut self, index: usize, value: u32) {
+        self.rtc.regs.bkpr[index].write(|w| w.bkp().bits(value));
+    }
+}
@@ -0,0 +1,1013 @@
+//! Line based command interface.
+//!
+//! Commands are sent by the host as ASCII lines (terminated by `\n`, an optional preceding `\r` is
+//! ignored). Each command is answered with a single line starting with either `OK` or `ERR`, which
+//! is only preceded by the lines of the metrics for `metrics`.
+
+use crate::acquisition::Acquisition;
+use crate::calibration::MAX_TARGET_MM;
+use crate::capture::{self, Limit};
+#[cfg(feature = "littlefs")]
+use crate::file_system::FilePath;
+use crate::geofence::{Actions, ZoneConfig, MAX_ZONES};
+use crate::log_backend::{Channel, Level};
+use crate::option_bytes::Region;
+use crate::preset::Preset;
+use crate::profile::Profile;
+use crate::publish_interval::MIN_INTERVAL_MS;
+use crate::scaling::{Milli, Scaling, Unit};
+use crate::schedule::Schedule;
+use crate::telemetry::FrameFormat;
+use crate::time_sync::SyncReply;
+
+/// Maximum length of a single command line (excluding the line ending).
+pub const MAX_LINE_LEN: usize = 64;
+
+/// Maximum length of a single response line (including the line ending).
+pub const MAX_RESPONSE_LEN: usize = 448;
+
+/// A response to a command.
+pub type Response = heapless::String<MAX_RESPONSE_LEN>;
+
+/// All commands understood by the firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Command {
+    /// Start ranging.
+    Start,
+    /// Stop ranging.
+    Stop,
+    /// Report the current state.
+    Status,
+    /// List the available commands.
+    Help,
+    /// Switch the format of the telemetry frames.
+    Format(FrameFormat),
+    /// Select the unit of the scaled distance or report it (`None`), see [`crate::scaling`].
+    Unit(Option<Unit>),
+    /// Set the scale & the offset of the scaled distance or report them (`None`).
+    Scale(Option<(Milli, Milli)>),
+    /// Publish at a fixed interval (in ms, `Some(None)` to publish every measurement) or report it
+    /// (`None`), see [`crate::publish_interval`].
+    Publish(Option<Option<u16>>),
+    /// Publish only the measurements which change by more than the delta (in mm, `Some(None)` to
+    /// publish every measurement) or report it (`None`), see [`crate::change_filter`].
+    Change(Option<Option<u16>>),
+    /// Accept only the valid measurements within the window (the shortest & the longest distance
+    /// in mm, `Some(None)` to accept every distance) or report it (`None`), see
+    /// [`crate::range_gate`].
+    Gate(Option<Option<(u16, u16)>>),
+    /// Change the active hours of the alarms & the presence events or report them (`None`), see
+    /// [`crate::schedule`].
+    Schedule(Option<Schedule>),
+    /// Switch how the measurements are acquired or report it (`None`), see
+    /// [`crate::acquisition`].
+    Acquisition(Option<Acquisition>),
+    /// Store the current settings so that they're used again after a reset.
+    Save,
+    /// Manage the named profiles.
+    Profile(ProfileCommand),
+    /// Run the guided calibration, see [`crate::calibration_wizard`].
+    Cal(CalCommand),
+    /// Monitor the drift of the calibration, see [`crate::drift`].
+    Drift(DriftCommand),
+    /// Apply a [`Preset`] or report the latest applied one (`None`).
+    Preset(Option<Preset>),
+    /// Report the number of boots & the causes of the last resets.
+    Resets,
+    /// Report the newest entries (at most this many) of the [`crate::event_log`].
+    Events(u8),
+    /// Configure or remove (`None`) a zone of the [`crate::geofence`] or report all of them
+    /// (`None`).
+    Zone(Option<(u8, Option<ZoneConfig>)>),
+    /// Report the intervals between the measurements or start collecting them again (`true`), see
+    /// [`crate::jitter`].
+    Jitter(bool),
+    /// Report the minimum & the maximum distance or start capturing them again (`true`), see
+    /// [`crate::extremes`].
+    Stats(bool),
+    /// Run a bounded capture, see [`crate::capture`].
+    Capture(CaptureCommand),
+    /// Report all counters & gauges in a text format like the one of Prometheus, see
+    /// [`crate::metrics`].
+    Metrics,
+    /// Set the level of a channel of the log (`None` turns it off) or report both (`None`), see
+    /// [`crate::log_backend`].
+    Log(Option<(Channel, Option<Level>)>),
+    /// Scan the shared I2C bus, see [`crate::i2c_scan`].
+    Scan,
+    /// Report the optical configuration of the TOF sensor, see
+    /// [`crate::range_sensor::Optics`].
+    Optics,
+    /// Set the calendar time (UTC, in seconds since the Unix epoch) or report it (`None`).
+    Time(Option<u32>),
+    /// Synchronise with the clock of the host, see [`crate::time_sync`].
+    Sync(Option<SyncReply>),
+    /// Stop ranging & the data loggers & halt so that the power can be removed safely, see
+    /// [`crate::shutdown`].
+    Shutdown,
+    /// Reset into the bootloader in the system memory, see [`crate::bootloader`].
+    Dfu,
+    /// Request a [`ResetToken`] (`None`) or stop ranging & the data loggers & reset with it.
+    Reset(Option<u16>),
+    /// Report or change the protection of the flash, see [`crate::option_bytes`].
+    Protect(ProtectCommand),
+    /// Send all measurements stored in the flash log, see [`crate::flash_log`], compressed
+    /// (`true`) with [`crate::log_compression`].
+    #[cfg(feature = "nor-flash")]
+    Dump(bool),
+    /// Send the newest measurements (at most this many, all by default) kept in the
+    /// [`crate::sample_history`] as CSV.
+    DumpCsv(Option<u16>),
+    /// Report a snapshot of the registers of the TOF sensor, see [`crate::register_dump`].
+    DumpRegs,
+    /// List a directory of the file system (the root directory by default), see
+    /// [`crate::file_system`].
+    #[cfg(feature = "littlefs")]
+    Ls(FilePath),
+    /// Send the content of a file.
+    #[cfg(feature = "littlefs")]
+    Cat(FilePath),
+    /// Remove a file or an empty directory.
+    #[cfg(feature = "littlefs")]
+    Rm(FilePath),
+    /// Download the stored measurements with XMODEM or YMODEM, see [`crate::xmodem`].
+    #[cfg(feature = "xmodem")]
+    Transfer(crate::xmodem::Request),
+    /// Receive a new firmware & install it, see [`crate::firmware_update`].
+    #[cfg(feature = "firmware-update")]
+    Update,
+    /// Enable (`true`) or mute (`false`) the buzzer.
+    #[cfg(feature = "buzzer")]
+    Buzzer(bool),
+    /// Report the state of the alarm output or clear the latched alarm (`true`), see
+    /// [`crate::alarm_output`].
+    #[cfg(feature = "alarm-output")]
+    Alarm(bool),
+    /// Control the distance hold controller.
+    #[cfg(feature = "motor-pid")]
+    Pid(PidCommand),
+    /// Switch the mode of the stepper motor or report its state (`None`).
+    #[cfg(feature = "stepper")]
+    Stepper(Option<crate::stepper::Mode>),
+    /// Run the loopback self-test of the UART protocol, see [`crate::loopback`].
+    #[cfg(feature = "uart-loopback")]
+    SelfTest(crate::loopback::Mode),
+}
+
+/// Arguments of [`Command::Profile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ProfileCommand {
+    /// Report the active profile.
+    Status,
+    /// Apply the settings of the profile & make it the active one.
+    Load(Profile),
+    /// Save the current settings of the TOF sensor to the profile.
+    Save(Profile),
+}
+
+/// Arguments of [`Command::Cal`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum CalCommand {
+    /// Report the current step of the wizard.
+    Status,
+    /// Start (or restart) the wizard.
+    Wizard,
+    /// Measure once the target has been placed.
+    Next,
+    /// Store the results.
+    Save,
+    /// Restore the previous calibration & end the wizard.
+    Discard,
+    /// Set the distance to the target of the offset calibration (in mm) or report it (`None`).
+    Target(Option<u16>),
+}
+
+/// Arguments of [`Command::Drift`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum DriftCommand {
+    /// Report the reference distance & the latest estimated drift.
+    Status,
+    /// Check the drift now.
+    Check,
+    /// Set the distance to the static scene (in mm) or stop the monitoring (`None`).
+    Reference(Option<u16>),
+}
+
+/// Arguments of [`Command::Capture`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum CaptureCommand {
+    /// Report the running capture.
+    Status,
+    Start(capture::Request),
+    /// End the running capture.
+    Abort,
+}
+
+/// Arguments of [`Command::Protect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ProtectCommand {
+    /// Report the write & the read protection.
+    Status,
+    /// Write protect exactly the sectors of the region.
+    Write(Region),
+    /// Request a [`ResetToken`] (`None`) or raise the read protection to level 1 with it.
+    Read(Option<u16>),
+}
+
+/// Confirmation of [`Command::Reset`], so that a reset isn't triggered by accident (e.g. by a
+/// stray line or a replayed log): the first `reset` is answered with a token which has to be sent
+/// back with the second one within [`ResetToken::TIMEOUT_MS`]. `protect rdp 1` is confirmed the
+/// same way, with a token of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetToken {
+    token: u16,
+    /// Time since boot at which the token has been issued.
+    issued_ms: u32,
+}
+
+impl ResetToken {
+    /// Time for which a token is valid.
+    pub const TIMEOUT_MS: u32 = 30_000;
+
+    /// Issue a new token derived from `seed` (e.g. the time of the request in ticks).
+    pub fn new(seed: u32, issued_ms: u32) -> Self {
+        let token = (seed.wrapping_mul(0x9E37_79B9) >> 16) as u16;
+        Self {
+            // 0 is never issued so that it can't be guessed
+            token: token.max(1),
+            issued_ms,
+        }
+    }
+
+    pub fn token(&self) -> u16 {
+        self.token
+    }
+
+    /// Whether `token` confirms the reset at `now_ms`.
+    pub fn confirms(&self, token: u16, now_ms: u32) -> bool {
+        token == self.token && now_ms.wrapping_sub(self.issued_ms) <= Self::TIMEOUT_MS
+    }
+}
+
+/// Arguments of [`Command::Pid`].
+#[cfg(feature = "motor-pid")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum PidCommand {
+    /// Report the settings of the controller.
+    Status,
+    /// Enable (`true`) or disable (`false`) the controller.
+    Enable(bool),
+    /// Set the distance to keep.
+    Target(u16),
+    /// Set the gains, in thousandths.
+    Gains {
+        kp_milli: u32,
+        ki_milli: u32,
+        kd_milli: u32,
+    },
+}
+
+/// Reasons why a line could not be turned into a [`Command`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ParseError {
+    /// The line was empty.
+    Empty,
+    /// The command is not known.
+    UnknownCommand,
+    /// An argument of the command is missing or invalid.
+    InvalidArgument,
+    /// The line was longer than [`MAX_LINE_LEN`].
+    LineTooLong,
+}
+
+impl ParseError {
+    /// Human readable description, used in the `ERR` response.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParseError::Empty => "empty command",
+            ParseError::UnknownCommand => "unknown command",
+            ParseError::InvalidArgument => "invalid argument",
+            ParseError::LineTooLong => "line too long",
+        }
+    }
+}
+
+/// All commands with their arguments, listed in response to [`Command::Help`].
+pub const COMMANDS: &[&str] = &[
+    "start",
+    "stop",
+    "status",
+    "format csv|json",
+    "unit [mm|cm|in]",
+    "scale [<scale> <offset>]",
+    "publish [<interval_ms>|off]",
+    "change [<delta_mm>|off]",
+    "gate [<min_mm> <max_mm>|off]",
+    "schedule [always|<days> <hh:mm>-<hh:mm>]",
+    #[cfg(not(any(feature = "trigger-input", feature = "second-tof")))]
+    "acquisition [interrupt|polled|timed]",
+    #[cfg(all(feature = "trigger-input", not(feature = "second-tof")))]
+    "acquisition [interrupt|polled|timed|triggered]",
+    #[cfg(all(feature = "second-tof", not(feature = "trigger-input")))]
+    "acquisition [interrupt|polled|timed|interleaved]",
+    #[cfg(all(feature = "trigger-input", feature = "second-tof"))]
+    "acquisition [interrupt|polled|timed|triggered|interleaved]",
+    "save",
+    "profile [load|save indoor|outdoor|demo|fast]",
+    "cal [wizard|next|save|discard|target [<mm>]]",
+    "drift [check|ref <mm>|ref off]",
+    "preset [indoor-short|outdoor-long|high-reflectivity|low-reflectivity]",
+    "resets",
+    "events [<count>]",
+    "zone [<n> off|<n> <enter_mm> <exit_mm> <debounce_ms> [<actions>]]",
+    "jitter [reset]",
+    "stats [reset]",
+    "capture [<samples>|<seconds>s [<rate_hz>]|abort]",
+    "metrics",
+    "log [rtt|uart info|warn|error|off]",
+    "scan",
+    "optics",
+    "time [set <unix_s>]",
+    "sync [<fw_ms> <host_ms>]",
+    "shutdown",
+    "dfu",
+    "reset [<token>]",
+    "protect [none|firmware|eeprom|all|rdp 1 [<token>]]",
+    #[cfg(feature = "nor-flash")]
+    "dump [compressed]",
+    "dump csv [<count>]",
+    "dump regs",
+    #[cfg(feature = "littlefs")]
+    "ls [<dir>]",
+    #[cfg(feature = "littlefs")]
+    "cat <file>",
+    #[cfg(feature = "littlefs")]
+    "rm <path>",
+    #[cfg(feature = "xmodem")]
+    "xmodem|ymodem [<offset>]",
+    #[cfg(feature = "firmware-update")]
+    "update",
+    #[cfg(feature = "buzzer")]
+    "buzzer on|off",
+    #[cfg(feature = "alarm-output")]
+    "alarm [clear]",
+    #[cfg(feature = "motor-pid")]
+    "pid [on|off|target <mm>|gains <kp> <ki> <kd>]",
+    #[cfg(feature = "stepper")]
+    "stepper [follow|scan <mm>]",
+    #[cfg(feature = "uart-loopback")]
+    "selftest [internal]",
+    "help",
+];
+
+/// Write the response to [`Command::Help`].
+pub fn write_help(response: &mut impl core::fmt::Write) -> core::fmt::Result {
+    write!(response, "OK commands: ")?;
+    for (i, command) in COMMANDS.iter().enumerate() {
+        if i > 0 {
+            write!(response, ", ")?;
+        }
+        write!(response, "{}", command)?;
+    }
+    write!(response, "\r\n")
+}
+
+/// Parse a single line (without line ending) into a [`Command`].
+pub fn parse(line: &str) -> Result<Command, ParseError> {
+    let mut words = line.split_ascii_whitespace();
+    let command = match words.next() {
+        None => return Err(ParseError::Empty),
+        Some("start") => Command::Start,
+        Some("stop") => Command::Stop,
+        Some("status") => Command::Status,
+        Some("help") => Command::Help,
+        Some("format") => Command::Format(match words.next() {
+            Some("csv") => FrameFormat::Csv,
+            Some("json") => FrameFormat::Json,
+            _ => return Err(ParseError::InvalidArgument),
+        }),
+        Some("unit") => Command::Unit(match words.next() {
+            None => None,
+            Some(name) => Some(Unit::from_name(name).ok_or(ParseError::InvalidArgument)?),
+        }),
+        Some("scale") => Command::Scale(match words.next() {
+            None => None,
+            Some(scale) => {
+                let scale = parse_signed_milli(scale)
+                    .filter(|scale| scale.unsigned_abs() <= Scaling::MAX_SCALE as u32);
+                let offset = words.next().and_then(parse_signed_milli);
+                match (scale, offset) {
+                    (Some(scale), Some(offset)) => Some((Milli(scale), Milli(offset))),
+                    _ => return Err(ParseError::InvalidArgument),
+                }
+            }
+        }),
+        Some("publish") => Command::Publish(match words.next() {
+            None => None,
+            Some("off") => Some(None),
+            Some(interval) => Some(Some(
+                interval
+                    .parse()
+                    .ok()
+                    .filter(|&interval| interval >= MIN_INTERVAL_MS)
+                    .ok_or(ParseError::InvalidArgument)?,
+            )),
+        }),
+        Some("change") => Command::Change(match words.next() {
+            None => None,
+            Some("off") => Some(None),
+            Some(delta) => Some(Some(
+                delta
+                    .parse()
+                    .ok()
+                    .filter(|&delta| delta > 0)
+                    .ok_or(ParseError::InvalidArgument)?,
+            )),
+        }),
+        Some("gate") => Command::Gate(match words.next() {
+            None => None,
+            Some("off") => Some(None),
+            Some(min_mm) => {
+                let min_mm: u16 = min_mm.parse().map_err(|_| ParseError::InvalidArgument)?;
+                let max_mm: u16 = words
+                    .next()
+                    .and_then(|max_mm| max_mm.parse().ok())
+                    .filter(|&max_mm| max_mm >= min_mm)
+                    .ok_or(ParseError::InvalidArgument)?;
+                Some(Some((min_mm, max_mm)))
+            }
+        }),
+        Some("schedule") => Command::Schedule(match words.next() {
+            None => None,
+            Some("always") => Some(Schedule::ALWAYS),
+            Some(days) => {
+                let days = Schedule::parse_days(days).ok_or(ParseError::InvalidArgument)?;
+                let (start, end) = words
+                    .next()
+                    .and_then(|times| times.split_once('-'))
+                    .ok_or(ParseError::InvalidArgument)?;
+                let schedule = Schedule::parse_time(start)
+                    .zip(Schedule::parse_time(end))
+                    .and_then(|(start_min, end_min)| Schedule::new(days, start_min, end_min));
+                Some(schedule.ok_or(ParseError::InvalidArgument)?)
+            }
+        }),
+        Some("acquisition") => Command::Acquisition(match words.next() {
+            None => None,
+            Some(name) => Some(Acquisition::from_name(name).ok_or(ParseError::InvalidArgument)?),
+        }),
+        Some("save") => Command::Save,
+        Some("resets") => Command::Resets,
+        Some("scan") => Command::Scan,
+        Some("optics") => Command::Optics,
+        Some("events") => Command::Events(match words.next() {
+            None => crate::event_log::MAX_RESPONSE_LEN as u8,
+            Some(count) => count.parse().map_err(|_| ParseError::InvalidArgument)?,
+        }),
+        Some("zone") => Command::Zone(match words.next() {
+            None => None,
+            Some(index) => {
+                let index = index
+                    .parse()
+                    .ok()
+                    .filter(|&index: &u8| (index as usize) < MAX_ZONES)
+                    .ok_or(ParseError::InvalidArgument)?;
+                let number = |word: Option<&str>| {
+                    word.and_then(|word| word.parse().ok())
+                        .ok_or(ParseError::InvalidArgument)
+                };
+                let zone = match words.next() {
+                    Some("off") => None,
+                    enter_mm => Some(ZoneConfig {
+                        enter_mm: number(enter_mm)?,
+                        exit_mm: number(words.next())?,
+                        debounce_ms: number(words.next())?,
+                        actions: match words.next() {
+                            None => Actions::EVENT,
+                            Some(names) => {
+                                Actions::from_names(names).ok_or(ParseError::InvalidArgument)?
+                            }
+                        },
+                    }),
+                };
+                Some((index, zone))
+            }
+        }),
+        Some("jitter") => Command::Jitter(match words.next() {
+            None => false,
+            Some("reset") => true,
+            _ => return Err(ParseError::InvalidArgument),
+        }),
+        Some("stats") => Command::Stats(match words.next() {
+            None => false,
+            Some("reset") => true,
+            _ => return Err(ParseError::InvalidArgument),
+        }),
+        Some("capture") => Command::Capture(parse_capture(&mut words)?),
+        Some("metrics") => Command::Metrics,
+        Some("log") => Command::Log(match words.next() {
+            None => None,
+            Some(channel) => {
+                let channel = Channel::from_name(channel).ok_or(ParseError::InvalidArgument)?;
+                let level = match words.next() {
+                    Some("off") => None,
+                    Some(level) => {
+                        Some(Level::from_name(level).ok_or(ParseError::InvalidArgument)?)
+                    }
+                    None => return Err(ParseError::InvalidArgument),
+                };
+                Some((channel, level))
+            }
+        }),
+        Some("shutdown") => Command::Shutdown,
+        Some("dfu") => Command::Dfu,
+        Some("reset") => Command::Reset(match words.next() {
+            None => None,
+            Some(token) => {
+                Some(u16::from_str_radix(token, 16).map_err(|_| ParseError::InvalidArgument)?)
+            }
+        }),
+        Some("protect") => Command::Protect(match words.next() {
+            None => ProtectCommand::Status,
+            Some("rdp") => match words.next() {
+                Some("1") => ProtectCommand::Read(match words.next() {
+                    None => None,
+                    Some(token) => Some(
+                        u16::from_str_radix(token, 16).map_err(|_| ParseError::InvalidArgument)?,
+                    ),
+                }),
+                _ => return Err(ParseError::InvalidArgument),
+            },
+            Some(name) => {
+                ProtectCommand::Write(Region::from_name(name).ok_or(ParseError::InvalidArgument)?)
+            }
+        }),
+        Some("dump") => match words.next() {
+            Some("csv") => Command::DumpCsv(match words.next() {
+                None => None,
+                Some(count) => Some(count.parse().map_err(|_| ParseError::InvalidArgument)?),
+            }),
+            Some("regs") => Command::DumpRegs,
+            #[cfg(feature = "nor-flash")]
+            None => Command::Dump(false),
+            #[cfg(feature = "nor-flash")]
+            Some("compressed") => Command::Dump(true),
+            _ => return Err(ParseError::InvalidArgument),
+        },
+        #[cfg(feature = "littlefs")]
+        Some("ls") => Command::Ls(match words.next() {
+            None => FilePath::ROOT,
+            Some(path) => FilePath::new(path).ok_or(ParseError::InvalidArgument)?,
+        }),
+        #[cfg(feature = "littlefs")]
+        Some(command @ ("cat" | "rm")) => {
+            let path = words
+                .next()
+                .and_then(FilePath::new)
+                .ok_or(ParseError::InvalidArgument)?;
+            if command == "cat" {
+                Command::Cat(path)
+            } else {
+                Command::Rm(path)
+            }
+        }
+        #[cfg(feature = "xmodem")]
+        Some(command @ ("xmodem" | "ymodem")) => Command::Transfer(crate::xmodem::Request {
+            protocol: if command == "xmodem" {
+                crate::xmodem::Protocol::Xmodem
+            } else {
+                crate::xmodem::Protocol::Ymodem
+            },
+            offset: match words.next() {
+                None => 0,
+                Some(offset) => offset.parse().map_err(|_| ParseError::InvalidArgument)?,
+            },
+        }),
+        #[cfg(feature = "firmware-update")]
+        Some("update") => Command::Update,
+        Some("time") => Command::Time(match words.next() {
+            None => None,
+            Some("set") => Some(
+                words
+                    .next()
+                    .and_then(|word| word.parse().ok())
+                    .ok_or(ParseError::InvalidArgument)?,
+            ),
+            _ => return Err(ParseError::InvalidArgument),
+        }),
+        Some("sync") => Command::Sync(match words.next() {
+            None => None,
+            Some(sent_ms) => Some(SyncReply {
+                sent_ms: sent_ms.parse().map_err(|_| ParseError::InvalidArgument)?,
+                host_ms: words
+                    .next()
+                    .and_then(|word| word.parse().ok())
+                    .ok_or(ParseError::InvalidArgument)?,
+            }),
+        }),
+        Some("profile") => Command::Profile(match words.next() {
+            None => ProfileCommand::Status,
+            Some(action @ ("load" | "save")) => {
+                let profile = words
+                    .next()
+                    .and_then(Profile::from_name)
+                    .ok_or(ParseError::InvalidArgument)?;
+                if action == "load" {
+                    ProfileCommand::Load(profile)
+                } else {
+                    ProfileCommand::Save(profile)
+                }
+            }
+            _ => return Err(ParseError::InvalidArgument),
+        }),
+        Some("cal") => Command::Cal(match words.next() {
+            None => CalCommand::Status,
+            Some("wizard") => CalCommand::Wizard,
+            Some("next") => CalCommand::Next,
+            Some("save") => CalCommand::Save,
+            Some("discard") => CalCommand::Discard,
+            Some("target") => CalCommand::Target(match words.next() {
+                None => None,
+                Some(target_mm) => Some(
+                    target_mm
+                        .parse()
+                        .ok()
+                        .filter(|target_mm| (1..=MAX_TARGET_MM).contains(target_mm))
+                        .ok_or(ParseError::InvalidArgument)?,
+                ),
+            }),
+            _ => return Err(ParseError::InvalidArgument),
+        }),
+        Some("drift") => Command::Drift(match words.next() {
+            None => DriftCommand::Status,
+            Some("check") => DriftCommand::Check,
+            Some("ref") => DriftCommand::Reference(match words.next() {
+                Some("off") => None,
+                Some(reference_mm) => Some(
+                    reference_mm
+                        .parse()
+                        .ok()
+                        .filter(|reference_mm| (1..=MAX_TARGET_MM).contains(reference_mm))
+                        .ok_or(ParseError::InvalidArgument)?,
+                ),
+                None => return Err(ParseError::InvalidArgument),
+            }),
+            _ => return Err(ParseError::InvalidArgument),
+        }),
+        Some("preset") => Command::Preset(match words.next() {
+            None => None,
+            Some(name) => Some(Preset::from_name(name).ok_or(ParseError::InvalidArgument)?),
+        }),
+        #[cfg(feature = "buzzer")]
+        Some("buzzer") => match words.next() {
+            Some("on") => Command::Buzzer(true),
+            Some("off") => Command::Buzzer(false),
+            _ => return Err(ParseError::InvalidArgument),
+        },
+        #[cfg(feature = "alarm-output")]
+        Some("alarm") => Command::Alarm(match words.next() {
+            None => false,
+            Some("clear") => true,
+            _ => return Err(ParseError::InvalidArgument),
+        }),
+        #[cfg(feature = "motor-pid")]
+        Some("pid") => Command::Pid(parse_pid(&mut words)?),
+        #[cfg(feature = "stepper")]
+        Some("stepper") => Command::Stepper(match words.next() {
+            None => None,
+            Some("follow") => Some(crate::stepper::Mode::Follow),
+            Some("scan") => Some(crate::stepper::Mode::Scan(
+                words
+                    .next()
+                    .and_then(|word| word.parse().ok())
+                    .ok_or(ParseError::InvalidArgument)?,
+            )),
+            _ => return Err(ParseError::InvalidArgument),
+        }),
+        #[cfg(feature = "uart-loopback")]
+        Some("selftest") => Command::SelfTest(match words.next() {
+            None => crate::loopback::Mode::Uart,
+            Some("internal") => crate::loopback::Mode::Internal,
+            _ => return Err(ParseError::InvalidArgument),
+        }),
+        Some(_) => return Err(ParseError::UnknownCommand),
+    };
+    if words.next().is_some() {
+        return Err(ParseError::InvalidArgument);
+    }
+    Ok(command)
+}
+
+fn parse_capture<'a>(
+    words: &mut impl Iterator<Item = &'a str>,
+) -> Result<CaptureCommand, ParseError> {
+    let limit = match words.next() {
+        None => return Ok(CaptureCommand::Status),
+        Some("abort") => return Ok(CaptureCommand::Abort),
+        Some(word) => match word.strip_suffix('s') {
+            Some(seconds) => seconds
+                .parse()
+                .ok()
+                .filter(|seconds| (1..=capture::MAX_DURATION_S).contains(seconds))
+                .map(Limit::Seconds),
+            None => word
+                .parse()
+                .ok()
+                .filter(|&samples| samples > 0)
+                .map(Limit::Samples),
+        }
+        .ok_or(ParseError::InvalidArgument)?,
+    };
+    let rate_hz = match words.next() {
+        None => None,
+        Some(rate_hz) => Some(
+            rate_hz
+                .parse()
+                .ok()
+                .filter(|rate_hz| (1..=capture::MAX_RATE_HZ).contains(rate_hz))
+                .ok_or(ParseError::InvalidArgument)?,
+        ),
+    };
+    Ok(CaptureCommand::Start(capture::Request { limit, rate_hz }))
+}
+
+#[cfg(feature = "motor-pid")]
+fn parse_pid<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<PidCommand, ParseError> {
+    let milli = |word: Option<&str>| {
+        word.and_then(parse_milli)
+            .ok_or(ParseError::InvalidArgument)
+    };
+    Ok(match words.next() {
+        None => PidCommand::Status,
+        Some("on") => PidCommand::Enable(true),
+        Some("off") => PidCommand::Enable(false),
+        Some("target") => PidCommand::Target(
+            words
+                .next()
+                .and_then(|word| word.parse().ok())
+                .ok_or(ParseError::InvalidArgument)?,
+        ),
+        Some("gains") => PidCommand::Gains {
+            kp_milli: milli(words.next())?,
+            ki_milli: milli(words.next())?,
+            kd_milli: milli(words.next())?,
+        },
+        Some(_) => return Err(ParseError::InvalidArgument),
+    })
+}
+
+/// Parse a non-negative decimal number with up to three decimal places (e.g. `1.25`) into
+/// thousandths.
+fn parse_milli(word: &str) -> Option<u32> {
+    let (integer, fraction) = word.split_once('.').unwrap_or((word, ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut milli: u32 = integer.parse::<u32>().ok()?.checked_mul(1000)?;
+    for (digit, scale) in fraction.bytes().zip([100, 10, 1]) {
+        milli = milli.checked_add((digit - b'0') as u32 * scale)?;
+    }
+    Some(milli)
+}
+
+/// Parse a decimal number with up to three decimal places (e.g. `-1.25`) into thousandths.
+fn parse_signed_milli(word: &str) -> Option<i32> {
+    match word.strip_prefix('-') {
+        Some(magnitude) => i32::try_from(parse_milli(magnitude)?)
+            .ok()
+            .map(|milli| -milli),
+        None => i32::try_from(parse_milli(word)?).ok(),
+    }
+}
+
+/// Collects incoming bytes until a full line has been received.
+pub struct LineBuffer {
+    buffer: heapless::Vec<u8, MAX_LINE_LEN>,
+    overflowed: bool,
+}
+
+#[cfg_attr(feature = "mqtt-sn", allow(dead_code))]
+impl LineBuffer {
+    pub const fn new() -> Self {
+        Self {
+            buffer: heapless::Vec::new(),
+            overflowed: false,
+        }
+    }
+
+    /// Feed a single byte into the buffer. Returns the parsed command once a full line has been
+    /// received.
+    ///
+    /// Empty lines are silently ignored so that `\r\n` line endings don't cause spurious errors.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Command, ParseError>> {
+        match byte {
+            b'\r' => None,
+            b'\n' => {
+                let result = if self.overflowed {
+                    Err(ParseError::LineTooLong)
+                } else {
+                    match core::str::from_utf8(&self.buffer) {
+                        Ok(line) => parse(line),
+                        Err(_) => Err(ParseError::UnknownCommand),
+                    }
+                };
+                self.buffer.clear();
+                self.overflowed = false;
+                match result {
+                    Err(ParseError::Empty) => None,
+                    result => Some(result),
+                }
+            }
+            byte => {
+                if self.buffer.push(byte).is_err() {
+                    self.overflowed = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_publish_interval() {
+        assert_eq!(parse("publish"), Ok(Command::Publish(None)));
+        assert_eq!(parse("publish off"), Ok(Command::Publish(Some(None))));
+        assert_eq!(
+            parse("publish 1000"),
+            Ok(Command::Publish(Some(Some(1_000))))
+        );
+        for line in [
+            "publish 9",
+            "publish 70000",
+            "publish 1s",
+            "publish 100 200",
+        ] {
+            assert_eq!(parse(line), Err(ParseError::InvalidArgument), "{}", line);
+        }
+    }
+
+    #[test]
+    fn parses_the_change_delta() {
+        assert_eq!(parse("change"), Ok(Command::Change(None)));
+        assert_eq!(parse("change off"), Ok(Command::Change(Some(None))));
+        assert_eq!(parse("change 5"), Ok(Command::Change(Some(Some(5)))));
+        for line in ["change 0", "change -5", "change 5mm", "change 5 10"] {
+            assert_eq!(parse(line), Err(ParseError::InvalidArgument), "{}", line);
+        }
+    }
+    #[test]
+    fn parses_the_captures() {
+        let start = |limit, rate_hz| {
+            Ok(Command::Capture(CaptureCommand::Start(capture::Request {
+                limit,
+                rate_hz,
+            })))
+        };
+        assert_eq!(
+            parse("capture"),
+            Ok(Command::Capture(CaptureCommand::Status))
+        );
+        assert_eq!(
+            parse("capture abort"),
+            Ok(Command::Capture(CaptureCommand::Abort))
+        );
+        assert_eq!(parse("capture 500"), start(Limit::Samples(500), None));
+        assert_eq!(parse("capture 60s 25"), start(Limit::Seconds(60), Some(25)));
+    }
+
+    #[test]
+    fn rejects_a_malformed_capture() {
+        for line in [
+            "capture 0",
+            "capture 0s",
+            "capture 3601s",
+            "capture s",
+            "capture 10m",
+            "capture 100 0",
+            "capture 100 51",
+            "capture 100 25 hz",
+            "capture abort now",
+        ] {
+            assert_eq!(parse(line), Err(ParseError::InvalidArgument), "{}", line);
+        }
+    }
+
+    #[test]
+    fn parses_the_gate() {
+        assert_eq!(parse("gate"), Ok(Command::Gate(None)));
+        assert_eq!(parse("gate off"), Ok(Command::Gate(Some(None))));
+        assert_eq!(
+            parse("gate 100 2000"),
+            Ok(Command::Gate(Some(Some((100, 2_000)))))
+        );
+        assert_eq!(
+            parse("gate 500 500"),
+            Ok(Command::Gate(Some(Some((500, 500)))))
+        );
+    }
+
+    #[test]
+    fn rejects_a_malformed_gate() {
+        for line in [
+            "gate 100",
+            "gate 2000 100",
+            "gate -1 100",
+            "gate 100 70000",
+            "gate 100 max",
+            "gate 100 2000 3000",
+            "gate off 100",
+        ] {
+            assert_eq!(parse(line), Err(ParseError::InvalidArgument), "{}", line);
+        }
+    }
+    #[test]
+    fn parses_the_drift_monitoring() {
+        assert_eq!(parse("drift"), Ok(Command::Drift(DriftCommand::Status)));
+        assert_eq!(
+            parse("drift check"),
+            Ok(Command::Drift(DriftCommand::Check))
+        );
+        assert_eq!(
+            parse("drift ref 2000"),
+            Ok(Command::Drift(DriftCommand::Reference(Some(2_000))))
+        );
+        assert_eq!(
+            parse("drift ref off"),
+            Ok(Command::Drift(DriftCommand::Reference(None)))
+        );
+    }
+
+    #[test]
+    fn rejects_a_malformed_drift_reference() {
+        for line in [
+            "drift ref",
+            "drift ref 0",
+            "drift ref 4001",
+            "drift ref -5",
+            "drift ref 2000mm",
+            "drift ref 2000 off",
+            "drift measure",
+        ] {
+            assert_eq!(parse(line), Err(ParseError::InvalidArgument), "{}", line);
+        }
+    }
+    #[test]
+    fn parses_the_schedules_it_reports() {
+        assert_eq!(parse("schedule"), Ok(Command::Schedule(None)));
+        for text in [
+            "always",
+            "mon-fri 18:00-08:00",
+            "mon,wed,sat-sun 07:30-07:30",
+        ] {
+            let mut line = heapless::String::<MAX_LINE_LEN>::new();
+            line.push_str("schedule ").unwrap();
+            line.push_str(text).unwrap();
+            let Ok(Command::Schedule(Some(schedule))) = parse(&line) else {
+                panic!("{} isn't parsed", text);
+            };
+            let mut written = heapless::String::<MAX_LINE_LEN>::new();
+            schedule.write_to(&mut written).unwrap();
+            assert_eq!(written, text);
+        }
+    }
+
+    #[test]
+    fn rejects_a_malformed_schedule() {
+        for line in [
+            "schedule mon",
+            "schedule mon 18:00",
+            "schedule mon 18:00-",
+            "schedule xyz 08:00-18:00",
+            "schedule mon 24:00-08:00",
+            "schedule mon 08:00-18:60",
+            "schedule mon 0800-1800",
+            "schedule mon 08:00-18:00 sun",
+            "schedule always mon",
+        ] {
+            assert_eq!(parse(line), Err(ParseError::InvalidArgument), "{}", line);
+        }
+    }
+
+    #[test]
+    fn parses_the_protection() {
+        assert_eq!(
+            parse("protect eeprom"),
+            Ok(Command::Protect(ProtectCommand::Write(Region::Eeprom)))
+        );
+        assert_eq!(
+            parse("protect rdp 1"),
+            Ok(Command::Protect(ProtectCommand::Read(None)))
+        );
+        assert_eq!(
+            parse("protect rdp 1 3f2a"),
+            Ok(Command::Protect(ProtectCommand::Read(Some(0x3f2a))))
+        );
+        for line in ["protect rdp", "protect rdp 2", "protect rdp 1 xyz"] {
+            assert_eq!(parse(line), Err(ParseError::InvalidArgument), "{}", line);
+        }
+    }
+}
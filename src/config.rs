@@ -0,0 +1,1118 @@
+//! Compile-time configuration of the application.
+//!
+//! Settings which are specific to a deployment (e.g. credentials) are read from environment
+//! variables at build time, e.g. `WIFI_SSID=my-network cargo build --features wifi`.
+
+/// Use the value of the environment variable at build time or the default if it isn't set.
+#[allow(unused_macros)]
+macro_rules! env_or {
+    ($name:literal, $default:expr) => {
+        match option_env!($name) {
+            Some(value) => value,
+            None => $default,
+        }
+    };
+}
+
+/// Like [`env_or`] but for numeric settings. Invalid values fail the build.
+#[allow(unused_macros)]
+macro_rules! env_u32_or {
+    ($name:literal, $default:expr) => {
+        match option_env!($name) {
+            Some(value) => $crate::config::parse_u32(value),
+            None => $default,
+        }
+    };
+}
+
+/// Like [`env_u32_or`] but for signed numeric settings.
+#[allow(unused_macros)]
+macro_rules! env_i32_or {
+    ($name:literal, $default:expr) => {
+        match option_env!($name) {
+            Some(value) => $crate::config::parse_i32(value),
+            None => $default,
+        }
+    };
+}
+
+/// Like [`env_or`] but for boolean settings (`0`/`1`/`false`/`true`). Invalid values fail the
+/// build.
+#[allow(unused_macros)]
+macro_rules! env_bool_or {
+    ($name:literal, $default:expr) => {
+        match option_env!($name) {
+            Some(value) => $crate::config::parse_bool(value),
+            None => $default,
+        }
+    };
+}
+
+/// Parse a boolean at compile time.
+#[allow(dead_code)]
+pub const fn parse_bool(value: &str) -> bool {
+    match value.as_bytes() {
+        b"1" | b"true" => true,
+        b"0" | b"false" => false,
+        _ => panic!("invalid boolean"),
+    }
+}
+
+/// Parse a decimal or hexadecimal (with a `0x` prefix, e.g. for I2C addresses) number at compile
+/// time.
+#[allow(dead_code)]
+pub const fn parse_u32(value: &str) -> u32 {
+    let bytes = value.as_bytes();
+    let (radix, mut i) = match bytes {
+        [b'0', b'x', ..] => (16, 2),
+        _ => (10, 0),
+    };
+    assert!(bytes.len() > i, "empty number");
+    let mut result: u32 = 0;
+    while i < bytes.len() {
+        let digit = match bytes[i] {
+            digit @ b'0'..=b'9' => digit - b'0',
+            digit @ b'a'..=b'f' if radix == 16 => digit - b'a' + 10,
+            digit @ b'A'..=b'F' if radix == 16 => digit - b'A' + 10,
+            _ => panic!("invalid number"),
+        };
+        result = result * radix + digit as u32;
+        i += 1;
+    }
+    result
+}
+
+/// Parse a number like [`parse_u32`] with an optional `-` sign at compile time.
+#[allow(dead_code)]
+pub const fn parse_i32(value: &str) -> i32 {
+    match value.as_bytes() {
+        [b'-', digits @ ..] => match core::str::from_utf8(digits) {
+            Ok(digits) => -(parse_u32(digits) as i32),
+            Err(_) => panic!("invalid number"),
+        },
+        _ => parse_u32(value) as i32,
+    }
+}
+
+/// The real-time configuration of the RTIC application: the depths of the queues between the tasks.
+///
+/// The priorities of the tasks, the interrupts which dispatch the software tasks & their capacities
+/// are only set in the attributes in `main.rs`, RTIC only accepts literals there.
+///
+/// Invariants of the priorities:
+/// * everything which may take long (the sensors, the flash, the storage, the displays) runs at
+///   priority 1, only the reception of the links (2) & the step pulses of the stepper motor (2)
+///   run above it. They mustn't wait for anything.
+/// * a resource which is shared with a task above priority 1 (`links` & `outputs`) blocks that
+///   task while it's locked, thus it has to be locked briefly (e.g. not while formatting a long
+///   response or writing to the flash).
+/// * there's one dispatcher per priority of the software tasks. The dispatchers & the interrupts
+///   which are pended by the firmware itself (e.g. `EXTI3` for the displays) aren't used by any
+///   peripheral.
+pub mod rt {
+    /// Number of measurements which can wait for `publish`, see [`crate::measurement_queue`].
+    pub const MEASUREMENT_QUEUE_LEN: usize = 8;
+    /// Size of the transmit buffer of each UART link, see [`crate::uart`].
+    pub const UART_TX_QUEUE_LEN: usize = 640;
+
+    const _: () = assert!(
+        MEASUREMENT_QUEUE_LEN.is_power_of_two(),
+        "the measurement queue must be a power of 2"
+    );
+    // a response is written at once or dropped
+    const _: () = assert!(
+        UART_TX_QUEUE_LEN >= crate::command::MAX_RESPONSE_LEN,
+        "the longest response must fit into the transmit buffer"
+    );
+}
+
+/// Settings of the [`crate::ambient`] light monitoring.
+pub mod ambient {
+    /// Ambient rate above which the ambient light is high.
+    pub const HIGH_KCPS: u16 = env_u32_or!("AMBIENT_HIGH_KCPS", 8_000) as u16;
+    /// Whether to switch to the short distance mode while the ambient light is high.
+    pub const SHORT_MODE: bool = env_bool_or!("AMBIENT_SHORT_MODE", false);
+
+    const _: () = assert!(HIGH_KCPS > 0, "the ambient limit must not be 0");
+}
+
+/// Settings of the [`crate::lens`] monitoring.
+pub mod lens {
+    /// Drop of the signal (in percent of its baseline) at which the cover glass is dirty.
+    pub const DIRTY_PERCENT: u32 = env_u32_or!("LENS_DIRTY_PERCENT", 30);
+
+    const _: () = assert!(
+        DIRTY_PERCENT >= 10 && DIRTY_PERCENT <= 90,
+        "the limit must be between 10 and 90 percent"
+    );
+}
+
+/// Settings of the application modes, see [`crate::app_mode`].
+pub mod app_mode {
+    /// A target closer than this is considered to be present.
+    pub const PRESENCE_MM: u16 = env_u32_or!("PRESENCE_MM", 1000) as u16;
+
+    const _: () = assert!(PRESENCE_MM > 0, "the presence threshold must not be 0");
+}
+
+/// Settings of the low-power presence mode, see [`crate::low_power`].
+pub mod low_power {
+    /// Interval of the measurements while waiting for a target.
+    pub const INTERVAL_MS: u16 = env_u32_or!("LOW_POWER_INTERVAL_MS", 1000) as u16;
+    /// Timing budget of the measurements while waiting for a target, 33 ms is the shortest one of
+    /// the long distance mode.
+    pub const TIMING_BUDGET_MS: u16 = env_u32_or!("LOW_POWER_TIMING_BUDGET_MS", 33) as u16;
+    /// Time after which the firmware waits for the next target once the present one is gone.
+    pub const LINGER_MS: u32 = env_u32_or!("LOW_POWER_LINGER_MS", 5_000);
+
+    const _: () = assert!(
+        TIMING_BUDGET_MS < INTERVAL_MS,
+        "the measurements must fit into their interval"
+    );
+}
+
+/// The default zones of the [`crate::geofence`], which are used until others have been saved.
+pub mod geofence {
+    /// A target closer than this is in the first zone.
+    pub const NEAR_MM: u16 = env_u32_or!("EVENT_NEAR_MM", 300) as u16;
+    /// A target closer than this is in the second zone.
+    pub const FAR_MM: u16 = env_u32_or!("EVENT_FAR_MM", 1000) as u16;
+    /// Time for which the target has to be in (or out of) a zone before it's entered (or left).
+    pub const DEBOUNCE_MS: u16 = env_u32_or!("GEOFENCE_DEBOUNCE_MS", 300) as u16;
+
+    const _: () = assert!(
+        NEAR_MM < FAR_MM,
+        "the near zone must end before the far one"
+    );
+}
+
+/// Settings of the offset calibration, see [`crate::calibration`].
+pub mod calibration {
+    /// Distance to the target during the calibration, ST recommends 100 mm.
+    pub const TARGET_MM: u16 = env_u32_or!("CALIBRATION_TARGET_MM", 100) as u16;
+
+    /// Distance to the target during the crosstalk calibration, ST recommends the distance at
+    /// which the sensor starts to report a too short distance, 600 mm is typical behind a cover
+    /// glass.
+    pub const CROSS_TALK_TARGET_MM: u16 =
+        env_u32_or!("CALIBRATION_CROSS_TALK_TARGET_MM", 600) as u16;
+
+    const _: () = assert!(
+        TARGET_MM > 0 && TARGET_MM <= crate::calibration::MAX_TARGET_MM,
+        "the calibration target must be between 1 & 4000 mm"
+    );
+    const _: () = assert!(
+        CROSS_TALK_TARGET_MM > 0,
+        "the crosstalk calibration target must not be at 0 mm"
+    );
+}
+
+/// Settings of the [`crate::fast_mode`] profile.
+pub mod fast {
+    /// Whether the fast profile uses the long distance mode, which reaches a lower rate than the
+    /// short one.
+    pub const LONG_DISTANCE: bool = env_bool_or!("FAST_LONG_DISTANCE", false);
+    /// The rate the fast profile is expected to reach, which is checked when it's applied.
+    pub const RATE_HZ: u32 = env_u32_or!("FAST_RATE_HZ", 50);
+
+    // the sensor doesn't measure faster than about 52 Hz
+    const _: () = assert!(
+        RATE_HZ > 0 && RATE_HZ <= 50,
+        "the rate of the fast profile must be 1 - 50 Hz"
+    );
+    // the long distance mode takes 24 ms per measurement, i.e. about 41.6 Hz
+    const _: () = assert!(
+        !LONG_DISTANCE || RATE_HZ <= 41,
+        "the long distance mode of the fast profile reaches at most 41 Hz, lower FAST_RATE_HZ"
+    );
+}
+
+/// Settings of the [`crate::drift`] monitoring of the calibration.
+pub mod drift {
+    /// The distance to the static scene the drift is checked against, `0` disables the
+    /// monitoring until a distance is set with `drift ref`.
+    pub const REFERENCE_MM: u16 = env_u32_or!("DRIFT_REFERENCE_MM", 0) as u16;
+    /// Interval at which the drift is checked.
+    pub const INTERVAL_S: u32 = env_u32_or!("DRIFT_INTERVAL_S", 3_600);
+    /// Estimated drift of the offset at which a recalibration is requested.
+    pub const LIMIT_MM: u16 = env_u32_or!("DRIFT_LIMIT_MM", 20) as u16;
+
+    // the default is 0, where clippy considers the check pointless
+    #[allow(clippy::absurd_extreme_comparisons)]
+    const _: () = assert!(
+        REFERENCE_MM <= crate::calibration::MAX_TARGET_MM,
+        "the reference distance must be at most 4000 mm"
+    );
+    const _: () = assert!(INTERVAL_S > 0, "the drift check interval must not be 0");
+    const _: () = assert!(LIMIT_MM > 0, "the drift limit must not be 0 mm");
+}
+
+/// Settings of the independent watchdog.
+pub mod watchdog {
+    /// Time without feeding after which the watchdog resets the board.
+    pub const TIMEOUT_MS: u32 = env_u32_or!("WATCHDOG_TIMEOUT_MS", 1_000);
+    /// Interval at which the watchdog is fed (and the links are ticked).
+    pub const FEED_INTERVAL_MS: u32 = env_u32_or!("WATCHDOG_FEED_INTERVAL_MS", 200);
+    /// A feed with less time left until the timeout is logged as a warning, see
+    /// [`crate::watchdog_margin`].
+    pub const MIN_MARGIN_MS: u32 = env_u32_or!("WATCHDOG_MIN_MARGIN_MS", TIMEOUT_MS / 2);
+
+    // the longest timeout of the watchdog is 4096 ticks of the LSI (32 kHz) divided by 256
+    const _: () = assert!(
+        TIMEOUT_MS > 0 && TIMEOUT_MS <= 32_000,
+        "the watchdog timeout must be between 1 & 32000 ms"
+    );
+    // a late feed (e.g. by a busy higher priority task) mustn't reset the board
+    const _: () = assert!(
+        FEED_INTERVAL_MS > 0 && FEED_INTERVAL_MS < TIMEOUT_MS / 2,
+        "the watchdog must be fed more often than every half timeout"
+    );
+    const _: () = assert!(
+        MIN_MARGIN_MS < TIMEOUT_MS - FEED_INTERVAL_MS,
+        "the periodic feeds mustn't be below the minimum margin of the watchdog"
+    );
+}
+
+/// Settings of the [`crate::task_watchdog`].
+pub mod task_watchdog {
+    /// Time by which a task may be late before it counts as failed, e.g. while a flash sector is
+    /// erased.
+    pub const MARGIN_MS: u32 = env_u32_or!("TASK_WATCHDOG_MARGIN_MS", 5_000);
+
+    const _: () = assert!(
+        MARGIN_MS >= super::watchdog::TIMEOUT_MS,
+        "the margin of the task watchdog must be at least the watchdog timeout"
+    );
+}
+
+/// Settings of the I2C buses, see [`crate::i2c_timing`] & [`crate::i2c_guard`].
+pub mod i2c {
+    /// Speed of the buses in kHz: `400` (fast mode) or `100` (standard mode, e.g. for long
+    /// cables).
+    pub const SPEED_KHZ: u32 = env_u32_or!("I2C_SPEED_KHZ", 400);
+    /// Maximum time in µs during which a bus may not progress (e.g. while a device
+    /// stretches the clock) before a transaction times out.
+    pub const TIMEOUT_US: u32 = env_u32_or!("I2C_TIMEOUT_US", 5_000);
+
+    const _: () = assert!(
+        SPEED_KHZ == 100 || SPEED_KHZ == 400,
+        "the I2C speed must be 100 or 400 kHz"
+    );
+    const _: () = assert!(
+        TIMEOUT_US >= 100 && TIMEOUT_US <= 100_000,
+        "the I2C timeout must be 100 µs - 100 ms"
+    );
+    const _: () = assert!(
+        TIMEOUT_US / 1_000 < super::watchdog::TIMEOUT_MS,
+        "the I2C timeout must be below the timeout of the watchdog"
+    );
+}
+
+/// Settings of the [`crate::sensor_supervisor`].
+pub mod sensor_supervisor {
+    /// Time without a successful read after which a ranging sensor is considered to be failing.
+    pub const STALL_TIMEOUT_MS: u32 = env_u32_or!("SENSOR_STALL_TIMEOUT_MS", 5_000);
+    /// Number of re-initialisations per boot before the board is reset.
+    pub const MAX_REINITS: u8 = env_u32_or!("SENSOR_MAX_REINITS", 3) as u8;
+    /// Number of resets in a row before the safe mode is entered.
+    pub const MAX_RESETS: u32 = env_u32_or!("SENSOR_MAX_RESETS", 2);
+
+    // the slowest measurement rate is 1 Hz
+    const _: () = assert!(
+        STALL_TIMEOUT_MS > 1_000,
+        "the stall timeout must be longer than 1000 ms"
+    );
+}
+
+/// Settings of the [`crate::interrupt_guard`].
+pub mod interrupt_guard {
+    /// Number of data ready interrupts per second above which the line is disabled.
+    pub const MAX_RATE_HZ: u32 = env_u32_or!("INTERRUPT_MAX_RATE_HZ", 200);
+
+    // the fastest measurement rate is below 100 Hz
+    const _: () = assert!(
+        MAX_RATE_HZ >= 100,
+        "the maximum interrupt rate must be at least 100 Hz"
+    );
+}
+
+/// Settings of the data ready interrupt of the TOF sensors, see [`crate::tof`].
+///
+/// The polarity is the one of the driver, which inverts the bit of the register: a sensor set to
+/// [`vl53l1x_uld::Polarity::ActiveHigh`] pulls its (open-drain) line low for a measurement, thus
+/// the interrupt has to be taken on the falling edge & the active low polarity needs the rising
+/// edge. Any other combination fires once the sensor clears the interrupt, i.e. a measurement
+/// late or not at all, thus it fails the build.
+pub mod tof_interrupt {
+    /// Whether the sensor's interrupt is set to the (driver's) active high polarity.
+    pub const ACTIVE_HIGH: bool = env_bool_or!("TOF_INTERRUPT_ACTIVE_HIGH", true);
+    /// Whether the EXTI line triggers on the falling edge (otherwise on the rising edge).
+    pub const FALLING_EDGE: bool = env_bool_or!("TOF_INTERRUPT_FALLING_EDGE", true);
+
+    const _: () = assert!(
+        ACTIVE_HIGH == FALLING_EDGE,
+        "the active high polarity needs the falling edge & the active low polarity the rising edge"
+    );
+}
+
+/// Settings of the [`crate::isr_budget`].
+pub mod isr_budget {
+    /// The longest time the data ready interrupt may take without a warning, the default leaves
+    /// room for reading a measurement at 100 kHz.
+    pub const TOF_INTERRUPT_US: u32 = env_u32_or!("ISR_BUDGET_TOF_INTERRUPT_US", 3_000);
+
+    const _: () = assert!(TOF_INTERRUPT_US > 0, "the budget must not be 0");
+}
+
+/// Settings of the [`crate::brown_out`] detection.
+pub mod brown_out {
+    /// Level of the brown-out reset: 3 (2.7 V), 2 (2.4 V), 1 (2.1 V) or 0 (off, only the
+    /// power-on reset at 1.8 V).
+    pub const BOR_LEVEL: u8 = env_u32_or!("BOR_LEVEL", 3) as u8;
+    /// Supply voltage below which the data loggers are stopped, 2200 to 2900 mV in steps of
+    /// 100 mV.
+    pub const PVD_LEVEL_MV: u32 = env_u32_or!("PVD_LEVEL_MV", 2_900);
+
+    const _: () = assert!(BOR_LEVEL <= 3, "the BOR level must be between 0 & 3");
+    const _: () = assert!(
+        PVD_LEVEL_MV >= 2_200 && PVD_LEVEL_MV <= 2_900 && PVD_LEVEL_MV.is_multiple_of(100),
+        "the PVD level must be between 2200 & 2900 mV in steps of 100 mV"
+    );
+    // the loggers have to be stopped before the reset
+    const _: () = assert!(
+        BOR_LEVEL < 3 || PVD_LEVEL_MV > 2_700,
+        "the PVD level must be above the BOR level"
+    );
+}
+
+/// Settings of the health report.
+pub mod health {
+    /// Interval at which the health report is sent.
+    pub const INTERVAL_S: u32 = env_u32_or!("HEALTH_INTERVAL_S", 10);
+
+    const _: () = assert!(INTERVAL_S > 0, "the health report interval must not be 0");
+}
+
+/// Settings of the summary which is logged periodically.
+pub mod summary {
+    /// Interval at which the summary is logged.
+    pub const INTERVAL_S: u32 = env_u32_or!("SUMMARY_INTERVAL_S", 60);
+
+    const _: () = assert!(INTERVAL_S > 0, "the summary interval must not be 0");
+}
+
+/// Settings of the [`crate::measurement_queue`].
+pub mod measurement_queue {
+    use crate::measurement_queue::OverflowPolicy;
+
+    /// What to drop if the queue is full, the oldest measurement unless
+    /// `MEASUREMENT_QUEUE_DROP_NEWEST` is set.
+    pub const POLICY: OverflowPolicy = if env_bool_or!("MEASUREMENT_QUEUE_DROP_NEWEST", false) {
+        OverflowPolicy::DropNewest
+    } else {
+        OverflowPolicy::DropOldest
+    };
+}
+
+/// Settings of the flow control & the backpressure of the UART links, see [`crate::uart`].
+pub mod uart {
+    /// Whether the host can pause the transmission with XOFF & resume it with XON.
+    pub const XON_XOFF: bool = env_bool_or!("UART_XON_XOFF", true);
+    /// Bytes of the transmit buffer which the measurement frames leave free for the responses &
+    /// the other frames, by default enough for the longest response.
+    pub const RESERVED_LEN: usize =
+        env_u32_or!("UART_RESERVED_LEN", crate::command::MAX_RESPONSE_LEN as u32) as usize;
+
+    const _: () = assert!(
+        RESERVED_LEN < crate::uart::TX_QUEUE_LEN,
+        "the reserved space must be smaller than the transmit buffer"
+    );
+}
+
+/// Settings of the [`crate::log_backend`].
+pub mod log_backend {
+    /// Whether the warnings & errors are sent over the UART links if no debugger is attached at
+    /// boot.
+    pub const UART_FALLBACK: bool = env_bool_or!("LOG_UART_FALLBACK", true);
+}
+
+/// Settings of the [`crate::wrap_around`] check.
+pub mod wrap_around {
+    /// A valid measurement which is closer than the previous one by more than this is suspect.
+    pub const MAX_JUMP_MM: u16 = env_u32_or!("WRAP_AROUND_MAX_JUMP_MM", 1_500) as u16;
+    /// Whether wrapped measurements are dropped instead of being reported as
+    /// [`RangeStatus::Wraparound`](vl53l1x_uld::RangeStatus::Wraparound).
+    pub const SUPPRESS: bool = env_bool_or!("WRAP_AROUND_SUPPRESS", false);
+
+    const _: () = assert!(
+        MAX_JUMP_MM >= 100,
+        "the maximum jump must be at least 100 mm"
+    );
+}
+
+/// Settings of the [`crate::range_gate`].
+pub mod range_gate {
+    /// The shortest accepted distance.
+    pub const MIN_MM: u16 = env_u32_or!("RANGE_GATE_MIN_MM", 0) as u16;
+    /// The longest accepted distance, `0` opens the gate.
+    pub const MAX_MM: u16 = env_u32_or!("RANGE_GATE_MAX_MM", 0) as u16;
+
+    // the default of the shortest distance is 0, where clippy considers the check pointless
+    #[allow(clippy::absurd_extreme_comparisons)]
+    const _: () = assert!(
+        MAX_MM == 0 || MIN_MM <= MAX_MM,
+        "the shortest accepted distance must not be longer than the longest one"
+    );
+}
+
+/// Settings of the [`crate::rollup`].
+pub mod rollup {
+    /// Interval at which the rollup is sent.
+    pub const INTERVAL_S: u32 = env_u32_or!("ROLLUP_INTERVAL_S", 60);
+    /// Deviation of the measurement rate from the configured one (in percent) which is flagged.
+    pub const RATE_TOLERANCE_PERCENT: u32 = env_u32_or!("ROLLUP_RATE_TOLERANCE_PERCENT", 10);
+
+    const _: () = assert!(INTERVAL_S > 0, "the rollup interval must not be 0");
+}
+
+/// Settings of the [`crate::schedule`].
+pub mod schedule {
+    /// Offset of the local time (in which the active hours are given) from UTC in minutes, e.g.
+    /// `60` for CET. Daylight saving time isn't applied.
+    pub const UTC_OFFSET_MIN: i32 = env_i32_or!("SCHEDULE_UTC_OFFSET_MIN", 0);
+
+    const _: () = assert!(
+        UTC_OFFSET_MIN >= -12 * 60 && UTC_OFFSET_MIN <= 14 * 60,
+        "the offset from UTC must be -720 - 840 minutes"
+    );
+}
+
+/// Settings of the [`crate::sample_history`].
+pub mod sample_history {
+    /// Number of the latest measurements which are kept for the `dump csv` command.
+    pub const LEN: usize = env_u32_or!("SAMPLE_HISTORY_LEN", 64) as usize;
+
+    const _: () = assert!(
+        LEN > 0 && LEN <= u16::MAX as usize,
+        "the sample history must keep 1 - 65535 measurements"
+    );
+}
+
+/// Settings of the [`crate::change_filter`].
+pub mod change {
+    /// Interval at which a keepalive frame is sent while no measurement is published.
+    pub const KEEPALIVE_S: u32 = env_u32_or!("KEEPALIVE_S", 10);
+
+    const _: () = assert!(KEEPALIVE_S > 0, "the keepalive interval must not be 0");
+}
+
+/// Settings of the [`crate::firmware_update`].
+#[cfg(feature = "firmware-update")]
+pub mod firmware_update {
+    /// Time after the first boot of a new firmware within which it has to be healthy, otherwise
+    /// the previous one boots again.
+    pub const CONFIRM_TIMEOUT_S: u32 = env_u32_or!("FIRMWARE_CONFIRM_TIMEOUT_S", 60);
+
+    const _: () = assert!(CONFIRM_TIMEOUT_S > 0, "the confirm timeout must not be 0");
+}
+
+/// Settings of the [`crate::warm_up`].
+pub mod warm_up {
+    /// Time after the start of the ranging during which the measurements are settling, `0`
+    /// disables the warm-up.
+    pub const DURATION_MS: u32 = env_u32_or!("WARM_UP_MS", 500);
+
+    const _: () = assert!(
+        DURATION_MS <= 60_000,
+        "the warm-up must not be longer than 60 s"
+    );
+}
+
+/// Settings of the [`crate::pause`].
+pub mod pause {
+    /// Number of measurements which are discarded after the acquisition has been resumed, e.g.
+    /// the one which was pending when it was paused.
+    pub const DISCARD: u32 = env_u32_or!("PAUSE_DISCARD", 2);
+
+    const _: () = assert!(DISCARD <= 100, "at most 100 measurements can be discarded");
+}
+
+/// Settings of the [`crate::webhook`] payloads.
+pub mod webhook {
+    /// Send the presence & zone events as JSON webhook payloads.
+    pub const ENABLED: bool = env_bool_or!("WEBHOOK_EVENTS", false);
+}
+
+/// Settings of the [`crate::track`]ing of a moving target.
+pub mod track {
+    /// Publish a track record with each measurement.
+    pub const ENABLED: bool = env_bool_or!("TRACK_OUTPUT", false);
+    /// Share of the difference to the measured distance by which the position is corrected.
+    pub const ALPHA_PCT: u32 = env_u32_or!("TRACK_ALPHA_PCT", 50);
+    /// Share of the difference to the measured distance by which the velocity is corrected.
+    pub const BETA_PCT: u32 = env_u32_or!("TRACK_BETA_PCT", 10);
+
+    // the filter is only stable with a velocity gain below the position gain
+    const _: () = assert!(
+        ALPHA_PCT >= 1 && ALPHA_PCT <= 100 && BETA_PCT >= 1 && BETA_PCT <= ALPHA_PCT,
+        "the gains of the tracking must be 1 - 100 %, the velocity gain at most the position gain"
+    );
+}
+
+/// Settings of the [`crate::batch`]ing of the published measurements.
+pub mod batch {
+    /// Number of measurements per batch, 1 to publish each measurement on its own.
+    pub const SIZE: u32 = env_u32_or!("BATCH_SIZE", 1);
+    /// Time after its first measurement at which a batch is published even if it isn't full.
+    pub const TIMEOUT_MS: u32 = env_u32_or!("BATCH_TIMEOUT_MS", 1_000);
+    /// Whether the measurements are batched.
+    pub const ENABLED: bool = SIZE > 1;
+
+    const _: () = assert!(
+        SIZE >= 1 && SIZE as usize <= crate::batch::MAX_SIZE,
+        "the batch size must be 1 - 6"
+    );
+    const _: () = assert!(
+        TIMEOUT_MS >= 1 && TIMEOUT_MS <= crate::batch::MAX_TIMEOUT_MS,
+        "the batch timeout must be 1 - 9999 ms"
+    );
+}
+
+/// Settings of the WiFi uplink.
+#[cfg(feature = "wifi")]
+pub mod wifi {
+    /// SSID of the WiFi network to join.
+    pub const SSID: &str = env_or!("WIFI_SSID", "tof-sensor");
+    /// Password of the WiFi network to join.
+    pub const PASSWORD: &str = env_or!("WIFI_PASSWORD", "");
+    /// Host name or IP address of the endpoint to which the measurements are sent.
+    pub const HOST: &str = env_or!("WIFI_HOST", "192.168.1.1");
+    /// TCP port of the endpoint.
+    pub const PORT: &str = env_or!("WIFI_PORT", "8080");
+    /// If set, measurements are sent as HTTP `POST` requests to this path, otherwise the raw
+    /// telemetry frames are streamed over the TCP connection.
+    pub const HTTP_PATH: &str = env_or!("WIFI_HTTP_PATH", "");
+}
+
+/// Settings of the Bluetooth module.
+#[cfg(feature = "bluetooth")]
+pub mod bluetooth {
+    /// Name under which the module is visible to other devices.
+    pub const NAME: &str = env_or!("BT_NAME", "tof-sensor");
+}
+
+/// Settings of the MQTT-SN client.
+#[cfg(feature = "mqtt-sn")]
+pub mod mqtt_sn {
+    /// Client id used to connect to the gateway.
+    pub const CLIENT_ID: &str = env_or!("MQTT_SN_CLIENT_ID", "tof-sensor");
+    /// Topic to which the telemetry frames are published.
+    pub const DISTANCE_TOPIC: &str = env_or!("MQTT_SN_DISTANCE_TOPIC", "tof/distance");
+    /// Topic to which the command responses are published.
+    pub const EVENT_TOPIC: &str = env_or!("MQTT_SN_EVENT_TOPIC", "tof/events");
+    /// Topic on which commands are received.
+    pub const COMMAND_TOPIC: &str = env_or!("MQTT_SN_COMMAND_TOPIC", "tof/command");
+    /// Keep alive interval announced to the gateway.
+    pub const KEEP_ALIVE_S: u32 = env_u32_or!("MQTT_SN_KEEP_ALIVE_S", 60);
+
+    const _: () = assert!(
+        KEEP_ALIVE_S > 0 && KEEP_ALIVE_S <= u16::MAX as u32,
+        "invalid keep alive interval"
+    );
+}
+
+/// Settings of the WS2812 LED strip.
+#[cfg(feature = "led-strip")]
+pub mod led_strip {
+    /// Number of LEDs on the strip.
+    pub const LEN: usize = env_u32_or!("LED_STRIP_LEN", 8) as usize;
+    /// Distances up to this are in the near zone (red).
+    pub const NEAR_MM: u16 = env_u32_or!("LED_STRIP_NEAR_MM", 300) as u16;
+    /// Distances up to this are in the middle zone (yellow), everything beyond in the far zone
+    /// (green).
+    pub const FAR_MM: u16 = env_u32_or!("LED_STRIP_FAR_MM", 1000) as u16;
+    /// Distance at which no LED is lit anymore, all LEDs are lit at 0 mm.
+    pub const MAX_MM: u16 = env_u32_or!("LED_STRIP_MAX_MM", 2000) as u16;
+    /// Brightness of the lit LEDs (1 - 255).
+    pub const BRIGHTNESS: u8 = env_u32_or!("LED_STRIP_BRIGHTNESS", 32) as u8;
+
+    const _: () = assert!(LEN > 0, "the LED strip needs at least one LED");
+    const _: () = assert!(
+        NEAR_MM < FAR_MM && FAR_MM < MAX_MM,
+        "the zones of the LED strip must be increasing"
+    );
+}
+
+/// Settings of the proximity LED.
+#[cfg(feature = "proximity-led")]
+pub mod proximity_led {
+    /// The LED is at full brightness at or below this distance.
+    pub const MIN_MM: u16 = env_u32_or!("PROXIMITY_LED_MIN_MM", 100) as u16;
+    /// The LED is off at or beyond this distance.
+    pub const MAX_MM: u16 = env_u32_or!("PROXIMITY_LED_MAX_MM", 1000) as u16;
+
+    const _: () = assert!(
+        MIN_MM < MAX_MM,
+        "the minimum distance must be below the maximum"
+    );
+}
+
+/// Settings of the battery operation.
+#[cfg(feature = "battery")]
+pub mod battery {
+    /// Resistance of the voltage divider between the battery & the ADC pin.
+    pub const DIVIDER_TOP_KOHM: u32 = env_u32_or!("BATTERY_DIVIDER_TOP_KOHM", 100);
+    /// Resistance of the voltage divider between the ADC pin & GND.
+    pub const DIVIDER_BOTTOM_KOHM: u32 = env_u32_or!("BATTERY_DIVIDER_BOTTOM_KOHM", 100);
+    /// Below this voltage the battery is low & the measurement rate is reduced.
+    pub const LOW_MV: u32 = env_u32_or!("BATTERY_LOW_MV", 3_500);
+    /// Below this voltage the battery is critical & the firmware shuts down.
+    pub const CRITICAL_MV: u32 = env_u32_or!("BATTERY_CRITICAL_MV", 3_300);
+    /// The battery isn't low anymore once the voltage has risen this much above [`LOW_MV`].
+    pub const HYSTERESIS_MV: u32 = env_u32_or!("BATTERY_HYSTERESIS_MV", 100);
+    /// Inter-measurement period of the TOF sensor while the battery is low.
+    pub const LOW_INTER_MEASUREMENT_MS: u16 =
+        env_u32_or!("BATTERY_LOW_INTER_MEASUREMENT_MS", 1_000) as u16;
+
+    const _: () = assert!(
+        DIVIDER_BOTTOM_KOHM > 0,
+        "the divider needs a bottom resistor"
+    );
+    const _: () = assert!(
+        CRITICAL_MV < LOW_MV,
+        "the critical threshold must be below the low one"
+    );
+    const _: () = assert!(
+        LOW_MV * DIVIDER_BOTTOM_KOHM / (DIVIDER_TOP_KOHM + DIVIDER_BOTTOM_KOHM) < 3_300,
+        "the divided low threshold must be within the range of the ADC (3.3 V)"
+    );
+    const _: () = assert!(
+        LOW_INTER_MEASUREMENT_MS >= 20,
+        "the inter-measurement period must be at least the shortest timing budget"
+    );
+}
+
+/// Settings of the environmental sensor.
+#[cfg(feature = "environment")]
+pub mod environment {
+    /// I2C address of the BME280, `0x76` with `SDO` low and `0x77` with `SDO` high.
+    pub const ADDRESS: u8 = env_u32_or!("BME280_ADDRESS", 0x76) as u8;
+}
+
+/// Settings of the power monitor.
+#[cfg(feature = "power")]
+pub mod power {
+    /// I2C address of the INA219, `0x40` with `A0` & `A1` low.
+    pub const ADDRESS: u8 = env_u32_or!("INA219_ADDRESS", 0x40) as u8;
+    /// Resistance of the shunt, 0.1 Ω on most breakout boards.
+    pub const SHUNT_MILLIOHM: u32 = env_u32_or!("INA219_SHUNT_MILLIOHM", 100);
+
+    const _: () = assert!(SHUNT_MILLIOHM > 0, "the shunt resistance must not be 0");
+}
+
+/// Settings of the GPIO expander with the configuration switches & the status LEDs.
+#[cfg(feature = "gpio-expander")]
+pub mod gpio_expander {
+    /// I2C address of the MCP23017, `0x20` with `A0` - `A2` low.
+    pub const ADDRESS: u8 = env_u32_or!("MCP23017_ADDRESS", 0x20) as u8;
+
+    const _: () = assert!(
+        ADDRESS >= 0x20 && ADDRESS <= 0x27,
+        "the address of the MCP23017 must be within 0x20 - 0x27"
+    );
+}
+
+/// Settings of the PWM expander driving the indicator LEDs of the zones.
+#[cfg(feature = "pwm-expander")]
+pub mod pwm_expander {
+    use crate::geofence::MAX_ZONES;
+
+    /// I2C address of the PCA9685, `0x40` with `A0` - `A5` low.
+    pub const ADDRESS: u8 = env_u32_or!("PCA9685_ADDRESS", 0x40) as u8;
+    /// The channel of the indicator of zone 0, the other zones follow.
+    pub const FIRST_CHANNEL: u8 = env_u32_or!("PCA9685_FIRST_CHANNEL", 0) as u8;
+    /// Frequency of the PWM.
+    pub const FREQUENCY_HZ: u32 = env_u32_or!("PCA9685_FREQUENCY_HZ", 1_000);
+    /// Brightness of a lit indicator.
+    pub const BRIGHTNESS_PERCENT: u32 = env_u32_or!("PCA9685_BRIGHTNESS_PERCENT", 100);
+
+    const _: () = assert!(
+        ADDRESS >= 0x40 && ADDRESS < 0x80 && ADDRESS != 0x70,
+        "the address of the PCA9685 must be within 0x40 - 0x7F but not 0x70 (all call)"
+    );
+    #[cfg(feature = "power")]
+    const _: () = assert!(
+        ADDRESS != super::power::ADDRESS,
+        "the PCA9685 & the INA219 must have different addresses"
+    );
+    const _: () = assert!(
+        FIRST_CHANNEL as usize + MAX_ZONES <= crate::pwm_expander::CHANNELS as usize,
+        "the indicators of all zones must fit into the 16 channels"
+    );
+    const _: () = assert!(
+        FREQUENCY_HZ >= 24 && FREQUENCY_HZ <= 1_526,
+        "the PWM frequency must be within 24 - 1526 Hz"
+    );
+    const _: () = assert!(
+        BRIGHTNESS_PERCENT <= 100,
+        "the brightness can't be more than 100 %"
+    );
+}
+
+/// Settings of the TOF sensors behind the I2C multiplexer.
+#[cfg(feature = "tof-mux")]
+pub mod tof_mux {
+    /// I2C address of the TCA9548A, `0x70` with `A0` - `A2` low.
+    pub const ADDRESS: u8 = env_u32_or!("TOF_MUX_ADDRESS", 0x70) as u8;
+    /// The channels with a sensor, bit `n` for channel `n` (e.g. `0x0F` for channels 0 - 3).
+    pub const CHANNELS: u8 = env_u32_or!("TOF_MUX_CHANNELS", 0xFF) as u8;
+    /// Interval at which the sensors are polled for measurements.
+    pub const POLL_INTERVAL_MS: u32 = env_u32_or!("TOF_MUX_POLL_INTERVAL_MS", 20);
+
+    const _: () = assert!(
+        ADDRESS >= 0x70 && ADDRESS <= 0x77,
+        "the address of the TCA9548A must be within 0x70 - 0x77"
+    );
+    const _: () = assert!(CHANNELS != 0, "at least one channel must have a sensor");
+    const _: () = assert!(POLL_INTERVAL_MS > 0, "the poll interval must not be 0");
+}
+
+/// Settings of the IMU used for the tilt compensation.
+#[cfg(feature = "imu")]
+pub mod imu {
+    use crate::imu::Axis;
+
+    /// Axis of the IMU (`x`, `y` or `z`) along which the TOF sensor is pointing.
+    pub const AXIS: Axis = match env_or!("IMU_AXIS", "z").as_bytes() {
+        b"x" => Axis::X,
+        b"y" => Axis::Y,
+        b"z" => Axis::Z,
+        _ => panic!("invalid IMU axis"),
+    };
+}
+
+/// Settings of the servo gauge.
+#[cfg(feature = "servo")]
+pub mod servo {
+    /// The servo is at its minimum position at or below this distance.
+    pub const MIN_MM: u16 = env_u32_or!("SERVO_MIN_MM", 0) as u16;
+    /// The servo is at its maximum position at or beyond this distance.
+    pub const MAX_MM: u16 = env_u32_or!("SERVO_MAX_MM", 2000) as u16;
+    /// Pulse width for the minimum position. Swap the pulse widths to reverse the direction.
+    pub const MIN_PULSE_US: u32 = env_u32_or!("SERVO_MIN_PULSE_US", 1000);
+    /// Pulse width for the maximum position.
+    pub const MAX_PULSE_US: u32 = env_u32_or!("SERVO_MAX_PULSE_US", 2000);
+    /// Pulse width while the TOF sensor is lost, see [`crate::sensor_supervisor`].
+    pub const FAILSAFE_PULSE_US: u32 = env_u32_or!("SERVO_FAILSAFE_PULSE_US", MIN_PULSE_US);
+
+    const _: () = assert!(
+        MIN_MM < MAX_MM,
+        "the minimum distance must be below the maximum"
+    );
+    const _: () = assert!(
+        MIN_PULSE_US < crate::servo::PERIOD_US
+            && MAX_PULSE_US < crate::servo::PERIOD_US
+            && FAILSAFE_PULSE_US < crate::servo::PERIOD_US,
+        "the pulse widths must be below the period of 20 ms"
+    );
+}
+
+/// Settings of the [`crate::analog_output`].
+#[cfg(feature = "analog-output")]
+pub mod analog_output {
+    /// The output is at its minimum voltage at or below this distance.
+    pub const MIN_MM: u16 = env_u32_or!("ANALOG_OUTPUT_MIN_MM", 0) as u16;
+    /// The output is at its maximum voltage at or beyond this distance.
+    pub const MAX_MM: u16 = env_u32_or!("ANALOG_OUTPUT_MAX_MM", 2000) as u16;
+    /// Filtered voltage at the minimum distance. Swap the voltages to reverse the direction.
+    pub const MIN_MV: u32 = env_u32_or!("ANALOG_OUTPUT_MIN_MV", 0);
+    /// Filtered voltage at the maximum distance.
+    pub const MAX_MV: u32 = env_u32_or!("ANALOG_OUTPUT_MAX_MV", 3300);
+    /// Filtered voltage while the TOF sensor is lost (see [`crate::sensor_supervisor`]), 0 V by
+    /// default so that e.g. a PLC can tell it from a measured distance with a 0.1 - 3.3 V range.
+    pub const FAILSAFE_MV: u32 = env_u32_or!("ANALOG_OUTPUT_FAILSAFE_MV", 0);
+    /// Frequency of the PWM signal, the higher the less ripple remains after the filter but the
+    /// lower the resolution (84 MHz / frequency steps).
+    pub const FREQUENCY_HZ: u32 = env_u32_or!("ANALOG_OUTPUT_FREQUENCY_HZ", 20_000);
+
+    const _: () = assert!(
+        MIN_MM < MAX_MM,
+        "the minimum distance must be below the maximum"
+    );
+    // the defaults of the lowest & the failsafe voltage are 0, where clippy considers the check
+    // pointless
+    #[allow(clippy::absurd_extreme_comparisons)]
+    const _: () = assert!(
+        MIN_MV <= crate::analog_output::FULL_SCALE_MV
+            && MAX_MV <= crate::analog_output::FULL_SCALE_MV
+            && FAILSAFE_MV <= crate::analog_output::FULL_SCALE_MV,
+        "the voltages must be within 0 - 3300 mV"
+    );
+    const _: () = assert!(
+        FREQUENCY_HZ >= 1_000 && FREQUENCY_HZ <= 100_000,
+        "the frequency must be within 1 - 100 kHz"
+    );
+}
+
+/// Settings of the distance hold controller driving a motor.
+#[cfg(feature = "motor-pid")]
+pub mod motor {
+    use crate::pid::Gains;
+
+    /// Distance to keep at boot, can be changed with the `pid target` command.
+    pub const TARGET_MM: u16 = env_u32_or!("MOTOR_TARGET_MM", 300) as u16;
+    /// Gains at boot, can be changed with the `pid gains` command. The output of the controller is
+    /// in per mille of the full speed, the gains are set in thousandths (e.g. `MOTOR_KP_MILLI=1500`
+    /// for a proportional gain of 1.5 ‰/mm).
+    pub const GAINS: Gains = Gains {
+        kp: env_u32_or!("MOTOR_KP_MILLI", 2000) as f32 / 1000.0,
+        ki: env_u32_or!("MOTOR_KI_MILLI", 500) as f32 / 1000.0,
+        kd: env_u32_or!("MOTOR_KD_MILLI", 100) as f32 / 1000.0,
+    };
+    /// Whether the motor can run in both directions (`1`) or only towards the obstacle (`0`).
+    pub const REVERSIBLE: bool = env_bool_or!("MOTOR_REVERSIBLE", true);
+    /// The motor is stopped if no measurement has been received for this time.
+    pub const TIMEOUT_MS: u32 = env_u32_or!("MOTOR_TIMEOUT_MS", 500);
+}
+
+/// Settings of the stepper motor.
+#[cfg(feature = "stepper")]
+pub mod stepper {
+    /// Use a step/dir driver (`1`) instead of a ULN2003 (`0`).
+    pub const STEP_DIR: bool = env_bool_or!("STEPPER_STEP_DIR", false);
+    /// Length of the travel in steps (half steps for the ULN2003).
+    pub const STEPS: i32 = env_u32_or!("STEPPER_STEPS", 4096) as i32;
+    /// In the follow mode the motor is at the start of the travel at or below this distance.
+    pub const MIN_MM: u16 = env_u32_or!("STEPPER_MIN_MM", 0) as u16;
+    /// In the follow mode the motor is at the end of the travel at or beyond this distance.
+    pub const MAX_MM: u16 = env_u32_or!("STEPPER_MAX_MM", 2000) as u16;
+    /// Maximum speed in steps per second.
+    pub const MAX_SPEED: u32 = env_u32_or!("STEPPER_MAX_SPEED", 800);
+    /// Acceleration in steps per second squared.
+    pub const ACCELERATION: u32 = env_u32_or!("STEPPER_ACCELERATION", 1600);
+
+    const _: () = assert!(
+        MIN_MM < MAX_MM,
+        "the minimum distance must be below the maximum"
+    );
+    const _: () = assert!(
+        MAX_SPEED >= 50,
+        "the maximum speed must be at least the start speed of 50 steps/s"
+    );
+    const _: () = assert!(ACCELERATION > 0, "the acceleration must not be 0");
+}
+
+/// Settings of the parking assist buzzer.
+#[cfg(feature = "buzzer")]
+pub mod buzzer {
+    /// The buzzer sounds continuously up to this distance.
+    pub const NEAR_MM: u16 = env_u32_or!("BUZZER_NEAR_MM", 300) as u16;
+    /// The buzzer beeps fast up to this distance.
+    pub const MIDDLE_MM: u16 = env_u32_or!("BUZZER_MIDDLE_MM", 600) as u16;
+    /// The buzzer beeps slowly up to this distance and is silent beyond it.
+    pub const FAR_MM: u16 = env_u32_or!("BUZZER_FAR_MM", 1000) as u16;
+
+    const _: () = assert!(
+        NEAR_MM < MIDDLE_MM && MIDDLE_MM < FAR_MM,
+        "the zones of the buzzer must be increasing"
+    );
+}
+
+/// Settings of the alarm output.
+#[cfg(feature = "alarm-output")]
+pub mod alarm_output {
+    /// Lower limit of the distance window in which the alarm is asserted.
+    pub const MIN_MM: u16 = env_u32_or!("ALARM_MIN_MM", 0) as u16;
+    /// Upper limit of the distance window in which the alarm is asserted.
+    pub const MAX_MM: u16 = env_u32_or!("ALARM_MAX_MM", 300) as u16;
+    /// The distance must stay within the window for this time before the alarm is asserted.
+    pub const DWELL_MS: u32 = env_u32_or!("ALARM_DWELL_MS", 500);
+    /// The alarm is deasserted once the distance has been outside the window for this time.
+    pub const HOLD_MS: u32 = env_u32_or!("ALARM_HOLD_MS", 2000);
+    /// Use an open-drain output which pulls low when asserted instead of an active-high push-pull
+    /// output.
+    pub const OPEN_DRAIN: bool = env_bool_or!("ALARM_OPEN_DRAIN", false);
+    /// Keep the alarm asserted until it's cleared, see [`crate::alarm_output`].
+    pub const LATCHING: bool = env_bool_or!("ALARM_LATCHING", false);
+    /// Assert the alarm while the TOF sensor is lost (see [`crate::sensor_supervisor`]), so that a
+    /// failed sensor doesn't look like a free path.
+    pub const FAILSAFE_ASSERTED: bool = env_bool_or!("ALARM_FAILSAFE_ASSERTED", true);
+
+    // the default for the lower limit is 0, where clippy considers the check pointless
+    #[allow(clippy::absurd_extreme_comparisons)]
+    const _: () = assert!(MIN_MM <= MAX_MM, "the alarm window is empty");
+}
+
+/// Settings of the potentiometer which sets the alarm threshold.
+#[cfg(feature = "threshold-pot")]
+pub mod threshold_pot {
+    /// Threshold at the lower end of the potentiometer.
+    pub const MIN_MM: u16 = env_u32_or!("THRESHOLD_POT_MIN_MM", 50) as u16;
+    /// Threshold at the upper end of the potentiometer.
+    pub const MAX_MM: u16 = env_u32_or!("THRESHOLD_POT_MAX_MM", 2000) as u16;
+
+    const _: () = assert!(
+        MIN_MM < MAX_MM,
+        "the minimum threshold must be below the maximum"
+    );
+}
+
+/// Settings of the data logging to the SD card.
+#[cfg(feature = "sd-card")]
+pub mod sd_card {
+    /// A new file is started once the current one has reached this size.
+    pub const FILE_SIZE_KB: u32 = env_u32_or!("SD_FILE_SIZE_KB", 256);
+    /// Number of files which are kept, older ones are deleted. All files are kept if it's 0.
+    pub const MAX_FILES: u32 = env_u32_or!("SD_MAX_FILES", 0);
+    /// Interval at which the size of the file is updated on the card, the data written since then
+    /// is lost if the power fails.
+    pub const FLUSH_INTERVAL_S: u32 = env_u32_or!("SD_FLUSH_INTERVAL_S", 10);
+
+    const _: () = assert!(FILE_SIZE_KB > 0, "the files must not be empty");
+}
+
+/// Settings of the data logging to the external NOR flash.
+#[cfg(all(feature = "nor-flash", not(feature = "littlefs")))]
+pub mod flash_log {
+    /// Size of the log at the start of the flash, the whole flash is used if it's 0. Thereby the
+    /// number of measurements which are kept can be limited, e.g. to keep the rest of the flash
+    /// for other data.
+    pub const SIZE_KB: u32 = env_u32_or!("FLASH_LOG_SIZE_KB", 0);
+
+    const _: () = assert!(
+        SIZE_KB.is_multiple_of(4),
+        "the log must consist of whole 4 KiB sectors"
+    );
+    const _: () = assert!(
+        SIZE_KB != 4,
+        "the log needs at least two sectors as one is erased before it's written"
+    );
+}
+
+/// Settings of the littlefs file system on the external NOR flash.
+#[cfg(feature = "littlefs")]
+pub mod file_system {
+    /// Size of the file system at the start of the flash. It's fixed at build time, the default
+    /// fits onto the smallest supported flash (W25Q80, 1 MiB).
+    pub const SIZE_KB: u32 = env_u32_or!("FS_SIZE_KB", 1024);
+    /// A new log file is started once the current one has reached this size.
+    pub const LOG_FILE_KB: u32 = env_u32_or!("FS_LOG_FILE_KB", 32);
+
+    const _: () = assert!(
+        SIZE_KB.is_multiple_of(4),
+        "the file system must consist of whole 4 KiB sectors"
+    );
+    const _: () = assert!(
+        SIZE_KB >= 64,
+        "the file system needs at least 64 KiB to hold all files"
+    );
+    const _: () = assert!(
+        LOG_FILE_KB > 0 && LOG_FILE_KB * 4 <= SIZE_KB,
+        "the file system must hold at least 4 log files"
+    );
+}
+
+/// Settings of the LoRa radio.
+#[cfg(feature = "lora")]
+pub mod lora {
+    /// Carrier frequency, must match the region & the receiving gateway.
+    pub const FREQUENCY_HZ: u32 = env_u32_or!("LORA_FREQUENCY_HZ", 868_100_000);
+    /// Spreading factor (7 - 12). Higher values increase the range but reduce the data rate.
+    pub const SPREADING_FACTOR: u8 = env_u32_or!("LORA_SPREADING_FACTOR", 9) as u8;
+    /// Interval at which the latest measurement is reported.
+    pub const REPORT_INTERVAL_S: u32 = env_u32_or!("LORA_REPORT_INTERVAL_S", 60);
+    /// An alarm is sent immediately when a valid measurement is closer than this distance.
+    pub const ALARM_THRESHOLD_MM: u16 = env_u32_or!("LORA_ALARM_THRESHOLD_MM", 200) as u16;
+    /// The alarm is cleared again once the distance exceeds the threshold by this amount.
+    pub const ALARM_HYSTERESIS_MM: u16 = 50;
+
+    const _: () = assert!(
+        SPREADING_FACTOR >= 7 && SPREADING_FACTOR <= 12,
+        "the spreading factor must be between 7 and 12"
+    );
+}
+
+/// Settings of the synthetic measurements of the [`crate::sim`].
+#[cfg(feature = "sim")]
+pub mod sim {
+    use crate::sim::Waveform;
+
+    /// Shape of the distance: `constant`, `sine`, `triangle`, `square` or `sawtooth`.
+    pub const WAVEFORM: Waveform = Waveform::parse(env_or!("SIM_WAVEFORM", "sine"));
+    /// Period of the waveform.
+    pub const PERIOD_MS: u32 = env_u32_or!("SIM_PERIOD_MS", 20_000);
+    /// Distance at the bottom of the waveform.
+    pub const MIN_MM: u16 = env_u32_or!("SIM_MIN_MM", 200) as u16;
+    /// Distance at the top of the waveform.
+    pub const MAX_MM: u16 = env_u32_or!("SIM_MAX_MM", 2_000) as u16;
+    /// The distances vary randomly by up to this amount.
+    pub const NOISE_MM: u16 = env_u32_or!("SIM_NOISE_MM", 10) as u16;
+    /// Share of the measurements without a target.
+    pub const DROPOUT_PERCENT: u8 = env_u32_or!("SIM_DROPOUT_PERCENT", 5) as u8;
+
+    const _: () = assert!(PERIOD_MS >= 100, "the period must be at least 100 ms");
+    const _: () = assert!(MIN_MM <= MAX_MM, "the minimum must not exceed the maximum");
+    const _: () = assert!(
+        DROPOUT_PERCENT <= 100,
+        "the share of dropouts must be at most 100 %"
+    );
+}
+
+/// Settings of the [`crate::demo`].
+#[cfg(feature = "demo")]
+pub mod demo {
+    /// Duration of each step of the demo.
+    pub const STEP_S: u32 = env_u32_or!("DEMO_STEP_S", 30);
+    /// Time for which a narration is shown on the displays instead of the distance.
+    pub const NARRATION_MS: u32 = env_u32_or!("DEMO_NARRATION_MS", 3_000);
+    /// A hand closer than this makes a gesture.
+    pub const GESTURE_MM: u16 = env_u32_or!("DEMO_GESTURE_MM", 500) as u16;
+
+    const _: () = assert!(
+        NARRATION_MS < STEP_S * 1000,
+        "the narration must be shorter than a step"
+    );
+    const _: () = assert!(GESTURE_MM > 0, "the distance of the gestures must not be 0");
+}
+
+/// Settings of the [`crate::marker`] input.
+#[cfg(feature = "marker-input")]
+pub mod marker {
+    /// An edge within this time of the previous one is ignored as bouncing.
+    pub const DEBOUNCE_MS: u32 = env_u32_or!("MARKER_DEBOUNCE_MS", 20);
+}
+
+/// Settings of the [`crate::i2c_trace`].
+#[cfg(feature = "i2c-trace")]
+pub mod i2c_trace {
+    /// Maximum number of traced transactions per second.
+    pub const MAX_PER_S: u32 = env_u32_or!("I2C_TRACE_MAX_PER_S", 100);
+
+    const _: () = assert!(
+        MAX_PER_S > 0,
+        "at least one transaction must be traced per second"
+    );
+}
+
+/// Settings of the [`crate::fault_injection`].
+#[cfg(feature = "fault-injection")]
+pub mod fault_injection {
+    /// Seed of the pseudo-random faults, the same seed results in the same faults.
+    pub const SEED: u32 = env_u32_or!("FAULT_SEED", 1);
+    /// Share of the transfers to the TOF sensor which aren't acknowledged, in ‰.
+    pub const NAK_PERMILLE: u16 = env_u32_or!("FAULT_NAK_PERMILLE", 5) as u16;
+    /// Share of the reads from the TOF sensor in which a bit is flipped, in ‰.
+    pub const CORRUPT_PERMILLE: u16 = env_u32_or!("FAULT_CORRUPT_PERMILLE", 5) as u16;
+    /// Share of the data ready interrupts which are handled late, in ‰.
+    pub const DELAY_PERMILLE: u16 = env_u32_or!("FAULT_DELAY_PERMILLE", 20) as u16;
+    /// Longest delay of an interrupt.
+    pub const MAX_DELAY_MS: u32 = env_u32_or!("FAULT_MAX_DELAY_MS", 500);
+
+    // the xorshift generator is stuck at 0
+    const _: () = assert!(SEED != 0, "the seed must not be 0");
+    const _: () = assert!(
+        NAK_PERMILLE <= 1000 && CORRUPT_PERMILLE <= 1000 && DELAY_PERMILLE <= 1000,
+        "the shares of the faults must be at most 1000 permille"
+    );
+    const _: () = assert!(MAX_DELAY_MS > 0, "the longest delay must not be 0");
+}
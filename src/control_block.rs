@@ -0,0 +1,127 @@
+//! A control block in RAM through which a debugger triggers actions while no UART is connected,
+//! e.g. on a deployed unit: it's exported as the symbol `CONTROL_BLOCK` (its address is listed by
+//! `nm`) & polled every [`POLL_INTERVAL_MS`] by a low-priority task.
+//!
+//! The debugger writes the argument & the command, then [`MAGIC`] to `magic`. The firmware clears
+//! `magic` once it has taken the request & writes the result of the command to `status`:
+//!
+//! | Offset | Field     | Written by                                  |
+//! |--------|-----------|---------------------------------------------|
+//! | 0      | `magic`   | the debugger ([`MAGIC`]), cleared when taken |
+//! | 4      | `command` | the debugger, see [`Request`]               |
+//! | 8      | `arg`     | the debugger                                |
+//! | 12     | `status`  | the firmware, see [`Status`]                |
+
+use crate::log_backend::Level;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Marks a pending request, `CB` followed by the version of the layout.
+pub const MAGIC: u32 = u32::from_le_bytes(*b"CB01");
+/// Interval at which the control block is polled.
+pub const POLL_INTERVAL_MS: u32 = 100;
+
+#[repr(C)]
+pub struct ControlBlock {
+    magic: AtomicU32,
+    command: AtomicU32,
+    arg: AtomicU32,
+    status: AtomicU32,
+}
+
+/// The control block, it's only accessed with atomics thus the debugger can write it at any time.
+#[allow(unsafe_code)]
+// SAFETY: no other symbol of the firmware has this name
+#[no_mangle]
+pub static CONTROL_BLOCK: ControlBlock = ControlBlock::new();
+
+/// The actions which can be requested, the number is the value of `command`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Request {
+    /// `1`: log the statistics of the measurements.
+    DumpStats,
+    /// `2`: log only the messages of the level `arg` & above (0: info, 1: warn, 2: error), see
+    /// [`crate::log_backend::set_min_level`].
+    SetLogLevel(Level),
+    /// `3`: set up the TOF sensor again.
+    Reinit,
+}
+
+/// The result of the latest request, the number is the value of `status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Status {
+    /// `0`: no request has been taken since boot.
+    Idle = 0,
+    /// `1`: the latest request has been executed.
+    Done = 1,
+    /// `2`: the latest request failed.
+    Failed = 2,
+    /// `3`: the command or its argument is unknown.
+    Invalid = 3,
+}
+
+impl ControlBlock {
+    const fn new() -> Self {
+        Self {
+            magic: AtomicU32::new(0),
+            command: AtomicU32::new(0),
+            arg: AtomicU32::new(0),
+            status: AtomicU32::new(Status::Idle as u32),
+        }
+    }
+
+    /// Take the pending request, if any. An invalid one is answered with [`Status::Invalid`].
+    pub fn take(&self) -> Option<Request> {
+        if self.magic.load(Ordering::Acquire) != MAGIC {
+            return None;
+        }
+        let request = decode(
+            self.command.load(Ordering::Relaxed),
+            self.arg.load(Ordering::Relaxed),
+        );
+        self.magic.store(0, Ordering::Release);
+        if request.is_none() {
+            self.finish(Status::Invalid);
+        }
+        request
+    }
+
+    /// Report the result of the request which has been taken.
+    pub fn finish(&self, status: Status) {
+        self.status.store(status as u32, Ordering::Release);
+    }
+}
+
+fn decode(command: u32, arg: u32) -> Option<Request> {
+    match command {
+        1 => Some(Request::DumpStats),
+        2 => Level::from_number(arg).map(Request::SetLogLevel),
+        3 => Some(Request::Reinit),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_the_requests() {
+        let block = ControlBlock::new();
+        block.command.store(2, Ordering::Relaxed);
+        block.arg.store(1, Ordering::Relaxed);
+        assert_eq!(block.take(), None);
+        block.magic.store(MAGIC, Ordering::Relaxed);
+        assert_eq!(block.take(), Some(Request::SetLogLevel(Level::Warn)));
+        assert_eq!(block.take(), None);
+        block.finish(Status::Done);
+        assert_eq!(block.status.load(Ordering::Relaxed), Status::Done as u32);
+
+        block.arg.store(3, Ordering::Relaxed);
+        block.magic.store(MAGIC, Ordering::Relaxed);
+        assert_eq!(block.take(), None);
+        assert_eq!(block.status.load(Ordering::Relaxed), Status::Invalid as u32);
+        assert_eq!(decode(1, 0), Some(Request::DumpStats));
+        assert_eq!(decode(3, 0), Some(Request::Reinit));
+        assert_eq!(decode(0, 0), None);
+    }
+}
@@ -0,0 +1,77 @@
+//! All local controls (e.g. knobs & buttons) which are used to operate the device without a host.
+//!
+//! The controls are combined into a single struct so that the tasks don't need to know which
+//! controls have been enabled.
+
+#[cfg(feature = "menu")]
+use crate::display::MenuView;
+#[cfg(feature = "menu")]
+use crate::menu::Menu;
+#[cfg(feature = "menu")]
+use crate::rotary::RotaryEncoder;
+#[cfg(feature = "threshold-pot")]
+use crate::threshold_pot::ThresholdPot;
+
+/// An action requested using the controls.
+///
+/// This doesn't implement [`defmt::Format`] as [`vl53l1x_uld::DistanceMode`] doesn't, use
+/// [`defmt::Debug2Format`] to log it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Set the threshold of the alarms.
+    #[cfg(any(
+        feature = "threshold-pot",
+        all(feature = "menu", any(feature = "alarm-output", feature = "lora"))
+    ))]
+    AlarmThreshold(u16),
+    /// Switch the distance mode of the TOF sensor.
+    #[cfg(feature = "menu")]
+    DistanceMode(vl53l1x_uld::DistanceMode),
+    /// Set the measurement rate of the TOF sensor in Hz.
+    #[cfg(feature = "menu")]
+    Rate(u8),
+}
+
+pub struct Controls {
+    #[cfg(feature = "threshold-pot")]
+    pub threshold_pot: ThresholdPot,
+    #[cfg(feature = "menu")]
+    pub rotary_encoder: RotaryEncoder,
+    #[cfg(feature = "menu")]
+    pub menu: Menu,
+}
+
+impl Controls {
+    /// Whether any control is enabled, otherwise they don't need to be polled.
+    pub const ENABLED: bool = cfg!(any(feature = "threshold-pot", feature = "menu"));
+    /// Interval at which [`Self::poll`] is called, the rotary encoder needs to be sampled fast
+    /// enough to not miss any transitions.
+    pub const POLL_INTERVAL_MS: u32 = if cfg!(feature = "menu") { 1 } else { 20 };
+
+    /// Read all controls, `on_event` is called for each resulting event.
+    pub fn poll(&mut self, now_ms: u32, mut on_event: impl FnMut(Event)) {
+        #[cfg(feature = "threshold-pot")]
+        if let Some(threshold_mm) = self.threshold_pot.poll() {
+            on_event(Event::AlarmThreshold(threshold_mm));
+        }
+        #[cfg(feature = "menu")]
+        {
+            if let Some(input) = self.rotary_encoder.poll(now_ms) {
+                if let Some(event) = self.menu.handle(input, now_ms) {
+                    on_event(event);
+                }
+            }
+            self.menu.tick(now_ms);
+        }
+        #[cfg(not(any(feature = "threshold-pot", feature = "menu")))]
+        let _ = &mut on_event;
+        #[cfg(not(feature = "menu"))]
+        let _ = now_ms;
+    }
+
+    /// What should be shown on the displays instead of the distance, `None` if the menu is closed.
+    #[cfg(feature = "menu")]
+    pub fn menu_view(&self) -> Option<MenuView> {
+        self.menu.view()
+    }
+}
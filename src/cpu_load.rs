@@ -0,0 +1,57 @@
+//! Measurement of the CPU load, i.e. the share of the time in which the microcontroller isn't
+//! sleeping in the idle loop.
+//!
+//! The idle loop waits for the next interrupt with `WFI` with the interrupts masked, so that the
+//! time until it wakes up is exactly the time asleep (the interrupt is only handled once they're
+//! unmasked again). The load is calculated anew for each window of at least [`WINDOW_US`] &
+//! reported with the `metrics` command.
+
+/// The minimal length of the window over which the load is calculated.
+pub const WINDOW_US: u32 = 1_000_000;
+
+pub struct CpuLoad {
+    /// Time since boot of the start of the current window, `None` before the first sleep.
+    window_start_us: Option<u32>,
+    /// Time asleep in the current window.
+    sleep_us: u32,
+}
+
+impl CpuLoad {
+    pub const fn new() -> Self {
+        Self {
+            window_start_us: None,
+            sleep_us: 0,
+        }
+    }
+
+    /// Record a sleep from `start_us` to `end_us`, returns the load (in %) of the window once it's
+    /// over.
+    pub fn on_sleep(&mut self, start_us: u32, end_us: u32) -> Option<u8> {
+        let window_start_us = *self.window_start_us.get_or_insert(start_us);
+        self.sleep_us = self.sleep_us.wrapping_add(end_us.wrapping_sub(start_us));
+        let elapsed_us = end_us.wrapping_sub(window_start_us);
+        if elapsed_us < WINDOW_US {
+            return None;
+        }
+        let busy_us = elapsed_us.saturating_sub(self.sleep_us);
+        self.window_start_us = Some(end_us);
+        self.sleep_us = 0;
+        Some((busy_us as u64 * 100 / elapsed_us as u64) as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_of_a_window() {
+        let mut load = CpuLoad::new();
+        assert_eq!(load.on_sleep(0, 300_000), None);
+        assert_eq!(load.on_sleep(500_000, 800_000), None);
+        assert_eq!(load.on_sleep(900_000, 1_000_000), Some(30));
+        // the next window starts at the end of the previous one
+        assert_eq!(load.on_sleep(1_000_000, 1_600_000), None);
+        assert_eq!(load.on_sleep(1_600_000, 2_000_000), Some(0));
+    }
+}
@@ -0,0 +1,481 @@
+//! All data loggers which store the measurements on the device, e.g. for standalone deployments.
+//!
+//! The loggers are combined into a single struct so that the task doesn't need to know which of
+//! them have been enabled. They are run by a low-priority task so that the slow storage never
+//! delays the handling of the measurements, the other tasks hand their requests over with
+//! [`LogRequests`].
+
+#[cfg(feature = "littlefs")]
+use crate::file_system::{FilePath, FileSystem, FileWrite};
+#[cfg(all(feature = "nor-flash", not(feature = "littlefs")))]
+use crate::flash_log::FlashLog;
+#[cfg(feature = "nor-flash")]
+use crate::log_compression::{self, Compressor};
+#[cfg(feature = "nor-flash")]
+use crate::log_record::{self, Dump};
+#[cfg(feature = "sd-card")]
+use crate::sd_card::SdCardLog;
+use crate::telemetry::Measurement;
+#[cfg(feature = "xmodem")]
+use crate::xmodem::{self, Sender};
+#[cfg(feature = "nor-flash")]
+use core::fmt::Write;
+
+/// Number of measurements which can wait for the loggers.
+const QUEUE_LEN: usize = 16;
+/// Number of measurements after which the loggers are woken up when they're batched, see
+/// [`LogRequests::is_batch_full`].
+pub const BATCH_LEN: usize = QUEUE_LEN / 2;
+/// Number of files which can wait to be written.
+#[cfg(feature = "littlefs")]
+const FILE_QUEUE_LEN: usize = 2;
+/// Interval at which the output of a command is continued while the transmit buffers are full.
+#[cfg(feature = "nor-flash")]
+pub const OUTPUT_INTERVAL_MS: u32 = 10;
+
+/// A data logger failed to write its data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct LogError;
+
+/// What happens once the data loggers have been stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Shutdown {
+    /// The power can be removed, see [`crate::command::Command::Shutdown`].
+    PowerOff,
+    /// The board is reset, see [`crate::command::Command::Reset`].
+    Reset,
+    /// The supply voltage is dropping, the loggers stay stopped until the next reset, see
+    /// [`crate::brown_out`].
+    Undervoltage,
+    /// The battery is critical, the board halts like with [`Shutdown::PowerOff`] (also if the
+    /// loggers have failed), see [`crate::battery`].
+    #[cfg(feature = "battery")]
+    Battery,
+}
+
+/// Work for the data loggers, handed over by the other tasks.
+pub struct LogRequests {
+    /// Measurements which still need to be logged.
+    measurements: heapless::Deque<Measurement, QUEUE_LEN>,
+    /// The command (`shutdown` or `reset`) or the undervoltage which waits for the loggers to be
+    /// stopped.
+    pub shutdown: Option<Shutdown>,
+    /// The command which waits to be started.
+    #[cfg(feature = "nor-flash")]
+    pub command: Option<LogCommand>,
+    /// Files which still need to be written.
+    #[cfg(feature = "littlefs")]
+    files: heapless::Deque<FileWrite, FILE_QUEUE_LEN>,
+}
+
+impl LogRequests {
+    pub const fn new() -> Self {
+        Self {
+            measurements: heapless::Deque::new(),
+            shutdown: None,
+            #[cfg(feature = "nor-flash")]
+            command: None,
+            #[cfg(feature = "littlefs")]
+            files: heapless::Deque::new(),
+        }
+    }
+
+    /// Queue a measurement, it's dropped if the loggers are lagging behind too much.
+    pub fn push(&mut self, measurement: Measurement) {
+        if self.measurements.push_back(measurement).is_err() {
+            defmt::warn!("data log: dropping measurement {}", measurement.seq);
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Measurement> {
+        self.measurements.pop_front()
+    }
+
+    /// Whether [`BATCH_LEN`] measurements are waiting, at fast rates the loggers are only woken up
+    /// for a whole batch instead of for each measurement.
+    pub fn is_batch_full(&self) -> bool {
+        self.measurements.len() >= BATCH_LEN
+    }
+
+    /// Queue a file to be written, it's dropped if too many are waiting.
+    #[cfg(feature = "littlefs")]
+    pub fn write_file(&mut self, file: FileWrite) {
+        if let Err(file) = self.files.push_back(file) {
+            defmt::warn!("data log: dropping file {=str}", file.path.as_str());
+        }
+    }
+
+    #[cfg(feature = "littlefs")]
+    pub fn pop_file(&mut self) -> Option<FileWrite> {
+        self.files.pop_front()
+    }
+}
+
+/// A command which is run by the data loggers, its output is sent line by line.
+#[cfg(feature = "nor-flash")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum LogCommand {
+    /// Send all stored measurements.
+    Dump,
+    /// Send all stored measurements compressed, see [`crate::log_compression`].
+    CompressedDump,
+    /// List the entries of a directory.
+    #[cfg(feature = "littlefs")]
+    List(FilePath),
+    /// Send the content of a file.
+    #[cfg(feature = "littlefs")]
+    Show(FilePath),
+    /// Remove a file or an empty directory.
+    #[cfg(feature = "littlefs")]
+    Remove(FilePath),
+    /// Download the output of [`Self::Dump`] with XMODEM or YMODEM.
+    #[cfg(feature = "xmodem")]
+    Transfer(xmodem::Request),
+}
+
+/// A line of the output of a [`LogCommand`].
+#[cfg(feature = "nor-flash")]
+pub type OutputLine = heapless::String<{ log_record::MAX_LINE_LEN }>;
+
+/// A running [`LogCommand::Transfer`].
+#[cfg(feature = "xmodem")]
+pub struct Transfer {
+    sender: Sender,
+    request: xmodem::Request,
+    source: TransferSource,
+}
+
+/// The content of the transferred file: the measurement lines of the dump.
+#[cfg(feature = "xmodem")]
+struct TransferSource {
+    /// The line which is being sent & the number of its bytes which have been sent.
+    line: OutputLine,
+    sent: usize,
+    /// Number of bytes which still have to be skipped to resume at the requested offset.
+    skip: u32,
+    /// The final response of the dump, once it's done.
+    response: Option<OutputLine>,
+}
+
+#[cfg(feature = "xmodem")]
+impl TransferSource {
+    /// Fill the buffer with the next bytes of the file, returns their number (`0` at its end).
+    fn fill(&mut self, buffer: &mut [u8], mut next_line: impl FnMut() -> OutputLine) -> usize {
+        let mut len = 0;
+        while len < buffer.len() {
+            if self.sent == self.line.len() {
+                if self.response.is_some() {
+                    break;
+                }
+                let line = next_line();
+                if !line.starts_with("L,") {
+                    self.response = Some(line);
+                    break;
+                }
+                let skip = line.len().min(self.skip as usize);
+                self.skip -= skip as u32;
+                self.line = line;
+                self.sent = skip;
+                continue;
+            }
+            let bytes = &self.line.as_bytes()[self.sent..];
+            let count = bytes.len().min(buffer.len() - len);
+            buffer[len..len + count].copy_from_slice(&bytes[..count]);
+            len += count;
+            self.sent += count;
+        }
+        len
+    }
+}
+
+pub struct DataLog {
+    #[cfg(feature = "sd-card")]
+    pub sd_card: SdCardLog,
+    /// `None` if no flash has been found.
+    #[cfg(all(feature = "nor-flash", not(feature = "littlefs")))]
+    pub flash_log: Option<FlashLog>,
+    /// `None` if no flash has been found or if the file system can't be mounted.
+    #[cfg(feature = "littlefs")]
+    pub file_system: Option<FileSystem>,
+    /// The running transfer, `None` at the start.
+    #[cfg(feature = "xmodem")]
+    pub transfer: Option<Transfer>,
+    /// The compression of the running [`LogCommand::CompressedDump`], `None` at the start.
+    #[cfg(feature = "nor-flash")]
+    pub compressor: Option<Compressor>,
+}
+
+impl DataLog {
+    /// Whether any data logger has been enabled, otherwise the task doesn't need to run.
+    pub const ENABLED: bool = cfg!(any(feature = "sd-card", feature = "nor-flash"));
+
+    pub fn log(&mut self, measurement: &Measurement) {
+        #[cfg(feature = "sd-card")]
+        self.sd_card.log(measurement);
+        #[cfg(all(feature = "nor-flash", not(feature = "littlefs")))]
+        if let Some(flash_log) = &mut self.flash_log {
+            flash_log.log(measurement);
+        }
+        #[cfg(feature = "littlefs")]
+        if let Some(file_system) = &mut self.file_system {
+            file_system.log(measurement);
+        }
+        #[cfg(not(any(feature = "sd-card", feature = "nor-flash")))]
+        let _ = measurement;
+    }
+
+    /// Handle the timing of the loggers, `utc_ms` is the current calendar time (if known).
+    pub fn tick(&mut self, now_ms: u32, utc_ms: Option<u64>) {
+        #[cfg(feature = "sd-card")]
+        self.sd_card.tick(now_ms, utc_ms);
+        #[cfg(all(feature = "nor-flash", not(feature = "littlefs")))]
+        if let Some(flash_log) = &mut self.flash_log {
+            flash_log.tick();
+        }
+        #[cfg(feature = "littlefs")]
+        if let Some(file_system) = &mut self.file_system {
+            file_system.tick(now_ms);
+        }
+        #[cfg(not(feature = "sd-card"))]
+        let _ = (now_ms, utc_ms);
+    }
+
+    /// Write all data & stop the loggers, e.g. before the power is removed.
+    pub fn shutdown(&mut self) -> Result<(), LogError> {
+        #[cfg(feature = "sd-card")]
+        self.sd_card.shutdown().map_err(|_| LogError)?;
+        #[cfg(all(feature = "nor-flash", not(feature = "littlefs")))]
+        if let Some(flash_log) = &mut self.flash_log {
+            flash_log.shutdown().map_err(|_| LogError)?;
+        }
+        #[cfg(feature = "littlefs")]
+        if let Some(file_system) = &mut self.file_system {
+            file_system.shutdown().map_err(|_| LogError)?;
+        }
+        Ok(())
+    }
+
+    /// Write a file requested by another task, see [`LogRequests::write_file`].
+    #[cfg(feature = "littlefs")]
+    pub fn write_file(&mut self, file: &FileWrite) {
+        if let Some(file_system) = &mut self.file_system {
+            file_system.write_file(file);
+        }
+    }
+
+    /// Start running the command, its output is fetched with [`Self::next_line`] (or sent by
+    /// [`Self::poll_transfer`] for a transfer). Returns `false` if there's no storage to run it on.
+    #[cfg(feature = "nor-flash")]
+    pub fn start(&mut self, command: LogCommand) -> bool {
+        let started = self.start_command(command);
+        if started && command == LogCommand::CompressedDump {
+            self.compressor = Some(Compressor::new());
+        }
+        #[cfg(feature = "xmodem")]
+        if let (true, LogCommand::Transfer(request)) = (started, command) {
+            self.transfer = Some(Transfer {
+                sender: Sender::new(request.protocol),
+                request,
+                source: TransferSource {
+                    line: OutputLine::new(),
+                    sent: 0,
+                    skip: request.offset,
+                    response: None,
+                },
+            });
+        }
+        started
+    }
+
+    #[cfg(all(feature = "nor-flash", not(feature = "littlefs")))]
+    fn start_command(&mut self, command: LogCommand) -> bool {
+        let Some(flash_log) = &mut self.flash_log else {
+            return false;
+        };
+        match command {
+            LogCommand::Dump | LogCommand::CompressedDump => flash_log.start_dump(),
+            // the transferred file is the output of the dump
+            #[cfg(feature = "xmodem")]
+            LogCommand::Transfer(_) => flash_log.start_dump(),
+        }
+        true
+    }
+
+    #[cfg(feature = "littlefs")]
+    fn start_command(&mut self, command: LogCommand) -> bool {
+        let Some(file_system) = &mut self.file_system else {
+            return false;
+        };
+        file_system.start(command);
+        true
+    }
+
+    #[cfg(all(feature = "nor-flash", not(feature = "littlefs")))]
+    pub fn is_running(&self) -> bool {
+        // the compressor may still hold the end of a dump which has caught up with the log
+        self.compressor.is_some()
+            || self
+                .flash_log
+                .as_ref()
+                .is_some_and(|flash_log| flash_log.is_dumping())
+    }
+
+    #[cfg(feature = "littlefs")]
+    pub fn is_running(&self) -> bool {
+        self.compressor.is_some()
+            || self
+                .file_system
+                .as_ref()
+                .is_some_and(|file_system| file_system.is_running())
+    }
+
+    /// The next line of the output of the running command, the last one is the response (`OK ...`
+    /// or `ERR ...`).
+    #[cfg(feature = "nor-flash")]
+    pub fn next_line(&mut self) -> OutputLine {
+        let Some(mut compressor) = self.compressor.take() else {
+            return self.next_output_line();
+        };
+        let line = self.next_compressed_line(&mut compressor);
+        if line.starts_with("Z,") {
+            self.compressor = Some(compressor);
+        }
+        line
+    }
+
+    #[cfg(all(feature = "nor-flash", not(feature = "littlefs")))]
+    fn next_output_line(&mut self) -> OutputLine {
+        let mut line = OutputLine::new();
+        match self.dump_next() {
+            Some(Ok(Dump::Record(record))) => return record.line(),
+            Some(Ok(Dump::Done { records })) => write!(line, "OK records={}\r\n", records),
+            Some(Err(LogError)) => write!(line, "ERR storage failed\r\n"),
+            None => write!(line, "ERR storage not available\r\n"),
+        }
+        .ok();
+        line
+    }
+
+    #[cfg(feature = "littlefs")]
+    fn next_output_line(&mut self) -> OutputLine {
+        self.file_system
+            .as_mut()
+            .map_or_else(OutputLine::new, |file_system| file_system.next_line())
+    }
+
+    /// The next line of a [`LogCommand::CompressedDump`], the records are taken from the dump until
+    /// a chunk is ready.
+    #[cfg(feature = "nor-flash")]
+    fn next_compressed_line(&mut self, compressor: &mut Compressor) -> OutputLine {
+        let mut line = OutputLine::new();
+        loop {
+            if let Some(chunk) = compressor.next_chunk() {
+                return log_compression::chunk_line(&chunk);
+            }
+            if compressor.is_done() {
+                write!(
+                    line,
+                    "OK records={} bytes={}\r\n",
+                    compressor.records(),
+                    compressor.bytes()
+                )
+                .ok();
+                return line;
+            }
+            match self.dump_next() {
+                Some(Ok(Dump::Record(record))) => compressor.push(&record),
+                Some(Ok(Dump::Done { .. })) => compressor.finish(),
+                Some(Err(LogError)) => {
+                    write!(line, "ERR storage failed\r\n").ok();
+                    return line;
+                }
+                None => {
+                    write!(line, "ERR storage not available\r\n").ok();
+                    return line;
+                }
+            }
+        }
+    }
+
+    /// The next step of the running dump, `None` if there's no storage.
+    #[cfg(all(feature = "nor-flash", not(feature = "littlefs")))]
+    fn dump_next(&mut self) -> Option<Result<Dump, LogError>> {
+        let result = self.flash_log.as_mut()?.dump_next();
+        Some(result.map_err(|e| {
+            defmt::error!("flash log: dump failed: {}", e);
+            LogError
+        }))
+    }
+
+    /// The next step of the running dump, `None` if there's no storage.
+    #[cfg(feature = "littlefs")]
+    fn dump_next(&mut self) -> Option<Result<Dump, LogError>> {
+        let result = self.file_system.as_mut()?.dump_next();
+        Some(result.map_err(|e| {
+            defmt::error!("file system: dump failed: {}", e.code());
+            LogError
+        }))
+    }
+
+    /// Stop the running command, e.g. if the transfer of its output has been cancelled.
+    #[cfg(all(feature = "xmodem", not(feature = "littlefs")))]
+    fn stop(&mut self) {
+        if let Some(flash_log) = &mut self.flash_log {
+            flash_log.stop_dump();
+        }
+    }
+
+    /// Stop the running command, e.g. if the transfer of its output has been cancelled.
+    #[cfg(all(feature = "xmodem", feature = "littlefs"))]
+    fn stop(&mut self) {
+        if let Some(file_system) = &mut self.file_system {
+            file_system.stop();
+        }
+    }
+
+    #[cfg(feature = "xmodem")]
+    pub fn is_transferring(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    /// Continue the running transfer with the bytes received from the host, `write` queues the
+    /// bytes for sending & returns how many of them fit. Returns the response once it's done.
+    #[cfg(feature = "xmodem")]
+    pub fn poll_transfer(
+        &mut self,
+        now_ms: u32,
+        received: &[u8],
+        mut write: impl FnMut(&[u8]) -> usize,
+    ) -> Option<OutputLine> {
+        let Transfer {
+            mut sender,
+            request,
+            mut source,
+        } = self.transfer.take()?;
+        sender.poll(now_ms, received, &mut |buffer| {
+            source.fill(buffer, || self.next_line())
+        });
+        let len = write(sender.pending());
+        sender.advance(len);
+
+        let Some(result) = sender.result() else {
+            self.transfer = Some(Transfer {
+                sender,
+                request,
+                source,
+            });
+            return None;
+        };
+        if self.is_running() {
+            self.stop();
+        }
+        let mut line = OutputLine::new();
+        match (result, source.response) {
+            (Ok(()), Some(response)) if response.starts_with("ERR") => return Some(response),
+            (Ok(()), _) => write!(line, "OK bytes={}\r\n", request.offset + sender.bytes()),
+            (Err(e), _) => write!(line, "ERR {}\r\n", e.as_str()),
+        }
+        .ok();
+        Some(line)
+    }
+}
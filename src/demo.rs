@@ -0,0 +1,246 @@
+//! The self-running demo of the `demo` feature, so that the board can run unattended at an
+//! exhibition: it cycles through the [`Step`]s, each one for [`config::STEP_S`], which switch the
+//! [`AppMode`] & exercise the telemetry, the presence & zone events, the gestures, the outputs &
+//! the displays.
+//!
+//! Each step is narrated with defmt & on the displays, which show the narration instead of the
+//! distance for [`config::NARRATION_MS`] (as do the detected gestures). The demo neither stores
+//! the mode nor any other setting, the board starts with the first step after a reset. Without a
+//! sensor it's best combined with the `sim` feature.
+
+use crate::app_mode::AppMode;
+use crate::config::demo as config;
+use crate::display::{MenuValue, MenuView};
+use crate::telemetry::Measurement;
+use vl53l1x_uld::RangeStatus;
+
+/// The distance has to change by this much for a push or a pull.
+pub const PUSH_MM: u16 = 100;
+/// A hand which is only present for less than this makes a swipe.
+pub const SWIPE_MS: u32 = 600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Step {
+    /// Every measurement is published.
+    Streaming,
+    /// Only the appearance & the disappearance of a target are published.
+    Presence,
+    /// The zone events of the geofence & the periodic rollups.
+    Zones,
+    /// The gestures of a hand in front of the sensor, see [`GestureDetector`].
+    Gestures,
+    /// The displays & the local outputs follow the distance, nothing is published.
+    Display,
+}
+
+impl Step {
+    pub const COUNT: u8 = 5;
+
+    pub fn next(&self) -> Self {
+        match self {
+            Step::Streaming => Step::Presence,
+            Step::Presence => Step::Zones,
+            Step::Zones => Step::Gestures,
+            Step::Gestures => Step::Display,
+            Step::Display => Step::Streaming,
+        }
+    }
+
+    /// Number of the step (starting at 1).
+    pub fn number(&self) -> u8 {
+        *self as u8 + 1
+    }
+
+    /// The application mode during the step.
+    pub fn app_mode(&self) -> AppMode {
+        match self {
+            Step::Streaming => AppMode::Streaming,
+            Step::Presence => AppMode::Presence,
+            Step::Zones => AppMode::Rollup,
+            Step::Gestures | Step::Display => AppMode::ParkingAssist,
+        }
+    }
+
+    /// The narration which is shown on the displays, short enough for the LCD.
+    pub fn title(&self) -> &'static str {
+        match self {
+            Step::Streaming => "streaming",
+            Step::Presence => "presence",
+            Step::Zones => "zones",
+            Step::Gestures => "gestures",
+            Step::Display => "display",
+        }
+    }
+
+    /// The narration which is logged.
+    pub fn narration(&self) -> &'static str {
+        match self {
+            Step::Streaming => "every measurement is sent to the host",
+            Step::Presence => "step in front of the sensor & away again",
+            Step::Zones => "walk through the zones of the geofence",
+            Step::Gestures => "swipe a hand over the sensor or push & pull it",
+            Step::Display => "the displays & the outputs follow the distance",
+        }
+    }
+}
+
+/// A gesture of a hand within [`config::GESTURE_MM`] of the sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Gesture {
+    /// The hand has passed by within [`SWIPE_MS`].
+    Swipe,
+    /// The hand has approached by [`PUSH_MM`].
+    Push,
+    /// The hand has moved away by [`PUSH_MM`].
+    Pull,
+}
+
+impl Gesture {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Gesture::Swipe => "swipe",
+            Gesture::Push => "push",
+            Gesture::Pull => "pull",
+        }
+    }
+}
+
+/// Detects the [`Gesture`]s in the measurements.
+pub struct GestureDetector {
+    /// The time & the distance at which the hand has appeared or last made a gesture.
+    start: Option<(u32, u16)>,
+    /// Whether the hand has made a push or a pull, so that it's no swipe.
+    moved: bool,
+}
+
+impl GestureDetector {
+    pub const fn new() -> Self {
+        Self {
+            start: None,
+            moved: false,
+        }
+    }
+
+    pub fn update(&mut self, measurement: &Measurement) -> Option<Gesture> {
+        let present = measurement.status == RangeStatus::Valid
+            && measurement.distance_mm < config::GESTURE_MM;
+        let (now_ms, distance_mm) = (measurement.timestamp_ms, measurement.distance_mm);
+        let Some((since_ms, start_mm)) = self.start else {
+            if present {
+                self.start = Some((now_ms, distance_mm));
+                self.moved = false;
+            }
+            return None;
+        };
+        if !present {
+            self.start = None;
+            let swipe = !self.moved && now_ms.wrapping_sub(since_ms) < SWIPE_MS;
+            return swipe.then_some(Gesture::Swipe);
+        }
+        let gesture = if distance_mm + PUSH_MM <= start_mm {
+            Gesture::Push
+        } else if distance_mm >= start_mm + PUSH_MM {
+            Gesture::Pull
+        } else {
+            return None;
+        };
+        self.start = Some((now_ms, distance_mm));
+        self.moved = true;
+        Some(gesture)
+    }
+}
+
+pub struct Demo {
+    step: Step,
+    /// Whether the first step has been started.
+    started: bool,
+    gestures: GestureDetector,
+}
+
+impl Demo {
+    pub const fn new() -> Self {
+        Self {
+            step: Step::Streaming,
+            started: false,
+            gestures: GestureDetector::new(),
+        }
+    }
+
+    /// Start the next step (the first one at boot) & return it.
+    pub fn advance(&mut self) -> Step {
+        if self.started {
+            self.step = self.step.next();
+        }
+        self.started = true;
+        self.gestures = GestureDetector::new();
+        self.step
+    }
+
+    /// The gesture made with the measurement, only during [`Step::Gestures`].
+    pub fn on_measurement(&mut self, measurement: &Measurement) -> Option<Gesture> {
+        if self.step != Step::Gestures {
+            return None;
+        }
+        self.gestures.update(measurement)
+    }
+}
+
+/// The narration of the step on the displays, e.g. `demo 2/5` & `presence`.
+pub fn step_view(step: Step) -> MenuView {
+    const LABELS: [&str; Step::COUNT as usize] =
+        ["demo 1/5", "demo 2/5", "demo 3/5", "demo 4/5", "demo 5/5"];
+    MenuView {
+        label: LABELS[step.number() as usize - 1],
+        value: MenuValue::Text(step.title()),
+        editing: false,
+    }
+}
+
+/// The narration of a gesture on the displays.
+pub fn gesture_view(gesture: Gesture) -> MenuView {
+    MenuView {
+        label: "gesture",
+        value: MenuValue::Text(gesture.name()),
+        editing: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test_measurement;
+
+    #[test]
+    fn detects_the_gestures_of_its_step() {
+        let mut demo = Demo::new();
+        assert_eq!(demo.advance(), Step::Streaming);
+        assert_eq!(demo.on_measurement(&test_measurement(0, 300)), None);
+        while demo.advance() != Step::Gestures {}
+        assert_eq!(demo.step.app_mode(), AppMode::ParkingAssist);
+
+        // a swipe
+        assert_eq!(demo.on_measurement(&test_measurement(0, 300)), None);
+        assert_eq!(
+            demo.on_measurement(&test_measurement(100, 1_200)),
+            Some(Gesture::Swipe)
+        );
+        // a push, which leaves without a swipe
+        assert_eq!(demo.on_measurement(&test_measurement(1_000, 400)), None);
+        assert_eq!(
+            demo.on_measurement(&test_measurement(1_100, 290)),
+            Some(Gesture::Push)
+        );
+        assert_eq!(
+            demo.on_measurement(&test_measurement(1_200, 400)),
+            Some(Gesture::Pull)
+        );
+        assert_eq!(demo.on_measurement(&test_measurement(1_300, 2_000)), None);
+        // a hand held still
+        assert_eq!(demo.on_measurement(&test_measurement(2_000, 300)), None);
+        assert_eq!(demo.on_measurement(&test_measurement(3_000, 1_200)), None);
+
+        assert_eq!(demo.advance(), Step::Display);
+        assert_eq!(step_view(Step::Display).label, "demo 5/5");
+        assert_eq!(demo.advance(), Step::Streaming);
+    }
+}
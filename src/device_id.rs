@@ -0,0 +1,45 @@
+//! A short identifier of the board, so that the data of several boards can be told apart once it's
+//! aggregated. It's the CRC-32 of the 96-bit unique device ID of the microcontroller, which is
+//! programmed at the factory.
+
+#[cfg(not(test))]
+use crate::storage::crc32;
+use core::fmt;
+#[cfg(not(test))]
+use stm32f4xx_hal::signature::Uid;
+
+/// Formatted as 8 hexadecimal digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceId(pub u32);
+
+impl DeviceId {
+    /// The identifier of this board.
+    #[cfg(not(test))]
+    pub fn get() -> Self {
+        let uid = Uid::get();
+        let mut bytes = [0; 12];
+        bytes[..2].copy_from_slice(&uid.x().to_le_bytes());
+        bytes[2..4].copy_from_slice(&uid.y().to_le_bytes());
+        bytes[4] = uid.waf_num();
+        bytes[5..].copy_from_slice(uid.lot_num().as_bytes());
+        Self(crc32(&bytes))
+    }
+
+    /// A fixed identifier for the tests on the host, which has no unique device ID to read.
+    #[cfg(test)]
+    pub fn get() -> Self {
+        Self(0x5f3a91c2)
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+
+impl defmt::Format for DeviceId {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{=u32:08x}", self.0)
+    }
+}
@@ -0,0 +1,120 @@
+//! All local displays showing the live distance.
+//!
+//! The displays are combined into a single struct so that the rendering task doesn't need to know
+//! which displays have been enabled. They are redrawn by a low-priority task so that the slow
+//! transfers never delay the handling of the measurements.
+
+#[cfg(feature = "display-hd44780")]
+use crate::lcd::Lcd;
+#[cfg(feature = "led-strip")]
+use crate::led_strip::LedStrip;
+#[cfg(feature = "display-ssd1306")]
+use crate::oled::Oled;
+use crate::telemetry::Measurement;
+
+/// Everything which is shown on the displays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayState {
+    pub measurement: Option<Measurement>,
+    pub ranging: bool,
+    /// Shown instead of the distance while the on-device menu is open.
+    pub menu: Option<MenuView>,
+}
+
+/// The currently selected item of the on-device menu (see `crate::menu`).
+#[cfg_attr(not(any(feature = "menu", feature = "demo")), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MenuView {
+    pub label: &'static str,
+    pub value: MenuValue,
+    /// Whether the value is currently being changed.
+    pub editing: bool,
+}
+
+/// The value of a [`MenuView`].
+#[cfg_attr(not(any(feature = "menu", feature = "demo")), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuValue {
+    Text(&'static str),
+    /// A number with its unit.
+    Number(u16, &'static str),
+    /// The item doesn't have a value.
+    Empty,
+}
+
+impl MenuValue {
+    /// Write the value as text, e.g. `1200 mm`.
+    pub fn write_to(&self, f: &mut impl core::fmt::Write) -> core::fmt::Result {
+        match self {
+            MenuValue::Text(text) => f.write_str(text),
+            MenuValue::Number(value, unit) => write!(f, "{} {}", value, unit),
+            MenuValue::Empty => Ok(()),
+        }
+    }
+}
+
+/// The display could not be updated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct RenderError;
+
+/// A display backend which can show the [`DisplayState`].
+pub trait Display {
+    /// Show the state. This is only called if the state has changed.
+    fn render(&mut self, state: &DisplayState) -> Result<(), RenderError>;
+}
+
+pub struct Displays {
+    /// `None` if the display isn't connected.
+    #[cfg(feature = "display-ssd1306")]
+    pub oled: Option<Oled>,
+    /// `None` if the display isn't connected.
+    #[cfg(feature = "display-hd44780")]
+    pub lcd: Option<Lcd>,
+    #[cfg(feature = "led-strip")]
+    pub led_strip: LedStrip,
+    /// The state which is currently shown, used to skip redundant redraws.
+    pub shown: Option<DisplayState>,
+}
+
+impl Displays {
+    /// Whether any display has been enabled, otherwise there's no need to render anything.
+    pub const ENABLED: bool = cfg!(any(
+        feature = "display-ssd1306",
+        feature = "display-hd44780",
+        feature = "led-strip"
+    ));
+
+    /// Redraw all displays if the state has changed.
+    pub fn render(&mut self, state: &DisplayState) {
+        if self.shown.as_ref() == Some(state) {
+            return;
+        }
+        let mut ok = true;
+        self.for_each(|display| ok &= display.render(state).is_ok());
+        if ok {
+            self.shown = Some(*state);
+        } else {
+            defmt::warn!("failed to update the display");
+        }
+    }
+
+    /// Call `f` for every display which is connected.
+    fn for_each(&mut self, mut f: impl FnMut(&mut dyn Display)) {
+        #[cfg(feature = "display-ssd1306")]
+        if let Some(oled) = &mut self.oled {
+            f(oled);
+        }
+        #[cfg(feature = "display-hd44780")]
+        if let Some(lcd) = &mut self.lcd {
+            f(lcd);
+        }
+        #[cfg(feature = "led-strip")]
+        f(&mut self.led_strip);
+        #[cfg(not(any(
+            feature = "display-ssd1306",
+            feature = "display-hd44780",
+            feature = "led-strip"
+        )))]
+        let _ = &mut f;
+    }
+}
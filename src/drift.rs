@@ -0,0 +1,208 @@
+//! Monitoring of the drift of the offset calibration: every [`config::INTERVAL_S`] (or with
+//! `drift check`) the mean of the next [`SAMPLES`] valid measurements is compared with the known
+//! distance to a static scene (e.g. the floor at night), see [`config::REFERENCE_MM`]. Their
+//! difference is the estimated drift of the offset, a recalibration is requested once it's more
+//! than [`config::LIMIT_MM`].
+//!
+//! A check whose measurements spread by more than [`STATIC_TOLERANCE_MM`] is discarded, as
+//! something has moved in front of the sensor meanwhile.
+
+use crate::config::drift as config;
+use crate::telemetry::Measurement;
+use vl53l1x_uld::RangeStatus;
+
+/// Number of valid measurements which are averaged by a check.
+pub const SAMPLES: u16 = 32;
+/// The largest spread of the measurements of a check at which the scene is static.
+pub const STATIC_TOLERANCE_MM: u16 = 30;
+
+/// The result of a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The estimated drift (positive if the distances are too long) is within the limit.
+    Drift(i32),
+    /// The estimated drift is beyond the limit, the sensor has to be calibrated again.
+    Exceeded(i32),
+    /// The measurements spread too much to estimate the drift.
+    NotStatic { spread_mm: u16 },
+}
+
+/// The measurements of a running check.
+struct Check {
+    sum: u32,
+    count: u16,
+    min_mm: u16,
+    max_mm: u16,
+}
+
+pub struct DriftMonitor {
+    /// The distance to the static scene, `None` if the drift isn't monitored.
+    reference_mm: Option<u16>,
+    check: Option<Check>,
+    /// The drift estimated by the latest successful check.
+    latest_mm: Option<i32>,
+}
+
+impl DriftMonitor {
+    pub const fn new(reference_mm: Option<u16>) -> Self {
+        Self {
+            reference_mm,
+            check: None,
+            latest_mm: None,
+        }
+    }
+
+    pub fn reference_mm(&self) -> Option<u16> {
+        self.reference_mm
+    }
+
+    /// Change the distance to the static scene, which discards the running check & the latest
+    /// estimate.
+    pub fn set_reference_mm(&mut self, reference_mm: Option<u16>) {
+        *self = Self::new(reference_mm);
+    }
+
+    pub fn latest_mm(&self) -> Option<i32> {
+        self.latest_mm
+    }
+
+    pub fn is_checking(&self) -> bool {
+        self.check.is_some()
+    }
+
+    /// Start a check (or restart the running one), returns `false` without a reference.
+    pub fn start(&mut self) -> bool {
+        if self.reference_mm.is_none() {
+            return false;
+        }
+        self.check = Some(Check {
+            sum: 0,
+            count: 0,
+            min_mm: u16::MAX,
+            max_mm: 0,
+        });
+        true
+    }
+
+    /// Add a measurement to the running check, returns its outcome once it's done. The invalid &
+    /// the settling measurements are ignored.
+    pub fn add(&mut self, measurement: &Measurement) -> Option<Outcome> {
+        let reference_mm = self.reference_mm?;
+        let check = self.check.as_mut()?;
+        if measurement.status != RangeStatus::Valid || measurement.settling {
+            return None;
+        }
+        let distance_mm = measurement.distance_mm;
+        check.sum += distance_mm as u32;
+        check.count += 1;
+        check.min_mm = check.min_mm.min(distance_mm);
+        check.max_mm = check.max_mm.max(distance_mm);
+        if check.count < SAMPLES {
+            return None;
+        }
+        let check = self.check.take()?;
+        let spread_mm = check.max_mm - check.min_mm;
+        if spread_mm > STATIC_TOLERANCE_MM {
+            return Some(Outcome::NotStatic { spread_mm });
+        }
+        let mean_mm = (check.sum + check.count as u32 / 2) / check.count as u32;
+        let drift_mm = mean_mm as i32 - reference_mm as i32;
+        self.latest_mm = Some(drift_mm);
+        if drift_mm.unsigned_abs() > config::LIMIT_MM as u32 {
+            Some(Outcome::Exceeded(drift_mm))
+        } else {
+            Some(Outcome::Drift(drift_mm))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test_measurement;
+
+    fn check(monitor: &mut DriftMonitor, distances_mm: impl Fn(u16) -> u16) -> Option<Outcome> {
+        assert!(monitor.start());
+        (0..SAMPLES).find_map(|i| monitor.add(&test_measurement(0, distances_mm(i))))
+    }
+
+    #[test]
+    fn doesnt_check_without_a_reference() {
+        let mut monitor = DriftMonitor::new(None);
+        assert!(!monitor.start());
+        assert_eq!(monitor.add(&test_measurement(0, 2_000)), None);
+        assert!(!monitor.is_checking());
+    }
+
+    #[test]
+    fn ignores_the_measurements_between_the_checks() {
+        let mut monitor = DriftMonitor::new(Some(2_000));
+        for _ in 0..SAMPLES {
+            assert_eq!(monitor.add(&test_measurement(0, 2_100)), None);
+        }
+        assert_eq!(monitor.latest_mm(), None);
+    }
+
+    #[test]
+    fn averages_the_measurements_of_a_check() {
+        let mut monitor = DriftMonitor::new(Some(2_005));
+        let outcome = check(&mut monitor, |i| 2_003 + i % 5);
+        assert_eq!(outcome, Some(Outcome::Drift(0)));
+        assert_eq!(monitor.latest_mm(), Some(0));
+        assert!(!monitor.is_checking());
+    }
+
+    #[test]
+    fn a_drift_up_to_the_limit_is_accepted() {
+        let mut monitor = DriftMonitor::new(Some(2_000));
+        let outcome = check(&mut monitor, |_| 2_000 + config::LIMIT_MM);
+        assert_eq!(outcome, Some(Outcome::Drift(config::LIMIT_MM as i32)));
+    }
+
+    #[test]
+    fn a_drift_beyond_the_limit_is_reported() {
+        let mut monitor = DriftMonitor::new(Some(2_000));
+        let outcome = check(&mut monitor, |_| 2_000 - config::LIMIT_MM - 1);
+        let drift_mm = -(config::LIMIT_MM as i32) - 1;
+        assert_eq!(outcome, Some(Outcome::Exceeded(drift_mm)));
+        assert_eq!(monitor.latest_mm(), Some(drift_mm));
+    }
+
+    #[test]
+    fn skips_the_invalid_and_the_settling_measurements() {
+        let mut monitor = DriftMonitor::new(Some(2_000));
+        assert!(monitor.start());
+        let invalid = Measurement {
+            status: RangeStatus::SignalFailure,
+            ..test_measurement(0, 100)
+        };
+        let settling = Measurement {
+            settling: true,
+            ..test_measurement(0, 100)
+        };
+        assert_eq!(monitor.add(&invalid), None);
+        assert_eq!(monitor.add(&settling), None);
+        let outcome = (0..SAMPLES).find_map(|_| monitor.add(&test_measurement(0, 2_000)));
+        assert_eq!(outcome, Some(Outcome::Drift(0)));
+    }
+
+    #[test]
+    fn keeps_the_latest_estimate_if_the_scene_isnt_static() {
+        let mut monitor = DriftMonitor::new(Some(2_000));
+        check(&mut monitor, |_| 2_004);
+        let outcome = check(&mut monitor, |i| 2_000 + i * 10);
+        assert_eq!(outcome, Some(Outcome::NotStatic { spread_mm: 310 }));
+        assert_eq!(monitor.latest_mm(), Some(4));
+    }
+
+    #[test]
+    fn a_new_reference_discards_the_check_and_the_estimate() {
+        let mut monitor = DriftMonitor::new(Some(2_000));
+        check(&mut monitor, |_| 2_004);
+        assert!(monitor.start());
+        monitor.set_reference_mm(Some(1_500));
+        assert!(!monitor.is_checking());
+        assert_eq!(monitor.latest_mm(), None);
+        assert_eq!(monitor.reference_mm(), Some(1_500));
+    }
+}
@@ -0,0 +1,442 @@
+//! Emulation of an EEPROM in the internal flash for settings which are changed frequently.
+//!
+//! The last two 128 KiB sectors (6 at `0x0804_0000` & 7 at `0x0806_0000`, excluded from the
+//! firmware in `memory.x`) are used alternately. Each sector starts with a header containing a
+//! generation counter, the valid sector with the highest generation is the active one. Every write
+//! appends a record with the key, the version of its format & the value to the active sector, the
+//! latest valid record of a key is its current value.
+//!
+//! Once the active sector is full, the latest record of each key is copied to the other sector
+//! which is only activated by writing its header afterwards. Together with the checksum of each
+//! record this makes all updates atomic: after an interrupted write (e.g. a reset) the previous
+//! value is read. The sectors are thus only erased every few thousand writes.
+
+use crate::storage::{self, Flash, Watchdog, ROW_LEN, SECTOR_LEN};
+use stm32f4xx_hal::flash;
+
+/// The sectors which are used alternately.
+const SECTORS: [u8; 2] = [6, 7];
+/// Number of rows per sector, including the header.
+const ROWS: usize = SECTOR_LEN / ROW_LEN;
+/// Marks the header of a sector, `EEP` followed by the version of the layout.
+const MAGIC: [u8; 4] = *b"EEP1";
+/// Maximum length of a value.
+pub const MAX_VALUE_LEN: usize = 8;
+
+/// Identifies a value.
+pub type Key = u8;
+
+/// The keys of all values in the EEPROM, a key must never be reused for another value.
+pub mod keys {
+    use super::Key;
+
+    /// [`crate::calibration_store::CalibrationData`]
+    pub const CALIBRATION: Key = 1;
+    /// [`crate::app_mode::AppMode`]
+    pub const APP_MODE: Key = 2;
+    /// [`crate::settings::TofSettings`]
+    pub const TOF: Key = 3;
+    /// [`crate::settings::Settings::frame_format`]
+    pub const FRAME_FORMAT: Key = 4;
+    // the keys of the optional features are defined in all builds as the CRC of the settings
+    // covers them, see [`crate::settings::SCHEMA_VERSION`]
+    /// The alarm threshold of the settings (`alarm-output` & `lora` features).
+    pub const ALARM_THRESHOLD: Key = 5;
+    /// Whether the buzzer of the settings is muted (`buzzer` feature).
+    pub const BUZZER: Key = 6;
+    /// The settings of the distance hold controller (`motor-pid` feature) without the gains,
+    /// which don't fit into a single value.
+    pub const MOTOR: Key = 7;
+    pub const MOTOR_KP: Key = 8;
+    pub const MOTOR_KI: Key = 9;
+    pub const MOTOR_KD: Key = 10;
+    /// The settings of the [`crate::profile::Profile`]s.
+    pub const PROFILE_INDOOR: Key = 11;
+    pub const PROFILE_OUTDOOR: Key = 12;
+    pub const PROFILE_DEMO: Key = 13;
+    /// The active [`crate::profile::Profile`].
+    pub const ACTIVE_PROFILE: Key = 14;
+    /// The number of boots, see [`crate::reset_log`].
+    pub const BOOT_COUNT: Key = 15;
+    /// The first of the [`crate::reset_log::LOG_LEN`] keys of the reset log.
+    pub const RESET_LOG: Key = 16;
+    /// The first of the [`crate::event_log::LOG_LEN`] keys of the event log.
+    pub const EVENT_LOG: Key = 22;
+    /// The slot which boots & the state of a new firmware, see [`crate::firmware_update`].
+    #[cfg(feature = "firmware-update")]
+    pub const FIRMWARE_UPDATE: Key = 54;
+    /// [`crate::settings::Settings::scaling`]
+    pub const SCALING: Key = 55;
+    /// The configured zones of [`crate::settings::Settings::zones`] (a bit for each).
+    pub const ZONES: Key = 56;
+    /// The first of the [`crate::geofence::MAX_ZONES`] keys of the zones.
+    pub const ZONE: Key = 57;
+    /// [`crate::settings::Settings::publish_interval_ms`]
+    pub const PUBLISH_INTERVAL: Key = 65;
+    /// [`crate::settings::Settings::change_delta_mm`]
+    pub const CHANGE_DELTA: Key = 66;
+    /// The schema version & the CRC of the settings, see [`crate::settings::SCHEMA_VERSION`].
+    pub const SETTINGS_SCHEMA: Key = 67;
+    /// [`crate::settings::Settings::cal_target_mm`]
+    pub const CAL_TARGET: Key = 68;
+    /// The settings of the fast [`crate::profile::Profile`].
+    pub const PROFILE_FAST: Key = 69;
+    /// [`crate::settings::Settings::schedule`]
+    pub const SCHEDULE: Key = 70;
+}
+
+pub struct Eeprom {
+    /// Index of the active sector in [`SECTORS`].
+    active: usize,
+    generation: u32,
+    /// The first row of the active sector which hasn't been written yet.
+    next_row: usize,
+}
+
+/// A record as stored in a row: key, version, length, a reserved byte, the value (padded to
+/// [`MAX_VALUE_LEN`]) & the CRC-32 of the preceding bytes.
+struct Record<'a> {
+    key: Key,
+    version: u8,
+    value: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    fn encode(&self) -> [u8; ROW_LEN] {
+        let mut row = [0; ROW_LEN];
+        row[0] = self.key;
+        row[1] = self.version;
+        row[2] = self.value.len() as u8;
+        row[4..4 + self.value.len()].copy_from_slice(self.value);
+        let crc = storage::crc32(&row[..12]);
+        row[12..].copy_from_slice(&crc.to_le_bytes());
+        row
+    }
+
+    fn decode(row: &'a [u8]) -> Option<Self> {
+        let crc = u32::from_le_bytes(row[12..16].try_into().ok()?);
+        let len = row[2] as usize;
+        if len > MAX_VALUE_LEN || storage::crc32(&row[..12]) != crc {
+            return None;
+        }
+        Some(Self {
+            key: row[0],
+            version: row[1],
+            value: &row[4..4 + len],
+        })
+    }
+}
+
+impl Eeprom {
+    /// Find the active sector, an empty one is set up if there's none (e.g. on the first boot).
+    pub fn open(
+        flash: &mut impl Flash,
+        watchdog: &mut impl Watchdog,
+    ) -> Result<Self, flash::Error> {
+        let active = (0..SECTORS.len())
+            .filter_map(|index| Some((index, read_header(flash, index)?)))
+            .max_by_key(|&(_, generation)| generation);
+        let eeprom = match active {
+            Some((active, generation)) => {
+                let next_row = rows(flash, active)
+                    .skip(1)
+                    .position(storage::is_erased)
+                    .map_or(ROWS, |row| row + 1);
+                Self {
+                    active,
+                    generation,
+                    next_row,
+                }
+            }
+            None => {
+                defmt::info!("EEPROM: no valid sector, formatting");
+                storage::erase_sector(flash, SECTORS[0], watchdog)?;
+                write_header(flash, 0, 1)?;
+                Self {
+                    active: 0,
+                    generation: 1,
+                    next_row: 1,
+                }
+            }
+        };
+        defmt::info!(
+            "EEPROM: sector {}, generation {}, {} of {} rows used",
+            SECTORS[eeprom.active],
+            eeprom.generation,
+            eeprom.next_row,
+            ROWS
+        );
+        Ok(eeprom)
+    }
+
+    /// Read the current value of the key into `value` & return its length. Returns `None` if the
+    /// key hasn't been written yet or has been written with another version of its format.
+    pub fn read(
+        &self,
+        flash: &impl Flash,
+        key: Key,
+        version: u8,
+        value: &mut [u8],
+    ) -> Option<usize> {
+        let record = self.latest(flash, key)?;
+        if record.version != version {
+            defmt::warn!(
+                "EEPROM: key {} has version {}, expected {}",
+                key,
+                record.version,
+                version
+            );
+            return None;
+        }
+        let len = record.value.len().min(value.len());
+        value[..len].copy_from_slice(&record.value[..len]);
+        Some(len)
+    }
+
+    /// Read the current value of the key, which must have exactly `N` bytes.
+    pub fn read_array<const N: usize>(
+        &self,
+        flash: &impl Flash,
+        key: Key,
+        version: u8,
+    ) -> Option<[u8; N]> {
+        let mut value = [0; N];
+        let len = self.read(flash, key, version, &mut value)?;
+        (len == N).then_some(value)
+    }
+
+    /// Write the value of the key, which must not be longer than [`MAX_VALUE_LEN`]. Nothing is
+    /// written if the value hasn't changed. If the active sector is full the other one is
+    /// activated first, see [`storage::erase_sector`] for the watchdog.
+    pub fn write(
+        &mut self,
+        flash: &mut impl Flash,
+        key: Key,
+        version: u8,
+        value: &[u8],
+        watchdog: &mut impl Watchdog,
+    ) -> Result<(), flash::Error> {
+        assert!(value.len() <= MAX_VALUE_LEN, "EEPROM value too long");
+        if let Some(current) = self.latest(flash, key) {
+            if current.version == version && current.value == value {
+                return Ok(());
+            }
+        }
+        if self.next_row == ROWS {
+            self.swap(flash, watchdog)?;
+        }
+        let record = Record {
+            key,
+            version,
+            value,
+        };
+        let offset = storage::sector_offset(SECTORS[self.active]) + self.next_row * ROW_LEN;
+        // the row is used even if programming fails as it might have been programmed partially
+        self.next_row += 1;
+        storage::program(flash, offset, &record.encode())
+    }
+
+    /// The latest valid record of the key.
+    fn latest<'a>(&self, flash: &'a impl Flash, key: Key) -> Option<Record<'a>> {
+        rows(flash, self.active)
+            .take(self.next_row)
+            .skip(1)
+            .filter_map(Record::decode)
+            .filter(|record| record.key == key)
+            .last()
+    }
+
+    /// Copy the latest record of each key to the other sector & activate it.
+    fn swap(
+        &mut self,
+        flash: &mut impl Flash,
+        watchdog: &mut impl Watchdog,
+    ) -> Result<(), flash::Error> {
+        let target = 1 - self.active;
+        defmt::info!("EEPROM: sector full, moving to sector {}", SECTORS[target]);
+        storage::erase_sector(flash, SECTORS[target], watchdog)?;
+
+        // the rows are copied starting with the latest one, thus older records of the same keys
+        // are skipped
+        let mut copied = [false; Key::MAX as usize + 1];
+        let mut next_row = 1;
+        let destination = storage::sector_offset(SECTORS[target]);
+        for row_index in (1..self.next_row).rev() {
+            // copied as the flash can't be borrowed while programming it
+            let mut row = [0; ROW_LEN];
+            if let Some(source) = rows(flash, self.active).nth(row_index) {
+                row.copy_from_slice(source);
+            }
+            let Some(record) = Record::decode(&row) else {
+                continue;
+            };
+            if core::mem::replace(&mut copied[record.key as usize], true) {
+                continue;
+            }
+            storage::program(flash, destination + next_row * ROW_LEN, &row)?;
+            next_row += 1;
+        }
+
+        // writing the header only now ensures that the old sector stays active if this fails
+        let generation = self.generation + 1;
+        write_header(flash, target, generation)?;
+        self.active = target;
+        self.generation = generation;
+        self.next_row = next_row;
+        Ok(())
+    }
+}
+
+fn rows(flash: &impl Flash, index: usize) -> impl Iterator<Item = &[u8]> {
+    storage::read_sector(flash, SECTORS[index]).chunks_exact(ROW_LEN)
+}
+
+/// The generation of the sector, `None` if it doesn't have a valid header.
+fn read_header(flash: &impl Flash, index: usize) -> Option<u32> {
+    let header = rows(flash, index).next()?;
+    let crc = u32::from_le_bytes(header[12..16].try_into().ok()?);
+    if header[..4] != MAGIC || storage::crc32(&header[..12]) != crc {
+        return None;
+    }
+    Some(u32::from_le_bytes(header[4..8].try_into().ok()?))
+}
+
+/// Layout: magic, generation, 4 reserved bytes & the CRC-32 of the preceding bytes.
+fn write_header(flash: &mut impl Flash, index: usize, generation: u32) -> Result<(), flash::Error> {
+    let mut header = [0; ROW_LEN];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4..8].copy_from_slice(&generation.to_le_bytes());
+    let crc = storage::crc32(&header[..12]);
+    header[12..].copy_from_slice(&crc.to_le_bytes());
+    storage::program(flash, storage::sector_offset(SECTORS[index]), &header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{NoWatchdog, RamFlash};
+
+    /// The offset of a row of a sector in the flash.
+    fn row_offset(index: usize, row: usize) -> usize {
+        storage::sector_offset(SECTORS[index]) + row * ROW_LEN
+    }
+
+    #[test]
+    fn formats_an_erased_flash() {
+        let mut flash = RamFlash::new();
+        let eeprom = Eeprom::open(&mut flash, &mut NoWatchdog).unwrap();
+        assert_eq!(
+            (eeprom.active, eeprom.generation, eeprom.next_row),
+            (0, 1, 1)
+        );
+        assert_eq!(read_header(&flash, 0), Some(1));
+        assert_eq!(read_header(&flash, 1), None);
+    }
+
+    #[test]
+    fn reads_back_the_written_values() {
+        let mut flash = RamFlash::new();
+        let mut eeprom = Eeprom::open(&mut flash, &mut NoWatchdog).unwrap();
+        eeprom
+            .write(&mut flash, 1, 2, &[1, 2, 3], &mut NoWatchdog)
+            .unwrap();
+        let value = [0xA5; MAX_VALUE_LEN];
+        eeprom
+            .write(&mut flash, 2, 1, &value, &mut NoWatchdog)
+            .unwrap();
+        eeprom
+            .write(&mut flash, 1, 2, &[4, 5, 6], &mut NoWatchdog)
+            .unwrap();
+
+        assert_eq!(eeprom.read_array(&flash, 1, 2), Some([4, 5, 6]));
+        assert_eq!(eeprom.read_array(&flash, 2, 1), Some(value));
+        // another version or length of the format isn't read
+        assert_eq!(eeprom.read_array::<3>(&flash, 1, 3), None);
+        assert_eq!(eeprom.read_array::<4>(&flash, 1, 2), None);
+        assert_eq!(eeprom.read_array::<1>(&flash, 3, 1), None);
+
+        // nothing is written for an unchanged value
+        eeprom
+            .write(&mut flash, 1, 2, &[4, 5, 6], &mut NoWatchdog)
+            .unwrap();
+        assert_eq!(eeprom.next_row, 4);
+
+        let eeprom = Eeprom::open(&mut flash, &mut NoWatchdog).unwrap();
+        assert_eq!(eeprom.next_row, 4);
+        assert_eq!(eeprom.read_array(&flash, 1, 2), Some([4, 5, 6]));
+    }
+
+    #[test]
+    fn a_record_with_a_crc_mismatch_is_ignored() {
+        let mut flash = RamFlash::new();
+        let mut eeprom = Eeprom::open(&mut flash, &mut NoWatchdog).unwrap();
+        eeprom
+            .write(&mut flash, 1, 1, &[1, 2], &mut NoWatchdog)
+            .unwrap();
+        eeprom
+            .write(&mut flash, 1, 1, &[3, 4], &mut NoWatchdog)
+            .unwrap();
+        // e.g. a write interrupted by a reset
+        flash.0[row_offset(0, 2) + 5] ^= 0x01;
+        assert_eq!(eeprom.read_array(&flash, 1, 1), Some([1, 2]));
+
+        flash.0[row_offset(0, 1) + 13] ^= 0x80;
+        assert_eq!(eeprom.read_array::<2>(&flash, 1, 1), None);
+    }
+
+    #[test]
+    fn a_sector_with_a_crc_mismatch_in_the_header_isnt_active() {
+        let mut flash = RamFlash::new();
+        write_header(&mut flash, 0, 1).unwrap();
+        write_header(&mut flash, 1, 2).unwrap();
+        flash.0[row_offset(1, 0) + 4] = 3;
+        let eeprom = Eeprom::open(&mut flash, &mut NoWatchdog).unwrap();
+        assert_eq!((eeprom.active, eeprom.generation), (0, 1));
+    }
+
+    #[test]
+    fn a_full_sector_is_swapped_with_the_latest_records() {
+        let mut flash = RamFlash::new();
+        write_header(&mut flash, 0, 1).unwrap();
+        let record = Record {
+            key: 2,
+            version: 1,
+            value: &[0xEE],
+        };
+        storage::program(&mut flash, row_offset(0, 1), &record.encode()).unwrap();
+        // all the other rows are records of the same key
+        for row in 2..ROWS {
+            let value = (row as u16).to_le_bytes();
+            let record = Record {
+                key: 1,
+                version: 1,
+                value: &value,
+            };
+            storage::program(&mut flash, row_offset(0, row), &record.encode()).unwrap();
+        }
+        let mut eeprom = Eeprom::open(&mut flash, &mut NoWatchdog).unwrap();
+        assert_eq!(eeprom.next_row, ROWS);
+        let latest = (ROWS as u16 - 1).to_le_bytes();
+        assert_eq!(eeprom.read_array(&flash, 1, 1), Some(latest));
+
+        eeprom
+            .write(&mut flash, 3, 1, &[7], &mut NoWatchdog)
+            .unwrap();
+        // the header, the latest records of the keys 1 & 2 and the new one
+        assert_eq!(
+            (eeprom.active, eeprom.generation, eeprom.next_row),
+            (1, 2, 4)
+        );
+        assert_eq!(eeprom.read_array(&flash, 1, 1), Some(latest));
+        assert_eq!(eeprom.read_array(&flash, 2, 1), Some([0xEE]));
+        assert_eq!(eeprom.read_array(&flash, 3, 1), Some([7]));
+
+        // the old sector still has a valid header, the newer generation wins
+        let eeprom = Eeprom::open(&mut flash, &mut NoWatchdog).unwrap();
+        assert_eq!(
+            (eeprom.active, eeprom.generation, eeprom.next_row),
+            (1, 2, 4)
+        );
+        assert_eq!(eeprom.read_array(&flash, 1, 1), Some(latest));
+    }
+}
@@ -0,0 +1,40 @@
+//! A quadrature encoder on `PB4` (A) & `PB5` (B) read by TIM3 in encoder mode, e.g. for mounting
+//! the sensor on a moving carriage to record distance profiles.
+//!
+//! TIM3 counts all four edges of each encoder cycle and only has 16 bits, the count is extended to
+//! 32 bits whenever it is read. The encoder must thus not move by more than 32767 counts between
+//! two readings.
+
+use stm32f4xx_hal::hal::Qei as _;
+use stm32f4xx_hal::pac::TIM3;
+use stm32f4xx_hal::qei::Qei;
+
+#[cfg(feature = "proximity-led")]
+compile_error!("the features `encoder` and `proximity-led` can't be combined as both use TIM3");
+
+pub struct Encoder {
+    qei: Qei<TIM3>,
+    last_count: u16,
+    position: i32,
+}
+
+impl Encoder {
+    pub fn new(qei: Qei<TIM3>) -> Self {
+        let last_count = qei.count();
+        Self {
+            qei,
+            last_count,
+            position: 0,
+        }
+    }
+
+    /// Position in encoder counts relative to the position at boot.
+    pub fn position(&mut self) -> i32 {
+        let count = self.qei.count();
+        self.position = self
+            .position
+            .wrapping_add(count.wrapping_sub(self.last_count) as i16 as i32);
+        self.last_count = count;
+        self.position
+    }
+}
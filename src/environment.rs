@@ -0,0 +1,189 @@
+//! Temperature, humidity & pressure from a BME280 connected to the shared I2C bus.
+//!
+//! The sensor runs in its normal mode with a new sample every second. The raw values are
+//! compensated with the integer formulas of the datasheet using the calibration data stored in the
+//! sensor.
+
+use crate::config::environment as config;
+use crate::I2cBus;
+use stm32f4xx_hal::hal::blocking::i2c::{Write, WriteRead};
+
+const CHIP_ID: u8 = 0x60;
+
+const REG_CALIBRATION_1: u8 = 0x88;
+const REG_CHIP_ID: u8 = 0xD0;
+const REG_CALIBRATION_2: u8 = 0xE1;
+const REG_CTRL_HUM: u8 = 0xF2;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_CONFIG: u8 = 0xF5;
+const REG_DATA: u8 = 0xF7;
+
+/// The temperature of the TOF sensor is recalibrated once the temperature has changed by this much
+/// (in 0.01 °C) since the last calibration, as recommended by ST.
+const RECALIBRATION_DELTA: i32 = 800;
+
+/// A compensated sample of the sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Environment {
+    /// Temperature in 0.01 °C.
+    pub temperature: i32,
+    /// Relative humidity in 0.01 %.
+    pub humidity: u32,
+    /// Pressure in Pa.
+    pub pressure_pa: u32,
+}
+
+/// Calibration data of the sensor, named as in the datasheet.
+struct Calibration {
+    t1: i32,
+    t2: i32,
+    t3: i32,
+    p: [i64; 9],
+    h1: i32,
+    h2: i32,
+    h3: i32,
+    h4: i32,
+    h5: i32,
+    h6: i32,
+}
+
+pub struct EnvironmentSensor {
+    i2c: I2cBus,
+    calibration: Calibration,
+    /// Temperature at which the TOF sensor has been calibrated last.
+    calibrated_temperature: Option<i32>,
+}
+
+impl EnvironmentSensor {
+    /// Set up the sensor. Returns `None` if no sensor is connected.
+    pub fn new(mut i2c: I2cBus) -> Option<Self> {
+        let mut chip_id = [0];
+        if i2c
+            .write_read(config::ADDRESS, &[REG_CHIP_ID], &mut chip_id)
+            .is_err()
+            || chip_id[0] != CHIP_ID
+        {
+            defmt::warn!("no BME280 found");
+            return None;
+        }
+
+        let mut c1 = [0; 26];
+        let mut c2 = [0; 7];
+        i2c.write_read(config::ADDRESS, &[REG_CALIBRATION_1], &mut c1)
+            .ok()?;
+        i2c.write_read(config::ADDRESS, &[REG_CALIBRATION_2], &mut c2)
+            .ok()?;
+        let u16_at = |i: usize| u16::from_le_bytes([c1[i], c1[i + 1]]) as i32;
+        let i16_at = |i: usize| i16::from_le_bytes([c1[i], c1[i + 1]]) as i32;
+        let mut p = [u16_at(6) as i64; 9];
+        for (i, p) in p.iter_mut().enumerate().skip(1) {
+            *p = i16_at(6 + 2 * i) as i64;
+        }
+        let calibration = Calibration {
+            t1: u16_at(0),
+            t2: i16_at(2),
+            t3: i16_at(4),
+            p,
+            h1: c1[25] as i32,
+            h2: i16::from_le_bytes([c2[0], c2[1]]) as i32,
+            h3: c2[2] as i32,
+            h4: (c2[3] as i8 as i32) << 4 | (c2[4] & 0x0F) as i32,
+            h5: (c2[5] as i8 as i32) << 4 | (c2[4] >> 4) as i32,
+            h6: c2[6] as i8 as i32,
+        };
+
+        // humidity, temperature & pressure oversampling x1, normal mode with 1 s standby. The
+        // humidity setting only takes effect after writing the measurement control register.
+        for (register, value) in [
+            (REG_CTRL_HUM, 0x01),
+            (REG_CONFIG, 0xA0),
+            (REG_CTRL_MEAS, 0x27),
+        ] {
+            i2c.write(config::ADDRESS, &[register, value]).ok()?;
+        }
+        defmt::trace!("BME280 set up");
+        Some(Self {
+            i2c,
+            calibration,
+            calibrated_temperature: None,
+        })
+    }
+
+    /// Read the latest sample.
+    pub fn read(&mut self) -> Option<Environment> {
+        let mut data = [0; 8];
+        self.i2c
+            .write_read(config::ADDRESS, &[REG_DATA], &mut data)
+            .ok()?;
+        let adc_p = (data[0] as i32) << 12 | (data[1] as i32) << 4 | (data[2] >> 4) as i32;
+        let adc_t = (data[3] as i32) << 12 | (data[4] as i32) << 4 | (data[5] >> 4) as i32;
+        let adc_h = (data[6] as i32) << 8 | data[7] as i32;
+
+        let t_fine = self.t_fine(adc_t);
+        Some(Environment {
+            temperature: (t_fine * 5 + 128) >> 8,
+            humidity: self.humidity(adc_h, t_fine) * 100 / 1024,
+            pressure_pa: self.pressure(adc_p, t_fine) / 256,
+        })
+    }
+
+    /// Whether the temperature has changed far enough since the last temperature calibration of
+    /// the TOF sensor that it should be recalibrated. The first sample only records the
+    /// temperature as the sensor has been calibrated at boot.
+    pub fn needs_tof_calibration(&mut self, environment: &Environment) -> bool {
+        match self.calibrated_temperature {
+            Some(calibrated)
+                if (environment.temperature - calibrated).abs() < RECALIBRATION_DELTA =>
+            {
+                false
+            }
+            Some(_) => {
+                self.calibrated_temperature = Some(environment.temperature);
+                true
+            }
+            None => {
+                self.calibrated_temperature = Some(environment.temperature);
+                false
+            }
+        }
+    }
+
+    /// Fine temperature used by the compensation of all values.
+    fn t_fine(&self, adc_t: i32) -> i32 {
+        let c = &self.calibration;
+        let var1 = (((adc_t >> 3) - (c.t1 << 1)) * c.t2) >> 11;
+        let var2 = (((((adc_t >> 4) - c.t1) * ((adc_t >> 4) - c.t1)) >> 12) * c.t3) >> 14;
+        var1 + var2
+    }
+
+    /// Pressure in Pa as Q24.8.
+    fn pressure(&self, adc_p: i32, t_fine: i32) -> u32 {
+        let p = &self.calibration.p;
+        let mut var1 = t_fine as i64 - 128_000;
+        let mut var2 = var1 * var1 * p[5];
+        var2 += (var1 * p[4]) << 17;
+        var2 += p[3] << 35;
+        var1 = ((var1 * var1 * p[2]) >> 8) + ((var1 * p[1]) << 12);
+        var1 = (((1i64 << 47) + var1) * p[0]) >> 33;
+        if var1 == 0 {
+            return 0;
+        }
+        let mut pressure = 1_048_576 - adc_p as i64;
+        pressure = (((pressure << 31) - var2) * 3125) / var1;
+        var1 = (p[8] * (pressure >> 13) * (pressure >> 13)) >> 25;
+        var2 = (p[7] * pressure) >> 19;
+        (((pressure + var1 + var2) >> 8) + (p[6] << 4)) as u32
+    }
+
+    /// Relative humidity in % as Q22.10.
+    fn humidity(&self, adc_h: i32, t_fine: i32) -> u32 {
+        let c = &self.calibration;
+        let mut v = t_fine - 76_800;
+        v = (((adc_h << 14) - (c.h4 << 20) - (c.h5 * v) + 16_384) >> 15)
+            * (((((((v * c.h6) >> 10) * (((v * c.h3) >> 11) + 32_768)) >> 10) + 2_097_152) * c.h2
+                + 8_192)
+                >> 14);
+        v -= ((((v >> 15) * (v >> 15)) >> 7) * c.h1) >> 4;
+        (v.clamp(0, 419_430_400) >> 12) as u32
+    }
+}
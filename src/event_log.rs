@@ -0,0 +1,344 @@
+//! A log of the last [`LOG_LEN`] high-level events (presence changes, zone transitions, alarms,
+//! changes of the ambient light, maintenance requests, undervoltage, the battery level & the
+//! marks of the marker input), stored in the [`crate::eeprom`] separately from the measurements
+//! so that it survives a reset & can be queried with the `events` command.
+//!
+//! Each event is stamped with the calendar time if the [`crate::clock`] has been set, otherwise
+//! with the number of the boot & the time since it.
+
+#[cfg(feature = "battery")]
+use crate::battery::Level;
+use crate::eeprom::{self, Eeprom, Key};
+use core::cmp::Reverse;
+use core::fmt::Write;
+use stm32f4xx_hal::flash;
+use stm32f4xx_hal::pac::FLASH;
+use stm32f4xx_hal::watchdog::IndependentWatchdog;
+
+/// Number of events which are logged.
+pub const LOG_LEN: usize = 32;
+/// Number of events in the response to the `events` command, a longer one wouldn't fit into a
+/// line.
+pub const MAX_RESPONSE_LEN: usize = 8;
+/// Version of the format in which the events are stored, 1 had fixed zones.
+const EEPROM_VERSION: u8 = 2;
+/// Set in the kind of an entry if the time is the calendar time.
+const KIND_UTC: u8 = 0x80;
+const CODE_MASK: u8 = 0x0F;
+/// The number of the zone in the kind of an entry of [`Event::Zone`].
+const ZONE_SHIFT: u8 = 4;
+
+// the sequence numbers of all entries must be distinguishable in 8 bits
+const _: () = assert!(256 % LOG_LEN == 0 && LOG_LEN <= 64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Event {
+    /// A target has appeared (`true`) or disappeared, see [`crate::app_mode::PresenceDetector`].
+    Presence(bool),
+    /// The target has entered (`entered`) or left a zone of the [`crate::geofence`].
+    Zone { zone: u8, entered: bool },
+    /// The alarm output has been asserted (`true`) or deasserted.
+    #[cfg(feature = "alarm-output")]
+    Alarm(bool),
+    /// The ambient light has become high (`true`) or normal again, see [`crate::ambient`].
+    Ambient(bool),
+    /// The cover glass has become dirty (`true`) or clean again, see [`crate::lens`].
+    Lens(bool),
+    /// The supply voltage has dropped below the level of the PVD (`true`) or risen above it again,
+    /// see [`crate::brown_out`].
+    Undervoltage(bool),
+    /// The level of the battery has changed, see [`crate::battery`].
+    #[cfg(feature = "battery")]
+    Battery(crate::battery::Level),
+    /// The marker input has become active (`true`) or inactive, see [`crate::marker`].
+    #[cfg(feature = "marker-input")]
+    Marker(bool),
+}
+
+impl Event {
+    /// The code stored in the lower bits of the kind of an entry, together with the number of the
+    /// zone.
+    fn code(&self) -> u8 {
+        match self {
+            Event::Presence(present) => *present as u8,
+            Event::Zone { zone, entered } => zone << ZONE_SHIFT | (2 + *entered as u8),
+            #[cfg(feature = "alarm-output")]
+            Event::Alarm(asserted) => 6 + *asserted as u8,
+            Event::Ambient(high) => 8 + *high as u8,
+            Event::Lens(dirty) => 10 + *dirty as u8,
+            Event::Undervoltage(low) => 12 + *low as u8,
+            #[cfg(feature = "battery")]
+            Event::Battery(Level::Ok) => 14,
+            #[cfg(feature = "battery")]
+            Event::Battery(Level::Low) => 15,
+            // the upper bits only hold a zone with the codes 2 & 3
+            #[cfg(feature = "battery")]
+            Event::Battery(Level::Critical) => 1 << ZONE_SHIFT | 14,
+            #[cfg(feature = "marker-input")]
+            Event::Marker(active) => 1 << ZONE_SHIFT | *active as u8,
+        }
+    }
+
+    /// The event with the code, `None` if it's unknown (e.g. an alarm logged by a firmware with
+    /// the alarm output).
+    fn from_code(code: u8) -> Option<Self> {
+        if let kind @ (2 | 3) = code & CODE_MASK {
+            return Some(Event::Zone {
+                zone: code >> ZONE_SHIFT,
+                entered: kind == 3,
+            });
+        }
+        match code {
+            0 | 1 => Some(Event::Presence(code == 1)),
+            #[cfg(feature = "alarm-output")]
+            6 | 7 => Some(Event::Alarm(code == 7)),
+            8 | 9 => Some(Event::Ambient(code == 9)),
+            10 | 11 => Some(Event::Lens(code == 11)),
+            12 | 13 => Some(Event::Undervoltage(code == 13)),
+            #[cfg(feature = "battery")]
+            14 => Some(Event::Battery(Level::Ok)),
+            #[cfg(feature = "battery")]
+            15 => Some(Event::Battery(Level::Low)),
+            #[cfg(feature = "battery")]
+            0x1E => Some(Event::Battery(Level::Critical)),
+            #[cfg(feature = "marker-input")]
+            0x10 | 0x11 => Some(Event::Marker(code == 0x11)),
+            _ => None,
+        }
+    }
+
+    /// The name used in the response to the `events` command.
+    fn write_name(&self, response: &mut impl Write) -> core::fmt::Result {
+        match self {
+            Event::Presence(true) => write!(response, "present"),
+            Event::Presence(false) => write!(response, "gone"),
+            Event::Zone { zone, entered } => {
+                write!(
+                    response,
+                    "zone{}={}",
+                    zone,
+                    if *entered { "in" } else { "out" }
+                )
+            }
+            #[cfg(feature = "alarm-output")]
+            Event::Alarm(asserted) => {
+                write!(response, "alarm={}", if *asserted { "on" } else { "off" })
+            }
+            Event::Ambient(high) => {
+                write!(
+                    response,
+                    "ambient={}",
+                    if *high { "high" } else { "normal" }
+                )
+            }
+            Event::Lens(dirty) => {
+                write!(response, "lens={}", if *dirty { "dirty" } else { "clean" })
+            }
+            Event::Undervoltage(low) => {
+                write!(response, "supply={}", if *low { "low" } else { "ok" })
+            }
+            #[cfg(feature = "battery")]
+            Event::Battery(level) => write!(response, "battery={}", level.name()),
+            #[cfg(feature = "marker-input")]
+            Event::Marker(active) => {
+                write!(response, "marker={}", if *active { "rise" } else { "fall" })
+            }
+        }
+    }
+}
+
+/// The time at which an event happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Timestamp {
+    /// Seconds since the Unix epoch.
+    Utc(u32),
+    /// The lowest 16 bits of the number of the boot & the seconds since it.
+    Uptime { boot: u16, uptime_s: u32 },
+}
+
+/// An entry of the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct EventRecord {
+    /// Sequence number of the entry, wraps around.
+    seq: u8,
+    pub event: Event,
+    pub timestamp: Timestamp,
+}
+
+impl EventRecord {
+    /// Layout: sequence number, kind, boot & time.
+    fn encode(&self) -> [u8; 8] {
+        let (kind, boot, time) = match self.timestamp {
+            Timestamp::Utc(unix_s) => (self.event.code() | KIND_UTC, 0, unix_s),
+            Timestamp::Uptime { boot, uptime_s } => (self.event.code(), boot, uptime_s),
+        };
+        let mut value = [0; 8];
+        value[0] = self.seq;
+        value[1] = kind;
+        value[2..4].copy_from_slice(&boot.to_le_bytes());
+        value[4..].copy_from_slice(&time.to_le_bytes());
+        value
+    }
+
+    fn decode(value: &[u8; 8]) -> Option<Self> {
+        let kind = value[1];
+        let time = u32::from_le_bytes([value[4], value[5], value[6], value[7]]);
+        let timestamp = if kind & KIND_UTC != 0 {
+            Timestamp::Utc(time)
+        } else {
+            Timestamp::Uptime {
+                boot: u16::from_le_bytes([value[2], value[3]]),
+                uptime_s: time,
+            }
+        };
+        Some(Self {
+            seq: value[0],
+            event: Event::from_code(kind & !KIND_UTC)?,
+            timestamp,
+        })
+    }
+}
+
+/// Writes the events to the ring of entries in the EEPROM.
+pub struct EventLog {
+    /// Sequence number of the next entry.
+    next_seq: u8,
+    /// The number of this boot, see [`crate::reset_log`].
+    boot: u32,
+}
+
+impl EventLog {
+    /// Continue the log stored in the EEPROM (if there is one).
+    pub fn open(eeprom: Option<&Eeprom>, flash: &FLASH, boot: u32) -> Self {
+        let next_seq = eeprom
+            .and_then(|eeprom| records(eeprom, flash).last().map(|record| record.seq))
+            .map_or(0, |seq| seq.wrapping_add(1));
+        Self { next_seq, boot }
+    }
+
+    /// Store the event which happened at `uptime_ms` (resp. `utc_ms` if the clock has been set),
+    /// the oldest entry is overwritten.
+    pub fn log(
+        &mut self,
+        eeprom: &mut Eeprom,
+        flash: &mut FLASH,
+        watchdog: &mut IndependentWatchdog,
+        event: Event,
+        uptime_ms: u32,
+        utc_ms: Option<u64>,
+    ) -> Result<(), flash::Error> {
+        let timestamp = match utc_ms {
+            Some(utc_ms) => Timestamp::Utc((utc_ms / 1000) as u32),
+            None => Timestamp::Uptime {
+                boot: self.boot as u16,
+                uptime_s: uptime_ms / 1000,
+            },
+        };
+        let record = EventRecord {
+            seq: self.next_seq,
+            event,
+            timestamp,
+        };
+        eeprom.write(
+            flash,
+            log_key(record.seq),
+            EEPROM_VERSION,
+            &record.encode(),
+            watchdog,
+        )?;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        Ok(())
+    }
+}
+
+/// The logged events, starting with the oldest one.
+pub fn records(eeprom: &Eeprom, flash: &FLASH) -> heapless::Vec<EventRecord, LOG_LEN> {
+    let mut records: heapless::Vec<EventRecord, LOG_LEN> = (0..LOG_LEN as u8)
+        .filter_map(|slot| read(eeprom, flash, slot))
+        .collect();
+    if let Some(newest) = newest(&records) {
+        records.sort_unstable_by_key(|record| Reverse(newest.wrapping_sub(record.seq)));
+    }
+    records
+}
+
+/// Write the response to the `events` command: the number of logged events & the newest `count`
+/// ones (at most [`MAX_RESPONSE_LEN`]) starting with the oldest one.
+pub fn write_response(
+    eeprom: &Eeprom,
+    flash: &FLASH,
+    count: usize,
+    response: &mut impl Write,
+) -> core::fmt::Result {
+    let records = records(eeprom, flash);
+    write!(response, "OK events={} log=", records.len())?;
+    let skip = records.len().saturating_sub(count.min(MAX_RESPONSE_LEN));
+    for (i, record) in records[skip..].iter().enumerate() {
+        if i > 0 {
+            write!(response, ",")?;
+        }
+        record.event.write_name(response)?;
+        match record.timestamp {
+            Timestamp::Utc(unix_s) => write!(response, "@{}", unix_s)?,
+            Timestamp::Uptime { boot, uptime_s } => write!(response, "@b{}+{}s", boot, uptime_s)?,
+        }
+    }
+    write!(response, "\r\n")
+}
+
+/// The entry stored in the key of the slot.
+fn read(eeprom: &Eeprom, flash: &FLASH, slot: u8) -> Option<EventRecord> {
+    eeprom
+        .read_array(flash, log_key(slot), EEPROM_VERSION)
+        .and_then(|value| EventRecord::decode(&value))
+}
+
+/// The sequence number of the newest of the entries. All of them are within [`LOG_LEN`] of it,
+/// thus their distance to any one of them fits into an `i8`.
+fn newest(records: &[EventRecord]) -> Option<u8> {
+    let first = records.first()?.seq;
+    records
+        .iter()
+        .map(|record| record.seq)
+        .max_by_key(|seq| seq.wrapping_sub(first) as i8)
+}
+
+fn log_key(seq: u8) -> Key {
+    eeprom::keys::EVENT_LOG + (seq as usize % LOG_LEN) as Key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zone_events_round_trip() {
+        for event in [
+            Event::Zone {
+                zone: 7,
+                entered: true,
+            },
+            Event::Zone {
+                zone: 0,
+                entered: false,
+            },
+            Event::Lens(true),
+            Event::Undervoltage(true),
+        ] {
+            let record = EventRecord {
+                seq: 3,
+                event,
+                timestamp: Timestamp::Utc(1_700_000_000),
+            };
+            assert_eq!(EventRecord::decode(&record.encode()), Some(record));
+        }
+        let mut name = heapless::String::<16>::new();
+        Event::Zone {
+            zone: 7,
+            entered: true,
+        }
+        .write_name(&mut name)
+        .unwrap();
+        assert_eq!(name, "zone7=in");
+    }
+}
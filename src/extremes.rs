@@ -0,0 +1,88 @@
+//! The minimum & the maximum distance since boot or the latest `stats reset`, e.g. to measure the
+//! clearance under a passing vehicle. They're reported by `stats` & in the response to `status`.
+//!
+//! Only valid measurements count. The distances are filtered with a [`Median3`] so that a single
+//! outlier doesn't become the minimum or the maximum, the first median is taken once three
+//! measurements have been added (the filter doesn't start out with a real distance).
+
+use crate::filter::Median3;
+use crate::telemetry::Measurement;
+use vl53l1x_uld::RangeStatus;
+
+/// Number of measurements after which the filter only contains real distances.
+const FILTER_LEN: u32 = 3;
+
+pub struct Extremes {
+    filter: Median3,
+    /// Number of valid measurements since the reset.
+    count: u32,
+    min_mm: u16,
+    max_mm: u16,
+}
+
+impl Extremes {
+    pub const fn new() -> Self {
+        Self {
+            filter: Median3::new(0),
+            count: 0,
+            min_mm: u16::MAX,
+            max_mm: 0,
+        }
+    }
+
+    /// Start capturing again.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn add(&mut self, measurement: &Measurement) {
+        if measurement.status != RangeStatus::Valid {
+            return;
+        }
+        let distance_mm = self.filter.update(measurement.distance_mm);
+        self.count += 1;
+        if self.count >= FILTER_LEN {
+            self.min_mm = self.min_mm.min(distance_mm);
+            self.max_mm = self.max_mm.max(distance_mm);
+        }
+    }
+
+    /// Number of valid measurements since the reset.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// The minimum & the maximum filtered distance, `None` until enough measurements have been
+    /// added.
+    pub fn range_mm(&self) -> Option<(u16, u16)> {
+        (self.count >= FILTER_LEN).then_some((self.min_mm, self.max_mm))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test_measurement;
+
+    #[test]
+    fn captures_the_filtered_extremes() {
+        let mut extremes = Extremes::new();
+        for distance_mm in [500, 510] {
+            extremes.add(&test_measurement(0, distance_mm));
+        }
+        assert_eq!(extremes.range_mm(), None);
+        // the outlier & the invalid measurement are ignored
+        for distance_mm in [505, 20, 520, 515, 480, 490] {
+            extremes.add(&test_measurement(0, distance_mm));
+        }
+        extremes.add(&Measurement {
+            status: RangeStatus::SignalFailure,
+            ..test_measurement(0, 5)
+        });
+        assert_eq!(extremes.range_mm(), Some((490, 515)));
+        assert_eq!(extremes.count(), 8);
+
+        extremes.reset();
+        assert_eq!((extremes.range_mm(), extremes.count()), (None, 0));
+    }
+}
@@ -0,0 +1,109 @@
+//! The settings of the `fast` [`crate::profile::Profile`]: the shortest timing budget of the
+//! distance mode (15 ms in the short one, 20 ms in the long one) & the shortest inter-measurement
+//! period the sensor accepts with it, about 52 Hz or 42 Hz.
+//!
+//! To sustain that rate the results are received by DMA (see [`crate::i2c_guard`]) & the data
+//! loggers are woken up once per [`crate::data_log::BATCH_LEN`] measurements instead of for each
+//! one. At boot (& when the profile is loaded) the rate is checked against [`config::RATE_HZ`],
+//! the budget of the data ready interrupt & the bandwidth of the UART, see [`check`].
+
+use crate::config::fast as config;
+use crate::settings::TofSettings;
+use vl53l1x_uld::DistanceMode;
+
+/// Time the sensor needs between two measurements on top of the timing budget.
+pub const OVERHEAD_MS: u16 = 4;
+/// Typical length of a measurement frame in bytes, for the load of the UART.
+pub const FRAME_LEN: u32 = 48;
+
+/// The shortest timing budget the sensor accepts in the distance mode.
+pub fn min_timing_budget_ms(distance_mode: DistanceMode) -> u16 {
+    match distance_mode {
+        DistanceMode::Short => 15,
+        DistanceMode::Long => 20,
+    }
+}
+
+/// The fastest settings in the distance mode.
+pub fn settings(distance_mode: DistanceMode) -> TofSettings {
+    let timing_budget_ms = min_timing_budget_ms(distance_mode);
+    TofSettings {
+        distance_mode,
+        timing_budget_ms,
+        inter_measurement_ms: timing_budget_ms + OVERHEAD_MS,
+    }
+}
+
+/// The rate reached with the settings in mHz.
+pub fn rate_mhz(settings: &TofSettings) -> u32 {
+    let period_ms = settings
+        .inter_measurement_ms
+        .max(settings.timing_budget_ms + OVERHEAD_MS);
+    1_000_000 / period_ms as u32
+}
+
+/// The estimated load of the settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    pub rate_mhz: u32,
+    /// Share of the time spent in the data ready interrupt if it takes its whole budget.
+    pub isr_load_percent: u32,
+    /// Share of the bandwidth of the UART taken by the measurement frames.
+    pub uart_load_percent: u32,
+}
+
+impl Report {
+    /// Whether the settings reach [`config::RATE_HZ`] & the estimated loads are below 100%.
+    pub fn is_achievable(&self) -> bool {
+        self.rate_mhz >= config::RATE_HZ * 1_000
+            && self.isr_load_percent < 100
+            && self.uart_load_percent <= 100
+    }
+}
+
+/// Check the settings against the budget of the data ready interrupt & the baud rate of the UART,
+/// each measurement taking `bytes_per_measurement` (e.g. less with the [`crate::batch`]ing).
+pub fn check(
+    settings: &TofSettings,
+    isr_budget_us: u32,
+    baud_rate: u32,
+    bytes_per_measurement: u32,
+) -> Report {
+    let rate_mhz = rate_mhz(settings);
+    let isr_load_percent = (rate_mhz as u64 * isr_budget_us as u64 / 10_000_000) as u32;
+    // 10 bits per byte with the start & the stop bit
+    let bits_per_s = rate_mhz as u64 * bytes_per_measurement as u64 * 10 / 1_000;
+    let uart_load_percent = (bits_per_s * 100 / baud_rate as u64) as u32;
+    Report {
+        rate_mhz,
+        isr_load_percent,
+        uart_load_percent,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_the_fastest_settings() {
+        let short = settings(DistanceMode::Short);
+        assert_eq!(
+            (short.timing_budget_ms, short.inter_measurement_ms),
+            (15, 19)
+        );
+        assert_eq!(rate_mhz(&short), 52_631);
+        let long = settings(DistanceMode::Long);
+        assert_eq!(rate_mhz(&long), 41_666);
+
+        let report = check(&short, 3_000, 115_200, FRAME_LEN);
+        assert_eq!(report.isr_load_percent, 15);
+        assert_eq!(report.uart_load_percent, 21);
+        // a slow UART doesn't keep up without batching
+        assert_eq!(
+            check(&short, 3_000, 9_600, FRAME_LEN).uart_load_percent,
+            263
+        );
+        assert!(!check(&short, 3_000, 9_600, FRAME_LEN).is_achievable());
+    }
+}
@@ -0,0 +1,257 @@
+//! Injection of faults into the access to the TOF sensor (`fault-injection` feature), to
+//! demonstrate & test the retries, the recovery by the [`crate::sensor_supervisor`] & the safe
+//! mode.
+//!
+//! [`FaultyBus`] wraps the bus of the sensor: transfers fail as if they hadn't been acknowledged
+//! (a NAK) & single bits of the data which is read are flipped. [`InterruptFaults`] delays the
+//! handling of the data ready interrupts. The faults are pseudo-random from
+//! [`config::SEED`], thus the same seed results in the same sequence of faults.
+
+use crate::config::fault_injection as config;
+use crate::range_sensor::{ReadShortIndex, WriteShortIndex};
+use vl53l1x_uld::comm::{Read, Write};
+
+/// A xorshift generator of pseudo-random numbers.
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// Whether an event with the probability (in ‰) happens.
+    fn happens(&mut self, permille: u16) -> bool {
+        self.next() % 1000 < permille as u32
+    }
+}
+
+/// An error of a [`FaultyBus`].
+#[derive(Debug)]
+pub enum FaultError<E> {
+    /// The transfer has failed on the bus itself.
+    Bus(E),
+    /// An injected NAK.
+    Nak,
+}
+
+/// A bus to the TOF sensor with injected faults.
+pub struct FaultyBus<B> {
+    bus: B,
+    random: Random,
+    nak_permille: u16,
+    corrupt_permille: u16,
+}
+
+impl<B> FaultyBus<B> {
+    /// Inject the faults as configured at build time.
+    pub fn new(bus: B) -> Self {
+        Self::with(
+            bus,
+            config::SEED,
+            config::NAK_PERMILLE,
+            config::CORRUPT_PERMILLE,
+        )
+    }
+
+    /// Inject NAKs into `nak_permille` ‰ of the transfers & flip a bit in `corrupt_permille` ‰ of
+    /// the successful reads.
+    pub fn with(bus: B, seed: u32, nak_permille: u16, corrupt_permille: u16) -> Self {
+        Self {
+            bus,
+            random: Random(seed),
+            nak_permille,
+            corrupt_permille,
+        }
+    }
+}
+
+impl<B: Write> Write for FaultyBus<B> {
+    type Error = FaultError<B::Error>;
+
+    fn write_registers(
+        &mut self,
+        address: u8,
+        register: [u8; 2],
+        bytes: &[u8],
+    ) -> Result<(), Self::Error> {
+        if self.random.happens(self.nak_permille) {
+            defmt::warn!("fault injection: NAK of a write");
+            return Err(FaultError::Nak);
+        }
+        self.bus
+            .write_registers(address, register, bytes)
+            .map_err(FaultError::Bus)
+    }
+}
+
+impl<B: Read> Read for FaultyBus<B> {
+    type Error = FaultError<B::Error>;
+
+    fn read_registers(
+        &mut self,
+        address: u8,
+        register: [u8; 2],
+        bytes: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        if self.random.happens(self.nak_permille) {
+            defmt::warn!("fault injection: NAK of a read");
+            return Err(FaultError::Nak);
+        }
+        self.bus
+            .read_registers(address, register, bytes)
+            .map_err(FaultError::Bus)?;
+        if !bytes.is_empty() && self.random.happens(self.corrupt_permille) {
+            let bit = self.random.next() as usize % (bytes.len() * 8);
+            defmt::warn!("fault injection: flipping bit {} of a read", bit);
+            bytes[bit / 8] ^= 1 << (bit % 8);
+        }
+        Ok(())
+    }
+}
+
+/// Delays the handling of the data ready interrupts of the TOF sensor.
+pub struct InterruptFaults {
+    random: Random,
+}
+
+impl InterruptFaults {
+    pub const fn new() -> Self {
+        // another sequence than the one of the bus
+        Self {
+            random: Random(config::SEED ^ 0x5555_5555),
+        }
+    }
+
+    /// The delay (up to [`config::MAX_DELAY_MS`]) after which the interrupt is to be handled
+    /// instead, `None` to handle it now.
+    pub fn delay_ms(&mut self) -> Option<u32> {
+        if !self.random.happens(config::DELAY_PERMILLE) {
+            return None;
+        }
+        let delay_ms = 1 + self.random.next() % config::MAX_DELAY_MS;
+        defmt::warn!("fault injection: delaying the interrupt by {} ms", delay_ms);
+        Some(delay_ms)
+    }
+}
+
+impl<B: ReadShortIndex> ReadShortIndex for FaultyBus<B> {
+    type Error = FaultError<B::Error>;
+
+    fn read_short_index(
+        &mut self,
+        address: u8,
+        index: u8,
+        bytes: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        if self.random.happens(self.nak_permille) {
+            defmt::warn!("fault injection: NAK of a read");
+            return Err(FaultError::Nak);
+        }
+        self.bus
+            .read_short_index(address, index, bytes)
+            .map_err(FaultError::Bus)
+    }
+}
+
+impl<B: WriteShortIndex> WriteShortIndex for FaultyBus<B> {
+    type Error = FaultError<B::Error>;
+
+    fn write_short_index(
+        &mut self,
+        address: u8,
+        index: u8,
+        bytes: &[u8],
+    ) -> Result<(), Self::Error> {
+        if self.random.happens(self.nak_permille) {
+            defmt::warn!("fault injection: NAK of a write");
+            return Err(FaultError::Nak);
+        }
+        self.bus
+            .write_short_index(address, index, bytes)
+            .map_err(FaultError::Bus)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tof::{self, SetupError};
+    use core::convert::Infallible;
+    use vl53l1x_uld::VL53L1X;
+
+    /// A bus which reads the same value from all registers.
+    struct Constant(u8);
+
+    impl Write for Constant {
+        type Error = Infallible;
+
+        fn write_registers(&mut self, _: u8, _: [u8; 2], _: &[u8]) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    impl Read for Constant {
+        type Error = Infallible;
+
+        fn read_registers(
+            &mut self,
+            _: u8,
+            _: [u8; 2],
+            bytes: &mut [u8],
+        ) -> Result<(), Infallible> {
+            bytes.fill(self.0);
+            Ok(())
+        }
+    }
+
+    fn reads(bus: &mut FaultyBus<Constant>) -> Vec<Option<[u8; 4]>> {
+        (0..100)
+            .map(|_| {
+                let mut bytes = [0; 4];
+                bus.read_registers(0x29, [0, 0], &mut bytes)
+                    .ok()
+                    .map(|()| bytes)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_faults() {
+        let faults = reads(&mut FaultyBus::with(Constant(0xAA), 42, 100, 100));
+        assert_eq!(
+            faults,
+            reads(&mut FaultyBus::with(Constant(0xAA), 42, 100, 100))
+        );
+        assert!(faults.contains(&None));
+        assert!(faults.contains(&Some([0xAA; 4])));
+        assert_ne!(
+            faults,
+            reads(&mut FaultyBus::with(Constant(0xAA), 43, 100, 100))
+        );
+    }
+
+    #[test]
+    fn corrupt_read_flips_a_bit() {
+        let mut bus = FaultyBus::with(Constant(0xAA), 1, 0, 1000);
+        for bytes in reads(&mut bus) {
+            let flipped: u32 = bytes
+                .unwrap()
+                .iter()
+                .map(|byte| (byte ^ 0xAA).count_ones())
+                .sum();
+            assert_eq!(flipped, 1);
+        }
+    }
+
+    #[test]
+    fn setup_fails_with_naks() {
+        let mut dev = VL53L1X::new(FaultyBus::with(Constant(0), 1, 1000, 0), 0x29);
+        assert!(matches!(
+            tof::setup(&mut dev, None, None),
+            Err(SetupError::Sensor(_))
+        ));
+    }
+}
@@ -0,0 +1,690 @@
+//! A littlefs file system on the external SPI NOR flash (see [`crate::w25q`]), in which the
+//! measurement log, the calibration & the profiles are kept as files.
+//!
+//! littlefs is resilient to a loss of power: all changes are written copy-on-write & only become
+//! visible once they're complete, thus a file always has either its old or its new content. The
+//! erase cycles are spread over the whole file system.
+//!
+//! The files:
+//! - `log/<n>.bin`: the measurements as records (see [`crate::log_record`]), each file is a
+//!   separate chain of records. A new file is started at boot & once the current one has reached
+//!   [`config::LOG_FILE_KB`], the oldest ones are deleted when the file system runs out of space.
+//! - `calibration.txt`: a copy of the calibration of the TOF sensor, written whenever it's stored.
+//! - `profiles/<name>.txt`: a copy of each saved profile.
+//!
+//! The calibration & the profiles are still loaded from the internal [`crate::eeprom`] at boot,
+//! the copies allow reading them with the `cat` command, e.g. to back them up.
+//!
+//! The measurements are collected & appended to the log file in batches of [`BATCH_LEN`], at
+//! least every [`FLUSH_INTERVAL_MS`]. Only the measurements of the current batch are lost if the
+//! power fails.
+
+use crate::calibration_store::CalibrationData;
+use crate::config::file_system as config;
+use crate::data_log::{LogCommand, OutputLine};
+use crate::log_record::{Chain, Decoded, Dump, Record, MAX_RECORD_LEN};
+use crate::profile::Profile;
+use crate::settings::TofSettings;
+use crate::telemetry::Measurement;
+use crate::w25q::{W25q, SECTOR_LEN};
+use core::fmt::Write;
+use littlefs2::consts::{U2, U256};
+use littlefs2::fs::{Allocation, Filesystem};
+use littlefs2::io::{self, OpenSeekFrom, Write as _};
+use littlefs2::path;
+use littlefs2::path::{Path, PathBuf};
+use vl53l1x_uld::DistanceMode;
+
+/// Number of measurements which are appended to the log file at once.
+const BATCH_LEN: usize = 12;
+/// Interval at which the collected measurements are appended to the log file.
+const FLUSH_INTERVAL_MS: u32 = 5_000;
+/// Number of bytes which are read at once by the `dump` command.
+const DUMP_CHUNK_LEN: usize = 256;
+/// Number of bytes of a file which are sent per line by the `cat` command.
+const CAT_CHUNK_LEN: usize = 48;
+const LOG_DIR: &Path = path!("log");
+const PROFILES_DIR: &Path = path!("profiles");
+
+/// Maximum length of the path of a file command.
+pub const MAX_PATH_LEN: usize = 48;
+/// Maximum length of a file written by [`FileSystem::write_file`].
+pub const MAX_FILE_LEN: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    /// The flash is smaller than [`config::SIZE_KB`].
+    TooSmall,
+    /// An error code of littlefs.
+    FileSystem(i32),
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::FileSystem(error.code())
+    }
+}
+
+/// A path given to a file command. It's stored inline so that the commands can be copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilePath {
+    bytes: [u8; MAX_PATH_LEN],
+    len: u8,
+}
+
+impl FilePath {
+    pub const ROOT: Self = {
+        let mut bytes = [0; MAX_PATH_LEN];
+        bytes[0] = b'/';
+        Self { bytes, len: 1 }
+    };
+
+    /// `None` if the path is too long or contains anything but printable ASCII.
+    pub fn new(path: &str) -> Option<Self> {
+        if path.is_empty()
+            || path.len() > MAX_PATH_LEN
+            || !path.bytes().all(|byte| byte.is_ascii_graphic())
+        {
+            return None;
+        }
+        let mut bytes = [0; MAX_PATH_LEN];
+        bytes[..path.len()].copy_from_slice(path.as_bytes());
+        Some(Self {
+            bytes,
+            len: path.len() as u8,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+
+    fn to_path(self) -> io::Result<PathBuf> {
+        PathBuf::try_from(self.as_str()).map_err(|_| io::Error::INVALID)
+    }
+}
+
+impl defmt::Format for FilePath {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{=str}", self.as_str())
+    }
+}
+
+/// A file which is written on behalf of another task.
+pub struct FileWrite {
+    pub path: heapless::String<MAX_PATH_LEN>,
+    pub content: heapless::String<MAX_FILE_LEN>,
+}
+
+impl FileWrite {
+    /// The copy of the calibration.
+    pub fn calibration(calibration: &CalibrationData) -> Self {
+        let mut file = Self {
+            path: heapless::String::new(),
+            content: heapless::String::new(),
+        };
+        file.path.push_str("calibration.txt").ok();
+        write!(
+            file.content,
+            "offset_mm={}\ncross_talk_cps={}\n",
+            calibration.offset_mm, calibration.cross_talk_cps
+        )
+        .ok();
+        file
+    }
+
+    /// The copy of a saved profile.
+    pub fn profile(profile: Profile, settings: &TofSettings) -> Self {
+        let mut file = Self {
+            path: heapless::String::new(),
+            content: heapless::String::new(),
+        };
+        write!(file.path, "profiles/{}.txt", profile.name()).ok();
+        let distance_mode = match settings.distance_mode {
+            DistanceMode::Short => "short",
+            DistanceMode::Long => "long",
+        };
+        write!(
+            file.content,
+            "distance_mode={}\ntiming_budget_ms={}\ninter_measurement_ms={}\n",
+            distance_mode, settings.timing_budget_ms, settings.inter_measurement_ms
+        )
+        .ok();
+        file
+    }
+}
+
+/// The flash as storage of littlefs.
+pub struct FlashStorage {
+    flash: W25q,
+}
+
+impl FlashStorage {
+    pub fn new(flash: W25q) -> Result<Self, Error> {
+        if flash.capacity() < config::SIZE_KB * 1024 {
+            return Err(Error::TooSmall);
+        }
+        Ok(Self { flash })
+    }
+}
+
+impl littlefs2::driver::Storage for FlashStorage {
+    const READ_SIZE: usize = 16;
+    const WRITE_SIZE: usize = 16;
+    const BLOCK_SIZE: usize = SECTOR_LEN as usize;
+    const BLOCK_COUNT: usize = (config::SIZE_KB * 1024 / SECTOR_LEN) as usize;
+    /// The metadata is moved to another block after this many erase cycles.
+    const BLOCK_CYCLES: isize = 500;
+    type CACHE_SIZE = U256;
+    /// In multiples of 64 blocks which are searched at once for free ones.
+    type LOOKAHEAD_SIZE = U2;
+
+    fn read(&mut self, off: usize, buf: &mut [u8]) -> io::Result<usize> {
+        self.flash
+            .read(off as u32, buf)
+            .map_err(|_| io::Error::IO)?;
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, off: usize, data: &[u8]) -> io::Result<usize> {
+        self.flash
+            .write(off as u32, data)
+            .map_err(|_| io::Error::IO)?;
+        Ok(data.len())
+    }
+
+    fn erase(&mut self, off: usize, len: usize) -> io::Result<usize> {
+        for address in (off..off + len).step_by(Self::BLOCK_SIZE) {
+            self.flash
+                .erase(address as u32)
+                .map_err(|_| io::Error::IO)?;
+        }
+        Ok(len)
+    }
+}
+
+/// A command which is being run, see [`FileSystem::next_line`].
+enum Job {
+    Dump(DumpCursor),
+    List {
+        path: FilePath,
+        entries: u32,
+    },
+    Show {
+        path: FilePath,
+        offset: u32,
+        newline: bool,
+    },
+    Remove(FilePath),
+}
+
+/// Position of a running dump.
+struct DumpCursor {
+    file: u32,
+    /// Offset in the file after the records which have been read.
+    offset: u32,
+    /// Number of records which have been dumped.
+    count: u32,
+    chain: Chain,
+}
+
+pub struct FileSystem {
+    fs: Filesystem<'static, FlashStorage>,
+    /// Measurements which still have to be appended to the log file.
+    batch: heapless::Vec<Record, BATCH_LEN>,
+    /// The chain of the records of the current log file.
+    chain: Chain,
+    /// Time at which the batch has been appended the last time.
+    flushed_ms: u32,
+    /// Number of the oldest log file.
+    first_file: u32,
+    /// Number of the log file which is being written.
+    current_file: u32,
+    /// Size of the current log file.
+    current_len: u32,
+    job: Option<Job>,
+    /// Records which have been read by the dump but not sent yet, starting at `dump_pos`. They're
+    /// kept outside of the [`Job`] as they're much larger than the other jobs.
+    dump_records: heapless::Vec<u8, DUMP_CHUNK_LEN>,
+    dump_pos: usize,
+    /// Set by the `shutdown` command.
+    stopped: bool,
+}
+
+// `Filesystem` isn't `Send` as littlefs keeps raw pointers to the allocation & the storage. Both
+// are `'static` & the file system is only used by the task to which it's moved from `init`.
+#[allow(unsafe_code)]
+unsafe impl Send for FileSystem {}
+
+impl FileSystem {
+    /// Mount the file system & find the log files. A flash without a file system is formatted.
+    pub fn mount(
+        storage: &'static mut FlashStorage,
+        alloc: &'static mut Allocation<FlashStorage>,
+    ) -> Result<Self, Error> {
+        if !Filesystem::is_mountable(storage) {
+            defmt::info!("file system: none found, formatting the flash");
+            Filesystem::format(storage)?;
+        }
+        let mut file_system = Self {
+            fs: Filesystem::mount(alloc, storage)?,
+            batch: heapless::Vec::new(),
+            chain: Chain::new(),
+            flushed_ms: 0,
+            first_file: 0,
+            current_file: 0,
+            current_len: 0,
+            job: None,
+            dump_records: heapless::Vec::new(),
+            dump_pos: 0,
+            stopped: false,
+        };
+        file_system.fs.create_dir_all(PROFILES_DIR)?;
+        file_system.scan_log()?;
+        defmt::info!(
+            "file system: {} KiB free, logging to file {}",
+            file_system.fs.available_space()? / 1024,
+            file_system.current_file
+        );
+        Ok(file_system)
+    }
+
+    /// Collect a measurement, it's appended to the log file with the rest of the batch.
+    pub fn log(&mut self, measurement: &Measurement) {
+        if self.stopped {
+            return;
+        }
+        self.batch.push(measurement.into()).ok();
+        if self.batch.is_full() {
+            self.flush().ok();
+        }
+    }
+
+    /// Append the collected measurements regularly. Must be called periodically.
+    pub fn tick(&mut self, now_ms: u32) {
+        if now_ms.wrapping_sub(self.flushed_ms) >= FLUSH_INTERVAL_MS {
+            self.flushed_ms = now_ms;
+            self.flush().ok();
+        }
+    }
+
+    /// Append the collected measurements, nothing is logged anymore afterwards.
+    pub fn shutdown(&mut self) -> Result<(), Error> {
+        self.flush()?;
+        self.stopped = true;
+        Ok(())
+    }
+
+    /// Write a whole file, replacing its previous content.
+    pub fn write_file(&mut self, file: &FileWrite) {
+        let result = PathBuf::try_from(file.path.as_str())
+            .map_err(|_| io::Error::INVALID)
+            .and_then(|path| self.fs.write(&path, file.content.as_bytes()));
+        match result {
+            Ok(()) => defmt::info!("file system: wrote {=str}", file.path.as_str()),
+            Err(e) => defmt::error!(
+                "file system: failed to write {=str}: {}",
+                file.path.as_str(),
+                e.code()
+            ),
+        }
+    }
+
+    /// Start running a command, its output is fetched with [`Self::next_line`].
+    pub fn start(&mut self, command: LogCommand) {
+        self.job = Some(match command {
+            LogCommand::Dump | LogCommand::CompressedDump => self.start_dump(),
+            // the transferred file is the output of the dump
+            #[cfg(feature = "xmodem")]
+            LogCommand::Transfer(_) => self.start_dump(),
+            LogCommand::List(path) => Job::List { path, entries: 0 },
+            LogCommand::Show(path) => Job::Show {
+                path,
+                offset: 0,
+                newline: true,
+            },
+            LogCommand::Remove(path) => Job::Remove(path),
+        });
+    }
+
+    fn start_dump(&mut self) -> Job {
+        // the dump includes the measurements which haven't been appended yet
+        self.flush().ok();
+        self.dump_records.clear();
+        self.dump_pos = 0;
+        Job::Dump(DumpCursor {
+            file: self.first_file,
+            offset: 0,
+            count: 0,
+            chain: Chain::new(),
+        })
+    }
+
+    #[cfg(feature = "xmodem")]
+    pub fn stop(&mut self) {
+        self.job = None;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.job.is_some()
+    }
+
+    /// The next line of the output of the running command. It's stopped once the final response
+    /// (`OK ...` or `ERR ...`) has been returned.
+    pub fn next_line(&mut self) -> OutputLine {
+        let mut line = OutputLine::new();
+        let Some(mut job) = self.job.take() else {
+            return line;
+        };
+        let result = match &mut job {
+            Job::Dump(cursor) => self.dump_record(cursor).map(|record| match record {
+                Some(record) => {
+                    line = record.line();
+                    true
+                }
+                None => {
+                    write!(line, "OK records={}\r\n", cursor.count).ok();
+                    false
+                }
+            }),
+            Job::List { path, entries } => self.list_next(*path, entries, &mut line),
+            Job::Show {
+                path,
+                offset,
+                newline,
+            } => self.show_next(*path, offset, newline, &mut line),
+            Job::Remove(path) => self.remove(*path, &mut line),
+        };
+        match result {
+            Ok(true) => self.job = Some(job),
+            Ok(false) => {}
+            Err(e) => {
+                defmt::warn!("file system: command failed: {}", e.code());
+                line.clear();
+                write!(line, "ERR {}\r\n", error_text(e)).ok();
+            }
+        }
+        line
+    }
+
+    /// The next step of the running dump, for the records of a [`LogCommand::CompressedDump`]
+    /// (its lines aren't used). It's stopped once it's done.
+    pub fn dump_next(&mut self) -> io::Result<Dump> {
+        let Some(mut job) = self.job.take() else {
+            return Ok(Dump::Done { records: 0 });
+        };
+        let Job::Dump(cursor) = &mut job else {
+            self.job = Some(job);
+            return Ok(Dump::Done { records: 0 });
+        };
+        match self.dump_record(cursor)? {
+            Some(record) => {
+                self.job = Some(job);
+                Ok(Dump::Record(record))
+            }
+            None => Ok(Dump::Done {
+                records: cursor.count,
+            }),
+        }
+    }
+
+    /// The next record of the dump, `None` once it has caught up with the log.
+    fn dump_record(&mut self, cursor: &mut DumpCursor) -> io::Result<Option<Record>> {
+        loop {
+            let records = self.dump_records.get(self.dump_pos..).unwrap_or_default();
+            match cursor.chain.decode(records) {
+                Decoded::Record(record, len) => {
+                    self.dump_pos += len;
+                    cursor.count += 1;
+                    return Ok(Some(record));
+                }
+                Decoded::Corrupt(len) => {
+                    self.dump_pos += len;
+                    continue;
+                }
+                // the rest of the file can't be decoded
+                Decoded::End => {
+                    self.dump_from(cursor, cursor.file + 1);
+                    continue;
+                }
+                Decoded::Incomplete => {}
+            }
+            if cursor.file < self.first_file {
+                // the file has been deleted in the meantime, continue with the now oldest one
+                self.dump_from(cursor, self.first_file);
+            }
+            if cursor.file > self.current_file
+                || (cursor.file == self.current_file && cursor.offset >= self.current_len)
+            {
+                return Ok(None);
+            }
+            // an incomplete record is read again from its start
+            let pending = self.dump_records.len() - self.dump_pos;
+            let offset = cursor.offset - pending as u32;
+            let result = self
+                .fs
+                .read_chunk(&log_path(cursor.file), OpenSeekFrom::Start(offset));
+            match result {
+                Ok((records, _)) if records.len() > pending => {
+                    cursor.offset = offset + records.len() as u32;
+                    self.dump_records = records;
+                    self.dump_pos = 0;
+                }
+                Ok(_) => self.dump_from(cursor, cursor.file + 1),
+                Err(e) if e == io::Error::NO_SUCH_ENTRY => self.dump_from(cursor, cursor.file + 1),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Continue the dump at the start of the log file.
+    fn dump_from(&mut self, cursor: &mut DumpCursor, file: u32) {
+        cursor.file = file;
+        cursor.offset = 0;
+        cursor.chain = Chain::new();
+        self.dump_records.clear();
+        self.dump_pos = 0;
+    }
+
+    /// Returns whether the listing continues. The directory is read again for each entry so that
+    /// it doesn't need to stay open.
+    fn list_next(
+        &mut self,
+        path: FilePath,
+        entries: &mut u32,
+        line: &mut OutputLine,
+    ) -> io::Result<bool> {
+        let entry = self.fs.read_dir_and_then(&path.to_path()?, |dir| {
+            dir.filter(|entry| {
+                !entry
+                    .as_ref()
+                    .is_ok_and(|entry| matches!(entry.file_name().as_str(), "." | ".."))
+            })
+            .nth(*entries as usize)
+            .transpose()
+        })?;
+        let Some(entry) = entry else {
+            write!(line, "OK entries={}\r\n", entries).ok();
+            return Ok(false);
+        };
+        *entries += 1;
+        let name = entry.file_name().as_str();
+        if entry.metadata().is_dir() {
+            write!(line, "D,{}\r\n", name).ok();
+        } else {
+            write!(line, "F,{},{}\r\n", name, entry.metadata().len()).ok();
+        }
+        Ok(true)
+    }
+
+    /// Returns whether the output continues. Bytes which aren't printable ASCII are replaced by
+    /// `.`, e.g. those of the log files (use the `dump` command instead).
+    fn show_next(
+        &mut self,
+        path: FilePath,
+        offset: &mut u32,
+        newline: &mut bool,
+        line: &mut OutputLine,
+    ) -> io::Result<bool> {
+        let (chunk, _) = self
+            .fs
+            .read_chunk::<CAT_CHUNK_LEN>(&path.to_path()?, OpenSeekFrom::Start(*offset))?;
+        if chunk.is_empty() {
+            // the response always starts on a new line
+            if !*newline {
+                line.push_str("\r\n").ok();
+            }
+            write!(line, "OK bytes={}\r\n", offset).ok();
+            return Ok(false);
+        }
+        *offset += chunk.len() as u32;
+        *newline = chunk.last() == Some(&b'\n');
+        for byte in chunk {
+            let printable = byte.is_ascii_graphic() || matches!(byte, b' ' | b'\t' | b'\r' | b'\n');
+            line.push(if printable { byte as char } else { '.' }).ok();
+        }
+        Ok(true)
+    }
+
+    fn remove(&mut self, path: FilePath, line: &mut OutputLine) -> io::Result<bool> {
+        self.fs.remove(&path.to_path()?)?;
+        defmt::info!("file system: removed {}", path);
+        // a log file might have been removed
+        self.scan_log()?;
+        line.push_str("OK\r\n").ok();
+        Ok(false)
+    }
+
+    /// Append the collected measurements to the log file. They're dropped if that fails, so that
+    /// a broken flash doesn't stop the logging of the following ones.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let result = self.append_batch();
+        if let Err(e) = result {
+            defmt::error!(
+                "file system: dropping {} measurements: {}",
+                self.batch.len(),
+                e.code()
+            );
+        }
+        self.batch.clear();
+        result
+    }
+
+    /// The chain only advances once the batch has been appended, so that the file can still be
+    /// decoded if it's dropped.
+    fn append_batch(&mut self) -> io::Result<()> {
+        let mut chain = self.chain;
+        let mut data = encode(&self.batch, &mut chain);
+        if self.current_len > 0 && self.current_len + data.len() as u32 > config::LOG_FILE_KB * 1024
+        {
+            self.current_file += 1;
+            self.current_len = 0;
+            self.chain = Chain::new();
+            chain = Chain::new();
+            data = encode(&self.batch, &mut chain);
+            // keep the space for a whole file free for the next one & for the other files
+            while self.fs.available_space()? < 2 * (config::LOG_FILE_KB * 1024) as usize
+                && self.remove_oldest_log()?
+            {}
+            defmt::debug!("file system: starting log file {}", self.current_file);
+        }
+        let path = log_path(self.current_file);
+        let mut result = self.append(&path, &data);
+        if result == Err(io::Error::NO_SPACE) && self.remove_oldest_log()? {
+            result = self.append(&path, &data);
+        }
+        result?;
+        self.current_len += data.len() as u32;
+        self.chain = chain;
+        Ok(())
+    }
+
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.fs.open_file_with_options_and_then(
+            |options| options.write(true).create(true).append(true),
+            path,
+            |file| file.write_all(data),
+        )
+    }
+
+    /// Delete the oldest log file. Returns `false` if only the current one is left.
+    fn remove_oldest_log(&mut self) -> io::Result<bool> {
+        if self.first_file >= self.current_file {
+            return Ok(false);
+        }
+        match self.fs.remove(&log_path(self.first_file)) {
+            Err(e) if e != io::Error::NO_SUCH_ENTRY => return Err(e),
+            _ => defmt::info!("file system: deleted log file {}", self.first_file),
+        }
+        self.first_file += 1;
+        Ok(true)
+    }
+
+    /// Find the oldest & the newest log file. As the chain of the newest one isn't known anymore,
+    /// the log continues in a new file.
+    fn scan_log(&mut self) -> io::Result<()> {
+        self.fs.create_dir_all(LOG_DIR)?;
+        let files = self.fs.read_dir_and_then(LOG_DIR, |dir| {
+            let mut files: Option<(u32, u32)> = None;
+            for entry in dir {
+                if let Some(index) = parse_index(entry?.file_name().as_str()) {
+                    files = Some(files.map_or((index, index), |(first, last)| {
+                        (first.min(index), last.max(index))
+                    }));
+                }
+            }
+            Ok(files)
+        })?;
+        (self.first_file, self.current_file) = files.unwrap_or((0, 0));
+        self.current_len = match self.fs.metadata(&log_path(self.current_file)) {
+            Ok(metadata) => metadata.len() as u32,
+            Err(e) if e == io::Error::NO_SUCH_ENTRY => 0,
+            Err(e) => return Err(e),
+        };
+        if self.current_len > 0 {
+            self.current_file += 1;
+            self.current_len = 0;
+        }
+        self.chain = Chain::new();
+        Ok(())
+    }
+}
+
+/// Encode the records as a continuation of the chain.
+fn encode(
+    records: &[Record],
+    chain: &mut Chain,
+) -> heapless::Vec<u8, { BATCH_LEN * MAX_RECORD_LEN }> {
+    let mut data = heapless::Vec::new();
+    for record in records {
+        data.extend_from_slice(&chain.encode(record)).ok();
+    }
+    data
+}
+
+fn log_path(index: u32) -> PathBuf {
+    let mut path = heapless::String::<20>::new();
+    write!(path, "log/{:06}.bin", index).ok();
+    PathBuf::try_from(path.as_str()).unwrap_or_default()
+}
+
+/// The number of a log file, `None` if it's another file.
+fn parse_index(name: &str) -> Option<u32> {
+    name.strip_suffix(".bin")?.parse().ok()
+}
+
+/// Human readable description of an error, used in the `ERR` response.
+fn error_text(error: io::Error) -> &'static str {
+    match error {
+        e if e == io::Error::NO_SUCH_ENTRY => "not found",
+        e if e == io::Error::PATH_IS_DIR => "is a directory",
+        e if e == io::Error::PATH_NOT_DIR => "not a directory",
+        e if e == io::Error::DIR_NOT_EMPTY => "directory not empty",
+        e if e == io::Error::INVALID => "invalid path",
+        e if e == io::Error::NO_SPACE => "storage full",
+        _ => "storage failed",
+    }
+}
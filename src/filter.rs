@@ -0,0 +1,79 @@
+//! Filters of the measured distance. They don't depend on the hardware, thus they're tested on
+//! the host.
+
+/// A median filter over the last three values, which suppresses single outliers.
+pub struct Median3 {
+    history: [u16; 3],
+}
+
+impl Median3 {
+    /// The history starts out filled with `initial`, e.g. a distance outside of any window.
+    pub const fn new(initial: u16) -> Self {
+        Self {
+            history: [initial; 3],
+        }
+    }
+
+    /// Add a value & return the median of the last three values.
+    pub fn update(&mut self, value: u16) -> u16 {
+        self.history.rotate_left(1);
+        self.history[2] = value;
+        let mut sorted = self.history;
+        sorted.sort_unstable();
+        sorted[1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn median_is_within_the_last_three_values(
+            initial: u16,
+            values in prop::collection::vec(any::<u16>(), 3..100),
+        ) {
+            let mut filter = Median3::new(initial);
+            for (i, &value) in values.iter().enumerate() {
+                let median = filter.update(value);
+                if i >= 2 {
+                    let window = &values[i - 2..=i];
+                    prop_assert!(window.contains(&median));
+                    prop_assert!(*window.iter().min().unwrap() <= median);
+                    prop_assert!(median <= *window.iter().max().unwrap());
+                }
+            }
+        }
+
+        #[test]
+        fn step_response_is_monotone(from: u16, to: u16, settled in 2..10usize) {
+            let mut filter = Median3::new(from);
+            for _ in 0..settled {
+                filter.update(from);
+            }
+            let mut previous = from;
+            for i in 0..5 {
+                let median = filter.update(to);
+                if to >= from {
+                    prop_assert!(previous <= median && median <= to);
+                } else {
+                    prop_assert!(to <= median && median <= previous);
+                }
+                // the step passes the filter with a delay of one value
+                prop_assert_eq!(median == to, i >= 1 || from == to);
+                previous = median;
+            }
+        }
+
+        #[test]
+        fn single_outliers_are_suppressed(level: u16, outlier: u16, position in 3..20usize) {
+            let mut filter = Median3::new(level);
+            for i in 0..25 {
+                let value = if i == position { outlier } else { level };
+                prop_assert_eq!(filter.update(value), level);
+            }
+        }
+    }
+}
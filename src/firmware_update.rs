@@ -0,0 +1,442 @@
+//! In-application update of the firmware over the virtual COM port, with two slots so that a
+//! broken firmware is rolled back.
+//!
+//! The firmware runs from slot A (the sectors 0 - 4 at `0x0800_0000`) or slot B (sector 5 at
+//! `0x0802_0000`) of 128 KiB each, it's linked for one of them (see `memory.x`). Slot A holds the
+//! boot firmware, which is flashed with a debug probe (or the ROM bootloader) & is never erased by
+//! an update: the `update` command only receives a signed image (see `tof-host sign`) built for
+//! slot B with YMODEM. Once its CRC has been verified the image is marked as pending in the
+//! [`crate::eeprom`] & the board resets. The firmware in slot B can't erase itself, it resets into
+//! slot A for the `update` instead (see [`Error::InSlotB`]).
+//!
+//! The hardware always starts the firmware in slot A, which selects the slot at boot: it resets
+//! into slot B (marked in a backup register of the [`crate::clock`]) if that's the confirmed one or
+//! the pending one & its vectors are valid. The pending firmware is tried once: unless it confirms
+//! within [`crate::config::firmware_update::CONFIRM_TIMEOUT_S`] that it's healthy (see
+//! [`confirm`]), e.g. because it resets, it hangs until the watchdog resets it or it enters the
+//! safe mode, the next boot stays in slot A. A loss of power during an update leaves an invalid
+//! slot B, thus the board stays in slot A as well.
+
+use crate::eeprom::{self, Eeprom};
+use crate::image_check::ImageState;
+use crate::storage;
+use crate::xmodem::{self, Receiver};
+use core::fmt::Write;
+use core::ops::{Range, RangeInclusive};
+use cortex_m::peripheral::{NVIC, SCB};
+use stm32f4xx_hal::crc32::Crc32;
+use stm32f4xx_hal::flash::{self, FlashExt};
+use stm32f4xx_hal::pac::FLASH;
+use stm32f4xx_hal::watchdog::IndependentWatchdog;
+
+/// Interval in which the transfer is continued.
+pub const POLL_INTERVAL_MS: u32 = 10;
+/// Version of the format in which the boot state is stored.
+const EEPROM_VERSION: u8 = 2;
+/// Range of the initial stack pointer of a valid image: the RAM.
+const RAM: Range<u32> = 0x2000_0000..0x2001_8001;
+/// Start of the flash.
+const FLASH_START: u32 = 0x0800_0000;
+/// Length of each slot.
+const SLOT_LEN: usize = 128 * 1024;
+
+extern "C" {
+    // provided by the linker script of cortex-m-rt
+    static __vector_table: u32;
+}
+
+/// The two slots from which the firmware can run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Slot {
+    /// The sectors 0 - 4, started by the hardware & never updated.
+    A,
+    /// Sector 5, the target of the updates.
+    B,
+}
+
+impl Slot {
+    /// The slot for which the running firmware has been linked.
+    pub fn running() -> Self {
+        let start = core::ptr::addr_of!(__vector_table) as u32;
+        if Slot::B.range().contains(&start) {
+            Slot::B
+        } else {
+            Slot::A
+        }
+    }
+
+    /// The name used in the responses.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Slot::A => "a",
+            Slot::B => "b",
+        }
+    }
+
+    /// The addresses of the slot.
+    fn range(self) -> Range<u32> {
+        let start = FLASH_START + self.offset() as u32;
+        start..start + SLOT_LEN as u32
+    }
+
+    /// Offset of the slot from the start of the flash.
+    fn offset(self) -> usize {
+        match self {
+            Slot::A => 0,
+            Slot::B => storage::sector_offset(5),
+        }
+    }
+
+    /// The sectors of the flash which make up the slot.
+    fn sectors(self) -> RangeInclusive<u8> {
+        match self {
+            Slot::A => 0..=4,
+            Slot::B => 5..=5,
+        }
+    }
+}
+
+/// The state of the firmware in slot B while it isn't confirmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum Trial {
+    None = 0,
+    /// A new firmware has been received & boots next.
+    Pending = 1,
+    /// The new firmware has been booted but hasn't been confirmed yet.
+    Started = 2,
+}
+
+/// Which slot boots, stored in the EEPROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+struct BootState {
+    /// The slot with the confirmed firmware, slot A if slot B doesn't contain one.
+    active: Slot,
+    trial: Trial,
+}
+
+impl BootState {
+    /// The stored state, slot A without a new firmware if there's none.
+    fn read(eeprom: &Eeprom, flash: &FLASH) -> Self {
+        let value = eeprom.read_array(flash, eeprom::keys::FIRMWARE_UPDATE, EEPROM_VERSION);
+        let (active, trial) = match value {
+            Some([active, trial]) => (active, trial),
+            _ => (0, 0),
+        };
+        Self {
+            active: if active == 1 { Slot::B } else { Slot::A },
+            trial: match trial {
+                1 => Trial::Pending,
+                2 => Trial::Started,
+                _ => Trial::None,
+            },
+        }
+    }
+
+    fn write(
+        &self,
+        eeprom: &mut Eeprom,
+        flash: &mut FLASH,
+        watchdog: &mut IndependentWatchdog,
+    ) -> Result<(), flash::Error> {
+        eeprom.write(
+            flash,
+            eeprom::keys::FIRMWARE_UPDATE,
+            EEPROM_VERSION,
+            &[self.active as u8, self.trial as u8],
+            watchdog,
+        )
+    }
+}
+
+/// Why the received image isn't booted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    Transfer(xmodem::Error),
+    /// The image isn't a signed firmware for this board or its CRC doesn't match.
+    InvalidImage,
+    /// The image hasn't been linked for the slot.
+    WrongSlot(Slot),
+    /// The running firmware in slot B hasn't been confirmed yet.
+    Unconfirmed,
+    /// The firmware runs from slot B, which is updated: the board has to reset into slot A (see
+    /// [`crate::clock::Clock::request_slot_a`]) & start the update again.
+    InSlotB,
+    /// The EEPROM isn't available or the slot couldn't be written.
+    Storage,
+}
+
+impl Error {
+    /// Human readable description, used in the `ERR` response.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::Transfer(e) => e.as_str(),
+            Error::InvalidImage => "invalid image",
+            Error::WrongSlot(Slot::A) => "image not built for slot a",
+            Error::WrongSlot(Slot::B) => "image not built for slot b",
+            Error::Unconfirmed => "firmware not confirmed yet",
+            Error::InSlotB => "running from slot b, restarting into slot a, repeat the update",
+            Error::Storage => "storage failed",
+        }
+    }
+}
+
+/// A running update.
+pub struct FirmwareUpdate {
+    crc: Crc32,
+    /// `None` if no update is running.
+    receiver: Option<Receiver>,
+}
+
+impl FirmwareUpdate {
+    pub fn new(crc: Crc32) -> Self {
+        Self {
+            crc,
+            receiver: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.receiver.is_some()
+    }
+
+    /// Erase slot B & wait for the sender, the transfer is continued by [`Self::poll`]. Slot A is
+    /// never erased, the firmware in it selects the slot at boot.
+    pub fn start(
+        &mut self,
+        flash: &mut FLASH,
+        eeprom: Option<&mut Eeprom>,
+        watchdog: &mut IndependentWatchdog,
+    ) -> Result<(), Error> {
+        let eeprom = eeprom.ok_or(Error::Storage)?;
+        let state = BootState::read(eeprom, flash);
+        if Slot::running() == Slot::B {
+            return Err(if state.trial == Trial::None {
+                Error::InSlotB
+            } else {
+                Error::Unconfirmed
+            });
+        }
+        // the slot must not be booted while it's incomplete
+        let erased = BootState {
+            active: Slot::A,
+            trial: Trial::None,
+        };
+        if state != erased {
+            erased
+                .write(eeprom, flash, watchdog)
+                .map_err(|_| Error::Storage)?;
+        }
+        for sector in Slot::B.sectors() {
+            storage::erase_sector(flash, sector, watchdog).map_err(|e| {
+                defmt::error!(
+                    "firmware update: failed to erase slot b: {}",
+                    defmt::Debug2Format(&e)
+                );
+                Error::Storage
+            })?;
+        }
+        defmt::info!("firmware update: waiting for the image for slot b");
+        self.receiver = Some(Receiver::new(SLOT_LEN as u32));
+        Ok(())
+    }
+
+    /// Continue the transfer with the bytes received from the host, `write` queues the bytes for
+    /// the host & returns how many it took. Returns the result once the transfer has finished, the
+    /// received image is then pending if it's valid.
+    pub fn poll(
+        &mut self,
+        now_ms: u32,
+        received: &[u8],
+        flash: &mut FLASH,
+        eeprom: Option<&mut Eeprom>,
+        watchdog: &mut IndependentWatchdog,
+        mut write: impl FnMut(&[u8]) -> usize,
+    ) -> Option<Result<u32, Error>> {
+        let receiver = self.receiver.as_mut()?;
+        receiver.poll(now_ms, received, &mut |offset, data| {
+            storage::program(flash, Slot::B.offset() + offset as usize, data).is_ok()
+        });
+        let sent = write(receiver.pending());
+        receiver.advance(sent);
+        let result = receiver.result()?;
+        self.receiver = None;
+        Some(self.finish(result, flash, eeprom, watchdog))
+    }
+
+    /// Verify the received image & mark it as pending.
+    fn finish(
+        &mut self,
+        result: Result<u32, xmodem::Error>,
+        flash: &mut FLASH,
+        eeprom: Option<&mut Eeprom>,
+        watchdog: &mut IndependentWatchdog,
+    ) -> Result<u32, Error> {
+        let len = result.map_err(Error::Transfer)?;
+        let image = image(flash, Slot::B, len).ok_or(Error::InvalidImage)?;
+        verify(image, Slot::B, &mut self.crc)?;
+        let eeprom = eeprom.ok_or(Error::Storage)?;
+        BootState {
+            active: Slot::A,
+            trial: Trial::Pending,
+        }
+        .write(eeprom, flash, watchdog)
+        .map_err(|e| {
+            defmt::error!(
+                "firmware update: failed to mark the image as pending: {}",
+                defmt::Debug2Format(&e)
+            );
+            Error::Storage
+        })?;
+        defmt::info!("firmware update: {} bytes received, pending in slot b", len);
+        Ok(len)
+    }
+}
+
+/// Write the response to a finished update.
+pub fn write_response(result: Result<u32, Error>, response: &mut impl Write) -> core::fmt::Result {
+    match result {
+        Ok(len) => write!(
+            response,
+            "OK update size={} slot={} rebooting\r\n",
+            len,
+            Slot::B.name()
+        ),
+        Err(e) => write!(response, "ERR {}\r\n", e.as_str()),
+    }
+}
+
+/// Select the slot which boots, must be called at boot once the EEPROM has been opened. Resets
+/// into slot B if it has to boot (see [`jump_to_slot_b`]) unless slot A has been requested for an
+/// update, otherwise returns whether the running firmware is on trial & has to [`confirm`] that
+/// it's healthy.
+pub fn select_slot(
+    eeprom: &mut Eeprom,
+    flash: &mut FLASH,
+    watchdog: &mut IndependentWatchdog,
+    clock: &mut crate::clock::Clock,
+) -> bool {
+    let running = Slot::running();
+    let state = BootState::read(eeprom, flash);
+    defmt::info!("running from slot {}, boot state: {}", running, state);
+    // only the firmware in slot A is started by the hardware, slot B has already been selected
+    if running == Slot::B {
+        return state.trial == Trial::Started;
+    }
+    if clock.take_slot_a_request() {
+        defmt::info!("staying in slot a for the update");
+        return false;
+    }
+    let mut next = match state.trial {
+        Trial::None => state,
+        Trial::Pending => BootState {
+            trial: Trial::Started,
+            ..state
+        },
+        Trial::Started => {
+            defmt::error!("firmware update: slot b hasn't been confirmed, rolling back");
+            BootState {
+                active: Slot::A,
+                trial: Trial::None,
+            }
+        }
+    };
+    let boot_b = next.active == Slot::B || next.trial == Trial::Started;
+    // the CRC has been verified when the image was received, it's checked again once it runs
+    let valid =
+        image(flash, Slot::B, 8).is_some_and(|image| verify_vectors(image, Slot::B).is_ok());
+    if boot_b && !valid {
+        defmt::error!("firmware update: slot b is invalid, staying in slot a");
+        next = BootState {
+            active: Slot::A,
+            trial: Trial::None,
+        };
+    }
+    if next != state {
+        if let Err(e) = next.write(eeprom, flash, watchdog) {
+            // without the mark a new firmware would be tried forever
+            defmt::error!(
+                "firmware update: failed to store the boot state: {}",
+                defmt::Debug2Format(&e)
+            );
+            return false;
+        }
+    }
+    if boot_b && valid {
+        clock.request_slot_b();
+        defmt::info!("resetting into slot b");
+        SCB::sys_reset();
+    }
+    // the firmware in slot A is never updated, thus never on trial
+    false
+}
+
+/// Mark the running firmware as healthy, it's booted from now on.
+pub fn confirm(
+    eeprom: &mut Eeprom,
+    flash: &mut FLASH,
+    watchdog: &mut IndependentWatchdog,
+) -> Result<(), flash::Error> {
+    BootState {
+        active: Slot::running(),
+        trial: Trial::None,
+    }
+    .write(eeprom, flash, watchdog)?;
+    defmt::info!("firmware update: slot {} confirmed", Slot::running());
+    Ok(())
+}
+
+/// Start the firmware in slot B. Must be called right after the reset (before clocks, interrupts
+/// or the watchdog have been set up) by the firmware in slot A.
+#[allow(unsafe_code)]
+pub fn jump_to_slot_b() -> ! {
+    defmt::info!("jumping to slot b");
+    let nvic = NVIC::PTR;
+    let start = Slot::B.range().start;
+    unsafe {
+        // the firmware in slot B sets up its own interrupts
+        for i in 0..(*nvic).icer.len() {
+            (*nvic).icer[i].write(u32::MAX);
+            (*nvic).icpr[i].write(u32::MAX);
+        }
+        // its interrupts are handled by its vector table
+        (*SCB::PTR).vtor.write(start);
+        // the slot starts with the initial stack pointer & the reset handler of the firmware
+        cortex_m::asm::bootload(start as *const u32)
+    }
+}
+
+/// The first `len` bytes of the slot.
+fn image(flash: &FLASH, slot: Slot, len: u32) -> Option<&[u8]> {
+    let slot = &flash.read()[slot.offset()..slot.offset() + SLOT_LEN];
+    slot.get(..len as usize)
+        .filter(|image| image.len() % 4 == 0)
+}
+
+/// Check that the image is a signed firmware for this board which has been linked for the slot.
+fn verify(image: &[u8], slot: Slot, crc: &mut Crc32) -> Result<(), Error> {
+    verify_vectors(image, slot)?;
+    match ImageState::verify(image, crc) {
+        ImageState::Valid => Ok(()),
+        _ => Err(Error::InvalidImage),
+    }
+}
+
+/// Check that the image starts with the initial stack pointer & the reset handler of a firmware
+/// which has been linked for the slot.
+fn verify_vectors(image: &[u8], slot: Slot) -> Result<(), Error> {
+    let word = |i: usize| {
+        image
+            .get(i * 4..i * 4 + 4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+    };
+    let (Some(stack), Some(reset)) = (word(0), word(1)) else {
+        return Err(Error::InvalidImage);
+    };
+    if !RAM.contains(&stack) {
+        return Err(Error::InvalidImage);
+    }
+    if !slot.range().contains(&reset) {
+        return Err(Error::WrongSlot(slot));
+    }
+    Ok(())
+}
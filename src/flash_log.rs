@@ -0,0 +1,286 @@
+//! Standalone data logging to an external SPI NOR flash, see [`crate::w25q`].
+//!
+//! The log is a ring of sectors at the start of the flash ([`config::SIZE_KB`], by default the
+//! whole flash) which are written one after the other, the oldest sector is erased once all of
+//! them are full. Thus each sector is erased equally often & the latest measurements are kept.
+//!
+//! Each sector starts with a header (a magic value, its sequence number, which increments with each
+//! sector, & the [`DeviceId`] of the board) followed by the records, see [`crate::log_record`].
+//! Each sector is a separate chain of records so that it can be decoded on its own once the older
+//! ones have been erased.
+//!
+//! The sector with the highest sequence number is continued after a reset, records which have
+//! been interrupted by a loss of power are skipped by the `dump` command.
+
+use crate::config::flash_log as config;
+use crate::device_id::DeviceId;
+use crate::log_record::{Chain, Decoded, Dump, Record, MAX_RECORD_LEN};
+use crate::telemetry::Measurement;
+use crate::w25q::{self, W25q, SECTOR_LEN};
+
+/// Marks the header of a sector, `FL` followed by the version of the layout.
+const MAGIC: u32 = u32::from_le_bytes(*b"FL02");
+/// Length of the header of a sector.
+const HEADER_LEN: u32 = 16;
+/// Number of measurements which can be held while a sector is being erased.
+const PENDING_LEN: usize = 16;
+
+pub type Error = w25q::Error;
+
+/// Position of a running dump.
+struct Cursor {
+    sector: u32,
+    /// Offset of the next record in the sector.
+    offset: u32,
+    /// Whether the header of the sector has been checked.
+    checked: bool,
+    /// Number of records which have been dumped.
+    count: u32,
+    chain: Chain,
+}
+
+impl Cursor {
+    fn start_of(sector: u32, count: u32) -> Self {
+        Self {
+            sector,
+            offset: HEADER_LEN,
+            checked: false,
+            count,
+            chain: Chain::new(),
+        }
+    }
+}
+
+pub struct FlashLog {
+    flash: W25q,
+    /// Number of sectors of the log.
+    sectors: u32,
+    /// The sector which is currently being written.
+    head: u32,
+    /// Sequence number of the head sector.
+    head_seq: u32,
+    /// Offset of the next record in the head sector.
+    next_offset: u32,
+    /// The chain of the records of the head sector.
+    chain: Chain,
+    /// Whether the head sector is being erased, its header is written afterwards.
+    erasing: bool,
+    /// Measurements which still have to be written.
+    pending: heapless::Deque<Record, PENDING_LEN>,
+    dump: Option<Cursor>,
+    /// Set by the `shutdown` command.
+    stopped: bool,
+}
+
+impl FlashLog {
+    /// Find the position at which the log continues.
+    pub fn new(mut flash: W25q) -> Result<Self, Error> {
+        let sectors = match config::SIZE_KB * 1024 {
+            0 => flash.capacity(),
+            size => size.min(flash.capacity()),
+        } / SECTOR_LEN;
+        let mut head = None;
+        for sector in 0..sectors {
+            if let Some(seq) = read_header(&mut flash, sector)? {
+                if head.is_none_or(|(_, head_seq)| seq > head_seq) {
+                    head = Some((sector, seq));
+                }
+            }
+        }
+
+        let mut log = Self {
+            flash,
+            sectors,
+            head: 0,
+            head_seq: 0,
+            next_offset: HEADER_LEN,
+            chain: Chain::new(),
+            erasing: false,
+            pending: heapless::Deque::new(),
+            dump: None,
+            stopped: false,
+        };
+        match head {
+            Some((head, head_seq)) => {
+                log.head = head;
+                log.head_seq = head_seq;
+                // the chain is continued after the last record
+                while let Decoded::Record(_, len) | Decoded::Corrupt(len) =
+                    read_record(&mut log.flash, head, log.next_offset, &mut log.chain)?
+                {
+                    log.next_offset += len as u32;
+                }
+            }
+            None => {
+                defmt::info!("flash log: empty, starting a new log");
+                log.start_sector(0)?;
+            }
+        }
+        defmt::info!(
+            "flash log: {} sectors, continuing in sector {} at offset {}",
+            sectors,
+            log.head,
+            log.next_offset
+        );
+        Ok(log)
+    }
+
+    /// Append a measurement. It's written once the current erase operation has finished.
+    pub fn log(&mut self, measurement: &Measurement) {
+        if self.stopped {
+            return;
+        }
+        if self.pending.is_full() {
+            self.pending.pop_front();
+            defmt::warn!("flash log: dropping a measurement");
+        }
+        self.pending.push_back(measurement.into()).ok();
+        if let Err(e) = self.write_pending() {
+            defmt::error!("flash log: failed to write: {}", e);
+        }
+    }
+
+    /// Write the pending measurements once the erase operation has finished. Must be called
+    /// periodically.
+    pub fn tick(&mut self) {
+        if let Err(e) = self.write_pending() {
+            defmt::error!("flash log: failed to write: {}", e);
+        }
+    }
+
+    /// Write all pending measurements, nothing is logged anymore afterwards.
+    pub fn shutdown(&mut self) -> Result<(), Error> {
+        while self.erasing || !self.pending.is_empty() {
+            self.write_pending()?;
+        }
+        self.stopped = true;
+        Ok(())
+    }
+
+    /// Start dumping all records, from the oldest to the newest one.
+    pub fn start_dump(&mut self) {
+        self.dump = Some(Cursor::start_of((self.head + 1) % self.sectors, 0));
+    }
+
+    #[cfg(feature = "xmodem")]
+    pub fn stop_dump(&mut self) {
+        self.dump = None;
+    }
+
+    pub fn is_dumping(&self) -> bool {
+        self.dump.is_some()
+    }
+
+    /// The next step of the dump, it's stopped once it's done or failed.
+    pub fn dump_next(&mut self) -> Result<Dump, Error> {
+        let Some(mut cursor) = self.dump.take() else {
+            return Ok(Dump::Done { records: 0 });
+        };
+        let result = self.advance(&mut cursor);
+        if matches!(result, Ok(Dump::Record(_))) {
+            self.dump = Some(cursor);
+        }
+        result
+    }
+
+    fn advance(&mut self, cursor: &mut Cursor) -> Result<Dump, Error> {
+        loop {
+            if cursor.sector == self.head && cursor.offset >= self.next_offset {
+                return Ok(Dump::Done {
+                    records: cursor.count,
+                });
+            }
+            if !cursor.checked && read_header(&mut self.flash, cursor.sector)?.is_none() {
+                *cursor = Cursor::start_of((cursor.sector + 1) % self.sectors, cursor.count);
+                continue;
+            }
+            cursor.checked = true;
+            match read_record(
+                &mut self.flash,
+                cursor.sector,
+                cursor.offset,
+                &mut cursor.chain,
+            )? {
+                Decoded::Record(record, len) => {
+                    cursor.offset += len as u32;
+                    cursor.count += 1;
+                    return Ok(Dump::Record(record));
+                }
+                Decoded::Corrupt(len) => cursor.offset += len as u32,
+                // the rest of the sector hasn't been written
+                Decoded::Incomplete | Decoded::End => {
+                    *cursor = Cursor::start_of((cursor.sector + 1) % self.sectors, cursor.count);
+                }
+            }
+        }
+    }
+
+    fn write_pending(&mut self) -> Result<(), Error> {
+        if self.erasing {
+            if self.flash.is_busy()? {
+                return Ok(());
+            }
+            let mut header = [0xFF; HEADER_LEN as usize];
+            header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+            header[4..8].copy_from_slice(&self.head_seq.to_le_bytes());
+            header[8..12].copy_from_slice(&DeviceId::get().0.to_le_bytes());
+            self.flash.write(self.head * SECTOR_LEN, &header)?;
+            self.erasing = false;
+        }
+        while let Some(record) = self.pending.front() {
+            // the chain only advances once the record has been written
+            let mut chain = self.chain;
+            let value = chain.encode(record);
+            if self.next_offset + value.len() as u32 > SECTOR_LEN {
+                let next = (self.head + 1) % self.sectors;
+                return self.start_sector(next);
+            }
+            self.flash
+                .write(self.head * SECTOR_LEN + self.next_offset, &value)?;
+            self.next_offset += value.len() as u32;
+            self.chain = chain;
+            self.pending.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Erase the sector to continue the log in it, the header is written once it's erased.
+    fn start_sector(&mut self, sector: u32) -> Result<(), Error> {
+        defmt::debug!("flash log: erasing sector {}", sector);
+        if let Some(cursor) = &mut self.dump {
+            // the records of the sector are lost, continue with the now oldest one
+            if cursor.sector == sector {
+                *cursor = Cursor::start_of((sector + 1) % self.sectors, cursor.count);
+            }
+        }
+        self.flash.start_erase(sector * SECTOR_LEN)?;
+        self.head = sector;
+        self.head_seq = self.head_seq.wrapping_add(1);
+        self.next_offset = HEADER_LEN;
+        self.chain = Chain::new();
+        self.erasing = true;
+        Ok(())
+    }
+}
+
+/// The sequence number of the sector, `None` if it doesn't have a valid header.
+fn read_header(flash: &mut W25q, sector: u32) -> Result<Option<u32>, Error> {
+    let mut header = [0; 8];
+    flash.read(sector * SECTOR_LEN, &mut header)?;
+    let [m0, m1, m2, m3, s0, s1, s2, s3] = header;
+    Ok((u32::from_le_bytes([m0, m1, m2, m3]) == MAGIC)
+        .then(|| u32::from_le_bytes([s0, s1, s2, s3])))
+}
+
+/// Read & decode the record at `offset` in the sector.
+fn read_record(
+    flash: &mut W25q,
+    sector: u32,
+    offset: u32,
+    chain: &mut Chain,
+) -> Result<Decoded, Error> {
+    let mut value = [0; MAX_RECORD_LEN];
+    let value = &mut value[..MAX_RECORD_LEN.min((SECTOR_LEN - offset) as usize)];
+    flash.read(sector * SECTOR_LEN + offset, value)?;
+    Ok(chain.decode(value))
+}
@@ -0,0 +1,103 @@
+//! Fusion of the optical distance of the TOF sensor with the acoustic one of the ultrasonic sensor
+//! (see [`crate::ultrasonic`]), which complement each other: light passes through glass & isn't
+//! reflected well by dark targets, while sound is reflected by both but is less precise.
+//!
+//! If both distances are valid & agree they're averaged (weighted towards the more precise optical
+//! one), otherwise the nearer one is used as a target which only one of the sensors sees (e.g. a
+//! pane of glass) is still an obstacle. It doesn't depend on the hardware, thus it's tested on the
+//! host.
+
+/// Maximum difference of agreeing distances, whichever of the two is larger.
+pub const AGREEMENT_MM: u16 = 50;
+pub const AGREEMENT_PCT: u16 = 10;
+/// Weight of the optical distance in the average of agreeing distances, out of 4.
+const OPTICAL_WEIGHT: u32 = 3;
+
+/// The sensors whose distance has been used, the values are bit flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Source {
+    Optical = 1,
+    Acoustic = 2,
+    Both = 3,
+}
+
+/// A fused distance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fused {
+    pub distance_mm: u16,
+    pub source: Source,
+}
+
+/// Fuse the `optical` & the `acoustic` distance, each `None` if the sensor hasn't measured a valid
+/// distance. It's `None` if neither is valid.
+pub fn fuse(optical: Option<u16>, acoustic: Option<u16>) -> Option<Fused> {
+    let (distance_mm, source) = match (optical, acoustic) {
+        (Some(optical), Some(acoustic)) if agree(optical, acoustic) => {
+            let sum = OPTICAL_WEIGHT * optical as u32 + (4 - OPTICAL_WEIGHT) * acoustic as u32;
+            ((sum / 4) as u16, Source::Both)
+        }
+        (Some(optical), Some(acoustic)) if optical <= acoustic => (optical, Source::Optical),
+        (Some(_), Some(acoustic)) => (acoustic, Source::Acoustic),
+        (Some(optical), None) => (optical, Source::Optical),
+        (None, Some(acoustic)) => (acoustic, Source::Acoustic),
+        (None, None) => return None,
+    };
+    Some(Fused {
+        distance_mm,
+        source,
+    })
+}
+
+fn agree(a: u16, b: u16) -> bool {
+    let tolerance = AGREEMENT_MM.max((a.max(b) as u32 * AGREEMENT_PCT as u32 / 100) as u16);
+    a.abs_diff(b) <= tolerance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn agreeing_distances_are_averaged() {
+        let fused = fuse(Some(1_000), Some(1_040)).unwrap();
+        assert_eq!(fused.source, Source::Both);
+        assert_eq!(fused.distance_mm, 1_010);
+    }
+
+    #[test]
+    fn nearer_distance_is_used_if_they_disagree() {
+        // the light passes through a pane of glass at 500 mm
+        assert_eq!(
+            fuse(Some(2_000), Some(500)),
+            Some(Fused {
+                distance_mm: 500,
+                source: Source::Acoustic
+            })
+        );
+        assert_eq!(
+            fuse(Some(300), Some(800)),
+            Some(Fused {
+                distance_mm: 300,
+                source: Source::Optical
+            })
+        );
+    }
+
+    #[test]
+    fn single_valid_distance_is_used() {
+        assert_eq!(fuse(Some(700), None).unwrap().source, Source::Optical);
+        // a dark target which the TOF sensor doesn't see
+        assert_eq!(fuse(None, Some(700)).unwrap().source, Source::Acoustic);
+        assert_eq!(fuse(None, None), None);
+    }
+
+    proptest! {
+        #[test]
+        fn fused_distance_is_within_the_inputs(optical: u16, acoustic: u16) {
+            let fused = fuse(Some(optical), Some(acoustic)).unwrap();
+            prop_assert!(optical.min(acoustic) <= fused.distance_mm);
+            prop_assert!(fused.distance_mm <= optical.max(acoustic));
+        }
+    }
+}
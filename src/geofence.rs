@@ -0,0 +1,337 @@
+//! Up to [`MAX_ZONES`] zones of the distance, each of which is entered & left independently of the
+//! others & triggers its own actions: an event in the [`crate::event_log`], the zone output on
+//! `PC3` (`zone-output` feature, asserted while any of the zones with this action is occupied), a
+//! chirp of the buzzer (`buzzer` feature) when it's entered & its own indicator LED on the PWM
+//! expander (`pwm-expander` feature, lit while the zone is occupied, see [`crate::pwm_expander`]).
+//!
+//! A zone with an enter threshold below its exit threshold is near the sensor: it's entered once
+//! the target is closer than the enter threshold & left once it's further away than the exit
+//! threshold (or no valid target has been measured). With the thresholds the other way round it's
+//! far from the sensor. Either condition has to hold for the debounce time before the zone is
+//! entered or left. The zones are configured with the `zone` command & stored with `save`, by
+//! default there are two (see [`crate::config::geofence`]).
+
+use crate::config::geofence as config;
+use crate::telemetry::Measurement;
+use core::fmt;
+use vl53l1x_uld::RangeStatus;
+
+#[cfg(all(feature = "zone-output", feature = "stepper"))]
+compile_error!("the features `zone-output` and `stepper` can't be combined as both use PC3");
+
+/// Number of zones which can be configured.
+pub const MAX_ZONES: usize = 8;
+/// Distance between the enter & the exit threshold of the default zones.
+const DEFAULT_HYSTERESIS_MM: u16 = 50;
+
+/// The actions of a zone, a set of the flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Actions(u8);
+
+impl Actions {
+    pub const NONE: Self = Self(0);
+    /// Log the event when the zone is entered or left.
+    pub const EVENT: Self = Self(1);
+    /// Assert the zone output while the zone is occupied.
+    pub const GPIO: Self = Self(2);
+    /// Chirp the buzzer when the zone is entered.
+    pub const BUZZER: Self = Self(4);
+    /// Light the indicator LED of the zone while it's occupied.
+    pub const INDICATOR: Self = Self(8);
+    const ALL: [(Self, &'static str); 4] = [
+        (Self::EVENT, "event"),
+        (Self::GPIO, "gpio"),
+        (Self::BUZZER, "buzzer"),
+        (Self::INDICATOR, "indicator"),
+    ];
+
+    /// Parse a list of actions separated by `+` (e.g. `event+gpio`) or `none`.
+    pub fn from_names(names: &str) -> Option<Self> {
+        if names == "none" {
+            return Some(Self::NONE);
+        }
+        names.split('+').try_fold(Self::NONE, |actions, name| {
+            let (action, _) = Self::ALL.iter().find(|(_, n)| *n == name)?;
+            Some(Self(actions.0 | action.0))
+        })
+    }
+
+    pub fn contains(self, action: Self) -> bool {
+        self.0 & action.0 == action.0
+    }
+}
+
+impl fmt::Display for Actions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Self::NONE {
+            return write!(f, "none");
+        }
+        let names = Self::ALL
+            .iter()
+            .filter(|(action, _)| self.contains(*action));
+        for (i, (_, name)) in names.enumerate() {
+            write!(f, "{}{}", if i > 0 { "+" } else { "" }, name)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ZoneConfig {
+    pub enter_mm: u16,
+    pub exit_mm: u16,
+    pub debounce_ms: u16,
+    pub actions: Actions,
+}
+
+impl ZoneConfig {
+    /// Whether the zone is near the sensor, i.e. entered when the target gets closer.
+    fn is_near(&self) -> bool {
+        self.enter_mm <= self.exit_mm
+    }
+
+    /// Whether the distance (`None` without a valid target) is in the zone, `inside` tells which
+    /// threshold applies.
+    fn contains(&self, distance_mm: Option<u16>, inside: bool) -> bool {
+        let threshold_mm = if inside { self.exit_mm } else { self.enter_mm };
+        match distance_mm {
+            Some(distance_mm) if self.is_near() => match inside {
+                true => distance_mm <= threshold_mm,
+                false => distance_mm < threshold_mm,
+            },
+            Some(distance_mm) => match inside {
+                true => distance_mm >= threshold_mm,
+                false => distance_mm > threshold_mm,
+            },
+            None => !self.is_near(),
+        }
+    }
+
+    /// Layout: enter & exit threshold, debounce time & actions.
+    pub fn encode(&self) -> [u8; 7] {
+        let mut value = [0; 7];
+        value[0..2].copy_from_slice(&self.enter_mm.to_le_bytes());
+        value[2..4].copy_from_slice(&self.exit_mm.to_le_bytes());
+        value[4..6].copy_from_slice(&self.debounce_ms.to_le_bytes());
+        value[6] = self.actions.0;
+        value
+    }
+
+    pub fn decode(value: &[u8; 7]) -> Self {
+        Self {
+            enter_mm: u16::from_le_bytes([value[0], value[1]]),
+            exit_mm: u16::from_le_bytes([value[2], value[3]]),
+            debounce_ms: u16::from_le_bytes([value[4], value[5]]),
+            actions: Actions(value[6]),
+        }
+    }
+}
+
+// the zones are stored with a bit each & logged with their number in 3 bits
+const _: () = assert!(MAX_ZONES <= 8);
+
+/// The configured zones.
+pub type Zones = [Option<ZoneConfig>; MAX_ZONES];
+
+/// The default zones: near the sensor (closer than [`config::NEAR_MM`]) & not far from it
+/// (closer than [`config::FAR_MM`]), both with the event action.
+pub const DEFAULT_ZONES: Zones = {
+    const fn zone(threshold_mm: u16) -> Option<ZoneConfig> {
+        Some(ZoneConfig {
+            enter_mm: threshold_mm,
+            exit_mm: threshold_mm.saturating_add(DEFAULT_HYSTERESIS_MM),
+            debounce_ms: config::DEBOUNCE_MS,
+            actions: Actions::EVENT,
+        })
+    }
+    let mut zones = [None; MAX_ZONES];
+    zones[0] = zone(config::NEAR_MM);
+    zones[1] = zone(config::FAR_MM);
+    zones
+};
+
+/// A zone has been entered or left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Transition {
+    pub zone: u8,
+    pub entered: bool,
+    pub actions: Actions,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ZoneState {
+    inside: bool,
+    /// Time since which the target has been on the other side of the threshold.
+    pending_since_ms: Option<u32>,
+}
+
+/// Tracks in which of the zones the target is.
+pub struct Geofence {
+    zones: Zones,
+    states: [ZoneState; MAX_ZONES],
+}
+
+impl Geofence {
+    pub fn new(zones: Zones) -> Self {
+        Self {
+            zones,
+            states: [ZoneState::default(); MAX_ZONES],
+        }
+    }
+
+    pub fn zones(&self) -> &Zones {
+        &self.zones
+    }
+
+    /// Configure (or remove, `None`) a zone, the target is outside of it until the next
+    /// measurement has been checked.
+    pub fn set_zone(&mut self, index: usize, zone: Option<ZoneConfig>) {
+        self.zones[index] = zone;
+        self.states[index] = ZoneState::default();
+    }
+
+    /// Whether any of the occupied zones has the action, e.g. for the zone output.
+    pub fn is_occupied(&self, action: Actions) -> bool {
+        self.occupied(action) != 0
+    }
+
+    /// The occupied zones which have the action, bit `n` for zone `n`.
+    pub fn occupied(&self, action: Actions) -> u8 {
+        self.zones
+            .iter()
+            .zip(&self.states)
+            .enumerate()
+            .filter(|(_, (zone, state))| {
+                state.inside && zone.is_some_and(|z| z.actions.contains(action))
+            })
+            .fold(0, |occupied, (index, _)| occupied | 1 << index)
+    }
+
+    /// Update the zones with the measurement. Returns the zones which have been entered or left.
+    pub fn update(&mut self, measurement: &Measurement) -> heapless::Vec<Transition, MAX_ZONES> {
+        let distance_mm =
+            (measurement.status == RangeStatus::Valid).then_some(measurement.distance_mm);
+        let now_ms = measurement.timestamp_ms;
+        let mut transitions = heapless::Vec::new();
+        for (index, (zone, state)) in self.zones.iter().zip(&mut self.states).enumerate() {
+            let Some(zone) = zone else { continue };
+            if zone.contains(distance_mm, state.inside) == state.inside {
+                state.pending_since_ms = None;
+                continue;
+            }
+            let since_ms = *state.pending_since_ms.get_or_insert(now_ms);
+            if now_ms.wrapping_sub(since_ms) < zone.debounce_ms as u32 {
+                continue;
+            }
+            state.inside = !state.inside;
+            state.pending_since_ms = None;
+            defmt::info!(
+                "zone {} {}",
+                index,
+                if state.inside { "entered" } else { "left" }
+            );
+            // there's one entry per zone
+            transitions
+                .push(Transition {
+                    zone: index as u8,
+                    entered: state.inside,
+                    actions: zone.actions,
+                })
+                .ok();
+        }
+        transitions
+    }
+}
+
+/// Write the response to the `zone` command, e.g. `OK zones=0:300/350/200ms/event`.
+pub fn write_response(zones: &Zones, response: &mut impl fmt::Write) -> fmt::Result {
+    write!(response, "OK zones=")?;
+    let zones = zones
+        .iter()
+        .enumerate()
+        .filter_map(|(index, zone)| Some((index, (*zone)?)));
+    for (i, (index, zone)) in zones.enumerate() {
+        write!(
+            response,
+            "{}{}:{}/{}/{}ms/{}",
+            if i > 0 { "," } else { "" },
+            index,
+            zone.enter_mm,
+            zone.exit_mm,
+            zone.debounce_ms,
+            zone.actions
+        )?;
+    }
+    write!(response, "\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Response;
+    use crate::telemetry::test_measurement;
+
+    const NEAR: ZoneConfig = ZoneConfig {
+        enter_mm: 300,
+        exit_mm: 400,
+        debounce_ms: 100,
+        actions: Actions::GPIO,
+    };
+    const FAR: ZoneConfig = ZoneConfig {
+        enter_mm: 1000,
+        exit_mm: 900,
+        debounce_ms: 0,
+        actions: Actions::EVENT,
+    };
+
+    fn entered(geofence: &mut Geofence, timestamp_ms: u32, distance_mm: u16) -> Vec<(u8, bool)> {
+        geofence
+            .update(&test_measurement(timestamp_ms, distance_mm))
+            .iter()
+            .map(|transition| (transition.zone, transition.entered))
+            .collect()
+    }
+
+    #[test]
+    fn thresholds_and_debounce() {
+        let mut zones = [None; MAX_ZONES];
+        zones[2] = Some(NEAR);
+        zones[5] = Some(FAR);
+        let mut geofence = Geofence::new(zones);
+        assert_eq!(entered(&mut geofence, 0, 1200), [(5, true)]);
+        assert_eq!(entered(&mut geofence, 100, 250), [(5, false)]);
+        assert_eq!(entered(&mut geofence, 150, 250), []);
+        assert!(!geofence.is_occupied(Actions::GPIO));
+        assert_eq!(entered(&mut geofence, 200, 250), [(2, true)]);
+        assert!(geofence.is_occupied(Actions::GPIO));
+        assert_eq!(geofence.occupied(Actions::GPIO), 1 << 2);
+        // within the hysteresis
+        assert_eq!(entered(&mut geofence, 500, 350), []);
+        assert_eq!(entered(&mut geofence, 600, 450), []);
+        assert_eq!(entered(&mut geofence, 650, 350), []);
+        // no valid target leaves the near zone & enters the far one
+        let invalid = Measurement {
+            status: RangeStatus::SignalFailure,
+            ..test_measurement(800, 0)
+        };
+        assert_eq!(geofence.update(&invalid).len(), 1);
+        let invalid = Measurement {
+            status: RangeStatus::SignalFailure,
+            ..test_measurement(900, 0)
+        };
+        assert_eq!(geofence.update(&invalid)[0].zone, 2);
+    }
+
+    #[test]
+    fn actions_and_encoding() {
+        let actions = Actions::from_names("event+buzzer").unwrap();
+        assert!(actions.contains(Actions::BUZZER) && !actions.contains(Actions::GPIO));
+        assert_eq!(Actions::from_names("event+led"), None);
+        assert_eq!(ZoneConfig::decode(&NEAR.encode()), NEAR);
+        let mut response = Response::new();
+        let mut zones = [Some(FAR); MAX_ZONES];
+        zones[0] = Some(ZoneConfig { actions, ..NEAR });
+        write_response(&zones, &mut response).unwrap();
+        assert!(response.starts_with("OK zones=0:300/400/100ms/event+buzzer,1:1000/900/0ms/event,"));
+    }
+}
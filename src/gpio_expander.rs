@@ -0,0 +1,130 @@
+//! A MCP23017 16-bit GPIO expander connected to the shared I2C bus (`gpio-expander` feature),
+//! which frees the pins of the Nucleo for the timers & buses of the other features:
+//!
+//! * port A reads a bank of 8 configuration switches (switches to GND, the pull-ups of the
+//!   expander are used): switches 1 - 7 select the [`crate::boot_config`] at boot like the DIP
+//!   switch of the `dip-switch` feature. A change while running raises its interrupt (`INTA`,
+//!   open-drain, on `PB11`), which is logged, it's applied at the next boot.
+//! * port B drives the status LEDs (active high) on `GPB0` - `GPB3`, see the `LED_*` constants.
+
+use crate::config::gpio_expander as config;
+use crate::I2cBus;
+use stm32f4xx_hal::gpio::{Edge, ExtiPin, Input, PB11};
+use stm32f4xx_hal::hal::blocking::i2c::{Write, WriteRead};
+use stm32f4xx_hal::pac::EXTI;
+use stm32f4xx_hal::syscfg::SysCfg;
+
+/// The registers with `IOCON.BANK` = 0, the registers of port B follow those of port A.
+const REG_IODIRA: u8 = 0x00;
+const REG_IPOLA: u8 = 0x02;
+const REG_GPINTENA: u8 = 0x04;
+const REG_IOCON: u8 = 0x0A;
+const REG_GPPUA: u8 = 0x0C;
+const REG_GPIOA: u8 = 0x12;
+const REG_OLATB: u8 = 0x15;
+
+/// `INTA` is an open-drain output.
+const IOCON_ODR: u8 = 0x04;
+
+/// The TOF sensor is ranging.
+pub const LED_RANGING: u8 = 1 << 0;
+/// The latest communication with the TOF sensor failed.
+pub const LED_SENSOR_ERROR: u8 = 1 << 1;
+/// The firmware is in the safe mode.
+pub const LED_SAFE_MODE: u8 = 1 << 2;
+/// A calibration is running.
+pub const LED_CALIBRATION: u8 = 1 << 3;
+
+pub type Error = <I2cBus as Write>::Error;
+
+pub struct GpioExpander {
+    i2c: I2cBus,
+    interrupt: PB11<Input>,
+    /// The latest state of the switches, bit `n` is set if switch `n + 1` is on.
+    switches: u8,
+    /// The state of the LEDs, `None` if it's unknown after a failed write.
+    leds: Option<u8>,
+}
+
+impl GpioExpander {
+    /// Set up the expander with all LEDs off & read the switches. Returns `None` if no MCP23017 is
+    /// connected.
+    pub fn new(
+        i2c: I2cBus,
+        interrupt: PB11<Input>,
+        syscfg: &mut SysCfg,
+        exti: &mut EXTI,
+    ) -> Option<Self> {
+        let mut expander = Self {
+            i2c,
+            interrupt,
+            switches: 0,
+            leds: Some(0),
+        };
+        // before the switches are read, which clears the interrupt of the expander
+        expander.interrupt.make_interrupt_source(syscfg);
+        expander.interrupt.enable_interrupt(exti);
+        expander.interrupt.trigger_on_edge(exti, Edge::Falling);
+        if expander.setup().is_err() {
+            defmt::warn!("no MCP23017 found");
+            return None;
+        }
+        defmt::info!("MCP23017 set up, switches {=u8:#b}", expander.switches);
+        Some(expander)
+    }
+
+    fn setup(&mut self) -> Result<(), Error> {
+        self.i2c.write(config::ADDRESS, &[REG_IOCON, IOCON_ODR])?;
+        // port A inputs, port B outputs
+        self.i2c.write(config::ADDRESS, &[REG_IODIRA, 0xFF, 0x00])?;
+        // a closed switch pulls the pin low, which is read as 1
+        self.i2c.write(config::ADDRESS, &[REG_IPOLA, 0xFF])?;
+        self.i2c.write(config::ADDRESS, &[REG_GPPUA, 0xFF])?;
+        self.i2c.write(config::ADDRESS, &[REG_OLATB, 0])?;
+        // interrupt on every change of the switches
+        self.i2c.write(config::ADDRESS, &[REG_GPINTENA, 0xFF])?;
+        self.read_switches().map(|_| ())
+    }
+
+    /// The latest state of the switches, bit `n` is set if switch `n + 1` is on.
+    pub fn switches(&self) -> u8 {
+        self.switches
+    }
+
+    /// Whether the expander has raised the interrupt, which is acknowledged.
+    pub fn take_interrupt(&mut self) -> bool {
+        let edge = self.interrupt.check_interrupt();
+        if edge {
+            self.interrupt.clear_interrupt_pending_bit();
+        }
+        edge
+    }
+
+    /// Read the switches, which also clears the interrupt of the expander. Returns the switches
+    /// which have changed since the previous read.
+    pub fn read_switches(&mut self) -> Result<u8, Error> {
+        let mut switches = [0];
+        self.i2c
+            .write_read(config::ADDRESS, &[REG_GPIOA], &mut switches)?;
+        let changed = self.switches ^ switches[0];
+        self.switches = switches[0];
+        Ok(changed)
+    }
+
+    /// Switch the LEDs (see the `LED_*` constants), they're only written if they have changed. A
+    /// failure is only returned once, until a write has succeeded again.
+    pub fn set_leds(&mut self, leds: u8) -> Result<(), Error> {
+        if self.leds == Some(leds) {
+            return Ok(());
+        }
+        let known = self.leds.take().is_some();
+        match self.i2c.write(config::ADDRESS, &[REG_OLATB, leds]) {
+            Ok(()) => {
+                self.leds = Some(leds);
+                Ok(())
+            }
+            Err(e) if known => Err(e),
+            Err(_) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Periodic health report of the firmware.
+//!
+//! The report contains the state of the firmware and the readings of the optional monitoring
+//! devices, it is sent to all links which accept commands every [`crate::config::health`] interval.
+
+#[cfg(feature = "power")]
+use crate::power::PowerMonitor;
+use crate::telemetry::Health;
+
+/// The optional monitoring devices, combined into a single struct so that the task doesn't need to
+/// know which of them have been enabled.
+pub struct HealthMonitor {
+    #[cfg(feature = "power")]
+    pub power: Option<PowerMonitor>,
+}
+
+impl HealthMonitor {
+    /// Add the readings of the monitoring devices to the report.
+    pub fn sample(&mut self, health: &mut Health) {
+        #[cfg(feature = "power")]
+        {
+            health.power = self.power.as_mut().and_then(|power| power.read());
+        }
+        #[cfg(not(feature = "power"))]
+        let _ = health;
+    }
+}
@@ -0,0 +1,465 @@
+//! A timeout for the transactions on the I2C buses: the shared one (I2C1) & the one of the second
+//! TOF sensor (I2C3, see [`crate::second_tof`]).
+//!
+//! The blocking transfers of the HAL wait for the flags of the peripheral without a limit: a bus
+//! which is stuck (e.g. SDA held low by a device which lost power in the middle of a transfer)
+//! blocks the task until the independent watchdog resets the board. The transfers are thus done
+//! on the registers here, each wait for the bus is limited to [`config::TIMEOUT_US`] (measured
+//! with the cycle counter of the DWT, which has to be enabled). A transaction which times out fails
+//! with [`Error::Timeout`] & the bus is cleared before the peripheral is reset & initialised
+//! again, so that the next one starts on an idle bus: SCL is clocked as a GPIO (up to 9 pulses)
+//! until the device holding SDA low has shifted out its byte & released it, then a STOP is
+//! generated. The devices themselves are recovered by their users, e.g. the TOF sensor is
+//! initialised again by the [`crate::sensor_supervisor`].
+//!
+//! Reads of 2 bytes or more on the shared bus are received by DMA (stream 0 of DMA1, see
+//! [`GuardedI2c::use_rx_dma`]), e.g. the results of the TOF sensor: the bytes don't depend on the
+//! task reading them in time, thus a preemption of the task (at the rate of the
+//! [`crate::fast_mode`]) doesn't stretch the bus. The task still waits for the end of the
+//! transfer.
+
+use crate::config::i2c as config;
+use core::sync::atomic::{self, AtomicU32, Ordering};
+use cortex_m::peripheral::DWT;
+use stm32f4xx_hal::dma::traits::{Stream, StreamISR};
+use stm32f4xx_hal::dma::{DmaChannel, DmaDataSize, DmaDirection, Stream0};
+use stm32f4xx_hal::gpio::PinExt;
+use stm32f4xx_hal::hal::blocking::i2c::{Read, Write, WriteRead};
+use stm32f4xx_hal::i2c::{I2c, Instance};
+use stm32f4xx_hal::pac::{gpioa, i2c1, DMA1, GPIOA, GPIOB, GPIOC, GPIOD, GPIOH, I2C1};
+use stm32f4xx_hal::prelude::*;
+use stm32f4xx_hal::rcc::Clocks;
+use stm32f4xx_hal::time::Hertz;
+
+static TIMEOUTS: AtomicU32 = AtomicU32::new(0);
+
+/// Number of clock pulses after which a device has shifted out the rest of a byte & the ACK bit.
+const BUS_CLEAR_PULSES: u8 = 9;
+/// Frequency of the clock pulses of the bus clear, the one of the standard mode.
+const BUS_CLEAR_HZ: u32 = 100_000;
+/// The values of `MODER` of the pins.
+const MODE_OUTPUT: u32 = 0b01;
+const MODE_ALTERNATE: u32 = 0b10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    /// The bus didn't progress within [`config::TIMEOUT_US`], the peripheral has been reset.
+    Timeout,
+    /// The address or a byte hasn't been acknowledged.
+    NoAcknowledge,
+    /// Another master has taken over the bus.
+    ArbitrationLoss,
+    /// A byte has been lost (also by the DMA), or the buffer to read into is empty.
+    Overrun,
+}
+
+/// Number of transactions which timed out since boot, on all buses.
+pub fn timeouts() -> u32 {
+    TIMEOUTS.load(Ordering::Relaxed)
+}
+
+/// An I2C peripheral with the timeout, all of them have the registers of I2C1.
+pub struct GuardedI2c<I2C: Instance = I2C1> {
+    /// The HAL driver, which configures the peripheral & the pins. Always `Some`, it's only taken
+    /// while the peripheral is reset.
+    i2c: Option<I2c<I2C>>,
+    speed: Hertz,
+    clocks: Clocks,
+    timeout_cycles: u32,
+    scl: BusPin,
+    sda: BusPin,
+    /// The stream which receives the bytes of I2C1 (on channel 1), `None` to receive them without
+    /// DMA. It's only taken during a reception.
+    rx_dma: Option<Stream0<DMA1>>,
+}
+
+impl GuardedI2c<I2C1> {
+    /// Receive the reads of 2 bytes or more by DMA with the `stream`, which has to be stream 0 of
+    /// the DMA1 (the one of I2C1 on channel 1).
+    pub fn use_rx_dma(&mut self, stream: Stream0<DMA1>) {
+        self.rx_dma = Some(stream);
+    }
+}
+
+impl<I2C: Instance> GuardedI2c<I2C> {
+    pub fn new(
+        i2c: I2C,
+        (scl, sda): (impl PinExt + Into<I2C::Scl>, impl PinExt + Into<I2C::Sda>),
+        speed: Hertz,
+        clocks: &Clocks,
+    ) -> Self {
+        Self {
+            scl: BusPin::new(&scl),
+            sda: BusPin::new(&sda),
+            i2c: Some(I2c::new(i2c, (scl, sda), speed, clocks)),
+            speed,
+            clocks: *clocks,
+            timeout_cycles: (config::TIMEOUT_US as u64 * clocks.sysclk().raw() as u64 / 1_000_000)
+                as u32,
+            rx_dma: None,
+        }
+    }
+
+    /// The registers of the peripheral, which is owned by the HAL driver.
+    #[allow(unsafe_code)]
+    fn regs(&self) -> &i2c1::RegisterBlock {
+        // SAFETY: the peripheral is owned by `self.i2c` & only accessed through `&mut self`
+        unsafe { &*I2C::ptr() }
+    }
+
+    /// Run the transaction, reset the peripheral if it times out & release the bus after an error.
+    fn guard(
+        &mut self,
+        transaction: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let result = transaction(self);
+        match result {
+            Err(Error::Timeout) => self.reset(),
+            Err(_) => self.regs().cr1.modify(|_, w| w.stop().set_bit()),
+            Ok(()) => {}
+        }
+        result
+    }
+
+    fn reset(&mut self) {
+        TIMEOUTS.fetch_add(1, Ordering::Relaxed);
+        if let Some(i2c) = self.i2c.take() {
+            let (i2c, pins) = i2c.release();
+            // resetting the peripheral doesn't release a device which holds SDA low
+            self.clear_bus();
+            // the HAL resets the peripheral before initialising it
+            self.i2c = Some(I2c::new(i2c, pins, self.speed, &self.clocks));
+        }
+    }
+
+    /// Clock SCL as an open-drain GPIO until SDA is released (at most [`BUS_CLEAR_PULSES`]) &
+    /// generate a STOP, the pins are handed back to the peripheral afterwards.
+    fn clear_bus(&self) {
+        let half_period = self.clocks.sysclk().raw() / BUS_CLEAR_HZ / 2;
+        let (scl, sda) = (self.scl, self.sda);
+        scl.set_high();
+        sda.set_high();
+        scl.set_mode(MODE_OUTPUT);
+        sda.set_mode(MODE_OUTPUT);
+        cortex_m::asm::delay(half_period);
+        let mut pulses = 0;
+        while !sda.is_high() && pulses < BUS_CLEAR_PULSES {
+            scl.set_low();
+            cortex_m::asm::delay(half_period);
+            scl.set_high();
+            cortex_m::asm::delay(half_period);
+            pulses += 1;
+        }
+        if !sda.is_high() {
+            defmt::warn!("I2C: SDA still held low after {} clock pulses", pulses);
+        }
+        // STOP: SDA rises while SCL is high
+        scl.set_low();
+        sda.set_low();
+        cortex_m::asm::delay(half_period);
+        scl.set_high();
+        cortex_m::asm::delay(half_period);
+        sda.set_high();
+        cortex_m::asm::delay(half_period);
+        scl.set_mode(MODE_ALTERNATE);
+        sda.set_mode(MODE_ALTERNATE);
+    }
+
+    /// Wait until `done` is true, fails with the first error flag or if it takes longer than the
+    /// timeout.
+    fn wait(&self, done: impl Fn(&i2c1::RegisterBlock, i2c1::sr1::R) -> bool) -> Result<(), Error> {
+        let start = DWT::cycle_count();
+        loop {
+            let sr1 = self.check_errors()?;
+            if done(self.regs(), sr1) {
+                return Ok(());
+            }
+            if DWT::cycle_count().wrapping_sub(start) > self.timeout_cycles {
+                return Err(Error::Timeout);
+            }
+        }
+    }
+
+    /// Read the status register 1 & clear the error flags, the first error is returned.
+    fn check_errors(&self) -> Result<i2c1::sr1::R, Error> {
+        let regs = self.regs();
+        let sr1 = regs.sr1.read();
+        if sr1.ovr().bit_is_set() {
+            regs.sr1.modify(|_, w| w.ovr().clear_bit());
+            return Err(Error::Overrun);
+        }
+        if sr1.af().bit_is_set() {
+            regs.sr1.modify(|_, w| w.af().clear_bit());
+            return Err(Error::NoAcknowledge);
+        }
+        if sr1.arlo().bit_is_set() {
+            regs.sr1.modify(|_, w| w.arlo().clear_bit());
+            return Err(Error::ArbitrationLoss);
+        }
+        // the errata recommends to ignore the bus error, it may be detected incorrectly
+        if sr1.berr().bit_is_set() {
+            regs.sr1.modify(|_, w| w.berr().clear_bit());
+        }
+        Ok(sr1)
+    }
+
+    /// Send a (repeated) START & the address. ADDR is only cleared when writing, [`Self::receive`]
+    /// clears it when reading as the ACK has to be set up before.
+    #[allow(unsafe_code)]
+    fn start(&self, addr: u8, read: bool) -> Result<(), Error> {
+        let regs = self.regs();
+        // POS may still be set by a 2 byte reception which has failed
+        regs.cr1
+            .modify(|_, w| w.start().set_bit().ack().bit(read).pos().clear_bit());
+        self.wait(|_, sr1| sr1.sb().bit_is_set())?;
+        self.wait(|regs, _| {
+            let sr2 = regs.sr2.read();
+            sr2.msl().bit_is_set() || sr2.busy().bit_is_set()
+        })?;
+        regs.dr
+            .write(|w| unsafe { w.bits((u32::from(addr) << 1) | u32::from(read)) });
+        self.wait(|_, sr1| sr1.addr().bit_is_set())?;
+        if !read {
+            self.clear_addr();
+        }
+        Ok(())
+    }
+
+    /// Clear ADDR by reading the status registers, the bus is stretched until then.
+    fn clear_addr(&self) {
+        let regs = self.regs();
+        regs.sr1.read();
+        regs.sr2.read();
+    }
+
+    #[allow(unsafe_code)]
+    fn send(&self, bytes: &[u8]) -> Result<(), Error> {
+        for &byte in bytes {
+            self.wait(|_, sr1| sr1.tx_e().bit_is_set())?;
+            self.regs().dr.write(|w| unsafe { w.bits(u32::from(byte)) });
+            self.wait(|_, sr1| sr1.btf().bit_is_set())?;
+        }
+        Ok(())
+    }
+
+    /// Receive the bytes after [`Self::start`], the last one is answered with NACK & STOP. The
+    /// ACK & the STOP are set up as in the reference manual (RM0368, 18.3.3) for 1, 2 & more bytes,
+    /// so that the NACK is sent in time even if the task is preempted meanwhile.
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        if buffer.len() >= 2 {
+            if let Some(mut stream) = self.rx_dma.take() {
+                let result = self.receive_dma(&mut stream, buffer);
+                self.rx_dma = Some(stream);
+                return result;
+            }
+        }
+        let regs = self.regs();
+        let len = buffer.len();
+        match buffer {
+            [] => return Err(Error::Overrun),
+            [byte] => {
+                // the NACK has to be set up before ADDR is cleared & the STOP right after it
+                regs.cr1.modify(|_, w| w.ack().clear_bit());
+                cortex_m::interrupt::free(|_| {
+                    self.clear_addr();
+                    regs.cr1.modify(|_, w| w.stop().set_bit());
+                });
+                *byte = self.receive_byte()?;
+            }
+            [first, second] => {
+                // POS: the NACK applies to the 2nd byte, which is in the shift register once BTF
+                // is set
+                regs.cr1.modify(|_, w| w.ack().clear_bit().pos().set_bit());
+                self.clear_addr();
+                self.wait(|_, sr1| sr1.btf().bit_is_set())?;
+                cortex_m::interrupt::free(|_| {
+                    regs.cr1.modify(|_, w| w.stop().set_bit());
+                    *first = regs.dr.read().bits() as u8;
+                });
+                *second = regs.dr.read().bits() as u8;
+                regs.cr1.modify(|_, w| w.pos().clear_bit());
+            }
+            _ => {
+                self.clear_addr();
+                let (buffer, last) = buffer.split_at_mut(len - 3);
+                for byte in buffer {
+                    *byte = self.receive_byte()?;
+                }
+                // the 3rd last byte is in DR & the 2nd last one in the shift register, the last
+                // one is answered with NACK once the 3rd last one has been read
+                self.wait(|_, sr1| sr1.btf().bit_is_set())?;
+                regs.cr1.modify(|_, w| w.ack().clear_bit());
+                last[0] = regs.dr.read().bits() as u8;
+                self.wait(|_, sr1| sr1.btf().bit_is_set())?;
+                cortex_m::interrupt::free(|_| {
+                    regs.cr1.modify(|_, w| w.stop().set_bit());
+                    last[1] = regs.dr.read().bits() as u8;
+                });
+                last[2] = self.receive_byte()?;
+            }
+        }
+        self.wait(|regs, _| regs.cr1.read().stop().bit_is_clear())
+    }
+
+    /// Receive the bytes after [`Self::start`] by DMA: with LAST the peripheral answers the byte
+    /// after the last but one transfer of the DMA with NACK itself (RM0368, 18.3.8), the STOP is
+    /// sent once the DMA has finished.
+    #[allow(unsafe_code)]
+    fn receive_dma(&self, stream: &mut Stream0<DMA1>, buffer: &mut [u8]) -> Result<(), Error> {
+        let regs = self.regs();
+        // SAFETY: the stream is disabled while it's configured, the buffer outlives the transfer
+        // as it's waited for (or the stream is disabled) before returning
+        unsafe {
+            stream.disable();
+            stream.clear_all_flags();
+            stream.set_channel(DmaChannel::Channel1);
+            stream.set_direction(DmaDirection::PeripheralToMemory);
+            stream.set_peripheral_address(regs.dr.as_ptr() as u32);
+            stream.set_peripheral_increment(false);
+            stream.set_peripheral_size(DmaDataSize::Byte);
+            stream.set_memory_address(buffer.as_mut_ptr() as u32);
+            stream.set_memory_increment(true);
+            stream.set_memory_size(DmaDataSize::Byte);
+            stream.set_number_of_transfers(buffer.len() as u16);
+            atomic::compiler_fence(Ordering::Release);
+            stream.enable();
+        }
+        regs.cr2.modify(|_, w| w.dmaen().enabled().last().last());
+        // the reception starts once ADDR is cleared
+        self.clear_addr();
+        let result = self
+            .wait(|_, _| stream.is_transfer_complete() || stream.is_transfer_error())
+            .and_then(|()| {
+                if stream.is_transfer_error() {
+                    return Err(Error::Overrun);
+                }
+                regs.cr1.modify(|_, w| w.stop().set_bit());
+                self.wait(|regs, _| regs.cr1.read().stop().bit_is_clear())
+            });
+        // SAFETY: stops the transfer, the buffer isn't written anymore afterwards
+        unsafe {
+            stream.disable();
+        }
+        stream.clear_all_flags();
+        regs.cr2
+            .modify(|_, w| w.dmaen().disabled().last().not_last());
+        atomic::compiler_fence(Ordering::Acquire);
+        result
+    }
+
+    fn receive_byte(&self) -> Result<u8, Error> {
+        self.wait(|_, sr1| sr1.rx_ne().bit_is_set())?;
+        Ok(self.regs().dr.read().bits() as u8)
+    }
+
+    fn stop(&self) -> Result<(), Error> {
+        self.regs().cr1.modify(|_, w| w.stop().set_bit());
+        self.wait(|regs, _| regs.cr1.read().stop().bit_is_clear())
+    }
+}
+
+/// A pin of the bus (in the alternate function of the peripheral), which is driven as an
+/// open-drain GPIO to clear the bus. The HAL driver owns the pin, thus it's accessed through the
+/// registers of its port.
+#[derive(Debug, Clone, Copy)]
+struct BusPin {
+    port: u8,
+    pin: u8,
+}
+
+impl BusPin {
+    fn new(pin: &impl PinExt) -> Self {
+        Self {
+            port: pin.port_id(),
+            pin: pin.pin_id(),
+        }
+    }
+
+    /// The registers of the port, all of them have the registers of GPIOA.
+    #[allow(unsafe_code)]
+    fn regs(&self) -> &gpioa::RegisterBlock {
+        let ptr = match self.port {
+            0 => GPIOA::ptr(),
+            1 => GPIOB::ptr() as _,
+            2 => GPIOC::ptr() as _,
+            3 => GPIOD::ptr() as _,
+            _ => GPIOH::ptr() as _,
+        };
+        // SAFETY: only the bits of this pin are changed, atomically (see `Self::set_mode`), the
+        // HAL driver doesn't access the pin meanwhile
+        unsafe { &*ptr }
+    }
+
+    /// Change the mode, the output type stays open-drain & the alternate function unchanged.
+    #[allow(unsafe_code)]
+    fn set_mode(&self, mode: u32) {
+        let shift = 2 * u32::from(self.pin);
+        // the other pins of the port are used by other tasks
+        // SAFETY: both bits of the mode are valid for all values
+        cortex_m::interrupt::free(|_| {
+            self.regs()
+                .moder
+                .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (mode << shift)) });
+        });
+    }
+
+    #[allow(unsafe_code)]
+    fn set_high(&self) {
+        // SAFETY: BSRR only sets the bits which are written
+        self.regs().bsrr.write(|w| unsafe { w.bits(1 << self.pin) });
+    }
+
+    #[allow(unsafe_code)]
+    fn set_low(&self) {
+        // SAFETY: BSRR only resets the bits which are written
+        self.regs()
+            .bsrr
+            .write(|w| unsafe { w.bits(1 << (self.pin + 16)) });
+    }
+
+    fn is_high(&self) -> bool {
+        self.regs().idr.read().bits() & (1 << self.pin) != 0
+    }
+}
+
+impl<I2C: Instance> Write for GuardedI2c<I2C> {
+    type Error = Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.guard(|i2c| {
+            i2c.start(addr, false)?;
+            i2c.send(bytes)?;
+            i2c.stop()
+        })
+    }
+}
+
+impl<I2C: Instance> Read for GuardedI2c<I2C> {
+    type Error = Error;
+
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        if buffer.is_empty() {
+            return Err(Error::Overrun);
+        }
+        self.guard(|i2c| {
+            i2c.start(addr, true)?;
+            i2c.receive(buffer)
+        })
+    }
+}
+
+impl<I2C: Instance> WriteRead for GuardedI2c<I2C> {
+    type Error = Error;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        if buffer.is_empty() {
+            return Err(Error::Overrun);
+        }
+        self.guard(|i2c| {
+            i2c.start(addr, false)?;
+            i2c.send(bytes)?;
+            i2c.start(addr, true)?;
+            i2c.receive(buffer)
+        })
+    }
+}
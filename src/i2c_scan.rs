@@ -0,0 +1,130 @@
+//! Scanner of the shared I2C bus, so that a miswired shield or a device with an unexpected
+//! address is noticed immediately: the bus is scanned at boot & with the `scan` command, the
+//! devices which answer are logged (with the name of the part which usually has that address) &
+//! listed in the response.
+//!
+//! Each address is probed by reading a single byte, which doesn't change the state of the devices
+//! used by the firmware. The reserved addresses (`0x00` - `0x07` & `0x78` - `0x7F`) aren't probed.
+
+use core::fmt;
+use stm32f4xx_hal::hal::blocking::i2c::Read;
+
+/// The range of the probed 7-bit addresses.
+pub const FIRST_ADDRESS: u8 = 0x08;
+pub const LAST_ADDRESS: u8 = 0x77;
+
+/// The addresses which answered the probe, a bit per address.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Devices(u128);
+
+impl Devices {
+    pub fn contains(&self, address: u8) -> bool {
+        address < 0x80 && self.0 & (1 << address) != 0
+    }
+
+    pub fn len(&self) -> u32 {
+        self.0.count_ones()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// The addresses in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (FIRST_ADDRESS..=LAST_ADDRESS).filter(|address| self.contains(*address))
+    }
+}
+
+/// The addresses separated by commas, `-` if there are none.
+impl fmt::Display for Devices {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "-");
+        }
+        for (i, address) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{:#04x}", address)?;
+        }
+        Ok(())
+    }
+}
+
+/// Probe all addresses on the `bus`.
+pub fn scan<B: Read>(bus: &mut B) -> Devices {
+    let mut devices = Devices::default();
+    for address in FIRST_ADDRESS..=LAST_ADDRESS {
+        let mut byte = [0];
+        if bus.read(address, &mut byte).is_ok() {
+            devices.0 |= 1 << address;
+        }
+    }
+    devices
+}
+
+/// The part supported by the firmware which has this address by default, if any.
+pub fn part(address: u8) -> Option<&'static str> {
+    match address {
+        0x27 => Some("PCF8574 (HD44780 LCD)"),
+        0x29 => Some("VL53L1X"),
+        0x3C | 0x3D => Some("SSD1306 OLED"),
+        0x40 => Some("INA219"),
+        0x68 => Some("MPU6050"),
+        0x76 | 0x77 => Some("BME280"),
+        _ => None,
+    }
+}
+
+/// Log the result of a scan.
+pub fn log(devices: &Devices) {
+    defmt::info!("I2C scan: {} device(s)", devices.len());
+    for address in devices.iter() {
+        match part(address) {
+            Some(part) => defmt::info!("  {=u8:#04x}: {=str}?", address, part),
+            None => defmt::info!("  {=u8:#04x}: unknown", address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::i2c::{Mock, Transaction};
+    use embedded_hal_mock::MockError;
+    use std::io::ErrorKind;
+
+    /// A bus on which only the devices at `present` acknowledge their address.
+    fn scan_with(present: &[u8]) -> Devices {
+        let transactions: Vec<_> = (FIRST_ADDRESS..=LAST_ADDRESS)
+            .map(|address| {
+                let read = Transaction::read(address, vec![0]);
+                if present.contains(&address) {
+                    read
+                } else {
+                    read.with_error(MockError::Io(ErrorKind::Other))
+                }
+            })
+            .collect();
+        let mut bus = Mock::new(&transactions);
+        let devices = scan(&mut bus);
+        bus.done();
+        devices
+    }
+
+    #[test]
+    fn responding_devices_are_listed() {
+        let devices = scan_with(&[0x3C, 0x29]);
+        assert_eq!(devices.len(), 2);
+        assert!(devices.contains(0x29) && devices.contains(0x3C));
+        assert_eq!(devices.to_string(), "0x29,0x3c");
+    }
+
+    #[test]
+    fn empty_bus() {
+        let devices = scan_with(&[]);
+        assert!(devices.is_empty());
+        assert_eq!(devices.to_string(), "-");
+    }
+}
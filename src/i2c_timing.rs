@@ -0,0 +1,120 @@
+//! Validation of the speed of the I2C buses (see [`crate::config::i2c`]) against the clock of the
+//! peripherals & the timing which results from it, which is logged at boot.
+//!
+//! The timing is calculated like the HAL configures the peripheral: the SCL period is a multiple
+//! of the period of the APB1 clock, thus the effective speed may differ slightly from the
+//! requested one. Standard mode (up to 100 kHz) has a duty cycle of 1:1, fast mode one of 2:1.
+//! It doesn't depend on the hardware, thus it's tested on the host.
+
+/// Speed up to which the standard mode is used.
+pub const STANDARD_MAX_HZ: u32 = 100_000;
+/// Highest speed of the fast mode.
+pub const FAST_MAX_HZ: u32 = 400_000;
+/// The range of the APB1 clock supported by the peripheral, the fast mode needs at least 4 MHz.
+const MIN_PCLK_HZ: u32 = 2_000_000;
+const MIN_FAST_PCLK_HZ: u32 = 4_000_000;
+const MAX_PCLK_HZ: u32 = 50_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Mode {
+    Standard,
+    Fast,
+}
+
+/// The effective timing of a bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Timing {
+    pub mode: Mode,
+    /// The speed without the rise time of the bus, which lowers it further.
+    pub speed_hz: u32,
+    pub high_ns: u32,
+    pub low_ns: u32,
+}
+
+/// Reasons why a speed can't be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum TimingError {
+    /// The speed is higher than the one of the fast mode (or 0).
+    UnsupportedSpeed,
+    /// The APB1 clock is outside of the range supported by the peripheral (in this mode).
+    UnsupportedClock,
+}
+
+/// Validate the requested speed & calculate the timing, with the APB1 clock at `pclk_hz`.
+pub fn timing(pclk_hz: u32, speed_hz: u32) -> Result<Timing, TimingError> {
+    if speed_hz == 0 || speed_hz > FAST_MAX_HZ {
+        return Err(TimingError::UnsupportedSpeed);
+    }
+    let mode = if speed_hz <= STANDARD_MAX_HZ {
+        Mode::Standard
+    } else {
+        Mode::Fast
+    };
+    let min_pclk_hz = match mode {
+        Mode::Standard => MIN_PCLK_HZ,
+        Mode::Fast => MIN_FAST_PCLK_HZ,
+    };
+    if !(min_pclk_hz..=MAX_PCLK_HZ).contains(&pclk_hz) {
+        return Err(TimingError::UnsupportedClock);
+    }
+    // the divider of the clock (CCR) for the high time
+    let (ccr, low_periods) = match mode {
+        Mode::Standard => ((pclk_hz / (2 * speed_hz)).max(4), 1),
+        Mode::Fast => ((pclk_hz / (3 * speed_hz)).max(1), 2),
+    };
+    let period_ns = |ticks: u32| (ticks as u64 * 1_000_000_000 / pclk_hz as u64) as u32;
+    Ok(Timing {
+        mode,
+        speed_hz: pclk_hz / ((1 + low_periods) * ccr),
+        high_ns: period_ns(ccr),
+        low_ns: period_ns(low_periods * ccr),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The APB1 clock of the firmware.
+    const PCLK_HZ: u32 = 42_000_000;
+
+    #[test]
+    fn standard_mode() {
+        let timing = timing(PCLK_HZ, 100_000).unwrap();
+        assert_eq!(timing.mode, Mode::Standard);
+        assert_eq!(timing.speed_hz, 100_000);
+        assert_eq!((timing.high_ns, timing.low_ns), (5_000, 5_000));
+    }
+
+    #[test]
+    fn fast_mode() {
+        let timing = timing(PCLK_HZ, 400_000).unwrap();
+        assert_eq!(timing.mode, Mode::Fast);
+        assert_eq!(timing.speed_hz, 400_000);
+        assert_eq!(timing.low_ns, 2 * timing.high_ns);
+    }
+
+    #[test]
+    fn speed_depends_on_the_divider() {
+        // the divider is rounded down from 33.3
+        assert_eq!(timing(40_000_000, 400_000).unwrap().speed_hz, 404_040);
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        assert_eq!(
+            timing(PCLK_HZ, 1_000_000),
+            Err(TimingError::UnsupportedSpeed)
+        );
+        assert_eq!(timing(PCLK_HZ, 0), Err(TimingError::UnsupportedSpeed));
+        assert_eq!(
+            timing(3_000_000, 400_000),
+            Err(TimingError::UnsupportedClock)
+        );
+        assert!(timing(3_000_000, 100_000).is_ok());
+        assert_eq!(
+            timing(84_000_000, 100_000),
+            Err(TimingError::UnsupportedClock)
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! Tracing of the register accesses of the TOF sensor (`i2c-trace` feature), e.g. to compare them
+//! with the reference traces of ST when porting to another breakout.
+//!
+//! [`TracedBus`] wraps the bus of the sensor & logs each transaction at the trace level (which has
+//! to be enabled with `DEFMT_LOG`): the address, the register, the bytes written or read, the
+//! duration (measured with the cycle counter of the DWT) & whether it failed. At most
+//! [`config::MAX_PER_S`] transactions per second are traced so that the log keeps up, the number
+//! of the skipped ones is logged with the next traced one.
+
+use crate::config::i2c_trace as config;
+use crate::range_sensor::{ReadShortIndex, WriteShortIndex};
+use cortex_m::peripheral::DWT;
+use vl53l1x_uld::comm::{Read, Write};
+
+/// Limits the traced transactions to a maximum per window.
+struct RateLimit {
+    max: u32,
+    window_cycles: u32,
+    window_start: u32,
+    traced: u32,
+    skipped: u32,
+}
+
+impl RateLimit {
+    const fn new(max: u32, window_cycles: u32) -> Self {
+        Self {
+            max,
+            window_cycles,
+            window_start: 0,
+            traced: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Whether the transaction at `now_cycles` is traced. Once a new window has started, the
+    /// number of transactions skipped before is returned as well.
+    fn admit(&mut self, now_cycles: u32) -> (bool, Option<u32>) {
+        let mut skipped = None;
+        // the cycle counter wraps around within a minute, thus a window might last longer after a
+        // long pause
+        if now_cycles.wrapping_sub(self.window_start) >= self.window_cycles {
+            self.window_start = now_cycles;
+            self.traced = 0;
+            skipped = Some(core::mem::take(&mut self.skipped)).filter(|&skipped| skipped > 0);
+        }
+        if self.traced < self.max {
+            self.traced += 1;
+            (true, skipped)
+        } else {
+            self.skipped += 1;
+            (false, skipped)
+        }
+    }
+}
+
+/// A bus to the TOF sensor whose transactions are traced.
+pub struct TracedBus<B> {
+    bus: B,
+    cycles_per_us: u32,
+    limit: RateLimit,
+}
+
+impl<B> TracedBus<B> {
+    /// Trace the transactions on `bus`, `sysclk_hz` is the frequency of the cycle counter.
+    pub fn new(bus: B, sysclk_hz: u32) -> Self {
+        Self {
+            bus,
+            cycles_per_us: sysclk_hz / 1_000_000,
+            limit: RateLimit::new(config::MAX_PER_S, sysclk_hz),
+        }
+    }
+
+    /// Run the transaction & return its result with its duration in µs, if it's traced.
+    fn time<T>(&mut self, transaction: impl FnOnce(&mut B) -> T) -> (T, Option<u32>) {
+        let start = DWT::cycle_count();
+        let result = transaction(&mut self.bus);
+        let (traced, skipped) = self.limit.admit(start);
+        if let Some(skipped) = skipped {
+            defmt::trace!("i2c: {} transactions not traced", skipped);
+        }
+        let duration_us = DWT::cycle_count().wrapping_sub(start) / self.cycles_per_us;
+        (result, traced.then_some(duration_us))
+    }
+}
+
+impl<B: Write> Write for TracedBus<B> {
+    type Error = B::Error;
+
+    fn write_registers(
+        &mut self,
+        address: u8,
+        register: [u8; 2],
+        bytes: &[u8],
+    ) -> Result<(), Self::Error> {
+        let (result, duration_us) = self.time(|bus| bus.write_registers(address, register, bytes));
+        if let Some(duration_us) = duration_us {
+            defmt::trace!(
+                "i2c: write {=u8:#04x} {=u16:#06x} {=[u8]:x} {} us{}",
+                address,
+                u16::from_be_bytes(register),
+                bytes,
+                duration_us,
+                if result.is_ok() { "" } else { " failed" }
+            );
+        }
+        result
+    }
+}
+
+impl<B: Read> Read for TracedBus<B> {
+    type Error = B::Error;
+
+    fn read_registers(
+        &mut self,
+        address: u8,
+        register: [u8; 2],
+        bytes: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let (result, duration_us) = self.time(|bus| bus.read_registers(address, register, bytes));
+        if let Some(duration_us) = duration_us {
+            defmt::trace!(
+                "i2c: read {=u8:#04x} {=u16:#06x} {=[u8]:x} {} us{}",
+                address,
+                u16::from_be_bytes(register),
+                bytes,
+                duration_us,
+                if result.is_ok() { "" } else { " failed" }
+            );
+        }
+        result
+    }
+}
+
+impl<B: ReadShortIndex> ReadShortIndex for TracedBus<B> {
+    type Error = B::Error;
+
+    fn read_short_index(
+        &mut self,
+        address: u8,
+        index: u8,
+        bytes: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let (result, duration_us) = self.time(|bus| bus.read_short_index(address, index, bytes));
+        if let Some(duration_us) = duration_us {
+            defmt::trace!(
+                "i2c: read {=u8:#04x} {=u8:#04x} {=[u8]:x} {} us{}",
+                address,
+                index,
+                bytes,
+                duration_us,
+                if result.is_ok() { "" } else { " failed" }
+            );
+        }
+        result
+    }
+}
+
+impl<B: WriteShortIndex> WriteShortIndex for TracedBus<B> {
+    type Error = B::Error;
+
+    fn write_short_index(
+        &mut self,
+        address: u8,
+        index: u8,
+        bytes: &[u8],
+    ) -> Result<(), Self::Error> {
+        let (result, duration_us) = self.time(|bus| bus.write_short_index(address, index, bytes));
+        if let Some(duration_us) = duration_us {
+            defmt::trace!(
+                "i2c: write {=u8:#04x} {=u8:#04x} {=[u8]:x} {} us{}",
+                address,
+                index,
+                bytes,
+                duration_us,
+                if result.is_ok() { "" } else { " failed" }
+            );
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_the_traces_per_window() {
+        let mut limit = RateLimit::new(2, 1_000);
+        let admitted = [0, 10, 20, 30].map(|cycles| limit.admit(cycles));
+        assert_eq!(
+            admitted,
+            [(true, None), (true, None), (false, None), (false, None)]
+        );
+        // the skipped ones are reported with the next window, also across the wrap-around
+        let mut limit = RateLimit::new(1, 1_000);
+        limit.window_start = u32::MAX - 100;
+        assert_eq!(limit.admit(u32::MAX), (true, None));
+        assert_eq!(limit.admit(0), (false, None));
+        assert_eq!(limit.admit(900), (true, Some(1)));
+        assert_eq!(limit.admit(950), (false, None));
+    }
+}
@@ -0,0 +1,80 @@
+//! Self-check of the firmware image in the flash, so that a corrupted image (e.g. by a failed
+//! update) doesn't operate the outputs.
+//!
+//! The image (everything which is loaded into the flash from the vector table up to the end of the
+//! initial values of `.data`) is followed by the CRC of it, which is appended to the binary by
+//! `tof-host sign`. The CRC is calculated by the CRC peripheral: CRC-32/MPEG-2 (polynomial
+//! `0x04C1_1DB7`, no reflection, no final XOR) over the little endian words of the image. Images
+//! without a CRC (e.g. flashed by `probe-run`, which leaves the following word erased) aren't
+//! checked.
+
+use crate::storage;
+use stm32f4xx_hal::crc32::Crc32;
+use stm32f4xx_hal::flash::FlashExt;
+use stm32f4xx_hal::pac::FLASH;
+
+extern "C" {
+    // provided by the linker script of cortex-m-rt
+    static __vector_table: u32;
+    static __sdata: u32;
+    static __edata: u32;
+    static __sidata: u32;
+}
+
+/// The result of the self-check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ImageState {
+    /// The CRC of the image matches.
+    Valid,
+    /// No CRC has been appended to the image.
+    Unsigned,
+    /// The CRC of the image doesn't match.
+    Corrupt,
+}
+
+impl ImageState {
+    /// Check the image in the flash.
+    pub fn check(flash: &FLASH, crc: &mut Crc32) -> Self {
+        let flash = flash.read();
+        let data_len =
+            core::ptr::addr_of!(__edata) as usize - core::ptr::addr_of!(__sdata) as usize;
+        // the image doesn't start at the start of the flash if it runs from slot B of the
+        // `firmware_update`
+        let start = core::ptr::addr_of!(__vector_table) as usize - flash.as_ptr() as usize;
+        let len = core::ptr::addr_of!(__sidata) as usize - flash.as_ptr() as usize + data_len;
+        match flash.get(start..len + 4) {
+            Some(image) => Self::verify(image, crc),
+            None => ImageState::Corrupt,
+        }
+    }
+
+    /// Check an image which is followed by its CRC (e.g. a signed binary).
+    pub fn verify(image: &[u8], crc: &mut Crc32) -> Self {
+        let Some((expected, image)) = image
+            .len()
+            .checked_sub(4)
+            .map(|len| (&image[len..], &image[..len]))
+        else {
+            return ImageState::Corrupt;
+        };
+        if storage::is_erased(expected) {
+            return ImageState::Unsigned;
+        }
+        let expected = u32::from_le_bytes([expected[0], expected[1], expected[2], expected[3]]);
+        crc.init();
+        if crc.update_bytes(image) == expected {
+            ImageState::Valid
+        } else {
+            ImageState::Corrupt
+        }
+    }
+
+    /// The name used in the response to the `status` command.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImageState::Valid => "ok",
+            ImageState::Unsigned => "unsigned",
+            ImageState::Corrupt => "corrupt",
+        }
+    }
+}
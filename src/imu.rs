@@ -0,0 +1,88 @@
+//! Tilt compensation using a MPU6050 IMU connected to the shared I2C bus.
+//!
+//! The IMU must be mounted rigidly to the TOF sensor. The direction of gravity measured by its
+//! accelerometer gives the tilt of the sensor, which is used to split the measured (slant) distance
+//! into its vertical and horizontal part.
+
+use crate::config::imu as config;
+use crate::I2cBus;
+use stm32f4xx_hal::hal::blocking::i2c::{Write, WriteRead};
+
+/// I2C address of the MPU6050 with `AD0` low.
+const ADDRESS: u8 = 0x68;
+
+const REG_CONFIG: u8 = 0x1A;
+const REG_ACCEL_CONFIG: u8 = 0x1C;
+const REG_ACCEL_XOUT_H: u8 = 0x3B;
+const REG_PWR_MGMT_1: u8 = 0x6B;
+const REG_WHO_AM_I: u8 = 0x75;
+
+/// Axis of the IMU along which the TOF sensor is pointing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+/// The measured distance split up according to the tilt of the sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Components {
+    pub vertical_mm: u16,
+    pub horizontal_mm: u16,
+}
+
+pub struct Imu {
+    i2c: I2cBus,
+}
+
+impl Imu {
+    /// Set up the IMU. Returns `None` if no IMU is connected.
+    pub fn new(mut i2c: I2cBus) -> Option<Self> {
+        let mut who_am_i = [0];
+        if i2c
+            .write_read(ADDRESS, &[REG_WHO_AM_I], &mut who_am_i)
+            .is_err()
+            || who_am_i[0] != ADDRESS
+        {
+            defmt::warn!("no MPU6050 found");
+            return None;
+        }
+        // wake up using the PLL of the X gyro as clock, ±2 g range & 5 Hz low-pass filter to
+        // suppress vibrations
+        for (register, value) in [
+            (REG_PWR_MGMT_1, 0x01),
+            (REG_ACCEL_CONFIG, 0x00),
+            (REG_CONFIG, 0x06),
+        ] {
+            i2c.write(ADDRESS, &[register, value]).ok()?;
+        }
+        defmt::trace!("MPU6050 set up");
+        Some(Self { i2c })
+    }
+
+    /// Split the distance into its vertical & horizontal part based on the current tilt.
+    pub fn components(&mut self, distance_mm: u16) -> Option<Components> {
+        let mut data = [0; 6];
+        self.i2c
+            .write_read(ADDRESS, &[REG_ACCEL_XOUT_H], &mut data)
+            .ok()?;
+        let [x, y, z] = [0, 2, 4].map(|i| i16::from_be_bytes([data[i], data[i + 1]]) as i64);
+        let (along, across) = match config::AXIS {
+            Axis::X => (x, [y, z]),
+            Axis::Y => (y, [x, z]),
+            Axis::Z => (z, [x, y]),
+        };
+        let across_squared = across[0] * across[0] + across[1] * across[1];
+        let total = (along * along + across_squared).isqrt();
+        if total == 0 {
+            // free fall, the tilt is unknown
+            return None;
+        }
+        let component = |value: i64| (distance_mm as i64 * value / total) as u16;
+        Some(Components {
+            vertical_mm: component(along.abs()),
+            horizontal_mm: component(across_squared.isqrt()),
+        })
+    }
+}
@@ -0,0 +1,72 @@
+//! All local inputs which are sampled together with each measurement.
+//!
+//! The inputs are combined into a single struct so that the tasks don't need to know which inputs
+//! have been enabled.
+
+#[cfg(feature = "encoder")]
+use crate::encoder::Encoder;
+#[cfg(feature = "environment")]
+use crate::environment::EnvironmentSensor;
+#[cfg(feature = "imu")]
+use crate::imu::Imu;
+use crate::telemetry::Measurement;
+#[cfg(feature = "ultrasonic")]
+use crate::ultrasonic::Ultrasonic;
+
+pub struct Inputs {
+    #[cfg(feature = "encoder")]
+    pub encoder: Encoder,
+    #[cfg(feature = "imu")]
+    pub imu: Option<Imu>,
+    #[cfg(feature = "environment")]
+    pub environment: Option<EnvironmentSensor>,
+    #[cfg(feature = "ultrasonic")]
+    pub ultrasonic: Ultrasonic,
+}
+
+impl Inputs {
+    /// Add the current state of the inputs to a new measurement.
+    pub fn sample(&mut self, measurement: &mut Measurement) {
+        #[cfg(feature = "encoder")]
+        {
+            measurement.position = self.encoder.position();
+        }
+        #[cfg(feature = "imu")]
+        {
+            measurement.components = self
+                .imu
+                .as_mut()
+                .and_then(|imu| imu.components(measurement.distance_mm));
+        }
+        #[cfg(feature = "environment")]
+        {
+            measurement.environment = self.environment.as_mut().and_then(|sensor| sensor.read());
+        }
+        #[cfg(feature = "ultrasonic")]
+        {
+            measurement.acoustic_mm = self.ultrasonic.sample(measurement.timestamp_ms);
+            let optical = (measurement.status == vl53l1x_uld::RangeStatus::Valid)
+                .then_some(measurement.distance_mm);
+            measurement.fused = crate::fusion::fuse(optical, measurement.acoustic_mm);
+        }
+        #[cfg(not(any(
+            feature = "encoder",
+            feature = "imu",
+            feature = "environment",
+            feature = "ultrasonic"
+        )))]
+        let _ = measurement;
+    }
+
+    /// Whether the temperature calibration of the TOF sensor should be repeated as the temperature
+    /// in the sampled measurement has changed too much.
+    pub fn needs_tof_calibration(&mut self, measurement: &Measurement) -> bool {
+        #[cfg(feature = "environment")]
+        if let (Some(sensor), Some(environment)) = (&mut self.environment, &measurement.environment)
+        {
+            return sensor.needs_tof_calibration(environment);
+        }
+        let _ = measurement;
+        false
+    }
+}
@@ -0,0 +1,82 @@
+//! Time-interleaved ranging of TOF sensors with overlapping fields of view: each one would see the
+//! light emitted by the others (optical crosstalk). With `acquisition interleaved` (with the
+//! `second-tof` feature) the [`crate::cadence`] opens a window for one sensor after the other &
+//! each sensor ranges a single measurement in its window, thus only one of them emits at a time.
+//!
+//! The windows are as long as the inter-measurement period, each sensor thus measures once every
+//! `sensors` periods. A sensor which hasn't finished its measurement when the window of the next
+//! one opens (e.g. because the timing budget is too long) overlaps with it, this is counted.
+
+/// The order of the windows of the sensors, sensor 0 being the main TOF sensor.
+#[derive(Debug, Clone, PartialEq, Eq, defmt::Format)]
+pub struct Interleaver {
+    sensors: u8,
+    /// The sensor of the current window, `None` before the first one.
+    current: Option<u8>,
+    /// Whether the sensor of the current window is still measuring.
+    measuring: bool,
+    overlaps: u32,
+}
+
+impl Interleaver {
+    pub fn new(sensors: u8) -> Self {
+        Self {
+            sensors,
+            current: None,
+            measuring: false,
+            overlaps: 0,
+        }
+    }
+
+    /// Start over with the window of sensor 0.
+    pub fn reset(&mut self) {
+        self.current = None;
+        self.measuring = false;
+    }
+
+    /// Open the window of the next sensor, which has to start its measurement. Returns its index
+    /// & whether the previous one is still measuring (& thus overlaps with it).
+    pub fn next_window(&mut self) -> (u8, bool) {
+        let overlap = self.measuring;
+        if overlap {
+            self.overlaps = self.overlaps.wrapping_add(1);
+        }
+        let next = self
+            .current
+            .map_or(0, |current| (current + 1) % self.sensors);
+        self.current = Some(next);
+        self.measuring = true;
+        (next, overlap)
+    }
+
+    /// The `sensor` has finished its measurement (or failed to start it).
+    pub fn finish(&mut self, sensor: u8) {
+        if self.current == Some(sensor) {
+            self.measuring = false;
+        }
+    }
+
+    /// Number of windows which have overlapped with the measurement of the previous sensor.
+    pub fn overlaps(&self) -> u32 {
+        self.overlaps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alternates_between_the_sensors() {
+        let mut interleaver = Interleaver::new(2);
+        assert_eq!(interleaver.next_window(), (0, false));
+        interleaver.finish(0);
+        assert_eq!(interleaver.next_window(), (1, false));
+        // a late measurement of the previous window doesn't finish the current one
+        interleaver.finish(0);
+        assert_eq!(interleaver.next_window(), (0, true));
+        assert_eq!(interleaver.overlaps(), 1);
+        interleaver.reset();
+        assert_eq!(interleaver.next_window(), (0, false));
+    }
+}
@@ -0,0 +1,209 @@
+//! Protection against a storm of data ready interrupts, e.g. from a floating or noisy interrupt
+//! line, which would otherwise keep the firmware busy reading the TOF sensor.
+//!
+//! The sensor delivers a measurement at most every [`MIN_INTERVAL_MS`]: further interrupts within
+//! that time are coalesced, i.e. only a single deferred read is scheduled for the end of it, the
+//! others are suppressed. If there are more than [`config::MAX_RATE_HZ`] interrupts within a
+//! second, the line can't be trusted: the guard trips & the firmware falls back to
+//! [`Acquisition::Polled`](crate::acquisition::Acquisition::Polled) with the line disabled until
+//! the acquisition is switched back to interrupts. The suppressed interrupts are counted since
+//! boot & reported in the health report.
+//!
+//! An interrupt after which the sensor has no new measurement (e.g. a glitch on the line, an
+//! interrupt which hasn't been cleared or the wrong polarity, which fires once the sensor clears
+//! it) is a duplicate: it's counted separately from the reads & its share of the interrupts is
+//! reported by `status` & the summary. A warning is logged once it exceeds
+//! [`SUSPICIOUS_DUPLICATE_PERCENT`], which points at a misconfiguration of the line.
+
+use crate::config::interrupt_guard as config;
+
+/// Less than the shortest interval of the measurements, the timing budget of the sensor is at
+/// least 15 ms.
+pub const MIN_INTERVAL_MS: u32 = 10;
+/// The window in which the rate of the interrupts is measured.
+const RATE_WINDOW_MS: u32 = 1_000;
+/// Share of the duplicate interrupts above which the line is likely misconfigured.
+pub const SUSPICIOUS_DUPLICATE_PERCENT: u32 = 20;
+/// Number of interrupts before the share of the duplicates is judged.
+const MIN_JUDGED_INTERRUPTS: u32 = 20;
+
+/// How an interrupt is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Verdict {
+    /// Read the measurement now.
+    Handle,
+    /// Read the measurement after this delay, once the burst is over.
+    Defer(u32),
+    /// Ignore the interrupt, a read is already pending.
+    Suppress,
+    /// Too many interrupts, fall back to polling.
+    Trip,
+}
+
+pub struct InterruptGuard {
+    /// Time since boot of the latest read.
+    read_ms: Option<u32>,
+    /// Whether a deferred read is pending.
+    deferred: bool,
+    window_start_ms: u32,
+    /// Number of interrupts in the current window.
+    window_count: u32,
+    /// Number of suppressed interrupts since boot.
+    suppressed: u32,
+    /// Number of reads of the sensor since boot.
+    reads: u32,
+    /// Number of duplicate interrupts since boot.
+    duplicates: u32,
+    /// Whether the warning about the duplicates has been given.
+    duplicates_reported: bool,
+    tripped: bool,
+}
+
+impl InterruptGuard {
+    pub fn new() -> Self {
+        Self {
+            read_ms: None,
+            deferred: false,
+            window_start_ms: 0,
+            window_count: 0,
+            suppressed: 0,
+            reads: 0,
+            duplicates: 0,
+            duplicates_reported: false,
+            tripped: false,
+        }
+    }
+
+    /// Number of suppressed (or coalesced) interrupts since boot.
+    pub fn suppressed(&self) -> u32 {
+        self.suppressed
+    }
+
+    /// Number of duplicate interrupts since boot.
+    pub fn duplicates(&self) -> u32 {
+        self.duplicates
+    }
+
+    /// Share of the duplicates of all the interrupts (duplicates & reads) since boot in percent.
+    pub fn duplicate_percent(&self) -> u32 {
+        let total = u64::from(self.duplicates) + u64::from(self.reads);
+        if total == 0 {
+            return 0;
+        }
+        (u64::from(self.duplicates) * 100 / total) as u32
+    }
+
+    /// Whether the guard has tripped & the line is disabled.
+    pub fn is_tripped(&self) -> bool {
+        self.tripped
+    }
+
+    /// Decide how to handle an interrupt of the line at `now_ms`.
+    pub fn on_interrupt(&mut self, now_ms: u32) -> Verdict {
+        if self.tripped {
+            self.suppressed = self.suppressed.wrapping_add(1);
+            return Verdict::Suppress;
+        }
+        if now_ms.wrapping_sub(self.window_start_ms) >= RATE_WINDOW_MS {
+            self.window_start_ms = now_ms;
+            self.window_count = 0;
+        }
+        self.window_count += 1;
+        if self.window_count > config::MAX_RATE_HZ {
+            self.tripped = true;
+            self.suppressed = self.suppressed.wrapping_add(1);
+            return Verdict::Trip;
+        }
+        let elapsed_ms = self.read_ms.map(|read_ms| now_ms.wrapping_sub(read_ms));
+        match elapsed_ms {
+            Some(elapsed_ms) if elapsed_ms < MIN_INTERVAL_MS => {
+                self.suppressed = self.suppressed.wrapping_add(1);
+                if self.deferred {
+                    Verdict::Suppress
+                } else {
+                    self.deferred = true;
+                    Verdict::Defer(MIN_INTERVAL_MS - elapsed_ms)
+                }
+            }
+            _ => Verdict::Handle,
+        }
+    }
+
+    /// Record a read of the sensor at `now_ms` (handled, deferred or polled).
+    pub fn on_read(&mut self, now_ms: u32) {
+        self.read_ms = Some(now_ms);
+        self.deferred = false;
+        self.reads = self.reads.wrapping_add(1);
+    }
+
+    /// Record an interrupt after which the sensor had no new measurement. Returns `true` once, when
+    /// the share of the duplicates has become suspicious.
+    pub fn on_duplicate(&mut self) -> bool {
+        self.duplicates = self.duplicates.wrapping_add(1);
+        let suspicious = self.duplicates.saturating_add(self.reads) >= MIN_JUDGED_INTERRUPTS
+            && self.duplicate_percent() > SUSPICIOUS_DUPLICATE_PERCENT;
+        let report = suspicious && !self.duplicates_reported;
+        self.duplicates_reported |= suspicious;
+        report
+    }
+
+    /// Arm the guard again once the line is enabled again.
+    pub fn reset(&mut self) {
+        self.tripped = false;
+        self.deferred = false;
+        self.window_count = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regular_interrupts_are_handled() {
+        let mut guard = InterruptGuard::new();
+        for now_ms in (0..1_000).step_by(20) {
+            assert_eq!(guard.on_interrupt(now_ms), Verdict::Handle);
+            guard.on_read(now_ms);
+        }
+        assert_eq!(guard.suppressed(), 0);
+    }
+
+    #[test]
+    fn bursts_are_coalesced() {
+        let mut guard = InterruptGuard::new();
+        assert_eq!(guard.on_interrupt(100), Verdict::Handle);
+        guard.on_read(100);
+        assert_eq!(guard.on_interrupt(103), Verdict::Defer(7));
+        assert_eq!(guard.on_interrupt(104), Verdict::Suppress);
+        guard.on_read(110);
+        assert_eq!(guard.on_interrupt(130), Verdict::Handle);
+        assert_eq!(guard.suppressed(), 2);
+    }
+
+    #[test]
+    fn duplicates_are_reported_once() {
+        let mut guard = InterruptGuard::new();
+        for now_ms in (0..300).step_by(20) {
+            guard.on_read(now_ms);
+        }
+        // fewer than 20 interrupts aren't judged yet
+        assert!(!(0..4).any(|_| guard.on_duplicate()));
+        assert_eq!((guard.duplicates(), guard.duplicate_percent()), (4, 21));
+        assert!(guard.on_duplicate());
+        assert!(!guard.on_duplicate());
+    }
+
+    #[test]
+    fn storm_trips_the_guard() {
+        let mut guard = InterruptGuard::new();
+        let verdicts: Vec<_> = (0..=config::MAX_RATE_HZ)
+            .map(|i| guard.on_interrupt(i / 2))
+            .collect();
+        assert_eq!(verdicts.last(), Some(&Verdict::Trip));
+        assert!(guard.is_tripped());
+        assert_eq!(guard.on_interrupt(2_000), Verdict::Suppress);
+        guard.reset();
+        assert_eq!(guard.on_interrupt(3_000), Verdict::Handle);
+    }
+}
@@ -0,0 +1,51 @@
+//! Detection of regressions in the execution time of the data ready interrupt
+//! (`tof_interrupt_triggered`), which delays all other tasks at its priority: each invocation is
+//! measured with the cycle counter of the DWT & a warning is logged whenever it takes longer than
+//! [`crate::config::isr_budget::TOF_INTERRUPT_US`].
+//!
+//! The cycles include the time in which the interrupt has been preempted by a task at a higher
+//! priority (see [`crate::config::rt`]).
+
+pub struct IsrBudget {
+    budget_cycles: u32,
+    overruns: u32,
+}
+
+impl IsrBudget {
+    /// A budget of `budget_us` at a core clock of `sysclk_hz`.
+    pub fn new(budget_us: u32, sysclk_hz: u32) -> Self {
+        Self {
+            budget_cycles: (budget_us as u64 * sysclk_hz as u64 / 1_000_000) as u32,
+            overruns: 0,
+        }
+    }
+
+    pub fn budget_cycles(&self) -> u32 {
+        self.budget_cycles
+    }
+
+    /// Record an invocation which took `cycles`, returns the number of overruns since boot if it
+    /// has exceeded the budget.
+    pub fn check(&mut self, cycles: u32) -> Option<u32> {
+        if cycles <= self.budget_cycles {
+            return None;
+        }
+        self.overruns = self.overruns.wrapping_add(1);
+        Some(self.overruns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_the_overruns() {
+        let mut budget = IsrBudget::new(500, 84_000_000);
+        assert_eq!(budget.budget_cycles(), 42_000);
+        assert_eq!(budget.check(42_000), None);
+        assert_eq!(budget.check(42_001), Some(1));
+        assert_eq!(budget.check(10), None);
+        assert_eq!(budget.check(50_000), Some(2));
+    }
+}
@@ -0,0 +1,121 @@
+//! Statistics of the intervals between the measurements (their jitter), so that users of a
+//! control loop can verify that the cadence of the sensor is stable enough for them.
+//!
+//! The intervals are measured in µs when the data ready interrupt (or the poll) is handled, thus
+//! they include the latency of the firmware; while polling they're a multiple of the poll interval.
+//! Gaps longer than [`MAX_INTERVAL_US`] (e.g. while ranging is stopped) aren't intervals. The
+//! statistics are collected since boot or the latest `jitter reset`.
+
+/// The longest interval, longer than the longest inter-measurement period.
+pub const MAX_INTERVAL_US: u32 = 5_000_000;
+
+/// A summary of the intervals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jitter {
+    pub intervals: u32,
+    pub min_us: u32,
+    pub max_us: u32,
+    pub mean_us: u32,
+    /// Standard deviation.
+    pub stddev_us: u32,
+}
+
+pub struct JitterStats {
+    /// Time of the latest measurement.
+    previous_us: Option<u32>,
+    intervals: u32,
+    min_us: u32,
+    max_us: u32,
+    sum_us: u64,
+    sum_squares: u128,
+}
+
+impl JitterStats {
+    pub const fn new() -> Self {
+        Self {
+            previous_us: None,
+            intervals: 0,
+            min_us: u32::MAX,
+            max_us: 0,
+            sum_us: 0,
+            sum_squares: 0,
+        }
+    }
+
+    /// Start collecting again.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Record a measurement at `now_us` (of a wrapping µs timer).
+    pub fn on_measurement(&mut self, now_us: u32) {
+        let previous_us = self.previous_us.replace(now_us);
+        let Some(interval_us) = previous_us.map(|previous_us| now_us.wrapping_sub(previous_us))
+        else {
+            return;
+        };
+        if interval_us > MAX_INTERVAL_US {
+            return;
+        }
+        self.intervals += 1;
+        self.min_us = self.min_us.min(interval_us);
+        self.max_us = self.max_us.max(interval_us);
+        self.sum_us += interval_us as u64;
+        self.sum_squares += interval_us as u128 * interval_us as u128;
+    }
+
+    /// The summary of the intervals, `None` if there hasn't been any.
+    pub fn summary(&self) -> Option<Jitter> {
+        if self.intervals == 0 {
+            return None;
+        }
+        let n = self.intervals as u128;
+        let sum = self.sum_us as u128;
+        // n² · variance, exact in integers
+        let scaled_variance = n * self.sum_squares - sum * sum;
+        Some(Jitter {
+            intervals: self.intervals,
+            min_us: self.min_us,
+            max_us: self.max_us,
+            mean_us: (sum / n) as u32,
+            stddev_us: (scaled_variance.isqrt() / n) as u32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statistics_of_the_intervals() {
+        let mut stats = JitterStats::new();
+        assert_eq!(stats.summary(), None);
+        for now_us in [0, 99_000, 200_000, 299_000, 400_000] {
+            stats.on_measurement(now_us);
+        }
+        assert_eq!(
+            stats.summary(),
+            Some(Jitter {
+                intervals: 4,
+                min_us: 99_000,
+                max_us: 101_000,
+                mean_us: 100_000,
+                stddev_us: 1_000,
+            })
+        );
+    }
+
+    #[test]
+    fn gaps_and_wrap_around() {
+        let mut stats = JitterStats::new();
+        stats.on_measurement(u32::MAX - 49_999);
+        stats.on_measurement(50_000);
+        // ranging has been stopped in between
+        stats.on_measurement(10_000_000);
+        stats.on_measurement(10_100_000);
+        let summary = stats.summary().unwrap();
+        assert_eq!((summary.intervals, summary.min_us), (2, 100_000));
+        assert_eq!(summary.stddev_us, 0);
+    }
+}
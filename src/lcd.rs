@@ -0,0 +1,104 @@
+//! Support for a 16x2 HD44780 character LCD with a PCF8574 I2C backpack connected to the shared I2C
+//! bus.
+//!
+//! The first line shows the distance, the second one the mode (ranging or stopped) and the range
+//! status if the latest measurement isn't valid. While the menu is open it shows the selected item
+//! & its value instead, the value is enclosed in brackets while it's being changed.
+
+use crate::display::{Display, DisplayState, MenuView, RenderError};
+use crate::I2cBus;
+use core::fmt::Write;
+use cortex_m::delay::Delay;
+use hd44780_driver::bus::I2CBus;
+use hd44780_driver::HD44780;
+use stm32f4xx_hal::hal::blocking::i2c;
+use vl53l1x_uld::RangeStatus;
+
+/// I2C address of the backpack, `0x27` for the PCF8574 & `0x3F` for the PCF8574A.
+const ADDRESS: u8 = 0x27;
+/// Number of characters per line.
+const COLUMNS: usize = 16;
+/// Address of the first character of the second line in the display RAM.
+const SECOND_LINE: u8 = 0x40;
+/// Bit of the backpack which switches on the backlight.
+const BACKLIGHT: u8 = 0x08;
+
+type Line = heapless::String<COLUMNS>;
+
+pub struct Lcd {
+    lcd: HD44780<I2CBus<I2cBus>>,
+    delay: Delay,
+}
+
+impl Lcd {
+    /// Set up the display. Returns `None` if no display is connected.
+    pub fn new(mut i2c: I2cBus, mut delay: Delay) -> Option<Self> {
+        // the driver ignores all I2C errors, thus check whether the backpack responds at all
+        if i2c::Write::write(&mut i2c, ADDRESS, &[BACKLIGHT]).is_err() {
+            defmt::warn!("no HD44780 display found");
+            return None;
+        }
+        let mut lcd = HD44780::new_i2c(i2c, ADDRESS, &mut delay).ok()?;
+        lcd.clear(&mut delay).ok()?;
+        defmt::trace!("LCD set up");
+        Some(Self { lcd, delay })
+    }
+
+    /// The lines showing the distance & the state.
+    fn status_lines(state: &DisplayState) -> (Line, Line) {
+        let mut first = Line::new();
+        match state.measurement.filter(|m| m.status == RangeStatus::Valid) {
+            Some(m) => write!(first, "Dist: {:>5} mm", m.distance_mm),
+            None => write!(first, "Dist:  ---- mm"),
+        }
+        .ok();
+
+        let mut second = Line::new();
+        let mode = if state.ranging { "RUN" } else { "STOP" };
+        match state.measurement.filter(|m| m.status != RangeStatus::Valid) {
+            Some(m) => write!(second, "{:<8}ERR S{}", mode, m.status as u8),
+            None => write!(second, "{}", mode),
+        }
+        .ok();
+        (first, second)
+    }
+
+    /// The lines showing the menu.
+    fn menu_lines(menu: &MenuView) -> (Line, Line) {
+        let mut first = Line::new();
+        write!(first, "> {}", menu.label).ok();
+        let mut second = Line::new();
+        if menu.editing {
+            second.push('[').ok();
+        }
+        menu.value.write_to(&mut second).ok();
+        if menu.editing {
+            second.push(']').ok();
+        }
+        (first, second)
+    }
+
+    fn write_line(&mut self, position: u8, line: &Line) -> Result<(), RenderError> {
+        self.lcd
+            .set_cursor_pos(position, &mut self.delay)
+            .and_then(|_| self.lcd.write_str(line, &mut self.delay))
+            .map_err(|_| RenderError)
+    }
+}
+
+impl Display for Lcd {
+    fn render(&mut self, state: &DisplayState) -> Result<(), RenderError> {
+        let (mut first, mut second) = match &state.menu {
+            Some(menu) => Self::menu_lines(menu),
+            None => Self::status_lines(state),
+        };
+
+        // both lines are padded to the full width to overwrite the previous content, this avoids
+        // the flickering caused by clearing the display
+        for line in [&mut first, &mut second] {
+            while line.push(' ').is_ok() {}
+        }
+        self.write_line(0, &first)?;
+        self.write_line(SECOND_LINE, &second)
+    }
+}
@@ -0,0 +1,100 @@
+//! Visualization of the distance on a WS2812 LED strip with its data input connected to `PA7`.
+//!
+//! The closer the target the more LEDs are lit, their color shows the zone (red = near, yellow =
+//! middle, green = far, see [`crate::config::led_strip`]). The strip is dark while the sensor
+//! isn't ranging and only the first LED is lit (blue) if the latest measurement isn't valid. The
+//! on-device menu isn't shown on the strip.
+//!
+//! The WS2812 protocol is generated using the MOSI output of SPI1: at 2.625 MHz three SPI bits (of
+//! 381 ns each) encode one bit for the LEDs, `0b100` for a 0 and `0b110` for a 1.
+
+use crate::config::led_strip as config;
+use crate::display::{Display, DisplayState, RenderError};
+use stm32f4xx_hal::gpio::PA7;
+use stm32f4xx_hal::hal::spi::MODE_0;
+use stm32f4xx_hal::pac::SPI1;
+use stm32f4xx_hal::prelude::*;
+use stm32f4xx_hal::rcc::Clocks;
+use stm32f4xx_hal::spi::{NoMiso, NoSck, Spi};
+use vl53l1x_uld::RangeStatus;
+
+/// SPI bytes needed for the 24 bits of one LED.
+const BYTES_PER_LED: usize = 9;
+/// The strip latches the data once the line has been low for at least 50 us (= 132 SPI bits).
+const RESET_BYTES: usize = 20;
+
+/// Color of a single LED.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rgb(u8, u8, u8);
+
+const OFF: Rgb = Rgb(0, 0, 0);
+
+pub struct LedStrip {
+    spi: Spi<SPI1>,
+    buffer: [u8; config::LEN * BYTES_PER_LED + RESET_BYTES],
+}
+
+impl LedStrip {
+    pub fn new(spi: SPI1, mosi: PA7, clocks: &Clocks) -> Self {
+        // the prescaler is rounded to 32, resulting in 2.625 MHz with an 84 MHz APB2 clock
+        let spi = Spi::new(
+            spi,
+            (NoSck::new(), NoMiso::new(), mosi),
+            MODE_0,
+            3.MHz(),
+            clocks,
+        );
+        defmt::trace!("LED strip set up");
+        Self {
+            spi,
+            buffer: [0; config::LEN * BYTES_PER_LED + RESET_BYTES],
+        }
+    }
+
+    /// Color of the LED at `index` (0 = first LED) for the given state.
+    fn color(state: &DisplayState, index: usize) -> Rgb {
+        let Some(measurement) = state.measurement.filter(|_| state.ranging) else {
+            return OFF;
+        };
+        if measurement.status != RangeStatus::Valid {
+            return if index == 0 {
+                Rgb(0, 0, config::BRIGHTNESS)
+            } else {
+                OFF
+            };
+        }
+
+        let distance = measurement.distance_mm.min(config::MAX_MM) as usize;
+        let max = config::MAX_MM as usize;
+        // rounded up so that at least one LED is lit as long as the target is within range
+        let lit = (config::LEN * (max - distance)).div_ceil(max);
+        if index >= lit {
+            OFF
+        } else if measurement.distance_mm <= config::NEAR_MM {
+            Rgb(config::BRIGHTNESS, 0, 0)
+        } else if measurement.distance_mm <= config::FAR_MM {
+            Rgb(config::BRIGHTNESS, config::BRIGHTNESS, 0)
+        } else {
+            Rgb(0, config::BRIGHTNESS, 0)
+        }
+    }
+}
+
+impl Display for LedStrip {
+    fn render(&mut self, state: &DisplayState) -> Result<(), RenderError> {
+        let leds = &mut self.buffer[..config::LEN * BYTES_PER_LED];
+        for (index, chunk) in leds.chunks_exact_mut(BYTES_PER_LED).enumerate() {
+            let Rgb(red, green, blue) = Self::color(state, index);
+            // the LEDs expect the colors in the order green, red, blue with the MSB first
+            let bits = (green as u32) << 16 | (red as u32) << 8 | blue as u32;
+            let mut encoded: u128 = 0;
+            for bit in (0..24).rev() {
+                let pattern = if bits & (1 << bit) != 0 { 0b110 } else { 0b100 };
+                encoded = encoded << 3 | pattern;
+            }
+            chunk.copy_from_slice(&encoded.to_be_bytes()[16 - BYTES_PER_LED..]);
+        }
+        // the reset bytes at the end of the buffer are never touched & remain 0
+        self.spi.write(&self.buffer).map_err(|_| RenderError)
+    }
+}
@@ -0,0 +1,142 @@
+//! Detection of a dirty cover glass (or lens) in front of the TOF sensor, so that an installed
+//! sensor can request cleaning before its measurements fail.
+//!
+//! Dirt on the glass reflects part of the emitted light back into the sensor (crosstalk) &
+//! attenuates the light returned by the target, thus the signal rate per SPAD of the valid
+//! measurements drifts down while the scene doesn't change. Its baseline is learnt from the
+//! first [`LEARN_SAMPLES`] valid measurements after boot & after each offset calibration, its
+//! long-term average is then tracked. Once the average has dropped by more than
+//! [`config::DIRTY_PERCENT`] of the baseline the glass is dirty, once it has recovered to less than
+//! half of that drop (e.g. after cleaning) it's clean again.
+
+use crate::config::lens as config;
+use crate::range_sensor::Reading;
+use vl53l1x_uld::RangeStatus;
+
+/// Number of valid measurements from which the baseline is learnt.
+pub const LEARN_SAMPLES: u32 = 500;
+/// Weight of the average, a measurement changes it by 1/1024 of its difference.
+const SMOOTHING: i64 = 1_024;
+/// Fixed point scale of the baseline & the average.
+const SCALE: i64 = 1_024;
+
+pub struct LensMonitor {
+    /// Number of measurements & sum of their signal while learning the baseline.
+    learnt: u32,
+    sum: u32,
+    /// The baseline & the average signal, scaled by [`SCALE`].
+    baseline: i64,
+    average: i64,
+    dirty: bool,
+}
+
+impl LensMonitor {
+    pub fn new() -> Self {
+        Self {
+            learnt: 0,
+            sum: 0,
+            baseline: 0,
+            average: 0,
+            dirty: false,
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// The average signal in percent of the baseline, `None` while it's learnt.
+    pub fn signal_percent(&self) -> Option<u32> {
+        (self.learnt >= LEARN_SAMPLES && self.baseline > 0)
+            .then(|| (self.average * 100 / self.baseline) as u32)
+    }
+
+    /// Learn the baseline again, e.g. after the glass has been replaced & the sensor calibrated.
+    pub fn reset(&mut self) {
+        *self = Self {
+            dirty: self.dirty,
+            ..Self::new()
+        };
+    }
+
+    /// Update with a measurement, returns the new state if it has changed.
+    pub fn update(&mut self, reading: &Reading) -> Option<bool> {
+        if reading.status != RangeStatus::Valid {
+            return None;
+        }
+        let signal = i64::from(reading.signal_per_spad_kcps) * SCALE;
+        if self.learnt < LEARN_SAMPLES {
+            self.learnt += 1;
+            self.sum += u32::from(reading.signal_per_spad_kcps);
+            if self.learnt == LEARN_SAMPLES {
+                self.baseline = i64::from(self.sum) * SCALE / i64::from(LEARN_SAMPLES);
+                self.average = self.baseline;
+            }
+            return None;
+        }
+        self.average += (signal - self.average) / SMOOTHING;
+
+        let drop_percent = 100 - self.signal_percent()? as i64;
+        let dirty = if self.dirty {
+            drop_percent > config::DIRTY_PERCENT as i64 / 2
+        } else {
+            drop_percent > config::DIRTY_PERCENT as i64
+        };
+        if dirty == self.dirty {
+            return None;
+        }
+        self.dirty = dirty;
+        Some(dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update_with(monitor: &mut LensMonitor, signal_per_spad_kcps: u16, count: u32) -> Vec<bool> {
+        let reading = Reading {
+            distance_mm: 1_000,
+            status: RangeStatus::Valid,
+            ambient_kcps: 0,
+            signal_per_spad_kcps,
+        };
+        (0..count)
+            .filter_map(|_| monitor.update(&reading))
+            .collect()
+    }
+
+    #[test]
+    fn stable_signal_is_clean() {
+        let mut monitor = LensMonitor::new();
+        assert!(update_with(&mut monitor, 800, LEARN_SAMPLES + 10_000).is_empty());
+        assert_eq!(monitor.signal_percent(), Some(100));
+    }
+
+    #[test]
+    fn dropping_signal_is_dirty_until_cleaned() {
+        let mut monitor = LensMonitor::new();
+        update_with(&mut monitor, 800, LEARN_SAMPLES);
+        // a drop of 25 % isn't enough
+        assert!(update_with(&mut monitor, 600, 10_000).is_empty());
+        assert_eq!(update_with(&mut monitor, 400, 10_000), [true]);
+        assert!(monitor.is_dirty());
+        assert_eq!(update_with(&mut monitor, 800, 10_000), [false]);
+    }
+
+    #[test]
+    fn invalid_measurements_are_ignored() {
+        let mut monitor = LensMonitor::new();
+        update_with(&mut monitor, 800, LEARN_SAMPLES);
+        let reading = Reading {
+            distance_mm: 0,
+            status: RangeStatus::SignalFailure,
+            ambient_kcps: 0,
+            signal_per_spad_kcps: 0,
+        };
+        for _ in 0..10_000 {
+            assert_eq!(monitor.update(&reading), None);
+        }
+        assert_eq!(monitor.signal_percent(), Some(100));
+    }
+}
@@ -0,0 +1,189 @@
+//! The hardware independent parts & the drivers of the firmware, shared by the application (in
+//! `main.rs`), the on-target tests (in `tests/`) & the host tests.
+
+#![deny(unsafe_code)]
+#![cfg_attr(not(test), no_std)]
+// the library only exists for the tests, it isn't an API for other crates
+#![allow(clippy::new_without_default)]
+
+pub mod acquisition;
+#[cfg(feature = "alarm-output")]
+pub mod alarm_output;
+pub mod ambient;
+#[cfg(feature = "analog-output")]
+pub mod analog_output;
+pub mod app_mode;
+pub mod batch;
+#[cfg(feature = "battery")]
+pub mod battery;
+pub mod blink_code;
+#[cfg(feature = "bluetooth")]
+pub mod bluetooth;
+pub mod board;
+pub mod boot_config;
+pub mod bootloader;
+pub mod brown_out;
+pub mod build_info;
+#[cfg(feature = "buzzer")]
+pub mod buzzer;
+pub mod cadence;
+pub mod calibration;
+pub mod calibration_store;
+pub mod calibration_wizard;
+pub mod capture;
+pub mod change_filter;
+pub mod clock;
+pub mod command;
+pub mod config;
+pub mod control_block;
+pub mod controls;
+pub mod cpu_load;
+pub mod data_log;
+#[cfg(feature = "demo")]
+pub mod demo;
+pub mod device_id;
+pub mod display;
+pub mod drift;
+pub mod eeprom;
+#[cfg(feature = "encoder")]
+pub mod encoder;
+#[cfg(feature = "environment")]
+pub mod environment;
+pub mod event_log;
+pub mod extremes;
+pub mod fast_mode;
+#[cfg(feature = "fault-injection")]
+pub mod fault_injection;
+#[cfg(feature = "littlefs")]
+pub mod file_system;
+pub mod filter;
+#[cfg(feature = "firmware-update")]
+pub mod firmware_update;
+#[cfg(all(feature = "nor-flash", not(feature = "littlefs")))]
+pub mod flash_log;
+pub mod fusion;
+pub mod geofence;
+#[cfg(feature = "gpio-expander")]
+pub mod gpio_expander;
+pub mod health;
+pub mod i2c_guard;
+pub mod i2c_scan;
+pub mod i2c_timing;
+#[cfg(feature = "i2c-trace")]
+pub mod i2c_trace;
+pub mod image_check;
+#[cfg(feature = "imu")]
+pub mod imu;
+pub mod inputs;
+pub mod interleave;
+pub mod interrupt_guard;
+pub mod isr_budget;
+pub mod jitter;
+#[cfg(feature = "display-hd44780")]
+pub mod lcd;
+#[cfg(feature = "led-strip")]
+pub mod led_strip;
+pub mod lens;
+pub mod links;
+pub mod log_backend;
+#[cfg(feature = "nor-flash")]
+pub mod log_compression;
+#[cfg(any(feature = "nor-flash", feature = "uart-binary"))]
+pub mod log_record;
+#[cfg(feature = "uart-loopback")]
+pub mod loopback;
+#[cfg(feature = "lora")]
+pub mod lora;
+pub mod low_power;
+#[cfg(feature = "marker-input")]
+pub mod marker;
+pub mod measurement_queue;
+#[cfg(feature = "menu")]
+pub mod menu;
+pub mod metrics;
+#[cfg(feature = "motor-pid")]
+pub mod motor;
+#[cfg(feature = "mqtt-sn")]
+pub mod mqtt_sn;
+#[cfg(feature = "display-ssd1306")]
+pub mod oled;
+pub mod option_bytes;
+pub mod outputs;
+#[cfg(any(feature = "panic-reset", feature = "panic-persist"))]
+pub mod panic_persist;
+pub mod pause;
+#[cfg(feature = "motor-pid")]
+pub mod pid;
+pub mod post;
+#[cfg(feature = "power")]
+pub mod power;
+pub mod preset;
+pub mod profile;
+#[cfg(feature = "proximity-led")]
+pub mod proximity_led;
+pub mod publish_interval;
+#[cfg(feature = "pwm-expander")]
+pub mod pwm_expander;
+pub mod range_gate;
+pub mod range_sensor;
+pub mod register_dump;
+pub mod reset_log;
+pub mod rollup;
+#[cfg(feature = "menu")]
+pub mod rotary;
+pub mod sample_history;
+pub mod scaling;
+pub mod schedule;
+#[cfg(feature = "sd-card")]
+pub mod sd_card;
+#[cfg(feature = "second-tof")]
+pub mod second_tof;
+pub mod sensor_supervisor;
+#[cfg(feature = "servo")]
+pub mod servo;
+pub mod session;
+pub mod settings;
+pub mod shutdown;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod sink;
+pub mod spawn_guard;
+pub mod status_led;
+#[cfg(feature = "stepper")]
+pub mod stepper;
+pub mod storage;
+pub mod task_watchdog;
+pub mod telemetry;
+#[cfg(feature = "threshold-pot")]
+pub mod threshold_pot;
+pub mod time_sync;
+pub mod tof;
+#[cfg(feature = "tof-mux")]
+pub mod tof_mux;
+pub mod track;
+pub mod trigger;
+pub mod uart;
+#[cfg(feature = "ultrasonic")]
+pub mod ultrasonic;
+#[cfg(feature = "usb")]
+pub mod usb;
+pub mod user_button;
+pub mod vl53l0x;
+#[cfg(feature = "nor-flash")]
+pub mod w25q;
+pub mod warm_up;
+pub mod watchdog_margin;
+pub mod webhook;
+#[cfg(feature = "wifi")]
+pub mod wifi;
+pub mod wrap_around;
+#[cfg(any(feature = "xmodem", feature = "firmware-update"))]
+pub mod xmodem;
+
+/// The I2C bus shared by the TOF sensor and all other I2C devices, with a timeout for each
+/// transaction (see [`i2c_guard`]).
+///
+/// All users of the bus run at the same priority and thus never preempt each other, which is
+/// verified by the [`shared_bus::AtomicCheckMutex`].
+pub type I2cBus =
+    shared_bus::I2cProxy<'static, shared_bus::AtomicCheckMutex<i2c_guard::GuardedI2c>>;
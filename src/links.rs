@@ -0,0 +1,199 @@
+//! All links to a host over which telemetry is sent and commands are received.
+//!
+//! The links are combined into a single struct so that the tasks don't need to know which links
+//! have been enabled. The telemetry is handed to the pipeline of all links & the other sinks, see
+//! [`crate::sink`].
+
+#[cfg(feature = "lora")]
+use crate::lora::LoraUplink;
+#[cfg(feature = "mqtt-sn")]
+use crate::mqtt_sn::MqttSnLink;
+#[cfg(feature = "uart-binary")]
+use crate::sink::BinaryUart;
+#[cfg(feature = "defmt-sink")]
+use crate::sink::DefmtSink;
+use crate::sink::Sink;
+use crate::telemetry::Measurement;
+#[cfg(any(not(feature = "mqtt-sn"), feature = "bluetooth"))]
+use crate::uart::UartLink;
+#[cfg(feature = "usb")]
+use crate::usb::UsbLink;
+#[cfg(feature = "wifi")]
+use crate::wifi::WifiUplink;
+#[cfg(not(feature = "mqtt-sn"))]
+use stm32f4xx_hal::pac::USART2;
+#[cfg(feature = "bluetooth")]
+use stm32f4xx_hal::pac::USART6;
+
+pub struct Links {
+    /// The virtual COM port provided by the ST-LINK.
+    #[cfg(not(feature = "mqtt-sn"))]
+    pub vcp: UartLink<USART2>,
+    /// The virtual COM port provided by the ST-LINK, speaking MQTT-SN to a gateway on the host.
+    #[cfg(feature = "mqtt-sn")]
+    pub vcp: MqttSnLink,
+    #[cfg(feature = "usb")]
+    pub usb: UsbLink,
+    /// A Bluetooth serial bridge module.
+    #[cfg(feature = "bluetooth")]
+    pub bluetooth: UartLink<USART6>,
+    /// Send-only uplink, doesn't receive commands.
+    #[cfg(feature = "wifi")]
+    pub wifi: WifiUplink,
+    /// Send-only uplink, doesn't receive commands. `None` if the radio couldn't be set up.
+    #[cfg(feature = "lora")]
+    pub lora: Option<LoraUplink>,
+    #[cfg(feature = "defmt-sink")]
+    pub defmt: DefmtSink,
+    /// Send-only, binary records instead of the frames.
+    #[cfg(feature = "uart-binary")]
+    pub binary: BinaryUart,
+}
+
+impl Links {
+    /// Best-effort write of the data to all links which accept commands. Data which can't be sent
+    /// right now is dropped.
+    pub fn write(&mut self, bytes: &[u8]) {
+        self.vcp.write(bytes);
+        #[cfg(feature = "usb")]
+        self.usb.write(bytes);
+        #[cfg(feature = "bluetooth")]
+        self.bluetooth.write(bytes);
+    }
+
+    /// Number of bytes which can currently be written to all links which accept commands without
+    /// dropping any. The USB link can't tell, it's assumed to keep up.
+    #[cfg_attr(not(feature = "nor-flash"), allow(dead_code))]
+    pub fn free_space(&self) -> usize {
+        let free_space = self.vcp.free_space();
+        #[cfg(feature = "bluetooth")]
+        let free_space = free_space.min(self.bluetooth.free_space());
+        free_space
+    }
+
+    /// Send a measurement to all sinks. Text based ones get the already formatted `frame`, the
+    /// UART links drop it first if the host is slow (see [`crate::uart`]).
+    pub fn publish(&mut self, measurement: &Measurement, frame: &str) {
+        self.for_each_sink(|sink| sink.measurement(measurement, frame));
+    }
+
+    /// Send a frame which isn't a measurement (e.g. a rollup) to all text based sinks, i.e. all
+    /// links except for LoRa.
+    pub fn publish_frame(&mut self, frame: &str) {
+        self.for_each_sink(|sink| sink.frame(frame));
+    }
+
+    /// Send the frame of a [`crate::batch`] to all text based sinks, it's dropped like a
+    /// measurement if a link doesn't keep up. LoRa gets the measurements themselves, see
+    /// [`Links::publish_batched`].
+    pub fn publish_batch(&mut self, frame: &str) {
+        self.for_each_sink(|sink| sink.batch(frame));
+    }
+
+    /// Hand a measurement which is published in a batch to the sinks which don't send batches,
+    /// e.g. LoRa with its own reports & alarms.
+    pub fn publish_batched(&mut self, measurement: &Measurement) {
+        self.for_each_sink(|sink| sink.batched(measurement));
+    }
+
+    /// Call `f` for every sink which has been enabled, in the order in which they get the
+    /// telemetry.
+    fn for_each_sink(&mut self, mut f: impl FnMut(&mut dyn Sink)) {
+        f(&mut self.vcp);
+        #[cfg(feature = "usb")]
+        f(&mut self.usb);
+        #[cfg(feature = "bluetooth")]
+        f(&mut self.bluetooth);
+        #[cfg(feature = "wifi")]
+        f(&mut self.wifi);
+        #[cfg(feature = "lora")]
+        if let Some(lora) = &mut self.lora {
+            f(lora);
+        }
+        #[cfg(feature = "defmt-sink")]
+        f(&mut self.defmt);
+        #[cfg(feature = "uart-binary")]
+        f(&mut self.binary);
+    }
+
+    /// Send a [`crate::webhook`] payload to all text based links, as a line on the serial ones.
+    pub fn publish_event(&mut self, payload: &str) {
+        #[cfg(not(feature = "mqtt-sn"))]
+        {
+            self.vcp.write(payload.as_bytes());
+            self.vcp.write(b"\r\n");
+        }
+        #[cfg(feature = "mqtt-sn")]
+        self.vcp.publish(payload);
+        #[cfg(feature = "usb")]
+        {
+            self.usb.write(payload.as_bytes());
+            self.usb.write(b"\r\n");
+        }
+        #[cfg(feature = "bluetooth")]
+        {
+            self.bluetooth.write(payload.as_bytes());
+            self.bluetooth.write(b"\r\n");
+        }
+        #[cfg(feature = "wifi")]
+        self.wifi.post_event(payload);
+    }
+
+    /// Number of frames the UART links have dropped since boot as the host didn't keep up.
+    pub fn dropped_frames(&self) -> u32 {
+        #[cfg(not(feature = "mqtt-sn"))]
+        let dropped = self.vcp.dropped_frames();
+        #[cfg(feature = "mqtt-sn")]
+        let dropped = 0u32;
+        #[cfg(feature = "bluetooth")]
+        let dropped = dropped.wrapping_add(self.bluetooth.dropped_frames());
+        dropped
+    }
+
+    /// Change the threshold of the alarms sent by the links.
+    #[cfg_attr(
+        not(any(feature = "threshold-pot", feature = "alarm-output", feature = "lora")),
+        allow(dead_code)
+    )]
+    pub fn set_alarm_threshold(&mut self, threshold_mm: u16) {
+        #[cfg(feature = "lora")]
+        if let Some(lora) = &mut self.lora {
+            lora.set_alarm_threshold(threshold_mm);
+        }
+        #[cfg(not(feature = "lora"))]
+        let _ = threshold_mm;
+    }
+
+    /// Mute the alarms sent by the links outside of the active hours, see [`crate::schedule`].
+    pub fn set_alarms_active(&mut self, active: bool) {
+        #[cfg(feature = "lora")]
+        if let Some(lora) = &mut self.lora {
+            lora.set_alarms_muted(!active);
+        }
+        #[cfg(not(feature = "lora"))]
+        let _ = active;
+    }
+
+    /// The threshold of the alarms sent by the links, `None` if none sends alarms.
+    #[cfg(any(feature = "alarm-output", feature = "lora"))]
+    pub fn alarm_threshold_mm(&self) -> Option<u16> {
+        #[cfg(feature = "lora")]
+        return self.lora.as_ref().map(LoraUplink::alarm_threshold_mm);
+        #[cfg(not(feature = "lora"))]
+        None
+    }
+
+    /// Handle timeouts of the links. Must be called periodically.
+    pub fn tick(&mut self, now_ms: u32) {
+        #[cfg(feature = "mqtt-sn")]
+        self.vcp.tick(now_ms);
+        #[cfg(feature = "wifi")]
+        self.wifi.tick(now_ms);
+        #[cfg(feature = "lora")]
+        if let Some(lora) = &mut self.lora {
+            lora.tick(now_ms);
+        }
+        #[cfg(not(any(feature = "mqtt-sn", feature = "wifi", feature = "lora")))]
+        let _ = now_ms;
+    }
+}
@@ -0,0 +1,273 @@
+//! Where the log goes: defmt sends it over RTT, which is only read while a debugger is attached.
+//! Headless units would thus log into a buffer which nobody reads. At boot [`select`] checks
+//! whether a debugger is attached (`C_DEBUGEN` in `DHCSR`); if not, the messages logged with
+//! [`log_info!`](crate::log_info) (only the periodic summary & the result of the POST),
+//! [`log_warn!`](crate::log_warn) & [`log_error!`](crate::log_error) are additionally queued as
+//! text (see [`pop`]) & sent as log frames over the UART links (see
+//! [`crate::telemetry::log_frame`]). The other messages only go over RTT.
+//!
+//! Both channels can be used at the same time with their own level, e.g. the measurement frames
+//! and the errors on the UART for the application & all the diagnostics on RTT for the developer.
+//! The levels are changed at runtime with [`set_level`] (the `log` command) or [`set_min_level`]
+//! (the [`crate::control_block`]): the messages logged with these macros below the level of a
+//! channel aren't sent over it, the other messages only go over RTT & are only filtered by the
+//! level set with `DEFMT_LOG` at build time.
+//!
+//! `C_DEBUGEN` stays set once a debugger has been attached until the next power-on reset, thus a
+//! unit which has been flashed has to be power cycled before it falls back to the UART.
+//!
+//! The messages are also formatted with [`core::fmt`], thus their arguments have to implement
+//! `Display` (use [`DebugFormat`] instead of [`defmt::Debug2Format`]) and the format string may
+//! only use `{}`.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use heapless::mpmc::MpMcQueue;
+
+/// Maximum length of a queued message, the parts beyond are left out.
+pub const LINE_LEN: usize = 96;
+/// Number of messages which are queued until they're sent, further ones are dropped.
+const QUEUE_LEN: usize = 8;
+
+pub type Line = heapless::String<LINE_LEN>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Backend {
+    /// Only RTT, a debugger is attached (or the UART fallback is disabled).
+    Rtt,
+    /// The messages are sent over the UART links as well.
+    Uart,
+}
+
+/// Where the messages logged with the macros can be sent, each with its own level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Channel {
+    /// The defmt log.
+    Rtt,
+    /// The log frames on the UART links.
+    Uart,
+}
+
+impl Channel {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rtt => "rtt",
+            Self::Uart => "uart",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rtt" => Some(Self::Rtt),
+            "uart" => Some(Self::Uart),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warn => "warn",
+            Self::Error => "error",
+        }
+    }
+
+    /// The level with the number (0: info, 1: warn, 2: error).
+    pub fn from_number(number: u32) -> Option<Self> {
+        match number {
+            0 => Some(Self::Info),
+            1 => Some(Self::Warn),
+            2 => Some(Self::Error),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "info" => Some(Self::Info),
+            "warn" => Some(Self::Warn),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+
+    fn from_u8(value: u8) -> Option<Self> {
+        Self::from_number(value as u32)
+    }
+}
+
+/// The level of a channel which is off.
+const OFF: u8 = Level::Error as u8 + 1;
+
+static RTT_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static UART_LEVEL: AtomicU8 = AtomicU8::new(OFF);
+static LINES: MpMcQueue<(Level, Line), QUEUE_LEN> = MpMcQueue::new();
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+fn level_of(channel: Channel) -> &'static AtomicU8 {
+    match channel {
+        Channel::Rtt => &RTT_LEVEL,
+        Channel::Uart => &UART_LEVEL,
+    }
+}
+
+/// Select the backend depending on whether a debugger is attached, as early as possible during
+/// the initialisation.
+pub fn select() -> Backend {
+    let backend = if crate::config::log_backend::UART_FALLBACK
+        && !cortex_m::peripheral::DCB::is_debugger_attached()
+    {
+        Backend::Uart
+    } else {
+        Backend::Rtt
+    };
+    if backend == Backend::Uart {
+        set_level(Channel::Uart, Some(Level::Info));
+    }
+    backend
+}
+
+/// Send the messages of the `level` & above over the channel, `None` turns it off.
+pub fn set_level(channel: Channel, level: Option<Level>) {
+    let value = level.map_or(OFF, |level| level as u8);
+    level_of(channel).store(value, Ordering::Relaxed);
+}
+
+/// The lowest level which is sent over the channel, `None` if it's off.
+pub fn level(channel: Channel) -> Option<Level> {
+    Level::from_u8(level_of(channel).load(Ordering::Relaxed))
+}
+
+/// Drop the messages logged with the macros below the `level` from now on, on all channels which
+/// are on.
+pub fn set_min_level(level: Level) {
+    for channel in [Channel::Rtt, Channel::Uart] {
+        if self::level(channel).is_some() {
+            set_level(channel, Some(level));
+        }
+    }
+}
+
+/// Whether the messages of the `level` are sent over the channel, see [`set_level`].
+pub fn enabled(channel: Channel, level: Level) -> bool {
+    level as u8 >= level_of(channel).load(Ordering::Relaxed)
+}
+
+/// Queue a message for the UART links if it's sent over [`Channel::Uart`].
+pub fn push(level: Level, args: fmt::Arguments) {
+    if !enabled(Channel::Uart, level) {
+        return;
+    }
+    let mut line = Line::new();
+    // the parts which don't fit anymore are left out
+    write!(line, "{}", args).ok();
+    if LINES.enqueue((level, line)).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The oldest queued message, which has to be sent.
+pub fn pop() -> Option<(Level, Line)> {
+    LINES.dequeue()
+}
+
+/// Number of messages which have been dropped since boot as the queue was full.
+pub fn dropped() -> u32 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Formats a value with its `Debug` implementation for both defmt & [`core::fmt`].
+pub struct DebugFormat<'a, T: fmt::Debug>(pub &'a T);
+
+impl<T: fmt::Debug> defmt::Format for DebugFormat<'_, T> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}", defmt::Debug2Format(self.0));
+    }
+}
+
+impl<T: fmt::Debug> fmt::Display for DebugFormat<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+/// Log an information with defmt & queue it for the UART links (depending on the level of each
+/// channel), see [`crate::log_backend`].
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)+) => {{
+        if $crate::log_backend::enabled(
+            $crate::log_backend::Channel::Rtt,
+            $crate::log_backend::Level::Info,
+        ) {
+            defmt::info!($($arg)+);
+        }
+        $crate::log_backend::push($crate::log_backend::Level::Info, format_args!($($arg)+));
+    }};
+}
+
+/// Log a warning with defmt & queue it for the UART links (depending on the level of each
+/// channel), see [`crate::log_backend`].
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)+) => {{
+        if $crate::log_backend::enabled(
+            $crate::log_backend::Channel::Rtt,
+            $crate::log_backend::Level::Warn,
+        ) {
+            defmt::warn!($($arg)+);
+        }
+        $crate::log_backend::push($crate::log_backend::Level::Warn, format_args!($($arg)+));
+    }};
+}
+
+/// Log an error with defmt & queue it for the UART links (depending on the level of each
+/// channel), see [`crate::log_backend`].
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)+) => {{
+        if $crate::log_backend::enabled(
+            $crate::log_backend::Channel::Rtt,
+            $crate::log_backend::Level::Error,
+        ) {
+            defmt::error!($($arg)+);
+        }
+        $crate::log_backend::push($crate::log_backend::Level::Error, format_args!($($arg)+));
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queues_the_messages_only_for_the_uart() {
+        push(Level::Warn, format_args!("not queued"));
+        assert_eq!(pop(), None);
+        set_level(Channel::Uart, Some(Level::Warn));
+        push(Level::Info, format_args!("below the level"));
+        push(
+            Level::Error,
+            format_args!("failed: {}", DebugFormat(&Some(42))),
+        );
+        push(Level::Warn, format_args!("{}", "x".repeat(2 * LINE_LEN)));
+        for _ in 0..QUEUE_LEN {
+            push(Level::Warn, format_args!("dropped"));
+        }
+        set_level(Channel::Uart, None);
+        let (level, line) = pop().unwrap();
+        assert_eq!((level, line.as_str()), (Level::Error, "failed: Some(42)"));
+        // the overlong argument is left out
+        assert_eq!(pop().unwrap().1.as_str(), "");
+        assert_eq!(dropped(), 2);
+    }
+}
@@ -0,0 +1,275 @@
+//! The compression of `dump compressed`, which sends the stored measurements in a fraction of the
+//! time of the `L,...` lines of `dump` (the host tool inflates them again).
+//!
+//! The records are encoded again as a single [`Chain`] across all sectors & files (like
+//! [`crate::log_record`], but without the CRC byte, the length in the first byte still counts
+//! it). The resulting stream is compressed with a byte-aligned LZSS: each group of up to 8 tokens
+//! is preceded by a control byte, bit `n` of which is set if token `n` is a back-reference instead
+//! of a literal byte:
+//!
+//! | Size | Content                                                                     |
+//! |------|-----------------------------------------------------------------------------|
+//! | 1    | distance of the match - 1 (within the previous [`WINDOW_LEN`] bytes)        |
+//! | 1    | length of the match - [`MIN_MATCH`]                                         |
+//!
+//! A match may overlap with the bytes it produces, runs of the same records thus take only a few
+//! bytes. The compressed bytes are sent in lines of [`CHUNK_LEN`] bytes: `Z,<base64>,<crc>` with
+//! the CRC-32 of the bytes of the line (8 hexadecimal digits).
+
+use crate::log_record::{Chain, Record, MAX_LINE_LEN, MAX_RECORD_LEN};
+use crate::storage::crc32;
+use core::fmt::Write;
+
+/// Number of the previous bytes in which matches are searched.
+pub const WINDOW_LEN: usize = 256;
+/// The shortest match, shorter ones are sent as literals.
+pub const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = MIN_MATCH + u8::MAX as usize;
+/// Number of compressed bytes per line, the base64 of which fits into [`MAX_LINE_LEN`].
+pub const CHUNK_LEN: usize = 36;
+/// The window, a whole match & a record which is added.
+const BUFFER_LEN: usize = WINDOW_LEN + MAX_MATCH + MAX_RECORD_LEN;
+/// A control byte & 8 matches.
+const GROUP_LEN: usize = 1 + 8 * 2;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub type Chunk = heapless::Vec<u8, CHUNK_LEN>;
+
+pub struct Compressor {
+    chain: Chain,
+    /// The window followed by the bytes which haven't been compressed yet, starting at `pos`.
+    buffer: heapless::Vec<u8, BUFFER_LEN>,
+    pos: usize,
+    /// The tokens of the current group & its control byte.
+    group: heapless::Vec<u8, { GROUP_LEN - 1 }>,
+    control: u8,
+    tokens: u8,
+    /// Compressed bytes which haven't been taken yet.
+    output: heapless::Vec<u8, { CHUNK_LEN + GROUP_LEN }>,
+    /// Number of records which have been added & compressed bytes which have been taken.
+    records: u32,
+    bytes: u32,
+    finished: bool,
+}
+
+impl Compressor {
+    pub const fn new() -> Self {
+        Self {
+            chain: Chain::new(),
+            buffer: heapless::Vec::new(),
+            pos: 0,
+            group: heapless::Vec::new(),
+            control: 0,
+            tokens: 0,
+            output: heapless::Vec::new(),
+            records: 0,
+            bytes: 0,
+            finished: false,
+        }
+    }
+
+    /// Add the next record, only once [`Self::next_chunk`] has returned `None`.
+    pub fn push(&mut self, record: &Record) {
+        // keep the window before the bytes which haven't been compressed
+        let start = self.pos.saturating_sub(WINDOW_LEN);
+        self.buffer.copy_within(start.., 0);
+        self.buffer.truncate(self.buffer.len() - start);
+        self.pos -= start;
+
+        let encoded = self.chain.encode(record);
+        // without the CRC
+        if self
+            .buffer
+            .extend_from_slice(&encoded[..encoded.len() - 1])
+            .is_err()
+        {
+            defmt::error!("log compression: buffer overflow");
+        }
+        self.records += 1;
+    }
+
+    /// All records have been added, the rest is compressed.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Whether all records have been added & all compressed bytes have been taken.
+    pub fn is_done(&self) -> bool {
+        self.finished && self.pos == self.buffer.len() && self.tokens == 0 && self.output.is_empty()
+    }
+
+    /// The next chunk of compressed bytes, `None` if more records are needed (or once it's done).
+    pub fn next_chunk(&mut self) -> Option<Chunk> {
+        while self.output.len() < CHUNK_LEN {
+            let pending = self.buffer.len() - self.pos;
+            if !self.finished && pending < MAX_MATCH {
+                return None;
+            }
+            if pending == 0 {
+                self.end_group();
+                break;
+            }
+            self.compress_token();
+        }
+        let len = self.output.len().min(CHUNK_LEN);
+        if len == 0 {
+            return None;
+        }
+        let chunk = Chunk::from_slice(&self.output[..len]).ok()?;
+        self.output.copy_within(len.., 0);
+        self.output.truncate(self.output.len() - len);
+        self.bytes += len as u32;
+        Some(chunk)
+    }
+
+    pub fn records(&self) -> u32 {
+        self.records
+    }
+
+    /// Number of compressed bytes which have been taken.
+    pub fn bytes(&self) -> u32 {
+        self.bytes
+    }
+
+    fn compress_token(&mut self) {
+        let (distance, len) = self.longest_match();
+        if len >= MIN_MATCH {
+            self.control |= 1 << self.tokens;
+            self.group
+                .extend_from_slice(&[(distance - 1) as u8, (len - MIN_MATCH) as u8])
+                .ok();
+            self.pos += len;
+        } else {
+            self.group.push(self.buffer[self.pos]).ok();
+            self.pos += 1;
+        }
+        self.tokens += 1;
+        if self.tokens == 8 {
+            self.end_group();
+        }
+    }
+
+    fn end_group(&mut self) {
+        if self.tokens == 0 {
+            return;
+        }
+        self.output.push(self.control).ok();
+        self.output.extend_from_slice(&self.group).ok();
+        self.group.clear();
+        self.control = 0;
+        self.tokens = 0;
+    }
+
+    /// The distance & the length of the longest match at `pos`.
+    fn longest_match(&self) -> (usize, usize) {
+        let input = &self.buffer[self.pos..];
+        let max_len = input.len().min(MAX_MATCH);
+        let mut best = (0, 0);
+        for distance in 1..=self.pos.min(WINDOW_LEN) {
+            let start = self.pos - distance;
+            let len = (0..max_len)
+                .take_while(|&index| self.buffer[start + index] == input[index])
+                .count();
+            if len > best.1 {
+                best = (distance, len);
+                if len == max_len {
+                    break;
+                }
+            }
+        }
+        best
+    }
+}
+
+/// The line with the chunk: `Z,<base64>,<crc>`.
+pub fn chunk_line(chunk: &[u8]) -> heapless::String<MAX_LINE_LEN> {
+    let mut line = heapless::String::new();
+    line.push_str("Z,").ok();
+    for group in chunk.chunks(3) {
+        let bits = group.iter().enumerate().fold(0u32, |bits, (index, &byte)| {
+            bits | (byte as u32) << (16 - 8 * index)
+        });
+        for index in 0..4 {
+            let symbol = if index <= group.len() {
+                BASE64[(bits >> (18 - 6 * index) & 0x3F) as usize] as char
+            } else {
+                '='
+            };
+            line.push(symbol).ok();
+        }
+    }
+    write!(line, ",{:08x}\r\n", crc32(chunk)).ok();
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_record::Decoded;
+
+    /// The decompression of the host tool.
+    fn inflate(compressed: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut bytes = compressed.iter().copied();
+        while let Some(control) = bytes.next() {
+            for token in 0..8 {
+                let Some(byte) = bytes.next() else {
+                    break;
+                };
+                if control & 1 << token == 0 {
+                    output.push(byte);
+                    continue;
+                }
+                let distance = byte as usize + 1;
+                let len = bytes.next().unwrap() as usize + MIN_MATCH;
+                for _ in 0..len {
+                    output.push(output[output.len() - distance]);
+                }
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn compresses_the_records() {
+        let records: Vec<Record> = (1..=1000)
+            .map(|seq| Record {
+                seq,
+                timestamp_ms: seq * 100 + seq % 3,
+                utc_ms: (seq > 500).then_some(1_760_000_000_000 + seq as u64 * 100),
+                distance_mm: 500 + (seq % 7) as u16,
+                status: (seq % 250 == 0) as u8,
+            })
+            .collect();
+        let mut compressor = Compressor::new();
+        let mut compressed = Vec::new();
+        let mut pending = records.iter();
+        while !compressor.is_done() {
+            match compressor.next_chunk() {
+                Some(chunk) => compressed.extend_from_slice(&chunk),
+                None => match pending.next() {
+                    Some(record) => compressor.push(record),
+                    None => compressor.finish(),
+                },
+            }
+        }
+        assert_eq!(compressor.records(), 1000);
+        assert_eq!(compressor.bytes() as usize, compressed.len());
+        // 40 bytes per line of `dump`
+        assert!(compressed.len() < 1000 * 40 / 20);
+
+        let stream = inflate(&compressed);
+        let (mut chain, mut offset) = (Chain::new(), 0);
+        for record in &records {
+            let len = (stream[offset] & 0x1F) as usize;
+            let mut value = stream[offset..offset + len].to_vec();
+            value.push(crc32(&value) as u8);
+            assert_eq!(chain.decode(&value), Decoded::Record(*record, len + 1));
+            offset += len;
+        }
+        assert_eq!(offset, stream.len());
+
+        assert_eq!(chunk_line(b"abcd").as_str(), "Z,YWJjZA==,ed82cd11\r\n");
+    }
+}
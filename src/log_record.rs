@@ -0,0 +1,421 @@
+//! The format in which the measurements are stored on the external NOR flash, both by the
+//! [`crate::flash_log`] & by the log files of the [`crate::file_system`].
+//!
+//! As the values change slowly each measurement is stored as a record which only contains the
+//! differences to the previous one of the same [`Chain`] (a sector or a file), the first one is
+//! the difference to zero. The differences are stored as variable length integers (7 bits per
+//! byte, least significant group first, the highest bit is set if another byte follows), signed
+//! ones are zig-zag encoded (`0, -1, 1, -2, ...` => `0, 1, 2, 3, ...`). Thus a typical record needs
+//! 6 bytes instead of the 20 bytes of a fixed layout:
+//!
+//! | Size   | Content                                                                       |
+//! |--------|-------------------------------------------------------------------------------|
+//! | 1      | length of the rest of the record (bits 0 - 4) & the flags (bits 5 & 6)        |
+//! | varint | difference of the sequence number                                             |
+//! | varint | difference of the time since boot in ms                                       |
+//! | varint | difference of the distance in mm (signed)                                     |
+//! | 0 / 1  | range status, omitted if [`FLAG_SAME_STATUS`] is set                          |
+//! | varint | difference of the calendar time (signed), omitted unless [`FLAG_UTC`] is set  |
+//! | 1      | lowest byte of the CRC-32 of the preceding bytes                              |
+//!
+//! The calendar time (in ms since the Unix epoch) is compared to the previous one plus the
+//! difference of the time since boot, thus it's usually `0`.
+//!
+//! The highest bit of the first byte is never set, thus erased flash (`0xFF`) marks the end.
+
+use crate::storage::crc32;
+use crate::telemetry::Measurement;
+use core::fmt::Write;
+
+/// Maximum length of a record.
+pub const MAX_RECORD_LEN: usize = 1 + 5 + 5 + 3 + 1 + 10 + 1;
+
+/// Maximum length of a line of the `dump` command.
+pub const MAX_LINE_LEN: usize = 64;
+
+/// The calendar time is known & stored.
+const FLAG_UTC: u8 = 0x20;
+/// The range status is the same as the one of the previous record & isn't stored.
+const FLAG_SAME_STATUS: u8 = 0x40;
+const LEN_MASK: u8 = 0x1F;
+
+/// An encoded record.
+pub type Encoded = heapless::Vec<u8, MAX_RECORD_LEN>;
+
+/// A measurement as stored in the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub seq: u32,
+    pub timestamp_ms: u32,
+    pub utc_ms: Option<u64>,
+    pub distance_mm: u16,
+    /// The raw range status.
+    pub status: u8,
+}
+
+impl Record {
+    /// The record to which the first record of a chain is compared.
+    const ZERO: Self = Self {
+        seq: 0,
+        timestamp_ms: 0,
+        utc_ms: None,
+        distance_mm: 0,
+        status: 0,
+    };
+
+    /// The line sent by the `dump` command:
+    /// `L,<seq>,<timestamp_ms>,<distance_mm>,<status>,<utc_ms>` (the same fields as the CSV
+    /// telemetry frame).
+    pub fn line(&self) -> heapless::String<MAX_LINE_LEN> {
+        let mut line = heapless::String::new();
+        write!(
+            line,
+            "L,{},{},{},{},",
+            self.seq, self.timestamp_ms, self.distance_mm, self.status
+        )
+        .ok();
+        if let Some(utc_ms) = self.utc_ms {
+            write!(line, "{}", utc_ms).ok();
+        }
+        write!(line, "\r\n").ok();
+        line
+    }
+}
+
+impl From<&Measurement> for Record {
+    fn from(measurement: &Measurement) -> Self {
+        Self {
+            seq: measurement.seq,
+            timestamp_ms: measurement.timestamp_ms,
+            utc_ms: measurement.utc_ms,
+            distance_mm: measurement.distance_mm,
+            status: measurement.status as u8,
+        }
+    }
+}
+
+/// A step of a dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dump {
+    Record(Record),
+    /// The dump has caught up with the log.
+    Done {
+        records: u32,
+    },
+}
+
+/// The result of [`Chain::decode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoded {
+    /// A record & the number of bytes it took.
+    Record(Record, usize),
+    /// The record of this length is corrupt (e.g. interrupted by a loss of power) & is skipped.
+    Corrupt(usize),
+    /// More bytes are needed to decode the record.
+    Incomplete,
+    /// The chain ends here (erased flash or an invalid record).
+    End,
+}
+
+/// A sequence of records, each of which is stored as the difference to the previous one. The
+/// encoder & the decoder each keep their own chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chain {
+    previous: Record,
+}
+
+impl Chain {
+    pub const fn new() -> Self {
+        Self {
+            previous: Record::ZERO,
+        }
+    }
+
+    pub fn encode(&mut self, record: &Record) -> Encoded {
+        let previous = core::mem::replace(&mut self.previous, *record);
+        let mut value = Encoded::new();
+        let mut flags = 0;
+        value.push(0).ok();
+        push_varint(&mut value, record.seq.wrapping_sub(previous.seq) as u64);
+        let elapsed_ms = record.timestamp_ms.wrapping_sub(previous.timestamp_ms);
+        push_varint(&mut value, elapsed_ms as u64);
+        push_varint(
+            &mut value,
+            zigzag(record.distance_mm as i64 - previous.distance_mm as i64),
+        );
+        if record.status == previous.status {
+            flags |= FLAG_SAME_STATUS;
+        } else {
+            value.push(record.status).ok();
+        }
+        if let Some(utc_ms) = record.utc_ms {
+            flags |= FLAG_UTC;
+            let expected = predict_utc(previous.utc_ms, elapsed_ms);
+            push_varint(&mut value, zigzag(utc_ms.wrapping_sub(expected) as i64));
+        }
+        value[0] = flags | value.len() as u8;
+        value.push(crc32(&value) as u8).ok();
+        value
+    }
+
+    /// Decode the record at the start of `bytes`.
+    pub fn decode(&mut self, bytes: &[u8]) -> Decoded {
+        let Some(&first) = bytes.first() else {
+            return Decoded::Incomplete;
+        };
+        let len = (first & LEN_MASK) as usize + 1;
+        if first & 0x80 != 0 || len < 5 {
+            return Decoded::End;
+        }
+        let Some(value) = bytes.get(..len) else {
+            return Decoded::Incomplete;
+        };
+        let (crc, value) = (value[len - 1], &value[..len - 1]);
+        match self.decode_fields(first, value) {
+            Some(record) if crc == crc32(value) as u8 => {
+                self.previous = record;
+                Decoded::Record(record, len)
+            }
+            _ => Decoded::Corrupt(len),
+        }
+    }
+
+    fn decode_fields(&self, first: u8, value: &[u8]) -> Option<Record> {
+        let previous = &self.previous;
+        let mut bytes = value[1..].iter().copied();
+        let seq = previous.seq.wrapping_add(read_varint(&mut bytes)? as u32);
+        let elapsed_ms = read_varint(&mut bytes)? as u32;
+        let distance_mm = previous.distance_mm as i64 + unzigzag(read_varint(&mut bytes)?);
+        let status = if first & FLAG_SAME_STATUS != 0 {
+            previous.status
+        } else {
+            bytes.next()?
+        };
+        let utc_ms = if first & FLAG_UTC != 0 {
+            let expected = predict_utc(previous.utc_ms, elapsed_ms);
+            Some(expected.wrapping_add(unzigzag(read_varint(&mut bytes)?) as u64))
+        } else {
+            None
+        };
+        if bytes.next().is_some() {
+            return None;
+        }
+        Some(Record {
+            seq,
+            timestamp_ms: previous.timestamp_ms.wrapping_add(elapsed_ms),
+            utc_ms,
+            distance_mm: distance_mm.try_into().ok()?,
+            status,
+        })
+    }
+}
+
+/// The expected calendar time if the clock has advanced like the time since boot.
+fn predict_utc(previous: Option<u64>, elapsed_ms: u32) -> u64 {
+    previous.map_or(0, |utc_ms| utc_ms.wrapping_add(elapsed_ms as u64))
+}
+
+fn push_varint(value: &mut Encoded, mut number: u64) {
+    while number >= 0x80 {
+        value.push(number as u8 | 0x80).ok();
+        number >>= 7;
+    }
+    value.push(number as u8).ok();
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<u64> {
+    let mut number = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = bytes.next()?;
+        number |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(number);
+        }
+    }
+    None
+}
+
+fn zigzag(number: i64) -> u64 {
+    ((number << 1) ^ (number >> 63)) as u64
+}
+
+fn unzigzag(number: u64) -> i64 {
+    (number >> 1) as i64 ^ -((number & 1) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// The longest record: all the differences need the longest varints & all the optional
+    /// fields are stored.
+    const LONGEST: Record = Record {
+        seq: u32::MAX,
+        timestamp_ms: u32::MAX,
+        utc_ms: Some(1 << 63),
+        distance_mm: u16::MAX,
+        status: 0xFF,
+    };
+
+    fn record(seq: u32, timestamp_ms: u32, distance_mm: u16, status: u8) -> Record {
+        Record {
+            seq,
+            timestamp_ms,
+            utc_ms: None,
+            distance_mm,
+            status,
+        }
+    }
+
+    fn records() -> impl Strategy<Value = Record> {
+        (
+            any::<u32>(),
+            any::<u32>(),
+            any::<Option<u64>>(),
+            any::<u16>(),
+            any::<u8>(),
+        )
+            .prop_map(|(seq, timestamp_ms, utc_ms, distance_mm, status)| Record {
+                seq,
+                timestamp_ms,
+                utc_ms,
+                distance_mm,
+                status,
+            })
+    }
+
+    #[test]
+    fn the_longest_record_has_the_maximum_length() {
+        let encoded = Chain::new().encode(&LONGEST);
+        assert_eq!(encoded.len(), MAX_RECORD_LEN);
+        assert_eq!(
+            Chain::new().decode(&encoded),
+            Decoded::Record(LONGEST, MAX_RECORD_LEN)
+        );
+    }
+
+    #[test]
+    fn round_trip_of_the_extreme_differences() {
+        let records = [
+            LONGEST,
+            // the differences wrap around
+            Record {
+                seq: 0,
+                timestamp_ms: 0,
+                utc_ms: Some(0),
+                distance_mm: 0,
+                status: 0,
+            },
+            Record {
+                seq: i32::MAX as u32,
+                timestamp_ms: i32::MAX as u32,
+                utc_ms: Some(u64::MAX),
+                distance_mm: u16::MAX,
+                status: 0,
+            },
+            Record {
+                seq: i32::MIN as u32,
+                timestamp_ms: i32::MIN as u32,
+                utc_ms: Some(i64::MAX as u64),
+                distance_mm: 0,
+                status: 0,
+            },
+            // without the calendar time & with the same status
+            record(i32::MIN as u32 + 1, 0, 1, 0),
+        ];
+        let (mut encoder, mut decoder) = (Chain::new(), Chain::new());
+        for record in records {
+            let encoded = encoder.encode(&record);
+            assert_eq!(
+                decoder.decode(&encoded),
+                Decoded::Record(record, encoded.len())
+            );
+        }
+    }
+
+    #[test]
+    fn a_typical_record_is_short() {
+        let mut chain = Chain::new();
+        chain.encode(&record(1, 100, 1_000, 0));
+        let encoded = chain.encode(&record(2, 200, 1_003, 0));
+        assert_eq!(encoded.len(), 5);
+        // the highest bit of the first byte is never set
+        assert_eq!(encoded[0] & 0x80, 0);
+    }
+
+    #[test]
+    fn a_truncated_record_is_incomplete() {
+        let encoded = Chain::new().encode(&LONGEST);
+        for len in 0..encoded.len() {
+            assert_eq!(
+                Chain::new().decode(&encoded[..len]),
+                Decoded::Incomplete,
+                "{}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn a_corrupt_record_is_skipped() {
+        let mut encoder = Chain::new();
+        let first = encoder.encode(&record(1, 100, 1_000, 0));
+        let second = encoder.encode(&record(2, 200, 1_010, 0));
+        let mut corrupt = second.clone();
+        corrupt[2] ^= 0x01;
+
+        let mut decoder = Chain::new();
+        decoder.decode(&first);
+        assert_eq!(decoder.decode(&corrupt), Decoded::Corrupt(second.len()));
+        // the chain continues with the previous valid record
+        assert_eq!(
+            decoder.decode(&second),
+            Decoded::Record(record(2, 200, 1_010, 0), second.len())
+        );
+
+        // a record whose fields don't match its length can't be decoded either
+        let mut padded = heapless::Vec::<u8, 32>::new();
+        padded.push(first[0] + 1).unwrap();
+        padded
+            .extend_from_slice(&first[1..first.len() - 1])
+            .unwrap();
+        padded.push(0).unwrap();
+        let crc = crc32(&padded) as u8;
+        padded.push(crc).unwrap();
+        assert_eq!(Chain::new().decode(&padded), Decoded::Corrupt(padded.len()));
+    }
+
+    #[test]
+    fn the_chain_ends_at_the_erased_flash() {
+        assert_eq!(Chain::new().decode(&[0xFF; MAX_RECORD_LEN]), Decoded::End);
+        // too short for a record
+        assert_eq!(Chain::new().decode(&[0x03, 0, 0, 0, 0]), Decoded::End);
+    }
+
+    #[test]
+    fn lines_have_the_fields_of_the_csv_frames() {
+        let record = Record {
+            utc_ms: Some(1_760_000_000_000),
+            ..record(7, 1_500, 1_234, 2)
+        };
+        assert_eq!(record.line(), "L,7,1500,1234,2,1760000000000\r\n");
+        let record = Record {
+            utc_ms: None,
+            ..record
+        };
+        assert_eq!(record.line(), "L,7,1500,1234,2,\r\n");
+    }
+
+    proptest! {
+        #[test]
+        fn chains_of_records_round_trip(records in prop::collection::vec(records(), 1..50)) {
+            let (mut encoder, mut decoder) = (Chain::new(), Chain::new());
+            for record in &records {
+                let encoded = encoder.encode(record);
+                prop_assert!(encoded.len() <= MAX_RECORD_LEN);
+                prop_assert_eq!(decoder.decode(&encoded), Decoded::Record(*record, encoded.len()));
+            }
+        }
+    }
+}
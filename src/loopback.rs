@@ -0,0 +1,401 @@
+//! Loopback self-test of the UART protocol on USART1 (`uart-loopback` feature), to validate the
+//! wiring of a serial link in the field: with `PA9` (TX) jumpered to `PA10` (RX) the `selftest`
+//! command sends framed test vectors to itself & checks that they're received intact.
+//!
+//! Each frame is delimited by [`FLAG`] bytes & contains a sequence number, the test vector & its
+//! CRC-32, with the delimiters in the content escaped. The test vectors are sent in several rounds
+//! as fast as the transmit buffer takes them, its free space is the flow control: a frame is only
+//! queued once it fits completely, thus no frame may be lost. One of the frames is deliberately
+//! corrupted & has to be rejected by the CRC check.
+//!
+//! `selftest internal` runs the same test without the UART, the frames are handed from the encoder
+//! to the decoder through a buffer of the same size. If it passes but the test over the UART fails
+//! the wiring (or the UART) is at fault.
+
+use crate::storage::crc32;
+use crate::uart::{BufferedUart, TX_QUEUE_LEN};
+use core::fmt;
+use stm32f4xx_hal::pac::USART1;
+
+#[cfg(feature = "wifi")]
+compile_error!("the features `uart-loopback` and `wifi` can't be combined as both use USART1");
+
+/// Delimits the frames.
+const FLAG: u8 = 0x7E;
+/// Precedes a [`FLAG`] or an [`ESCAPE`] in the content of a frame, which is XORed with
+/// [`ESCAPE_XOR`].
+const ESCAPE: u8 = 0x7D;
+const ESCAPE_XOR: u8 = 0x20;
+/// Length of the longest test vector.
+const MAX_PAYLOAD_LEN: usize = 64;
+/// Length of the longest content of a frame: the sequence number, the test vector & the CRC.
+const MAX_CONTENT_LEN: usize = 1 + MAX_PAYLOAD_LEN + 4;
+/// Length of the longest encoded frame, if every byte of the content is escaped.
+const MAX_FRAME_LEN: usize = 2 + 2 * MAX_CONTENT_LEN;
+/// Number of times the test vectors are sent.
+const ROUNDS: usize = 4;
+/// Number of frames sent by a test.
+const FRAMES: u8 = (ROUNDS * TEST_VECTORS.len()) as u8;
+/// The frame whose CRC is corrupted.
+const CORRUPTED_SEQ: u8 = 1;
+/// Time after which the frames which haven't been received yet are considered lost.
+const TIMEOUT_MS: u32 = 1_000;
+/// Interval at which a running test is checked for completion.
+pub const POLL_INTERVAL_MS: u32 = 20;
+
+/// A run of consecutive bytes around [`FLAG`] & [`ESCAPE`], of the longest length.
+const CONSECUTIVE: [u8; MAX_PAYLOAD_LEN] = {
+    let mut bytes = [0; MAX_PAYLOAD_LEN];
+    let mut i = 0;
+    while i < MAX_PAYLOAD_LEN {
+        bytes[i] = 0x60 + i as u8;
+        i += 1;
+    }
+    bytes
+};
+
+/// The test vectors, sent in this order in each round.
+const TEST_VECTORS: [&[u8]; 6] = [
+    b"",
+    &[0x00; 16],
+    &[0xFF; 16],
+    &[FLAG, ESCAPE, FLAG ^ ESCAPE_XOR, ESCAPE, FLAG],
+    &CONSECUTIVE,
+    b"D,1,100,1234,0,,0123456789abcdef",
+];
+
+/// The test vector in the frame `seq`.
+fn test_vector(seq: u8) -> &'static [u8] {
+    TEST_VECTORS[seq as usize % TEST_VECTORS.len()]
+}
+
+/// The way the frames take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Mode {
+    /// Over the UART, with TX jumpered to RX.
+    Uart,
+    /// From the encoder to the decoder without the UART.
+    Internal,
+}
+
+/// Encode a frame, `corrupt` flips a bit of the CRC.
+fn encode(seq: u8, payload: &[u8], corrupt: bool) -> heapless::Vec<u8, MAX_FRAME_LEN> {
+    let mut content = heapless::Vec::<u8, MAX_CONTENT_LEN>::new();
+    content.push(seq).ok();
+    content.extend_from_slice(payload).ok();
+    let crc = crc32(&content) ^ corrupt as u32;
+    content.extend_from_slice(&crc.to_le_bytes()).ok();
+
+    let mut frame = heapless::Vec::new();
+    frame.push(FLAG).ok();
+    for &byte in &content {
+        if byte == FLAG || byte == ESCAPE {
+            frame.extend_from_slice(&[ESCAPE, byte ^ ESCAPE_XOR]).ok();
+        } else {
+            frame.push(byte).ok();
+        }
+    }
+    frame.push(FLAG).ok();
+    frame
+}
+
+/// A decoded frame.
+struct Frame {
+    seq: u8,
+    payload: heapless::Vec<u8, MAX_PAYLOAD_LEN>,
+}
+
+/// Reasons why a received frame has been rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameError {
+    /// The CRC doesn't match the content.
+    Crc,
+    /// The frame is too short to contain a sequence number & a CRC or too long for any test
+    /// vector.
+    Length,
+}
+
+/// Collects the received bytes until a frame is complete.
+#[derive(Default)]
+struct Decoder {
+    content: heapless::Vec<u8, MAX_CONTENT_LEN>,
+    /// Whether the previous byte was an [`ESCAPE`].
+    escaped: bool,
+    /// Whether the content didn't fit into the buffer.
+    overflow: bool,
+}
+
+impl Decoder {
+    /// Decode the next byte, returns the frame once it's complete.
+    fn push(&mut self, byte: u8) -> Option<Result<Frame, FrameError>> {
+        if byte != FLAG {
+            let byte = if self.escaped {
+                self.escaped = false;
+                byte ^ ESCAPE_XOR
+            } else if byte == ESCAPE {
+                self.escaped = true;
+                return None;
+            } else {
+                byte
+            };
+            self.overflow |= self.content.push(byte).is_err();
+            return None;
+        }
+        let decoder = core::mem::take(self);
+        match decoder.content.len() {
+            // between the end of a frame & the start of the next one
+            0 if !decoder.overflow => None,
+            len if len < 5 || decoder.overflow => Some(Err(FrameError::Length)),
+            len => {
+                let (data, crc) = decoder.content.split_at(len - 4);
+                if crc32(data).to_le_bytes() != crc {
+                    return Some(Err(FrameError::Crc));
+                }
+                Some(Ok(Frame {
+                    seq: data[0],
+                    payload: heapless::Vec::from_slice(&data[1..]).ok()?,
+                }))
+            }
+        }
+    }
+}
+
+/// The result of a self-test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    pub mode: Mode,
+    /// Number of frames received intact & in order.
+    pub received: u8,
+    /// Number of frames rejected because of their CRC or their length.
+    pub crc_errors: u8,
+    /// Number of frames with a valid CRC but an unexpected content or sequence number.
+    pub mismatched: u8,
+}
+
+impl Report {
+    /// Number of frames which are expected to be received intact, all except the corrupted one.
+    pub const EXPECTED: u8 = FRAMES - 1;
+
+    /// Whether all frames have been received intact & the corrupted one has been rejected.
+    pub fn passed(&self) -> bool {
+        self.received == Self::EXPECTED && self.crc_errors == 1 && self.mismatched == 0
+    }
+
+    /// Write the response to the `selftest` command.
+    pub fn write_response(&self, response: &mut impl fmt::Write) -> fmt::Result {
+        write!(
+            response,
+            "{} loopback={} received={}/{} crc_errors={} mismatched={}\r\n",
+            if self.passed() { "OK" } else { "ERR" },
+            match self.mode {
+                Mode::Uart => "uart",
+                Mode::Internal => "internal",
+            },
+            self.received,
+            Self::EXPECTED,
+            self.crc_errors,
+            self.mismatched
+        )
+    }
+}
+
+/// A running self-test.
+struct SelfTest {
+    report: Report,
+    /// The next frame to be sent.
+    next_seq: u8,
+    /// The frame expected next, frames may be lost but not reordered or duplicated.
+    expected_seq: u8,
+    decoder: Decoder,
+    /// Time since boot at which the test has been started.
+    started_ms: u32,
+}
+
+impl SelfTest {
+    fn new(mode: Mode, started_ms: u32) -> Self {
+        Self {
+            report: Report {
+                mode,
+                received: 0,
+                crc_errors: 0,
+                mismatched: 0,
+            },
+            next_seq: 0,
+            expected_seq: 0,
+            decoder: Decoder::default(),
+            started_ms,
+        }
+    }
+
+    /// Write the next frames as long as they fit completely into the `free_space` of the transmit
+    /// buffer.
+    fn send(&mut self, mut free_space: usize, mut write: impl FnMut(&[u8])) {
+        while self.next_seq < FRAMES {
+            let seq = self.next_seq;
+            let frame = encode(seq, test_vector(seq), seq == CORRUPTED_SEQ);
+            if frame.len() > free_space {
+                break;
+            }
+            write(&frame);
+            free_space -= frame.len();
+            self.next_seq += 1;
+        }
+    }
+
+    fn receive(&mut self, byte: u8) {
+        match self.decoder.push(byte) {
+            None => {}
+            Some(Ok(frame)) => {
+                let expected = (self.expected_seq..FRAMES).contains(&frame.seq)
+                    && frame.seq != CORRUPTED_SEQ
+                    && frame.payload == test_vector(frame.seq);
+                if expected {
+                    self.report.received += 1;
+                    self.expected_seq = frame.seq + 1;
+                } else {
+                    self.report.mismatched = self.report.mismatched.saturating_add(1);
+                }
+            }
+            Some(Err(_)) => self.report.crc_errors = self.report.crc_errors.saturating_add(1),
+        }
+    }
+
+    /// Whether all frames have been sent & each of them has been received, rejected or
+    /// mismatched.
+    fn is_complete(&self) -> bool {
+        let report = &self.report;
+        let handled = report.received as u16 + report.crc_errors as u16 + report.mismatched as u16;
+        self.next_seq == FRAMES && handled >= FRAMES as u16
+    }
+}
+
+/// Run the self-test without the UART.
+pub fn run_internal() -> Report {
+    let mut test = SelfTest::new(Mode::Internal, 0);
+    let mut buffer = heapless::Deque::<u8, TX_QUEUE_LEN>::new();
+    loop {
+        let free_space = buffer.capacity() - buffer.len();
+        test.send(free_space, |bytes| {
+            for &byte in bytes {
+                buffer.push_back(byte).ok();
+            }
+        });
+        // like the UART, which sends a byte at a time while the next frames are queued
+        let Some(byte) = buffer.pop_front() else {
+            break;
+        };
+        test.receive(byte);
+    }
+    test.report
+}
+
+/// USART1 with TX jumpered to RX, on which the self-test is run.
+pub struct UartLoopback {
+    uart: BufferedUart<USART1>,
+    /// The running test, `None` if none is running.
+    test: Option<SelfTest>,
+}
+
+impl UartLoopback {
+    pub fn new(uart: BufferedUart<USART1>) -> Self {
+        Self { uart, test: None }
+    }
+
+    /// Start a test over the UART, `false` if one is already running.
+    pub fn start(&mut self, now_ms: u32) -> bool {
+        if self.test.is_some() {
+            return false;
+        }
+        let mut test = SelfTest::new(Mode::Uart, now_ms);
+        test.send(self.uart.free_space(), |bytes| self.uart.write(bytes));
+        self.test = Some(test);
+        true
+    }
+
+    /// Handle the UART interrupt: decode the received bytes & queue the next frames. The bytes
+    /// received while no test is running are ignored.
+    pub fn on_interrupt(&mut self) {
+        let test = &mut self.test;
+        self.uart.on_interrupt(|_, byte| {
+            if let Some(test) = test {
+                test.receive(byte);
+            }
+        });
+        if let Some(test) = &mut self.test {
+            test.send(self.uart.free_space(), |bytes| self.uart.write(bytes));
+        }
+    }
+
+    /// The report of the running test once all frames have been received or it has timed out,
+    /// `None` while it's still running (or if none has been started).
+    pub fn poll(&mut self, now_ms: u32) -> Option<Report> {
+        let test = self.test.as_ref()?;
+        if !test.is_complete() && now_ms.wrapping_sub(test.started_ms) < TIMEOUT_MS {
+            return None;
+        }
+        self.test.take().map(|test| test.report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(decoder: &mut Decoder, bytes: &[u8]) -> Vec<Result<(u8, Vec<u8>), FrameError>> {
+        bytes
+            .iter()
+            .filter_map(|&byte| decoder.push(byte))
+            .map(|result| result.map(|frame| (frame.seq, frame.payload.to_vec())))
+            .collect()
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut decoder = Decoder::default();
+        for (seq, vector) in TEST_VECTORS.iter().enumerate() {
+            let frame = encode(seq as u8, vector, false);
+            // the delimiter only appears at the start & the end
+            assert!(!frame[1..frame.len() - 1].contains(&FLAG));
+            assert_eq!(
+                decode(&mut decoder, &frame),
+                vec![Ok((seq as u8, vector.to_vec()))]
+            );
+        }
+    }
+
+    #[test]
+    fn corrupted_frames_are_rejected() {
+        let mut decoder = Decoder::default();
+        let frame = encode(7, &CONSECUTIVE, true);
+        assert_eq!(decode(&mut decoder, &frame), vec![Err(FrameError::Crc)]);
+
+        let mut frame = encode(7, b"test", false);
+        frame[3] ^= 0x04;
+        assert_eq!(decode(&mut decoder, &frame), vec![Err(FrameError::Crc)]);
+        let frame = encode(8, b"test", false);
+        assert_eq!(
+            decode(&mut decoder, &frame),
+            vec![Ok((8, b"test".to_vec()))]
+        );
+    }
+
+    #[test]
+    fn internal_loopback_passes() {
+        let report = run_internal();
+        assert!(report.passed(), "{report:?}");
+    }
+
+    #[test]
+    fn lost_bytes_fail_the_test() {
+        let mut test = SelfTest::new(Mode::Uart, 0);
+        let mut sent = Vec::new();
+        test.send(usize::MAX, |bytes| sent.extend_from_slice(bytes));
+        // an overrun of the receiver, the byte after the first delimiter
+        sent.remove(1);
+        for byte in sent {
+            test.receive(byte);
+        }
+        assert!(!test.report.passed());
+        assert!(test.report.received < Report::EXPECTED);
+    }
+}
@@ -0,0 +1,275 @@
+//! Long-range reporting using a SX1276/77/78/79 LoRa radio.
+//!
+//! The radio is connected to SPI2 (`PB13` = SCK, `PB14` = MISO, `PB15` = MOSI, `PB12` = NSS) with
+//! its reset line on `PC9`. It is operated in raw LoRa mode (no LoRaWAN), sending small binary
+//! packets:
+//!
+//! | Offset | Size | Content                                     |
+//! |--------|------|---------------------------------------------|
+//! | 0      | 1    | packet type: `b'D'` = report, `b'A'` = alarm |
+//! | 1      | 4    | sequence number of the measurement (LE)     |
+//! | 5      | 2    | distance in mm (LE)                         |
+//! | 7      | 1    | range status                                |
+//!
+//! The latest measurement is reported periodically, alarms (a valid measurement closer than the
+//! configured threshold) are sent immediately.
+
+use crate::config::lora as config;
+use crate::sink::Sink;
+use crate::telemetry::Measurement;
+use stm32f4xx_hal::gpio::{Output, PB12, PC9};
+use stm32f4xx_hal::pac::SPI2;
+use stm32f4xx_hal::spi::Spi;
+use vl53l1x_uld::RangeStatus;
+
+/// Frequency of the crystal oscillator of the radio.
+const OSCILLATOR_HZ: u64 = 32_000_000;
+/// Expected content of the version register.
+const SX127X_VERSION: u8 = 0x12;
+/// Size of a packet.
+const PACKET_LEN: usize = 8;
+
+mod reg {
+    pub const FIFO: u8 = 0x00;
+    pub const OP_MODE: u8 = 0x01;
+    pub const FRF_MSB: u8 = 0x06;
+    pub const PA_CONFIG: u8 = 0x09;
+    pub const FIFO_ADDR_PTR: u8 = 0x0D;
+    pub const FIFO_TX_BASE_ADDR: u8 = 0x0E;
+    pub const IRQ_FLAGS: u8 = 0x12;
+    pub const MODEM_CONFIG_1: u8 = 0x1D;
+    pub const MODEM_CONFIG_2: u8 = 0x1E;
+    pub const PAYLOAD_LENGTH: u8 = 0x22;
+    pub const MODEM_CONFIG_3: u8 = 0x26;
+    pub const SYNC_WORD: u8 = 0x39;
+    pub const VERSION: u8 = 0x42;
+}
+
+mod mode {
+    pub const LONG_RANGE: u8 = 0x80;
+    pub const SLEEP: u8 = 0x00;
+    pub const STANDBY: u8 = 0x01;
+    pub const TX: u8 = 0x03;
+}
+
+const IRQ_TX_DONE: u8 = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    Spi,
+    /// The radio didn't report the expected version, it is probably not connected.
+    UnknownVersion(u8),
+}
+
+pub struct LoraUplink {
+    spi: Spi<SPI2>,
+    nss: PB12<Output>,
+    /// Whether a packet is currently being transmitted.
+    transmitting: bool,
+    latest: Option<Measurement>,
+    /// Time at which the last report has been sent.
+    last_report_ms: u32,
+    /// Whether the alarm condition is currently active.
+    alarm_active: bool,
+    /// An alarm packet which still needs to be sent.
+    pending_alarm: Option<Measurement>,
+    /// Alarms are raised below this distance, can be changed at runtime.
+    alarm_threshold_mm: u16,
+    /// Whether no alarms are raised as it's outside of the active hours, see [`crate::schedule`].
+    alarms_muted: bool,
+}
+
+impl LoraUplink {
+    /// Reset & configure the radio.
+    pub fn new(
+        spi: Spi<SPI2>,
+        nss: PB12<Output>,
+        mut reset: PC9<Output>,
+        sysclk_hz: u32,
+    ) -> Result<Self, Error> {
+        let mut radio = Self {
+            spi,
+            nss,
+            transmitting: false,
+            latest: None,
+            last_report_ms: 0,
+            alarm_active: false,
+            pending_alarm: None,
+            alarm_threshold_mm: config::ALARM_THRESHOLD_MM,
+            alarms_muted: false,
+        };
+        radio.nss.set_high();
+
+        // this runs during init where no timers are available yet
+        reset.set_low();
+        cortex_m::asm::delay(sysclk_hz / 1_000);
+        reset.set_high();
+        cortex_m::asm::delay(sysclk_hz / 100);
+
+        let version = radio.read_register(reg::VERSION)?;
+        if version != SX127X_VERSION {
+            return Err(Error::UnknownVersion(version));
+        }
+
+        // the LoRa mode can only be selected in sleep mode
+        radio.write_register(reg::OP_MODE, mode::LONG_RANGE | mode::SLEEP)?;
+        let frf = ((config::FREQUENCY_HZ as u64) << 19) / OSCILLATOR_HZ;
+        radio.write_register(reg::FRF_MSB, (frf >> 16) as u8)?;
+        radio.write_register(reg::FRF_MSB + 1, (frf >> 8) as u8)?;
+        radio.write_register(reg::FRF_MSB + 2, frf as u8)?;
+        radio.write_register(reg::FIFO_TX_BASE_ADDR, 0)?;
+        // PA_BOOST output with 17 dBm
+        radio.write_register(reg::PA_CONFIG, 0x80 | 0x0F)?;
+        // 125 kHz bandwidth, coding rate 4/5, explicit header
+        radio.write_register(reg::MODEM_CONFIG_1, 0x72)?;
+        // spreading factor, CRC enabled
+        radio.write_register(reg::MODEM_CONFIG_2, (config::SPREADING_FACTOR << 4) | 0x04)?;
+        // AGC on, low data rate optimization needed if a symbol takes longer than 16 ms
+        let low_data_rate_optimize = if config::SPREADING_FACTOR >= 11 {
+            0x08
+        } else {
+            0
+        };
+        radio.write_register(reg::MODEM_CONFIG_3, 0x04 | low_data_rate_optimize)?;
+        // private network
+        radio.write_register(reg::SYNC_WORD, 0x12)?;
+        radio.write_register(reg::OP_MODE, mode::LONG_RANGE | mode::STANDBY)?;
+
+        defmt::info!(
+            "LoRa radio set up: {} Hz, SF{}",
+            config::FREQUENCY_HZ,
+            config::SPREADING_FACTOR
+        );
+        Ok(radio)
+    }
+
+    /// Change the distance below which alarms are raised.
+    pub fn set_alarm_threshold(&mut self, threshold_mm: u16) {
+        self.alarm_threshold_mm = threshold_mm;
+    }
+
+    /// Stop (or resume) raising alarms, the reports are still sent.
+    pub fn set_alarms_muted(&mut self, muted: bool) {
+        self.alarms_muted = muted;
+    }
+
+    /// The distance below which alarms are raised.
+    pub fn alarm_threshold_mm(&self) -> u16 {
+        self.alarm_threshold_mm
+    }
+
+    /// Hand a new measurement to the uplink. Alarms are sent immediately.
+    pub fn publish(&mut self, measurement: &Measurement) {
+        self.latest = Some(*measurement);
+
+        if measurement.status != RangeStatus::Valid {
+            return;
+        }
+        if !self.alarm_active
+            && !self.alarms_muted
+            && measurement.distance_mm < self.alarm_threshold_mm
+        {
+            defmt::info!("LoRa: alarm at {}mm", measurement.distance_mm);
+            self.alarm_active = true;
+            self.pending_alarm = Some(*measurement);
+            self.send_pending().ok();
+        } else if self.alarm_active
+            && measurement.distance_mm
+                > self
+                    .alarm_threshold_mm
+                    .saturating_add(config::ALARM_HYSTERESIS_MM)
+        {
+            self.alarm_active = false;
+        }
+    }
+
+    /// Send pending alarms and periodic reports. Must be called periodically.
+    pub fn tick(&mut self, now_ms: u32) {
+        if let Err(e) = self.send_pending().and_then(|()| self.send_report(now_ms)) {
+            defmt::warn!("LoRa: failed to send: {}", e);
+        }
+    }
+
+    fn send_pending(&mut self) -> Result<(), Error> {
+        if let Some(measurement) = self.pending_alarm {
+            if self.send(b'A', &measurement)? {
+                self.pending_alarm = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the latest measurement once the report is due. A pending alarm takes precedence, the
+    /// report is retried at the next tick (as it is while the radio is still busy).
+    fn send_report(&mut self, now_ms: u32) -> Result<(), Error> {
+        if now_ms.wrapping_sub(self.last_report_ms) < config::REPORT_INTERVAL_S * 1_000
+            || self.pending_alarm.is_some()
+        {
+            return Ok(());
+        }
+        if let Some(measurement) = self.latest {
+            if self.send(b'D', &measurement)? {
+                self.last_report_ms = now_ms;
+                self.latest = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start transmitting a packet. Returns `false` if the radio is still busy with the previous
+    /// packet.
+    fn send(&mut self, packet_type: u8, measurement: &Measurement) -> Result<bool, Error> {
+        if self.transmitting {
+            if self.read_register(reg::IRQ_FLAGS)? & IRQ_TX_DONE == 0 {
+                return Ok(false);
+            }
+            self.write_register(reg::IRQ_FLAGS, 0xFF)?;
+            self.transmitting = false;
+        }
+
+        let mut packet = [0u8; PACKET_LEN];
+        packet[0] = packet_type;
+        packet[1..5].copy_from_slice(&measurement.seq.to_le_bytes());
+        packet[5..7].copy_from_slice(&measurement.distance_mm.to_le_bytes());
+        packet[7] = measurement.status as u8;
+
+        self.write_register(reg::FIFO_ADDR_PTR, 0)?;
+        self.nss.set_low();
+        let result = self
+            .spi
+            .write(&[reg::FIFO | 0x80])
+            .and_then(|_| self.spi.write(&packet));
+        self.nss.set_high();
+        result.map_err(|_| Error::Spi)?;
+        self.write_register(reg::PAYLOAD_LENGTH, PACKET_LEN as u8)?;
+        self.write_register(reg::OP_MODE, mode::LONG_RANGE | mode::TX)?;
+        self.transmitting = true;
+        Ok(true)
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u8, Error> {
+        let mut buffer = [register & 0x7F, 0];
+        self.nss.set_low();
+        let result = self.spi.transfer_in_place(&mut buffer).map(|_| buffer[1]);
+        self.nss.set_high();
+        result.map_err(|_| Error::Spi)
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), Error> {
+        self.nss.set_low();
+        let result = self.spi.write(&[register | 0x80, value]);
+        self.nss.set_high();
+        result.map_err(|_| Error::Spi)
+    }
+}
+
+/// The reports & the alarms are derived from the measurements, the frames aren't sent.
+impl Sink for LoraUplink {
+    fn measurement(&mut self, measurement: &Measurement, _frame: &str) {
+        self.publish(measurement);
+    }
+
+    fn batched(&mut self, measurement: &Measurement) {
+        self.publish(measurement);
+    }
+}
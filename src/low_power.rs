@@ -0,0 +1,232 @@
+//! The low-power presence mode ([`crate::app_mode::AppMode::LowPower`]): while there's no target
+//! the sensor measures on its own at a long interval & only signals the measurements closer than
+//! [`crate::config::app_mode::PRESENCE_MM`] with its interrupt (see
+//! [`crate::range_sensor::RangeSensor::set_interrupt_threshold`]), the microcontroller waits in the
+//! stop mode in between.
+//!
+//! A valid measurement closer than the threshold wakes the firmware up: the sensor continues with
+//! the previous settings & the measurements are processed as in the presence mode. Other ones
+//! (e.g. an invalid measurement which crossed the threshold) are dropped & the microcontroller
+//! stops again. Once the target has been gone for [`config::LINGER_MS`] the sensor waits for the
+//! next one again.
+//!
+//! The timer of the tasks stops together with the clocks, thus the uptime (& the timestamps of the
+//! measurements) only advances while the firmware is awake & the periodic tasks (e.g. the health
+//! report) pause. The wakeup timer of the RTC wakes the microcontroller every
+//! [`WAKEUP_INTERVAL_S`] to feed the watchdog.
+
+use crate::config::app_mode::PRESENCE_MM;
+use crate::config::low_power as config;
+use crate::range_sensor::Reading;
+use crate::settings::TofSettings;
+use stm32f4xx_hal::pac;
+use vl53l1x_uld::RangeStatus;
+
+/// Interval at which the microcontroller is woken up to feed the watchdog.
+pub const WAKEUP_INTERVAL_S: u32 = 1;
+/// Timeout of the watchdog while waiting for a target, long enough for the wakeup interval.
+pub const WATCHDOG_TIMEOUT_MS: u32 = 4_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum State {
+    /// The low-power mode isn't active.
+    Off,
+    /// Waiting for a target with the threshold of the sensor, the microcontroller stops.
+    Armed,
+    /// Ranging with the previous settings since a target has been detected.
+    Awake,
+}
+
+/// What to do with a measurement, see [`LowPower::on_reading`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Wakeup {
+    /// The mode isn't armed, the measurement is processed as usual.
+    NotArmed,
+    /// A target has been detected, the previous settings have to be restored.
+    Presence,
+    /// The measurement doesn't show a target & is dropped.
+    Ignored,
+}
+
+pub struct LowPower {
+    state: State,
+    /// The settings of the sensor before it has been armed, they're restored once woken up.
+    settings: Option<TofSettings>,
+    /// Time since which the target has been gone while awake.
+    gone_since_ms: Option<u32>,
+    /// Whether the microcontroller is kept running, see [`Self::hold`].
+    held: bool,
+}
+
+impl LowPower {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Off,
+            settings: None,
+            gone_since_ms: None,
+            held: false,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.state == State::Armed
+    }
+
+    /// Whether the microcontroller may stop until the next interrupt.
+    pub fn may_stop(&self) -> bool {
+        self.is_armed() && !self.held
+    }
+
+    /// Keep the microcontroller running until [`Self::release`], e.g. while the user button is
+    /// debounced with the timer of the tasks.
+    pub fn hold(&mut self) {
+        self.held = true;
+    }
+
+    pub fn release(&mut self) {
+        self.held = false;
+    }
+
+    /// The sensor waits for a target with the [`armed_settings`], `settings` are the ones which
+    /// were used before.
+    pub fn arm(&mut self, settings: TofSettings) {
+        defmt::info!("waiting for a target in the low-power mode");
+        self.state = State::Armed;
+        self.settings = Some(settings);
+        self.gone_since_ms = None;
+    }
+
+    /// Check a measurement of the sensor.
+    pub fn on_reading(&mut self, reading: &Reading) -> Wakeup {
+        if self.state != State::Armed {
+            return Wakeup::NotArmed;
+        }
+        if reading.status != RangeStatus::Valid || reading.distance_mm >= PRESENCE_MM {
+            return Wakeup::Ignored;
+        }
+        defmt::info!("woken up by a target at {} mm", reading.distance_mm);
+        self.state = State::Awake;
+        Wakeup::Presence
+    }
+
+    /// The settings which have to be restored after waking up (or leaving the mode), they're only
+    /// returned once.
+    pub fn take_settings(&mut self) -> Option<TofSettings> {
+        self.settings.take()
+    }
+
+    /// Leave the mode, the settings have to be restored if they haven't been yet.
+    pub fn disable(&mut self) {
+        self.state = State::Off;
+    }
+
+    /// Track the presence while awake, returns whether the sensor should wait for the next target
+    /// again.
+    pub fn on_presence(&mut self, present: bool, now_ms: u32) -> bool {
+        if self.state != State::Awake || present {
+            self.gone_since_ms = None;
+            return false;
+        }
+        let gone_since_ms = *self.gone_since_ms.get_or_insert(now_ms);
+        now_ms.wrapping_sub(gone_since_ms) >= config::LINGER_MS
+    }
+}
+
+/// The settings of the sensor while waiting for a target, in its current distance mode.
+pub fn armed_settings(settings: &TofSettings) -> TofSettings {
+    TofSettings {
+        timing_budget_ms: config::TIMING_BUDGET_MS,
+        inter_measurement_ms: config::INTERVAL_MS,
+        ..*settings
+    }
+}
+
+/// Wait in the stop mode until the next interrupt, the interrupts have to be masked so that the
+/// clocks are running again before it's handled.
+#[allow(unsafe_code)]
+pub fn stop() {
+    // SAFETY: the interrupts are masked, nothing else accesses the registers meanwhile
+    let device = unsafe { pac::Peripherals::steal() };
+    let mut core = unsafe { cortex_m::Peripherals::steal() };
+    // the voltage regulator in low-power mode & the flash powered down
+    device
+        .PWR
+        .cr
+        .modify(|_, w| w.pdds().clear_bit().lpds().set_bit().fpds().set_bit());
+    core.SCB.set_sleepdeep();
+    cortex_m::asm::wfi();
+    core.SCB.clear_sleepdeep();
+
+    // the microcontroller continues on the HSI, the PLL (& the HSE it may use) has to be started
+    // again, its configuration is kept
+    let rcc = &device.RCC;
+    if rcc.pllcfgr.read().pllsrc().is_hse() {
+        rcc.cr.modify(|_, w| w.hseon().set_bit());
+        while rcc.cr.read().hserdy().bit_is_clear() {}
+    }
+    rcc.cr.modify(|_, w| w.pllon().set_bit());
+    while rcc.cr.read().pllrdy().bit_is_clear() {}
+    rcc.cfgr.modify(|_, w| w.sw().pll());
+    while !rcc.cfgr.read().sws().is_pll() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vl53l1x_uld::DistanceMode;
+
+    const SETTINGS: TofSettings = TofSettings {
+        distance_mode: DistanceMode::Long,
+        timing_budget_ms: 50,
+        inter_measurement_ms: 100,
+    };
+
+    fn reading(distance_mm: u16, status: RangeStatus) -> Reading {
+        Reading {
+            distance_mm,
+            status,
+            ambient_kcps: 0,
+            signal_per_spad_kcps: 0,
+        }
+    }
+
+    #[test]
+    fn wakes_up_on_a_genuine_presence() {
+        let mut low_power = LowPower::new();
+        let near = reading(PRESENCE_MM / 2, RangeStatus::Valid);
+        assert_eq!(low_power.on_reading(&near), Wakeup::NotArmed);
+        low_power.arm(SETTINGS);
+        assert!(low_power.may_stop());
+        low_power.hold();
+        assert!(low_power.is_armed() && !low_power.may_stop());
+        let invalid = reading(PRESENCE_MM / 2, RangeStatus::SignalFailure);
+        assert_eq!(low_power.on_reading(&invalid), Wakeup::Ignored);
+        let far = reading(PRESENCE_MM, RangeStatus::Valid);
+        assert_eq!(low_power.on_reading(&far), Wakeup::Ignored);
+        assert_eq!(low_power.on_reading(&near), Wakeup::Presence);
+        assert_eq!(low_power.state(), State::Awake);
+        assert_eq!(low_power.take_settings(), Some(SETTINGS));
+        assert_eq!(low_power.take_settings(), None);
+    }
+
+    #[test]
+    fn waits_again_once_the_target_is_gone() {
+        let mut low_power = LowPower::new();
+        low_power.arm(SETTINGS);
+        low_power.on_reading(&reading(100, RangeStatus::Valid));
+        assert!(!low_power.on_presence(true, 0));
+        assert!(!low_power.on_presence(false, 1_000));
+        // the target is back before the linger time is over
+        assert!(!low_power.on_presence(true, 2_000));
+        assert!(!low_power.on_presence(false, 3_000));
+        assert!(low_power.on_presence(false, 3_000 + config::LINGER_MS));
+        assert_eq!(
+            armed_settings(&SETTINGS).inter_measurement_ms,
+            config::INTERVAL_MS
+        );
+    }
+}
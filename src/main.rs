@@ -2,120 +2,5291 @@
 #![no_main]
 #![no_std]
 
-// Halt on panic
-//use panic_halt as _; // panic handler
+// Halt on panic for the debugger or reset, see `panic_persist`
+#[cfg(feature = "panic-probe")]
 use panic_probe as _;
+#[cfg(not(any(
+    feature = "panic-probe",
+    feature = "panic-reset",
+    feature = "panic-persist"
+)))]
+compile_error!("one of the features `panic-probe`, `panic-reset` or `panic-persist` is required");
+
+#[cfg(any(feature = "panic-reset", feature = "panic-persist"))]
+#[panic_handler]
+fn on_panic(info: &core::panic::PanicInfo) -> ! {
+    panic_persist::reset(info)
+}
 
 use defmt_rtt as _;
 
-#[rtic::app(device = stm32f4xx_hal::pac, dispatchers = [EXTI1, EXTI2])]
+use nucleo_f401re_rtic_vl53l1x_uld::*;
+
+// the invariants of the priorities & the dispatchers are described in `config::rt`
+#[rtic::app(device = stm32f4xx_hal::pac, dispatchers = [EXTI1, SPI4])]
 mod app {
+    use crate::acquisition::{self, Acquisition};
+    use crate::ambient::AmbientMonitor;
+    use crate::app_mode::{self, AppMode, PresenceDetector, Selection};
+    use crate::batch::{self, Batch, Batcher};
+    use crate::blink_code::{self, ErrorClass};
+    use crate::boot_config::BootConfig;
+    use crate::bootloader;
+    use crate::brown_out::{self, SupplyMonitor};
+    use crate::build_info;
+    use crate::cadence::CadenceTimer;
+    use crate::calibration::{OffsetCalibration, Progress};
+    use crate::calibration_store::{self, CalibrationData};
+    use crate::calibration_wizard::{CalibrationWizard, Step};
+    use crate::capture::{self, Capture, Outcome};
+    use crate::change_filter::{self, ChangeFilter};
+    use crate::clock::Clock;
+    #[cfg(feature = "motor-pid")]
+    use crate::command::PidCommand;
+    use crate::command::{
+        self, CalCommand, CaptureCommand, Command, DriftCommand, ParseError, ProfileCommand,
+        ProtectCommand, ResetToken, Response,
+    };
+    use crate::control_block::{self, Request, Status, CONTROL_BLOCK};
+    use crate::controls::Controls;
+    use crate::cpu_load::CpuLoad;
+    #[cfg(feature = "nor-flash")]
+    use crate::data_log::LogCommand;
+    use crate::data_log::{DataLog, LogRequests, Shutdown};
+    use crate::device_id::DeviceId;
+    use crate::display::{DisplayState, Displays, MenuView};
+    use crate::drift::{self, DriftMonitor};
+    use crate::eeprom::Eeprom;
+    use crate::event_log::{self, Event, EventLog};
+    use crate::extremes::Extremes;
+    use crate::fast_mode;
+    #[cfg(feature = "littlefs")]
+    use crate::file_system::{FileSystem, FileWrite, FlashStorage};
+    use crate::geofence::{self, Actions, Geofence};
+    use crate::health::HealthMonitor;
+    use crate::i2c_guard::{self, GuardedI2c};
+    use crate::i2c_scan;
+    use crate::i2c_timing;
+    use crate::image_check::ImageState;
+    use crate::inputs::Inputs;
+    use crate::interleave::Interleaver;
+    use crate::interrupt_guard::{self, InterruptGuard, Verdict};
+    use crate::isr_budget::IsrBudget;
+    use crate::jitter::JitterStats;
+    use crate::lens::LensMonitor;
+    use crate::links::Links;
+    use crate::log_backend::{self, Channel, DebugFormat};
+    #[cfg(feature = "nor-flash")]
+    use crate::log_record;
+    use crate::low_power::{self, LowPower, Wakeup};
+    use crate::measurement_queue::MeasurementQueue;
+    use crate::metrics;
+    use crate::option_bytes::{self, Protection};
+    use crate::outputs::Outputs;
+    use crate::pause::{self, Pause};
+    use crate::post::{Post, Stage};
+    use crate::preset::Preset;
+    use crate::profile::Profile;
+    use crate::publish_interval::{self, Averager};
+    use crate::range_gate::RangeGate;
+    use crate::range_sensor::{self, Backend, RangeSensor, Reading};
+    use crate::reset_log::{self, ResetCause};
+    use crate::rollup::RollupAccumulator;
+    use crate::sample_history::{self, Export, SampleHistory};
+    use crate::scaling::Scaling;
+    use crate::schedule::Schedule;
+    use crate::sensor_supervisor::{self, Action, SensorSupervisor};
+    use crate::session::{self, SessionId};
+    use crate::settings::{SchemaState, Settings, TofSettings};
+    use crate::shutdown::TofShutdown;
+    use crate::spawn_guard::Spawned;
+    use crate::status_led::{Pattern, StatusLed};
+    use crate::task_watchdog::{self, Task, TaskWatchdog};
+    use crate::telemetry::{self, FrameFormat, Health, Measurement};
+    use crate::time_sync::TimeSync;
+    use crate::track::Tracker;
+    use crate::trigger::Trigger;
+    use crate::user_button::{ButtonEvent, UserButton};
+    use crate::warm_up::WarmUp;
+    use crate::watchdog_margin::WatchdogMargin;
+    use crate::webhook;
+    use crate::wrap_around::WrapCheck;
+    use crate::I2cBus;
+    use crate::{board_pins, log_error, log_info, log_warn};
+    use core::fmt::Write;
+    use cortex_m::peripheral::DWT;
+    // the tasks import it into their modules, `handle_tof_interrupt` locks outside of them
+    use rtic::Mutex as _;
+    use stm32f4xx_hal::dma::StreamsTuple;
     use stm32f4xx_hal::pac::IWDG;
     use stm32f4xx_hal::rcc::{Clocks, Rcc};
+    use stm32f4xx_hal::timer::fugit::ExtU64 as _;
     use stm32f4xx_hal::{
-        gpio::{Edge, Input, PA0},
-        i2c::{I2c, I2c1},
-        pac,
-        prelude::*,
-        timer::MonoTimerUs,
+        crc32::Crc32, pac, prelude::*, serial::Serial, timer::MonoTimer64Us,
         watchdog::IndependentWatchdog,
     };
-    use vl53l1x_uld::{IOVoltage, Polarity, VL53L1X};
+    use vl53l1x_uld::{DistanceMode, RangeStatus};
+
+    use stm32f4xx_hal::adc::{
+        config::{AdcConfig, SampleTime},
+        Adc, Temperature, Vref,
+    };
+    #[cfg(feature = "usb")]
+    use stm32f4xx_hal::otg_fs::{UsbBus, UsbBusType, USB};
+    #[cfg(any(feature = "proximity-led", feature = "buzzer", feature = "servo"))]
+    use stm32f4xx_hal::timer::Channel1;
+    #[cfg(any(feature = "motor-pid", feature = "analog-output"))]
+    use stm32f4xx_hal::timer::Channel2;
+    #[cfg(any(feature = "lora", feature = "sd-card", feature = "nor-flash"))]
+    use stm32f4xx_hal::{hal, spi::Spi};
+    #[cfg(feature = "usb")]
+    use usb_device::bus::UsbBusAllocator;
 
     #[monotonic(binds = TIM2, default = true)]
-    type MicrosecMono = MonoTimerUs<pac::TIM2>;
+    type MicrosecMono = MonoTimer64Us<pac::TIM2>;
+    type Instant = <MicrosecMono as rtic::Monotonic>::Instant;
 
-    type TOFSensor = VL53L1X<I2c1>;
+    /// The bus to the TOF sensor, the simulated one with the `sim` feature.
+    #[cfg(not(feature = "sim"))]
+    type SensorBus = I2cBus;
+    #[cfg(feature = "sim")]
+    type SensorBus = crate::sim::SimBus;
+    #[cfg(not(feature = "i2c-trace"))]
+    type TracedBus = SensorBus;
+    #[cfg(feature = "i2c-trace")]
+    type TracedBus = crate::i2c_trace::TracedBus<SensorBus>;
+    #[cfg(not(feature = "fault-injection"))]
+    type TofBus = TracedBus;
+    #[cfg(feature = "fault-injection")]
+    type TofBus = crate::fault_injection::FaultyBus<TracedBus>;
+    /// The TOF sensor with the backend of the probed variant, the tasks use it only as a
+    /// [`RangeSensor`].
+    type TOFSensor = Backend<TofBus>;
+    type TofError = <TOFSensor as RangeSensor>::Error;
+    /// The running firmware update, a placeholder without the feature as the fields of the
+    /// resources can't be configured out.
+    #[cfg(feature = "firmware-update")]
+    type FirmwareUpdate = crate::firmware_update::FirmwareUpdate;
+    #[cfg(not(feature = "firmware-update"))]
+    type FirmwareUpdate = ();
+    /// The delays of the data ready interrupts of the `fault-injection` feature, a placeholder
+    /// without it.
+    #[cfg(feature = "fault-injection")]
+    type InterruptFaults = crate::fault_injection::InterruptFaults;
+    #[cfg(not(feature = "fault-injection"))]
+    type InterruptFaults = ();
+    /// The UART of the loopback self-test of the `uart-loopback` feature & the way its frames take,
+    /// placeholders without it.
+    #[cfg(feature = "uart-loopback")]
+    type Loopback = crate::loopback::UartLoopback;
+    #[cfg(not(feature = "uart-loopback"))]
+    type Loopback = ();
+    #[cfg(feature = "uart-loopback")]
+    type LoopbackMode = crate::loopback::Mode;
+    #[cfg(not(feature = "uart-loopback"))]
+    type LoopbackMode = ();
+    /// The second TOF sensor of the `second-tof` feature, a placeholder without it.
+    #[cfg(feature = "second-tof")]
+    type SecondTof = crate::second_tof::SecondTof;
+    #[cfg(not(feature = "second-tof"))]
+    type SecondTof = ();
+    /// The TOF sensors behind the I2C multiplexer of the `tof-mux` feature, a placeholder without
+    /// it.
+    #[cfg(feature = "tof-mux")]
+    type TofArray = crate::tof_mux::TofArray;
+    #[cfg(not(feature = "tof-mux"))]
+    type TofArray = ();
+    /// The battery monitor of the `battery` feature, a placeholder without it.
+    #[cfg(feature = "battery")]
+    type Battery = crate::battery::Battery;
+    #[cfg(not(feature = "battery"))]
+    type Battery = ();
+    /// The GPIO expander of the `gpio-expander` feature (`None` if it isn't available), a
+    /// placeholder without it.
+    #[cfg(feature = "gpio-expander")]
+    type GpioExpander = Option<crate::gpio_expander::GpioExpander>;
+    #[cfg(not(feature = "gpio-expander"))]
+    type GpioExpander = ();
+    /// The trigger input of the `trigger-input` feature, a placeholder without it.
+    #[cfg(feature = "trigger-input")]
+    type TriggerPin = crate::trigger::TriggerPin;
+    #[cfg(not(feature = "trigger-input"))]
+    type TriggerPin = ();
+    /// The marker input of the `marker-input` feature, a placeholder without it.
+    #[cfg(feature = "marker-input")]
+    type MarkerInput = crate::marker::MarkerInput;
+    #[cfg(not(feature = "marker-input"))]
+    type MarkerInput = ();
+    /// An edge of the marker input, a placeholder without the `marker-input` feature.
+    #[cfg(feature = "marker-input")]
+    type Mark = crate::marker::Mark;
+    #[cfg(not(feature = "marker-input"))]
+    type Mark = ();
+    /// The demo of the `demo` feature, a placeholder without it.
+    #[cfg(feature = "demo")]
+    type Demo = crate::demo::Demo;
+    #[cfg(not(feature = "demo"))]
+    type Demo = ();
 
     #[shared]
-    struct Shared {}
+    struct Shared {
+        watchdog: IndependentWatchdog,
+        tof_sensor: TOFSensor,
+        /// Whether the TOF sensor is currently ranging.
+        ranging: bool,
+        acquisition: Acquisition,
+        /// Number of measurements received, continued after a reset unless the power was lost.
+        measurement_count: u32,
+        /// Number of measurements since boot which weren't valid or were suppressed.
+        rejected_count: u32,
+        /// Drops the measurements outside of the accepted window, see [`crate::range_gate`].
+        range_gate: RangeGate,
+        /// Checks the drift of the calibration against a static scene, see [`crate::drift`].
+        drift_monitor: DriftMonitor,
+        /// The active hours of the alarms & the presence events, see [`crate::schedule`].
+        schedule: Schedule,
+        /// The most recent measurement.
+        latest_measurement: Option<Measurement>,
+        /// Time of the latest successful read of the TOF sensor, also if the reading has been
+        /// dropped afterwards (e.g. by the range gate), see [`crate::sensor_supervisor`].
+        last_read_ms: Option<u32>,
+        /// The measurements which wait for [`publish`], only used at the same priority.
+        #[lock_free]
+        measurements: MeasurementQueue,
+        /// Whether the last communication with the TOF sensor failed.
+        sensor_error: bool,
+        /// Set if the TOF sensor can't be operated, e.g. because it failed to initialize. The
+        /// firmware keeps running (links, commands) but doesn't range anymore.
+        safe_mode: bool,
+        links: Links,
+        outputs: Outputs,
+        app_mode: AppMode,
+        frame_format: FrameFormat,
+        /// The unit & transform of the published value, see [`crate::scaling`].
+        scaling: Scaling,
+        /// The interval at which the [`averager`](Shared::averager) is published, `None` to
+        /// publish every measurement, see [`crate::publish_interval`].
+        publish_interval_ms: Option<u16>,
+        averager: Averager,
+        /// Suppresses the unchanged measurements, see [`crate::change_filter`].
+        change_filter: ChangeFilter,
+        geofence: Geofence,
+        low_power: LowPower,
+        user_button: UserButton,
+        /// The single measurements started by the trigger input or the cadence, see
+        /// [`crate::trigger`].
+        trigger: Trigger,
+        /// The windows of the TOF sensors while interleaved, see [`crate::interleave`].
+        interleaver: Interleaver,
+        /// Set by the cadence to start a measurement of the second TOF sensor in its window.
+        second_tof_start: bool,
+        /// The running offset calibration.
+        calibration: Option<OffsetCalibration>,
+        /// The distance to the target of the offset calibration, see [`crate::calibration`].
+        cal_target_mm: u16,
+        /// The running calibration wizard, see [`crate::calibration_wizard`].
+        cal_wizard: Option<CalibrationWizard>,
+        /// The internal flash, used by the [`eeprom`].
+        flash: pac::FLASH,
+        /// The real-time clock & its backup registers.
+        clock: Clock,
+        /// `None` if the EEPROM emulation can't be used, e.g. because the flash is damaged.
+        eeprom: Option<Eeprom>,
+        /// Number of blinks to be shown on the status LED, e.g. after changing a setting.
+        led_indication: Option<u8>,
+        /// The active profile, `None` if none has been selected yet.
+        profile: Option<Profile>,
+        /// The latest applied preset, `None` if none has been applied since boot.
+        preset: Option<Preset>,
+        /// The open on-device menu, shown on the displays instead of the distance.
+        menu_view: Option<MenuView>,
+        /// The narration of the demo, shown on the displays instead of the distance unless the
+        /// menu is open.
+        narration: Option<MenuView>,
+        demo: Demo,
+        log_requests: LogRequests,
+        /// The statistics of the measurements since the last rollup.
+        rollup: RollupAccumulator,
+        /// The latest measurements for the `dump csv` command.
+        sample_history: SampleHistory,
+        /// The minimum & the maximum distance for `stats` & `status`.
+        extremes: Extremes,
+        /// The running bounded capture, see [`crate::capture`].
+        capture: Capture,
+        /// The pending batch of measurements, see [`crate::batch`].
+        batcher: Batcher,
+        /// The warm-up of the TOF sensor after the ranging has been started.
+        warm_up: WarmUp,
+        /// Pauses the acquisition while the TOF sensor is changed, see [`crate::pause`].
+        pause: Pause,
+        /// Checks the cover glass, see [`crate::lens`].
+        lens: LensMonitor,
+        sensor_supervisor: SensorSupervisor,
+        loopback: Loopback,
+        interrupt_guard: InterruptGuard,
+        /// The intervals between the measurements.
+        jitter: JitterStats,
+        /// The CPU load of the latest window, `None` until it has been measured, see
+        /// [`crate::cpu_load`].
+        cpu_load: Option<u8>,
+        watchdog_margin: WatchdogMargin,
+        task_watchdog: TaskWatchdog,
+        /// The configuration switches & the status LEDs on the GPIO expander, see
+        /// [`crate::gpio_expander`].
+        gpio_expander: GpioExpander,
+    }
 
     #[local]
     struct Local {
-        watchdog: IndependentWatchdog,
-        tof_sensor: TOFSensor,
-        tof_data_interrupt: PA0<Input>,
+        tof_data_interrupt: crate::board::TofInterrupt,
+        /// Used to disable & enable the data ready interrupt, see [`crate::interrupt_guard`].
+        exti: pac::EXTI,
+        interrupt_faults: InterruptFaults,
+        cadence_timer: CadenceTimer,
+        isr_budget: IsrBudget,
+        supply_monitor: SupplyMonitor,
+        wrap_check: WrapCheck,
+        second_tof: SecondTof,
+        tof_array: TofArray,
+        trigger_pin: TriggerPin,
+        marker_input: MarkerInput,
+        tof_shutdown: TofShutdown,
+        firmware_update: FirmwareUpdate,
+        inputs: Inputs,
+        displays: Displays,
+        data_log: DataLog,
+        status_led: StatusLed,
+        health_monitor: HealthMonitor,
+        controls: Controls,
+        presence: PresenceDetector,
+        tracker: Tracker,
+        /// The result of the self-check of the firmware image at boot.
+        image: ImageState,
+        ambient: AmbientMonitor,
+        /// The distance mode to restore once the ambient light is normal again.
+        mode_before_ambient: Option<DistanceMode>,
+        battery: Battery,
+        /// The inter-measurement period to restore once the battery isn't low anymore.
+        period_before_low: Option<u16>,
+        event_log: EventLog,
+        /// The token issued by the latest `reset` command which hasn't been confirmed yet.
+        reset_token: Option<ResetToken>,
+        /// The token issued by the latest `protect rdp 1` command which hasn't been confirmed yet.
+        rdp_token: Option<ResetToken>,
+        /// A handle of the shared I2C bus for the `scan` command.
+        i2c_scanner: I2cBus,
     }
 
-    #[init]
+    #[init(local = [
+        #[cfg(feature = "usb")]
+        usb_ep_memory: [u32; crate::usb::EP_MEMORY_SIZE] = [0; crate::usb::EP_MEMORY_SIZE],
+        #[cfg(feature = "usb")]
+        usb_bus: Option<UsbBusAllocator<UsbBusType>> = None,
+        #[cfg(feature = "littlefs")]
+        fs_storage: Option<FlashStorage> = None,
+        #[cfg(feature = "littlefs")]
+        fs_alloc: Option<littlefs2::fs::Allocation<FlashStorage>> = None,
+    ])]
     fn init(mut ctx: init::Context) -> (Shared, Local, init::Monotonics) {
+        defmt::info!(
+            "firmware {=str} ({=str}, built at {}) on device {}",
+            build_info::VERSION,
+            build_info::GIT_HASH,
+            build_info::BUILD_UNIX_S,
+            DeviceId::get()
+        );
+        defmt::info!("log backend: {}", log_backend::select());
+        // for the budget of the data ready interrupt & the POST
+        ctx.core.DCB.enable_trace();
+        ctx.core.DWT.enable_cycle_counter();
+        let mut post = Post::start(DWT::cycle_count());
+        defmt::info!("POST {=str}", Stage::Clocks.name());
         let mut syscfg = ctx.device.SYSCFG.constrain();
 
+        let reset_cause = ResetCause::read(&ctx.device.RCC);
+        #[cfg(feature = "panic-persist")]
+        if let Some(message) = crate::panic_persist::take() {
+            log_error!("panicked before the reset: {}", message.as_str());
+        }
+        let mut pwr = ctx.device.PWR;
+        let mut clock = Clock::new(ctx.device.RTC, &mut pwr);
+        clock.listen_wakeup(&mut ctx.device.EXTI);
+        let supply_monitor = SupplyMonitor::new(pwr, &mut ctx.device.EXTI);
+        // the interrupt is only raised by a crossing of the level
+        if supply_monitor.is_low() {
+            rtic::pend(pac::Interrupt::PVD);
+        }
+        if clock.take_bootloader_request() {
+            bootloader::jump(&syscfg);
+        }
+        #[cfg(feature = "firmware-update")]
+        if clock.take_slot_b_request() {
+            crate::firmware_update::jump_to_slot_b();
+        }
+        if let Some(stage) = Stage::from_number(clock.boot_stage()) {
+            log_warn!(
+                "the previous boot didn't finish the POST stage {} (reset: {})",
+                stage.name(),
+                DebugFormat(&reset_cause)
+            );
+        }
+        clock.set_boot_stage(Stage::Clocks.number());
         let rcc = ctx.device.RCC.constrain();
         let clocks = setup_clocks(rcc);
-        let mono = ctx.device.TIM2.monotonic_us(&clocks);
+        let mono = ctx.device.TIM2.monotonic64_us(&clocks);
+        post.set_core_clock(clocks.sysclk().raw());
 
-        let watchdog = setup_watchdog(ctx.device.IWDG);
+        // the ADC is handed over to the controls & the battery after the session ID is derived
+        let mut adc = Adc::adc1(ctx.device.ADC1, true, AdcConfig::default());
+        let session = SessionId::from_noise(&sample_adc_noise(&mut adc));
+        session.set();
+        defmt::info!("session: {}", session);
+
+        next_post_stage(&mut post, &mut clock, Some(Stage::Watchdog));
+        let mut watchdog = setup_watchdog(ctx.device.IWDG);
 
         // set up I2C
+        next_post_stage(&mut post, &mut clock, Some(Stage::I2c));
+        let speed_hz = crate::config::i2c::SPEED_KHZ * 1_000;
+        let i2c_speed = match i2c_timing::timing(clocks.pclk1().raw(), speed_hz) {
+            Ok(timing) => {
+                defmt::info!("I2C timing: {}", timing);
+                speed_hz.Hz()
+            }
+            Err(e) => {
+                log_error!(
+                    "I2C speed of {} Hz not supported: {}, using 100 kHz",
+                    speed_hz,
+                    DebugFormat(&e)
+                );
+                blink_code::record(ErrorClass::Config);
+                post.fail();
+                i2c_timing::STANDARD_MAX_HZ.Hz()
+            }
+        };
+        let gpioa = ctx.device.GPIOA.split();
         let gpiob = ctx.device.GPIOB.split();
-        let i2c = I2c::new(ctx.device.I2C1, (gpiob.pb8, gpiob.pb9), 400.kHz(), &clocks);
+        let gpioc = ctx.device.GPIOC.split();
+        // `XSHUT` is high, the sensor is only shut down by the `shutdown` command
+        let mut pins = board_pins!(gpioa, gpiob, gpioc);
+        let mut i2c = GuardedI2c::new(ctx.device.I2C1, pins.i2c, i2c_speed, &clocks);
+        i2c.use_rx_dma(StreamsTuple::new(ctx.device.DMA1).0);
+        let i2c_bus = shared_bus::new_atomic_check!(GuardedI2c = i2c).expect("");
+        // the multiplexer isn't reset with the microcontroller, a channel may still be connected
+        #[cfg(feature = "tof-mux")]
+        let tof_mux = {
+            let mut tof_mux = crate::tof_mux::Tca9548a::new(
+                i2c_bus.acquire_i2c(),
+                crate::config::tof_mux::ADDRESS,
+            );
+            if let Err(e) = tof_mux.disable() {
+                log_error!("failed to reset the I2C mux: {}", DebugFormat(&e));
+            }
+            tof_mux
+        };
+        let mut i2c_scanner = i2c_bus.acquire_i2c();
+        i2c_scan::log(&i2c_scan::scan(&mut i2c_scanner));
 
-        let gpioa = ctx.device.GPIOA.split();
-        let mut tof_data_interrupt = gpioa.pa0.into_pull_down_input();
+        let mut tof_data_interrupt = pins.tof_interrupt;
         tof_data_interrupt.make_interrupt_source(&mut syscfg);
         tof_data_interrupt.enable_interrupt(&mut ctx.device.EXTI);
-        tof_data_interrupt.trigger_on_edge(&mut ctx.device.EXTI, Edge::Falling);
+        tof_data_interrupt.trigger_on_edge(&mut ctx.device.EXTI, crate::board::TOF_INTERRUPT_EDGE);
+        let tof_shutdown = pins.tof_shutdown;
+        let user_button = UserButton::new(pins.button, &mut syscfg, &mut ctx.device.EXTI);
+        #[cfg(feature = "trigger-input")]
+        let trigger_pin = crate::trigger::TriggerPin::new(
+            gpioc.pc10.into_pull_down_input(),
+            &mut syscfg,
+            &mut ctx.device.EXTI,
+        );
+        #[cfg(not(feature = "trigger-input"))]
+        let trigger_pin = ();
+        #[cfg(feature = "marker-input")]
+        let marker_input = crate::marker::MarkerInput::new(
+            gpioc.pc12.into_pull_down_input(),
+            &mut syscfg,
+            &mut ctx.device.EXTI,
+        );
+        #[cfg(not(feature = "marker-input"))]
+        let marker_input = ();
+        #[cfg(feature = "gpio-expander")]
+        let gpio_expander = crate::gpio_expander::GpioExpander::new(
+            i2c_bus.acquire_i2c(),
+            gpiob.pb11.into_pull_up_input(),
+            &mut syscfg,
+            &mut ctx.device.EXTI,
+        );
+        #[cfg(not(feature = "gpio-expander"))]
+        let gpio_expander = ();
+
+        #[cfg(feature = "dip-switch")]
+        let boot_config = BootConfig::read(
+            gpioc.pc4.into_pull_up_input(),
+            gpioc.pc5.into_pull_up_input(),
+            gpiob.pb10.into_pull_up_input(),
+            gpioa.pa15.into_pull_up_input(),
+            (
+                gpioc.pc0.into_pull_up_input(),
+                gpioc.pc1.into_pull_up_input(),
+                gpioc.pc2.into_pull_up_input(),
+            ),
+        );
+        #[cfg(feature = "gpio-expander")]
+        let boot_config = gpio_expander
+            .as_ref()
+            .map_or_else(BootConfig::default, |expander| {
+                BootConfig::from_switches(expander.switches())
+            });
+        #[cfg(not(any(feature = "dip-switch", feature = "gpio-expander")))]
+        let boot_config = BootConfig::default();
+        defmt::info!("boot configuration: {}", boot_config);
+
+        next_post_stage(&mut post, &mut clock, Some(Stage::Storage));
+        let mut flash = ctx.device.FLASH;
+        match brown_out::set_bor_level(&mut flash) {
+            Ok(true) => defmt::info!(
+                "brown-out reset level set to {}",
+                crate::config::brown_out::BOR_LEVEL
+            ),
+            Ok(false) => {}
+            Err(e) => log_error!(
+                "failed to set the brown-out reset level: {}",
+                DebugFormat(&e)
+            ),
+        }
+        let mut crc = Crc32::new(ctx.device.CRC);
+        let image = ImageState::check(&flash, &mut crc);
+        match image {
+            ImageState::Valid => defmt::info!("firmware image: CRC ok"),
+            ImageState::Unsigned => log_warn!("firmware image: no CRC appended, not checked"),
+            ImageState::Corrupt => log_error!("firmware image: CRC mismatch"),
+        }
+        let mut eeprom = Eeprom::open(&mut flash, &mut watchdog)
+            .map_err(|e| {
+                log_error!("failed to open the EEPROM: {}", DebugFormat(&e));
+                blink_code::record(ErrorClass::Storage);
+            })
+            .ok();
+        // resets if slot B has to boot
+        #[cfg(feature = "firmware-update")]
+        if let Some(eeprom) = &mut eeprom {
+            let trial =
+                crate::firmware_update::select_slot(eeprom, &mut flash, &mut watchdog, &mut clock);
+            if trial {
+                confirm_firmware::spawn_after(
+                    u64::from(crate::config::firmware_update::CONFIRM_TIMEOUT_S).secs(),
+                )
+                .or_count("confirm_firmware");
+            }
+        }
+        match &mut eeprom {
+            Some(eeprom) => {
+                let uptime_s = clock.previous_uptime_s();
+                match reset_log::log_boot(eeprom, &mut flash, reset_cause, uptime_s, &mut watchdog)
+                {
+                    Ok(boot) => defmt::info!("boot {} after reset: {}", boot, reset_cause),
+                    Err(e) => {
+                        log_error!("failed to log the reset: {}", DebugFormat(&e));
+                        blink_code::record(ErrorClass::Storage);
+                    }
+                }
+                for record in reset_log::records(eeprom, &flash) {
+                    defmt::info!("reset log: {}", record);
+                }
+            }
+            None => defmt::info!("reset cause: {}", reset_cause),
+        }
+        let boot = eeprom
+            .as_ref()
+            .map_or(0, |eeprom| reset_log::boot_count(eeprom, &flash));
+        let event_log = EventLog::open(eeprom.as_ref(), &flash, boot);
+        if let Some(eeprom) = &mut eeprom {
+            match Settings::migrate(eeprom, &mut flash, &mut watchdog) {
+                Ok(SchemaState::Current) => {}
+                Ok(SchemaState::Outdated(version)) => defmt::info!(
+                    "settings: migrated from schema {} to {}",
+                    version,
+                    crate::settings::SCHEMA_VERSION
+                ),
+                Ok(SchemaState::Newer(version)) => log_warn!(
+                    "settings: stored with the newer schema {}, using the defaults",
+                    version
+                ),
+                Ok(SchemaState::Corrupt) => log_warn!("settings: CRC mismatch, using the defaults"),
+                Err(e) => log_error!("failed to migrate the settings: {}", DebugFormat(&e)),
+            }
+        }
+        let mut settings = eeprom
+            .as_ref()
+            .map(|eeprom| Settings::load(eeprom, &flash))
+            .unwrap_or_default();
+        defmt::info!("stored settings: {}", defmt::Debug2Format(&settings));
+        let mut profile = eeprom
+            .as_ref()
+            .and_then(|eeprom| Profile::active(eeprom, &flash));
+        let mut led_indication = None;
+        let mut button_mode = None;
+        if user_button.is_pressed() {
+            let held_ms = measure_button_hold(
+                &user_button,
+                &mut pins.led,
+                &mut watchdog,
+                clocks.sysclk().raw(),
+            );
+            button_mode = AppMode::selected_by_hold(held_ms);
+            if let Some(mode) = button_mode {
+                defmt::info!(
+                    "user button held at boot for {} ms, selecting the application mode {}",
+                    held_ms,
+                    mode
+                );
+                if let Some(eeprom) = &mut eeprom {
+                    if let Err(e) = mode.store(eeprom, &mut flash, &mut watchdog) {
+                        log_error!("failed to store the application mode: {}", DebugFormat(&e));
+                    }
+                }
+                led_indication = Some(mode.number());
+            } else {
+                let next = profile.map_or(Profile::Indoor, |profile| profile.next());
+                defmt::info!("user button held at boot, selecting the next profile");
+                if let Some(eeprom) = &mut eeprom {
+                    next.activate(eeprom, &mut flash, &mut watchdog).ok();
+                }
+                profile = Some(next);
+                led_indication = Some(next.number());
+            }
+        }
+        // the settings of the active profile take precedence over the ones stored with `save`
+        if let Some(profile) = profile {
+            defmt::info!("profile: {}", profile);
+            settings.tof = Some(profile.settings(eeprom.as_ref(), &flash));
+        }
+        // and the preset selected with the DIP switch takes precedence over the profile
+        if let Some(preset) = boot_config.preset {
+            defmt::info!("preset: {}", preset);
+            settings.tof = Some(preset.settings());
+        } else if let (Some(Profile::Fast), Some(tof_settings)) = (profile, &settings.tof) {
+            check_fast_profile(tof_settings);
+        }
+        // the DIP switch (or the switches on the GPIO expander) takes precedence over the stored
+        // application mode & frame format, the mode selected with the user button over both
+        #[cfg(feature = "gpio-expander")]
+        let switches = gpio_expander.is_some();
+        #[cfg(not(feature = "gpio-expander"))]
+        let switches = cfg!(feature = "dip-switch");
+        let stored_mode = match &eeprom {
+            Some(eeprom) if !switches => AppMode::load(eeprom, &flash),
+            _ => None,
+        };
+        let (app_mode, selection) = match (button_mode, stored_mode) {
+            (Some(mode), _) => (mode, Selection::Button),
+            (None, _) if switches => (boot_config.app_mode, Selection::Switches),
+            (None, Some(mode)) => (mode, Selection::Stored),
+            (None, None) => (boot_config.app_mode, Selection::Default),
+        };
+        let frame_format = match &eeprom {
+            Some(_) if !switches => settings.frame_format.unwrap_or(boot_config.frame_format),
+            _ => boot_config.frame_format,
+        };
+        defmt::info!(
+            "application mode: {} ({}), frame format: {}",
+            app_mode,
+            selection,
+            frame_format
+        );
+        if app_mode == AppMode::LowPower {
+            low_power_mode::spawn(true).or_count("low_power_mode");
+        }
+        let publish_interval_ms = settings
+            .publish_interval_ms
+            .filter(|&interval_ms| interval_ms >= publish_interval::MIN_INTERVAL_MS);
+        if publish_interval_ms.is_some() {
+            publish_average::spawn(None).or_count("publish_average");
+        }
+        if eeprom.is_none() || image == ImageState::Corrupt {
+            post.fail();
+        }
 
         // set up the TOF sensor
-        let tof_sensor = setup_tof(i2c);
+        next_post_stage(&mut post, &mut clock, Some(Stage::Sensor));
+        #[cfg(not(feature = "sim"))]
+        let sensor_bus = || i2c_bus.acquire_i2c();
+        #[cfg(feature = "sim")]
+        let sensor_bus = || crate::sim::SimBus;
+        #[cfg(not(feature = "i2c-trace"))]
+        let traced_bus = sensor_bus;
+        #[cfg(feature = "i2c-trace")]
+        let traced_bus = || crate::i2c_trace::TracedBus::new(sensor_bus(), clocks.sysclk().raw());
+        #[cfg(not(feature = "fault-injection"))]
+        let tof_bus = traced_bus;
+        #[cfg(feature = "fault-injection")]
+        let tof_bus = || crate::fault_injection::FaultyBus::new(traced_bus());
+        let address = vl53l1x_uld::DEFAULT_ADDRESS + boot_config.i2c_address_offset;
+        // the sensor keeps its address across a reset of the microcontroller, thus it might
+        // already use the new one
+        let (current_address, variant) =
+            match range_sensor::probe(&mut tof_bus(), vl53l1x_uld::DEFAULT_ADDRESS) {
+                Err(_) if address != vl53l1x_uld::DEFAULT_ADDRESS => {
+                    (address, range_sensor::probe(&mut tof_bus(), address))
+                }
+                variant => (vl53l1x_uld::DEFAULT_ADDRESS, variant),
+            };
+        match variant {
+            Ok(Some(variant)) => {
+                defmt::info!("TOF sensor: {} at {=u8:#04x}", variant, current_address)
+            }
+            Ok(None) => defmt::warn!("TOF sensor: unknown model at {=u8:#04x}", current_address),
+            Err(_) => {
+                log_warn!("TOF sensor: no answer to the probe");
+                blink_code::record(ErrorClass::I2c);
+                post.fail();
+            }
+        }
+        // an unknown or silent sensor is still set up as a VL53L1X, which retries & reports it
+        let unsupported = matches!(variant, Ok(Some(variant)) if !variant.is_supported());
+        let mut tof_sensor = Backend::new(variant.ok().flatten(), tof_bus(), current_address);
+        if current_address != address && !unsupported && tof_sensor.set_address(address).is_err() {
+            log_error!("failed to set the address of the TOF sensor");
+            blink_code::record(ErrorClass::SensorInit);
+            post.fail();
+        }
+        let calibration = eeprom
+            .as_ref()
+            .and_then(|eeprom| calibration_store::load(eeprom, &flash));
+        if calibration.is_none() {
+            defmt::info!("no stored calibration, using the defaults of the sensor");
+        }
+        // the sensor isn't operated at all by a corrupt image
+        let safe_mode = image == ImageState::Corrupt
+            || unsupported
+            || tof_sensor.setup(calibration, settings.tof).is_err();
+        if safe_mode {
+            log_error!(
+                "{}, entering safe mode",
+                if image == ImageState::Corrupt {
+                    "the firmware image is corrupt"
+                } else if unsupported {
+                    "there's no backend for the TOF sensor"
+                } else {
+                    "failed to set up the TOF sensor"
+                }
+            );
+            if image != ImageState::Corrupt {
+                blink_code::record(ErrorClass::SensorInit);
+            }
+            post.fail();
+        } else {
+            if let Some(preset) = boot_config.preset {
+                let result = tof_sensor.reconfigure(true, |tof_sensor| {
+                    tof_sensor.set_thresholds(&preset.thresholds())
+                });
+                if result.is_err() {
+                    log_error!("failed to apply the thresholds of the preset");
+                    blink_code::record(ErrorClass::Config);
+                }
+            }
+            log_optics(&mut tof_sensor);
+        }
+
+        next_post_stage(&mut post, &mut clock, Some(Stage::Peripherals));
+        #[cfg(feature = "second-tof")]
+        let second_tof = crate::second_tof::SecondTof::new(
+            GuardedI2c::new(ctx.device.I2C3, (gpioa.pa8, gpioc.pc9), i2c_speed, &clocks),
+            ctx.device.GPIOD.split().pd2.into_pull_down_input(),
+            settings.tof,
+            &mut syscfg,
+            &mut ctx.device.EXTI,
+        );
+        #[cfg(not(feature = "second-tof"))]
+        let second_tof = ();
+        #[cfg(feature = "tof-mux")]
+        let tof_array =
+            crate::tof_mux::TofArray::new(tof_mux, || i2c_bus.acquire_i2c(), settings.tof);
+        #[cfg(not(feature = "tof-mux"))]
+        let tof_array = ();
+
+        let status_led = StatusLed::new(pins.led);
+
+        // set up the inputs
+        let inputs = Inputs {
+            #[cfg(feature = "encoder")]
+            // PB4 is used by JTAG after reset and must be reconfigured first, the pull-ups (which
+            // remain enabled in the alternate mode) allow open-collector encoders
+            encoder: crate::encoder::Encoder::new(ctx.device.TIM3.qei((
+                gpiob.pb4.into_pull_up_input(),
+                gpiob.pb5.into_pull_up_input(),
+            ))),
+            #[cfg(feature = "imu")]
+            imu: crate::imu::Imu::new(i2c_bus.acquire_i2c()),
+            #[cfg(feature = "environment")]
+            environment: crate::environment::EnvironmentSensor::new(i2c_bus.acquire_i2c()),
+            #[cfg(feature = "ultrasonic")]
+            ultrasonic: crate::ultrasonic::Ultrasonic::new(
+                ctx.device.TIM4,
+                gpiob.pb6,
+                gpioc.pc8.into_push_pull_output(),
+                &clocks,
+            ),
+        };
+
+        // set up the outputs
+        let mut outputs = Outputs {
+            #[cfg(feature = "proximity-led")]
+            proximity_led: crate::proximity_led::ProximityLed::new(
+                ctx.device
+                    .TIM3
+                    .pwm_hz(Channel1::new(gpioa.pa6), 1.kHz(), &clocks)
+                    .split(),
+            ),
+            #[cfg(feature = "buzzer")]
+            buzzer: crate::buzzer::Buzzer::new(ctx.device.TIM4.pwm_hz(
+                Channel1::new(gpiob.pb6),
+                2.kHz(),
+                &clocks,
+            )),
+            #[cfg(feature = "alarm-output")]
+            alarm: crate::alarm_output::AlarmOutput::new(gpiob.pb0.into_dynamic()),
+            #[cfg(feature = "servo")]
+            servo: crate::servo::Servo::new(
+                ctx.device
+                    .TIM1
+                    .pwm_us(
+                        Channel1::new(gpioa.pa8),
+                        crate::servo::PERIOD_US.micros(),
+                        &clocks,
+                    )
+                    .split(),
+            ),
+            #[cfg(feature = "motor-pid")]
+            motor: crate::motor::Motor::new(
+                ctx.device
+                    .TIM5
+                    .pwm_hz(Channel2::new(gpioa.pa1), 20.kHz(), &clocks)
+                    .split(),
+                gpioa.pa4.into_push_pull_output(),
+            ),
+            #[cfg(feature = "analog-output")]
+            analog: crate::analog_output::AnalogOutput::new(
+                ctx.device
+                    .TIM5
+                    .pwm_hz(
+                        Channel2::new(gpioa.pa1),
+                        crate::config::analog_output::FREQUENCY_HZ.Hz(),
+                        &clocks,
+                    )
+                    .split(),
+            ),
+            #[cfg(feature = "stepper")]
+            stepper: crate::stepper::Stepper::new(
+                [
+                    gpioc.pc0.into_push_pull_output().erase(),
+                    gpioc.pc1.into_push_pull_output().erase(),
+                    gpioc.pc2.into_push_pull_output().erase(),
+                    gpioc.pc3.into_push_pull_output().erase(),
+                ],
+                ctx.device.TIM9.counter_us(&clocks),
+            ),
+            #[cfg(feature = "zone-output")]
+            zone_output: gpioc.pc3.into_push_pull_output(),
+            #[cfg(feature = "pwm-expander")]
+            indicators: crate::pwm_expander::Indicators::new(i2c_bus.acquire_i2c()),
+        };
+        if Outputs::NEEDS_TICK {
+            tick_outputs::spawn().or_count("tick_outputs");
+        }
+        update_status_led::spawn().or_count("update_status_led");
+        #[cfg(feature = "sim")]
+        simulate_measurement::spawn().or_count("simulate_measurement");
+        #[cfg(feature = "demo")]
+        demo_step::spawn().or_count("demo_step");
+        #[cfg(feature = "tof-mux")]
+        poll_tof_array::spawn().or_count("poll_tof_array");
+
+        // set up the health report
+        let health_monitor = HealthMonitor {
+            #[cfg(feature = "power")]
+            power: crate::power::PowerMonitor::new(i2c_bus.acquire_i2c()),
+        };
+        report_health::spawn().or_count("report_health");
+        send_session_header::spawn().or_count("send_session_header");
+        // the rollup in progress before the reset continues, it's reported once its interval is over
+        let rollup = clock
+            .previous_rollup()
+            .map_or(RollupAccumulator::new(0), |registers| {
+                RollupAccumulator::restore(registers, 0)
+            });
+        let rollup_elapsed_s = rollup.elapsed_ms(0) / 1000;
+        report_rollup::spawn_after(
+            u64::from(crate::config::rollup::INTERVAL_S.saturating_sub(rollup_elapsed_s)).secs(),
+        )
+        .or_count("report_rollup");
+        log_summary::spawn_after(u64::from(crate::config::summary::INTERVAL_S).secs())
+            .or_count("log_summary");
+        check_drift::spawn_after(u64::from(crate::config::drift::INTERVAL_S).secs())
+            .or_count("check_drift");
+        poll_control_block::spawn().or_count("poll_control_block");
+        send_keepalive::spawn_after(u64::from(change_filter::CHECK_INTERVAL_MS).millis())
+            .or_count("send_keepalive");
+        let sensor_supervisor = SensorSupervisor::new(clock.sensor_resets());
+        supervise_sensor::spawn_after(u64::from(sensor_supervisor::CHECK_INTERVAL_MS).millis())
+            .or_count("supervise_sensor");
+
+        // set up the controls
+        let controls = Controls {
+            #[cfg(feature = "threshold-pot")]
+            threshold_pot: crate::threshold_pot::ThresholdPot::new(adc, gpiob.pb1.into_analog()),
+            #[cfg(feature = "menu")]
+            rotary_encoder: crate::rotary::RotaryEncoder::new(
+                gpioc.pc10.into_pull_up_input(),
+                gpioc.pc11.into_pull_up_input(),
+                gpioc.pc12.into_pull_up_input(),
+            ),
+            #[cfg(feature = "menu")]
+            menu: crate::menu::Menu::new(&settings),
+        };
+        if Controls::ENABLED {
+            poll_controls::spawn().or_count("poll_controls");
+        }
+        #[cfg(feature = "battery")]
+        let battery = crate::battery::Battery::new(adc, gpioa.pa4.into_analog());
+        #[cfg(not(feature = "battery"))]
+        let battery = ();
+        #[cfg(feature = "battery")]
+        monitor_battery::spawn().or_count("monitor_battery");
+
+        // set up the displays
+        let displays = Displays {
+            #[cfg(feature = "display-ssd1306")]
+            oled: crate::oled::Oled::new(i2c_bus.acquire_i2c()),
+            #[cfg(feature = "display-hd44780")]
+            lcd: crate::lcd::Lcd::new(
+                i2c_bus.acquire_i2c(),
+                cortex_m::delay::Delay::new(ctx.core.SYST, clocks.hclk().raw()),
+            ),
+            #[cfg(feature = "led-strip")]
+            led_strip: crate::led_strip::LedStrip::new(ctx.device.SPI1, gpioa.pa7, &clocks),
+            shown: None,
+        };
+
+        // set up the external NOR flash
+        #[cfg(feature = "nor-flash")]
+        let nor_flash = {
+            // PB3 & PB4 are used by JTAG after reset and must be reconfigured first
+            let spi = Spi::new(
+                ctx.device.SPI3,
+                (
+                    gpiob.pb3.into_alternate(),
+                    gpiob.pb4.into_alternate(),
+                    gpiob.pb5,
+                ),
+                hal::spi::MODE_0,
+                8.MHz(),
+                &clocks,
+            );
+            crate::w25q::W25q::new(
+                spi,
+                gpiob.pb7.into_push_pull_output(),
+                clocks.sysclk().raw(),
+            )
+            .map_err(|e| {
+                log_error!("failed to set up the NOR flash: {}", DebugFormat(&e));
+                blink_code::record(ErrorClass::Storage);
+                post.fail();
+            })
+            .ok()
+        };
+
+        // set up the data loggers
+        let data_log = DataLog {
+            #[cfg(feature = "sd-card")]
+            // PB3 & PB4 are used by JTAG after reset and must be reconfigured first
+            sd_card: crate::sd_card::SdCardLog::new(
+                Spi::new(
+                    ctx.device.SPI3,
+                    (
+                        gpiob.pb3.into_alternate(),
+                        gpiob.pb4.into_alternate(),
+                        gpiob.pb5,
+                    ),
+                    hal::spi::MODE_0,
+                    400.kHz(),
+                    &clocks,
+                ),
+                gpiob.pb7.into_push_pull_output(),
+                clocks.sysclk().raw(),
+            ),
+            #[cfg(all(feature = "nor-flash", not(feature = "littlefs")))]
+            flash_log: nor_flash.and_then(|flash| {
+                crate::flash_log::FlashLog::new(flash)
+                    .map_err(|e| {
+                        log_error!("failed to set up the flash log: {}", DebugFormat(&e));
+                        blink_code::record(ErrorClass::Storage);
+                        post.fail();
+                    })
+                    .ok()
+            }),
+            #[cfg(feature = "littlefs")]
+            file_system: nor_flash.and_then(|flash| {
+                FlashStorage::new(flash)
+                    .and_then(|storage| {
+                        FileSystem::mount(
+                            ctx.local.fs_storage.insert(storage),
+                            ctx.local.fs_alloc.insert(littlefs2::fs::Allocation::new()),
+                        )
+                    })
+                    .map_err(|e| {
+                        log_error!("failed to set up the file system: {}", DebugFormat(&e));
+                        blink_code::record(ErrorClass::Storage);
+                        post.fail();
+                    })
+                    .ok()
+            }),
+            #[cfg(feature = "xmodem")]
+            transfer: None,
+            #[cfg(feature = "nor-flash")]
+            compressor: None,
+        };
+
+        // set up the virtual COM port
+        next_post_stage(&mut post, &mut clock, Some(Stage::Telemetry));
+        let vcp =
+            Serial::new(ctx.device.USART2, pins.vcp, crate::uart::config(), &clocks).expect("");
+        #[cfg(not(feature = "mqtt-sn"))]
+        let vcp = crate::uart::UartLink::new(vcp);
+        #[cfg(feature = "mqtt-sn")]
+        let vcp = crate::mqtt_sn::MqttSnLink::new(crate::uart::BufferedUart::new(vcp));
+
+        // set up the Bluetooth module
+        #[cfg(feature = "bluetooth")]
+        let bluetooth = {
+            let serial = crate::bluetooth::setup(
+                ctx.device.USART6,
+                (gpioc.pc6, gpioc.pc7),
+                gpioc.pc8.into_push_pull_output(),
+                &clocks,
+            );
+            crate::uart::UartLink::new(serial)
+        };
+
+        // set up the WiFi module
+        #[cfg(feature = "wifi")]
+        let wifi = {
+            let serial = Serial::new(
+                ctx.device.USART1,
+                (gpioa.pa9, gpioa.pa10),
+                crate::uart::config(),
+                &clocks,
+            )
+            .expect("");
+            crate::wifi::WifiUplink::new(crate::uart::BufferedUart::new(serial))
+        };
+
+        // set up the UART of the loopback self-test
+        #[cfg(feature = "uart-loopback")]
+        let loopback = {
+            let serial = Serial::new(
+                ctx.device.USART1,
+                (gpioa.pa9, gpioa.pa10),
+                crate::uart::config(),
+                &clocks,
+            )
+            .expect("");
+            crate::loopback::UartLoopback::new(crate::uart::BufferedUart::new(serial))
+        };
+        #[cfg(not(feature = "uart-loopback"))]
+        let loopback = ();
+
+        // set up the UART of the binary sink
+        #[cfg(feature = "uart-binary")]
+        let binary = {
+            let serial = Serial::new(
+                ctx.device.USART1,
+                (gpioa.pa9, gpioa.pa10),
+                crate::uart::config(),
+                &clocks,
+            )
+            .expect("");
+            crate::sink::BinaryUart::new(crate::uart::BufferedUart::new(serial))
+        };
+
+        // set up the LoRa radio
+        #[cfg(feature = "lora")]
+        let lora = {
+            let spi = Spi::new(
+                ctx.device.SPI2,
+                (gpiob.pb13, gpiob.pb14, gpiob.pb15),
+                hal::spi::MODE_0,
+                1.MHz(),
+                &clocks,
+            );
+            crate::lora::LoraUplink::new(
+                spi,
+                gpiob.pb12.into_push_pull_output(),
+                gpioc.pc9.into_push_pull_output(),
+                clocks.sysclk().raw(),
+            )
+            .map_err(|e| {
+                log_error!("failed to set up the LoRa radio: {}", DebugFormat(&e));
+                blink_code::record(ErrorClass::Radio);
+                post.fail();
+            })
+            .ok()
+        };
 
+        // set up USB
+        #[cfg(feature = "usb")]
+        let usb = {
+            let usb = USB::new(
+                (
+                    ctx.device.OTG_FS_GLOBAL,
+                    ctx.device.OTG_FS_DEVICE,
+                    ctx.device.OTG_FS_PWRCLK,
+                ),
+                (gpioa.pa11, gpioa.pa12),
+                &clocks,
+            );
+            let usb_bus = ctx
+                .local
+                .usb_bus
+                .insert(UsbBus::new(usb, ctx.local.usb_ep_memory));
+            defmt::trace!("USB set up");
+            crate::usb::UsbLink::new(usb_bus)
+        };
+
+        next_post_stage(&mut post, &mut clock, None);
         defmt::info!("init done!");
 
+        #[cfg_attr(
+            not(any(feature = "alarm-output", feature = "lora")),
+            allow(unused_mut)
+        )]
+        let mut links = Links {
+            vcp,
+            #[cfg(feature = "usb")]
+            usb,
+            #[cfg(feature = "bluetooth")]
+            bluetooth,
+            #[cfg(feature = "wifi")]
+            wifi,
+            #[cfg(feature = "lora")]
+            lora,
+            #[cfg(feature = "defmt-sink")]
+            defmt: crate::sink::DefmtSink,
+            #[cfg(feature = "uart-binary")]
+            binary,
+        };
+        apply_settings(&settings, &mut outputs, &mut links);
+
+        #[cfg(feature = "firmware-update")]
+        let firmware_update = FirmwareUpdate::new(crc);
+        #[cfg(not(feature = "firmware-update"))]
+        let firmware_update = ();
+        #[cfg(feature = "fault-injection")]
+        let interrupt_faults = crate::fault_injection::InterruptFaults::new();
+        #[cfg(not(feature = "fault-injection"))]
+        let interrupt_faults = ();
+
         (
-            Shared {},
-            Local {
+            Shared {
                 watchdog,
                 tof_sensor,
+                ranging: !safe_mode,
+                acquisition: Acquisition::Interrupt,
+                // the sequence numbers continue after a reset
+                measurement_count: clock.previous_sequence().unwrap_or(0),
+                rejected_count: 0,
+                range_gate: RangeGate::new(),
+                schedule: settings.schedule.unwrap_or(Schedule::ALWAYS),
+                drift_monitor: DriftMonitor::new(
+                    Some(crate::config::drift::REFERENCE_MM)
+                        .filter(|&reference_mm| reference_mm > 0),
+                ),
+                latest_measurement: None,
+                last_read_ms: None,
+                measurements: MeasurementQueue::new(crate::config::measurement_queue::POLICY),
+                sensor_error: false,
+                safe_mode,
+                links,
+                outputs,
+                app_mode,
+                frame_format,
+                scaling: settings.scaling.unwrap_or(Scaling::IDENTITY),
+                publish_interval_ms,
+                averager: Averager::new(),
+                change_filter: ChangeFilter::new(
+                    settings.change_delta_mm.filter(|&delta_mm| delta_mm > 0),
+                ),
+                geofence: Geofence::new(settings.zones.unwrap_or(geofence::DEFAULT_ZONES)),
+                low_power: LowPower::new(),
+                user_button,
+                trigger: Trigger::default(),
+                interleaver: Interleaver::new(2),
+                second_tof_start: false,
+                calibration: None,
+                cal_target_mm: settings
+                    .cal_target_mm
+                    .unwrap_or(crate::config::calibration::TARGET_MM),
+                cal_wizard: None,
+                flash,
+                eeprom,
+                clock,
+                led_indication,
+                profile,
+                preset: boot_config.preset,
+                menu_view: None,
+                narration: None,
+                #[cfg(feature = "demo")]
+                demo: Demo::new(),
+                #[cfg(not(feature = "demo"))]
+                demo: (),
+                log_requests: LogRequests::new(),
+                rollup,
+                sample_history: SampleHistory::new(),
+                extremes: Extremes::new(),
+                capture: Capture::new(),
+                batcher: Batcher::new(crate::config::batch::SIZE as usize),
+                warm_up: WarmUp::new(),
+                pause: Pause::new(),
+                lens: LensMonitor::new(),
+                sensor_supervisor,
+                loopback,
+                interrupt_guard: InterruptGuard::new(),
+                jitter: JitterStats::new(),
+                cpu_load: None,
+                watchdog_margin: WatchdogMargin::new(
+                    crate::config::watchdog::TIMEOUT_MS,
+                    crate::config::watchdog::MIN_MARGIN_MS,
+                ),
+                task_watchdog: TaskWatchdog::new(),
+                gpio_expander,
+            },
+            Local {
                 tof_data_interrupt,
+                exti: ctx.device.EXTI,
+                interrupt_faults,
+                cadence_timer: CadenceTimer::new(ctx.device.TIM11.counter_us(&clocks)),
+                isr_budget: IsrBudget::new(
+                    crate::config::isr_budget::TOF_INTERRUPT_US,
+                    clocks.sysclk().raw(),
+                ),
+                supply_monitor,
+                wrap_check: WrapCheck::new(),
+                second_tof,
+                tof_array,
+                trigger_pin,
+                marker_input,
+                tof_shutdown,
+                firmware_update,
+                inputs,
+                displays,
+                data_log,
+                status_led,
+                health_monitor,
+                controls,
+                presence: PresenceDetector::new(),
+                tracker: Tracker::new(),
+                image,
+                ambient: AmbientMonitor::new(),
+                mode_before_ambient: None,
+                battery,
+                period_before_low: None,
+                event_log,
+                reset_token: None,
+                rdp_token: None,
+                i2c_scanner,
             },
             init::Monotonics(mono),
         )
     }
 
+    /// Sleep (or stop while the [`crate::low_power`] mode waits for a target) until the next
+    /// interrupt & measure the CPU load, see [`crate::cpu_load`].
+    #[idle(local = [meter: CpuLoad = CpuLoad::new()], shared = [cpu_load, low_power])]
+    fn idle(mut ctx: idle::Context) -> ! {
+        loop {
+            // the interrupt which wakes the microcontroller up is only handled after the end of the
+            // sleep has been taken (and the clocks are running again)
+            let (start_us, end_us) = cortex_m::interrupt::free(|_| {
+                let start_us = monotonics::now().ticks() as u32;
+                if ctx.shared.low_power.lock(|low_power| low_power.may_stop()) {
+                    low_power::stop();
+                } else {
+                    cortex_m::asm::wfi();
+                }
+                (start_us, monotonics::now().ticks() as u32)
+            });
+            if let Some(load) = ctx.local.meter.on_sleep(start_us, end_us) {
+                ctx.shared.cpu_load.lock(|cpu_load| *cpu_load = Some(load));
+            }
+        }
+    }
+
+    /// The uptime in ms of the timestamps. The monotonic timer counts 64 bit µs & thus doesn't
+    /// wrap, the timestamps wrap after 49 days (which they're compared for with `wrapping_sub`).
+    fn now_ms() -> u32 {
+        monotonics::now().duration_since_epoch().to_millis() as u32
+    }
+
+    /// The uptime in s, which unlike [`now_ms`] doesn't wrap (before 136 years).
+    fn uptime_s() -> u32 {
+        monotonics::now().duration_since_epoch().to_secs() as u32
+    }
+
+    /// Report the current stage of the POST & start the `next` one, see [`crate::post`]. The
+    /// result is logged after the last one (`None`).
+    fn next_post_stage(post: &mut Post, clock: &mut Clock, next: Option<Stage>) {
+        let report = post.next(next, DWT::cycle_count());
+        if report.failed {
+            log_error!(
+                "POST {}: failed after {} us",
+                report.stage.name(),
+                report.duration_us
+            );
+        } else {
+            defmt::info!(
+                "POST {=str}: ok in {} us",
+                report.stage.name(),
+                report.duration_us
+            );
+        }
+        clock.set_boot_stage(next.map_or(0, Stage::number));
+        match next {
+            Some(stage) => defmt::info!("POST {=str}", stage.name()),
+            None => log_info!(
+                "POST: done in {} ms, {} stages failed",
+                post.total_us() / 1000,
+                post.failures()
+            ),
+        }
+    }
+
     /// Set up the clocks of the microcontroller
+    #[cfg(not(feature = "usb"))]
     fn setup_clocks(rcc: Rcc) -> Clocks {
         rcc.cfgr.sysclk(84.MHz()).freeze()
     }
 
+    /// Set up the clocks of the microcontroller.
+    ///
+    /// USB needs a precise 48 MHz clock which can't be derived from the internal oscillator, thus
+    /// the 8 MHz clock provided by the ST-LINK of the Nucleo board is used.
+    #[cfg(feature = "usb")]
+    fn setup_clocks(rcc: Rcc) -> Clocks {
+        rcc.cfgr
+            .use_hse(8.MHz())
+            .bypass_hse_oscillator()
+            .sysclk(84.MHz())
+            .require_pll48clk()
+            .freeze()
+    }
+
+    /// Convert the internal channels of the ADC with the shortest sample time, for the noise in
+    /// the lower bits of the conversions.
+    fn sample_adc_noise(adc: &mut Adc<pac::ADC1>) -> [u16; session::NOISE_SAMPLES] {
+        adc.enable_temperature_and_vref();
+        let mut samples = [0; session::NOISE_SAMPLES];
+        for (i, sample) in samples.iter_mut().enumerate() {
+            *sample = if i % 2 == 0 {
+                adc.convert(&Temperature, SampleTime::Cycles_3)
+            } else {
+                adc.convert(&Vref, SampleTime::Cycles_3)
+            };
+        }
+        adc.disable_temperature_and_vref();
+        samples
+    }
+
+    /// Wait until the user button held at boot is released (at most until the last application
+    /// mode would be selected, see [`AppMode::selected_by_hold`]) & return for how long it has
+    /// been held. The user LED flashes at each [`app_mode::SELECTION_STEP_MS`].
+    fn measure_button_hold(
+        button: &UserButton,
+        led: &mut crate::board::Led,
+        watchdog: &mut IndependentWatchdog,
+        sysclk_hz: u32,
+    ) -> u32 {
+        let max_ms = AppMode::COUNT as u32 * app_mode::SELECTION_STEP_MS;
+        let mut held_ms = 0;
+        while button.is_pressed() && held_ms < max_ms {
+            cortex_m::asm::delay(sysclk_hz / 1_000);
+            watchdog.feed();
+            held_ms += 1;
+            match held_ms % app_mode::SELECTION_STEP_MS {
+                0 => led.set_high(),
+                100 => led.set_low(),
+                _ => {}
+            }
+        }
+        led.set_low();
+        held_ms
+    }
+
     /// Set up the independent watchdog and start the period task to feed it
     fn setup_watchdog(iwdg: IWDG) -> IndependentWatchdog {
         let mut watchdog = IndependentWatchdog::new(iwdg);
-        watchdog.start(1000u32.millis());
+        watchdog.start(crate::config::watchdog::TIMEOUT_MS.millis());
         watchdog.feed();
-        periodic::spawn().ok();
-        defmt::trace!("watchdog set up");
+        if !periodic::spawn().or_count("periodic") {
+            // nothing would feed the watchdog, reset right away like after a failed reschedule
+            log_error!("failed to start the watchdog feeding, resetting");
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+        defmt::info!(
+            "watchdog: timeout {} ms, fed every {} ms, warning below a margin of {} ms",
+            crate::config::watchdog::TIMEOUT_MS,
+            crate::config::watchdog::FEED_INTERVAL_MS,
+            crate::config::watchdog::MIN_MARGIN_MS
+        );
         watchdog
     }
 
-    /// Set up the TOF Sensor
-    fn setup_tof(i2c: I2c1) -> TOFSensor {
-        let mut dev = VL53L1X::new(i2c, vl53l1x_uld::DEFAULT_ADDRESS);
-        dev.init(IOVoltage::Volt2_8).expect("");
-        dev.set_interrupt_polarity(Polarity::ActiveHigh).expect("");
-        dev.start_ranging().expect("");
+    /// Log the optical configuration of the TOF sensor, after the setup & the offset calibration.
+    fn log_optics(tof_sensor: &mut TOFSensor) {
+        match tof_sensor.optics() {
+            Ok(optics) => defmt::info!("TOF optics: {}, centre at {}", optics, optics.center()),
+            Err(e) => log_warn!(
+                "failed to read the optics of the TOF sensor: {}",
+                DebugFormat(&e)
+            ),
+        }
+    }
+
+    /// Log whether the firmware keeps up with the settings of the fast profile, see
+    /// [`crate::fast_mode`].
+    fn check_fast_profile(settings: &TofSettings) {
+        let report = fast_mode::check(
+            settings,
+            crate::config::isr_budget::TOF_INTERRUPT_US,
+            crate::uart::BAUD_RATE,
+            // roughly, a batch leaves out the repeated parts of the frames
+            fast_mode::FRAME_LEN / crate::config::batch::SIZE,
+        );
+        if report.is_achievable() {
+            defmt::info!(
+                "fast profile: {} mHz, interrupt load {}%, UART load {}%",
+                report.rate_mhz,
+                report.isr_load_percent,
+                report.uart_load_percent
+            );
+        } else {
+            log_warn!(
+                "the fast profile doesn't sustain {} Hz: {} mHz, interrupt load {}%, UART load {}%",
+                crate::config::fast::RATE_HZ,
+                report.rate_mhz,
+                report.isr_load_percent,
+                report.uart_load_percent
+            );
+        }
+    }
+
+    /// The calibration & the measurement settings which are stored in the EEPROM & thus applied to
+    /// the TOF sensor at boot.
+    fn stored_tof_setup(
+        eeprom: Option<&Eeprom>,
+        flash: &pac::FLASH,
+    ) -> (Option<CalibrationData>, Option<TofSettings>) {
+        let Some(eeprom) = eeprom else {
+            return (None, None);
+        };
+        let settings = match Profile::active(eeprom, flash) {
+            Some(profile) => Some(profile.settings(Some(eeprom), flash)),
+            None => Settings::load(eeprom, flash).tof,
+        };
+        (calibration_store::load(eeprom, flash), settings)
+    }
 
-        dev
+    /// Apply the stored settings of the outputs & links, the other ones are applied where the
+    /// respective peripherals are set up.
+    fn apply_settings(settings: &Settings, outputs: &mut Outputs, links: &mut Links) {
+        #[cfg(any(feature = "alarm-output", feature = "lora"))]
+        if let Some(threshold_mm) = settings.alarm_threshold_mm {
+            outputs.set_alarm_threshold(threshold_mm);
+            links.set_alarm_threshold(threshold_mm);
+        }
+        #[cfg(feature = "buzzer")]
+        if let Some(muted) = settings.buzzer_muted {
+            outputs.buzzer.set_muted(muted);
+        }
+        #[cfg(feature = "motor-pid")]
+        if let Some(motor) = settings.motor {
+            outputs.motor.set_gains(motor.gains);
+            outputs.motor.set_target(motor.target_mm);
+            outputs.motor.set_enabled(motor.enabled);
+        }
+        #[cfg(not(any(feature = "alarm-output", feature = "lora")))]
+        let _ = links;
+        #[cfg(not(any(
+            feature = "alarm-output",
+            feature = "lora",
+            feature = "buzzer",
+            feature = "motor-pid"
+        )))]
+        let _ = (settings, outputs);
     }
 
     /// Triggers every time the TOF has data (= new range measurement) available to be consumed.
-    #[task(binds=EXTI0, local=[tof_sensor, tof_data_interrupt])]
+    /// It's also pended by [`poll_tof`] while polling, the interrupt line is ignored then.
+    /// The interrupts of the line pass the [`InterruptGuard`] first.
+    #[task(binds=EXTI0, local=[tof_data_interrupt, exti, interrupt_faults, wrap_check, inputs, isr_budget], shared=[tof_sensor, ranging, acquisition, measurement_count, rejected_count, range_gate, drift_monitor, latest_measurement, last_read_ms, measurements, sensor_error, calibration, cal_wizard, clock, interrupt_guard, jitter, low_power, trigger, interleaver, warm_up, pause, lens])]
     fn tof_interrupt_triggered(mut ctx: tof_interrupt_triggered::Context) {
+        let start = DWT::cycle_count();
+        handle_tof_interrupt(&mut ctx);
+        let cycles = DWT::cycle_count().wrapping_sub(start);
+        if let Some(overruns) = ctx.local.isr_budget.check(cycles) {
+            log_warn!(
+                "the data ready interrupt took {} cycles, over its budget of {} ({} times)",
+                cycles,
+                ctx.local.isr_budget.budget_cycles(),
+                overruns
+            );
+        }
+    }
+
+    /// The work of [`tof_interrupt_triggered`], which is measured against its budget. Only the
+    /// measurement is read here, the calibrations are finished & the drift is reported by
+    /// software tasks.
+    fn handle_tof_interrupt(ctx: &mut tof_interrupt_triggered::Context) {
+        let acquisition = ctx.shared.acquisition.lock(|acquisition| *acquisition);
+        let now_ms = now_ms();
+        if !admit_tof_interrupt(ctx, acquisition, now_ms) {
+            return;
+        }
+        ctx.shared
+            .interrupt_guard
+            .lock(|guard| guard.on_read(now_ms));
+        let result = ctx.shared.tof_sensor.lock(|tof_sensor| tof_sensor.read());
+        // a single measurement per pulse of the trigger input or the cadence
+        if acquisition.is_single_shot() && ctx.shared.trigger.lock(Trigger::on_measurement) {
+            ctx.shared
+                .tof_sensor
+                .lock(|tof_sensor| tof_sensor.stop())
+                .ok();
+            if acquisition.is_interleaved() {
+                ctx.shared
+                    .interleaver
+                    .lock(|interleaver| interleaver.finish(0));
+            }
+        }
+        if result.is_ok() {
+            let now_us = monotonics::now().ticks() as u32;
+            ctx.shared
+                .jitter
+                .lock(|jitter| jitter.on_measurement(now_us));
+            ctx.shared
+                .last_read_ms
+                .lock(|last_read_ms| *last_read_ms = Some(now_ms));
+        }
+
+        ctx.shared
+            .sensor_error
+            .lock(|sensor_error| *sensor_error = result.is_err());
+        if result.is_err() {
+            blink_code::record(ErrorClass::I2c);
+        }
+        if let Ok(reading) = result {
+            handle_reading(ctx, reading, now_ms);
+        }
+    }
+
+    /// Whether the interrupt is handled by reading a measurement: the interrupts of the line pass
+    /// the [`InterruptGuard`] & are checked for a measurement, the pended ones enable the line
+    /// again after a storm.
+    fn admit_tof_interrupt(
+        ctx: &mut tof_interrupt_triggered::Context,
+        acquisition: Acquisition,
+        now_ms: u32,
+    ) -> bool {
+        let from_line = ctx.local.tof_data_interrupt.check_interrupt();
         ctx.local.tof_data_interrupt.clear_interrupt_pending_bit();
+        if from_line {
+            if acquisition == Acquisition::Polled {
+                return false;
+            }
+            match ctx
+                .shared
+                .interrupt_guard
+                .lock(|guard| guard.on_interrupt(now_ms))
+            {
+                Verdict::Handle => {}
+                Verdict::Defer(delay_ms) => {
+                    // fails if a deferred interrupt is already pending, which reads the measurement
+                    delay_tof_interrupt::spawn_after(u64::from(delay_ms).millis()).ok();
+                    return false;
+                }
+                Verdict::Suppress => return false,
+                Verdict::Trip => {
+                    log_error!("interrupt storm on the data ready line, falling back to polling");
+                    ctx.local
+                        .tof_data_interrupt
+                        .disable_interrupt(ctx.local.exti);
+                    ctx.shared
+                        .acquisition
+                        .lock(|acquisition| *acquisition = Acquisition::Polled);
+                    poll_tof::spawn().or_count("poll_tof");
+                    return false;
+                }
+            }
+            // a failure to check is left to the read
+            let data_ready = ctx
+                .shared
+                .tof_sensor
+                .lock(|tof_sensor| tof_sensor.is_data_ready());
+            if matches!(data_ready, Ok(false)) {
+                if ctx
+                    .shared
+                    .interrupt_guard
+                    .lock(|guard| guard.on_duplicate())
+                {
+                    log_warn!(
+                        "over {}% of the data ready interrupts came without a measurement, check the wiring & the polarity of the line",
+                        interrupt_guard::SUSPICIOUS_DUPLICATE_PERCENT
+                    );
+                }
+                return false;
+            }
+        } else if acquisition == Acquisition::Interrupt
+            && ctx.shared.interrupt_guard.lock(|guard| guard.is_tripped())
+        {
+            // pended by the `acquisition` command after a storm
+            defmt::info!("enabling the data ready interrupt again");
+            ctx.local
+                .tof_data_interrupt
+                .enable_interrupt(ctx.local.exti);
+            ctx.shared.interrupt_guard.lock(|guard| guard.reset());
+            return false;
+        }
+        #[cfg(feature = "fault-injection")]
+        if let Some(delay_ms) = ctx.local.interrupt_faults.delay_ms() {
+            // fails if a deferred interrupt is already pending, which reads the measurement
+            delay_tof_interrupt::spawn_after(u64::from(delay_ms).millis()).ok();
+            return false;
+        }
+        #[cfg(not(feature = "fault-injection"))]
+        let _ = ctx.local.interrupt_faults;
+        true
+    }
+
+    /// Turn the reading into a measurement, feed the calibrations & the drift check with it & queue
+    /// it for [`publish`].
+    fn handle_reading(
+        ctx: &mut tof_interrupt_triggered::Context,
+        mut reading: Reading,
+        now_ms: u32,
+    ) {
+        defmt::info!("Received range: {}mm", reading.distance_mm);
+        // the calibration needs its target even if it's outside of the window
+        let calibrating = ctx
+            .shared
+            .calibration
+            .lock(|calibration| calibration.is_some())
+            || ctx.shared.cal_wizard.lock(|wizard| wizard.is_some());
+        if !accept_reading(ctx, &mut reading, calibrating) {
+            return;
+        }
+        add_to_cross_talk_calibration(ctx, &reading);
+        let measurement = new_measurement(ctx, &reading, now_ms);
+        if !calibrating {
+            let outcome = ctx
+                .shared
+                .drift_monitor
+                .lock(|monitor| monitor.add(&measurement));
+            if let Some(outcome) = outcome {
+                report_drift::spawn(outcome).or_count("report_drift");
+            }
+        }
+        if ctx.local.inputs.needs_tof_calibration(&measurement) {
+            defmt::info!("temperature has changed, recalibrating the TOF sensor");
+            pause_for(
+                &mut ctx.shared.pause,
+                pause::Reason::Temperature,
+                recalibrate_temperature::spawn().or_count("recalibrate_temperature"),
+            );
+        }
+        add_to_offset_calibration(ctx, &measurement);
+        ctx.shared
+            .latest_measurement
+            .lock(|latest| *latest = Some(measurement));
+        if ctx.shared.measurements.push(measurement) {
+            log_warn!("measurement queue full, dropped a measurement");
+        }
+        publish::spawn().or_count("publish");
+    }
+
+    /// Whether the reading passes the pause, the [`RangeGate`] & the presence detection of the
+    /// low power mode, it's marked if it has wrapped around & checked for a dirty cover glass.
+    fn accept_reading(
+        ctx: &mut tof_interrupt_triggered::Context,
+        reading: &mut Reading,
+        calibrating: bool,
+    ) -> bool {
+        if !calibrating && !ctx.shared.pause.lock(Pause::passes) {
+            defmt::trace!(
+                "discarded a measurement at {}mm after a pause",
+                reading.distance_mm
+            );
+            return false;
+        }
+        if !calibrating && !ctx.shared.range_gate.lock(|gate| gate.passes(reading)) {
+            defmt::trace!("gated a measurement at {}mm", reading.distance_mm);
+            return false;
+        }
+        match ctx
+            .shared
+            .low_power
+            .lock(|low_power| low_power.on_reading(reading))
+        {
+            Wakeup::NotArmed => {}
+            Wakeup::Presence => {
+                low_power_mode::spawn(false).or_count("low_power_mode");
+            }
+            Wakeup::Ignored => return false,
+        }
+        if ctx.local.wrap_check.is_wrapped(reading) {
+            if crate::config::wrap_around::SUPPRESS {
+                log_warn!("suppressed a wrapped measurement");
+                ctx.shared
+                    .rejected_count
+                    .lock(|count| *count = count.wrapping_add(1));
+                return false;
+            }
+            reading.status = RangeStatus::Wraparound;
+        }
+        let lens = ctx.shared.lens.lock(|lens| {
+            let dirty = lens.update(reading)?;
+            Some((dirty, lens.signal_percent()))
+        });
+        if let Some((dirty, signal_percent)) = lens {
+            if dirty {
+                log_warn!(
+                    "the cover glass is dirty, the signal has dropped to {}% of its baseline",
+                    DebugFormat(&signal_percent)
+                );
+            } else {
+                defmt::info!("the cover glass is clean again");
+            }
+            log_event::spawn(Event::Lens(dirty)).or_count("log_event");
+        }
+        true
+    }
+
+    /// The measurement of the reading, numbered & stamped with the time.
+    fn new_measurement(
+        ctx: &mut tof_interrupt_triggered::Context,
+        reading: &Reading,
+        now_ms: u32,
+    ) -> Measurement {
+        let seq = ctx.shared.measurement_count.lock(|count| {
+            *count = count.wrapping_add(1);
+            *count
+        });
+        if reading.status != RangeStatus::Valid {
+            ctx.shared
+                .rejected_count
+                .lock(|count| *count = count.wrapping_add(1));
+        }
+        let utc_ms = ctx.shared.clock.lock(|clock| {
+            clock.set_sequence(seq);
+            clock.now_ms()
+        });
+        let mut measurement = Measurement {
+            seq,
+            timestamp_ms: now_ms,
+            utc_ms,
+            distance_mm: reading.distance_mm,
+            status: reading.status,
+            ambient_kcps: reading.ambient_kcps,
+            value: None,
+            settling: ctx
+                .shared
+                .warm_up
+                .lock(|warm_up| warm_up.is_settling(now_ms)),
+            #[cfg(feature = "encoder")]
+            position: 0,
+            #[cfg(feature = "imu")]
+            components: None,
+            #[cfg(feature = "environment")]
+            environment: None,
+            #[cfg(feature = "ultrasonic")]
+            acoustic_mm: None,
+            #[cfg(feature = "ultrasonic")]
+            fused: None,
+        };
+        ctx.local.inputs.sample(&mut measurement);
+        measurement
+    }
+
+    /// Add the reading to the crosstalk calibration of the wizard, which is finished by
+    /// [`finish_cross_talk_calibration`].
+    fn add_to_cross_talk_calibration(
+        ctx: &mut tof_interrupt_triggered::Context,
+        reading: &Reading,
+    ) {
+        let cross_talk = ctx.shared.cal_wizard.lock(|wizard| {
+            let wizard = wizard.as_mut()?;
+            match wizard.add(reading) {
+                Progress::Running => None,
+                _ => Some((wizard.calibration().cross_talk_cps, wizard.was_ranging())),
+            }
+        });
+        if let Some((cross_talk_cps, was_ranging)) = cross_talk {
+            pause_for(
+                &mut ctx.shared.pause,
+                pause::Reason::Calibration,
+                finish_cross_talk_calibration::spawn(cross_talk_cps, was_ranging)
+                    .or_count("finish_cross_talk_calibration"),
+            );
+        }
+    }
+
+    /// Add the measurement to the running offset calibration, which is finished by
+    /// [`finish_offset_calibration`].
+    fn add_to_offset_calibration(
+        ctx: &mut tof_interrupt_triggered::Context,
+        measurement: &Measurement,
+    ) {
+        let finished = ctx.shared.calibration.lock(|calibration| {
+            // the settling measurements would distort the offset
+            if measurement.settling {
+                return None;
+            }
+            let progress = calibration.as_mut()?.add(measurement);
+            if progress == Progress::Running {
+                return None;
+            }
+            calibration
+                .take()
+                .map(|calibration| (progress, calibration))
+        });
+        if let Some((progress, calibration)) = finished {
+            pause_for(
+                &mut ctx.shared.pause,
+                pause::Reason::Calibration,
+                finish_offset_calibration::spawn(progress, calibration)
+                    .or_count("finish_offset_calibration"),
+            );
+        }
+    }
+
+    /// Pause the acquisition for the `reason` if the task which resumes it has been `spawned`, so
+    /// that the measurements read until it has finished are discarded.
+    fn pause_for(pause: &mut impl rtic::Mutex<T = Pause>, reason: pause::Reason, spawned: bool) {
+        if spawned {
+            pause.lock(|pause| pause.pause(reason));
+        }
+    }
+
+    /// Finish the crosstalk calibration of the wizard with the `cross_talk_cps` & prompt for the
+    /// next step.
+    #[task(shared=[tof_sensor, ranging, pause, sensor_error])]
+    fn finish_cross_talk_calibration(
+        ctx: finish_cross_talk_calibration::Context,
+        cross_talk_cps: u16,
+        was_ranging: bool,
+    ) {
+        let finish_cross_talk_calibration::SharedResources {
+            mut tof_sensor,
+            mut ranging,
+            mut pause,
+            mut sensor_error,
+        } = ctx.shared;
+        let finished = tof_sensor.lock(|tof_sensor| {
+            tof_sensor.finish_cross_talk_calibration(cross_talk_cps, was_ranging)
+        });
+        pause.lock(|pause| pause.resume(pause::Reason::Calibration));
+        if finished.is_err() {
+            sensor_error.lock(|sensor_error| *sensor_error = true);
+        }
+        ranging.lock(|ranging| *ranging = was_ranging);
+        send_cal_prompt::spawn().or_count("send_cal_prompt");
+    }
+
+    /// Finish the offset calibration, the result is stored unless the wizard continues with the
+    /// crosstalk calibration.
+    #[task(shared=[tof_sensor, ranging, pause, sensor_error, cal_wizard, lens])]
+    fn finish_offset_calibration(
+        ctx: finish_offset_calibration::Context,
+        progress: Progress,
+        calibration: OffsetCalibration,
+    ) {
+        let finish_offset_calibration::SharedResources {
+            mut tof_sensor,
+            mut ranging,
+            mut pause,
+            mut sensor_error,
+            mut cal_wizard,
+            mut lens,
+        } = ctx.shared;
+        let offset = match progress {
+            Progress::Done(offset) => offset,
+            _ => calibration.previous_offset(),
+        };
+        let was_ranging = calibration.was_ranging();
+        let result =
+            tof_sensor.lock(|tof_sensor| tof_sensor.finish_offset_calibration(offset, was_ranging));
+        pause.lock(|pause| pause.resume(pause::Reason::Calibration));
+        // the wizard continues with the crosstalk & stores the results once reviewed
+        let guided = cal_wizard.lock(|wizard| {
+            let done = matches!(progress, Progress::Done(_)) && result.is_ok();
+            wizard
+                .as_mut()
+                .map(|wizard| wizard.on_offset(done.then_some(offset)))
+                .is_some()
+        });
+        if result.is_err() {
+            sensor_error.lock(|sensor_error| *sensor_error = true);
+        } else if let Progress::Done(_) = progress {
+            if !guided {
+                save_calibration::spawn().or_count("save_calibration");
+            }
+            tof_sensor.lock(log_optics);
+            lens.lock(|lens| lens.reset());
+        }
+        ranging.lock(|ranging| *ranging = was_ranging);
+        if guided {
+            send_cal_prompt::spawn().or_count("send_cal_prompt");
+        }
+    }
+
+    /// Calibrate the TOF sensor for the changed temperature.
+    #[task(shared=[tof_sensor, pause, sensor_error])]
+    fn recalibrate_temperature(ctx: recalibrate_temperature::Context) {
+        let recalibrate_temperature::SharedResources {
+            mut tof_sensor,
+            mut pause,
+            mut sensor_error,
+        } = ctx.shared;
+        let result = tof_sensor.lock(|tof_sensor| tof_sensor.recalibrate_temperature());
+        pause.lock(|pause| pause.resume(pause::Reason::Temperature));
+        if result.is_err() {
+            sensor_error.lock(|sensor_error| *sensor_error = true);
+        }
+    }
+
+    /// Log the outcome of a check of the [`crate::drift`].
+    #[task]
+    fn report_drift(_: report_drift::Context, outcome: drift::Outcome) {
+        match outcome {
+            drift::Outcome::Drift(drift_mm) => {
+                log_info!("drift check: the offset has drifted by {} mm", drift_mm)
+            }
+            drift::Outcome::Exceeded(drift_mm) => log_warn!(
+                "drift check: the offset has drifted by {} mm, recalibrate the sensor",
+                drift_mm
+            ),
+            drift::Outcome::NotStatic { spread_mm } => log_warn!(
+                "drift check: discarded as the scene isn't static (spread {} mm)",
+                spread_mm
+            ),
+        }
+    }
+
+    /// Triggers every time the second TOF sensor of the `second-tof` feature has a measurement
+    /// available, which is sent while ranging or interleaved. It's pended by the `acquisition`
+    /// command to switch the interleaving & by the cadence to start a measurement in the window of
+    /// the sensor, see [`crate::interleave`].
+    #[task(binds=EXTI2, local=[second_tof], shared=[ranging, acquisition, interleaver, second_tof_start, clock, links, frame_format])]
+    fn second_tof_interrupt_triggered(ctx: second_tof_interrupt_triggered::Context) {
+        #[cfg(feature = "second-tof")]
+        {
+            let mut shared = ctx.shared;
+            let second_tof = ctx.local.second_tof;
+            let interleaved = shared
+                .acquisition
+                .lock(|acquisition| acquisition.is_interleaved());
+            if let Err(e) = second_tof.set_interleaved(interleaved) {
+                log_warn!(
+                    "failed to switch the interleaving of the second TOF sensor: {}",
+                    DebugFormat(&e)
+                );
+            }
+            if shared.second_tof_start.lock(core::mem::take) {
+                if let Err(e) = second_tof.start() {
+                    log_warn!("failed to start the second TOF sensor: {}", DebugFormat(&e));
+                    shared.interleaver.lock(|interleaver| interleaver.finish(1));
+                }
+                // a pending measurement raises the interrupt again
+                return;
+            }
+            if !second_tof.is_interrupt_pending() {
+                return;
+            }
+            let result = second_tof.on_interrupt();
+            if interleaved {
+                shared.interleaver.lock(|interleaver| interleaver.finish(1));
+            }
+            let (seq, reading) = match result {
+                Ok(measurement) => measurement,
+                Err(e) => {
+                    log_warn!("failed to read the second TOF sensor: {}", DebugFormat(&e));
+                    return;
+                }
+            };
+            if !interleaved && !shared.ranging.lock(|ranging| *ranging) {
+                return;
+            }
+            let measurement = telemetry::SecondMeasurement {
+                seq,
+                timestamp_ms: now_ms(),
+                utc_ms: shared.clock.lock(|clock| clock.now_ms()),
+                distance_mm: reading.distance_mm,
+                status: reading.status,
+            };
+            let format = shared.frame_format.lock(|format| *format);
+            match telemetry::second_measurement_frame(&measurement, format) {
+                Ok(frame) => shared.links.lock(|links| links.publish_frame(&frame)),
+                Err(_) => log_warn!("failed to format measurement {} of the second sensor", seq),
+            }
+        }
+        #[cfg(not(feature = "second-tof"))]
+        let _ = ctx;
+    }
+
+    /// Poll the TOF sensors behind the I2C multiplexer of the `tof-mux` feature & send their
+    /// measurements while ranging, runs every [`crate::config::tof_mux::POLL_INTERVAL_MS`] from
+    /// boot on.
+    #[task(local=[tof_array], shared=[ranging, clock, links, frame_format])]
+    fn poll_tof_array(ctx: poll_tof_array::Context) {
+        #[cfg(feature = "tof-mux")]
+        {
+            let mut shared = ctx.shared;
+            let readings = ctx.local.tof_array.poll();
+            if shared.ranging.lock(|ranging| *ranging) {
+                let timestamp_ms = now_ms();
+                let utc_ms = shared.clock.lock(|clock| clock.now_ms());
+                let format = shared.frame_format.lock(|format| *format);
+                for reading in readings {
+                    let measurement = telemetry::ArrayMeasurement {
+                        channel: reading.channel,
+                        seq: reading.seq,
+                        timestamp_ms,
+                        utc_ms,
+                        distance_mm: reading.reading.distance_mm,
+                        status: reading.reading.status,
+                    };
+                    match telemetry::array_measurement_frame(&measurement, format) {
+                        Ok(frame) => shared.links.lock(|links| links.publish_frame(&frame)),
+                        Err(_) => log_warn!(
+                            "failed to format measurement {} of channel {}",
+                            reading.seq,
+                            reading.channel
+                        ),
+                    }
+                }
+            }
+            poll_tof_array::spawn_after(
+                u64::from(crate::config::tof_mux::POLL_INTERVAL_MS).millis(),
+            )
+            .or_count("poll_tof_array");
+        }
+        #[cfg(not(feature = "tof-mux"))]
+        let _ = ctx;
+    }
+
+    /// Poll the data ready flag of the TOF sensor & handle a measurement like on the interrupt,
+    /// runs while the acquisition is [`Acquisition::Polled`].
+    #[task(shared=[tof_sensor, ranging, acquisition, sensor_error])]
+    fn poll_tof(mut ctx: poll_tof::Context) {
+        if ctx.shared.acquisition.lock(|acquisition| *acquisition) != Acquisition::Polled {
+            return;
+        }
+        if ctx.shared.ranging.lock(|ranging| *ranging) {
+            match ctx
+                .shared
+                .tof_sensor
+                .lock(|tof_sensor| tof_sensor.is_data_ready())
+            {
+                Ok(true) => rtic::pend(pac::Interrupt::EXTI0),
+                Ok(false) => {}
+                Err(_) => ctx
+                    .shared
+                    .sensor_error
+                    .lock(|sensor_error| *sensor_error = true),
+            }
+        }
+        poll_tof::spawn_after(u64::from(acquisition::POLL_INTERVAL_MS).millis())
+            .or_count("poll_tof");
+    }
+
+    /// Handle a data ready interrupt which has been delayed, either coalesced by the
+    /// [`InterruptGuard`] or by the `fault-injection` feature.
+    #[task]
+    fn delay_tof_interrupt(_: delay_tof_interrupt::Context) {
+        rtic::pend(pac::Interrupt::EXTI0);
+    }
+
+    /// Generate the synthetic measurements of the `sim` feature & raise the data ready interrupt
+    /// instead of the sensor.
+    /// While polling only the data ready flag is simulated.
+    #[task(shared=[acquisition])]
+    fn simulate_measurement(ctx: simulate_measurement::Context) {
+        #[cfg(feature = "sim")]
+        {
+            let mut acquisition = ctx.shared.acquisition;
+            let now_ms = now_ms();
+            let (available, interval_ms) = crate::sim::step(now_ms);
+            if available && acquisition.lock(|acquisition| *acquisition) == Acquisition::Interrupt {
+                rtic::pend(pac::Interrupt::EXTI0);
+            }
+            simulate_measurement::spawn_after(u64::from(interval_ms).millis())
+                .or_count("simulate_measurement");
+        }
+        #[cfg(not(feature = "sim"))]
+        let _ = ctx;
+    }
+
+    /// Start the next step of the demo of the `demo` feature every
+    /// [`crate::config::demo::STEP_S`], see [`crate::demo`].
+    #[task(shared = [demo, app_mode, narration])]
+    fn demo_step(ctx: demo_step::Context) {
+        #[cfg(feature = "demo")]
+        {
+            let demo_step::SharedResources {
+                mut demo,
+                mut app_mode,
+                mut narration,
+            } = ctx.shared;
+            let step = demo.lock(|demo| demo.advance());
+            log_info!(
+                "demo {}/{}: {}",
+                step.number(),
+                crate::demo::Step::COUNT,
+                step.narration()
+            );
+            app_mode.lock(|app_mode| *app_mode = step.app_mode());
+            low_power_mode::spawn(false).or_count("low_power_mode");
+            narrate(&mut narration, crate::demo::step_view(step));
+            demo_step::spawn_after(u64::from(crate::config::demo::STEP_S).secs())
+                .or_count("demo_step");
+        }
+        #[cfg(not(feature = "demo"))]
+        let _ = ctx;
+    }
+
+    /// Show the narration of the demo on the displays for [`crate::config::demo::NARRATION_MS`].
+    #[cfg(feature = "demo")]
+    fn narrate(narration: &mut impl rtic::Mutex<T = Option<MenuView>>, view: MenuView) {
+        narration.lock(|narration| *narration = Some(view));
+        if Displays::ENABLED {
+            rtic::pend(pac::Interrupt::EXTI3);
+        }
+        // a pending end of the previous narration ends this one
+        end_narration::spawn_after(u64::from(crate::config::demo::NARRATION_MS).millis()).ok();
+    }
+
+    /// Show the distance again after a narration of the demo.
+    #[task(shared = [narration])]
+    fn end_narration(mut ctx: end_narration::Context) {
+        ctx.shared.narration.lock(|narration| *narration = None);
+        if Displays::ENABLED {
+            rtic::pend(pac::Interrupt::EXTI3);
+        }
+    }
+
+    /// Publish the mean of the measurements of the [`crate::publish_interval`] which ends `at`
+    /// (`None` for now) & schedule the next one, until every measurement is published again.
+    #[task(shared = [publish_interval_ms, averager, change_filter, links, frame_format, scaling])]
+    fn publish_average(mut ctx: publish_average::Context, at: Option<Instant>) {
+        let Some(interval_ms) = ctx.shared.publish_interval_ms.lock(|interval| *interval) else {
+            return;
+        };
+        let at = at.unwrap_or_else(monotonics::now);
+        let mean = ctx.shared.averager.lock(|averager| averager.take());
+        if let Some(mut measurement) = mean.filter(|measurement| {
+            ctx.shared
+                .change_filter
+                .lock(|filter| filter.passes(measurement))
+        }) {
+            measurement.value = ctx
+                .shared
+                .scaling
+                .lock(|scaling| scaling.apply(measurement.distance_mm));
+            let format = ctx.shared.frame_format.lock(|format| *format);
+            match telemetry::measurement_frame(&measurement, format) {
+                Ok(frame) => ctx
+                    .shared
+                    .links
+                    .lock(|links| links.publish(&measurement, &frame)),
+                Err(_) => log_warn!("failed to format measurement {}", measurement.seq),
+            }
+        }
+        // scheduled at a fixed cadence, independent of how late this one has run
+        let next = at + u64::from(interval_ms).millis();
+        publish_average::spawn_at(next, Some(next)).or_count("publish_average");
+    }
+
+    /// Update the outputs & the data loggers with the queued measurements, log the events they
+    /// caused and send them to all connected telemetry sinks if the application mode asks for it.
+    #[task(local = [presence, ambient, tracker, alarms_active: bool = true], shared = [measurements, links, outputs, app_mode, frame_format, scaling, publish_interval_ms, averager, change_filter, geofence, low_power, log_requests, rollup, clock, sample_history, extremes, capture, batcher, schedule, demo, narration, profile])]
+    fn publish(mut ctx: publish::Context) {
+        // in the fast profile the data loggers are only woken up for whole batches, the rest is
+        // flushed by `periodic`
+        let batched = ctx
+            .shared
+            .profile
+            .lock(|profile| *profile == Some(Profile::Fast));
+        while let Some(mut measurement) = ctx.shared.measurements.pop() {
+            measurement.value = ctx
+                .shared
+                .scaling
+                .lock(|scaling| scaling.apply(measurement.distance_mm));
+            ctx.shared
+                .sample_history
+                .lock(|history| history.push(&measurement));
+            // the data loggers store all measurements
+            if DataLog::ENABLED {
+                let batch_full = ctx.shared.log_requests.lock(|requests| {
+                    requests.push(measurement);
+                    requests.is_batch_full()
+                });
+                if !batched || batch_full {
+                    rtic::pend(pac::Interrupt::EXTI4);
+                }
+            }
+            let step = ctx
+                .shared
+                .capture
+                .lock(|capture| capture.on_measurement(&measurement));
+            if let capture::Step::Ended(id) = step {
+                finish_capture::spawn(id, Outcome::Complete).or_count("finish_capture");
+            }
+            // the measurements of a capture are all published, the others not at all meanwhile
+            let captured = matches!(step, capture::Step::Captured | capture::Step::Last(_));
+            let excluded = step != capture::Step::Idle && !captured;
+            // the measurements of the warm-up are only published (while streaming) & logged, see
+            // [`crate::warm_up`]
+            if measurement.settling {
+                if excluded || ctx.shared.app_mode.lock(|app_mode| *app_mode) != AppMode::Streaming
+                {
+                    continue;
+                }
+            } else {
+                // the alarms & the presence events follow the active hours
+                let active = ctx
+                    .shared
+                    .schedule
+                    .lock(|schedule| schedule.is_active(measurement.utc_ms));
+                if active != *ctx.local.alarms_active {
+                    *ctx.local.alarms_active = active;
+                    defmt::info!("alarms {}", if active { "active" } else { "inactive" });
+                    ctx.shared
+                        .outputs
+                        .lock(|outputs| outputs.set_alarms_active(active));
+                    ctx.shared
+                        .links
+                        .lock(|links| links.set_alarms_active(active));
+                }
+                ctx.shared
+                    .outputs
+                    .lock(|outputs| outputs.update(&measurement));
+                ctx.shared
+                    .extremes
+                    .lock(|extremes| extremes.add(&measurement));
+
+                // the presence is also tracked in the other modes so that it's up to date when
+                // switching
+                let presence_changed = ctx.local.presence.update(&measurement);
+                if presence_changed && active {
+                    let event = Event::Presence(ctx.local.presence.is_present());
+                    log_event::spawn(event).or_count("log_event");
+                    publish_webhook(&mut ctx.shared.links, &event, &measurement);
+                }
+                let transitions = (&mut ctx.shared.geofence, &mut ctx.shared.outputs).lock(
+                    |geofence, outputs| {
+                        let transitions = geofence.update(&measurement);
+                        outputs.update_zones(geofence, &transitions);
+                        transitions
+                    },
+                );
+                for transition in transitions {
+                    if transition.actions.contains(Actions::EVENT) {
+                        let event = Event::Zone {
+                            zone: transition.zone,
+                            entered: transition.entered,
+                        };
+                        log_event::spawn(event).or_count("log_event");
+                        publish_webhook(&mut ctx.shared.links, &event, &measurement);
+                    }
+                }
+                #[cfg(feature = "demo")]
+                if let Some(gesture) = ctx
+                    .shared
+                    .demo
+                    .lock(|demo| demo.on_measurement(&measurement))
+                {
+                    log_info!("demo: {} gesture", gesture.name());
+                    narrate(
+                        &mut ctx.shared.narration,
+                        crate::demo::gesture_view(gesture),
+                    );
+                }
+                // a track record per measurement, regardless of the publishing of the measurements
+                let track = crate::config::track::ENABLED
+                    .then(|| ctx.local.tracker.update(&measurement))
+                    .flatten();
+                if let Some(track) = track {
+                    let format = ctx.shared.frame_format.lock(|format| *format);
+                    match telemetry::track_frame(&track, format) {
+                        Ok(frame) => ctx.shared.links.lock(|links| links.publish_frame(&frame)),
+                        Err(_) => log_warn!("failed to format the track of {}", track.seq),
+                    }
+                }
+                if let Some(high) = ctx.local.ambient.update(measurement.ambient_kcps) {
+                    if high {
+                        log_warn!(
+                            "high ambient light ({} kcps), the accuracy is reduced",
+                            measurement.ambient_kcps
+                        );
+                    } else {
+                        defmt::info!("ambient light is normal again");
+                    }
+                    log_event::spawn(Event::Ambient(high)).or_count("log_event");
+                    if crate::config::ambient::SHORT_MODE {
+                        adapt_to_ambient::spawn(high).or_count("adapt_to_ambient");
+                    }
+                }
+                let present = ctx.local.presence.is_present();
+                let registers = ctx.shared.rollup.lock(|rollup| {
+                    rollup.add(&measurement, present);
+                    rollup.backup(now_ms())
+                });
+                ctx.shared.clock.lock(|clock| clock.set_rollup(registers));
+
+                if excluded {
+                    continue;
+                }
+                let app_mode = ctx.shared.app_mode.lock(|app_mode| *app_mode);
+                if app_mode == AppMode::LowPower
+                    && ctx
+                        .shared
+                        .low_power
+                        .lock(|low_power| low_power.on_presence(present, measurement.timestamp_ms))
+                {
+                    low_power_mode::spawn(true).or_count("low_power_mode");
+                }
+                if !captured && !app_mode.publishes(presence_changed) {
+                    continue;
+                }
+                if !captured
+                    && app_mode == AppMode::Streaming
+                    && ctx
+                        .shared
+                        .publish_interval_ms
+                        .lock(|interval_ms| interval_ms.is_some())
+                {
+                    ctx.shared
+                        .averager
+                        .lock(|averager| averager.add(&measurement));
+                    continue;
+                }
+                if !captured
+                    && app_mode == AppMode::Streaming
+                    && !ctx
+                        .shared
+                        .change_filter
+                        .lock(|filter| filter.passes(&measurement))
+                {
+                    continue;
+                }
+            }
+
+            let format = ctx.shared.frame_format.lock(|format| *format);
+            if crate::config::batch::ENABLED {
+                if !captured {
+                    let update = ctx.shared.batcher.lock(|batcher| batcher.add(&measurement));
+                    handle_batch(&mut ctx.shared.links, format, update);
+                    ctx.shared
+                        .links
+                        .lock(|links| links.publish_batched(&measurement));
+                    continue;
+                }
+                // the measurements of a capture follow the earlier ones
+                if let Some(batch) = ctx.shared.batcher.lock(|batcher| batcher.flush()) {
+                    publish_batch(&mut ctx.shared.links, format, &batch);
+                }
+            }
+            let Ok(frame) = telemetry::measurement_frame(&measurement, format) else {
+                log_warn!("failed to format measurement {}", measurement.seq);
+                continue;
+            };
+
+            ctx.shared
+                .links
+                .lock(|links| links.publish(&measurement, &frame));
+            // the footer follows the last measurement
+            if let capture::Step::Last(id) = step {
+                finish_capture::spawn(id, Outcome::Complete).or_count("finish_capture");
+            }
+        }
+    }
+
+    /// Publish the pending [`crate::batch`] if it has timed out, the timeout is armed again for a
+    /// younger one.
+    #[task(shared = [batcher, links, frame_format])]
+    fn batch_timeout(mut ctx: batch_timeout::Context) {
+        let now_ms = now_ms();
+        let update = ctx
+            .shared
+            .batcher
+            .lock(|batcher| batcher.on_timeout(now_ms));
+        let format = ctx.shared.frame_format.lock(|format| *format);
+        handle_batch(&mut ctx.shared.links, format, update);
+    }
+
+    /// Publish the completed batch & schedule the timeout of the pending one.
+    fn handle_batch(
+        links: &mut impl rtic::Mutex<T = Links>,
+        format: FrameFormat,
+        update: batch::Update,
+    ) {
+        if let Some(batch) = update.complete {
+            publish_batch(links, format, &batch);
+        }
+        if let Some(timeout_ms) = update.timeout_ms {
+            batch_timeout::spawn_after(u64::from(timeout_ms).millis()).or_count("batch_timeout");
+        }
+    }
+
+    fn publish_batch(links: &mut impl rtic::Mutex<T = Links>, format: FrameFormat, batch: &Batch) {
+        match telemetry::batch_frame(batch, format) {
+            Ok(frame) => links.lock(|links| links.publish_batch(&frame)),
+            Err(_) => log_warn!("failed to format the batch of measurement {}", batch.seq),
+        }
+    }
+
+    /// Execute a command received from the host (at `received_ms`) and send the response to all
+    /// links. The commands of several links can arrive at once.
+    #[task(capacity = 2, local = [image, reset_token, rdp_token, i2c_scanner], shared = [tof_sensor, ranging, pause, acquisition, measurement_count, sensor_error, safe_mode, links, outputs, frame_format, scaling, publish_interval_ms, change_filter, range_gate, drift_monitor, schedule, geofence, flash, eeprom, clock, log_requests, interrupt_guard, preset, jitter, measurements, sensor_supervisor, cpu_load, watchdog_margin, sample_history, extremes])]
+    fn handle_command(ctx: handle_command::Context, command: Command, received_ms: u32) {
+        defmt::info!("executing command {}", command);
+        let handle_command::SharedResources {
+            mut tof_sensor,
+            mut ranging,
+            mut pause,
+            mut acquisition,
+            mut measurement_count,
+            mut sensor_error,
+            mut safe_mode,
+            mut links,
+            // only needed for commands of optional outputs
+            #[cfg_attr(
+                not(any(
+                    feature = "buzzer",
+                    feature = "alarm-output",
+                    feature = "motor-pid",
+                    feature = "stepper"
+                )),
+                allow(unused_mut, unused_variables)
+            )]
+            mut outputs,
+            mut frame_format,
+            mut scaling,
+            mut publish_interval_ms,
+            mut change_filter,
+            mut range_gate,
+            mut drift_monitor,
+            mut schedule,
+            mut geofence,
+            mut flash,
+            mut eeprom,
+            mut clock,
+            mut log_requests,
+            mut interrupt_guard,
+            mut preset,
+            mut jitter,
+            measurements,
+            mut sensor_supervisor,
+            mut cpu_load,
+            mut watchdog_margin,
+            mut sample_history,
+            mut extremes,
+        } = ctx.shared;
+
+        let safe_mode = safe_mode.lock(|safe_mode| *safe_mode);
+        if command == Command::Start && safe_mode {
+            links.lock(|links| links.write(b"ERR safe mode\r\n"));
+            return;
+        }
+        // the image is received on the virtual COM port, which can only be used by one transfer
+        #[cfg(feature = "firmware-update")]
+        if command == Command::Update {
+            if links.lock(|links| links.vcp.is_transferring()) {
+                links.lock(|links| links.write(b"ERR busy\r\n"));
+            } else {
+                rtic::pend(pac::Interrupt::EXTI9_5);
+            }
+            return;
+        }
+        // a reset has to be confirmed with the token issued by the previous `reset`, raising the
+        // read protection as well as it can only be undone by mass erasing the flash
+        let confirmation = match command {
+            Command::Reset(token) => Some((ctx.local.reset_token, token)),
+            Command::Protect(ProtectCommand::Read(token)) => Some((ctx.local.rdp_token, token)),
+            _ => None,
+        };
+        if let Some((pending, token)) = confirmation {
+            if !confirm(pending, token, received_ms, &mut links) {
+                return;
+            }
+        }
+        // these commands write to the flash, the response is sent once they're done
+        let spawned = match command {
+            Command::Save => Some(save_settings::spawn()),
+            Command::Profile(profile_command) => {
+                Some(handle_profile_command::spawn(profile_command).map_err(|_| ()))
+            }
+            // the response to `cal next` is sent once the measurement is done
+            Command::Cal(cal_command) => {
+                Some(handle_cal_command::spawn(cal_command).map_err(|_| ()))
+            }
+            Command::Capture(capture_command) => {
+                Some(handle_capture_command::spawn(capture_command).map_err(|_| ()))
+            }
+            // the response is sent once the test is done
+            #[cfg(feature = "uart-loopback")]
+            Command::SelfTest(mode) => Some(self_test::spawn(mode).map_err(|_| ())),
+            _ => None,
+        };
+        if let Some(spawned) = spawned {
+            if spawned.is_err() {
+                links.lock(|links| links.write(b"ERR busy\r\n"));
+            }
+            return;
+        }
+
+        let result = match command {
+            Command::Start => tof_sensor.lock(|tof_sensor| tof_sensor.start()).map(|_| {
+                ranging.lock(|ranging| *ranging = true);
+                send_session_header::spawn().or_count("send_session_header");
+            }),
+            Command::Stop | Command::Shutdown | Command::Dfu | Command::Reset(_) => tof_sensor
+                .lock(|tof_sensor| tof_sensor.stop())
+                .map(|_| ranging.lock(|ranging| *ranging = false)),
+            Command::Format(format) => {
+                frame_format.lock(|frame_format| *frame_format = format);
+                Ok(())
+            }
+            Command::Zone(Some((index, zone))) => {
+                geofence.lock(|geofence| geofence.set_zone(index as usize, zone));
+                Ok(())
+            }
+            Command::Unit(Some(unit)) => {
+                scaling.lock(|scaling| scaling.unit = unit);
+                Ok(())
+            }
+            Command::Scale(Some((scale, offset))) => {
+                scaling.lock(|scaling| {
+                    scaling.scale = scale;
+                    scaling.offset = offset;
+                });
+                Ok(())
+            }
+            Command::Change(Some(delta_mm)) => {
+                change_filter.lock(|filter| filter.set_delta_mm(delta_mm));
+                Ok(())
+            }
+            Command::Drift(DriftCommand::Check) => {
+                drift_monitor.lock(|monitor| monitor.start());
+                Ok(())
+            }
+            Command::Drift(DriftCommand::Reference(reference_mm)) => {
+                drift_monitor.lock(|monitor| monitor.set_reference_mm(reference_mm));
+                Ok(())
+            }
+            Command::Log(Some((channel, level))) => {
+                log_backend::set_level(channel, level);
+                Ok(())
+            }
+            Command::Gate(Some(window)) => {
+                range_gate.lock(|gate| gate.set_window(window));
+                Ok(())
+            }
+            Command::Schedule(Some(new)) => {
+                schedule.lock(|schedule| *schedule = new);
+                Ok(())
+            }
+            Command::Publish(Some(interval_ms)) => {
+                publish_interval_ms.lock(|publish_interval_ms| *publish_interval_ms = interval_ms);
+                if let Some(interval_ms) = interval_ms {
+                    // fails if the next interval is already scheduled, it uses the new one
+                    publish_average::spawn_after(u64::from(interval_ms).millis(), None).ok();
+                }
+                Ok(())
+            }
+            Command::Jitter(true) => {
+                jitter.lock(|jitter| jitter.reset());
+                Ok(())
+            }
+            Command::Stats(true) => {
+                extremes.lock(|extremes| extremes.reset());
+                Ok(())
+            }
+            Command::Preset(Some(selected)) => {
+                apply_preset(&mut tof_sensor, &mut ranging, &mut pause, selected).map(|_| {
+                    defmt::info!("preset: {}", selected);
+                    preset.lock(|preset| *preset = Some(selected));
+                })
+            }
 
-        let vl53l1x_dev = &mut ctx.local.tof_sensor;
-        if let Ok(distance) = vl53l1x_dev.get_distance() {
-            defmt::info!("Received range: {}mm", distance);
+            Command::Acquisition(Some(mode)) => {
+                let previous =
+                    acquisition.lock(|acquisition| core::mem::replace(acquisition, mode));
+                if mode == Acquisition::Polled && previous != Acquisition::Polled {
+                    poll_tof::spawn().or_count("poll_tof");
+                }
+                // starts & stops the cadence & the interleaving of the second sensor
+                if mode.uses_cadence() || previous.uses_cadence() {
+                    rtic::pend(pac::Interrupt::TIM1_TRG_COM_TIM11);
+                }
+                if mode.is_interleaved() != previous.is_interleaved() {
+                    rtic::pend(pac::Interrupt::EXTI2);
+                }
+                // the line is enabled again by the interrupt task
+                if mode == Acquisition::Interrupt
+                    && interrupt_guard.lock(|guard| guard.is_tripped())
+                {
+                    rtic::pend(pac::Interrupt::EXTI0);
+                }
+                defmt::info!("acquisition: {}", mode);
+                // the measurements are started one at a time from now on
+                if mode.is_single_shot() && ranging.lock(|ranging| *ranging) {
+                    tof_sensor
+                        .lock(|tof_sensor| tof_sensor.stop())
+                        .map(|_| ranging.lock(|ranging| *ranging = false))
+                } else {
+                    Ok(())
+                }
+            }
+            #[cfg(feature = "buzzer")]
+            Command::Buzzer(enabled) => {
+                outputs.lock(|outputs| outputs.buzzer.set_muted(!enabled));
+                Ok(())
+            }
+            #[cfg(feature = "motor-pid")]
+            Command::Pid(pid_command) => {
+                outputs.lock(|outputs| match pid_command {
+                    PidCommand::Status => {}
+                    PidCommand::Enable(enabled) => outputs.motor.set_enabled(enabled),
+                    PidCommand::Target(target_mm) => outputs.motor.set_target(target_mm),
+                    PidCommand::Gains {
+                        kp_milli,
+                        ki_milli,
+                        kd_milli,
+                    } => outputs.motor.set_gains(crate::pid::Gains {
+                        kp: kp_milli as f32 / 1000.0,
+                        ki: ki_milli as f32 / 1000.0,
+                        kd: kd_milli as f32 / 1000.0,
+                    }),
+                });
+                Ok(())
+            }
+            #[cfg(feature = "stepper")]
+            Command::Stepper(Some(mode)) => {
+                outputs.lock(|outputs| outputs.stepper.set_mode(mode));
+                Ok(())
+            }
+            Command::Status
+            | Command::Help
+            | Command::Acquisition(None)
+            | Command::Save
+            | Command::Profile(_)
+            | Command::Cal(_)
+            | Command::Capture(_)
+            | Command::Preset(None)
+            | Command::Resets
+            | Command::Events(_)
+            | Command::Jitter(false)
+            | Command::Stats(false)
+            | Command::Unit(None)
+            | Command::Zone(None)
+            | Command::Scale(None)
+            | Command::Publish(None)
+            | Command::Change(None)
+            | Command::Gate(None)
+            | Command::Schedule(None)
+            | Command::Metrics
+            | Command::Log(None)
+            | Command::Drift(DriftCommand::Status)
+            | Command::Scan
+            | Command::Optics
+            | Command::Protect(_)
+            | Command::Time(_)
+            | Command::Sync(_)
+            | Command::DumpCsv(_)
+            | Command::DumpRegs => Ok(()),
+            #[cfg(feature = "nor-flash")]
+            Command::Dump(_) => Ok(()),
+            #[cfg(feature = "littlefs")]
+            Command::Ls(_) | Command::Cat(_) | Command::Rm(_) => Ok(()),
+            #[cfg(feature = "xmodem")]
+            Command::Transfer(_) => Ok(()),
+            #[cfg(feature = "firmware-update")]
+            Command::Update => Ok(()),
+            #[cfg(feature = "stepper")]
+            Command::Stepper(None) => Ok(()),
+            // cleared with the response, which depends on the distance
+            #[cfg(feature = "alarm-output")]
+            Command::Alarm(_) => Ok(()),
+            #[cfg(feature = "uart-loopback")]
+            Command::SelfTest(_) => Ok(()),
+        };
+        if result.is_err() {
+            sensor_error.lock(|sensor_error| *sensor_error = true);
+        }
+        if command == Command::Dfu && result.is_ok() {
+            links.lock(|links| links.write(b"OK bootloader\r\n"));
+            // give the links time to send the response
+            enter_bootloader::spawn_after(u64::from(bootloader::RESET_DELAY_MS).millis())
+                .or_count("enter_bootloader");
+            return;
+        }
+        // the data loggers send the response once they've been stopped
+        let shutdown = match command {
+            Command::Shutdown => Some(Shutdown::PowerOff),
+            Command::Reset(_) => Some(Shutdown::Reset),
+            _ => None,
+        };
+        if let Some(shutdown) = shutdown.filter(|_| DataLog::ENABLED) {
+            log_requests.lock(|requests| requests.shutdown = Some(shutdown));
+            rtic::pend(pac::Interrupt::EXTI4);
+            return;
+        }
+        match command {
+            // give the links time to send the response
+            Command::Shutdown => {
+                links.lock(|links| links.write(b"OK halting\r\n"));
+                halt::spawn_after(u64::from(bootloader::RESET_DELAY_MS).millis()).or_count("halt");
+                return;
+            }
+            Command::Reset(_) => {
+                links.lock(|links| links.write(b"OK resetting\r\n"));
+                restart::spawn_after(u64::from(bootloader::RESET_DELAY_MS).millis())
+                    .or_count("restart");
+                return;
+            }
+            _ => {}
         }
-        vl53l1x_dev.clear_interrupt().ok();
+        // the data loggers send the output of these commands, followed by the response
+        #[cfg(feature = "nor-flash")]
+        let log_command = match command {
+            Command::Dump(false) => Some(LogCommand::Dump),
+            Command::Dump(true) => Some(LogCommand::CompressedDump),
+            #[cfg(feature = "littlefs")]
+            Command::Ls(path) => Some(LogCommand::List(path)),
+            #[cfg(feature = "littlefs")]
+            Command::Cat(path) => Some(LogCommand::Show(path)),
+            #[cfg(feature = "littlefs")]
+            Command::Rm(path) => Some(LogCommand::Remove(path)),
+            #[cfg(feature = "xmodem")]
+            Command::Transfer(request) => Some(LogCommand::Transfer(request)),
+            _ => None,
+        };
+        #[cfg(feature = "nor-flash")]
+        if let Some(log_command) = log_command {
+            let queued = log_requests.lock(|requests| {
+                let queued = requests.command.is_none();
+                if queued {
+                    requests.command = Some(log_command);
+                }
+                queued
+            });
+            if queued {
+                rtic::pend(pac::Interrupt::EXTI4);
+            } else {
+                links.lock(|links| links.write(b"ERR busy\r\n"));
+            }
+            return;
+        }
+        // the export sends its lines, followed by the response
+        if let Command::DumpCsv(count) = command {
+            let export = sample_history.lock(|history| history.export(count));
+            if export_csv::spawn(export).is_err() {
+                links.lock(|links| links.write(b"ERR busy\r\n"));
+            }
+            return;
+        }
+
+        let mut response = Response::new();
+        match (command, result) {
+            (_, Err(_)) => write!(response, "ERR sensor communication failed\r\n"),
+            (Command::Status, Ok(())) => write!(
+                response,
+                "OK ranging={} measurements={} safe_mode={} image={} device={} version={} git={} \
+                 built={}",
+                ranging.lock(|ranging| *ranging) as u8,
+                measurement_count.lock(|count| *count),
+                safe_mode as u8,
+                ctx.local.image.name(),
+                DeviceId::get(),
+                build_info::VERSION,
+                build_info::GIT_HASH,
+                build_info::BUILD_UNIX_S,
+            )
+            .and_then(|()| {
+                let (duplicates, percent) =
+                    interrupt_guard.lock(|guard| (guard.duplicates(), guard.duplicate_percent()));
+                write!(
+                    response,
+                    " duplicate_interrupts={} duplicate_percent={}",
+                    duplicates, percent
+                )
+            })
+            .and_then(|()| match extremes.lock(|extremes| extremes.range_mm()) {
+                Some((min_mm, max_mm)) => {
+                    write!(response, " min_mm={} max_mm={}\r\n", min_mm, max_mm)
+                }
+                None => write!(response, " min_mm=- max_mm=-\r\n"),
+            }),
+            (Command::Help, Ok(())) => command::write_help(&mut response),
+            (Command::Acquisition(_), Ok(())) => write!(
+                response,
+                "OK acquisition={}\r\n",
+                acquisition.lock(|acquisition| acquisition.name())
+            ),
+            (Command::Stats(_), Ok(())) => {
+                let (range_mm, count) =
+                    extremes.lock(|extremes| (extremes.range_mm(), extremes.count()));
+                match range_mm {
+                    Some((min_mm, max_mm)) => write!(
+                        response,
+                        "OK min_mm={} max_mm={} measurements={}\r\n",
+                        min_mm, max_mm, count
+                    ),
+                    None => write!(response, "OK measurements={}\r\n", count),
+                }
+            }
+            (Command::Jitter(_), Ok(())) => match jitter.lock(|jitter| jitter.summary()) {
+                Some(summary) => write!(
+                    response,
+                    "OK intervals={} min_us={} max_us={} mean_us={} stddev_us={}\r\n",
+                    summary.intervals,
+                    summary.min_us,
+                    summary.max_us,
+                    summary.mean_us,
+                    summary.stddev_us
+                ),
+                None => write!(response, "OK intervals=0\r\n"),
+            },
+            (Command::Drift(drift_command), Ok(())) => {
+                let (reference_mm, latest_mm, checking) = drift_monitor.lock(|monitor| {
+                    (
+                        monitor.reference_mm(),
+                        monitor.latest_mm(),
+                        monitor.is_checking(),
+                    )
+                });
+                match reference_mm {
+                    None if drift_command == DriftCommand::Check => {
+                        write!(response, "ERR no reference distance\r\n")
+                    }
+                    None => write!(response, "OK reference=off\r\n"),
+                    Some(reference_mm) => {
+                        write!(response, "OK reference={}mm drift=", reference_mm)
+                            .and_then(|()| match latest_mm {
+                                Some(drift_mm) => write!(response, "{:+}mm", drift_mm),
+                                None => write!(response, "-"),
+                            })
+                            .and_then(|()| write!(response, " checking={}\r\n", checking as u8))
+                    }
+                }
+            }
+            (Command::Log(_), Ok(())) => {
+                let name =
+                    |channel| log_backend::level(channel).map_or("off", log_backend::Level::name);
+                write!(
+                    response,
+                    "OK rtt={} uart={}\r\n",
+                    name(Channel::Rtt),
+                    name(Channel::Uart)
+                )
+            }
+            (Command::Metrics, Ok(())) => {
+                let health = Health {
+                    timestamp_ms: now_ms(),
+                    utc_ms: None,
+                    ranging: ranging.lock(|ranging| *ranging),
+                    measurements: measurement_count.lock(|count| *count),
+                    sensor_error: sensor_error.lock(|sensor_error| *sensor_error),
+                    safe_mode,
+                    stale: sensor_supervisor.lock(|supervisor| supervisor.is_lost()),
+                    reinits: sensor_supervisor.lock(|supervisor| supervisor.reinits()),
+                    suppressed_interrupts: interrupt_guard.lock(|guard| guard.suppressed()),
+                    queue_overflows: measurements.overflows(),
+                    #[cfg(feature = "power")]
+                    power: None,
+                };
+                let dropped_frames = links.lock(|links| links.dropped_frames());
+                let watchdog = watchdog_margin.lock(|margin| (margin.lowest_ms(), margin.low()));
+                metrics::write(
+                    &mut response,
+                    &health,
+                    cpu_load.lock(|load| *load),
+                    dropped_frames,
+                    watchdog,
+                )
+                .and_then(|()| write!(response, "OK\r\n"))
+            }
+            (Command::Zone(_), Ok(())) => {
+                geofence.lock(|geofence| geofence::write_response(geofence.zones(), &mut response))
+            }
+            (Command::Unit(_), Ok(())) => write!(
+                response,
+                "OK unit={}\r\n",
+                scaling.lock(|scaling| scaling.unit.name())
+            ),
+            (Command::Scale(_), Ok(())) => {
+                let scaling = scaling.lock(|scaling| *scaling);
+                write!(
+                    response,
+                    "OK scale={} offset={}\r\n",
+                    scaling.scale, scaling.offset
+                )
+            }
+            (Command::Publish(_), Ok(())) => match publish_interval_ms.lock(|interval| *interval) {
+                Some(interval_ms) => write!(response, "OK publish={}ms\r\n", interval_ms),
+                None => write!(response, "OK publish=off\r\n"),
+            },
+            (Command::Change(_), Ok(())) => match change_filter.lock(|filter| filter.delta_mm()) {
+                Some(delta_mm) => write!(response, "OK change={}mm\r\n", delta_mm),
+                None => write!(response, "OK change=off\r\n"),
+            },
+            (Command::Gate(_), Ok(())) => {
+                let (window, gated) = range_gate.lock(|gate| (gate.window(), gate.gated()));
+                match window {
+                    Some((min_mm, max_mm)) => write!(
+                        response,
+                        "OK gate={}-{}mm gated={}\r\n",
+                        min_mm, max_mm, gated
+                    ),
+                    None => write!(response, "OK gate=off gated={}\r\n", gated),
+                }
+            }
+            (Command::Schedule(_), Ok(())) => {
+                let schedule = schedule.lock(|schedule| *schedule);
+                let active = schedule.is_active(clock.lock(|clock| clock.now_ms()));
+                write!(response, "OK schedule=")
+                    .and_then(|()| schedule.write_to(&mut response))
+                    .and_then(|()| write!(response, " active={}\r\n", active as u8))
+            }
+            (Command::Preset(_), Ok(())) => write!(
+                response,
+                "OK preset={}\r\n",
+                preset.lock(|preset| preset.map_or("-", |preset| preset.name()))
+            ),
+            (Command::Resets, Ok(())) => {
+                (&mut flash, &mut eeprom).lock(|flash, eeprom| match eeprom {
+                    Some(eeprom) => reset_log::write_response(eeprom, flash, &mut response),
+                    None => write!(response, "ERR storage not available\r\n"),
+                })
+            }
+            (Command::Events(count), Ok(())) => {
+                (&mut flash, &mut eeprom).lock(|flash, eeprom| match eeprom {
+                    Some(eeprom) => {
+                        event_log::write_response(eeprom, flash, count as usize, &mut response)
+                    }
+                    None => write!(response, "ERR storage not available\r\n"),
+                })
+            }
+            (Command::Optics, Ok(())) => match tof_sensor.lock(|tof_sensor| tof_sensor.optics()) {
+                Ok(optics) => {
+                    let (x, y) = optics.center();
+                    write!(
+                        response,
+                        "OK spads={} effective_spads={} roi={}x{} center={} x={} y={}\r\n",
+                        optics.enabled_spads,
+                        optics.effective_spads,
+                        optics.roi_width,
+                        optics.roi_height,
+                        optics.center_spad,
+                        x,
+                        y
+                    )
+                }
+                Err(_) => {
+                    sensor_error.lock(|sensor_error| *sensor_error = true);
+                    write!(response, "ERR sensor communication failed\r\n")
+                }
+            },
+            (Command::DumpRegs, Ok(())) => {
+                match tof_sensor.lock(|tof_sensor| tof_sensor.registers()) {
+                    Ok(snapshot) => write!(response, "OK {}\r\n", snapshot),
+                    Err(_) => {
+                        sensor_error.lock(|sensor_error| *sensor_error = true);
+                        write!(response, "ERR sensor communication failed\r\n")
+                    }
+                }
+            }
+            (Command::Protect(protect), Ok(())) => flash.lock(|flash| {
+                let result = match protect {
+                    ProtectCommand::Status => Ok(()),
+                    ProtectCommand::Write(region) => {
+                        defmt::info!("write protecting the flash: {}", region);
+                        option_bytes::set_write_protection(flash, region)
+                    }
+                    ProtectCommand::Read(_) => {
+                        log_warn!("raising the read protection of the flash to level 1");
+                        option_bytes::set_read_protection(flash)
+                    }
+                };
+                match result {
+                    Ok(()) => Protection::read(flash).write_status(&mut response),
+                    Err(e) => {
+                        log_error!("failed to program the option bytes: {}", DebugFormat(&e));
+                        write!(response, "ERR programming failed\r\n")
+                    }
+                }
+            }),
+            (Command::Scan, Ok(())) => {
+                let devices = i2c_scan::scan(ctx.local.i2c_scanner);
+                i2c_scan::log(&devices);
+                write!(response, "OK i2c={}\r\n", devices)
+            }
+            (Command::Sync(None), Ok(())) => write!(response, "OK sync={}\r\n", now_ms()),
+            (Command::Sync(Some(reply)), Ok(())) => match TimeSync::new(&reply, received_ms) {
+                Some(sync) => {
+                    defmt::info!("synchronised with the host: {}", sync);
+                    write!(
+                        response,
+                        "OK offset={} rtt={}\r\n",
+                        sync.offset_ms, sync.round_trip_ms
+                    )
+                }
+                None => write!(response, "ERR invalid argument\r\n"),
+            },
+            (Command::Time(time), Ok(())) => clock.lock(|clock| {
+                if let Some(unix_s) = time {
+                    if clock.set(unix_s).is_err() {
+                        return write!(response, "ERR invalid argument\r\n");
+                    }
+                }
+                match clock.now_ms() {
+                    Some(now_ms) => write!(response, "OK utc={}\r\n", now_ms / 1000),
+                    None => write!(response, "OK utc=-\r\n"),
+                }
+            }),
+            #[cfg(feature = "motor-pid")]
+            (Command::Pid(PidCommand::Status), Ok(())) => outputs.lock(|outputs| {
+                let gains = outputs.motor.gains();
+                write!(
+                    response,
+                    "OK enabled={} target={} kp={:.3} ki={:.3} kd={:.3}\r\n",
+                    outputs.motor.enabled() as u8,
+                    outputs.motor.target_mm(),
+                    gains.kp,
+                    gains.ki,
+                    gains.kd,
+                )
+            }),
+            #[cfg(feature = "alarm-output")]
+            (Command::Alarm(clear), Ok(())) => outputs.lock(|outputs| {
+                if clear && !outputs.alarm.clear() {
+                    return write!(response, "ERR alarm condition still present\r\n");
+                }
+                write!(
+                    response,
+                    "OK alarm={} latched={}\r\n",
+                    if outputs.alarm.is_asserted() {
+                        "on"
+                    } else {
+                        "off"
+                    },
+                    outputs.alarm.is_latched() as u8,
+                )
+            }),
+            #[cfg(feature = "stepper")]
+            (Command::Stepper(None), Ok(())) => outputs.lock(|outputs| {
+                write!(
+                    response,
+                    "OK mode={} position={}\r\n",
+                    match outputs.stepper.mode() {
+                        crate::stepper::Mode::Follow => "follow",
+                        crate::stepper::Mode::Scan(_) => "scan",
+                        crate::stepper::Mode::Stopped => "stopped",
+                    },
+                    outputs.stepper.position(),
+                )
+            }),
+            (_, Ok(())) => write!(response, "OK\r\n"),
+        }
+        .ok();
+
+        links.lock(|links| links.write(response.as_bytes()));
     }
 
-    /// Feed the watchdog to avoid hardware reset.
-    #[task(priority=1, local=[watchdog])]
-    fn periodic(ctx: periodic::Context) {
+    /// Check the `token` of a command which has to be confirmed (see [`ResetToken`]) against the
+    /// `pending` one: without a token a new one is issued & sent. Returns whether it's confirmed,
+    /// the error is sent otherwise.
+    fn confirm(
+        pending: &mut Option<ResetToken>,
+        token: Option<u16>,
+        received_ms: u32,
+        links: &mut impl rtic::Mutex<T = Links>,
+    ) -> bool {
+        let previous = pending.take();
+        let Some(token) = token else {
+            let seed = monotonics::now().ticks() as u32 ^ DeviceId::get().0;
+            let issued = ResetToken::new(seed, received_ms);
+            *pending = Some(issued);
+            let mut response = Response::new();
+            write!(response, "OK token={:04x}\r\n", issued.token()).ok();
+            links.lock(|links| links.write(response.as_bytes()));
+            return false;
+        };
+        if !previous.is_some_and(|previous| previous.confirms(token, received_ms)) {
+            links.lock(|links| links.write(b"ERR invalid token\r\n"));
+            return false;
+        }
+        true
+    }
+
+    /// Send the lines of a `dump csv` export (see [`crate::sample_history`]) which fit into the
+    /// transmit buffers, the rest follows after [`sample_history::OUTPUT_INTERVAL_MS`].
+    #[task(shared = [sample_history, links])]
+    fn export_csv(ctx: export_csv::Context, mut export: Export) {
+        let export_csv::SharedResources {
+            sample_history: mut history,
+            mut links,
+        } = ctx.shared;
+        while links.lock(|links| links.free_space()) >= sample_history::MAX_LINE_LEN {
+            match history.lock(|history| history.next_line(&mut export)) {
+                Some(line) => links.lock(|links| links.write(line.as_bytes())),
+                None => return,
+            }
+        }
+        export_csv::spawn_after(
+            u64::from(sample_history::OUTPUT_INTERVAL_MS).millis(),
+            export,
+        )
+        .or_count("export_csv");
+    }
+
+    /// Time the steps of the stepper motor.
+    #[cfg(feature = "stepper")]
+    #[task(binds=TIM1_BRK_TIM9, priority = 2, shared=[outputs])]
+    fn stepper_timer(mut ctx: stepper_timer::Context) {
+        ctx.shared
+            .outputs
+            .lock(|outputs| outputs.stepper.on_timer());
+    }
+
+    /// Handle USART2 events and receive commands sent by the host via the virtual COM port (either
+    /// as lines or via MQTT-SN).
+    #[task(binds=USART2, priority = 2, shared=[links])]
+    fn usart2(mut ctx: usart2::Context) {
+        ctx.shared.links.lock(|links| {
+            links.vcp.on_interrupt(|vcp, result| {
+                dispatch_command(result, |response| vcp.write(response))
+            })
+        });
+    }
+
+    /// Handle USB events and receive commands sent by the host.
+    #[cfg(feature = "usb")]
+    #[task(binds=OTG_FS, priority = 2, shared=[links])]
+    fn usb_fs(mut ctx: usb_fs::Context) {
+        ctx.shared.links.lock(|links| {
+            links
+                .usb
+                .poll(|usb, result| dispatch_command(result, |response| usb.write(response)))
+        });
+    }
+
+    /// Handle USART6 events and receive commands sent via the Bluetooth module.
+    #[cfg(feature = "bluetooth")]
+    #[task(binds=USART6, priority = 2, shared=[links])]
+    fn usart6(mut ctx: usart6::Context) {
+        ctx.shared.links.lock(|links| {
+            links.bluetooth.on_interrupt(|bluetooth, result| {
+                dispatch_command(result, |response| bluetooth.write(response))
+            })
+        });
+    }
+
+    /// Handle responses of the WiFi module, the events of the loopback self-test or the
+    /// transmission of the binary sink, which use the same UART.
+    #[cfg(any(feature = "wifi", feature = "uart-loopback", feature = "uart-binary"))]
+    #[task(binds=USART1, priority = 2, shared=[links, loopback])]
+    fn usart1(ctx: usart1::Context) {
+        #[cfg(feature = "wifi")]
+        {
+            let mut links = ctx.shared.links;
+            links.lock(|links| links.wifi.on_interrupt());
+        }
+        #[cfg(feature = "uart-binary")]
+        {
+            let mut links = ctx.shared.links;
+            links.lock(|links| links.binary.on_interrupt());
+        }
+        #[cfg(feature = "uart-loopback")]
+        {
+            let mut loopback = ctx.shared.loopback;
+            loopback.lock(|loopback| loopback.on_interrupt());
+        }
+    }
+
+    /// Hand a received command over to [`handle_command`] or report an error to the link it has
+    /// been received on.
+    fn dispatch_command(result: Result<Command, ParseError>, respond: impl FnOnce(&[u8])) {
+        let mut response = Response::new();
+        match result {
+            Ok(command) => {
+                // taken here as the command might wait for other tasks, e.g. the display updates
+                let received_ms = now_ms();
+                if handle_command::spawn(command, received_ms).is_ok() {
+                    return;
+                }
+                write!(response, "ERR busy\r\n").ok();
+            }
+            Err(e) => {
+                log_warn!("received invalid command: {}", e.as_str());
+                write!(response, "ERR {}\r\n", e.as_str()).ok();
+            }
+        }
+        respond(response.as_bytes());
+    }
+
+    /// Redraw the displays, pended by [`periodic`] if any display is enabled.
+    ///
+    /// This is a hardware task bound to an otherwise unused interrupt so that the slow display
+    /// updates run at the lowest priority without blocking the software task dispatchers.
+    #[task(binds=EXTI3, priority = 1, local=[displays], shared=[ranging, latest_measurement, menu_view, narration])]
+    fn update_displays(mut ctx: update_displays::Context) {
+        let narration = ctx.shared.narration.lock(|narration| *narration);
+        let state = DisplayState {
+            measurement: ctx.shared.latest_measurement.lock(|latest| *latest),
+            ranging: ctx.shared.ranging.lock(|ranging| *ranging),
+            menu: ctx
+                .shared
+                .menu_view
+                .lock(|menu_view| *menu_view)
+                .or(narration),
+        };
+        ctx.local.displays.render(&state);
+    }
+
+    /// Run the data loggers, pended by [`publish`] & [`periodic`] if any data logger is enabled.
+    ///
+    /// Like [`update_displays`] this is a hardware task bound to an otherwise unused interrupt, so
+    /// that the slow storage doesn't block the software task dispatchers.
+    #[task(binds=EXTI4, priority = 1, local=[data_log], shared=[log_requests, links, clock])]
+    fn run_data_log(mut ctx: run_data_log::Context) {
+        while let Some(measurement) = ctx.shared.log_requests.lock(|requests| requests.pop()) {
+            ctx.local.data_log.log(&measurement);
+        }
+        let now_ms = now_ms();
+        let utc_ms = ctx.shared.clock.lock(|clock| clock.now_ms());
+        ctx.local.data_log.tick(now_ms, utc_ms);
+
+        let shutdown = ctx
+            .shared
+            .log_requests
+            .lock(|requests| requests.shutdown.take());
+        if let Some(shutdown) = shutdown {
+            let result = ctx.local.data_log.shutdown();
+            let response: &[u8] = match (shutdown, result) {
+                // nobody waits for a response
+                (Shutdown::Undervoltage, Err(_)) => {
+                    log_error!("failed to stop the data loggers");
+                    b""
+                }
+                (Shutdown::Undervoltage, Ok(())) => b"",
+                #[cfg(feature = "battery")]
+                (Shutdown::Battery, Err(_)) => {
+                    log_error!("failed to stop the data loggers");
+                    b""
+                }
+                #[cfg(feature = "battery")]
+                (Shutdown::Battery, Ok(())) => b"",
+                (_, Err(_)) => b"ERR storage failed\r\n",
+                (Shutdown::PowerOff, Ok(())) => b"OK halting\r\n",
+                (Shutdown::Reset, Ok(())) => b"OK resetting\r\n",
+            };
+            if !response.is_empty() {
+                ctx.shared.links.lock(|links| links.write(response));
+            }
+            // give the links time to send the response
+            let delay = u64::from(bootloader::RESET_DELAY_MS).millis();
+            match (shutdown, result) {
+                (Shutdown::PowerOff, Ok(())) => {
+                    halt::spawn_after(delay).or_count("halt");
+                }
+                (Shutdown::Reset, Ok(())) => {
+                    restart::spawn_after(delay).or_count("restart");
+                }
+                // the battery doesn't last any longer
+                #[cfg(feature = "battery")]
+                (Shutdown::Battery, _) => {
+                    halt::spawn_after(delay).or_count("halt");
+                }
+                _ => {}
+            }
+        }
+
+        #[cfg(feature = "littlefs")]
+        while let Some(file) = ctx.shared.log_requests.lock(|requests| requests.pop_file()) {
+            ctx.local.data_log.write_file(&file);
+        }
+
+        #[cfg(feature = "nor-flash")]
+        {
+            let command = ctx
+                .shared
+                .log_requests
+                .lock(|requests| requests.command.take());
+            if let Some(command) = command {
+                let error: Option<&[u8]> = if ctx.local.data_log.is_running() {
+                    Some(b"ERR busy\r\n")
+                } else if !ctx.local.data_log.start(command) {
+                    Some(b"ERR storage not available\r\n")
+                } else {
+                    None
+                };
+                if let Some(error) = error {
+                    ctx.shared.links.lock(|links| links.write(error));
+                }
+                // the virtual COM port is used exclusively by the transfer until it's done
+                #[cfg(feature = "xmodem")]
+                if error.is_none() && matches!(command, LogCommand::Transfer(_)) {
+                    ctx.shared.links.lock(|links| links.vcp.start_transfer());
+                }
+            }
+            #[cfg(feature = "xmodem")]
+            if ctx.local.data_log.is_transferring() {
+                let received = ctx.shared.links.lock(|links| links.vcp.transfer_receive());
+                let links = &mut ctx.shared.links;
+                let response = ctx
+                    .local
+                    .data_log
+                    .poll_transfer(now_ms, &received, |bytes| {
+                        links.lock(|links| links.vcp.transfer_write(bytes))
+                    });
+                match response {
+                    Some(response) => ctx.shared.links.lock(|links| {
+                        links.vcp.stop_transfer();
+                        links.write(response.as_bytes());
+                    }),
+                    None => {
+                        // fails if the handler has been pended meanwhile & already continues
+                        continue_data_log::spawn_after(
+                            u64::from(crate::data_log::OUTPUT_INTERVAL_MS).millis(),
+                        )
+                        .ok();
+                    }
+                }
+                return;
+            }
+            // send as many lines as fit into the transmit buffers, the rest follows later
+            while ctx.local.data_log.is_running() {
+                if ctx.shared.links.lock(|links| links.free_space()) < log_record::MAX_LINE_LEN {
+                    // fails if the handler has been pended meanwhile & already continues
+                    continue_data_log::spawn_after(
+                        u64::from(crate::data_log::OUTPUT_INTERVAL_MS).millis(),
+                    )
+                    .ok();
+                    break;
+                }
+                let line = ctx.local.data_log.next_line();
+                ctx.shared.links.lock(|links| links.write(line.as_bytes()));
+            }
+        }
+    }
+
+    /// Receive a firmware update, see [`crate::firmware_update`]: started by the `update` command &
+    /// continued until the transfer has finished.
+    ///
+    /// Like [`run_data_log`] this is a hardware task bound to an otherwise unused interrupt, so
+    /// that writing to the flash doesn't block the software task dispatchers.
+    #[cfg(feature = "firmware-update")]
+    #[task(binds=EXTI9_5, priority = 1, local=[firmware_update], shared=[links, flash, eeprom, watchdog, clock])]
+    fn run_firmware_update(ctx: run_firmware_update::Context) {
+        let run_firmware_update::SharedResources {
+            mut links,
+            mut flash,
+            mut eeprom,
+            mut watchdog,
+            mut clock,
+        } = ctx.shared;
+        let update = ctx.local.firmware_update;
+
+        if !update.is_running() {
+            let started = (&mut flash, &mut eeprom, &mut watchdog)
+                .lock(|flash, eeprom, watchdog| update.start(flash, eeprom.as_mut(), watchdog));
+            if let Err(e) = started {
+                log_error!("failed to start the firmware update: {}", DebugFormat(&e));
+                let mut response = Response::new();
+                crate::firmware_update::write_response(Err(e), &mut response).ok();
+                links.lock(|links| links.write(response.as_bytes()));
+                // slot B is only updated by the firmware in slot A
+                if e == crate::firmware_update::Error::InSlotB {
+                    clock.lock(|clock| clock.request_slot_a());
+                    restart::spawn_after(u64::from(bootloader::RESET_DELAY_MS).millis())
+                        .or_count("restart");
+                }
+                return;
+            }
+            // the virtual COM port is used exclusively by the transfer until it's done
+            links.lock(|links| links.vcp.start_transfer());
+        }
+        let now_ms = now_ms();
+        let received = links.lock(|links| links.vcp.transfer_receive());
+        let result = (&mut flash, &mut eeprom, &mut watchdog).lock(|flash, eeprom, watchdog| {
+            update.poll(
+                now_ms,
+                &received,
+                flash,
+                eeprom.as_mut(),
+                watchdog,
+                |bytes| links.lock(|links| links.vcp.transfer_write(bytes)),
+            )
+        });
+        match result {
+            Some(result) => {
+                let mut response = Response::new();
+                crate::firmware_update::write_response(result, &mut response).ok();
+                links.lock(|links| {
+                    links.vcp.stop_transfer();
+                    links.write(response.as_bytes());
+                });
+                // the new firmware boots next
+                if result.is_ok() {
+                    restart::spawn_after(u64::from(bootloader::RESET_DELAY_MS).millis())
+                        .or_count("restart");
+                }
+            }
+            None => {
+                // fails if the handler has been pended meanwhile (e.g. by the UART) & already
+                // continues
+                continue_firmware_update::spawn_after(
+                    u64::from(crate::firmware_update::POLL_INTERVAL_MS).millis(),
+                )
+                .ok();
+            }
+        }
+    }
+
+    /// Continue the running firmware update.
+    #[task]
+    fn continue_firmware_update(_: continue_firmware_update::Context) {
+        rtic::pend(pac::Interrupt::EXTI9_5);
+    }
+
+    /// Confirm that the new firmware is healthy once its trial is over, it boots from now on.
+    /// Otherwise the board resets & boots the previous one, which also happens if it hangs
+    /// meanwhile (the watchdog resets it), see [`crate::firmware_update`].
+    #[task(shared=[safe_mode, sensor_error, flash, eeprom, watchdog])]
+    fn confirm_firmware(ctx: confirm_firmware::Context) {
+        #[cfg(feature = "firmware-update")]
+        {
+            let confirm_firmware::SharedResources {
+                mut safe_mode,
+                mut sensor_error,
+                mut flash,
+                mut eeprom,
+                mut watchdog,
+            } = ctx.shared;
+            let healthy = !safe_mode.lock(|safe_mode| *safe_mode)
+                && !sensor_error.lock(|sensor_error| *sensor_error);
+            let confirmed = healthy
+                && (&mut flash, &mut eeprom, &mut watchdog).lock(|flash, eeprom, watchdog| {
+                    eeprom.as_mut().is_some_and(|eeprom| {
+                        crate::firmware_update::confirm(eeprom, flash, watchdog).is_ok()
+                    })
+                });
+            if !confirmed {
+                log_error!("the new firmware isn't healthy, rolling back");
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+        }
+        #[cfg(not(feature = "firmware-update"))]
+        let _ = ctx;
+    }
+
+    /// Reset the board, e.g. to boot a firmware update or for the `reset` command.
+    #[task]
+    fn restart(_: restart::Context) {
+        defmt::info!("resetting");
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+
+    /// Park the TOF sensor & halt until the next reset, see [`crate::shutdown`].
+    #[task(local = [tof_shutdown], shared = [clock, watchdog])]
+    fn halt(ctx: halt::Context) {
+        let tof_shutdown = ctx.local.tof_shutdown;
+        (ctx.shared.clock, ctx.shared.watchdog)
+            .lock(|clock, watchdog| crate::shutdown::halt(tof_shutdown, clock, watchdog));
+    }
+
+    /// Run the data loggers again, e.g. to continue a dump once the transmit buffers have been
+    /// emptied.
+    #[task]
+    fn continue_data_log(_: continue_data_log::Context) {
+        rtic::pend(pac::Interrupt::EXTI4);
+    }
+
+    /// Send the [`webhook`] payload of an event caused by the measurement, if they're enabled.
+    fn publish_webhook(
+        links: &mut impl rtic::Mutex<T = Links>,
+        event: &Event,
+        measurement: &Measurement,
+    ) {
+        if !crate::config::webhook::ENABLED {
+            return;
+        }
+        match webhook::payload(event, measurement, DeviceId::get()) {
+            Some(Ok(payload)) => links.lock(|links| links.publish_event(&payload)),
+            Some(Err(_)) => log_warn!("failed to format the webhook payload"),
+            None => {}
+        }
+    }
+
+    /// Store an event in the [`event_log`], stamped with the current time. Several events can be
+    /// raised by the same measurement.
+    #[task(capacity = 4, local = [event_log], shared = [flash, eeprom, watchdog, clock])]
+    fn log_event(ctx: log_event::Context, event: Event) {
+        let log_event::SharedResources {
+            mut flash,
+            mut eeprom,
+            mut watchdog,
+            mut clock,
+        } = ctx.shared;
+        let uptime_ms = now_ms();
+        let utc_ms = clock.lock(|clock| clock.now_ms());
+        let event_log = ctx.local.event_log;
+        let result =
+            (&mut flash, &mut eeprom, &mut watchdog).lock(|flash, eeprom, watchdog| match eeprom {
+                Some(eeprom) => event_log.log(eeprom, flash, watchdog, event, uptime_ms, utc_ms),
+                None => Ok(()),
+            });
+        match result {
+            Ok(()) => defmt::info!("event: {}", event),
+            Err(e) => {
+                log_error!("failed to log an event: {}", DebugFormat(&e));
+                blink_code::record(ErrorClass::Storage);
+            }
+        }
+    }
+
+    /// Publish a mark of the marker input at `timestamp_ms` & store it in the event log, see
+    /// [`crate::marker`].
+    #[task(capacity = 4, shared = [measurement_count, links, frame_format, clock])]
+    fn publish_marker(ctx: publish_marker::Context, mark: Mark, timestamp_ms: u32) {
+        #[cfg(feature = "marker-input")]
+        {
+            let mut ctx = ctx;
+            let seq = ctx.shared.measurement_count.lock(|count| *count);
+            let utc_ms = ctx.shared.clock.lock(|clock| clock.now_ms());
+            let format = ctx.shared.frame_format.lock(|format| *format);
+            match telemetry::marker_frame(timestamp_ms, seq, &mark, utc_ms, format) {
+                Ok(frame) => ctx.shared.links.lock(|links| links.publish_frame(&frame)),
+                Err(_) => log_warn!("failed to format a marker"),
+            }
+            log_event::spawn(Event::Marker(mark.rising)).or_count("log_event");
+        }
+        #[cfg(not(feature = "marker-input"))]
+        let _ = (ctx, mark, timestamp_ms);
+    }
+
+    /// Handle the timing of the outputs, only spawned if any output needs it.
+    #[task(shared = [outputs])]
+    fn tick_outputs(mut ctx: tick_outputs::Context) {
+        let now_ms = now_ms();
+        if let Some(event) = ctx.shared.outputs.lock(|outputs| outputs.tick(now_ms)) {
+            log_event::spawn(event).or_count("log_event");
+        }
+
+        tick_outputs::spawn_after(u64::from(Outputs::TICK_INTERVAL_MS).millis())
+            .or_count("tick_outputs");
+    }
+
+    /// Read the controls & execute the requested actions, only spawned if any control is enabled.
+    #[task(local = [controls], shared = [tof_sensor, ranging, pause, sensor_error, links, outputs, menu_view])]
+    fn poll_controls(ctx: poll_controls::Context) {
+        let poll_controls::SharedResources {
+            #[cfg_attr(not(feature = "menu"), allow(unused_mut, unused_variables))]
+            mut tof_sensor,
+            #[cfg_attr(not(feature = "menu"), allow(unused_mut, unused_variables))]
+            mut ranging,
+            #[cfg_attr(not(feature = "menu"), allow(unused_mut, unused_variables))]
+            mut pause,
+            #[cfg_attr(not(feature = "menu"), allow(unused_mut, unused_variables))]
+            mut sensor_error,
+            #[cfg_attr(
+                not(any(
+                    feature = "threshold-pot",
+                    all(feature = "menu", any(feature = "alarm-output", feature = "lora"))
+                )),
+                allow(unused_mut, unused_variables)
+            )]
+            mut links,
+            #[cfg_attr(
+                not(any(
+                    feature = "threshold-pot",
+                    all(feature = "menu", any(feature = "alarm-output", feature = "lora"))
+                )),
+                allow(unused_mut, unused_variables)
+            )]
+            mut outputs,
+            #[cfg_attr(not(feature = "menu"), allow(unused_mut, unused_variables))]
+            mut menu_view,
+        } = ctx.shared;
+
+        let now_ms = now_ms();
+        ctx.local.controls.poll(now_ms, |event| {
+            defmt::debug!("control event: {}", defmt::Debug2Format(&event));
+            match event {
+                #[cfg(any(
+                    feature = "threshold-pot",
+                    all(feature = "menu", any(feature = "alarm-output", feature = "lora"))
+                ))]
+                crate::controls::Event::AlarmThreshold(threshold_mm) => {
+                    outputs.lock(|outputs| outputs.set_alarm_threshold(threshold_mm));
+                    links.lock(|links| links.set_alarm_threshold(threshold_mm));
+                }
+                #[cfg(feature = "menu")]
+                crate::controls::Event::DistanceMode(mode) => {
+                    let result =
+                        reconfigure_tof(&mut tof_sensor, &mut ranging, &mut pause, |tof_sensor| {
+                            let settings = tof_sensor.settings()?;
+                            tof_sensor.configure(&TofSettings {
+                                distance_mode: mode,
+                                ..settings
+                            })
+                        });
+                    sensor_error.lock(|sensor_error| *sensor_error = result.is_err());
+                }
+                #[cfg(feature = "menu")]
+                crate::controls::Event::Rate(rate_hz) => {
+                    let period_ms = 1000 / rate_hz as u16;
+                    // the timing budget must not exceed the period, a longer one is more precise
+                    let timing_budget_ms = if period_ms < 100 { 50 } else { 100 };
+                    let result =
+                        reconfigure_tof(&mut tof_sensor, &mut ranging, &mut pause, |tof_sensor| {
+                            let settings = tof_sensor.settings()?;
+                            tof_sensor.configure(&TofSettings {
+                                timing_budget_ms,
+                                inter_measurement_ms: period_ms,
+                                ..settings
+                            })
+                        });
+                    sensor_error.lock(|sensor_error| *sensor_error = result.is_err());
+                }
+            }
+        });
+
+        #[cfg(feature = "menu")]
+        {
+            let view = ctx.local.controls.menu_view();
+            let changed = menu_view.lock(|menu_view| core::mem::replace(menu_view, view) != view);
+            if changed && Displays::ENABLED {
+                rtic::pend(pac::Interrupt::EXTI3);
+            }
+        }
+
+        poll_controls::spawn_after(u64::from(Controls::POLL_INTERVAL_MS).millis())
+            .or_count("poll_controls");
+    }
+
+    /// Switch the TOF sensor to the short distance mode while the ambient light is `high` & back to
+    /// the previous mode afterwards, only spawned if
+    /// [`crate::config::ambient::SHORT_MODE`] is enabled.
+    #[task(local = [mode_before_ambient], shared = [tof_sensor, ranging, pause, sensor_error])]
+    fn adapt_to_ambient(mut ctx: adapt_to_ambient::Context, high: bool) {
+        let mode_before_ambient = ctx.local.mode_before_ambient;
+        if high == mode_before_ambient.is_some() {
+            return;
+        }
+        let result = reconfigure_tof(
+            &mut ctx.shared.tof_sensor,
+            &mut ctx.shared.ranging,
+            &mut ctx.shared.pause,
+            |tof_sensor| {
+                let settings = tof_sensor.settings()?;
+                let distance_mode = if high {
+                    *mode_before_ambient = Some(settings.distance_mode);
+                    DistanceMode::Short
+                } else {
+                    mode_before_ambient.take().unwrap_or(settings.distance_mode)
+                };
+                tof_sensor.configure(&TofSettings {
+                    distance_mode,
+                    ..settings
+                })
+            },
+        );
+        if result.is_err() {
+            log_error!("failed to adapt the distance mode to the ambient light");
+        }
+        ctx.shared
+            .sensor_error
+            .lock(|sensor_error| *sensor_error = result.is_err());
+    }
+
+    /// Measure the battery voltage of the `battery` feature every
+    /// [`crate::battery::POLL_INTERVAL_MS`]: extend the inter-measurement period of the TOF sensor
+    /// while the battery is low & shut down once it's critical, see [`crate::battery`].
+    #[task(local = [battery, period_before_low], shared = [tof_sensor, ranging, pause, sensor_error, log_requests])]
+    fn monitor_battery(ctx: monitor_battery::Context) {
+        #[cfg(feature = "battery")]
+        {
+            use crate::battery::{Level, POLL_INTERVAL_MS};
+            let mut shared = ctx.shared;
+            let battery = ctx.local.battery;
+            let period_before_low = ctx.local.period_before_low;
+            let Some(level) = battery.poll() else {
+                monitor_battery::spawn_after(u64::from(POLL_INTERVAL_MS).millis())
+                    .or_count("monitor_battery");
+                return;
+            };
+            log_event::spawn(Event::Battery(level)).or_count("log_event");
+            let voltage_mv = battery.voltage_mv().unwrap_or(0);
+            if level == Level::Critical {
+                log_error!("battery critical ({} mV), shutting down", voltage_mv);
+                // the data loggers halt the board once they've been stopped
+                if DataLog::ENABLED {
+                    shared
+                        .log_requests
+                        .lock(|requests| requests.shutdown = Some(Shutdown::Battery));
+                    rtic::pend(pac::Interrupt::EXTI4);
+                } else {
+                    halt::spawn_after(u64::from(bootloader::RESET_DELAY_MS).millis())
+                        .or_count("halt");
+                }
+                return;
+            }
+            if level == Level::Low {
+                log_warn!(
+                    "battery low ({} mV), reducing the measurement rate",
+                    voltage_mv
+                );
+            } else {
+                log_info!("battery ok again ({} mV)", voltage_mv);
+            }
+            let result = reconfigure_tof(
+                &mut shared.tof_sensor,
+                &mut shared.ranging,
+                &mut shared.pause,
+                |tof_sensor| {
+                    let settings = tof_sensor.settings()?;
+                    let inter_measurement_ms = if level == Level::Low {
+                        *period_before_low = Some(settings.inter_measurement_ms);
+                        settings
+                            .inter_measurement_ms
+                            .max(crate::config::battery::LOW_INTER_MEASUREMENT_MS)
+                    } else {
+                        period_before_low
+                            .take()
+                            .unwrap_or(settings.inter_measurement_ms)
+                    };
+                    tof_sensor.configure(&TofSettings {
+                        inter_measurement_ms,
+                        ..settings
+                    })
+                },
+            );
+            if result.is_err() {
+                log_error!("failed to adapt the measurement rate to the battery");
+            }
+            shared
+                .sensor_error
+                .lock(|sensor_error| *sensor_error = result.is_err());
+            monitor_battery::spawn_after(u64::from(POLL_INTERVAL_MS).millis())
+                .or_count("monitor_battery");
+        }
+        #[cfg(not(feature = "battery"))]
+        let _ = ctx;
+    }
+
+    /// Let the TOF sensor wait for a target with its threshold so that the microcontroller can stop
+    /// (`arm`), or restore its previous settings once a target has been detected or the mode has
+    /// been left, see [`crate::low_power`].
+    #[task(shared = [tof_sensor, ranging, pause, sensor_error, app_mode, low_power, watchdog, watchdog_margin, clock])]
+    fn low_power_mode(ctx: low_power_mode::Context, arm: bool) {
+        let low_power_mode::SharedResources {
+            mut tof_sensor,
+            mut ranging,
+            mut pause,
+            mut sensor_error,
+            mut app_mode,
+            mut low_power,
+            mut watchdog,
+            mut watchdog_margin,
+            mut clock,
+        } = ctx.shared;
+
+        let enabled = app_mode.lock(|app_mode| *app_mode == AppMode::LowPower);
+        let result = if arm {
+            if !enabled
+                || !ranging.lock(|ranging| *ranging)
+                || low_power.lock(|low_power| low_power.is_armed())
+            {
+                return;
+            }
+            reconfigure_tof(&mut tof_sensor, &mut ranging, &mut pause, |tof_sensor| {
+                let settings = tof_sensor.settings()?;
+                tof_sensor.configure(&low_power::armed_settings(&settings))?;
+                tof_sensor.set_interrupt_threshold(Some(crate::config::app_mode::PRESENCE_MM))?;
+                low_power.lock(|low_power| low_power.arm(settings));
+                Ok(())
+            })
+        } else {
+            let settings = low_power.lock(|low_power| {
+                if !enabled {
+                    low_power.disable();
+                }
+                low_power.take_settings()
+            });
+            let Some(settings) = settings else { return };
+            reconfigure_tof(&mut tof_sensor, &mut ranging, &mut pause, |tof_sensor| {
+                tof_sensor.set_interrupt_threshold(None)?;
+                tof_sensor.configure(&settings)
+            })
+        };
+        if result.is_err() {
+            log_error!("failed to reconfigure the TOF sensor for the low-power mode");
+        }
+        sensor_error.lock(|sensor_error| *sensor_error = result.is_err());
+
+        // the periodic task doesn't feed the watchdog while the microcontroller is stopped
+        let armed = low_power.lock(|low_power| low_power.is_armed());
+        let timeout_ms = match armed {
+            true => low_power::WATCHDOG_TIMEOUT_MS,
+            false => crate::config::watchdog::TIMEOUT_MS,
+        };
+        watchdog.lock(|watchdog| {
+            watchdog.start(timeout_ms.millis());
+            watchdog.feed();
+        });
+        let now_ms = now_ms();
+        watchdog_margin
+            .lock(|margin| margin.restart(now_ms, crate::storage::erasures(), timeout_ms));
+        clock.lock(|clock| clock.set_wakeup(armed.then_some(low_power::WAKEUP_INTERVAL_S)));
+    }
+
+    /// Triggers whenever the supply voltage crosses the level of the PVD, the data loggers are
+    /// stopped once it's below, see [`crate::brown_out`].
+    #[task(binds = PVD, local = [supply_monitor], shared = [log_requests])]
+    fn supply_changed(mut ctx: supply_changed::Context) {
+        ctx.local.supply_monitor.clear_interrupt();
+        let low = ctx.local.supply_monitor.is_low();
+        if low {
+            log_error!("undervoltage, stopping the data loggers");
+            if DataLog::ENABLED {
+                // a pending `shutdown` or `reset` stops them as well
+                ctx.shared.log_requests.lock(|requests| {
+                    requests.shutdown.get_or_insert(Shutdown::Undervoltage);
+                });
+                rtic::pend(pac::Interrupt::EXTI4);
+            }
+        } else {
+            log_warn!("the supply voltage has recovered");
+        }
+        log_event::spawn(Event::Undervoltage(low)).or_count("log_event");
+    }
+
+    /// Wakes the microcontroller from the stop mode while the [`crate::low_power`] mode waits for a
+    /// target, to feed the watchdog.
+    #[task(binds = RTC_WKUP, shared = [clock, watchdog, watchdog_margin, task_watchdog])]
+    fn rtc_wakeup(mut ctx: rtc_wakeup::Context) {
+        ctx.shared.clock.lock(|clock| clock.clear_wakeup());
+        feed_checked(
+            &mut ctx.shared.watchdog,
+            &mut ctx.shared.task_watchdog,
+            &mut ctx.shared.watchdog_margin,
+        );
+    }
+
+    /// Feed the watchdog unless a task has missed its check-in, see [`crate::task_watchdog`].
+    fn feed_checked(
+        watchdog: &mut impl rtic::Mutex<T = IndependentWatchdog>,
+        task_watchdog: &mut impl rtic::Mutex<T = TaskWatchdog>,
+        watchdog_margin: &mut impl rtic::Mutex<T = WatchdogMargin>,
+    ) {
+        let now_ms = now_ms();
+        match task_watchdog.lock(|task_watchdog| task_watchdog.check(now_ms)) {
+            task_watchdog::Status::Alive => {}
+            task_watchdog::Status::Missed(task, late_ms) => {
+                log_error!(
+                    "the {} task is {} ms late, not feeding the watchdog anymore",
+                    task.name(),
+                    late_ms
+                );
+                return;
+            }
+            task_watchdog::Status::Failed => return,
+        }
+        watchdog.lock(|watchdog| watchdog.feed());
+        feed_audited(watchdog_margin);
+    }
+
+    /// Check the margin of a feed of the watchdog, see [`crate::watchdog_margin`].
+    fn feed_audited(watchdog_margin: &mut impl rtic::Mutex<T = WatchdogMargin>) {
+        let now_ms = now_ms();
+        let erasures = crate::storage::erasures();
+        let low = watchdog_margin.lock(|margin| {
+            let margin_ms = margin.feed(now_ms, erasures)?;
+            Some((margin_ms, margin.low()))
+        });
+        if let Some((margin_ms, low)) = low {
+            log_warn!(
+                "watchdog fed {} ms before its timeout ({} times since boot)",
+                margin_ms,
+                low
+            );
+        }
+    }
+
+    /// Apply the measurement settings & the thresholds of the `preset` to the TOF sensor.
+    fn apply_preset(
+        tof_sensor: &mut impl rtic::Mutex<T = TOFSensor>,
+        ranging: &mut impl rtic::Mutex<T = bool>,
+        pause: &mut impl rtic::Mutex<T = Pause>,
+        preset: Preset,
+    ) -> Result<(), TofError> {
+        reconfigure_tof(tof_sensor, ranging, pause, |tof_sensor| {
+            tof_sensor.configure(&preset.settings())?;
+            tof_sensor.set_thresholds(&preset.thresholds())
+        })
+    }
+
+    /// Change settings of the TOF sensor, which is only possible while it isn't ranging: the
+    /// acquisition is paused meanwhile.
+    fn reconfigure_tof(
+        tof_sensor: &mut impl rtic::Mutex<T = TOFSensor>,
+        ranging: &mut impl rtic::Mutex<T = bool>,
+        pause: &mut impl rtic::Mutex<T = Pause>,
+        configure: impl FnOnce(&mut TOFSensor) -> Result<(), TofError>,
+    ) -> Result<(), TofError> {
+        let reason = pause::Reason::Reconfigure;
+        pause_acquisition(tof_sensor, ranging, pause, reason)?;
+        let result = tof_sensor.lock(configure);
+        resume_acquisition(tof_sensor, ranging, pause, reason).and(result)
+    }
+
+    /// Pause the acquisition for the `reason`, the ranging is stopped unless it's already paused.
+    /// The filters & the statistics keep their state, see [`crate::pause`].
+    fn pause_acquisition(
+        tof_sensor: &mut impl rtic::Mutex<T = TOFSensor>,
+        ranging: &mut impl rtic::Mutex<T = bool>,
+        pause: &mut impl rtic::Mutex<T = Pause>,
+        reason: pause::Reason,
+    ) -> Result<(), TofError> {
+        if !pause.lock(|pause| pause.pause(reason)) || !ranging.lock(|ranging| *ranging) {
+            return Ok(());
+        }
+        let result = tof_sensor.lock(|tof_sensor| tof_sensor.stop());
+        if result.is_err() {
+            pause.lock(|pause| pause.resume(reason));
+        }
+        result
+    }
+
+    /// Resume the acquisition after the `reason`, the ranging is started again once no other
+    /// reason pauses it. The first measurements are discarded.
+    fn resume_acquisition(
+        tof_sensor: &mut impl rtic::Mutex<T = TOFSensor>,
+        ranging: &mut impl rtic::Mutex<T = bool>,
+        pause: &mut impl rtic::Mutex<T = Pause>,
+        reason: pause::Reason,
+    ) -> Result<(), TofError> {
+        if !pause.lock(|pause| pause.resume(reason)) || !ranging.lock(|ranging| *ranging) {
+            return Ok(());
+        }
+        tof_sensor.lock(|tof_sensor| tof_sensor.start())
+    }
+
+    /// Triggers on every edge of the user button, it's read once it has settled. The line is
+    /// shared with the pulses of the trigger input of the `trigger-input` feature, each of which
+    /// starts a single measurement while the acquisition is [`Acquisition::Triggered`], with the
+    /// interrupt of the GPIO expander of the `gpio-expander` feature & with the edges of the marker
+    /// input of the `marker-input` feature.
+    #[task(binds=EXTI15_10, local=[trigger_pin, marker_input], shared=[user_button, low_power, tof_sensor, ranging, acquisition, sensor_error, trigger, gpio_expander])]
+    fn user_button_edge(mut ctx: user_button_edge::Context) {
+        #[cfg(feature = "gpio-expander")]
+        ctx.shared.gpio_expander.lock(|expander| {
+            let Some(expander) = expander.as_mut() else {
+                return;
+            };
+            if !expander.take_interrupt() {
+                return;
+            }
+            match expander.read_switches() {
+                Ok(0) => {}
+                Ok(_) => defmt::info!(
+                    "configuration switches changed to {=u8:#b}, applied at the next boot",
+                    expander.switches()
+                ),
+                Err(e) => log_warn!(
+                    "failed to read the configuration switches: {}",
+                    DebugFormat(&e)
+                ),
+            }
+        });
+        #[cfg(feature = "trigger-input")]
+        if ctx.local.trigger_pin.take_pulse()
+            && ctx.shared.acquisition.lock(|acquisition| *acquisition) == Acquisition::Triggered
+        {
+            start_single_measurement(
+                &mut ctx.shared.tof_sensor,
+                &mut ctx.shared.ranging,
+                &mut ctx.shared.sensor_error,
+                &mut ctx.shared.trigger,
+            );
+        }
+        #[cfg(not(feature = "trigger-input"))]
+        let _ = ctx.local.trigger_pin;
+        #[cfg(feature = "marker-input")]
+        {
+            let now_ms = now_ms();
+            if let Some(mark) = ctx.local.marker_input.take_mark(now_ms) {
+                publish_marker::spawn(mark, now_ms).or_count("publish_marker");
+            }
+        }
+        #[cfg(not(feature = "marker-input"))]
+        let _ = ctx.local.marker_input;
+        if !ctx
+            .shared
+            .user_button
+            .lock(|user_button| user_button.take_interrupt())
+        {
+            return;
+        }
+        // the debouncing needs the timer, which stops together with the microcontroller
+        ctx.shared.low_power.lock(|low_power| low_power.hold());
+        // fails while the button is already being debounced
+        debounce_user_button::spawn_after(u64::from(crate::user_button::DEBOUNCE_MS).millis()).ok();
+    }
+
+    /// Start a single measurement on a pulse of the trigger input or the cadence, unless the sensor
+    /// is ranging or still busy with the previous one, see [`Trigger`].
+    fn start_single_measurement(
+        tof_sensor: &mut impl rtic::Mutex<T = TOFSensor>,
+        ranging: &mut impl rtic::Mutex<T = bool>,
+        sensor_error: &mut impl rtic::Mutex<T = bool>,
+        trigger: &mut impl rtic::Mutex<T = Trigger>,
+    ) {
+        let idle = !ranging.lock(|ranging| *ranging);
+        let started = trigger.lock(|trigger| {
+            let started = trigger.on_pulse(idle);
+            if !started {
+                defmt::warn!("missed a start ({} in total)", trigger.missed());
+            }
+            started
+        });
+        if started && tof_sensor.lock(|tof_sensor| tof_sensor.start()).is_err() {
+            trigger.lock(Trigger::abort);
+            sensor_error.lock(|sensor_error| *sensor_error = true);
+        }
+    }
+
+    /// Start the measurements of [`Acquisition::Timed`] at the cadence of TIM11, see
+    /// [`crate::cadence`], or those of the sensor of the next window if interleaved, see
+    /// [`crate::interleave`]. Pended by the `acquisition` command to start the cadence with the
+    /// inter-measurement period of the sensor, it stops once the acquisition has been switched.
+    #[task(binds=TIM1_TRG_COM_TIM11, local=[cadence_timer], shared=[tof_sensor, ranging, acquisition, sensor_error, trigger, interleaver, second_tof_start])]
+    fn cadence_tick(mut ctx: cadence_tick::Context) {
+        let now_us = monotonics::now().ticks() as u32;
+        let timer = ctx.local.cadence_timer;
+        let acquisition = ctx.shared.acquisition.lock(|acquisition| *acquisition);
+        if !acquisition.uses_cadence() {
+            timer.stop();
+            return;
+        }
+        if !timer.is_running() {
+            match ctx
+                .shared
+                .tof_sensor
+                .lock(|tof_sensor| tof_sensor.settings())
+            {
+                Ok(settings) => {
+                    let period_us = settings.inter_measurement_ms as u32 * 1_000;
+                    defmt::info!("cadence started: period {} us", period_us);
+                    ctx.shared.interleaver.lock(Interleaver::reset);
+                    timer.start(period_us, now_us);
+                }
+                Err(_) => {
+                    log_error!("failed to read the inter-measurement period of the TOF sensor");
+                    ctx.shared
+                        .sensor_error
+                        .lock(|sensor_error| *sensor_error = true);
+                }
+            }
+            return;
+        }
+        if !timer.on_interrupt(now_us) {
+            return;
+        }
+        let sensor = if acquisition.is_interleaved() {
+            let (sensor, overlap) = ctx.shared.interleaver.lock(Interleaver::next_window);
+            if overlap {
+                defmt::warn!(
+                    "the previous window overlaps with the one of sensor {}",
+                    sensor
+                );
+            }
+            sensor
+        } else {
+            0
+        };
+        if sensor == 0 {
+            start_single_measurement(
+                &mut ctx.shared.tof_sensor,
+                &mut ctx.shared.ranging,
+                &mut ctx.shared.sensor_error,
+                &mut ctx.shared.trigger,
+            );
+        } else {
+            ctx.shared.second_tof_start.lock(|start| *start = true);
+            rtic::pend(pac::Interrupt::EXTI2);
+        }
+    }
+
+    /// Read the user button once it has settled.
+    #[task(shared = [user_button])]
+    fn debounce_user_button(mut ctx: debounce_user_button::Context) {
+        let now_ms = now_ms();
+        let (event, clicks_pending) = ctx
+            .shared
+            .user_button
+            .lock(|user_button| (user_button.update(now_ms), user_button.clicks_pending()));
+        if let Some(event) = event {
+            handle_button_event::spawn(event).or_count("handle_button_event");
+        }
+        if clicks_pending {
+            // fails if the clicks are already being waited for
+            finish_user_button_clicks::spawn_after(
+                u64::from(crate::user_button::CLICK_GAP_MS).millis(),
+            )
+            .ok();
+        }
+    }
+
+    /// Report the clicks on the user button once no further click follows.
+    #[task(shared = [user_button])]
+    fn finish_user_button_clicks(mut ctx: finish_user_button_clicks::Context) {
+        let now_ms = now_ms();
+        let (event, clicks_pending) = ctx.shared.user_button.lock(|user_button| {
+            (
+                user_button.finish_clicks(now_ms),
+                user_button.clicks_pending(),
+            )
+        });
+        if let Some(event) = event {
+            handle_button_event::spawn(event).or_count("handle_button_event");
+        }
+        if clicks_pending {
+            // there has been another click in the meantime, fails if its debouncing has already
+            // scheduled the wait
+            finish_user_button_clicks::spawn_after(
+                u64::from(crate::user_button::CLICK_GAP_MS).millis(),
+            )
+            .ok();
+        }
+    }
+
+    /// Execute the action of a gesture on the user button: a short press toggles the ranging (or
+    /// clears the latched alarm, see [`crate::alarm_output`]), a double click cycles through the
+    /// application modes, a triple click switches the distance mode, four clicks apply the next
+    /// preset, a long press starts the offset calibration & a very long one enters the bootloader.
+    #[task(shared = [tof_sensor, ranging, pause, sensor_error, safe_mode, calibration, cal_target_mm, cal_wizard, app_mode, led_indication, preset, low_power, outputs])]
+    fn handle_button_event(ctx: handle_button_event::Context, event: ButtonEvent) {
+        let handle_button_event::SharedResources {
+            mut tof_sensor,
+            mut ranging,
+            mut pause,
+            mut sensor_error,
+            mut safe_mode,
+            mut calibration,
+            mut cal_target_mm,
+            mut cal_wizard,
+            mut app_mode,
+            mut led_indication,
+            mut preset,
+            mut low_power,
+            // only needed to clear the latched alarm
+            #[cfg_attr(not(feature = "alarm-output"), allow(unused_mut, unused_variables))]
+            mut outputs,
+        } = ctx.shared;
+
+        defmt::debug!("user button: {}", event);
+        low_power.lock(|low_power| low_power.release());
+        if calibration.lock(|calibration| calibration.is_some())
+            || cal_wizard
+                .lock(|wizard| wizard.as_ref().is_some_and(CalibrationWizard::is_measuring))
+        {
+            log_warn!("ignoring the user button during the calibration");
+            return;
+        }
+        // the bootloader is also the way out of a corrupt image
+        if !matches!(event, ButtonEvent::DoubleClick | ButtonEvent::VeryLongPress)
+            && safe_mode.lock(|safe_mode| *safe_mode)
+        {
+            log_warn!("the TOF sensor can't be used in safe mode");
+            return;
+        }
+        match event {
+            ButtonEvent::DoubleClick => {
+                let mode = app_mode.lock(|app_mode| {
+                    *app_mode = app_mode.next();
+                    *app_mode
+                });
+                defmt::info!("application mode: {}", mode);
+                led_indication.lock(|indication| *indication = Some(mode.number()));
+                store_app_mode::spawn(mode).or_count("store_app_mode");
+                low_power_mode::spawn(mode == AppMode::LowPower).or_count("low_power_mode");
+            }
+            ButtonEvent::TripleClick => {
+                let result = tof_sensor
+                    .lock(|tof_sensor| tof_sensor.settings())
+                    .and_then(|settings| {
+                        let mode = match settings.distance_mode {
+                            DistanceMode::Short => DistanceMode::Long,
+                            DistanceMode::Long => DistanceMode::Short,
+                        };
+                        reconfigure_tof(&mut tof_sensor, &mut ranging, &mut pause, |tof_sensor| {
+                            tof_sensor.configure(&TofSettings {
+                                distance_mode: mode,
+                                ..settings
+                            })
+                        })
+                        .map(|_| mode)
+                    });
+                match result {
+                    Ok(mode) => {
+                        defmt::info!("distance mode: {}", defmt::Debug2Format(&mode));
+                        let number = if mode == DistanceMode::Short { 1 } else { 2 };
+                        led_indication.lock(|indication| *indication = Some(number));
+                    }
+                    Err(_) => sensor_error.lock(|sensor_error| *sensor_error = true),
+                }
+            }
+            ButtonEvent::QuadrupleClick => {
+                let next = preset
+                    .lock(|preset| preset.map_or(Preset::IndoorShort, |preset| preset.next()));
+                match apply_preset(&mut tof_sensor, &mut ranging, &mut pause, next) {
+                    Ok(()) => {
+                        defmt::info!("preset: {}", next);
+                        preset.lock(|preset| *preset = Some(next));
+                        led_indication.lock(|indication| *indication = Some(next.number()));
+                    }
+                    Err(_) => sensor_error.lock(|sensor_error| *sensor_error = true),
+                }
+            }
+            ButtonEvent::Press => {
+                #[cfg(feature = "alarm-output")]
+                if let Some(cleared) = outputs.lock(|outputs| {
+                    let latched = outputs.alarm.is_latched();
+                    latched.then(|| outputs.alarm.clear())
+                }) {
+                    if cleared {
+                        defmt::info!("latched alarm cleared");
+                    } else {
+                        log_warn!("the alarm condition is still present");
+                    }
+                    return;
+                }
+                let start = !ranging.lock(|ranging| *ranging);
+                let result = tof_sensor.lock(|tof_sensor| {
+                    if start {
+                        tof_sensor.start()
+                    } else {
+                        tof_sensor.stop()
+                    }
+                });
+                match result {
+                    Ok(()) => {
+                        defmt::info!("ranging {}", if start { "started" } else { "stopped" });
+                        ranging.lock(|ranging| *ranging = start);
+                        if start {
+                            send_session_header::spawn().or_count("send_session_header");
+                        }
+                    }
+                    Err(_) => sensor_error.lock(|sensor_error| *sensor_error = true),
+                }
+            }
+            ButtonEvent::LongPress => {
+                let was_ranging = ranging.lock(|ranging| *ranging);
+                let result =
+                    tof_sensor.lock(|tof_sensor| tof_sensor.start_offset_calibration(was_ranging));
+                match result {
+                    Ok(previous_offset) => {
+                        ranging.lock(|ranging| *ranging = true);
+                        let target_mm = cal_target_mm.lock(|target_mm| *target_mm);
+                        calibration.lock(|calibration| {
+                            *calibration = Some(OffsetCalibration::new(
+                                was_ranging,
+                                previous_offset,
+                                target_mm,
+                            ))
+                        });
+                    }
+                    Err(_) => sensor_error.lock(|sensor_error| *sensor_error = true),
+                }
+            }
+            ButtonEvent::VeryLongPress => {
+                // the sensor keeps ranging across a reset of the microcontroller
+                tof_sensor.lock(|tof_sensor| tof_sensor.stop()).ok();
+                enter_bootloader::spawn().or_count("enter_bootloader");
+            }
+        }
+    }
+
+    /// Store the current calibration of the TOF sensor in the flash.
+    #[task(shared = [tof_sensor, sensor_error, flash, eeprom, watchdog, log_requests])]
+    fn save_calibration(ctx: save_calibration::Context) {
+        let save_calibration::SharedResources {
+            mut tof_sensor,
+            mut sensor_error,
+            mut flash,
+            mut eeprom,
+            mut watchdog,
+            // only needed for the copy on the file system
+            #[cfg_attr(not(feature = "littlefs"), allow(unused_mut, unused_variables))]
+            mut log_requests,
+        } = ctx.shared;
+
+        let calibration = tof_sensor.lock(|tof_sensor| tof_sensor.calibration());
+        let Ok(calibration) = calibration else {
+            sensor_error.lock(|sensor_error| *sensor_error = true);
+            return;
+        };
+        let result = (&mut flash, &mut eeprom, &mut watchdog).lock(|flash, eeprom, watchdog| {
+            eeprom
+                .as_mut()
+                .map(|eeprom| calibration_store::save(eeprom, flash, &calibration, watchdog))
+        });
+        if let Some(Err(e)) = result {
+            log_error!("failed to store the calibration: {}", DebugFormat(&e));
+            blink_code::record(ErrorClass::Storage);
+        }
+        #[cfg(feature = "littlefs")]
+        {
+            log_requests.lock(|requests| requests.write_file(FileWrite::calibration(&calibration)));
+            rtic::pend(pac::Interrupt::EXTI4);
+        }
+    }
+
+    /// Run the loopback self-test of the `uart-loopback` feature for the `selftest` command. The
+    /// test over the UART is continued by [`poll_self_test`], which sends the response.
+    #[task(shared = [loopback, links])]
+    fn self_test(ctx: self_test::Context, mode: LoopbackMode) {
+        #[cfg(feature = "uart-loopback")]
+        {
+            let self_test::SharedResources {
+                mut loopback,
+                mut links,
+            } = ctx.shared;
+            let report = match mode {
+                crate::loopback::Mode::Internal => crate::loopback::run_internal(),
+                crate::loopback::Mode::Uart => {
+                    let now_ms = now_ms();
+                    if loopback.lock(|loopback| loopback.start(now_ms)) {
+                        poll_self_test::spawn_after(
+                            u64::from(crate::loopback::POLL_INTERVAL_MS).millis(),
+                        )
+                        .or_count("poll_self_test");
+                    } else {
+                        links.lock(|links| links.write(b"ERR busy\r\n"));
+                    }
+                    return;
+                }
+            };
+            let mut response = Response::new();
+            report.write_response(&mut response).ok();
+            links.lock(|links| links.write(response.as_bytes()));
+        }
+        #[cfg(not(feature = "uart-loopback"))]
+        let _ = (ctx, mode);
+    }
+
+    /// Send the response to the `selftest` command once the test over the UART is done.
+    #[task(shared = [loopback, links])]
+    fn poll_self_test(ctx: poll_self_test::Context) {
+        #[cfg(feature = "uart-loopback")]
+        {
+            let poll_self_test::SharedResources {
+                mut loopback,
+                mut links,
+            } = ctx.shared;
+            let now_ms = now_ms();
+            match loopback.lock(|loopback| loopback.poll(now_ms)) {
+                Some(report) => {
+                    let mut response = Response::new();
+                    report.write_response(&mut response).ok();
+                    links.lock(|links| links.write(response.as_bytes()));
+                }
+                None => {
+                    poll_self_test::spawn_after(
+                        u64::from(crate::loopback::POLL_INTERVAL_MS).millis(),
+                    )
+                    .or_count("poll_self_test");
+                }
+            }
+        }
+        #[cfg(not(feature = "uart-loopback"))]
+        let _ = ctx;
+    }
+
+    /// Store the current settings in the EEPROM & send the response to the `save` command.
+    #[task(shared = [tof_sensor, sensor_error, links, outputs, frame_format, scaling, publish_interval_ms, change_filter, geofence, cal_target_mm, schedule, flash, eeprom, watchdog])]
+    fn save_settings(ctx: save_settings::Context) {
+        let save_settings::SharedResources {
+            mut tof_sensor,
+            mut sensor_error,
+            mut links,
+            #[cfg_attr(
+                not(any(
+                    feature = "alarm-output",
+                    feature = "lora",
+                    feature = "buzzer",
+                    feature = "motor-pid"
+                )),
+                allow(unused_mut, unused_variables)
+            )]
+            mut outputs,
+            mut frame_format,
+            mut scaling,
+            mut publish_interval_ms,
+            mut change_filter,
+            mut geofence,
+            mut cal_target_mm,
+            mut schedule,
+            mut flash,
+            mut eeprom,
+            mut watchdog,
+        } = ctx.shared;
+
+        let Ok(tof) = tof_sensor.lock(|tof_sensor| tof_sensor.settings()) else {
+            sensor_error.lock(|sensor_error| *sensor_error = true);
+            links.lock(|links| links.write(b"ERR sensor communication failed\r\n"));
+            return;
+        };
+        let settings = Settings {
+            tof: Some(tof),
+            frame_format: Some(frame_format.lock(|format| *format)),
+            scaling: Some(scaling.lock(|scaling| *scaling)),
+            zones: Some(geofence.lock(|geofence| *geofence.zones())),
+            publish_interval_ms: Some(publish_interval_ms.lock(|interval| interval.unwrap_or(0))),
+            change_delta_mm: Some(change_filter.lock(|filter| filter.delta_mm().unwrap_or(0))),
+            cal_target_mm: Some(cal_target_mm.lock(|target_mm| *target_mm)),
+            schedule: Some(schedule.lock(|schedule| *schedule)),
+            #[cfg(any(feature = "alarm-output", feature = "lora"))]
+            alarm_threshold_mm: outputs
+                .lock(|outputs| outputs.alarm_threshold_mm())
+                .or_else(|| links.lock(|links| links.alarm_threshold_mm())),
+            #[cfg(feature = "buzzer")]
+            buzzer_muted: Some(outputs.lock(|outputs| outputs.buzzer.muted())),
+            #[cfg(feature = "motor-pid")]
+            motor: Some(outputs.lock(|outputs| crate::settings::MotorSettings {
+                enabled: outputs.motor.enabled(),
+                target_mm: outputs.motor.target_mm(),
+                gains: outputs.motor.gains(),
+            })),
+        };
+
+        let result = (&mut flash, &mut eeprom, &mut watchdog).lock(|flash, eeprom, watchdog| {
+            eeprom
+                .as_mut()
+                .map(|eeprom| settings.store(eeprom, flash, watchdog))
+        });
+        let response: &[u8] = match result {
+            Some(Ok(())) => {
+                defmt::info!("stored settings: {}", defmt::Debug2Format(&settings));
+                b"OK\r\n"
+            }
+            Some(Err(e)) => {
+                log_error!("failed to store the settings: {}", DebugFormat(&e));
+                blink_code::record(ErrorClass::Storage);
+                b"ERR storing failed\r\n"
+            }
+            None => b"ERR storage not available\r\n",
+        };
+        links.lock(|links| links.write(response));
+    }
+
+    /// Execute a [`Command::Profile`] & send the response to all links.
+    #[task(shared = [tof_sensor, ranging, pause, sensor_error, links, profile, flash, eeprom, watchdog, log_requests])]
+    fn handle_profile_command(ctx: handle_profile_command::Context, command: ProfileCommand) {
+        let handle_profile_command::SharedResources {
+            mut tof_sensor,
+            mut ranging,
+            mut pause,
+            mut sensor_error,
+            mut links,
+            mut profile,
+            mut flash,
+            mut eeprom,
+            mut watchdog,
+            // only needed for the copy on the file system
+            #[cfg_attr(not(feature = "littlefs"), allow(unused_mut, unused_variables))]
+            mut log_requests,
+        } = ctx.shared;
+
+        let mut response = Response::new();
+        let result = match command {
+            ProfileCommand::Status => {
+                let name = profile.lock(|profile| profile.map_or("none", |profile| profile.name()));
+                write!(response, "OK profile={}\r\n", name).ok();
+                Ok(())
+            }
+            ProfileCommand::Save(target) => {
+                match tof_sensor.lock(|tof_sensor| tof_sensor.settings()) {
+                    Ok(settings) => {
+                        #[cfg(feature = "littlefs")]
+                        {
+                            log_requests.lock(|requests| {
+                                requests.write_file(FileWrite::profile(target, &settings))
+                            });
+                            rtic::pend(pac::Interrupt::EXTI4);
+                        }
+                        (&mut flash, &mut eeprom, &mut watchdog).lock(|flash, eeprom, watchdog| {
+                            match eeprom {
+                                Some(eeprom) => target
+                                    .save(&settings, eeprom, flash, watchdog)
+                                    .map_err(|_| "storing failed"),
+                                None => Err("storage not available"),
+                            }
+                        })
+                    }
+                    Err(_) => {
+                        sensor_error.lock(|sensor_error| *sensor_error = true);
+                        Err("sensor communication failed")
+                    }
+                }
+            }
+            ProfileCommand::Load(target) => {
+                let settings = (&mut flash, &mut eeprom)
+                    .lock(|flash, eeprom| target.settings(eeprom.as_ref(), flash));
+                match reconfigure_tof(&mut tof_sensor, &mut ranging, &mut pause, |tof_sensor| {
+                    tof_sensor.configure(&settings)
+                }) {
+                    Ok(()) => {
+                        defmt::info!("profile: {}", target);
+                        if target == Profile::Fast {
+                            check_fast_profile(&settings);
+                        }
+                        profile.lock(|profile| *profile = Some(target));
+                        // the profile is applied even if it can't be stored as the active one
+                        (&mut flash, &mut eeprom, &mut watchdog).lock(|flash, eeprom, watchdog| {
+                            match eeprom {
+                                Some(eeprom) => target
+                                    .activate(eeprom, flash, watchdog)
+                                    .map_err(|_| "storing failed"),
+                                None => Err("storage not available"),
+                            }
+                        })
+                    }
+                    Err(_) => {
+                        sensor_error.lock(|sensor_error| *sensor_error = true);
+                        Err("sensor communication failed")
+                    }
+                }
+            }
+        };
+        match result {
+            Ok(()) if response.is_empty() => write!(response, "OK\r\n"),
+            Ok(()) => Ok(()),
+            Err(error) => write!(response, "ERR {}\r\n", error),
+        }
+        .ok();
+        links.lock(|links| links.write(response.as_bytes()));
+    }
+
+    /// Execute a [`Command::Cal`] & send the response to all links, see
+    /// [`crate::calibration_wizard`]. The response to `cal next` is sent by [`send_cal_prompt`]
+    /// once the measurement is done.
+    #[task(shared = [tof_sensor, ranging, sensor_error, safe_mode, calibration, cal_target_mm, cal_wizard, links])]
+    fn handle_cal_command(ctx: handle_cal_command::Context, command: CalCommand) {
+        let handle_cal_command::SharedResources {
+            mut tof_sensor,
+            mut ranging,
+            mut sensor_error,
+            mut safe_mode,
+            mut calibration,
+            mut cal_target_mm,
+            mut cal_wizard,
+            mut links,
+        } = ctx.shared;
+
+        let target_mm = cal_target_mm.lock(|target_mm| *target_mm);
+        let step = cal_wizard.lock(|wizard| wizard.as_ref().map(|wizard| wizard.step()));
+        let busy = calibration.lock(|calibration| calibration.is_some());
+        let mut sensor_failed = |_| {
+            sensor_error.lock(|sensor_error| *sensor_error = true);
+            "sensor communication failed"
+        };
+        // the ranging state before the measurement which has been started by `cal next`
+        let mut measuring = None;
+        let mut response = Response::new();
+        let result = match (command, step) {
+            (CalCommand::Status, None) => {
+                write!(response, "OK cal step=none\r\n").ok();
+                Ok(())
+            }
+            (CalCommand::Status, Some(_)) => Ok(()),
+            (CalCommand::Target(None), _) => {
+                write!(response, "OK cal target_mm={}\r\n", target_mm).ok();
+                Ok(())
+            }
+            // the wizard prompts for the target it has been started with
+            (CalCommand::Target(Some(_)), Some(_)) => Err("calibration wizard running"),
+            (CalCommand::Target(Some(_)), None) if busy => Err("busy"),
+            (CalCommand::Target(Some(target_mm)), None) => {
+                cal_target_mm.lock(|cal_target_mm| *cal_target_mm = target_mm);
+                write!(response, "OK cal target_mm={}\r\n", target_mm).ok();
+                Ok(())
+            }
+            (_, Some(Step::Offset | Step::CrossTalk)) => Err("busy"),
+            (CalCommand::Wizard, _) if safe_mode.lock(|safe_mode| *safe_mode) => Err("safe mode"),
+            (CalCommand::Wizard, _) if busy => Err("busy"),
+            (CalCommand::Wizard, _) => {
+                // a restart keeps the calibration from before the first start
+                let previous = match cal_wizard
+                    .lock(|wizard| wizard.as_ref().map(|wizard| wizard.previous()))
+                {
+                    Some(previous) => Ok(previous),
+                    None => tof_sensor.lock(|tof_sensor| tof_sensor.calibration()),
+                };
+                previous.map_err(&mut sensor_failed).map(|previous| {
+                    cal_wizard
+                        .lock(|wizard| *wizard = Some(CalibrationWizard::new(previous, target_mm)))
+                })
+            }
+            (_, None) => Err("no calibration wizard"),
+            (CalCommand::Next, Some(Step::OffsetTarget)) if busy => Err("busy"),
+            (CalCommand::Next, Some(Step::OffsetTarget)) => {
+                let was_ranging = ranging.lock(|ranging| *ranging);
+                let started =
+                    tof_sensor.lock(|tof_sensor| tof_sensor.start_offset_calibration(was_ranging));
+                started.map_err(&mut sensor_failed).map(|previous_offset| {
+                    calibration.lock(|calibration| {
+                        *calibration = Some(OffsetCalibration::new(
+                            was_ranging,
+                            previous_offset,
+                            target_mm,
+                        ))
+                    });
+                    measuring = Some(was_ranging);
+                })
+            }
+            (CalCommand::Next, Some(Step::CrossTalkTarget)) => {
+                let was_ranging = ranging.lock(|ranging| *ranging);
+                let started = tof_sensor
+                    .lock(|tof_sensor| tof_sensor.start_cross_talk_calibration(was_ranging));
+                started
+                    .map_err(&mut sensor_failed)
+                    .map(|()| measuring = Some(was_ranging))
+            }
+            (CalCommand::Next, Some(_)) => Ok(()),
+            (CalCommand::Save, Some(Step::Review)) => {
+                cal_wizard.lock(|wizard| *wizard = None);
+                save_calibration::spawn().or_count("save_calibration");
+                write!(response, "OK cal saved\r\n").ok();
+                Ok(())
+            }
+            (CalCommand::Save, Some(_)) => Err("calibration not finished"),
+            (CalCommand::Discard, Some(_)) => {
+                let previous =
+                    cal_wizard.lock(|wizard| wizard.take().map(|wizard| wizard.previous()));
+                let restored = previous.map_or(Ok(()), |previous| {
+                    tof_sensor.lock(|tof_sensor| tof_sensor.set_calibration(&previous))
+                });
+                restored.map_err(&mut sensor_failed).map(|()| {
+                    write!(response, "OK cal discarded\r\n").ok();
+                })
+            }
+        };
+        // the response follows once the measurement is done
+        if let (Ok(()), Some(was_ranging)) = (result, measuring) {
+            ranging.lock(|ranging| *ranging = true);
+            cal_wizard.lock(|wizard| wizard.as_mut().map(|wizard| wizard.next(was_ranging)));
+            return;
+        }
+        match result {
+            Ok(()) if response.is_empty() => {
+                cal_wizard
+                    .lock(|wizard| wizard.as_ref().map(|wizard| wizard.prompt(&mut response)));
+            }
+            Ok(()) => {}
+            Err(error) => {
+                write!(response, "ERR {}\r\n", error).ok();
+            }
+        }
+        links.lock(|links| links.write(response.as_bytes()));
+    }
+
+    /// Send the next step of the calibration wizard once a measurement is done.
+    #[task(shared = [cal_wizard, links])]
+    fn send_cal_prompt(mut ctx: send_cal_prompt::Context) {
+        let mut response = Response::new();
+        ctx.shared
+            .cal_wizard
+            .lock(|wizard| wizard.as_ref().map(|wizard| wizard.prompt(&mut response)));
+        ctx.shared
+            .links
+            .lock(|links| links.write(response.as_bytes()));
+    }
+
+    /// Execute a [`Command::Capture`] & send the response to all links, see [`crate::capture`].
+    #[task(shared = [tof_sensor, ranging, pause, sensor_error, safe_mode, capture, frame_format, links])]
+    fn handle_capture_command(ctx: handle_capture_command::Context, command: CaptureCommand) {
+        let handle_capture_command::SharedResources {
+            mut tof_sensor,
+            mut ranging,
+            mut pause,
+            mut sensor_error,
+            mut safe_mode,
+            mut capture,
+            mut frame_format,
+            mut links,
+        } = ctx.shared;
+
+        let running =
+            capture.lock(|capture| capture.window().map(|window| (window.id, window.count)));
+        let mut response = Response::new();
+        match (command, running) {
+            (CaptureCommand::Status, None) => write!(response, "OK capture=none\r\n"),
+            (CaptureCommand::Status | CaptureCommand::Abort, Some((id, count))) => {
+                if command == CaptureCommand::Abort {
+                    finish_capture::spawn(id, Outcome::Aborted).or_count("finish_capture");
+                }
+                write!(response, "OK capture={} count={}\r\n", id, count)
+            }
+            (CaptureCommand::Abort, None) => write!(response, "ERR no capture\r\n"),
+            (CaptureCommand::Start(_), Some(_)) => write!(response, "ERR busy\r\n"),
+            (CaptureCommand::Start(_), None) if safe_mode.lock(|safe_mode| *safe_mode) => {
+                write!(response, "ERR safe mode\r\n")
+            }
+            (CaptureCommand::Start(request), None) => {
+                match start_capture(
+                    &mut tof_sensor,
+                    &mut ranging,
+                    &mut pause,
+                    &mut capture,
+                    request,
+                ) {
+                    Ok(id) => {
+                        let now_ms = now_ms();
+                        let format = frame_format.lock(|format| *format);
+                        match telemetry::capture_start_frame(now_ms, id, &request, format) {
+                            Ok(frame) => links.lock(|links| links.publish_frame(&frame)),
+                            Err(_) => log_warn!("failed to format the header of capture {}", id),
+                        }
+                        write!(response, "OK capture={}\r\n", id)
+                    }
+                    Err(_) => {
+                        sensor_error.lock(|sensor_error| *sensor_error = true);
+                        write!(response, "ERR sensor communication failed\r\n")
+                    }
+                }
+            }
+        }
+        .ok();
+        links.lock(|links| links.write(response.as_bytes()));
+    }
+
+    /// Apply the rate of the capture & start ranging if needed, then start the capture & its
+    /// timeout. Returns the ID of the capture.
+    fn start_capture(
+        tof_sensor: &mut impl rtic::Mutex<T = TOFSensor>,
+        ranging: &mut impl rtic::Mutex<T = bool>,
+        pause: &mut impl rtic::Mutex<T = Pause>,
+        capture: &mut impl rtic::Mutex<T = Capture>,
+        request: capture::Request,
+    ) -> Result<u16, TofError> {
+        let settings = tof_sensor.lock(|tof_sensor| tof_sensor.settings())?;
+        let restore = match request.rate_hz {
+            Some(rate_hz) => {
+                let capture_settings = capture::settings_for_rate(settings, rate_hz);
+                reconfigure_tof(tof_sensor, ranging, pause, |tof_sensor| {
+                    tof_sensor.configure(&capture_settings)
+                })?;
+                Some(settings)
+            }
+            None => None,
+        };
+        let was_ranging = ranging.lock(|ranging| *ranging);
+        if !was_ranging {
+            tof_sensor.lock(|tof_sensor| tof_sensor.start())?;
+            ranging.lock(|ranging| *ranging = true);
+            send_session_header::spawn().or_count("send_session_header");
+        }
+        let id = capture.lock(|capture| capture.start(request, was_ranging, restore));
+        defmt::info!("capture {}: {}", id, request);
+        let timeout_ms = request.timeout_ms(u32::from(settings.inter_measurement_ms));
+        finish_capture::spawn_after(u64::from(timeout_ms).millis(), id, Outcome::TimedOut)
+            .or_count("finish_capture");
+        Ok(id)
+    }
+
+    /// End the capture with the ID unless it has already ended: send the footer, stop the ranging
+    /// if it has been started for the capture & restore the rate. The capacity covers the timeouts
+    /// of the earlier captures, which may still be pending.
+    #[task(capacity = 4, shared = [tof_sensor, ranging, pause, sensor_error, capture, frame_format, links])]
+    fn finish_capture(ctx: finish_capture::Context, id: u16, outcome: Outcome) {
+        let finish_capture::SharedResources {
+            mut tof_sensor,
+            mut ranging,
+            mut pause,
+            mut sensor_error,
+            mut capture,
+            mut frame_format,
+            mut links,
+        } = ctx.shared;
+
+        let Some(window) = capture.lock(|capture| capture.finish(id)) else {
+            return;
+        };
+        defmt::info!(
+            "capture {}: {} after {} measurements",
+            id,
+            outcome,
+            window.count
+        );
+        let now_ms = now_ms();
+        let format = frame_format.lock(|format| *format);
+        match telemetry::capture_end_frame(now_ms, id, outcome, window.count, format) {
+            Ok(frame) => links.lock(|links| links.publish_frame(&frame)),
+            Err(_) => log_warn!("failed to format the footer of capture {}", id),
+        }
+
+        let stopped = if window.was_ranging {
+            Ok(())
+        } else {
+            tof_sensor
+                .lock(|tof_sensor| tof_sensor.stop())
+                .map(|_| ranging.lock(|ranging| *ranging = false))
+        };
+        let restored = stopped.and_then(|()| match window.restore {
+            Some(settings) => {
+                reconfigure_tof(&mut tof_sensor, &mut ranging, &mut pause, |tof_sensor| {
+                    tof_sensor.configure(&settings)
+                })
+            }
+            None => Ok(()),
+        });
+        if restored.is_err() {
+            sensor_error.lock(|sensor_error| *sensor_error = true);
+            log_warn!("failed to restore the sensor after capture {}", id);
+        }
+    }
+
+    /// Store the application mode in the EEPROM so that it's used again after a reset.
+    #[task(shared = [flash, eeprom, watchdog])]
+    fn store_app_mode(ctx: store_app_mode::Context, mode: AppMode) {
+        let store_app_mode::SharedResources {
+            mut flash,
+            mut eeprom,
+            mut watchdog,
+        } = ctx.shared;
+
+        let result = (&mut flash, &mut eeprom, &mut watchdog).lock(|flash, eeprom, watchdog| {
+            eeprom
+                .as_mut()
+                .map(|eeprom| mode.store(eeprom, flash, watchdog))
+        });
+        if let Some(Err(e)) = result {
+            log_error!("failed to store the application mode: {}", DebugFormat(&e));
+            blink_code::record(ErrorClass::Storage);
+        }
+    }
+
+    /// Show the state of the firmware on the status LED.
+    #[task(local = [status_led], shared = [ranging, sensor_error, safe_mode, calibration, cal_wizard, led_indication, gpio_expander])]
+    fn update_status_led(ctx: update_status_led::Context) {
+        let update_status_led::SharedResources {
+            mut ranging,
+            mut sensor_error,
+            mut safe_mode,
+            mut calibration,
+            mut cal_wizard,
+            mut led_indication,
+            #[cfg_attr(not(feature = "gpio-expander"), allow(unused_mut, unused_variables))]
+            mut gpio_expander,
+        } = ctx.shared;
+
+        let safe_mode = safe_mode.lock(|safe_mode| *safe_mode);
+        let calibrating = calibration.lock(|calibration| calibration.is_some())
+            || cal_wizard
+                .lock(|wizard| wizard.as_ref().is_some_and(CalibrationWizard::is_measuring));
+        let sensor_error = sensor_error.lock(|sensor_error| *sensor_error);
+        let ranging = ranging.lock(|ranging| *ranging);
+        // the blink code of the latest error replaces the patterns of the failed states
+        let failed =
+            |pattern| blink_code::last().map_or(pattern, |class| Pattern::Code(class.blinks()));
+        let pattern = if safe_mode {
+            failed(Pattern::Solid)
+        } else if calibrating {
+            Pattern::SlowBlink
+        } else if sensor_error {
+            failed(Pattern::FastBlink)
+        } else if ranging {
+            Pattern::Heartbeat
+        } else {
+            Pattern::Off
+        };
+
+        // the LEDs on the GPIO expander show each state on its own
+        #[cfg(feature = "gpio-expander")]
+        gpio_expander.lock(|expander| {
+            use crate::gpio_expander::{
+                LED_CALIBRATION, LED_RANGING, LED_SAFE_MODE, LED_SENSOR_ERROR,
+            };
+            let Some(expander) = expander else { return };
+            let leds = [
+                (ranging, LED_RANGING),
+                (sensor_error, LED_SENSOR_ERROR),
+                (safe_mode, LED_SAFE_MODE),
+                (calibrating, LED_CALIBRATION),
+            ]
+            .iter()
+            .filter(|(on, _)| *on)
+            .fold(0, |leds, (_, led)| leds | led);
+            if let Err(e) = expander.set_leds(leds) {
+                defmt::warn!(
+                    "failed to switch the status LEDs: {}",
+                    defmt::Debug2Format(&e)
+                );
+            }
+        });
+
+        let now_ms = now_ms();
+        if let Some(count) = led_indication.lock(|indication| indication.take()) {
+            ctx.local.status_led.indicate(count, now_ms);
+        }
+        ctx.local.status_led.set_pattern(pattern, now_ms);
+        ctx.local.status_led.tick(now_ms);
+
+        update_status_led::spawn_after(50_u64.millis()).or_count("update_status_led");
+    }
+
+    /// Check the TOF sensor & escalate its failures, see [`crate::sensor_supervisor`].
+    #[task(shared = [tof_sensor, ranging, sensor_error, safe_mode, last_read_ms, sensor_supervisor, outputs, flash, eeprom, clock, task_watchdog])]
+    fn supervise_sensor(ctx: supervise_sensor::Context) {
+        let supervise_sensor::SharedResources {
+            mut tof_sensor,
+            mut ranging,
+            mut sensor_error,
+            mut safe_mode,
+            mut last_read_ms,
+            mut sensor_supervisor,
+            mut outputs,
+            mut flash,
+            mut eeprom,
+            mut clock,
+            mut task_watchdog,
+        } = ctx.shared;
+        if safe_mode.lock(|safe_mode| *safe_mode) {
+            task_watchdog.lock(|task_watchdog| task_watchdog.release(Task::Acquisition));
+            return;
+        }
+
+        let now_ms = now_ms();
+        task_watchdog.lock(|task_watchdog| task_watchdog.check_in(Task::Acquisition, now_ms));
+        let last_read_ms = last_read_ms.lock(|last_read_ms| *last_read_ms);
+        let action = sensor_supervisor.lock(|supervisor| {
+            supervisor.check(now_ms, ranging.lock(|ranging| *ranging), last_read_ms)
+        });
+        if matches!(
+            action,
+            Some(Action::Reinit | Action::Reset | Action::SafeMode)
+        ) {
+            // don't leave the outputs at the last valid measurement
+            outputs.lock(|outputs| outputs.failsafe());
+        }
+        match action {
+            Some(Action::Reinit) => {
+                let (calibration, settings) = (&mut flash, &mut eeprom)
+                    .lock(|flash, eeprom| stored_tof_setup(eeprom.as_ref(), flash));
+                let result = tof_sensor.lock(|tof_sensor| tof_sensor.setup(calibration, settings));
+                sensor_error.lock(|sensor_error| *sensor_error = result.is_err());
+                if result.is_err() {
+                    blink_code::record(ErrorClass::SensorInit);
+                }
+            }
+            Some(Action::Reset) => {
+                let resets = sensor_supervisor.lock(|supervisor| supervisor.next_resets());
+                clock.lock(|clock| clock.set_sensor_resets(resets));
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+            Some(Action::SafeMode) => {
+                tof_sensor.lock(|tof_sensor| tof_sensor.stop().ok());
+                ranging.lock(|ranging| *ranging = false);
+                safe_mode.lock(|safe_mode| *safe_mode = true);
+                task_watchdog.lock(|task_watchdog| task_watchdog.release(Task::Acquisition));
+                return;
+            }
+            Some(Action::Stable) => clock.lock(|clock| clock.set_sensor_resets(0)),
+            None => {}
+        }
+
+        supervise_sensor::spawn_after(u64::from(sensor_supervisor::CHECK_INTERVAL_MS).millis())
+            .or_count("supervise_sensor");
+    }
+
+    /// Send the health report to all links which accept commands.
+    #[task(local = [health_monitor], shared = [ranging, measurement_count, measurements, sensor_error, safe_mode, links, frame_format, clock, sensor_supervisor, interrupt_guard, task_watchdog])]
+    fn report_health(ctx: report_health::Context) {
+        let report_health::SharedResources {
+            mut ranging,
+            mut measurement_count,
+            measurements,
+            mut sensor_error,
+            mut safe_mode,
+            mut links,
+            mut frame_format,
+            mut clock,
+            mut sensor_supervisor,
+            mut interrupt_guard,
+            mut task_watchdog,
+        } = ctx.shared;
+
+        let now_ms = now_ms();
+        task_watchdog.lock(|task_watchdog| task_watchdog.check_in(Task::Telemetry, now_ms));
+        let mut health = Health {
+            timestamp_ms: now_ms,
+            utc_ms: clock.lock(|clock| clock.now_ms()),
+            ranging: ranging.lock(|ranging| *ranging),
+            measurements: measurement_count.lock(|count| *count),
+            sensor_error: sensor_error.lock(|sensor_error| *sensor_error),
+            safe_mode: safe_mode.lock(|safe_mode| *safe_mode),
+            stale: sensor_supervisor.lock(|supervisor| supervisor.is_lost()),
+            reinits: sensor_supervisor.lock(|supervisor| supervisor.reinits()),
+            suppressed_interrupts: interrupt_guard.lock(|guard| guard.suppressed()),
+            queue_overflows: measurements.overflows(),
+            #[cfg(feature = "power")]
+            power: None,
+        };
+        ctx.local.health_monitor.sample(&mut health);
+        match telemetry::health_frame(&health, frame_format.lock(|format| *format)) {
+            Ok(frame) => links.lock(|links| links.write(frame.as_bytes())),
+            Err(_) => log_warn!("failed to format the health report"),
+        }
+
+        report_health::spawn_after(u64::from(crate::config::health::INTERVAL_S).secs())
+            .or_count("report_health");
+    }
+
+    /// Send a keepalive frame to all links if the [`crate::change_filter`] hasn't published any
+    /// measurement for [`crate::config::change::KEEPALIVE_S`] in the streaming mode.
+    #[task(shared = [app_mode, change_filter, links, frame_format, clock, task_watchdog])]
+    fn send_keepalive(mut ctx: send_keepalive::Context) {
+        let now_ms = now_ms();
+        ctx.shared
+            .task_watchdog
+            .lock(|task_watchdog| task_watchdog.check_in(Task::Processing, now_ms));
+        let streaming = ctx
+            .shared
+            .app_mode
+            .lock(|app_mode| *app_mode == AppMode::Streaming);
+        let seq = ctx
+            .shared
+            .change_filter
+            .lock(|filter| filter.keepalive(now_ms, crate::config::change::KEEPALIVE_S * 1000));
+        if let (true, Some(seq)) = (streaming, seq) {
+            let utc_ms = ctx.shared.clock.lock(|clock| clock.now_ms());
+            let format = ctx.shared.frame_format.lock(|format| *format);
+            match telemetry::keepalive_frame(now_ms, seq, utc_ms, format) {
+                Ok(frame) => ctx.shared.links.lock(|links| links.publish_frame(&frame)),
+                Err(_) => log_warn!("failed to format the keepalive"),
+            }
+        }
+
+        send_keepalive::spawn_after(u64::from(change_filter::CHECK_INTERVAL_MS).millis())
+            .or_count("send_keepalive");
+    }
+
+    /// Send the header of a telemetry session (which identifies the firmware) to all links, at boot
+    /// & whenever the ranging is started. The warm-up of the TOF sensor is restarted as well.
+    #[task(shared = [links, frame_format, app_mode, warm_up])]
+    fn send_session_header(mut ctx: send_session_header::Context) {
+        let now_ms = now_ms();
+        ctx.shared.warm_up.lock(|warm_up| warm_up.restart(now_ms));
+        let format = ctx.shared.frame_format.lock(|format| *format);
+        let app_mode = ctx.shared.app_mode.lock(|app_mode| *app_mode);
+        match telemetry::session_frame(format, app_mode) {
+            Ok(frame) => ctx.shared.links.lock(|links| links.publish_frame(&frame)),
+            Err(_) => log_warn!("failed to format the session header"),
+        }
+    }
+
+    /// Reset into the bootloader in the system memory, see [`crate::bootloader`].
+    #[task(shared = [clock])]
+    fn enter_bootloader(mut ctx: enter_bootloader::Context) {
+        ctx.shared.clock.lock(|clock| clock.request_bootloader());
+        bootloader::reset();
+    }
+
+    /// Start a check of the drift of the calibration, see [`crate::drift`].
+    #[task(shared = [drift_monitor])]
+    fn check_drift(mut ctx: check_drift::Context) {
+        if ctx.shared.drift_monitor.lock(|monitor| monitor.start()) {
+            defmt::info!("checking the drift of the calibration");
+        }
+        check_drift::spawn_after(u64::from(crate::config::drift::INTERVAL_S).secs())
+            .or_count("check_drift");
+    }
+
+    /// Log a summary line with the uptime, the measurements, the rejected, the gated & the
+    /// discarded ones & the reinits of the TOF sensor as a heartbeat (see [`crate::log_backend`]).
+    #[task(shared = [measurement_count, rejected_count, range_gate, pause, sensor_supervisor, interrupt_guard])]
+    fn log_summary(ctx: log_summary::Context) {
+        let log_summary::SharedResources {
+            mut measurement_count,
+            mut rejected_count,
+            mut range_gate,
+            mut pause,
+            mut sensor_supervisor,
+            mut interrupt_guard,
+        } = ctx.shared;
+        let (duplicates, duplicate_percent) =
+            interrupt_guard.lock(|guard| (guard.duplicates(), guard.duplicate_percent()));
+        log_info!(
+            "summary: uptime={}s measurements={} rejected={} gated={} discarded={} reinits={} i2c_timeouts={} duplicate_interrupts={} ({}%)",
+            uptime_s(),
+            measurement_count.lock(|count| *count),
+            rejected_count.lock(|count| *count),
+            range_gate.lock(|gate| gate.gated()),
+            pause.lock(|pause| pause.discarded()),
+            sensor_supervisor.lock(|supervisor| supervisor.reinits()),
+            i2c_guard::timeouts(),
+            duplicates,
+            duplicate_percent
+        );
+        log_summary::spawn_after(u64::from(crate::config::summary::INTERVAL_S).secs())
+            .or_count("log_summary");
+    }
+
+    /// Execute the requests of a debugger, see [`crate::control_block`].
+    #[task(shared = [measurement_count, rejected_count, measurements, sensor_supervisor, interrupt_guard, tof_sensor, sensor_error, safe_mode, flash, eeprom])]
+    fn poll_control_block(ctx: poll_control_block::Context) {
+        let poll_control_block::SharedResources {
+            mut measurement_count,
+            mut rejected_count,
+            measurements,
+            mut sensor_supervisor,
+            mut interrupt_guard,
+            mut tof_sensor,
+            mut sensor_error,
+            mut safe_mode,
+            mut flash,
+            mut eeprom,
+        } = ctx.shared;
+
+        if let Some(request) = CONTROL_BLOCK.take() {
+            defmt::info!("control block: {}", request);
+            let status = match request {
+                Request::DumpStats => {
+                    defmt::info!(
+                        "stats: uptime={}s measurements={} rejected={} reinits={} suppressed={} overflows={} log_dropped={}",
+                        uptime_s(),
+                        measurement_count.lock(|count| *count),
+                        rejected_count.lock(|count| *count),
+                        sensor_supervisor.lock(|supervisor| supervisor.reinits()),
+                        interrupt_guard.lock(|guard| guard.suppressed()),
+                        measurements.overflows(),
+                        log_backend::dropped()
+                    );
+                    Status::Done
+                }
+                Request::SetLogLevel(level) => {
+                    log_backend::set_min_level(level);
+                    Status::Done
+                }
+                // the sensor isn't operated at all in the safe mode
+                Request::Reinit if safe_mode.lock(|safe_mode| *safe_mode) => Status::Failed,
+                Request::Reinit => {
+                    let (calibration, settings) = (&mut flash, &mut eeprom)
+                        .lock(|flash, eeprom| stored_tof_setup(eeprom.as_ref(), flash));
+                    let result =
+                        tof_sensor.lock(|tof_sensor| tof_sensor.setup(calibration, settings));
+                    sensor_error.lock(|sensor_error| *sensor_error = result.is_err());
+                    if result.is_err() {
+                        log_error!("failed to set up the TOF sensor again");
+                        blink_code::record(ErrorClass::SensorInit);
+                        Status::Failed
+                    } else {
+                        Status::Done
+                    }
+                }
+            };
+            CONTROL_BLOCK.finish(status);
+        }
+
+        poll_control_block::spawn_after(u64::from(control_block::POLL_INTERVAL_MS).millis())
+            .or_count("poll_control_block");
+    }
+
+    /// Send the statistics of the measurements since the last rollup to all links.
+    #[task(shared = [tof_sensor, ranging, links, frame_format, clock, rollup])]
+    fn report_rollup(mut ctx: report_rollup::Context) {
+        let now_ms = now_ms();
+        let utc_ms = ctx.shared.clock.lock(|clock| clock.now_ms());
+        let period_ms = if ctx.shared.ranging.lock(|ranging| *ranging) {
+            ctx.shared
+                .tof_sensor
+                .lock(|tof_sensor| tof_sensor.settings())
+                .ok()
+                .map(|settings| settings.inter_measurement_ms)
+        } else {
+            None
+        };
+        let (rollup, registers) = ctx.shared.rollup.lock(|rollup| {
+            let taken = rollup.take(now_ms, utc_ms, period_ms);
+            (taken, rollup.backup(now_ms))
+        });
+        ctx.shared.clock.lock(|clock| clock.set_rollup(registers));
+        if rollup.rate_ok == Some(false) {
+            log_warn!(
+                "measurement rate of {} mHz instead of every {} ms",
+                rollup.rate_mhz,
+                DebugFormat(&period_ms)
+            );
+        }
+        let format = ctx.shared.frame_format.lock(|format| *format);
+        match telemetry::rollup_frame(&rollup, format) {
+            Ok(frame) => ctx.shared.links.lock(|links| links.publish_frame(&frame)),
+            Err(_) => log_warn!("failed to format the rollup"),
+        }
+
+        report_rollup::spawn_after(u64::from(crate::config::rollup::INTERVAL_S).secs())
+            .or_count("report_rollup");
+    }
+
+    /// Feed the watchdog to avoid hardware reset (as long as the tasks check in), handle timeouts
+    /// of the links & send the queued messages of the [`crate::log_backend`].
+    #[task(priority=1, shared=[watchdog, watchdog_margin, task_watchdog, links, clock, frame_format])]
+    fn periodic(mut ctx: periodic::Context) {
         defmt::trace!("feeding the watchdog!");
-        ctx.local.watchdog.feed();
+        feed_checked(
+            &mut ctx.shared.watchdog,
+            &mut ctx.shared.task_watchdog,
+            &mut ctx.shared.watchdog_margin,
+        );
+
+        let now_ms = now_ms();
+        ctx.shared.clock.lock(|clock| clock.set_uptime(uptime_s()));
+        let format = ctx.shared.frame_format.lock(|format| *format);
+        ctx.shared.links.lock(|links| {
+            links.tick(now_ms);
+            while let Some((level, message)) = log_backend::pop() {
+                if let Ok(frame) = telemetry::log_frame(now_ms, level, &message, format) {
+                    links.write(frame.as_bytes());
+                }
+            }
+        });
+
+        if Displays::ENABLED {
+            rtic::pend(pac::Interrupt::EXTI3);
+        }
+        if DataLog::ENABLED {
+            rtic::pend(pac::Interrupt::EXTI4);
+        }
 
-        periodic::spawn_after(200.millis()).ok();
+        if !periodic::spawn_after(u64::from(crate::config::watchdog::FEED_INTERVAL_MS).millis())
+            .or_count("periodic")
+        {
+            // nothing would feed the watchdog anymore, reset right away instead of waiting for it
+            log_error!("failed to reschedule the watchdog feeding, resetting");
+            cortex_m::peripheral::SCB::sys_reset();
+        }
     }
 }
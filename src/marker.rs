@@ -0,0 +1,123 @@
+//! The marker input of the `marker-input` feature on `PC12` (EXTI15_10, shared with the user
+//! button), with which an experimenter marks external occurrences (e.g. an object placed or
+//! removed) while collecting data: each edge is published as a marker frame between the
+//! measurements (see [`crate::telemetry::marker_frame`]) & stored in the [`crate::event_log`].
+//!
+//! The input is pulled down & active high, e.g. for a push button to 3.3 V or the output of other
+//! equipment. The edges are debounced: an edge within [`config::DEBOUNCE_MS`] of the previous one
+//! is ignored, as is one which doesn't change the level.
+
+use crate::config::marker as config;
+use stm32f4xx_hal::gpio::{Edge, ExtiPin, Input, PC12};
+use stm32f4xx_hal::pac::EXTI;
+use stm32f4xx_hal::syscfg::SysCfg;
+
+#[cfg(feature = "menu")]
+compile_error!("the features `marker-input` and `menu` can't be combined as both use PC12");
+
+/// An edge of the marker input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Mark {
+    /// Number of the mark since boot, the rising & the falling edge of a pulse share it.
+    pub number: u32,
+    /// Whether the input has become active.
+    pub rising: bool,
+}
+
+impl Mark {
+    pub fn edge(&self) -> &'static str {
+        if self.rising {
+            "rise"
+        } else {
+            "fall"
+        }
+    }
+}
+
+/// The debouncing of the marker input.
+#[derive(Debug, Default)]
+pub struct Marker {
+    active: bool,
+    /// Time of the previous edge, `None` before the first one.
+    changed_ms: Option<u32>,
+    marks: u32,
+}
+
+impl Marker {
+    /// Handle an edge at `now_ms` after which the input is `active`, returns the mark unless it's
+    /// bouncing.
+    pub fn on_edge(&mut self, now_ms: u32, active: bool) -> Option<Mark> {
+        let bouncing = self
+            .changed_ms
+            .is_some_and(|changed_ms| now_ms.wrapping_sub(changed_ms) < config::DEBOUNCE_MS);
+        if bouncing || active == self.active {
+            return None;
+        }
+        self.active = active;
+        self.changed_ms = Some(now_ms);
+        if active {
+            self.marks = self.marks.wrapping_add(1);
+        }
+        Some(Mark {
+            number: self.marks,
+            rising: active,
+        })
+    }
+}
+
+/// The pin of the marker input (pulled down) with its debouncing.
+pub struct MarkerInput {
+    pin: PC12<Input>,
+    marker: Marker,
+}
+
+impl MarkerInput {
+    pub fn new(pin: PC12<Input>, syscfg: &mut SysCfg, exti: &mut EXTI) -> Self {
+        let mut pin = pin;
+        pin.make_interrupt_source(syscfg);
+        pin.enable_interrupt(exti);
+        pin.trigger_on_edge(exti, Edge::RisingFalling);
+        Self {
+            pin,
+            marker: Marker::default(),
+        }
+    }
+
+    /// The mark if an edge at `now_ms` has raised the interrupt (which is acknowledged), unless
+    /// it's bouncing.
+    pub fn take_mark(&mut self, now_ms: u32) -> Option<Mark> {
+        if !self.pin.check_interrupt() {
+            return None;
+        }
+        self.pin.clear_interrupt_pending_bit();
+        self.marker.on_edge(now_ms, self.pin.is_high())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debounces_the_edges() {
+        let mut marker = Marker::default();
+        let rise = marker.on_edge(1_000, true);
+        assert_eq!(
+            rise,
+            Some(Mark {
+                number: 1,
+                rising: true
+            })
+        );
+        // bouncing & repeated levels are ignored
+        assert_eq!(marker.on_edge(1_000 + config::DEBOUNCE_MS - 1, false), None);
+        assert_eq!(marker.on_edge(2_000 + config::DEBOUNCE_MS, true), None);
+        let fall = marker.on_edge(3_000 + config::DEBOUNCE_MS, false);
+        assert_eq!(
+            fall.map(|mark| (mark.number, mark.edge())),
+            Some((1, "fall"))
+        );
+        let rise = marker.on_edge(4_000 + config::DEBOUNCE_MS, true);
+        assert_eq!(rise.map(|mark| mark.number), Some(2));
+    }
+}
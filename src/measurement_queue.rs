@@ -0,0 +1,128 @@
+//! The hand-off of the measurements from the data ready interrupt to the `publish` task: a
+//! bounded lock-free queue, so that neither side has to wait for the other, with an explicit
+//! policy for when the task lags behind & the queue is full.
+//!
+//! The policy is set with [`crate::config::measurement_queue::POLICY`]: dropping the oldest
+//! measurement keeps the outputs up to date with the latest distance, dropping the newest one keeps
+//! the sequence without gaps until the queue is full. The dropped measurements are counted per
+//! policy & reported in the health report.
+
+use crate::telemetry::Measurement;
+use core::sync::atomic::{AtomicU32, Ordering};
+use heapless::mpmc::MpMcQueue;
+
+/// Number of measurements which can wait for the task, a power of 2.
+pub const QUEUE_LEN: usize = crate::config::rt::MEASUREMENT_QUEUE_LEN;
+
+/// What happens to a measurement which doesn't fit into the full queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued measurement to make room for the new one.
+    DropOldest,
+    /// Drop the new measurement.
+    DropNewest,
+}
+
+/// Number of dropped measurements since boot, for each policy.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Overflows {
+    pub oldest: u32,
+    pub newest: u32,
+}
+
+pub struct BoundedQueue<T, const N: usize> {
+    queue: MpMcQueue<T, N>,
+    policy: OverflowPolicy,
+    dropped_oldest: AtomicU32,
+    dropped_newest: AtomicU32,
+}
+
+/// The queue of the measurements, with the configured policy.
+pub type MeasurementQueue = BoundedQueue<Measurement, QUEUE_LEN>;
+
+impl<T, const N: usize> BoundedQueue<T, N> {
+    pub const fn new(policy: OverflowPolicy) -> Self {
+        Self {
+            queue: MpMcQueue::new(),
+            policy,
+            dropped_oldest: AtomicU32::new(0),
+            dropped_newest: AtomicU32::new(0),
+        }
+    }
+
+    /// Queue an item, returns whether one has been dropped.
+    pub fn push(&self, item: T) -> bool {
+        let Err(item) = self.queue.enqueue(item) else {
+            return false;
+        };
+        match self.policy {
+            OverflowPolicy::DropOldest => {
+                // the consumer may have made room in the meantime
+                if self.queue.dequeue().is_some() {
+                    self.dropped_oldest.fetch_add(1, Ordering::Relaxed);
+                }
+                if self.queue.enqueue(item).is_err() {
+                    self.dropped_newest.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            OverflowPolicy::DropNewest => {
+                self.dropped_newest.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        true
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.queue.dequeue()
+    }
+
+    pub fn overflows(&self) -> Overflows {
+        Overflows {
+            oldest: self.dropped_oldest.load(Ordering::Relaxed),
+            newest: self.dropped_newest.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(policy: OverflowPolicy) -> BoundedQueue<u32, 4> {
+        let queue = BoundedQueue::new(policy);
+        for item in 0..6 {
+            queue.push(item);
+        }
+        queue
+    }
+
+    fn drain(queue: &BoundedQueue<u32, 4>) -> Vec<u32> {
+        core::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_latest_items() {
+        let queue = fill(OverflowPolicy::DropOldest);
+        assert_eq!(drain(&queue), [2, 3, 4, 5]);
+        assert_eq!(
+            queue.overflows(),
+            Overflows {
+                oldest: 2,
+                newest: 0
+            }
+        );
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_first_items() {
+        let queue = fill(OverflowPolicy::DropNewest);
+        assert_eq!(drain(&queue), [0, 1, 2, 3]);
+        assert_eq!(
+            queue.overflows(),
+            Overflows {
+                oldest: 0,
+                newest: 2
+            }
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! A small on-device menu, operated with the rotary encoder & shown on the displays, to change
+//! settings without a host.
+//!
+//! Pressing the button opens the menu, turning the encoder selects an item & pressing the button
+//! again starts editing it. The edited value is applied with another press. The menu closes after
+//! the `Exit` item has been selected or after a while without any input, discarding the edit.
+
+use crate::controls::Event;
+use crate::display::{MenuValue, MenuView};
+use crate::rotary::RotaryEvent;
+use vl53l1x_uld::DistanceMode;
+
+/// The menu closes if there's no input for this time.
+const TIMEOUT_MS: u32 = 10_000;
+/// Measurement rates which can be selected.
+pub const RATES_HZ: [u8; 5] = [1, 2, 5, 10, 20];
+/// Change of the threshold per detent of the encoder.
+#[cfg(any(feature = "alarm-output", feature = "lora"))]
+const THRESHOLD_STEP_MM: u16 = 10;
+/// Maximum threshold which can be set, this is the maximum range of the sensor.
+#[cfg(any(feature = "alarm-output", feature = "lora"))]
+const THRESHOLD_MAX_MM: u16 = 4_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Item {
+    DistanceMode,
+    #[cfg(any(feature = "alarm-output", feature = "lora"))]
+    Threshold,
+    Rate,
+    Exit,
+}
+
+const ITEMS: &[Item] = &[
+    Item::DistanceMode,
+    #[cfg(any(feature = "alarm-output", feature = "lora"))]
+    Item::Threshold,
+    Item::Rate,
+    Item::Exit,
+];
+
+/// The settings which can be changed in the menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Settings {
+    distance_mode: DistanceMode,
+    #[cfg(any(feature = "alarm-output", feature = "lora"))]
+    threshold_mm: u16,
+    /// Index into [`RATES_HZ`].
+    rate: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Closed,
+    /// Selecting the item at this index of [`ITEMS`].
+    Browsing(usize),
+    /// Editing the item at this index of [`ITEMS`].
+    Editing(usize),
+}
+
+pub struct Menu {
+    state: State,
+    settings: Settings,
+    /// The settings including the value which is currently being edited.
+    edited: Settings,
+    last_input_ms: u32,
+}
+
+impl Menu {
+    /// Create the menu with the settings which are active at boot, i.e. the stored ones if there
+    /// are any.
+    pub fn new(stored: &crate::settings::Settings) -> Self {
+        // the defaults of the sensor & of the configuration
+        let settings = Settings {
+            distance_mode: stored
+                .tof
+                .map_or(DistanceMode::Long, |tof| tof.distance_mode),
+            #[cfg(feature = "alarm-output")]
+            threshold_mm: stored
+                .alarm_threshold_mm
+                .unwrap_or(crate::config::alarm_output::MAX_MM),
+            #[cfg(all(feature = "lora", not(feature = "alarm-output")))]
+            threshold_mm: stored
+                .alarm_threshold_mm
+                .unwrap_or(crate::config::lora::ALARM_THRESHOLD_MM),
+            rate: stored
+                .tof
+                .and_then(|tof| {
+                    RATES_HZ
+                        .iter()
+                        .position(|&rate| 1000 / rate as u16 == tof.inter_measurement_ms)
+                })
+                .unwrap_or(3),
+        };
+        Self {
+            state: State::Closed,
+            settings,
+            edited: settings,
+            last_input_ms: 0,
+        }
+    }
+
+    /// Handle an input of the rotary encoder. Returns the setting which should be applied.
+    pub fn handle(&mut self, event: RotaryEvent, now_ms: u32) -> Option<Event> {
+        self.last_input_ms = now_ms;
+        match (self.state, event) {
+            (State::Closed, RotaryEvent::Press) => self.state = State::Browsing(0),
+            (State::Closed, RotaryEvent::Turn(_)) => {}
+            (State::Browsing(index), RotaryEvent::Turn(direction)) => {
+                let index = (index as isize + direction as isize).rem_euclid(ITEMS.len() as isize);
+                self.state = State::Browsing(index as usize);
+            }
+            (State::Browsing(index), RotaryEvent::Press) => {
+                if ITEMS[index] == Item::Exit {
+                    self.state = State::Closed;
+                } else {
+                    self.edited = self.settings;
+                    self.state = State::Editing(index);
+                }
+            }
+            (State::Editing(index), RotaryEvent::Turn(direction)) => {
+                self.adjust(ITEMS[index], direction)
+            }
+            (State::Editing(index), RotaryEvent::Press) => {
+                self.state = State::Browsing(index);
+                if self.edited != self.settings {
+                    self.settings = self.edited;
+                    return Some(self.event(ITEMS[index]));
+                }
+            }
+        }
+        None
+    }
+
+    /// Close the menu after the timeout. Must be called periodically.
+    pub fn tick(&mut self, now_ms: u32) {
+        if self.state != State::Closed && now_ms.wrapping_sub(self.last_input_ms) >= TIMEOUT_MS {
+            defmt::debug!("menu timed out");
+            self.state = State::Closed;
+        }
+    }
+
+    /// What should be shown on the displays, `None` while the menu is closed.
+    pub fn view(&self) -> Option<MenuView> {
+        let (index, editing, settings) = match self.state {
+            State::Closed => return None,
+            State::Browsing(index) => (index, false, &self.settings),
+            State::Editing(index) => (index, true, &self.edited),
+        };
+        let (label, value) = match ITEMS[index] {
+            Item::DistanceMode => (
+                "Mode",
+                MenuValue::Text(match settings.distance_mode {
+                    DistanceMode::Short => "short",
+                    DistanceMode::Long => "long",
+                }),
+            ),
+            #[cfg(any(feature = "alarm-output", feature = "lora"))]
+            Item::Threshold => ("Threshold", MenuValue::Number(settings.threshold_mm, "mm")),
+            Item::Rate => (
+                "Rate",
+                MenuValue::Number(RATES_HZ[settings.rate] as u16, "Hz"),
+            ),
+            Item::Exit => ("Exit", MenuValue::Empty),
+        };
+        Some(MenuView {
+            label,
+            value,
+            editing,
+        })
+    }
+
+    fn adjust(&mut self, item: Item, direction: i8) {
+        let settings = &mut self.edited;
+        match item {
+            Item::DistanceMode => {
+                settings.distance_mode = match settings.distance_mode {
+                    DistanceMode::Short => DistanceMode::Long,
+                    DistanceMode::Long => DistanceMode::Short,
+                }
+            }
+            #[cfg(any(feature = "alarm-output", feature = "lora"))]
+            Item::Threshold => {
+                settings.threshold_mm = if direction > 0 {
+                    (settings.threshold_mm + THRESHOLD_STEP_MM).min(THRESHOLD_MAX_MM)
+                } else {
+                    settings.threshold_mm.saturating_sub(THRESHOLD_STEP_MM)
+                }
+            }
+            Item::Rate => {
+                settings.rate = if direction > 0 {
+                    (settings.rate + 1).min(RATES_HZ.len() - 1)
+                } else {
+                    settings.rate.saturating_sub(1)
+                }
+            }
+            Item::Exit => {}
+        }
+    }
+
+    fn event(&self, item: Item) -> Event {
+        match item {
+            Item::DistanceMode => Event::DistanceMode(self.settings.distance_mode),
+            #[cfg(any(feature = "alarm-output", feature = "lora"))]
+            Item::Threshold => Event::AlarmThreshold(self.settings.threshold_mm),
+            Item::Rate => Event::Rate(RATES_HZ[self.settings.rate]),
+            Item::Exit => unreachable!("the exit item can't be edited"),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! Exposition of the counters & gauges of the firmware with the `metrics` command, in a text format
+//! like the one of Prometheus so that a scraper on the host only has to forward the lines.
+//!
+//! Each metric is a line `<name> <value>` (the counters end with `_total`, the dropped
+//! measurements are labelled with the policy which dropped them), the response ends with the
+//! `OK` line. The metrics are the same as in the health report (see [`crate::health`]) plus the
+//! uptime, the CPU load (see [`crate::cpu_load`]), which is left out until it has been measured,
+//! the frames dropped by the UART links (see [`crate::uart`]) & the margin of the watchdog feeds
+//! (see [`crate::watchdog_margin`]).
+
+use crate::telemetry::Health;
+use core::fmt;
+
+/// Write the metrics of the `health` report, the `cpu_load_percent`, the `dropped_frames` & the
+/// lowest margin of the `watchdog` with the number of feeds below the minimum.
+pub fn write(
+    out: &mut impl fmt::Write,
+    health: &Health,
+    cpu_load_percent: Option<u8>,
+    dropped_frames: u32,
+    watchdog: (Option<u32>, u32),
+) -> fmt::Result {
+    write!(
+        out,
+        "uptime_seconds {}.{:03}\r\n",
+        health.timestamp_ms / 1_000,
+        health.timestamp_ms % 1_000
+    )?;
+    if let Some(load) = cpu_load_percent {
+        write!(out, "cpu_load_percent {}\r\n", load)?;
+    }
+    write!(out, "ranging {}\r\n", health.ranging as u8)?;
+    write!(out, "measurements_total {}\r\n", health.measurements)?;
+    write!(out, "sensor_error {}\r\n", health.sensor_error as u8)?;
+    write!(out, "safe_mode {}\r\n", health.safe_mode as u8)?;
+    write!(out, "sensor_stale {}\r\n", health.stale as u8)?;
+    write!(out, "sensor_reinits_total {}\r\n", health.reinits)?;
+    write!(
+        out,
+        "suppressed_interrupts_total {}\r\n",
+        health.suppressed_interrupts
+    )?;
+    let overflows = health.queue_overflows;
+    for (policy, dropped) in [("oldest", overflows.oldest), ("newest", overflows.newest)] {
+        write!(
+            out,
+            "dropped_measurements_total{{policy=\"{}\"}} {}\r\n",
+            policy, dropped
+        )?;
+    }
+    write!(out, "dropped_frames_total {}\r\n", dropped_frames)?;
+    let (lowest_margin_ms, low_margins) = watchdog;
+    if let Some(margin_ms) = lowest_margin_ms {
+        write!(out, "watchdog_lowest_margin_ms {}\r\n", margin_ms)?;
+    }
+    write!(out, "watchdog_low_margins_total {}\r\n", low_margins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Response;
+    use crate::measurement_queue::Overflows;
+
+    fn health(value: u32) -> Health {
+        Health {
+            timestamp_ms: value,
+            utc_ms: None,
+            ranging: true,
+            measurements: value,
+            sensor_error: false,
+            safe_mode: false,
+            stale: false,
+            reinits: value as u8,
+            suppressed_interrupts: value,
+            queue_overflows: Overflows {
+                oldest: value,
+                newest: value,
+            },
+            #[cfg(feature = "power")]
+            power: None,
+        }
+    }
+
+    #[test]
+    fn metrics_lines() {
+        let mut response = Response::new();
+        write(&mut response, &health(1_234), Some(7), 3, (None, 2)).unwrap();
+        let lines: Vec<_> = response.lines().collect();
+        assert_eq!(lines[0], "uptime_seconds 1.234");
+        assert_eq!(lines[1], "cpu_load_percent 7");
+        assert_eq!(lines[3], "measurements_total 1234");
+        assert_eq!(
+            lines[lines.len() - 3],
+            "dropped_measurements_total{policy=\"newest\"} 1234"
+        );
+        assert_eq!(lines[lines.len() - 2], "dropped_frames_total 3");
+        assert_eq!(lines.last(), Some(&"watchdog_low_margins_total 2"));
+    }
+
+    #[test]
+    fn largest_values_fit_into_a_response() {
+        let mut response = Response::new();
+        let watchdog = (Some(u32::MAX), u32::MAX);
+        write(
+            &mut response,
+            &health(u32::MAX),
+            Some(100),
+            u32::MAX,
+            watchdog,
+        )
+        .unwrap();
+        assert!(response.push_str("OK\r\n").is_ok());
+    }
+}
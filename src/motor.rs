@@ -0,0 +1,134 @@
+//! Keeps a configurable distance to an obstacle by driving a motor (e.g. of a fan or a cart) with
+//! a PID controller.
+//!
+//! The motor is driven by a PWM signal on `PA1` (TIM5 CH2) and the direction on `PA4` (high =
+//! towards the obstacle) for an H-bridge. For motors which can only run in one direction (see
+//! [`crate::config::motor`]) the motor only runs while the obstacle is too far away.
+//!
+//! The controller is disabled at boot and the motor is stopped for invalid measurements or if no
+//! measurement has been received for a while.
+
+use crate::config::motor as config;
+use crate::pid::{Gains, Pid};
+use crate::telemetry::Measurement;
+use stm32f4xx_hal::gpio::{Output, PA4};
+use stm32f4xx_hal::pac::TIM5;
+use stm32f4xx_hal::timer::PwmChannel;
+use vl53l1x_uld::RangeStatus;
+
+/// Output range of the controller, in per mille of the full duty cycle.
+const OUTPUT_LIMIT: f32 = 1000.0;
+
+pub struct Motor {
+    pwm: PwmChannel<TIM5, 1>,
+    direction: PA4<Output>,
+    pid: Pid,
+    enabled: bool,
+    /// Time of the last measurement handed to the controller.
+    last_update_ms: Option<u32>,
+}
+
+impl Motor {
+    pub fn new(mut pwm: PwmChannel<TIM5, 1>, direction: PA4<Output>) -> Self {
+        pwm.set_duty(0);
+        pwm.enable();
+        Self {
+            pwm,
+            direction,
+            pid: Pid::new(config::GAINS, config::TARGET_MM as f32, OUTPUT_LIMIT),
+            enabled: false,
+            last_update_ms: None,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn target_mm(&self) -> u16 {
+        self.pid.setpoint() as u16
+    }
+
+    pub fn gains(&self) -> Gains {
+        self.pid.gains()
+    }
+
+    /// Enable or disable the controller, the motor is stopped while it is disabled.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        defmt::info!(
+            "distance hold {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        self.enabled = enabled;
+        self.stop();
+    }
+
+    /// Set the distance to keep.
+    pub fn set_target(&mut self, target_mm: u16) {
+        defmt::info!("distance hold target: {} mm", target_mm);
+        self.pid.set_setpoint(target_mm as f32);
+    }
+
+    pub fn set_gains(&mut self, gains: Gains) {
+        defmt::info!("distance hold gains: {}", gains);
+        self.pid.set_gains(gains);
+    }
+
+    /// Run the controller with a new measurement.
+    pub fn update(&mut self, measurement: &Measurement) {
+        if !self.enabled {
+            return;
+        }
+        if measurement.status != RangeStatus::Valid {
+            self.stop();
+            return;
+        }
+        let dt_s = match self.last_update_ms {
+            Some(last) => measurement.timestamp_ms.wrapping_sub(last) as f32 / 1000.0,
+            None => 0.0,
+        };
+        self.last_update_ms = Some(measurement.timestamp_ms);
+        // the output of the controller is negative while the obstacle is too far away
+        let output = -self.pid.update(measurement.distance_mm as f32, dt_s);
+        self.drive(output);
+    }
+
+    /// Stop the motor as the TOF sensor is lost, the controller starts again with the next
+    /// measurement.
+    pub fn failsafe(&mut self) {
+        if self.enabled {
+            self.stop();
+        }
+    }
+
+    /// Stop the motor if the measurements stopped coming in. Must be called periodically.
+    pub fn tick(&mut self, now_ms: u32) {
+        if let Some(last) = self.last_update_ms {
+            if now_ms.wrapping_sub(last) > config::TIMEOUT_MS {
+                defmt::warn!("no measurements for distance hold, stopping motor");
+                self.stop();
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        self.pid.reset();
+        self.last_update_ms = None;
+        self.drive(0.0);
+    }
+
+    /// Drive the motor, `output` is in per mille of the full speed with positive values moving
+    /// towards the obstacle.
+    fn drive(&mut self, output: f32) {
+        let output = if config::REVERSIBLE {
+            output
+        } else {
+            output.max(0.0)
+        };
+        self.direction.set_state((output >= 0.0).into());
+        let max_duty = self.pwm.get_max_duty() as f32;
+        let magnitude = if output < 0.0 { -output } else { output };
+        self.pwm
+            .set_duty((magnitude * max_duty / OUTPUT_LIMIT) as u16);
+    }
+}
@@ -0,0 +1,349 @@
+//! MQTT-SN client on the virtual COM port, meant to be bridged into an MQTT broker by a host-side
+//! MQTT-SN gateway (e.g. the Eclipse Paho gateway with its serial transport).
+//!
+//! The MQTT-SN packets are sent as-is, back to back; each packet starts with its length which is
+//! used to delimit it. Only the 1 byte length encoding is supported, longer packets are dropped.
+//!
+//! After connecting to the gateway the topics are registered and the command topic is subscribed.
+//! Afterwards all telemetry frames are published to the distance topic and all command responses
+//! to the event topic (both with QoS 0). Payloads published to the command topic are handled like
+//! command lines received on the other links.
+
+use crate::command::{self, Command, ParseError};
+use crate::config::mqtt_sn as config;
+use crate::sink::Sink;
+use crate::telemetry::Measurement;
+use crate::uart::BufferedUart;
+use stm32f4xx_hal::pac::USART2;
+
+/// Maximum size of a single packet, the largest one with the 1 byte length encoding.
+const MAX_PACKET_LEN: usize = 255;
+
+/// Time to wait for a response of the gateway.
+const RESPONSE_TIMEOUT_MS: u32 = 2_000;
+/// Time to wait after a failure before reconnecting.
+const RETRY_DELAY_MS: u32 = 5_000;
+/// A partially received packet is discarded if it isn't completed within this time.
+const RECEIVE_TIMEOUT_MS: u32 = 100;
+
+const PROTOCOL_ID: u8 = 0x01;
+const FLAG_CLEAN_SESSION: u8 = 0x04;
+const RETURN_CODE_ACCEPTED: u8 = 0x00;
+
+type Packet = heapless::Vec<u8, MAX_PACKET_LEN>;
+
+mod msg_type {
+    pub const CONNECT: u8 = 0x04;
+    pub const CONNACK: u8 = 0x05;
+    pub const REGISTER: u8 = 0x0A;
+    pub const REGACK: u8 = 0x0B;
+    pub const PUBLISH: u8 = 0x0C;
+    pub const SUBSCRIBE: u8 = 0x12;
+    pub const SUBACK: u8 = 0x13;
+    pub const PINGREQ: u8 = 0x16;
+    pub const PINGRESP: u8 = 0x17;
+    pub const DISCONNECT: u8 = 0x18;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum State {
+    /// Waiting before (re-)connecting to the gateway.
+    Disconnected,
+    Connecting,
+    RegisteringDistanceTopic,
+    RegisteringEventTopic,
+    SubscribingCommandTopic,
+    /// Connected, all topics are known.
+    Ready,
+}
+
+pub struct MqttSnLink {
+    uart: BufferedUart<USART2>,
+    state: State,
+    /// Time at which the current state has been entered.
+    state_since_ms: u32,
+    /// Time of the last call to [`Self::tick`].
+    now_ms: u32,
+    rx_packet: Packet,
+    /// Time at which the first byte of `rx_packet` has been received.
+    rx_since_ms: u32,
+    /// Id of the last message which expects an acknowledgement.
+    msg_id: u16,
+    distance_topic_id: u16,
+    event_topic_id: u16,
+    command_topic_id: u16,
+    /// Time at which the last packet has been sent, used for the keep alive.
+    last_tx_ms: u32,
+    /// Time at which a `PINGREQ` has been sent which hasn't been answered yet.
+    ping_sent_ms: Option<u32>,
+}
+
+impl MqttSnLink {
+    pub fn new(uart: BufferedUart<USART2>) -> Self {
+        Self {
+            uart,
+            state: State::Disconnected,
+            state_since_ms: 0,
+            now_ms: 0,
+            rx_packet: Packet::new(),
+            rx_since_ms: 0,
+            msg_id: 0,
+            distance_topic_id: 0,
+            event_topic_id: 0,
+            command_topic_id: 0,
+            last_tx_ms: 0,
+            ping_sent_ms: None,
+        }
+    }
+
+    /// Publish a telemetry frame to the distance topic. Dropped if not connected.
+    pub fn publish(&mut self, frame: &str) {
+        self.publish_to(self.distance_topic_id, frame.trim_end().as_bytes());
+    }
+
+    /// Publish a command response to the event topic. Dropped if not connected.
+    pub fn write(&mut self, bytes: &[u8]) {
+        self.publish_to(self.event_topic_id, bytes.trim_ascii_end());
+    }
+
+    /// Length of a response which can currently be published without dropping it.
+    #[cfg_attr(not(feature = "nor-flash"), allow(dead_code))]
+    pub fn free_space(&self) -> usize {
+        // length, message type, flags, topic id & message id
+        const PUBLISH_OVERHEAD: usize = 7;
+        self.uart.free_space().saturating_sub(PUBLISH_OVERHEAD)
+    }
+
+    /// Handle timeouts & the keep alive. Must be called periodically.
+    pub fn tick(&mut self, now_ms: u32) {
+        self.now_ms = now_ms;
+
+        if !self.rx_packet.is_empty() && now_ms.wrapping_sub(self.rx_since_ms) >= RECEIVE_TIMEOUT_MS
+        {
+            defmt::warn!("MQTT-SN: discarding incomplete packet");
+            self.rx_packet.clear();
+        }
+
+        let elapsed = now_ms.wrapping_sub(self.state_since_ms);
+        match self.state {
+            State::Disconnected => {
+                if elapsed >= RETRY_DELAY_MS {
+                    self.connect();
+                }
+            }
+            State::Ready => {
+                if let Some(ping_sent_ms) = self.ping_sent_ms {
+                    if now_ms.wrapping_sub(ping_sent_ms) >= RESPONSE_TIMEOUT_MS {
+                        defmt::warn!("MQTT-SN: gateway not responding");
+                        self.enter(State::Disconnected);
+                    }
+                } else if now_ms.wrapping_sub(self.last_tx_ms) >= config::KEEP_ALIVE_S * 1_000 / 2 {
+                    self.ping_sent_ms = Some(now_ms);
+                    self.send(msg_type::PINGREQ, &[], &[]);
+                }
+            }
+            _ => {
+                if elapsed >= RESPONSE_TIMEOUT_MS {
+                    defmt::warn!("MQTT-SN: timeout in state {}", self.state);
+                    self.enter(State::Disconnected);
+                }
+            }
+        }
+    }
+
+    /// Handle the UART interrupt.
+    ///
+    /// Every payload published to the command topic is parsed and handed to `on_command`.
+    pub fn on_interrupt(
+        &mut self,
+        mut on_command: impl FnMut(&mut Self, Result<Command, ParseError>),
+    ) {
+        let mut rx_packet = core::mem::take(&mut self.rx_packet);
+        let mut rx_since_ms = self.rx_since_ms;
+        let now_ms = self.now_ms;
+        let mut packets = heapless::Vec::<Packet, 2>::new();
+        self.uart.on_interrupt(|_, byte| {
+            if rx_packet.is_empty() {
+                rx_since_ms = now_ms;
+            }
+            if rx_packet.push(byte).is_err() {
+                rx_packet.clear();
+                return;
+            }
+            let len = rx_packet[0] as usize;
+            // 0x00 is invalid & 0x01 announces the unsupported 3 byte length encoding
+            if len < 2 {
+                defmt::warn!("MQTT-SN: unsupported packet length");
+                rx_packet.clear();
+            } else if rx_packet.len() == len {
+                packets.push(core::mem::take(&mut rx_packet)).ok();
+            }
+        });
+        self.rx_packet = rx_packet;
+        self.rx_since_ms = rx_since_ms;
+
+        let mut commands = heapless::Vec::<Result<Command, ParseError>, 2>::new();
+        for packet in packets {
+            if let Some(result) = self.on_packet(packet[1], &packet[2..]) {
+                commands.push(result).ok();
+            }
+        }
+        for result in commands {
+            on_command(self, result);
+        }
+    }
+
+    fn on_packet(&mut self, msg_type: u8, body: &[u8]) -> Option<Result<Command, ParseError>> {
+        defmt::trace!(
+            "MQTT-SN: received {=u8:#x} in state {}",
+            msg_type,
+            self.state
+        );
+
+        match (self.state, msg_type, body) {
+            (State::Connecting, msg_type::CONNACK, [RETURN_CODE_ACCEPTED]) => {
+                defmt::info!("MQTT-SN: connected to gateway");
+                self.register(State::RegisteringDistanceTopic, config::DISTANCE_TOPIC);
+            }
+            (
+                State::RegisteringDistanceTopic | State::RegisteringEventTopic,
+                msg_type::REGACK,
+                [topic_h, topic_l, msg_h, msg_l, RETURN_CODE_ACCEPTED],
+            ) if u16::from_be_bytes([*msg_h, *msg_l]) == self.msg_id => {
+                let topic_id = u16::from_be_bytes([*topic_h, *topic_l]);
+                if self.state == State::RegisteringDistanceTopic {
+                    self.distance_topic_id = topic_id;
+                    self.register(State::RegisteringEventTopic, config::EVENT_TOPIC);
+                } else {
+                    self.event_topic_id = topic_id;
+                    self.subscribe();
+                }
+            }
+            (
+                State::SubscribingCommandTopic,
+                msg_type::SUBACK,
+                [_flags, topic_h, topic_l, msg_h, msg_l, RETURN_CODE_ACCEPTED],
+            ) if u16::from_be_bytes([*msg_h, *msg_l]) == self.msg_id => {
+                self.command_topic_id = u16::from_be_bytes([*topic_h, *topic_l]);
+                defmt::info!("MQTT-SN: ready");
+                self.ping_sent_ms = None;
+                self.enter(State::Ready);
+            }
+            (
+                State::Ready,
+                msg_type::PUBLISH,
+                [_flags, topic_h, topic_l, _msg_h, _msg_l, data @ ..],
+            ) if u16::from_be_bytes([*topic_h, *topic_l]) == self.command_topic_id => {
+                let result = match core::str::from_utf8(data) {
+                    Ok(line) if line.len() <= command::MAX_LINE_LEN => command::parse(line.trim()),
+                    Ok(_) => Err(ParseError::LineTooLong),
+                    Err(_) => Err(ParseError::UnknownCommand),
+                };
+                return Some(result);
+            }
+            (_, msg_type::PINGRESP, _) => self.ping_sent_ms = None,
+            (_, msg_type::DISCONNECT, _) => {
+                defmt::warn!("MQTT-SN: disconnected by gateway");
+                self.enter(State::Disconnected);
+            }
+            (State::Connecting, msg_type::CONNACK, _)
+            | (
+                State::RegisteringDistanceTopic | State::RegisteringEventTopic,
+                msg_type::REGACK,
+                _,
+            )
+            | (State::SubscribingCommandTopic, msg_type::SUBACK, _) => {
+                defmt::warn!("MQTT-SN: request rejected in state {}", self.state);
+                self.enter(State::Disconnected);
+            }
+            // everything else (e.g. publications to other topics) is of no interest
+            _ => {}
+        }
+        None
+    }
+
+    fn connect(&mut self) {
+        defmt::debug!("MQTT-SN: connecting as {}", config::CLIENT_ID);
+        let duration = (config::KEEP_ALIVE_S as u16).to_be_bytes();
+        self.send(
+            msg_type::CONNECT,
+            &[FLAG_CLEAN_SESSION, PROTOCOL_ID, duration[0], duration[1]],
+            config::CLIENT_ID.as_bytes(),
+        );
+        self.enter(State::Connecting);
+    }
+
+    fn register(&mut self, next: State, topic: &str) {
+        let msg_id = self.next_msg_id().to_be_bytes();
+        // the topic id is always 0 when sent by a client
+        self.send(
+            msg_type::REGISTER,
+            &[0, 0, msg_id[0], msg_id[1]],
+            topic.as_bytes(),
+        );
+        self.enter(next);
+    }
+
+    fn subscribe(&mut self) {
+        let msg_id = self.next_msg_id().to_be_bytes();
+        // QoS 0, normal topic name
+        self.send(
+            msg_type::SUBSCRIBE,
+            &[0, msg_id[0], msg_id[1]],
+            config::COMMAND_TOPIC.as_bytes(),
+        );
+        self.enter(State::SubscribingCommandTopic);
+    }
+
+    fn publish_to(&mut self, topic_id: u16, data: &[u8]) {
+        if self.state != State::Ready {
+            return;
+        }
+        let topic_id = topic_id.to_be_bytes();
+        // QoS 0, normal topic id, the message id is not relevant for QoS 0
+        self.send(
+            msg_type::PUBLISH,
+            &[0, topic_id[0], topic_id[1], 0, 0],
+            data,
+        );
+    }
+
+    fn next_msg_id(&mut self) -> u16 {
+        // 0 is not a valid message id
+        self.msg_id = self.msg_id.checked_add(1).unwrap_or(1);
+        self.msg_id
+    }
+
+    /// Send a packet consisting of the fixed `header` fields and the variable length `data`.
+    fn send(&mut self, msg_type: u8, header: &[u8], data: &[u8]) {
+        let len = 2 + header.len() + data.len();
+        // never send partial packets, the gateway would lose the synchronisation
+        if len > MAX_PACKET_LEN || len > self.uart.free_space() {
+            defmt::warn!("MQTT-SN: dropping packet of {} bytes", len);
+            return;
+        }
+        self.uart.write(&[len as u8, msg_type]);
+        self.uart.write(header);
+        self.uart.write(data);
+        self.last_tx_ms = self.now_ms;
+    }
+
+    fn enter(&mut self, state: State) {
+        self.state = state;
+        self.state_since_ms = self.now_ms;
+    }
+}
+
+impl Sink for MqttSnLink {
+    fn measurement(&mut self, _measurement: &Measurement, frame: &str) {
+        self.publish(frame);
+    }
+
+    fn batch(&mut self, frame: &str) {
+        self.publish(frame);
+    }
+
+    fn frame(&mut self, frame: &str) {
+        self.publish(frame);
+    }
+}
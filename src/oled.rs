@@ -0,0 +1,144 @@
+//! Support for a 128x64 SSD1306 OLED connected to the shared I2C bus.
+//!
+//! The display shows the latest distance, a bar graph of it and status icons for the ranging state
+//! and the range status. While the menu is open it shows the selected item & its value instead,
+//! the value is underlined while it's being changed.
+
+use crate::display::{Display, DisplayState, MenuView, RenderError};
+use crate::I2cBus;
+use core::fmt::Write;
+use embedded_graphics::mono_font::ascii::{FONT_10X20, FONT_6X10};
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{Circle, PrimitiveStyle, Rectangle, Triangle};
+use embedded_graphics::text::{Baseline, Text};
+use ssd1306::mode::BufferedGraphicsMode;
+use ssd1306::prelude::*;
+use ssd1306::{I2CDisplayInterface, Ssd1306};
+use vl53l1x_uld::RangeStatus;
+
+/// Distance which corresponds to a full bar graph, this is the maximum range of the sensor.
+const BAR_GRAPH_MAX_MM: u32 = 4_000;
+
+pub struct Oled {
+    display:
+        Ssd1306<I2CInterface<I2cBus>, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>,
+}
+
+impl Oled {
+    /// Set up the display. Returns `None` if no display is connected.
+    pub fn new(i2c: I2cBus) -> Option<Self> {
+        let mut display = Ssd1306::new(
+            I2CDisplayInterface::new(i2c),
+            DisplaySize128x64,
+            DisplayRotation::Rotate0,
+        )
+        .into_buffered_graphics_mode();
+        if display.init().is_err() {
+            defmt::warn!("no SSD1306 display found");
+            return None;
+        }
+        defmt::trace!("display set up");
+        Some(Self { display })
+    }
+}
+
+impl Display for Oled {
+    fn render(&mut self, state: &DisplayState) -> Result<(), RenderError> {
+        self.display.clear_buffer();
+        // drawing into the buffer can't fail
+        draw(&mut self.display, state).ok();
+        self.display.flush().map_err(|_| RenderError)
+    }
+}
+
+/// Draw the state onto the cleared display.
+fn draw<D>(target: &mut D, state: &DisplayState) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    if let Some(menu) = &state.menu {
+        return draw_menu(target, menu);
+    }
+
+    let large = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    let small = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    let outline = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
+    let fill = PrimitiveStyle::with_fill(BinaryColor::On);
+
+    let valid = state.measurement.filter(|m| m.status == RangeStatus::Valid);
+
+    // distance
+    let mut text = heapless::String::<16>::new();
+    match valid {
+        Some(m) => write!(text, "{} mm", m.distance_mm),
+        None => write!(text, "---- mm"),
+    }
+    .ok();
+    Text::with_baseline(&text, Point::new(0, 0), large, Baseline::Top).draw(target)?;
+
+    // bar graph
+    Rectangle::new(Point::new(0, 26), Size::new(128, 12))
+        .into_styled(outline)
+        .draw(target)?;
+    if let Some(m) = valid {
+        let width = (m.distance_mm as u32).min(BAR_GRAPH_MAX_MM) * 124 / BAR_GRAPH_MAX_MM;
+        Rectangle::new(Point::new(2, 28), Size::new(width, 8))
+            .into_styled(fill)
+            .draw(target)?;
+    }
+
+    // ranging state: filled circle while ranging, empty circle when stopped
+    let circle = Circle::new(Point::new(0, 48), 12);
+    if state.ranging {
+        circle.into_styled(fill).draw(target)?;
+    } else {
+        circle.into_styled(outline).draw(target)?;
+    }
+    Text::with_baseline(
+        if state.ranging { "RUN" } else { "STOP" },
+        Point::new(16, 50),
+        small,
+        Baseline::Top,
+    )
+    .draw(target)?;
+
+    // warning sign if the latest measurement isn't valid
+    if let Some(m) = state.measurement {
+        if m.status != RangeStatus::Valid {
+            Triangle::new(Point::new(70, 60), Point::new(76, 48), Point::new(82, 60))
+                .into_styled(outline)
+                .draw(target)?;
+            text.clear();
+            write!(text, "S{}", m.status as u8).ok();
+            Text::with_baseline(&text, Point::new(86, 50), small, Baseline::Top).draw(target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Draw the menu onto the cleared display.
+fn draw_menu<D>(target: &mut D, menu: &MenuView) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let large = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    let small = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+
+    Text::with_baseline("MENU", Point::new(0, 0), small, Baseline::Top).draw(target)?;
+    Text::with_baseline(menu.label, Point::new(0, 14), large, Baseline::Top).draw(target)?;
+
+    let mut text = heapless::String::<16>::new();
+    menu.value.write_to(&mut text).ok();
+    Text::with_baseline(&text, Point::new(0, 40), large, Baseline::Top).draw(target)?;
+    if menu.editing {
+        let width = text.len() as u32 * FONT_10X20.character_size.width;
+        Rectangle::new(Point::new(0, 61), Size::new(width, 2))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(target)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,239 @@
+//! The option bytes of the flash: the brown-out reset level (see [`crate::brown_out`]), the
+//! write protection of the sectors & the read protection (RDP), managed with the `protect`
+//! command so that deployed units can be hardened against modifications.
+//!
+//! Sectors 0 - 5 hold the firmware (both slots with `firmware-update`), sectors 6 & 7 the
+//! [`crate::eeprom`] with the settings, the calibration & the logs. Protected sectors can't be
+//! written anymore, thus a protected EEPROM fails all writes (e.g. `save`) & protected firmware
+//! sectors all updates.
+//!
+//! Only the read protection level 1 can be set: lowering it to 0 mass erases the flash (including
+//! the running firmware) & level 2 can never be removed again. Level 1 takes effect after the next
+//! reset, a debugger can't read the flash anymore then.
+
+use stm32f4xx_hal::pac::flash::optcr;
+use stm32f4xx_hal::pac::FLASH;
+
+/// The keys which unlock the option bytes.
+const OPT_KEYS: [u32; 2] = [0x0819_2A3B, 0x4C5D_6E7F];
+/// The bits of the 8 sectors of the STM32F401xE in `nWRP`, the others are reserved.
+const SECTOR_MASK: u16 = 0xFF;
+const N_WRP_MASK: u16 = 0x0FFF;
+/// The value of `RDP` for level 0, any other value than this one & [`RDP_LEVEL_2`] is level 1.
+const RDP_LEVEL_0: u8 = 0xAA;
+const RDP_LEVEL_2: u8 = 0xCC;
+/// The value of `RDP` which is written for level 1.
+const RDP_LEVEL_1: u8 = 0x55;
+/// Maximum number of polls of `BSY` while the option bytes are programmed, each one waits for
+/// [`CYCLES_PER_POLL`]. Programming takes a few tens of milliseconds, this is ~0.25 s at 84 MHz.
+const MAX_BUSY_POLLS: u32 = 20_000;
+const CYCLES_PER_POLL: u32 = 1_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    /// The flash stayed busy.
+    Timeout,
+    /// A sector is write protected (`WRPERR`).
+    WriteProtection,
+    /// The sequence of the programming was wrong (`PGSERR`).
+    Sequence,
+    /// The operation failed (`OPERR`).
+    Operation,
+}
+
+/// Groups of sectors which are write protected together, one bit per sector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Region {
+    None,
+    Firmware,
+    Eeprom,
+    All,
+}
+
+impl Region {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "firmware" => Some(Self::Firmware),
+            "eeprom" => Some(Self::Eeprom),
+            "all" => Some(Self::All),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Firmware => "firmware",
+            Self::Eeprom => "eeprom",
+            Self::All => "all",
+        }
+    }
+
+    pub fn sectors(self) -> u16 {
+        match self {
+            Self::None => 0,
+            Self::Firmware => 0x3F,
+            Self::Eeprom => 0xC0,
+            Self::All => 0xFF,
+        }
+    }
+
+    /// The region which consists of exactly the `sectors`.
+    pub fn from_sectors(sectors: u16) -> Option<Self> {
+        [Self::None, Self::Firmware, Self::Eeprom, Self::All]
+            .into_iter()
+            .find(|region| region.sectors() == sectors)
+    }
+}
+
+/// The level of the read protection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ReadProtection {
+    Level0,
+    Level1,
+    Level2,
+}
+
+impl ReadProtection {
+    fn from_rdp(rdp: u8) -> Self {
+        match rdp {
+            RDP_LEVEL_0 => Self::Level0,
+            RDP_LEVEL_2 => Self::Level2,
+            _ => Self::Level1,
+        }
+    }
+
+    pub fn level(self) -> u8 {
+        match self {
+            Self::Level0 => 0,
+            Self::Level1 => 1,
+            Self::Level2 => 2,
+        }
+    }
+}
+
+/// The protection as stored in the option bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Protection {
+    /// The write protected sectors, one bit per sector.
+    pub sectors: u16,
+    pub read: ReadProtection,
+}
+
+impl Protection {
+    pub fn read(flash: &FLASH) -> Self {
+        let optcr = flash.optcr.read();
+        Self::from_optcr(optcr.n_wrp().bits(), optcr.rdp().bits())
+    }
+
+    /// `n_wrp` has a cleared bit for each protected sector.
+    fn from_optcr(n_wrp: u16, rdp: u8) -> Self {
+        Self {
+            sectors: !n_wrp & SECTOR_MASK,
+            read: ReadProtection::from_rdp(rdp),
+        }
+    }
+
+    /// Write the response to the `protect` command.
+    pub fn write_status(&self, response: &mut impl core::fmt::Write) -> core::fmt::Result {
+        write!(response, "OK wrp=")?;
+        match Region::from_sectors(self.sectors) {
+            Some(region) => write!(response, "{}", region.name())?,
+            None => write!(response, "{:#04x}", self.sectors)?,
+        }
+        write!(response, " rdp={}\r\n", self.read.level())
+    }
+}
+
+/// Write protect exactly the sectors of the `region`, the others are unprotected.
+#[allow(unsafe_code)]
+pub fn set_write_protection(flash: &mut FLASH, region: Region) -> Result<(), Error> {
+    // the reserved bits stay set
+    let n_wrp = !region.sectors() & N_WRP_MASK;
+    // SAFETY: any combination of the sectors is valid
+    program(flash, |w| unsafe { w.n_wrp().bits(n_wrp) })
+}
+
+/// Raise the read protection to level 1, it applies after the next reset.
+#[allow(unsafe_code)]
+pub fn set_read_protection(flash: &mut FLASH) -> Result<(), Error> {
+    // SAFETY: the value is level 1, not the irreversible level 2
+    program(flash, |w| unsafe { w.rdp().bits(RDP_LEVEL_1) })
+}
+
+/// Modify the option bytes & program them, this stalls the execution from the flash for a few
+/// milliseconds. Returns once they've been written or the programming has failed, `OPTCR` then
+/// still holds the requested value but the option bytes don't.
+pub fn program(
+    flash: &mut FLASH,
+    modify: impl FnOnce(&mut optcr::W) -> &mut optcr::W,
+) -> Result<(), Error> {
+    wait_while_busy(flash)?;
+    // errors of earlier operations would be reported as the ones of this one
+    flash.sr.write(|w| {
+        w.operr()
+            .set_bit()
+            .wrperr()
+            .set_bit()
+            .pgaerr()
+            .set_bit()
+            .pgperr()
+            .set_bit()
+            .pgserr()
+            .set_bit()
+    });
+    if flash.optcr.read().optlock().bit_is_set() {
+        for key in OPT_KEYS {
+            flash.optkeyr.write(|w| w.optkey().bits(key));
+        }
+    }
+    flash.optcr.modify(|_, w| modify(w));
+    flash.optcr.modify(|_, w| w.optstrt().set_bit());
+    let result = wait_while_busy(flash).and_then(|()| {
+        let sr = flash.sr.read();
+        if sr.wrperr().bit_is_set() {
+            Err(Error::WriteProtection)
+        } else if sr.pgserr().bit_is_set() {
+            Err(Error::Sequence)
+        } else if sr.operr().bit_is_set() {
+            Err(Error::Operation)
+        } else {
+            Ok(())
+        }
+    });
+    flash.optcr.modify(|_, w| w.optlock().set_bit());
+    result
+}
+
+fn wait_while_busy(flash: &FLASH) -> Result<(), Error> {
+    for _ in 0..MAX_BUSY_POLLS {
+        if flash.sr.read().bsy().bit_is_clear() {
+            return Ok(());
+        }
+        cortex_m::asm::delay(CYCLES_PER_POLL);
+    }
+    Err(Error::Timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_the_protection() {
+        let protection = Protection::from_optcr(0x0F3F, RDP_LEVEL_0);
+        assert_eq!(
+            Region::from_sectors(protection.sectors),
+            Some(Region::Eeprom)
+        );
+        assert_eq!(protection.read, ReadProtection::Level0);
+        let protection = Protection::from_optcr(0x0FFE, 0x12);
+        assert_eq!(protection.sectors, 0x01);
+        assert_eq!(protection.read, ReadProtection::Level1);
+
+        let mut response = heapless::String::<32>::new();
+        protection.write_status(&mut response).unwrap();
+        assert_eq!(response, "OK wrp=0x01 rdp=1\r\n");
+    }
+}
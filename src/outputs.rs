@@ -0,0 +1,187 @@
+//! All local outputs driven by the measured distance.
+//!
+//! The outputs are combined into a single struct so that the tasks don't need to know which outputs
+//! have been enabled.
+
+#[cfg(feature = "alarm-output")]
+use crate::alarm_output::AlarmOutput;
+#[cfg(feature = "analog-output")]
+use crate::analog_output::AnalogOutput;
+#[cfg(feature = "buzzer")]
+use crate::buzzer::Buzzer;
+use crate::event_log::Event;
+use crate::geofence::{Geofence, Transition};
+#[cfg(feature = "motor-pid")]
+use crate::motor::Motor;
+#[cfg(feature = "proximity-led")]
+use crate::proximity_led::ProximityLed;
+#[cfg(feature = "servo")]
+use crate::servo::Servo;
+#[cfg(feature = "stepper")]
+use crate::stepper::Stepper;
+use crate::telemetry::Measurement;
+
+pub struct Outputs {
+    #[cfg(feature = "proximity-led")]
+    pub proximity_led: ProximityLed,
+    #[cfg(feature = "buzzer")]
+    pub buzzer: Buzzer,
+    #[cfg(feature = "alarm-output")]
+    pub alarm: AlarmOutput,
+    #[cfg(feature = "servo")]
+    pub servo: Servo,
+    #[cfg(feature = "motor-pid")]
+    pub motor: Motor,
+    #[cfg(feature = "analog-output")]
+    pub analog: AnalogOutput,
+    #[cfg(feature = "stepper")]
+    pub stepper: Stepper,
+    /// Asserted while a zone with the `gpio` action is occupied, see [`crate::geofence`].
+    #[cfg(feature = "zone-output")]
+    pub zone_output: stm32f4xx_hal::gpio::PC3<stm32f4xx_hal::gpio::Output>,
+    /// The indicators of the zones with the `indicator` action, `None` if the PWM expander isn't
+    /// available, see [`crate::pwm_expander`].
+    #[cfg(feature = "pwm-expander")]
+    pub indicators: Option<crate::pwm_expander::Indicators>,
+}
+
+impl Outputs {
+    /// Whether any output needs [`Self::tick`] to be called.
+    pub const NEEDS_TICK: bool = cfg!(any(
+        feature = "buzzer",
+        feature = "alarm-output",
+        feature = "motor-pid"
+    ));
+    /// Interval at which [`Self::tick`] is called.
+    pub const TICK_INTERVAL_MS: u32 = 10;
+
+    /// Update all outputs with a new measurement.
+    pub fn update(&mut self, measurement: &Measurement) {
+        #[cfg(feature = "proximity-led")]
+        self.proximity_led.update(measurement);
+        #[cfg(feature = "buzzer")]
+        self.buzzer.update(measurement);
+        #[cfg(feature = "alarm-output")]
+        self.alarm.update(measurement);
+        #[cfg(feature = "servo")]
+        self.servo.update(measurement);
+        #[cfg(feature = "motor-pid")]
+        self.motor.update(measurement);
+        #[cfg(feature = "analog-output")]
+        self.analog.update(measurement);
+        #[cfg(feature = "stepper")]
+        self.stepper.update(measurement);
+        #[cfg(not(any(
+            feature = "proximity-led",
+            feature = "buzzer",
+            feature = "alarm-output",
+            feature = "servo",
+            feature = "motor-pid",
+            feature = "analog-output",
+            feature = "stepper"
+        )))]
+        let _ = measurement;
+    }
+
+    /// Drive all outputs to their failsafe values as the TOF sensor is lost (see
+    /// [`crate::sensor_supervisor`]), they follow the distance again with the next [`Self::update`].
+    /// The zone output & the indicators are turned off.
+    pub fn failsafe(&mut self) {
+        #[cfg(feature = "proximity-led")]
+        self.proximity_led.failsafe();
+        #[cfg(feature = "buzzer")]
+        self.buzzer.failsafe();
+        #[cfg(feature = "alarm-output")]
+        self.alarm.failsafe();
+        #[cfg(feature = "servo")]
+        self.servo.failsafe();
+        #[cfg(feature = "motor-pid")]
+        self.motor.failsafe();
+        #[cfg(feature = "analog-output")]
+        self.analog.failsafe();
+        #[cfg(feature = "stepper")]
+        self.stepper.failsafe();
+        #[cfg(feature = "zone-output")]
+        self.zone_output.set_low();
+        #[cfg(feature = "pwm-expander")]
+        if let Some(indicators) = &mut self.indicators {
+            indicators.update(0);
+        }
+    }
+
+    /// Apply the actions of the zones of the `geofence` after the measurement which caused the
+    /// `transitions`.
+    pub fn update_zones(&mut self, geofence: &Geofence, transitions: &[Transition]) {
+        #[cfg(feature = "zone-output")]
+        if geofence.is_occupied(crate::geofence::Actions::GPIO) {
+            self.zone_output.set_high();
+        } else {
+            self.zone_output.set_low();
+        }
+        #[cfg(feature = "pwm-expander")]
+        if let Some(indicators) = &mut self.indicators {
+            indicators.update(geofence.occupied(crate::geofence::Actions::INDICATOR));
+        }
+        #[cfg(feature = "buzzer")]
+        if transitions.iter().any(|transition| {
+            transition.entered
+                && transition
+                    .actions
+                    .contains(crate::geofence::Actions::BUZZER)
+        }) {
+            self.buzzer.chirp();
+        }
+        #[cfg(not(any(feature = "zone-output", feature = "pwm-expander")))]
+        let _ = geofence;
+        #[cfg(not(feature = "buzzer"))]
+        let _ = transitions;
+    }
+
+    /// Change the threshold of the alarm output.
+    #[cfg_attr(
+        not(any(feature = "threshold-pot", feature = "alarm-output", feature = "lora")),
+        allow(dead_code)
+    )]
+    pub fn set_alarm_threshold(&mut self, threshold_mm: u16) {
+        #[cfg(feature = "alarm-output")]
+        self.alarm.set_max_mm(threshold_mm);
+        #[cfg(not(feature = "alarm-output"))]
+        let _ = threshold_mm;
+    }
+
+    /// Mute the alarm output outside of the active hours, see [`crate::schedule`].
+    pub fn set_alarms_active(&mut self, active: bool) {
+        #[cfg(feature = "alarm-output")]
+        self.alarm.set_muted(!active);
+        #[cfg(not(feature = "alarm-output"))]
+        let _ = active;
+    }
+
+    /// The threshold of the alarm output, `None` without it.
+    #[cfg(any(feature = "alarm-output", feature = "lora"))]
+    pub fn alarm_threshold_mm(&self) -> Option<u16> {
+        #[cfg(feature = "alarm-output")]
+        return Some(self.alarm.max_mm());
+        #[cfg(not(feature = "alarm-output"))]
+        None
+    }
+
+    /// Handle the timing of the outputs. Returns the event for the [`crate::event_log`] if an
+    /// output has changed its state.
+    pub fn tick(&mut self, now_ms: u32) -> Option<Event> {
+        #[cfg(feature = "buzzer")]
+        self.buzzer.tick(now_ms);
+        #[cfg(feature = "alarm-output")]
+        let event = self
+            .alarm
+            .tick(now_ms)
+            .then(|| Event::Alarm(self.alarm.is_asserted()));
+        #[cfg(not(feature = "alarm-output"))]
+        let event = None;
+        #[cfg(feature = "motor-pid")]
+        self.motor.tick(now_ms);
+        #[cfg(not(any(feature = "buzzer", feature = "alarm-output", feature = "motor-pid")))]
+        let _ = now_ms;
+        event
+    }
+}
@@ -0,0 +1,171 @@
+//! The panic handler of the field deployments, selected with one of the mutually exclusive
+//! features:
+//! * `panic-probe` (default): print the panic with defmt & halt for the debugger, see
+//!   [`panic_probe`](https://docs.rs/panic-probe) (this module isn't used)
+//! * `panic-reset`: print the panic with defmt & reset the microcontroller right away, so that
+//!   the device recovers on its own
+//! * `panic-persist`: like `panic-reset`, but the message & the location of the panic are kept in
+//!   a RAM section which isn't initialised at boot (`.uninit`), it's read once after the reset (see
+//!   [`take`]). Only a reset keeps the RAM, the message is lost with the power.
+//!
+//! Both reset handlers also write the panic to the virtual COM port (USART2) before the reset, so
+//! that a headless unit leaves a trace on whatever terminal is attached. It's blocking & best
+//! effort: the pending telemetry is abandoned (the panic starts on a new line), a pause of the host
+//! with XOFF is ignored & the output is given up if the transmitter doesn't get ready in time (e.g.
+//! if the panic happens before the UART is set up). It's left out with `mqtt-sn`, where the text
+//! would corrupt the packets for the gateway.
+
+#[cfg(all(feature = "panic-reset", feature = "panic-persist"))]
+compile_error!("the features `panic-reset` and `panic-persist` can't be combined");
+#[cfg(feature = "panic-probe")]
+compile_error!("the feature `panic-probe` can't be combined with `panic-reset` or `panic-persist`");
+
+#[cfg(any(feature = "panic-persist", not(feature = "mqtt-sn")))]
+use core::fmt::Write;
+use core::panic::PanicInfo;
+#[cfg(not(feature = "mqtt-sn"))]
+use stm32f4xx_hal::pac::{usart1, USART2};
+
+/// Maximum length of the persisted message, it's truncated beyond.
+#[cfg(feature = "panic-persist")]
+pub const MESSAGE_LEN: usize = 128;
+
+/// Marks a persisted message, `PN` followed by the version of the layout.
+#[cfg(feature = "panic-persist")]
+const MAGIC: u32 = u32::from_le_bytes(*b"PN01");
+
+#[cfg(feature = "panic-persist")]
+#[repr(C)]
+struct Record {
+    magic: u32,
+    len: u32,
+    message: [u8; MESSAGE_LEN],
+}
+
+#[cfg(feature = "panic-persist")]
+#[allow(unsafe_code)]
+#[link_section = ".uninit.panic_persist"]
+static mut RECORD: core::mem::MaybeUninit<Record> = core::mem::MaybeUninit::uninit();
+
+/// Spins waiting for the transmitter per byte before the output over the UART is given up, a few
+/// ms at 84 MHz while a byte takes less than 0.1 ms at 115200 baud.
+#[cfg(not(feature = "mqtt-sn"))]
+const UART_TIMEOUT_SPINS: u32 = 100_000;
+
+/// Log the panic (& persist it with `panic-persist`), then reset the microcontroller.
+pub fn reset(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    defmt::error!("{}", defmt::Display2Format(info));
+    #[cfg(feature = "panic-persist")]
+    persist(info);
+    #[cfg(not(feature = "mqtt-sn"))]
+    print(info);
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+/// Writes to the USART2 directly, bypassing the owner of the peripheral.
+#[cfg(not(feature = "mqtt-sn"))]
+struct UartWriter {
+    usart: &'static usart1::RegisterBlock,
+}
+
+#[cfg(not(feature = "mqtt-sn"))]
+impl UartWriter {
+    /// Wait until the flag of the status register is set, `false` on a timeout.
+    fn wait(&self, flag: impl Fn(&usart1::sr::R) -> bool) -> bool {
+        (0..UART_TIMEOUT_SPINS).any(|_| flag(&self.usart.sr.read()))
+    }
+}
+
+#[cfg(not(feature = "mqtt-sn"))]
+impl Write for UartWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &byte in s.as_bytes() {
+            if !self.wait(|sr| sr.txe().bit_is_set()) {
+                return Err(core::fmt::Error);
+            }
+            self.usart.dr.write(|w| w.dr().bits(byte.into()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "mqtt-sn"))]
+#[allow(unsafe_code)]
+fn print(info: &PanicInfo) {
+    // SAFETY: the interrupts are disabled & the firmware doesn't continue, the owner of the UART
+    // doesn't access it anymore
+    let usart = unsafe { &*USART2::ptr() };
+    if usart.cr1.read().ue().bit_is_clear() || usart.cr1.read().te().bit_is_clear() {
+        // not set up yet
+        return;
+    }
+    let mut writer = UartWriter { usart };
+    if write!(writer, "\r\npanic: {}\r\n", info).is_ok() {
+        // the last byte leaves before the reset
+        writer.wait(|sr| sr.tc().bit_is_set());
+    }
+}
+
+/// Truncates the message once the record is full.
+#[cfg(feature = "panic-persist")]
+struct Writer<'a> {
+    message: &'a mut [u8; MESSAGE_LEN],
+    len: usize,
+}
+
+#[cfg(feature = "panic-persist")]
+impl Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let len = s.len().min(MESSAGE_LEN - self.len);
+        self.message[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "panic-persist")]
+#[allow(unsafe_code)]
+fn persist(info: &PanicInfo) {
+    // SAFETY: the interrupts are disabled & the firmware doesn't continue, nothing else accesses
+    // the record meanwhile
+    let record = unsafe { (*core::ptr::addr_of_mut!(RECORD)).as_mut_ptr() };
+    let message = unsafe { &mut (*record).message };
+    let mut writer = Writer { message, len: 0 };
+    // the message is cut short once it's full
+    write!(writer, "{}", info).ok();
+    let len = writer.len;
+    unsafe {
+        core::ptr::write_volatile(core::ptr::addr_of_mut!((*record).len), len as u32);
+        core::ptr::write_volatile(core::ptr::addr_of_mut!((*record).magic), MAGIC);
+    }
+}
+
+/// The message of the panic before the reset, `None` if there hasn't been one. It's only returned
+/// once, a truncated character at its end is left out.
+#[cfg(feature = "panic-persist")]
+#[allow(unsafe_code)]
+pub fn take() -> Option<heapless::String<MESSAGE_LEN>> {
+    // SAFETY: only called during the initialisation, the record holds arbitrary bytes after a
+    // loss of power which are only used with the magic value
+    let record = unsafe { (*core::ptr::addr_of_mut!(RECORD)).as_mut_ptr() };
+    let (magic, len, message) = unsafe {
+        let magic = core::ptr::read_volatile(core::ptr::addr_of!((*record).magic));
+        core::ptr::write_volatile(core::ptr::addr_of_mut!((*record).magic), 0);
+        let len = core::ptr::read_volatile(core::ptr::addr_of!((*record).len));
+        let message = core::ptr::read_volatile(core::ptr::addr_of!((*record).message));
+        (magic, len, message)
+    };
+    if magic != MAGIC {
+        return None;
+    }
+    let message = &message[..(len as usize).min(MESSAGE_LEN)];
+    let message = match core::str::from_utf8(message) {
+        Ok(message) => message,
+        Err(e) => core::str::from_utf8(&message[..e.valid_up_to()]).ok()?,
+    };
+    let mut string = heapless::String::new();
+    // it has the same capacity
+    string.push_str(message).ok();
+    Some(string)
+}
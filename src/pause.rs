@@ -0,0 +1,147 @@
+//! Pausing the acquisition while the TOF sensor is changed, i.e. while it's reconfigured (e.g. by
+//! a preset, a profile or a mode switch), recalibrated on a change of the temperature or while a
+//! calibration is finished. The ranging is stopped for the first & started again after the last
+//! of the overlapping pauses (e.g. of tasks which preempt each other).
+//!
+//! Unlike a `stop` & `start` a pause doesn't end the telemetry session: there's no new session
+//! header, the [`crate::warm_up`] isn't restarted & the filters & the statistics keep their state.
+//! To avoid a transient in the published measurements the first [`config::DISCARD`] ones after
+//! the acquisition has been resumed are discarded (as is any measurement read while it's paused).
+
+use crate::config::pause as config;
+
+/// Why the acquisition is paused, several of them may overlap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Reason {
+    /// Settings are changed which can only be changed while the sensor isn't ranging.
+    Reconfigure,
+    /// The sensor is recalibrated on a change of the temperature.
+    Temperature,
+    /// An offset or crosstalk calibration is finished.
+    Calibration,
+}
+
+pub struct Pause {
+    /// Number of the overlapping pauses per [`Reason`].
+    reasons: [u8; 3],
+    /// Number of measurements still to be discarded after the last resume.
+    discard: u32,
+    /// Number of measurements discarded since boot.
+    discarded: u32,
+}
+
+impl Pause {
+    pub const fn new() -> Self {
+        Self {
+            reasons: [0; 3],
+            discard: 0,
+            discarded: 0,
+        }
+    }
+
+    /// Pause the acquisition, returns whether it has just been paused (i.e. the ranging has to be
+    /// stopped).
+    pub fn pause(&mut self, reason: Reason) -> bool {
+        let paused = self.is_paused();
+        let count = &mut self.reasons[reason as usize];
+        *count = count.saturating_add(1);
+        !paused
+    }
+
+    /// Resume the acquisition after the `reason`, returns whether it has just been resumed (i.e.
+    /// the ranging has to be started again).
+    pub fn resume(&mut self, reason: Reason) -> bool {
+        let count = &mut self.reasons[reason as usize];
+        if *count == 0 {
+            return false;
+        }
+        *count -= 1;
+        if self.is_paused() {
+            return false;
+        }
+        self.discard = config::DISCARD;
+        true
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.reasons.iter().any(|&count| count > 0)
+    }
+
+    /// Whether a measurement which has just been read is used, it's counted otherwise.
+    pub fn passes(&mut self) -> bool {
+        if !self.is_paused() && self.discard == 0 {
+            return true;
+        }
+        self.discard = self.discard.saturating_sub(1);
+        self.discarded = self.discarded.wrapping_add(1);
+        false
+    }
+
+    /// Number of measurements discarded since boot.
+    pub fn discarded(&self) -> u32 {
+        self.discarded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Resume after the `reason` & discard the measurements of the transient.
+    fn resume_fully(pause: &mut Pause, reason: Reason) {
+        assert!(pause.resume(reason));
+        for _ in 0..config::DISCARD {
+            assert!(!pause.passes());
+        }
+    }
+
+    #[test]
+    fn passes_while_not_paused() {
+        let mut pause = Pause::new();
+        assert!(!pause.is_paused());
+        assert!(pause.passes());
+        assert_eq!(pause.discarded(), 0);
+    }
+
+    #[test]
+    fn discards_the_measurements_while_paused() {
+        let mut pause = Pause::new();
+        assert!(pause.pause(Reason::Calibration));
+        assert!(!pause.passes());
+        assert!(!pause.passes());
+        assert_eq!(pause.discarded(), 2);
+    }
+
+    #[test]
+    fn discards_the_first_measurements_after_the_resume() {
+        let mut pause = Pause::new();
+        pause.pause(Reason::Reconfigure);
+        resume_fully(&mut pause, Reason::Reconfigure);
+        assert!(pause.passes());
+        assert_eq!(pause.discarded(), config::DISCARD);
+    }
+
+    #[test]
+    fn resumes_after_the_last_of_overlapping_pauses() {
+        let mut pause = Pause::new();
+        assert!(pause.pause(Reason::Reconfigure));
+        assert!(!pause.pause(Reason::Temperature));
+        assert!(!pause.pause(Reason::Reconfigure));
+        assert!(!pause.resume(Reason::Reconfigure));
+        assert!(!pause.resume(Reason::Temperature));
+        assert!(pause.is_paused());
+        resume_fully(&mut pause, Reason::Reconfigure);
+        assert!(pause.passes());
+    }
+
+    #[test]
+    fn ignores_a_resume_without_a_pause() {
+        let mut pause = Pause::new();
+        assert!(!pause.resume(Reason::Calibration));
+        assert!(pause.passes());
+
+        pause.pause(Reason::Temperature);
+        assert!(!pause.resume(Reason::Calibration));
+        assert!(pause.is_paused());
+    }
+}
@@ -0,0 +1,73 @@
+//! A generic PID controller.
+
+/// Gains of a [`Pid`] controller.
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct Gains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+pub struct Pid {
+    gains: Gains,
+    setpoint: f32,
+    /// The output is limited to `-output_limit..=output_limit`.
+    output_limit: f32,
+    integral: f32,
+    last_input: Option<f32>,
+}
+
+impl Pid {
+    pub const fn new(gains: Gains, setpoint: f32, output_limit: f32) -> Self {
+        Self {
+            gains,
+            setpoint,
+            output_limit,
+            integral: 0.0,
+            last_input: None,
+        }
+    }
+
+    pub fn gains(&self) -> Gains {
+        self.gains
+    }
+
+    pub fn set_gains(&mut self, gains: Gains) {
+        self.gains = gains;
+        self.reset();
+    }
+
+    pub fn setpoint(&self) -> f32 {
+        self.setpoint
+    }
+
+    pub fn set_setpoint(&mut self, setpoint: f32) {
+        self.setpoint = setpoint;
+    }
+
+    /// Forget the accumulated state, e.g. after the control loop has been interrupted.
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.last_input = None;
+    }
+
+    /// Calculate the output for a new input which has been sampled `dt_s` seconds after the
+    /// previous one.
+    pub fn update(&mut self, input: f32, dt_s: f32) -> f32 {
+        let error = self.setpoint - input;
+        let limit = self.output_limit;
+
+        // the integral is limited to what can have an effect on the output to avoid windup
+        if self.gains.ki != 0.0 {
+            self.integral = (self.integral + self.gains.ki * error * dt_s).clamp(-limit, limit);
+        }
+        // the derivative of the input instead of the error avoids kicks when the setpoint changes
+        let derivative = match self.last_input {
+            Some(last_input) if dt_s > 0.0 => (last_input - input) / dt_s,
+            _ => 0.0,
+        };
+        self.last_input = Some(input);
+
+        (self.gains.kp * error + self.integral + self.gains.kd * derivative).clamp(-limit, limit)
+    }
+}
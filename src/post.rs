@@ -0,0 +1,181 @@
+//! The power-on self-test (POST): the initialisation is split into named [`Stage`]s, each one is
+//! logged when it starts & reported with its result & its duration when it ends. The stage in
+//! progress is also kept in a backup register of the [`crate::clock`], thus a boot which hangs
+//! (and is reset by the watchdog) can be localized from the log or, after the reset, from the
+//! report of the previous boot.
+//!
+//! The durations are measured with the cycle counter of the DWT at the core clock at the start of
+//! each stage, the one of [`Stage::Clocks`] thus at the 16 MHz of the HSI.
+
+/// The stages in the order in which they run, the number is the one in the backup register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Stage {
+    /// The RTC, the backup registers & the clocks of the microcontroller.
+    Clocks = 1,
+    Watchdog = 2,
+    /// The shared I2C bus & the pins.
+    I2c = 3,
+    /// The check of the firmware image, the EEPROM & the settings stored in it.
+    Storage = 4,
+    /// Probing & setting up the TOF sensor.
+    Sensor = 5,
+    /// The inputs, the outputs, the displays & the data loggers.
+    Peripherals = 6,
+    /// The links which send the telemetry.
+    Telemetry = 7,
+}
+
+/// The core clock after a reset, the one of the HSI.
+const RESET_CORE_HZ: u32 = 16_000_000;
+
+impl Stage {
+    pub fn number(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_number(number: u32) -> Option<Self> {
+        match number {
+            1 => Some(Self::Clocks),
+            2 => Some(Self::Watchdog),
+            3 => Some(Self::I2c),
+            4 => Some(Self::Storage),
+            5 => Some(Self::Sensor),
+            6 => Some(Self::Peripherals),
+            7 => Some(Self::Telemetry),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Clocks => "clocks",
+            Self::Watchdog => "watchdog",
+            Self::I2c => "i2c",
+            Self::Storage => "storage",
+            Self::Sensor => "sensor",
+            Self::Peripherals => "peripherals",
+            Self::Telemetry => "telemetry",
+        }
+    }
+}
+
+/// The result of a stage which has ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct StageReport {
+    pub stage: Stage,
+    pub failed: bool,
+    pub duration_us: u32,
+}
+
+pub struct Post {
+    stage: Stage,
+    /// The cycle counter at the start of the stage.
+    started: u32,
+    core_hz: u32,
+    failed: bool,
+    /// Number of the stages which have failed so far.
+    failures: u8,
+    total_us: u32,
+}
+
+impl Post {
+    /// Start with [`Stage::Clocks`] at `cycles` of the cycle counter.
+    pub fn start(cycles: u32) -> Self {
+        Self {
+            stage: Stage::Clocks,
+            started: cycles,
+            core_hz: RESET_CORE_HZ,
+            failed: false,
+            failures: 0,
+            total_us: 0,
+        }
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    /// The core clock from now on, the cycles of the current stage are still converted with the
+    /// previous one.
+    pub fn set_core_clock(&mut self, core_hz: u32) {
+        self.core_hz = core_hz;
+    }
+
+    /// Mark the current stage as failed, the initialisation continues anyway.
+    pub fn fail(&mut self) {
+        self.failed = true;
+    }
+
+    /// End the current stage at `cycles` & start the `next` one (none after the last stage).
+    pub fn next(&mut self, next: Option<Stage>, cycles: u32) -> StageReport {
+        let elapsed = cycles.wrapping_sub(self.started) as u64;
+        let duration_us = (elapsed * 1_000_000 / self.stage_hz() as u64) as u32;
+        let report = StageReport {
+            stage: self.stage,
+            failed: self.failed,
+            duration_us,
+        };
+        self.failures += self.failed as u8;
+        self.total_us = self.total_us.saturating_add(duration_us);
+        if let Some(next) = next {
+            self.stage = next;
+        }
+        self.started = cycles;
+        self.failed = false;
+        report
+    }
+
+    /// The core clock during the current stage.
+    fn stage_hz(&self) -> u32 {
+        if self.stage == Stage::Clocks {
+            RESET_CORE_HZ
+        } else {
+            self.core_hz
+        }
+    }
+
+    pub fn failures(&self) -> u8 {
+        self.failures
+    }
+
+    /// The sum of the durations of the stages which have ended.
+    pub fn total_us(&self) -> u32 {
+        self.total_us
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_the_stages() {
+        let mut post = Post::start(u32::MAX - 15_999);
+        post.set_core_clock(84_000_000);
+        assert_eq!(
+            post.next(Some(Stage::Watchdog), 16_000),
+            StageReport {
+                stage: Stage::Clocks,
+                failed: false,
+                duration_us: 2_000,
+            }
+        );
+        post.fail();
+        assert_eq!(
+            post.next(Some(Stage::I2c), 16_000 + 84_000),
+            StageReport {
+                stage: Stage::Watchdog,
+                failed: true,
+                duration_us: 1_000,
+            }
+        );
+        let report = post.next(None, 16_000 + 84_000);
+        assert!(!report.failed);
+        assert_eq!(post.failures(), 1);
+        assert_eq!(post.total_us(), 3_000);
+        for number in 1..=7 {
+            assert_eq!(Stage::from_number(number).map(Stage::number), Some(number));
+        }
+        assert_eq!(Stage::from_number(0), None);
+    }
+}
@@ -0,0 +1,70 @@
+//! Bus voltage & current measured by an INA219 connected to the shared I2C bus, e.g. in the supply
+//! line of the board.
+//!
+//! The INA219 runs with its default configuration (32 V bus range, ±320 mV shunt range, continuous
+//! conversion). The current is calculated from the shunt voltage, so its calibration register isn't
+//! needed.
+
+use crate::config::power as config;
+use crate::I2cBus;
+use stm32f4xx_hal::hal::blocking::i2c::{Write, WriteRead};
+
+const REG_CONFIG: u8 = 0x00;
+const REG_SHUNT_VOLTAGE: u8 = 0x01;
+const REG_BUS_VOLTAGE: u8 = 0x02;
+
+/// Power-on default of the configuration register.
+const DEFAULT_CONFIG: u16 = 0x399F;
+
+/// A sample of the power monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Power {
+    pub bus_mv: u32,
+    pub current_ua: i32,
+}
+
+impl Power {
+    pub fn power_mw(&self) -> i32 {
+        (self.bus_mv as i64 * self.current_ua as i64 / 1_000_000) as i32
+    }
+}
+
+pub struct PowerMonitor {
+    i2c: I2cBus,
+}
+
+impl PowerMonitor {
+    /// Set up the power monitor. Returns `None` if no INA219 is connected.
+    pub fn new(mut i2c: I2cBus) -> Option<Self> {
+        // reset to the default configuration, this also checks whether the INA219 is present
+        let [high, low] = (DEFAULT_CONFIG | 0x8000).to_be_bytes();
+        if i2c
+            .write(config::ADDRESS, &[REG_CONFIG, high, low])
+            .is_err()
+        {
+            defmt::warn!("no INA219 found");
+            return None;
+        }
+        defmt::trace!("INA219 set up");
+        Some(Self { i2c })
+    }
+
+    pub fn read(&mut self) -> Option<Power> {
+        let shunt = i16::from_be_bytes(self.read_register(REG_SHUNT_VOLTAGE)?);
+        let bus = u16::from_be_bytes(self.read_register(REG_BUS_VOLTAGE)?);
+        Some(Power {
+            // the bus voltage is in bits 15 - 3 with 4 mV per bit
+            bus_mv: (bus >> 3) as u32 * 4,
+            // 10 uV per bit
+            current_ua: (shunt as i64 * 10 * 1000 / config::SHUNT_MILLIOHM as i64) as i32,
+        })
+    }
+
+    fn read_register(&mut self, register: u8) -> Option<[u8; 2]> {
+        let mut data = [0; 2];
+        self.i2c
+            .write_read(config::ADDRESS, &[register], &mut data)
+            .ok()?;
+        Some(data)
+    }
+}
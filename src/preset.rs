@@ -0,0 +1,123 @@
+//! Built-in presets of the TOF sensor for typical targets & environments, each bundling the
+//! distance mode, the timing budget & the thresholds of the sensor.
+//!
+//! Unlike the [`crate::profile`]s the presets can't be changed. A preset is applied with the
+//! `preset` command, by clicking the user button four times (which selects the next one) or at
+//! boot with the DIP switch (see [`crate::boot_config`]), which then takes precedence over the
+//! active profile. The measurement settings of the applied preset can be stored with `save`, its
+//! thresholds are only kept until the next reset.
+
+use crate::range_sensor::Thresholds;
+use crate::settings::TofSettings;
+use vl53l1x_uld::DistanceMode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Preset {
+    /// Short distance mode at 20 Hz for close targets in a room.
+    IndoorShort,
+    /// Long distance mode with a long timing budget & a higher signal threshold, which suppresses
+    /// the noise of the ambient light.
+    OutdoorLong,
+    /// Bright targets (e.g. white walls or reflectors): a higher signal threshold & a tighter
+    /// sigma threshold, so that weak reflections (e.g. of the surroundings) are rejected.
+    HighReflectivity,
+    /// Dark targets: a long timing budget & lower thresholds, so that weak signals are accepted.
+    LowReflectivity,
+}
+
+impl Preset {
+    const ALL: [Preset; 4] = [
+        Preset::IndoorShort,
+        Preset::OutdoorLong,
+        Preset::HighReflectivity,
+        Preset::LowReflectivity,
+    ];
+
+    /// The name used in the commands.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::IndoorShort => "indoor-short",
+            Preset::OutdoorLong => "outdoor-long",
+            Preset::HighReflectivity => "high-reflectivity",
+            Preset::LowReflectivity => "low-reflectivity",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|preset| preset.name() == name)
+    }
+
+    /// The preset with this number (starting at 1), e.g. as selected with the DIP switch.
+    pub fn from_number(number: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(number).checked_sub(1)?).copied()
+    }
+
+    /// The preset following this one, wrapping around after the last one.
+    pub fn next(&self) -> Self {
+        Self::ALL[(*self as usize + 1) % Self::ALL.len()]
+    }
+
+    /// The number of the preset (starting at 1), e.g. for indicating it on the status LED.
+    pub fn number(&self) -> u8 {
+        *self as u8 + 1
+    }
+
+    pub fn settings(&self) -> TofSettings {
+        match self {
+            Preset::IndoorShort => TofSettings {
+                distance_mode: DistanceMode::Short,
+                timing_budget_ms: 50,
+                inter_measurement_ms: 50,
+            },
+            Preset::OutdoorLong => TofSettings {
+                distance_mode: DistanceMode::Long,
+                timing_budget_ms: 200,
+                inter_measurement_ms: 200,
+            },
+            Preset::HighReflectivity => TofSettings {
+                distance_mode: DistanceMode::Long,
+                timing_budget_ms: 50,
+                inter_measurement_ms: 100,
+            },
+            Preset::LowReflectivity => TofSettings {
+                distance_mode: DistanceMode::Long,
+                timing_budget_ms: 200,
+                inter_measurement_ms: 200,
+            },
+        }
+    }
+
+    pub fn thresholds(&self) -> Thresholds {
+        match self {
+            Preset::IndoorShort => Thresholds::DEFAULT,
+            Preset::OutdoorLong => Thresholds {
+                signal_kcps: 1_536,
+                sigma_mm: 20,
+            },
+            Preset::HighReflectivity => Thresholds {
+                signal_kcps: 2_048,
+                sigma_mm: 10,
+            },
+            Preset::LowReflectivity => Thresholds {
+                signal_kcps: 512,
+                sigma_mm: 30,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_numbers() {
+        for preset in Preset::ALL {
+            assert_eq!(Preset::from_name(preset.name()), Some(preset));
+            assert_eq!(Preset::from_number(preset.number()), Some(preset));
+        }
+        assert_eq!(Preset::from_number(0), None);
+        assert_eq!(Preset::from_number(5), None);
+        assert_eq!(Preset::LowReflectivity.next(), Preset::IndoorShort);
+    }
+}
@@ -0,0 +1,139 @@
+//! Named profiles with the measurement settings of the TOF sensor for different environments.
+//!
+//! Each profile starts with a preset, which is replaced once the current settings have been saved
+//! to it. The active profile is stored in the [`crate::eeprom`] and applied at boot, holding the
+//! user button during the reset selects the next one.
+
+use crate::eeprom::{self, Eeprom, Key};
+use crate::fast_mode;
+use crate::settings::TofSettings;
+use stm32f4xx_hal::flash;
+use stm32f4xx_hal::pac::FLASH;
+use stm32f4xx_hal::watchdog::IndependentWatchdog;
+use vl53l1x_uld::DistanceMode;
+
+/// Version of the format in which the profiles are stored.
+const EEPROM_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Profile {
+    /// Long distance mode for the full range without ambient light.
+    Indoor,
+    /// Short distance mode, which is less sensitive to ambient light.
+    Outdoor,
+    /// Fast measurements (20 Hz) for a responsive demo.
+    Demo,
+    /// The shortest timing budget & period of the sensor, see [`crate::fast_mode`].
+    Fast,
+}
+
+impl Profile {
+    const ALL: [Profile; 4] = [
+        Profile::Indoor,
+        Profile::Outdoor,
+        Profile::Demo,
+        Profile::Fast,
+    ];
+
+    /// The name used in the commands.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Profile::Indoor => "indoor",
+            Profile::Outdoor => "outdoor",
+            Profile::Demo => "demo",
+            Profile::Fast => "fast",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|profile| profile.name() == name)
+    }
+
+    /// The profile following this one, wrapping around after the last one.
+    pub fn next(&self) -> Self {
+        Self::ALL[(*self as usize + 1) % Self::ALL.len()]
+    }
+
+    /// The number of the profile (starting at 1), e.g. for indicating it on the status LED.
+    pub fn number(&self) -> u8 {
+        *self as u8 + 1
+    }
+
+    /// The settings used until others have been saved to the profile.
+    fn preset(&self) -> TofSettings {
+        match self {
+            Profile::Indoor => TofSettings {
+                distance_mode: DistanceMode::Long,
+                timing_budget_ms: 100,
+                inter_measurement_ms: 100,
+            },
+            Profile::Outdoor => TofSettings {
+                distance_mode: DistanceMode::Short,
+                timing_budget_ms: 100,
+                inter_measurement_ms: 100,
+            },
+            Profile::Demo => TofSettings {
+                distance_mode: DistanceMode::Long,
+                timing_budget_ms: 50,
+                inter_measurement_ms: 50,
+            },
+            Profile::Fast => fast_mode::settings(if crate::config::fast::LONG_DISTANCE {
+                DistanceMode::Long
+            } else {
+                DistanceMode::Short
+            }),
+        }
+    }
+
+    fn key(&self) -> Key {
+        match self {
+            Profile::Indoor => eeprom::keys::PROFILE_INDOOR,
+            Profile::Outdoor => eeprom::keys::PROFILE_OUTDOOR,
+            Profile::Demo => eeprom::keys::PROFILE_DEMO,
+            Profile::Fast => eeprom::keys::PROFILE_FAST,
+        }
+    }
+
+    /// The settings of the profile, the preset if none have been saved.
+    pub fn settings(&self, eeprom: Option<&Eeprom>, flash: &FLASH) -> TofSettings {
+        eeprom
+            .and_then(|eeprom| eeprom.read_array(flash, self.key(), EEPROM_VERSION))
+            .and_then(|value| TofSettings::decode(&value))
+            .unwrap_or_else(|| self.preset())
+    }
+
+    /// Save the settings to the profile, see [`Eeprom::write`] for the watchdog.
+    pub fn save(
+        &self,
+        settings: &TofSettings,
+        eeprom: &mut Eeprom,
+        flash: &mut FLASH,
+        watchdog: &mut IndependentWatchdog,
+    ) -> Result<(), flash::Error> {
+        let value = settings.encode();
+        eeprom.write(flash, self.key(), EEPROM_VERSION, &value, watchdog)
+    }
+
+    /// The active profile, `None` if none has been selected yet.
+    pub fn active(eeprom: &Eeprom, flash: &FLASH) -> Option<Self> {
+        let [index] = eeprom.read_array(flash, eeprom::keys::ACTIVE_PROFILE, EEPROM_VERSION)?;
+        Self::ALL.get(index as usize).copied()
+    }
+
+    /// Make this the active profile, which is applied at boot.
+    pub fn activate(
+        &self,
+        eeprom: &mut Eeprom,
+        flash: &mut FLASH,
+        watchdog: &mut IndependentWatchdog,
+    ) -> Result<(), flash::Error> {
+        let value = [*self as u8];
+        eeprom.write(
+            flash,
+            eeprom::keys::ACTIVE_PROFILE,
+            EEPROM_VERSION,
+            &value,
+            watchdog,
+        )
+    }
+}
@@ -0,0 +1,60 @@
+//! A LED on `PA6` (TIM3 CH1) whose brightness increases the closer the target is.
+
+use crate::config::proximity_led as config;
+use crate::telemetry::Measurement;
+use stm32f4xx_hal::pac::TIM3;
+use stm32f4xx_hal::timer::PwmChannel;
+use vl53l1x_uld::RangeStatus;
+
+/// Perceived brightness (0 - 16) to PWM duty cycle (0 - 65535) using a gamma of 2.2.
+const GAMMA: [u32; 17] = [
+    0, 147, 676, 1648, 3104, 5072, 7574, 10632, 14263, 18482, 23303, 28739, 34802, 41503, 48853,
+    56860, 65535,
+];
+
+pub struct ProximityLed {
+    pwm: PwmChannel<TIM3, 0>,
+}
+
+impl ProximityLed {
+    pub fn new(mut pwm: PwmChannel<TIM3, 0>) -> Self {
+        pwm.set_duty(0);
+        pwm.enable();
+        Self { pwm }
+    }
+
+    /// Set the brightness according to the measurement. The LED is off for invalid measurements.
+    pub fn update(&mut self, measurement: &Measurement) {
+        let brightness = if measurement.status == RangeStatus::Valid {
+            brightness(measurement.distance_mm)
+        } else {
+            0
+        };
+        let max_duty = self.pwm.get_max_duty() as u32;
+        self.pwm
+            .set_duty((gamma(brightness) * max_duty / 0xFFFF) as u16);
+    }
+
+    /// Turn the LED off as the TOF sensor is lost.
+    pub fn failsafe(&mut self) {
+        self.pwm.set_duty(0);
+    }
+}
+
+/// Perceived brightness (0 - 0xFFFF) for the distance: full brightness up to the minimum distance,
+/// off from the maximum distance and linear in between.
+fn brightness(distance_mm: u16) -> u32 {
+    let distance = distance_mm.clamp(config::MIN_MM, config::MAX_MM) as u32;
+    let range = (config::MAX_MM - config::MIN_MM) as u32;
+    (config::MAX_MM as u32 - distance) * 0xFFFF / range
+}
+
+/// Apply the gamma correction by interpolating between the entries of [`GAMMA`].
+fn gamma(brightness: u32) -> u32 {
+    let index = (brightness >> 12) as usize;
+    let fraction = brightness & 0xFFF;
+    match GAMMA.get(index + 1) {
+        Some(next) => GAMMA[index] + (next - GAMMA[index]) * fraction / 0x1000,
+        None => GAMMA[index],
+    }
+}
@@ -0,0 +1,93 @@
+//! A fixed cadence of the published measurements in the streaming mode, independent of the rate of
+//! the sensor: with `publish <interval_ms>` a single measurement is published at the end of each
+//! interval, with the mean of the valid measurements in between (see [`Averager`]), so that
+//! plotting tools get a regular stream. `publish off` publishes every measurement again.
+//!
+//! The interval is stored with `save`. The other application modes & the local outputs aren't
+//! affected.
+
+use crate::telemetry::Measurement;
+use vl53l1x_uld::RangeStatus;
+
+/// The shortest interval, the frames of a shorter one wouldn't fit through the UART.
+pub const MIN_INTERVAL_MS: u16 = 10;
+
+/// Combines the measurements of an interval.
+pub struct Averager {
+    latest: Option<Measurement>,
+    valid: u32,
+    sum_mm: u32,
+    sum_ambient_kcps: u32,
+}
+
+impl Averager {
+    pub const fn new() -> Self {
+        Self {
+            latest: None,
+            valid: 0,
+            sum_mm: 0,
+            sum_ambient_kcps: 0,
+        }
+    }
+
+    pub fn add(&mut self, measurement: &Measurement) {
+        if measurement.status == RangeStatus::Valid {
+            self.valid += 1;
+            self.sum_mm += measurement.distance_mm as u32;
+            self.sum_ambient_kcps += measurement.ambient_kcps as u32;
+        }
+        self.latest = Some(*measurement);
+    }
+
+    /// The measurements since the previous call combined into one: the latest one with the mean
+    /// distance & ambient rate of the valid ones (& a valid status if there has been one),
+    /// `None` if there hasn't been any measurement. The scaled value has to be calculated again.
+    pub fn take(&mut self) -> Option<Measurement> {
+        let mut measurement = self.latest.take()?;
+        if let Some(mean_mm) = self.sum_mm.checked_div(self.valid) {
+            measurement.status = RangeStatus::Valid;
+            measurement.distance_mm = mean_mm as u16;
+            measurement.ambient_kcps = (self.sum_ambient_kcps / self.valid) as u16;
+        }
+        *self = Self::new();
+        Some(measurement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test_measurement;
+
+    #[test]
+    fn averages_the_valid_measurements() {
+        let mut averager = Averager::new();
+        assert_eq!(averager.take(), None);
+        averager.add(&Measurement {
+            seq: 1,
+            ambient_kcps: 10,
+            ..test_measurement(10, 100)
+        });
+        averager.add(&Measurement {
+            seq: 2,
+            ambient_kcps: 20,
+            ..test_measurement(20, 200)
+        });
+        averager.add(&Measurement {
+            seq: 3,
+            status: RangeStatus::SignalFailure,
+            ..test_measurement(30, 0)
+        });
+        let mean = averager.take().unwrap();
+        assert_eq!((mean.seq, mean.distance_mm), (3, 150));
+        assert_eq!((mean.status, mean.ambient_kcps), (RangeStatus::Valid, 15));
+        // without a valid one the latest is published as it is
+        averager.add(&Measurement {
+            seq: 4,
+            status: RangeStatus::SignalFailure,
+            ..test_measurement(40, 0)
+        });
+        assert_eq!(averager.take().unwrap().status, RangeStatus::SignalFailure);
+        assert_eq!(averager.take(), None);
+    }
+}
@@ -0,0 +1,115 @@
+//! An indicator LED per zone of the [`crate::geofence`] on a PCA9685 16-channel PWM expander
+//! connected to the shared I2C bus (`pwm-expander` feature), e.g. for an installation with a zone
+//! per parking space. This doesn't need a timer channel of the microcontroller per LED.
+//!
+//! The indicator of zone `n` is on channel [`config::FIRST_CHANNEL`] + `n`, it's lit with
+//! [`config::BRIGHTNESS_PERCENT`] while the zone has the `indicator` action & is occupied. The
+//! outputs are push-pull, the LEDs are connected to them with a series resistor each. The "all
+//! call" address (`0x70`, also the one of the TCA9548A of [`crate::tof_mux`]) is disabled when
+//! the expander is set up.
+
+use crate::config::pwm_expander as config;
+use crate::geofence::MAX_ZONES;
+use crate::I2cBus;
+use stm32f4xx_hal::hal::blocking::i2c::Write;
+
+/// Number of channels of the expander.
+pub const CHANNELS: u8 = 16;
+
+const REG_MODE1: u8 = 0x00;
+const REG_LED0_ON_L: u8 = 0x06;
+const REG_ALL_LED_ON_L: u8 = 0xFA;
+const REG_PRE_SCALE: u8 = 0xFE;
+
+/// Auto-increment of the register address, the other bits of `MODE1` (e.g. all call) are cleared.
+const MODE1_AI: u8 = 0x20;
+/// The oscillator is off, which is needed to change the prescaler.
+const MODE1_SLEEP: u8 = 0x10;
+/// The bit in `LEDn_ON_H` & `LEDn_OFF_H` which keeps the output on or off.
+const FULL: u8 = 0x10;
+
+/// Frequency of the internal oscillator.
+const OSCILLATOR_HZ: u32 = 25_000_000;
+/// Number of steps of the PWM.
+const STEPS: u32 = 4096;
+
+/// The value of the prescaler for the PWM frequency, rounded to the closest one.
+pub const fn prescale(frequency_hz: u32) -> u8 {
+    ((OSCILLATOR_HZ + STEPS * frequency_hz / 2) / (STEPS * frequency_hz) - 1) as u8
+}
+
+/// The registers of a channel (starting with the address of `LEDn_ON_L`) for the duty cycle of
+/// `duty` out of 4096 steps, it's fully on from 4096 on.
+pub fn channel_registers(channel: u8, duty: u16) -> [u8; 5] {
+    let address = REG_LED0_ON_L + 4 * (channel % CHANNELS);
+    match duty {
+        0 => [address, 0, 0, 0, FULL],
+        4096.. => [address, 0, FULL, 0, 0],
+        _ => {
+            let [off_high, off_low] = duty.to_be_bytes();
+            [address, 0, 0, off_low, off_high]
+        }
+    }
+}
+
+pub struct Indicators {
+    i2c: I2cBus,
+    /// The lit indicators, bit `n` for zone `n`.
+    lit: u8,
+}
+
+impl Indicators {
+    /// Set up the expander with all indicators off. Returns `None` if no PCA9685 is connected.
+    pub fn new(mut i2c: I2cBus) -> Option<Self> {
+        let mut setup = || {
+            i2c.write(config::ADDRESS, &[REG_MODE1, MODE1_SLEEP | MODE1_AI])?;
+            i2c.write(
+                config::ADDRESS,
+                &[REG_PRE_SCALE, prescale(config::FREQUENCY_HZ)],
+            )?;
+            i2c.write(config::ADDRESS, &[REG_MODE1, MODE1_AI])?;
+            i2c.write(config::ADDRESS, &[REG_ALL_LED_ON_L, 0, 0, 0, FULL])
+        };
+        if setup().is_err() {
+            defmt::warn!("no PCA9685 found");
+            return None;
+        }
+        defmt::trace!("PCA9685 set up");
+        Some(Self { i2c, lit: 0 })
+    }
+
+    /// Light the indicators of the `zones` (bit `n` for zone `n`) & turn the others off. Only the
+    /// changed channels are written, a failed one is written again at the next update.
+    pub fn update(&mut self, zones: u8) {
+        let duty = (STEPS * config::BRIGHTNESS_PERCENT / 100) as u16;
+        let changed = zones ^ self.lit;
+        for zone in (0..MAX_ZONES as u8).filter(|zone| changed & 1 << zone != 0) {
+            let duty = if zones & 1 << zone != 0 { duty } else { 0 };
+            let registers = channel_registers(config::FIRST_CHANNEL + zone, duty);
+            match self.i2c.write(config::ADDRESS, &registers) {
+                Ok(()) => self.lit ^= 1 << zone,
+                Err(e) => defmt::warn!(
+                    "failed to switch the indicator of zone {}: {}",
+                    zone,
+                    defmt::Debug2Format(&e)
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prescale_and_duty_cycle() {
+        // the examples of the datasheet
+        assert_eq!(prescale(200), 0x1E);
+        assert_eq!(prescale(1_526), 0x03);
+
+        assert_eq!(channel_registers(0, 0), [0x06, 0, 0, 0, FULL]);
+        assert_eq!(channel_registers(15, 4096), [0x42, 0, FULL, 0, 0]);
+        assert_eq!(channel_registers(2, 1024), [0x0E, 0, 0, 0x00, 0x04]);
+    }
+}
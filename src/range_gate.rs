@@ -0,0 +1,107 @@
+//! Gating of the measurements by their distance: the valid measurements outside of the window
+//! (e.g. the ceiling, the floor or the housing of the sensor) are dropped right after they have
+//! been read, before any check or filter sees them, & counted.
+//!
+//! The window is set with [`config::MIN_MM`] & [`config::MAX_MM`] and can be changed with the
+//! `gate` command. The measurements which aren't valid keep their status, their distance is
+//! meaningless anyway.
+
+use crate::config::range_gate as config;
+use crate::range_sensor::Reading;
+use vl53l1x_uld::RangeStatus;
+
+pub struct RangeGate {
+    /// The shortest & the longest accepted distance, `None` if the gate is open.
+    window: Option<(u16, u16)>,
+    /// Number of measurements since boot which were outside of the window.
+    gated: u32,
+}
+
+impl RangeGate {
+    pub const fn new() -> Self {
+        Self {
+            window: if config::MAX_MM != 0 {
+                Some((config::MIN_MM, config::MAX_MM))
+            } else {
+                None
+            },
+            gated: 0,
+        }
+    }
+
+    pub fn window(&self) -> Option<(u16, u16)> {
+        self.window
+    }
+
+    /// Change the window (the bounds are accepted), `None` accepts every distance.
+    pub fn set_window(&mut self, window: Option<(u16, u16)>) {
+        self.window = window;
+    }
+
+    /// Check a measurement, returns whether it's accepted. A rejected one is counted.
+    pub fn passes(&mut self, reading: &Reading) -> bool {
+        let Some((min_mm, max_mm)) = self.window else {
+            return true;
+        };
+        if reading.status != RangeStatus::Valid || (min_mm..=max_mm).contains(&reading.distance_mm)
+        {
+            return true;
+        }
+        self.gated = self.gated.wrapping_add(1);
+        false
+    }
+
+    pub fn gated(&self) -> u32 {
+        self.gated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(distance_mm: u16, status: RangeStatus) -> Reading {
+        Reading {
+            distance_mm,
+            status,
+            ambient_kcps: 0,
+            signal_per_spad_kcps: 0,
+        }
+    }
+
+    #[test]
+    fn accepts_the_bounds_of_the_window() {
+        let mut gate = RangeGate::new();
+        gate.set_window(Some((100, 2_000)));
+        assert!(gate.passes(&reading(100, RangeStatus::Valid)));
+        assert!(gate.passes(&reading(2_000, RangeStatus::Valid)));
+        assert_eq!(gate.gated(), 0);
+    }
+
+    #[test]
+    fn drops_and_counts_the_valid_measurements_outside_of_the_window() {
+        let mut gate = RangeGate::new();
+        gate.set_window(Some((100, 2_000)));
+        assert!(!gate.passes(&reading(99, RangeStatus::Valid)));
+        assert!(!gate.passes(&reading(2_400, RangeStatus::Valid)));
+        assert_eq!(gate.gated(), 2);
+    }
+
+    #[test]
+    fn keeps_the_invalid_measurements() {
+        let mut gate = RangeGate::new();
+        gate.set_window(Some((100, 2_000)));
+        // the status of an invalid one tells the outputs to ignore it
+        assert!(gate.passes(&reading(20, RangeStatus::SignalFailure)));
+        assert_eq!(gate.gated(), 0);
+    }
+
+    #[test]
+    fn an_open_gate_accepts_every_distance() {
+        let mut gate = RangeGate::new();
+        gate.set_window(None);
+        assert!(gate.passes(&reading(20, RangeStatus::Valid)));
+        assert!(gate.passes(&reading(u16::MAX, RangeStatus::Valid)));
+        assert_eq!(gate.gated(), 0);
+    }
+}
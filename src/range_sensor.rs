@@ -0,0 +1,556 @@
+//! Abstraction of the TOF sensor: the tasks only use [`RangeSensor`], which [`crate::tof`]
+//! implements for the VL53L1X & [`crate::vl53l0x`] for the VL53L0X.
+//!
+//! Which sensor is connected is [`probe`]d at boot & the matching [`Backend`] is selected, so that
+//! different breakouts can be stocked. The VL53L4CD is detected as well but refused, it isn't
+//! driven with the model ID checks of the VL53L1X.
+
+use crate::calibration_store::CalibrationData;
+use crate::register_dump::Snapshot;
+use crate::settings::TofSettings;
+use crate::vl53l0x::Vl53l0x;
+use core::fmt::Debug;
+use stm32f4xx_hal::hal::blocking::i2c::{Write as I2cWrite, WriteRead};
+use vl53l1x_uld::comm::{Read, Write};
+use vl53l1x_uld::{RangeStatus, Register, VL53L1X};
+
+/// The model ID of the VL53L4CD, it uses the same registers as the VL53L1X.
+pub const VL53L4CD_MODEL_ID: u16 = 0xEBAA;
+/// The index of the VL53L0X model ID register, it has 8 bit indices.
+const VL53L0X_MODEL_ID_INDEX: u8 = 0xC0;
+/// The model ID of the VL53L0X followed by its module type in the next register.
+const VL53L0X_MODEL_ID: [u8; 2] = [0xEE, 0xAA];
+
+/// Reading registers with an 8 bit index, as the VL53L0X uses them.
+pub trait ReadShortIndex {
+    type Error: Debug;
+
+    /// Read registers from `index` on (auto incremented) into `bytes`.
+    fn read_short_index(
+        &mut self,
+        address: u8,
+        index: u8,
+        bytes: &mut [u8],
+    ) -> Result<(), Self::Error>;
+}
+
+impl<I2C> ReadShortIndex for I2C
+where
+    I2C: WriteRead,
+    I2C::Error: Debug,
+{
+    type Error = I2C::Error;
+
+    fn read_short_index(
+        &mut self,
+        address: u8,
+        index: u8,
+        bytes: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.write_read(address, &[index], bytes)
+    }
+}
+
+/// Writing registers with an 8 bit index, as the VL53L0X uses them.
+pub trait WriteShortIndex {
+    type Error: Debug;
+
+    /// Write `bytes` (at most [`MAX_SHORT_WRITE_LEN`]) to the registers from `index` on (auto
+    /// incremented).
+    fn write_short_index(
+        &mut self,
+        address: u8,
+        index: u8,
+        bytes: &[u8],
+    ) -> Result<(), Self::Error>;
+}
+
+/// The longest write with an 8 bit index, the reference SPAD map of the VL53L0X.
+pub const MAX_SHORT_WRITE_LEN: usize = 6;
+
+impl<I2C> WriteShortIndex for I2C
+where
+    I2C: I2cWrite,
+    I2C::Error: Debug,
+{
+    type Error = I2C::Error;
+
+    fn write_short_index(
+        &mut self,
+        address: u8,
+        index: u8,
+        bytes: &[u8],
+    ) -> Result<(), Self::Error> {
+        assert!(bytes.len() <= MAX_SHORT_WRITE_LEN);
+        let mut buffer = [0; MAX_SHORT_WRITE_LEN + 1];
+        buffer[0] = index;
+        buffer[1..=bytes.len()].copy_from_slice(bytes);
+        self.write(address, &buffer[..=bytes.len()])
+    }
+}
+
+/// The ST TOF sensors which can be told apart by their model ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Variant {
+    Vl53l0x,
+    Vl53l1x,
+    Vl53l4cd,
+}
+
+impl Variant {
+    /// Whether the firmware has a [`Backend`] for the sensor, all but the VL53L4CD.
+    pub fn is_supported(self) -> bool {
+        self != Variant::Vl53l4cd
+    }
+}
+
+/// Probe the model ID registers of the device at `address`, it's `None` if it's none of the
+/// [`Variant`]s.
+///
+/// The model ID of the VL53L0X is read first with an 8 bit index: the VL53L1X family only receives
+/// a partial index, which isn't a write. The 16 bit index of the model ID of the VL53L1X family is
+/// only sent if the device isn't a VL53L0X, which would take its second byte as a value written to
+/// a register.
+pub fn probe<B>(bus: &mut B, address: u8) -> Result<Option<Variant>, <B as Read>::Error>
+where
+    B: Read + ReadShortIndex<Error = <B as Read>::Error>,
+{
+    let mut id = [0; 2];
+    bus.read_short_index(address, VL53L0X_MODEL_ID_INDEX, &mut id)?;
+    if id == VL53L0X_MODEL_ID {
+        return Ok(Some(Variant::Vl53l0x));
+    }
+    bus.read_registers(address, Register::IDENTIFICATION__MODEL_ID.into(), &mut id)?;
+    Ok(match u16::from_be_bytes(id) {
+        crate::tof::MODEL_ID => Some(Variant::Vl53l1x),
+        VL53L4CD_MODEL_ID => Some(Variant::Vl53l4cd),
+        _ => None,
+    })
+}
+
+/// A measurement read from the sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reading {
+    pub distance_mm: u16,
+    pub status: RangeStatus,
+    /// The ambient rate, see [`crate::ambient`].
+    pub ambient_kcps: u16,
+    /// The signal rate per SPAD, see [`crate::lens`].
+    pub signal_per_spad_kcps: u16,
+}
+
+/// The optical configuration of the sensor, reported so that it can be verified (e.g. after
+/// changing the cover glass).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Optics {
+    /// Number of enabled SPADs of the whole array.
+    pub enabled_spads: u16,
+    /// Number of SPADs which the latest measurement used, 0 before the first one.
+    pub effective_spads: u16,
+    /// Size of the region of interest in SPADs.
+    pub roi_width: u8,
+    pub roi_height: u8,
+    /// Number of the SPAD in the centre of the region of interest.
+    pub center_spad: u8,
+}
+
+impl Optics {
+    /// Column & row (0 - 15) of the [`Optics::center_spad`] in the 16x16 array, the SPADs are
+    /// numbered in columns from the opposite corners for the two halves.
+    pub fn center(&self) -> (u8, u8) {
+        let spad = self.center_spad;
+        if spad >= 128 {
+            ((spad - 128) >> 3, 15 - (spad & 7))
+        } else {
+            (15 - (spad >> 3), spad & 7)
+        }
+    }
+}
+
+/// The limits of the signal rate & the standard deviation (sigma) of the distance beyond which the
+/// sensor reports a measurement as invalid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Thresholds {
+    /// Minimum signal rate of the target.
+    pub signal_kcps: u16,
+    /// Maximum sigma.
+    pub sigma_mm: u16,
+}
+
+impl Thresholds {
+    /// The defaults of the sensor.
+    pub const DEFAULT: Thresholds = Thresholds {
+        signal_kcps: 1_024,
+        sigma_mm: 15,
+    };
+}
+
+/// Reasons why the sensor could not be set up.
+#[derive(Debug)]
+pub enum SetupError<E: Debug> {
+    /// The communication with the sensor failed.
+    Sensor(E),
+    /// The device at the address isn't the expected sensor, it reported this model ID.
+    UnknownModel(u16),
+    /// The sensor hasn't finished booting in time.
+    NotBooted,
+}
+
+impl<E: Debug> From<E> for SetupError<E> {
+    fn from(error: E) -> Self {
+        SetupError::Sensor(error)
+    }
+}
+
+/// A ranging TOF sensor.
+pub trait RangeSensor {
+    /// An error of the communication with the sensor.
+    type Error: Debug;
+
+    /// Set up the sensor, apply the calibration & the settings (if any) and start ranging.
+    fn setup(
+        &mut self,
+        calibration: Option<CalibrationData>,
+        settings: Option<TofSettings>,
+    ) -> Result<(), SetupError<Self::Error>>;
+
+    /// Start ranging, the sensor signals each measurement with its interrupt.
+    fn start(&mut self) -> Result<(), Self::Error>;
+
+    fn stop(&mut self) -> Result<(), Self::Error>;
+
+    /// Whether a measurement is available, for polling instead of waiting for the interrupt.
+    fn is_data_ready(&mut self) -> Result<bool, Self::Error>;
+
+    /// Read the measurement once the sensor has signalled it with its interrupt. The interrupt is
+    /// cleared even if reading fails so that the sensor continues with the next measurement.
+    fn read(&mut self) -> Result<Reading, Self::Error>;
+
+    /// Apply the measurement settings, the sensor must not be ranging.
+    fn configure(&mut self, settings: &TofSettings) -> Result<(), Self::Error>;
+
+    /// Apply the thresholds of the measurements.
+    fn set_thresholds(&mut self, thresholds: &Thresholds) -> Result<(), Self::Error>;
+
+    /// Only signal the measurements closer than `below_mm` with the interrupt (all of them for
+    /// `None`), e.g. so that the microcontroller sleeps until a target appears. The sensor must
+    /// not be ranging.
+    fn set_interrupt_threshold(&mut self, below_mm: Option<u16>) -> Result<(), Self::Error>;
+
+    /// The current measurement settings.
+    fn settings(&mut self) -> Result<TofSettings, Self::Error>;
+
+    /// The current calibration.
+    fn calibration(&mut self) -> Result<CalibrationData, Self::Error>;
+
+    /// Apply a calibration, e.g. to restore the previous one.
+    fn set_calibration(&mut self, calibration: &CalibrationData) -> Result<(), Self::Error>;
+
+    /// The current optical configuration.
+    fn optics(&mut self) -> Result<Optics, Self::Error>;
+
+    /// A snapshot of the registers for a bug report.
+    fn registers(&mut self) -> Result<Snapshot, Self::Error>;
+
+    /// Calibrate the sensor for the current temperature & continue ranging.
+    fn recalibrate_temperature(&mut self) -> Result<(), Self::Error>;
+
+    /// Start the offset calibration: the offset is cleared & the sensor is ranging afterwards.
+    /// Returns the previous offset.
+    fn start_offset_calibration(&mut self, ranging: bool) -> Result<i16, Self::Error>;
+
+    /// Finish the offset calibration with the `offset`, the sensor is stopped again unless it
+    /// `was_ranging` before.
+    fn finish_offset_calibration(
+        &mut self,
+        offset: i16,
+        was_ranging: bool,
+    ) -> Result<(), Self::Error>;
+
+    /// Start the crosstalk calibration: the crosstalk correction is cleared & the sensor is
+    /// ranging afterwards.
+    fn start_cross_talk_calibration(&mut self, ranging: bool) -> Result<(), Self::Error>;
+
+    /// Finish the crosstalk calibration with the `cross_talk_cps`, the sensor is stopped again
+    /// unless it `was_ranging` before.
+    fn finish_cross_talk_calibration(
+        &mut self,
+        cross_talk_cps: u16,
+        was_ranging: bool,
+    ) -> Result<(), Self::Error>;
+
+    /// Change settings which can only be changed while the sensor isn't ranging, it's stopped
+    /// before & started again afterwards if it's `ranging`.
+    fn reconfigure(
+        &mut self,
+        ranging: bool,
+        configure: impl FnOnce(&mut Self) -> Result<(), Self::Error>,
+    ) -> Result<(), Self::Error>
+    where
+        Self: Sized,
+    {
+        if ranging {
+            self.stop()?;
+        }
+        configure(self)?;
+        if ranging {
+            self.start()?;
+        }
+        Ok(())
+    }
+}
+
+/// An error of one of the [`Backend`]s.
+#[derive(Debug)]
+pub enum Error<E: Debug> {
+    Vl53l1x(crate::tof::Error<E>),
+    Vl53l0x(crate::vl53l0x::Error<E>),
+}
+
+impl<E: Debug> SetupError<E> {
+    fn map<F: Debug>(self, map: impl FnOnce(E) -> F) -> SetupError<F> {
+        match self {
+            SetupError::Sensor(error) => SetupError::Sensor(map(error)),
+            SetupError::UnknownModel(id) => SetupError::UnknownModel(id),
+            SetupError::NotBooted => SetupError::NotBooted,
+        }
+    }
+}
+
+/// The driver of the [`Variant`] which has been [`probe`]d.
+pub enum Backend<B: Write + Read> {
+    Vl53l1x(VL53L1X<B>),
+    Vl53l0x(Vl53l0x<B>),
+}
+
+/// Call the method on the driver of the backend & wrap its error.
+macro_rules! dispatch {
+    ($backend:expr, $sensor:ident => $call:expr) => {
+        match $backend {
+            Backend::Vl53l1x($sensor) => $call.map_err(Error::Vl53l1x),
+            Backend::Vl53l0x($sensor) => $call.map_err(Error::Vl53l0x),
+        }
+    };
+}
+
+impl<B, E> Backend<B>
+where
+    B: Write<Error = E> + Read<Error = E> + ReadShortIndex<Error = E> + WriteShortIndex<Error = E>,
+    E: Debug,
+{
+    /// The backend of the `variant`, an unknown (or silent) sensor is driven as a VL53L1X, whose
+    /// setup retries & reports it.
+    pub fn new(variant: Option<Variant>, bus: B, address: u8) -> Self {
+        match variant {
+            Some(Variant::Vl53l0x) => Backend::Vl53l0x(Vl53l0x::new(bus, address)),
+            _ => Backend::Vl53l1x(VL53L1X::new(bus, address)),
+        }
+    }
+
+    /// Change the I2C address of the sensor, it keeps it until it's powered off.
+    pub fn set_address(&mut self, address: u8) -> Result<(), Error<E>> {
+        dispatch!(self, sensor => sensor.set_address(address))
+    }
+}
+
+impl<B, E> RangeSensor for Backend<B>
+where
+    B: Write<Error = E> + Read<Error = E> + ReadShortIndex<Error = E> + WriteShortIndex<Error = E>,
+    E: Debug,
+{
+    type Error = Error<E>;
+
+    fn setup(
+        &mut self,
+        calibration: Option<CalibrationData>,
+        settings: Option<TofSettings>,
+    ) -> Result<(), SetupError<Error<E>>> {
+        match self {
+            Backend::Vl53l1x(sensor) => sensor
+                .setup(calibration, settings)
+                .map_err(|e| e.map(Error::Vl53l1x)),
+            Backend::Vl53l0x(sensor) => sensor
+                .setup(calibration, settings)
+                .map_err(|e| e.map(Error::Vl53l0x)),
+        }
+    }
+
+    fn start(&mut self) -> Result<(), Error<E>> {
+        dispatch!(self, sensor => sensor.start())
+    }
+
+    fn stop(&mut self) -> Result<(), Error<E>> {
+        dispatch!(self, sensor => sensor.stop())
+    }
+
+    fn is_data_ready(&mut self) -> Result<bool, Error<E>> {
+        dispatch!(self, sensor => RangeSensor::is_data_ready(sensor))
+    }
+
+    fn read(&mut self) -> Result<Reading, Error<E>> {
+        dispatch!(self, sensor => sensor.read())
+    }
+
+    fn configure(&mut self, settings: &TofSettings) -> Result<(), Error<E>> {
+        dispatch!(self, sensor => sensor.configure(settings))
+    }
+
+    fn set_thresholds(&mut self, thresholds: &Thresholds) -> Result<(), Error<E>> {
+        dispatch!(self, sensor => sensor.set_thresholds(thresholds))
+    }
+
+    fn set_interrupt_threshold(&mut self, below_mm: Option<u16>) -> Result<(), Error<E>> {
+        dispatch!(self, sensor => sensor.set_interrupt_threshold(below_mm))
+    }
+
+    fn settings(&mut self) -> Result<TofSettings, Error<E>> {
+        dispatch!(self, sensor => sensor.settings())
+    }
+
+    fn calibration(&mut self) -> Result<CalibrationData, Error<E>> {
+        dispatch!(self, sensor => sensor.calibration())
+    }
+
+    fn set_calibration(&mut self, calibration: &CalibrationData) -> Result<(), Error<E>> {
+        dispatch!(self, sensor => sensor.set_calibration(calibration))
+    }
+
+    fn optics(&mut self) -> Result<Optics, Error<E>> {
+        dispatch!(self, sensor => sensor.optics())
+    }
+
+    fn registers(&mut self) -> Result<Snapshot, Error<E>> {
+        dispatch!(self, sensor => sensor.registers())
+    }
+
+    fn recalibrate_temperature(&mut self) -> Result<(), Error<E>> {
+        dispatch!(self, sensor => sensor.recalibrate_temperature())
+    }
+
+    fn start_offset_calibration(&mut self, ranging: bool) -> Result<i16, Error<E>> {
+        dispatch!(self, sensor => sensor.start_offset_calibration(ranging))
+    }
+
+    fn finish_offset_calibration(
+        &mut self,
+        offset: i16,
+        was_ranging: bool,
+    ) -> Result<(), Error<E>> {
+        dispatch!(self, sensor => sensor.finish_offset_calibration(offset, was_ranging))
+    }
+
+    fn start_cross_talk_calibration(&mut self, ranging: bool) -> Result<(), Error<E>> {
+        dispatch!(self, sensor => sensor.start_cross_talk_calibration(ranging))
+    }
+
+    fn finish_cross_talk_calibration(
+        &mut self,
+        cross_talk_cps: u16,
+        was_ranging: bool,
+    ) -> Result<(), Error<E>> {
+        dispatch!(self, sensor => sensor.finish_cross_talk_calibration(cross_talk_cps, was_ranging))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::i2c::{Mock, Transaction};
+    use embedded_hal_mock::MockError;
+    use std::io::ErrorKind;
+    use vl53l1x_uld::roi::ROICenter;
+    use vl53l1x_uld::DEFAULT_ADDRESS;
+
+    fn read_model_id(response: &[u8]) -> Transaction {
+        let register: [u8; 2] = Register::IDENTIFICATION__MODEL_ID.into();
+        Transaction::write_read(DEFAULT_ADDRESS, register.to_vec(), response.to_vec())
+    }
+
+    fn read_vl53l0x(response: &[u8]) -> Transaction {
+        Transaction::write_read(
+            DEFAULT_ADDRESS,
+            vec![VL53L0X_MODEL_ID_INDEX],
+            response.to_vec(),
+        )
+    }
+
+    fn probe_with(transactions: &[Transaction]) -> Result<Option<Variant>, MockError> {
+        let mut bus = Mock::new(transactions);
+        let variant = probe(&mut bus, DEFAULT_ADDRESS);
+        bus.done();
+        variant
+    }
+
+    #[test]
+    fn center_is_the_inverse_of_the_spad_number() {
+        let center = |center_spad| {
+            let optics = Optics {
+                enabled_spads: 0,
+                effective_spads: 0,
+                roi_width: 16,
+                roi_height: 16,
+                center_spad,
+            };
+            optics.center()
+        };
+        for x in 0..16 {
+            for y in 0..16 {
+                assert_eq!(center(ROICenter::new(x, y).spad), (x, y));
+            }
+        }
+        // the default centre
+        assert_eq!(center(199), (8, 8));
+    }
+
+    #[test]
+    fn vl53l1x_family_is_identified_by_the_model_id() {
+        let id = crate::tof::MODEL_ID.to_be_bytes();
+        let transactions = [read_vl53l0x(&[0x12, 0x34]), read_model_id(&id)];
+        assert_eq!(probe_with(&transactions), Ok(Some(Variant::Vl53l1x)));
+        let id = VL53L4CD_MODEL_ID.to_be_bytes();
+        let transactions = [read_vl53l0x(&[0x12, 0x34]), read_model_id(&id)];
+        assert_eq!(probe_with(&transactions), Ok(Some(Variant::Vl53l4cd)));
+        let transactions = [read_vl53l0x(&[0x12, 0x34]), read_model_id(&[0x56, 0x78])];
+        assert_eq!(probe_with(&transactions), Ok(None));
+    }
+
+    #[test]
+    fn vl53l0x_is_probed_without_a_16_bit_index() {
+        let transactions = [read_vl53l0x(&VL53L0X_MODEL_ID)];
+        assert_eq!(probe_with(&transactions), Ok(Some(Variant::Vl53l0x)));
+        assert!(Variant::Vl53l0x.is_supported());
+        assert!(!Variant::Vl53l4cd.is_supported());
+    }
+
+    #[test]
+    fn backend_of_the_probed_variant_is_selected() {
+        let mut bus = Mock::new(&[]);
+        assert!(matches!(
+            Backend::new(None, bus.clone(), DEFAULT_ADDRESS),
+            Backend::Vl53l1x(_)
+        ));
+        assert!(matches!(
+            Backend::new(Some(Variant::Vl53l1x), bus.clone(), DEFAULT_ADDRESS),
+            Backend::Vl53l1x(_)
+        ));
+        bus.done();
+
+        // the VL53L0X is read with 8 bit indices
+        let transactions = [Transaction::write_read(
+            DEFAULT_ADDRESS,
+            vec![0x13],
+            vec![0x04],
+        )];
+        let mut bus = Mock::new(&transactions);
+        let mut backend = Backend::new(Some(Variant::Vl53l0x), bus.clone(), DEFAULT_ADDRESS);
+        assert!(matches!(backend, Backend::Vl53l0x(_)));
+        assert!(matches!(backend.is_data_ready(), Ok(true)));
+        bus.done();
+    }
+
+    #[test]
+    fn missing_sensor_is_an_error() {
+        let transactions = [read_vl53l0x(&[0, 0]).with_error(MockError::Io(ErrorKind::Other))];
+        assert!(probe_with(&transactions).is_err());
+    }
+}
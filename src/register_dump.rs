@@ -0,0 +1,179 @@
+//! A snapshot of the configuration & result registers of the TOF sensor for `dump regs`, so that
+//! a bug report can include the complete state of the sensor: its identity, the interrupt, the
+//! calibration, the timing, the thresholds, the region of interest (of the VL53L1X) & the latest
+//! result. Each [`crate::range_sensor::Backend`] has its own table of registers.
+//!
+//! The registers are read one after the other while the sensor may be ranging, thus the result
+//! registers may belong to different measurements.
+
+use core::fmt;
+use vl53l1x_uld::Register;
+
+/// A register of the snapshot.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// The label in the snapshot.
+    pub name: &'static str,
+    /// The index of the register, 16 bit for the VL53L1X & 8 bit for the VL53L0X.
+    pub index: u16,
+    /// The width of the value in bytes (1, 2 or 4).
+    pub len: usize,
+}
+
+const fn entry(name: &'static str, register: Register, len: usize) -> Entry {
+    short_entry(name, register as u16, len)
+}
+
+const fn short_entry(name: &'static str, index: u16, len: usize) -> Entry {
+    Entry { name, index, len }
+}
+
+/// The number of registers of each snapshot.
+pub const LEN: usize = 25;
+
+/// The registers of the snapshot of the VL53L1X in their order.
+pub const REGISTERS: [Entry; LEN] = [
+    entry("i2c_addr", Register::I2C_SLAVE__DEVICE_ADDRESS, 1),
+    entry("model", Register::IDENTIFICATION__MODEL_ID, 2),
+    entry("revision", Register::IDENTIFICATION__REVISION_ID, 1),
+    entry("status", Register::FIRMWARE__SYSTEM_STATUS, 1),
+    entry("gpio_mux", Register::GPIO_HV_MUX__CTRL, 1),
+    entry("int_config", Register::SYSTEM__INTERRUPT_CONFIG_GPIO, 1),
+    entry(
+        "xtalk",
+        Register::ALGO__CROSSTALK_COMPENSATION_PLANE_OFFSET_KCPS,
+        2,
+    ),
+    entry("offset", Register::ALGO__PART_TO_PART_RANGE_OFFSET_MM, 2),
+    entry("timeout_a", Register::RANGE_CONFIG__TIMEOUT_MACROP_A_HI, 2),
+    entry("vcsel_a", Register::RANGE_CONFIG__VCSEL_PERIOD_A, 1),
+    entry("timeout_b", Register::RANGE_CONFIG__TIMEOUT_MACROP_B_HI, 2),
+    entry("vcsel_b", Register::RANGE_CONFIG__VCSEL_PERIOD_B, 1),
+    entry("inter_period", Register::SYSTEM__INTERMEASUREMENT_PERIOD, 4),
+    entry("sigma", Register::RANGE_CONFIG__SIGMA_THRESH, 2),
+    entry(
+        "min_rate",
+        Register::RANGE_CONFIG__MIN_COUNT_RATE_RTN_LIMIT_MCPS,
+        2,
+    ),
+    entry("thresh_high", Register::SYSTEM__THRESH_HIGH, 2),
+    entry("thresh_low", Register::SYSTEM__THRESH_LOW, 2),
+    entry("roi_center", Register::ROI_CONFIG__USER_ROI_CENTRE_SPAD, 1),
+    entry(
+        "roi_size",
+        Register::ROI_CONFIG__USER_ROI_REQUESTED_GLOBAL_XY_SIZE,
+        1,
+    ),
+    entry("mode_start", Register::SYSTEM__MODE_START, 1),
+    entry("range_status", Register::RESULT__RANGE_STATUS, 1),
+    entry("spads", Register::RESULT__DSS_ACTUAL_EFFECTIVE_SPADS_SD0, 2),
+    entry("ambient", Register::RESULT__AMBIENT_COUNT_RATE_MCPS_SD0, 2),
+    entry(
+        "range_mm",
+        Register::RESULT__FINAL_CROSSTALK_CORRECTED_RANGE_MM_SD0,
+        2,
+    ),
+    entry(
+        "signal",
+        Register::RESULT__PEAK_SIGNAL_COUNT_RATE_CROSSTALK_CORRECTED_MCPS_SD0,
+        2,
+    ),
+];
+
+/// The registers of the snapshot of the VL53L0X in their order.
+pub const VL53L0X_REGISTERS: [Entry; LEN] = [
+    short_entry("i2c_addr", 0x8A, 1),
+    short_entry("model", 0xC0, 1),
+    short_entry("revision", 0xC2, 1),
+    short_entry("sequence", 0x01, 1),
+    short_entry("gpio_mux", 0x84, 1),
+    short_entry("int_config", 0x0A, 1),
+    short_entry("xtalk", 0x20, 2),
+    short_entry("offset", 0x28, 2),
+    short_entry("timeout_msrc", 0x46, 1),
+    short_entry("timeout_pre", 0x51, 2),
+    short_entry("vcsel_pre", 0x50, 1),
+    short_entry("timeout_final", 0x71, 2),
+    short_entry("vcsel_final", 0x70, 1),
+    short_entry("inter_period", 0x04, 4),
+    short_entry("osc_cal", 0xF8, 2),
+    short_entry("min_rate", 0x44, 2),
+    short_entry("thresh_high", 0x0C, 2),
+    short_entry("thresh_low", 0x0E, 2),
+    short_entry("mode_start", 0x00, 1),
+    short_entry("int_status", 0x13, 1),
+    short_entry("range_status", 0x14, 1),
+    short_entry("spads", 0x16, 2),
+    short_entry("signal", 0x1A, 2),
+    short_entry("ambient", 0x1C, 2),
+    short_entry("range_mm", 0x1E, 2),
+];
+
+/// The values of the `registers`, formatted as `<name>=<hex>` separated by spaces with as many
+/// digits as the register is wide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    pub registers: &'static [Entry; LEN],
+    pub values: [u32; LEN],
+}
+
+/// Read the snapshot of the `registers` with `read`, which fills the bytes of a register (big
+/// endian) at its index.
+pub fn read<E>(
+    registers: &'static [Entry; LEN],
+    mut read: impl FnMut(u16, &mut [u8]) -> Result<(), E>,
+) -> Result<Snapshot, E> {
+    let mut values = [0; LEN];
+    for (value, entry) in values.iter_mut().zip(registers) {
+        let mut bytes = [0; 4];
+        read(entry.index, &mut bytes[4 - entry.len..])?;
+        *value = u32::from_be_bytes(bytes);
+    }
+    Ok(Snapshot { registers, values })
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (value, entry)) in self.values.iter().zip(self.registers).enumerate() {
+            let separator = if i == 0 { "" } else { " " };
+            let width = 2 + 2 * entry.len;
+            write!(f, "{}{}={:#0width$x}", separator, entry.name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn widest_snapshot_fits_into_a_response() {
+        let snapshot = read(&REGISTERS, |index, bytes: &mut [u8]| {
+            bytes.fill(0xFF);
+            if index == Register::IDENTIFICATION__MODEL_ID as u16 {
+                bytes.copy_from_slice(&crate::tof::MODEL_ID.to_be_bytes());
+            }
+            Ok::<(), ()>(())
+        })
+        .unwrap();
+        let mut response = crate::command::Response::new();
+        write!(response, "OK {}\r\n", snapshot).unwrap();
+        assert!(response.starts_with("OK i2c_addr=0xff model=0xeacc revision=0xff"));
+        assert!(response.contains(" inter_period=0xffffffff "));
+    }
+
+    #[test]
+    fn snapshot_of_the_vl53l0x_fits_into_a_response() {
+        let snapshot = read(&VL53L0X_REGISTERS, |index, bytes: &mut [u8]| {
+            bytes.fill(if index == 0xC0 { 0xEE } else { 0xFF });
+            Ok::<(), ()>(())
+        })
+        .unwrap();
+        let mut response = crate::command::Response::new();
+        write!(response, "OK {}\r\n", snapshot).unwrap();
+        assert!(response.starts_with("OK i2c_addr=0xff model=0xee revision=0xff"));
+        assert!(response.ends_with(" range_mm=0xffff\r\n"));
+    }
+}
@@ -0,0 +1,199 @@
+//! A boot counter & a log of the causes of the last [`LOG_LEN`] resets, stored in the
+//! [`crate::eeprom`] to diagnose devices which reset sporadically.
+//!
+//! The calendar time is only known once the host has set the [`crate::clock`], thus each entry
+//! records for how long the firmware had been running before the reset instead, which is kept in
+//! the backup registers of the clock.
+
+use crate::eeprom::{self, Eeprom, Key};
+use core::fmt::Write;
+use stm32f4xx_hal::flash;
+use stm32f4xx_hal::pac::{FLASH, RCC};
+use stm32f4xx_hal::watchdog::IndependentWatchdog;
+
+/// Number of resets which are logged.
+pub const LOG_LEN: usize = 6;
+/// Version of the format in which the counter & the log are stored.
+const EEPROM_VERSION: u8 = 1;
+/// The uptime is stored in 24 bits, this marks an unknown one.
+const UNKNOWN_UPTIME: u32 = 0xFF_FFFF;
+
+/// The cause of a reset as reported by the RCC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ResetCause {
+    PowerOn,
+    Brownout,
+    /// The reset pin, e.g. the reset button or the debugger.
+    Pin,
+    /// A reset requested by the firmware.
+    Software,
+    IndependentWatchdog,
+    WindowWatchdog,
+    /// An illegal entry into a low power mode.
+    LowPower,
+    Unknown,
+}
+
+impl ResetCause {
+    /// Read & clear the reset flags. Several flags are set for some resets (e.g. a power-on reset
+    /// also sets the ones for the brownout & the pin), thus the most specific one is reported.
+    pub fn read(rcc: &RCC) -> Self {
+        let csr = rcc.csr.read();
+        let cause = if csr.lpwrrstf().bit_is_set() {
+            ResetCause::LowPower
+        } else if csr.wwdgrstf().bit_is_set() {
+            ResetCause::WindowWatchdog
+        } else if csr.wdgrstf().bit_is_set() {
+            ResetCause::IndependentWatchdog
+        } else if csr.sftrstf().bit_is_set() {
+            ResetCause::Software
+        } else if csr.porrstf().bit_is_set() {
+            ResetCause::PowerOn
+        } else if csr.borrstf().bit_is_set() {
+            ResetCause::Brownout
+        } else if csr.padrstf().bit_is_set() {
+            ResetCause::Pin
+        } else {
+            ResetCause::Unknown
+        };
+        rcc.csr.modify(|_, w| w.rmvf().set_bit());
+        cause
+    }
+
+    /// The short name used in the response to the `resets` command.
+    fn name(&self) -> &'static str {
+        match self {
+            ResetCause::PowerOn => "por",
+            ResetCause::Brownout => "bor",
+            ResetCause::Pin => "pin",
+            ResetCause::Software => "sw",
+            ResetCause::IndependentWatchdog => "iwdg",
+            ResetCause::WindowWatchdog => "wwdg",
+            ResetCause::LowPower => "lpwr",
+            ResetCause::Unknown => "unknown",
+        }
+    }
+
+    const ALL: [ResetCause; 8] = [
+        ResetCause::PowerOn,
+        ResetCause::Brownout,
+        ResetCause::Pin,
+        ResetCause::Software,
+        ResetCause::IndependentWatchdog,
+        ResetCause::WindowWatchdog,
+        ResetCause::LowPower,
+        ResetCause::Unknown,
+    ];
+}
+
+/// An entry of the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ResetRecord {
+    /// The number of the boot following the reset.
+    pub boot: u32,
+    pub cause: ResetCause,
+    /// For how long the firmware had been running before the reset, `None` if unknown (e.g.
+    /// after a loss of power).
+    pub uptime_s: Option<u32>,
+}
+
+impl ResetRecord {
+    /// Layout: boot, cause & the uptime in 24 bits.
+    fn encode(&self) -> [u8; 8] {
+        let mut value = [0; 8];
+        value[..4].copy_from_slice(&self.boot.to_le_bytes());
+        value[4] = self.cause as u8;
+        let uptime_s = self
+            .uptime_s
+            .map_or(UNKNOWN_UPTIME, |uptime_s| uptime_s.min(UNKNOWN_UPTIME - 1));
+        value[5..].copy_from_slice(&uptime_s.to_le_bytes()[..3]);
+        value
+    }
+
+    fn decode(value: &[u8; 8]) -> Option<Self> {
+        let uptime_s = u32::from_le_bytes([value[5], value[6], value[7], 0]);
+        Some(Self {
+            boot: u32::from_le_bytes(value[..4].try_into().ok()?),
+            cause: *ResetCause::ALL.get(value[4] as usize)?,
+            uptime_s: (uptime_s != UNKNOWN_UPTIME).then_some(uptime_s),
+        })
+    }
+}
+
+/// Count the boot & log the cause of the reset. Returns the number of this boot.
+pub fn log_boot(
+    eeprom: &mut Eeprom,
+    flash: &mut FLASH,
+    cause: ResetCause,
+    uptime_s: Option<u32>,
+    watchdog: &mut IndependentWatchdog,
+) -> Result<u32, flash::Error> {
+    let boot = boot_count(eeprom, flash).wrapping_add(1);
+    let record = ResetRecord {
+        boot,
+        cause,
+        uptime_s,
+    };
+    // the record is written first, thus an interrupted write doesn't overwrite the oldest entry
+    // on the next boot without having logged this one
+    eeprom.write(
+        flash,
+        log_key(boot),
+        EEPROM_VERSION,
+        &record.encode(),
+        watchdog,
+    )?;
+    eeprom.write(
+        flash,
+        eeprom::keys::BOOT_COUNT,
+        EEPROM_VERSION,
+        &boot.to_le_bytes(),
+        watchdog,
+    )?;
+    Ok(boot)
+}
+
+/// The number of boots so far.
+pub fn boot_count(eeprom: &Eeprom, flash: &FLASH) -> u32 {
+    eeprom
+        .read_array(flash, eeprom::keys::BOOT_COUNT, EEPROM_VERSION)
+        .map_or(0, u32::from_le_bytes)
+}
+
+/// The logged resets, starting with the oldest one.
+pub fn records<'a>(eeprom: &'a Eeprom, flash: &'a FLASH) -> impl Iterator<Item = ResetRecord> + 'a {
+    let boot = boot_count(eeprom, flash);
+    (0..LOG_LEN as u32).rev().filter_map(move |age| {
+        let expected = boot.checked_sub(age)?;
+        eeprom
+            .read_array(flash, log_key(expected), EEPROM_VERSION)
+            .and_then(|value| ResetRecord::decode(&value))
+            // skips the entries of older boots, e.g. if a boot couldn't be logged
+            .filter(|record| record.boot == expected)
+    })
+}
+
+/// Write the response to the `resets` command: the number of boots & the logged resets (cause &
+/// the uptime before it) starting with the oldest one.
+pub fn write_response(
+    eeprom: &Eeprom,
+    flash: &FLASH,
+    response: &mut impl Write,
+) -> core::fmt::Result {
+    write!(response, "OK boots={} resets=", boot_count(eeprom, flash))?;
+    for (i, record) in records(eeprom, flash).enumerate() {
+        if i > 0 {
+            write!(response, ",")?;
+        }
+        write!(response, "{}@", record.cause.name())?;
+        match record.uptime_s {
+            Some(uptime_s) => write!(response, "{}s", uptime_s)?,
+            None => write!(response, "-")?,
+        }
+    }
+    write!(response, "\r\n")
+}
+
+fn log_key(boot: u32) -> Key {
+    eeprom::keys::RESET_LOG + (boot % LOG_LEN as u32) as Key
+}
@@ -0,0 +1,238 @@
+//! Periodic statistics of the measurements (a rollup), sent every [`crate::config::rollup`]
+//! interval so that long-term monitoring doesn't need every single measurement, see
+//! [`crate::app_mode::AppMode::Rollup`].
+//!
+//! It includes the achieved measurement rate, which is flagged if it deviates from the configured
+//! inter-measurement period by more than [`config::RATE_TOLERANCE_PERCENT`] (e.g. because the bus
+//! is busy or the timing budget exceeds the period).
+//!
+//! The accumulator is mirrored into the backup registers of the [`crate::clock`] with every
+//! measurement, so that the rollup in progress continues after a reset.
+
+use crate::config::rollup as config;
+use crate::telemetry::{Measurement, Rollup};
+use vl53l1x_uld::RangeStatus;
+
+/// Number of backup registers which hold the accumulator, see [`RollupAccumulator::backup`].
+pub const BACKUP_REGISTERS: usize = 7;
+
+/// Collects the statistics of the measurements since the last rollup.
+pub struct RollupAccumulator {
+    /// Time since boot at which the collection has been started.
+    start_ms: u32,
+    measurements: u32,
+    /// Number of measurements with a valid distance.
+    valid: u32,
+    /// Number of measurements with a present target, see [`crate::app_mode::PresenceDetector`].
+    present: u32,
+    /// Sum of the valid distances.
+    sum_mm: u64,
+    min_mm: u16,
+    max_mm: u16,
+}
+
+impl RollupAccumulator {
+    pub const fn new(start_ms: u32) -> Self {
+        Self {
+            start_ms,
+            measurements: 0,
+            valid: 0,
+            present: 0,
+            sum_mm: 0,
+            min_mm: u16::MAX,
+            max_mm: 0,
+        }
+    }
+
+    /// Add a measurement, `present` is the presence of the target after it.
+    pub fn add(&mut self, measurement: &Measurement, present: bool) {
+        self.measurements += 1;
+        self.present += present as u32;
+        if measurement.status == RangeStatus::Valid {
+            self.valid += 1;
+            self.sum_mm += measurement.distance_mm as u64;
+            self.min_mm = self.min_mm.min(measurement.distance_mm);
+            self.max_mm = self.max_mm.max(measurement.distance_mm);
+        }
+    }
+
+    /// Time since the start of the rollup in progress at `now_ms`.
+    pub fn elapsed_ms(&self, now_ms: u32) -> u32 {
+        now_ms.wrapping_sub(self.start_ms)
+    }
+
+    /// The state as the values of the backup registers at `now_ms`: the time since the start, the
+    /// counters, the sum (low & high word) & the minimum & the maximum.
+    pub fn backup(&self, now_ms: u32) -> [u32; BACKUP_REGISTERS] {
+        [
+            self.elapsed_ms(now_ms),
+            self.measurements,
+            self.valid,
+            self.present,
+            self.sum_mm as u32,
+            (self.sum_mm >> 32) as u32,
+            (u32::from(self.min_mm) << 16) | u32::from(self.max_mm),
+        ]
+    }
+
+    /// Continue the rollup of the [`Self::backup`] from before the reset at `now_ms`, the time in
+    /// between isn't part of it. Implausible counters (e.g. all registers cleared after a loss of
+    /// power) start a new rollup.
+    pub fn restore(registers: [u32; BACKUP_REGISTERS], now_ms: u32) -> Self {
+        let [elapsed_ms, measurements, valid, present, sum_low, sum_high, range] = registers;
+        let mut accumulator = Self::new(now_ms.wrapping_sub(elapsed_ms));
+        if valid > measurements || present > measurements {
+            return Self::new(now_ms);
+        }
+        accumulator.measurements = measurements;
+        accumulator.valid = valid;
+        accumulator.present = present;
+        if valid > 0 {
+            accumulator.sum_mm = (u64::from(sum_high) << 32) | u64::from(sum_low);
+            accumulator.min_mm = (range >> 16) as u16;
+            accumulator.max_mm = range as u16;
+        }
+        accumulator
+    }
+
+    /// The statistics of the measurements since the last rollup, the next one starts at
+    /// `now_ms`. The rate is checked against the inter-measurement `period_ms` of the sensor, if
+    /// it has been ranging.
+    pub fn take(&mut self, now_ms: u32, utc_ms: Option<u64>, period_ms: Option<u16>) -> Rollup {
+        let accumulator = core::mem::replace(self, Self::new(now_ms));
+        let valid = accumulator.valid > 0;
+        let duration_ms = now_ms.wrapping_sub(accumulator.start_ms);
+        let rate_mhz = (accumulator.measurements as u64 * 1_000_000)
+            .checked_div(duration_ms as u64)
+            .unwrap_or(0) as u32;
+        let rate_ok = period_ms
+            .filter(|period_ms| *period_ms > 0 && accumulator.measurements > 0)
+            .map(|period_ms| {
+                let expected_mhz = 1_000_000 / period_ms as u32;
+                rate_mhz.abs_diff(expected_mhz) * 100
+                    <= expected_mhz * config::RATE_TOLERANCE_PERCENT
+            });
+        Rollup {
+            timestamp_ms: now_ms,
+            utc_ms,
+            duration_s: duration_ms / 1000,
+            measurements: accumulator.measurements,
+            errors: accumulator.measurements - accumulator.valid,
+            min_mm: valid.then_some(accumulator.min_mm),
+            max_mm: valid.then_some(accumulator.max_mm),
+            mean_mm: valid.then(|| (accumulator.sum_mm / accumulator.valid as u64) as u16),
+            occupancy_pct: (accumulator.present as u64 * 100)
+                .checked_div(accumulator.measurements as u64)
+                .unwrap_or(0) as u8,
+            rate_mhz,
+            rate_ok,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test_measurement;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn statistics_are_within_the_input_range(
+            samples in prop::collection::vec((any::<u16>(), any::<bool>(), any::<bool>()), 0..200),
+        ) {
+            let mut accumulator = RollupAccumulator::new(0);
+            for &(distance_mm, valid, present) in &samples {
+                let status = if valid {
+                    RangeStatus::Valid
+                } else {
+                    RangeStatus::SignalFailure
+                };
+                let measurement = Measurement {
+                    status,
+                    ..test_measurement(0, distance_mm)
+                };
+                accumulator.add(&measurement, present);
+            }
+            let rollup = accumulator.take(1000, None, None);
+
+            let valid: Vec<u16> = samples.iter().filter(|s| s.1).map(|s| s.0).collect();
+            prop_assert_eq!(rollup.measurements as usize, samples.len());
+            prop_assert_eq!(rollup.errors as usize, samples.len() - valid.len());
+            prop_assert_eq!(rollup.min_mm, valid.iter().min().copied());
+            prop_assert_eq!(rollup.max_mm, valid.iter().max().copied());
+            if let (Some(min), Some(mean), Some(max)) = (rollup.min_mm, rollup.mean_mm, rollup.max_mm) {
+                prop_assert!(min <= mean && mean <= max);
+            } else {
+                prop_assert!(valid.is_empty() && rollup.mean_mm.is_none());
+            }
+            prop_assert!(rollup.occupancy_pct <= 100);
+        }
+
+        /// The sum doesn't overflow even for far more measurements than fit into a rollup.
+        #[test]
+        fn mean_of_extreme_distances(distance_mm in prop_oneof![Just(u16::MAX), any::<u16>()]) {
+            let mut accumulator = RollupAccumulator::new(0);
+            for _ in 0..100_000 {
+                accumulator.add(&test_measurement(0, distance_mm), true);
+            }
+            let rollup = accumulator.take(0, None, None);
+            prop_assert_eq!(rollup.mean_mm, Some(distance_mm));
+            prop_assert_eq!(rollup.occupancy_pct, 100);
+        }
+    }
+
+    #[test]
+    fn rate_is_checked_against_the_period() {
+        let mut accumulator = RollupAccumulator::new(0);
+        for _ in 0..600 {
+            accumulator.add(&test_measurement(0, 500), false);
+        }
+        let rollup = accumulator.take(60_000, None, Some(100));
+        assert_eq!((rollup.rate_mhz, rollup.rate_ok), (10_000, Some(true)));
+
+        for _ in 0..400 {
+            accumulator.add(&test_measurement(0, 500), false);
+        }
+        let rollup = accumulator.take(120_000, None, Some(100));
+        assert_eq!((rollup.rate_mhz, rollup.rate_ok), (6_666, Some(false)));
+        assert_eq!(accumulator.take(180_000, None, Some(100)).rate_ok, None);
+    }
+
+    #[test]
+    fn continues_after_a_restore() {
+        let mut accumulator = RollupAccumulator::new(1_000);
+        accumulator.add(&test_measurement(0, 400), true);
+        accumulator.add(&test_measurement(0, 600), false);
+        accumulator.add(
+            &Measurement {
+                status: RangeStatus::SignalFailure,
+                ..test_measurement(0, 9_000)
+            },
+            false,
+        );
+        let mut restored = RollupAccumulator::restore(accumulator.backup(31_000), 500);
+        restored.add(&test_measurement(0, 800), true);
+        let rollup = restored.take(10_500, None, None);
+        assert_eq!(rollup.duration_s, 40);
+        assert_eq!((rollup.measurements, rollup.errors), (4, 1));
+        assert_eq!(
+            (rollup.min_mm, rollup.mean_mm, rollup.max_mm),
+            (Some(400), Some(600), Some(800))
+        );
+        assert_eq!(rollup.occupancy_pct, 50);
+    }
+
+    #[test]
+    fn cleared_registers_start_a_new_rollup() {
+        let mut accumulator = RollupAccumulator::restore([0; BACKUP_REGISTERS], 2_000);
+        accumulator.add(&test_measurement(0, 700), false);
+        let rollup = accumulator.take(62_000, None, None);
+        assert_eq!(rollup.duration_s, 60);
+        assert_eq!((rollup.min_mm, rollup.max_mm), (Some(700), Some(700)));
+
+        let corrupt = [0, 1, 2, 0, 0, 0, 0];
+        let rollup = RollupAccumulator::restore(corrupt, 0).take(1_000, None, None);
+        assert_eq!((rollup.measurements, rollup.errors), (0, 0));
+    }
+}
@@ -0,0 +1,84 @@
+//! A rotary encoder with push button (e.g. KY-040) on `PC10` (CLK), `PC11` (DT) & `PC12` (SW).
+//!
+//! The pins are polled every millisecond, the internal pull-ups are used for the common-ground
+//! encoders & buttons.
+
+use stm32f4xx_hal::gpio::{Input, PC10, PC11, PC12};
+
+/// Time the button must be stable before a change is accepted.
+const DEBOUNCE_MS: u32 = 20;
+
+/// Change of the encoder state (previous state in bits 3 - 2, new state in bits 1 - 0) to the
+/// direction, 0 for invalid transitions caused by bouncing.
+const TRANSITIONS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+/// Transitions per detent of the encoder.
+const TRANSITIONS_PER_DETENT: i8 = 4;
+
+/// An input of the rotary encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum RotaryEvent {
+    /// Turned by one detent, `1` clockwise & `-1` counter-clockwise.
+    Turn(i8),
+    Press,
+}
+
+pub struct RotaryEncoder {
+    clk: PC10<Input>,
+    dt: PC11<Input>,
+    sw: PC12<Input>,
+    /// Previous state of CLK (bit 1) & DT (bit 0).
+    state: u8,
+    /// Transitions since the last detent.
+    steps: i8,
+    pressed: bool,
+    /// Time at which the button has last changed its state.
+    button_changed_ms: u32,
+}
+
+impl RotaryEncoder {
+    pub fn new(clk: PC10<Input>, dt: PC11<Input>, sw: PC12<Input>) -> Self {
+        let mut encoder = Self {
+            clk,
+            dt,
+            sw,
+            state: 0,
+            steps: 0,
+            pressed: false,
+            button_changed_ms: 0,
+        };
+        encoder.state = encoder.read_state();
+        encoder
+    }
+
+    /// Read the pins. Must be called every millisecond.
+    pub fn poll(&mut self, now_ms: u32) -> Option<RotaryEvent> {
+        let state = self.read_state();
+        if state != self.state {
+            self.steps += TRANSITIONS[(self.state << 2 | state) as usize];
+            self.state = state;
+            // the detents are at the state where both pins are high
+            if state == 0b11 && self.steps.abs() >= TRANSITIONS_PER_DETENT {
+                let direction = self.steps.signum();
+                self.steps = 0;
+                return Some(RotaryEvent::Turn(direction));
+            }
+            if state == 0b11 {
+                self.steps = 0;
+            }
+        }
+
+        let pressed = self.sw.is_low();
+        if pressed != self.pressed && now_ms.wrapping_sub(self.button_changed_ms) >= DEBOUNCE_MS {
+            self.pressed = pressed;
+            self.button_changed_ms = now_ms;
+            if pressed {
+                return Some(RotaryEvent::Press);
+            }
+        }
+        None
+    }
+
+    fn read_state(&self) -> u8 {
+        (self.clk.is_high() as u8) << 1 | self.dt.is_high() as u8
+    }
+}
@@ -0,0 +1,176 @@
+//! The latest [`LEN`] measurements kept in RAM, exported as CSV (with a header) by the
+//! `dump csv [<n>]` command: a quick capture can be pasted straight into a spreadsheet, without
+//! a data logger or the decoder of the binary formats on the host.
+//!
+//! The export is sent line by line while the transmit buffers have room & ends with the response
+//! `OK samples=<n>`. It covers the measurements up to the newest one when it was started, those
+//! which are overwritten before they've been sent (e.g. while the host has paused the
+//! transmission) are left out.
+
+use crate::telemetry::Measurement;
+use core::fmt::Write;
+
+/// Number of measurements which are kept.
+pub const LEN: usize = crate::config::sample_history::LEN;
+/// Interval at which the export is continued while the transmit buffers are full.
+pub const OUTPUT_INTERVAL_MS: u32 = 10;
+/// Maximum length of a line of the export.
+pub const MAX_LINE_LEN: usize = 64;
+/// The first line of the export.
+const HEADER: &str = "seq,timestamp_ms,distance_mm,status,utc_ms\r\n";
+
+pub type Line = heapless::String<MAX_LINE_LEN>;
+
+/// The fields of a measurement which are exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sample {
+    seq: u32,
+    timestamp_ms: u32,
+    utc_ms: Option<u64>,
+    distance_mm: u16,
+    /// The raw range status.
+    status: u8,
+}
+
+/// A running export, see [`SampleHistory::export`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Export {
+    /// The sequence numbers of the measurements which haven't been sent yet.
+    next_seq: u32,
+    last_seq: u32,
+    header_sent: bool,
+    /// Number of the sent measurements, `None` once the response has been sent.
+    sent: Option<u16>,
+}
+
+pub struct SampleHistory {
+    samples: heapless::HistoryBuffer<Sample, LEN>,
+}
+
+impl SampleHistory {
+    pub const fn new() -> Self {
+        Self {
+            samples: heapless::HistoryBuffer::new(),
+        }
+    }
+
+    /// Keep the measurement, the oldest one is dropped once the history is full.
+    pub fn push(&mut self, measurement: &Measurement) {
+        self.samples.write(Sample {
+            seq: measurement.seq,
+            timestamp_ms: measurement.timestamp_ms,
+            utc_ms: measurement.utc_ms,
+            distance_mm: measurement.distance_mm,
+            status: measurement.status as u8,
+        });
+    }
+
+    /// Start the export of the newest `count` measurements (all of them if `None`), its lines are
+    /// fetched with [`Self::next_line`].
+    pub fn export(&self, count: Option<u16>) -> Export {
+        let count = count.map_or(LEN, usize::from).min(self.samples.len());
+        let first = self
+            .samples
+            .oldest_ordered()
+            .nth(self.samples.len() - count);
+        let (next_seq, last_seq) = match (first, self.samples.recent()) {
+            (Some(first), Some(last)) => (first.seq, last.seq),
+            // nothing to send
+            _ => (1, 0),
+        };
+        Export {
+            next_seq,
+            last_seq,
+            header_sent: false,
+            sent: Some(0),
+        }
+    }
+
+    /// The next line of the export, the last one is the response. `None` once it's done.
+    pub fn next_line(&self, export: &mut Export) -> Option<Line> {
+        let sent = export.sent?;
+        let mut line = Line::new();
+        if !export.header_sent {
+            export.header_sent = true;
+            line.push_str(HEADER).ok();
+            return Some(line);
+        }
+        let sample = self
+            .samples
+            .oldest_ordered()
+            .find(|sample| (export.next_seq..=export.last_seq).contains(&sample.seq));
+        match sample {
+            Some(sample) => {
+                export.next_seq = sample.seq + 1;
+                export.sent = Some(sent + 1);
+                write!(
+                    line,
+                    "{},{},{},{},",
+                    sample.seq, sample.timestamp_ms, sample.distance_mm, sample.status
+                )
+                .ok();
+                if let Some(utc_ms) = sample.utc_ms {
+                    write!(line, "{}", utc_ms).ok();
+                }
+                write!(line, "\r\n").ok();
+            }
+            None => {
+                export.sent = None;
+                write!(line, "OK samples={}\r\n", sent).ok();
+            }
+        }
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test_measurement;
+
+    fn lines(history: &SampleHistory, mut export: Export) -> heapless::Vec<Line, 8> {
+        core::iter::from_fn(|| history.next_line(&mut export)).collect()
+    }
+
+    #[test]
+    fn exports_the_newest_samples() {
+        let mut history = SampleHistory::new();
+        assert_eq!(
+            lines(&history, history.export(None)),
+            [HEADER, "OK samples=0\r\n"]
+        );
+
+        for seq in 1..=3 {
+            history.push(&Measurement {
+                seq,
+                utc_ms: (seq == 3).then_some(1_700_000_000_000),
+                ..test_measurement(seq * 100, 500 + seq as u16)
+            });
+        }
+        let export = history.export(Some(2));
+        assert_eq!(
+            lines(&history, export),
+            [
+                HEADER,
+                "2,200,502,0,\r\n",
+                "3,300,503,0,1700000000000\r\n",
+                "OK samples=2\r\n"
+            ]
+        );
+
+        // the newer ones aren't included, the overwritten ones are left out
+        let mut export = history.export(None);
+        history.next_line(&mut export);
+        history.next_line(&mut export);
+        for seq in 4..LEN as u32 + 3 {
+            history.push(&Measurement {
+                seq,
+                ..test_measurement(seq * 100, 500 + seq as u16)
+            });
+        }
+        assert_eq!(
+            lines(&history, export),
+            ["3,300,503,0,1700000000000\r\n", "OK samples=2\r\n"]
+        );
+    }
+}
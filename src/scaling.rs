@@ -0,0 +1,143 @@
+//! Conversion of the distance into another unit & a user-defined linear transform (`scale` &
+//! `offset`, in the selected unit), so that an application-specific quantity (e.g. the fill level
+//! of a tank, `-1` times the distance plus its height) is published directly.
+//!
+//! The result is published with each measurement in addition to the raw distance, unless the
+//! scaling is the identity. It's set with the `unit` & `scale` commands & stored with `save`.
+
+use core::fmt;
+
+/// The unit of the scaled value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Unit {
+    Millimetre = 0,
+    Centimetre = 1,
+    Inch = 2,
+}
+
+impl Unit {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mm" => Some(Self::Millimetre),
+            "cm" => Some(Self::Centimetre),
+            "in" => Some(Self::Inch),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Millimetre => "mm",
+            Self::Centimetre => "cm",
+            Self::Inch => "in",
+        }
+    }
+
+    /// The length of the unit in 0.1 mm.
+    fn deci_mm(self) -> i64 {
+        match self {
+            Self::Millimetre => 10,
+            Self::Centimetre => 100,
+            Self::Inch => 254,
+        }
+    }
+}
+
+/// A fixed-point value with three decimals, e.g. `1250` is `1.250`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Milli(pub i32);
+
+impl fmt::Display for Milli {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let value = self.0.unsigned_abs();
+        write!(f, "{}{}.{:03}", sign, value / 1000, value % 1000)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Scaling {
+    pub unit: Unit,
+    pub scale: Milli,
+    /// Added after scaling, in the unit.
+    pub offset: Milli,
+}
+
+impl Scaling {
+    /// The distance in mm.
+    pub const IDENTITY: Self = Self {
+        unit: Unit::Millimetre,
+        scale: Milli(1000),
+        offset: Milli(0),
+    };
+    /// The largest magnitude of the scale, it's stored with 24 bit.
+    pub const MAX_SCALE: i32 = (1 << 23) - 1;
+
+    /// The scaled distance, `None` for the identity.
+    pub fn apply(&self, distance_mm: u16) -> Option<Milli> {
+        if *self == Self::IDENTITY {
+            return None;
+        }
+        let value = distance_mm as i64 * 10_000 / self.unit.deci_mm();
+        let value = value * self.scale.0 as i64 / 1000 + self.offset.0 as i64;
+        Some(Milli(value.clamp(i32::MIN as i64, i32::MAX as i64) as i32))
+    }
+
+    /// Layout: unit, scale (24 bit) & offset.
+    pub fn encode(&self) -> [u8; 8] {
+        let mut value = [0; 8];
+        value[0] = self.unit as u8;
+        value[1..4].copy_from_slice(&self.scale.0.to_le_bytes()[..3]);
+        value[4..].copy_from_slice(&self.offset.0.to_le_bytes());
+        value
+    }
+
+    pub fn decode(value: &[u8; 8]) -> Option<Self> {
+        let unit = match value[0] {
+            0 => Unit::Millimetre,
+            1 => Unit::Centimetre,
+            2 => Unit::Inch,
+            _ => return None,
+        };
+        // sign extension of the 24 bit
+        let scale = i32::from_le_bytes([0, value[1], value[2], value[3]]) >> 8;
+        Some(Self {
+            unit,
+            scale: Milli(scale),
+            offset: Milli(i32::from_le_bytes([value[4], value[5], value[6], value[7]])),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_and_transform() {
+        let inch = Scaling {
+            unit: Unit::Inch,
+            ..Scaling::IDENTITY
+        };
+        assert_eq!(inch.apply(254), Some(Milli(10_000)));
+        // the fill level of a tank with a height of 150 cm
+        let level = Scaling {
+            unit: Unit::Centimetre,
+            scale: Milli(-1000),
+            offset: Milli(150_000),
+        };
+        assert_eq!(level.apply(1_234).map(|v| v.to_string()).unwrap(), "26.600");
+        assert_eq!(Scaling::IDENTITY.apply(1_234), None);
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let scaling = Scaling {
+            unit: Unit::Centimetre,
+            scale: Milli(-Scaling::MAX_SCALE),
+            offset: Milli(-42),
+        };
+        assert_eq!(Scaling::decode(&scaling.encode()), Some(scaling));
+        assert_eq!(Milli(-42).to_string(), "-0.042");
+    }
+}
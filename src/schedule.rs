@@ -0,0 +1,210 @@
+//! The active hours of the alarms: the alarm output, the LoRa alarms & the presence events are
+//! only raised on the configured days between the start & the end time, e.g. only outside of the
+//! business hours with `schedule mon-fri 18:00-08:00`. The times are local ones, see
+//! [`config::UTC_OFFSET_MIN`]. A window which ends before it starts continues into the next day
+//! & belongs to the day on which it starts.
+//!
+//! The schedule is part of the [`crate::settings`]. While the calendar time isn't known (see
+//! [`crate::clock`]) the alarms are always active, a missed alarm being worse than a spurious one.
+
+use crate::config::schedule as config;
+use core::fmt::{self, Write};
+
+pub const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// The names of the days, starting on Monday.
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const ALL_DAYS: u8 = 0x7F;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Schedule {
+    /// A bit per day, bit 0 is Monday.
+    days: u8,
+    /// Minute of the day at which the alarms become active.
+    start_min: u16,
+    /// Minute of the day at which the alarms become inactive, the whole day if it's the start.
+    end_min: u16,
+}
+
+impl Schedule {
+    /// The alarms are always active.
+    pub const ALWAYS: Self = Self {
+        days: ALL_DAYS,
+        start_min: 0,
+        end_min: 0,
+    };
+
+    /// `None` without any day or with a time beyond the day.
+    pub fn new(days: u8, start_min: u16, end_min: u16) -> Option<Self> {
+        (days != 0 && days & !ALL_DAYS == 0 && start_min.max(end_min) < MINUTES_PER_DAY).then_some(
+            Self {
+                days,
+                start_min,
+                end_min,
+            },
+        )
+    }
+
+    /// Whether the alarms are active at the calendar time (in ms since the Unix epoch).
+    pub fn is_active(&self, utc_ms: Option<u64>) -> bool {
+        let Some(utc_ms) = utc_ms else {
+            return true;
+        };
+        let local_min = (utc_ms / 60_000) as i64 + config::UTC_OFFSET_MIN as i64;
+        let day = local_min.div_euclid(MINUTES_PER_DAY as i64);
+        let minute = local_min.rem_euclid(MINUTES_PER_DAY as i64) as u16;
+        // the 1st of January 1970 was a Thursday
+        let enabled = |day: i64| self.days & 1 << (day + 3).rem_euclid(7) != 0;
+        if self.start_min == self.end_min {
+            enabled(day)
+        } else if self.start_min < self.end_min {
+            enabled(day) && (self.start_min..self.end_min).contains(&minute)
+        } else if minute >= self.start_min {
+            enabled(day)
+        } else {
+            minute < self.end_min && enabled(day - 1)
+        }
+    }
+
+    /// Layout: days, start & end minute.
+    pub fn encode(&self) -> [u8; 5] {
+        let mut value = [0; 5];
+        value[0] = self.days;
+        value[1..3].copy_from_slice(&self.start_min.to_le_bytes());
+        value[3..].copy_from_slice(&self.end_min.to_le_bytes());
+        value
+    }
+
+    pub fn decode(value: &[u8; 5]) -> Option<Self> {
+        Self::new(
+            value[0],
+            u16::from_le_bytes([value[1], value[2]]),
+            u16::from_le_bytes([value[3], value[4]]),
+        )
+    }
+
+    /// Parse the days: `all` or a comma separated list of days & ranges of them (e.g.
+    /// `mon-fri,sun`, a range may wrap around like `fri-mon`).
+    pub fn parse_days(names: &str) -> Option<u8> {
+        if names == "all" {
+            return Some(ALL_DAYS);
+        }
+        let index = |name| DAY_NAMES.iter().position(|&day| day == name);
+        names.split(',').try_fold(0, |days, range| {
+            let (first, last) = range.split_once('-').unwrap_or((range, range));
+            let (first, last) = (index(first)?, index(last)?);
+            let len = (last + 7 - first) % 7 + 1;
+            Some((first..first + len).fold(days, |days, day| days | 1 << (day % 7)))
+        })
+    }
+
+    /// Parse a time of the day `hh:mm` into the minute of the day.
+    pub fn parse_time(time: &str) -> Option<u16> {
+        let (hours, minutes) = time.split_once(':')?;
+        let (hours, minutes): (u16, u16) = (hours.parse().ok()?, minutes.parse().ok()?);
+        (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+    }
+
+    /// Write the schedule as it's parsed, e.g. `mon-fri 18:00-08:00`, or `always`.
+    pub fn write_to(&self, f: &mut impl Write) -> fmt::Result {
+        if *self == Self::ALWAYS {
+            return f.write_str("always");
+        }
+        let mut first = true;
+        let mut day = 0;
+        while day < 7 {
+            if self.days & 1 << day == 0 {
+                day += 1;
+                continue;
+            }
+            let start = day;
+            while day + 1 < 7 && self.days & 1 << (day + 1) != 0 {
+                day += 1;
+            }
+            f.write_str(if first { "" } else { "," })?;
+            f.write_str(DAY_NAMES[start])?;
+            if day > start {
+                write!(f, "-{}", DAY_NAMES[day])?;
+            }
+            first = false;
+            day += 1;
+        }
+        write!(
+            f,
+            " {:02}:{:02}-{:02}:{:02}",
+            self.start_min / 60,
+            self.start_min % 60,
+            self.end_min / 60,
+            self.end_min % 60
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Monday, the 13th of October 2025 at the local time, in ms since the Unix epoch.
+    fn monday_at(hours: i64, minutes: i64) -> Option<u64> {
+        let local_min = hours * 60 + minutes - config::UTC_OFFSET_MIN as i64;
+        Some((1_760_313_600_000 + local_min * 60_000) as u64)
+    }
+
+    fn schedule(days: &str, start: &str, end: &str) -> Schedule {
+        let days = Schedule::parse_days(days).unwrap();
+        let start = Schedule::parse_time(start).unwrap();
+        let end = Schedule::parse_time(end).unwrap();
+        Schedule::new(days, start, end).unwrap()
+    }
+
+    #[test]
+    fn only_the_selected_days_are_active() {
+        let weekend = schedule("sat-sun", "00:00", "00:00");
+        assert!(!weekend.is_active(monday_at(12, 0)));
+        assert!(weekend.is_active(monday_at(5 * 24 + 12, 0)));
+        assert!(weekend.is_active(monday_at(-1, 0)));
+        assert_eq!(Schedule::parse_days("sat-mon,wed"), Some(0b110_0101));
+        assert_eq!(Schedule::parse_days("mon-xyz"), None);
+        assert_eq!(Schedule::new(0, 0, 0), None);
+    }
+
+    #[test]
+    fn a_window_continues_past_midnight() {
+        let nights = schedule("mon-fri", "18:00", "08:00");
+        assert!(!nights.is_active(monday_at(17, 59)));
+        assert!(nights.is_active(monday_at(18, 0)));
+        // the window of Monday continues into Tuesday
+        assert!(nights.is_active(monday_at(24 + 7, 59)));
+        assert!(!nights.is_active(monday_at(24 + 8, 0)));
+        // the window of Sunday isn't active, but the one of Friday continues into Saturday
+        assert!(!nights.is_active(monday_at(-1, 0)));
+        assert!(nights.is_active(monday_at(5 * 24 + 1, 0)));
+    }
+
+    #[test]
+    fn a_whole_day_window() {
+        assert!(Schedule::ALWAYS.is_active(monday_at(12, 0)));
+        assert!(Schedule::ALWAYS.is_active(monday_at(0, 0)));
+        let monday = schedule("mon", "06:00", "06:00");
+        assert!(monday.is_active(monday_at(0, 0)));
+        assert!(monday.is_active(monday_at(23, 59)));
+        assert!(!monday.is_active(monday_at(24, 0)));
+        // the calendar time isn't known
+        assert!(monday.is_active(None));
+    }
+
+    #[test]
+    fn parses_what_it_writes() {
+        let nights = schedule("mon-fri", "18:00", "08:00");
+        let mut text = heapless::String::<32>::new();
+        nights.write_to(&mut text).unwrap();
+        assert_eq!(text, "mon-fri 18:00-08:00");
+        assert_eq!(Schedule::decode(&nights.encode()), Some(nights));
+        text.clear();
+        Schedule::ALWAYS.write_to(&mut text).unwrap();
+        assert_eq!(text, "always");
+        assert_eq!(Schedule::parse_time("24:00"), None);
+        assert_eq!(Schedule::parse_time("7:5"), Some(7 * 60 + 5));
+        assert_eq!(Schedule::parse_time("0800"), None);
+    }
+}
@@ -0,0 +1,345 @@
+//! Standalone data logging to CSV files on a SD card (FAT16 or FAT32) connected to SPI3
+//! (`PB3` = SCK, `PB4` = MISO, `PB5` = MOSI, `PB7` = CS).
+//!
+//! Each boot starts a new file `LOGnnnnn.CSV` in the root directory (starting with the
+//! [`DeviceId`] of the board & the column names), which is continued in the
+//! next file once it reaches [`config::FILE_SIZE_KB`]. If [`config::MAX_FILES`] is set the oldest
+//! file is deleted whenever a new one is started. The lines are collected & written a block at a
+//! time; the size of the file on the card is only updated every [`config::FLUSH_INTERVAL_S`],
+//! thus the data written since then is lost if the power fails. The `shutdown` command writes
+//! everything & unmounts the card so that it can be removed safely.
+//!
+//! The bus runs at the speed required for the initialisation of the card (~330 kHz), which is
+//! plenty for the measurements.
+
+use crate::config::sd_card as config;
+use crate::device_id::DeviceId;
+use crate::session::SessionId;
+use crate::telemetry::Measurement;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embedded_sdmmc::{
+    Directory, File, Mode, SdCard, SdCardError, ShortFileName, TimeSource, Timestamp, Volume,
+    VolumeIdx, VolumeManager,
+};
+use stm32f4xx_hal::gpio::{Output, PB7};
+use stm32f4xx_hal::hal::blocking::delay::DelayUs;
+use stm32f4xx_hal::pac::SPI3;
+use stm32f4xx_hal::spi::Spi;
+use time::OffsetDateTime;
+
+#[cfg(feature = "encoder")]
+compile_error!("the features `sd-card` and `encoder` can't be combined as both use PB4 & PB5");
+
+/// The size of a block of the card.
+const BLOCK_LEN: usize = 512;
+/// The column names, the line after the [`DeviceId`] of the board at the start of each file.
+const HEADER: &str = "seq,timestamp_ms,utc_ms,distance_mm,status\r\n";
+/// The first id of the handles, which makes them stand out in the logs.
+const ID_OFFSET: u32 = 5000;
+/// Time to wait before mounting the card again after it failed (e.g. because none is inserted).
+const RETRY_INTERVAL_MS: u32 = 5_000;
+
+type Card = SdCard<Spi<SPI3>, PB7<Output>, Delay>;
+/// Only a single volume, directory & file are open at any time.
+type Volumes = VolumeManager<Card, FileTime, 1, 1, 1>;
+pub type Error = embedded_sdmmc::Error<SdCardError>;
+
+/// The calendar time (in seconds since the Unix epoch) for the timestamps of the files, 0 if it
+/// isn't known. [`TimeSource`] doesn't allow passing it along with the operations.
+static UTC_S: AtomicU32 = AtomicU32::new(0);
+
+/// Busy waiting delay, only needed during the initialisation of the card.
+pub struct Delay {
+    sysclk_hz: u32,
+}
+
+impl DelayUs<u8> for Delay {
+    fn delay_us(&mut self, us: u8) {
+        cortex_m::asm::delay(self.sysclk_hz / 1_000_000 * us as u32);
+    }
+}
+
+pub struct FileTime;
+
+impl TimeSource for FileTime {
+    fn get_timestamp(&self) -> Timestamp {
+        let Some(now) = OffsetDateTime::from_unix_timestamp(UTC_S.load(Ordering::Relaxed).into())
+            .ok()
+            .filter(|now| now.year() >= 1980)
+        else {
+            // the start of the FAT timestamps
+            return Timestamp {
+                year_since_1970: 10,
+                zero_indexed_month: 0,
+                zero_indexed_day: 0,
+                hours: 0,
+                minutes: 0,
+                seconds: 0,
+            };
+        };
+        Timestamp {
+            year_since_1970: (now.year() - 1970) as u8,
+            zero_indexed_month: u8::from(now.month()) - 1,
+            zero_indexed_day: now.day() - 1,
+            hours: now.hour(),
+            minutes: now.minute(),
+            seconds: now.second(),
+        }
+    }
+}
+
+/// The file which is currently being written.
+struct LogFile {
+    volume: Volume,
+    root: Directory,
+    file: File,
+    /// The number in the name of the file.
+    index: u32,
+    /// The size of the file including the data which hasn't been written yet.
+    size: u32,
+}
+
+enum State {
+    /// The card isn't mounted, it's tried again at the given time.
+    Unmounted {
+        retry_ms: u32,
+    },
+    Mounted(LogFile),
+    /// The card has been unmounted with the `shutdown` command.
+    Stopped,
+}
+
+pub struct SdCardLog {
+    /// `None` only while it's being reset after a failure.
+    volumes: Option<Volumes>,
+    state: State,
+    /// The lines which haven't been written yet.
+    buffer: heapless::Vec<u8, BLOCK_LEN>,
+    /// Time at which the size of the file has been updated on the card the last time.
+    flushed_ms: u32,
+}
+
+impl SdCardLog {
+    /// The card is mounted once the loggers are run the first time.
+    pub fn new(spi: Spi<SPI3>, cs: PB7<Output>, sysclk_hz: u32) -> Self {
+        let card = SdCard::new(spi, cs, Delay { sysclk_hz });
+        Self {
+            volumes: Some(VolumeManager::new_with_limits(card, FileTime, ID_OFFSET)),
+            state: State::Unmounted { retry_ms: 0 },
+            buffer: heapless::Vec::new(),
+            flushed_ms: 0,
+        }
+    }
+
+    /// Append a measurement to the current file. Measurements are dropped while no card is
+    /// mounted.
+    pub fn log(&mut self, measurement: &Measurement) {
+        if !matches!(self.state, State::Mounted(_)) {
+            return;
+        }
+        let mut line = heapless::String::<64>::new();
+        write!(line, "{},{},", measurement.seq, measurement.timestamp_ms).ok();
+        if let Some(utc_ms) = measurement.utc_ms {
+            write!(line, "{}", utc_ms).ok();
+        }
+        write!(
+            line,
+            ",{},{}\r\n",
+            measurement.distance_mm, measurement.status as u8
+        )
+        .ok();
+        if self.buffer.len() + line.len() > BLOCK_LEN {
+            if let Err(e) = self.write_buffer() {
+                self.fail(e);
+                return;
+            }
+        }
+        self.buffer.extend_from_slice(line.as_bytes()).ok();
+    }
+
+    /// Mount the card & update the size of the file periodically. Must be called periodically.
+    pub fn tick(&mut self, now_ms: u32, utc_ms: Option<u64>) {
+        let utc_s = utc_ms.map_or(0, |utc_ms| (utc_ms / 1000) as u32);
+        UTC_S.store(utc_s, Ordering::Relaxed);
+        let result = match &self.state {
+            // the retry time has been reached
+            State::Unmounted { retry_ms } if now_ms.wrapping_sub(*retry_ms) < u32::MAX / 2 => {
+                self.mount()
+            }
+            State::Mounted(_)
+                if now_ms.wrapping_sub(self.flushed_ms) >= config::FLUSH_INTERVAL_S * 1000 =>
+            {
+                self.flush()
+            }
+            _ => return,
+        };
+        match result {
+            Ok(()) => self.flushed_ms = now_ms,
+            Err(e) => {
+                self.fail(e);
+                self.state = State::Unmounted {
+                    retry_ms: now_ms.wrapping_add(RETRY_INTERVAL_MS),
+                };
+            }
+        }
+    }
+
+    /// Write everything & unmount the card. Nothing is logged anymore afterwards.
+    pub fn shutdown(&mut self) -> Result<(), Error> {
+        let result = self.unmount();
+        if let Err(e) = &result {
+            self.fail(e.clone());
+        }
+        self.state = State::Stopped;
+        result
+    }
+
+    fn mount(&mut self) -> Result<(), Error> {
+        let volumes = self.volumes()?;
+        let volume = volumes.open_volume(VolumeIdx(0))?;
+        let root = volumes.open_root_dir(volume)?;
+        let mut last_index = 0;
+        volumes.iterate_dir(root, |entry| {
+            if let Some(index) = parse_index(&entry.name) {
+                last_index = last_index.max(index);
+            }
+        })?;
+        let index = last_index + 1;
+        let file =
+            volumes.open_file_in_dir(root, file_name(index).as_str(), Mode::ReadWriteCreate)?;
+        self.buffer.clear();
+        self.state = State::Mounted(LogFile {
+            volume,
+            root,
+            file,
+            index,
+            size: write_header(&mut self.buffer),
+        });
+        defmt::info!("SD card: logging to file {}", index);
+        self.delete_old_file(root, index)?;
+        Ok(())
+    }
+
+    /// Write the buffered lines, afterwards the file is continued in a new one if it's full.
+    fn write_buffer(&mut self) -> Result<(), Error> {
+        let State::Mounted(log_file) = &mut self.state else {
+            return Ok(());
+        };
+        let volumes = self.volumes.as_mut().ok_or(Error::BadHandle)?;
+        volumes.write(log_file.file, &self.buffer)?;
+        log_file.size += self.buffer.len() as u32;
+        self.buffer.clear();
+        if log_file.size < config::FILE_SIZE_KB * 1024 {
+            return Ok(());
+        }
+
+        volumes.close_file(log_file.file)?;
+        log_file.index += 1;
+        log_file.file = volumes.open_file_in_dir(
+            log_file.root,
+            file_name(log_file.index).as_str(),
+            Mode::ReadWriteCreateOrTruncate,
+        )?;
+        log_file.size = write_header(&mut self.buffer);
+        defmt::info!("SD card: continuing in file {}", log_file.index);
+        let (root, index) = (log_file.root, log_file.index);
+        self.delete_old_file(root, index)
+    }
+
+    /// Write the buffered lines & update the size of the file by closing & reopening it.
+    fn flush(&mut self) -> Result<(), Error> {
+        self.write_buffer()?;
+        let State::Mounted(log_file) = &mut self.state else {
+            return Ok(());
+        };
+        let volumes = self.volumes.as_mut().ok_or(Error::BadHandle)?;
+        volumes.close_file(log_file.file)?;
+        log_file.file = volumes.open_file_in_dir(
+            log_file.root,
+            file_name(log_file.index).as_str(),
+            Mode::ReadWriteAppend,
+        )?;
+        Ok(())
+    }
+
+    fn unmount(&mut self) -> Result<(), Error> {
+        self.write_buffer()?;
+        let State::Mounted(log_file) = &self.state else {
+            return Ok(());
+        };
+        let (volume, root, file) = (log_file.volume, log_file.root, log_file.file);
+        let volumes = self.volumes()?;
+        volumes.close_file(file)?;
+        volumes.close_dir(root)?;
+        volumes.close_volume(volume)?;
+        defmt::info!("SD card: unmounted");
+        Ok(())
+    }
+
+    /// Delete the oldest file to keep at most [`config::MAX_FILES`].
+    fn delete_old_file(&mut self, root: Directory, index: u32) -> Result<(), Error> {
+        let Some(old_index) = index.checked_sub(config::MAX_FILES) else {
+            return Ok(());
+        };
+        if config::MAX_FILES == 0 || old_index == 0 {
+            return Ok(());
+        }
+        match self
+            .volumes()?
+            .delete_file_in_dir(root, file_name(old_index).as_str())
+        {
+            Err(Error::FileNotFound) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Forget all open handles & start over with the initialisation of the card, as the state of
+    /// the handles is unknown after a failure.
+    fn fail(&mut self, error: Error) {
+        defmt::error!("SD card failed: {}", error);
+        if let Some(volumes) = self.volumes.take() {
+            let (card, time) = volumes.free();
+            card.mark_card_uninit();
+            self.volumes = Some(VolumeManager::new_with_limits(card, time, ID_OFFSET));
+        }
+        self.buffer.clear();
+        if !matches!(self.state, State::Stopped) {
+            self.state = State::Unmounted { retry_ms: 0 };
+        }
+    }
+
+    fn volumes(&mut self) -> Result<&mut Volumes, Error> {
+        self.volumes.as_mut().ok_or(Error::BadHandle)
+    }
+}
+
+/// Start a file with the [`DeviceId`], the [`SessionId`] & the column names. Returns the length.
+fn write_header(buffer: &mut heapless::Vec<u8, BLOCK_LEN>) -> u32 {
+    let mut line = heapless::String::<48>::new();
+    write!(
+        line,
+        "# device={} session={}\r\n",
+        DeviceId::get(),
+        SessionId::get()
+    )
+    .ok();
+    buffer.extend_from_slice(line.as_bytes()).ok();
+    buffer.extend_from_slice(HEADER.as_bytes()).ok();
+    (line.len() + HEADER.len()) as u32
+}
+
+fn file_name(index: u32) -> heapless::String<12> {
+    let mut name = heapless::String::new();
+    write!(name, "LOG{:05}.CSV", index % 100_000).ok();
+    name
+}
+
+/// The number in the name of a log file, `None` for other files.
+fn parse_index(name: &ShortFileName) -> Option<u32> {
+    let digits = name.base_name().strip_prefix(b"LOG")?;
+    if name.extension() != b"CSV" || digits.len() != 5 {
+        return None;
+    }
+    core::str::from_utf8(digits).ok()?.parse().ok()
+}
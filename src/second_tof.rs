@@ -0,0 +1,103 @@
+//! A second VL53L1X (`second-tof` feature) on its own I2C bus, so that two directions can be
+//! measured without changing the address of one of the sensors: I2C3 with SCL on `PA8` & SDA on
+//! `PC9`, the data ready interrupt (GPIO1) on `PD2` (EXTI2).
+//!
+//! It uses the same measurement settings as the first sensor but not its calibration. It ranges
+//! from boot on, its measurements are sent as separate frames (see
+//! [`crate::telemetry::second_measurement_frame`]) while the first sensor is ranging, they aren't
+//! processed by the outputs or loggers.
+//!
+//! With `acquisition interleaved` it takes turns with the first sensor, see [`crate::interleave`]:
+//! it only ranges a single measurement per window then.
+
+use crate::board::TOF_INTERRUPT_EDGE;
+use crate::i2c_guard::GuardedI2c;
+use crate::range_sensor::{RangeSensor, Reading};
+use crate::settings::TofSettings;
+use crate::tof;
+use stm32f4xx_hal::gpio::{ExtiPin, Input, PD2};
+use stm32f4xx_hal::pac::{EXTI, I2C3};
+use stm32f4xx_hal::syscfg::SysCfg;
+use vl53l1x_uld::VL53L1X;
+
+#[cfg(feature = "servo")]
+compile_error!("the features `second-tof` and `servo` can't be combined as both use PA8");
+#[cfg(feature = "lora")]
+compile_error!("the features `second-tof` and `lora` can't be combined as both use PC9");
+
+pub type Error = <VL53L1X<GuardedI2c<I2C3>> as RangeSensor>::Error;
+
+pub struct SecondTof {
+    sensor: VL53L1X<GuardedI2c<I2C3>>,
+    interrupt: PD2<Input>,
+    /// Number of measurements, the sequence number of the latest one.
+    count: u32,
+    /// Whether it only ranges in its windows, see [`crate::interleave`].
+    interleaved: bool,
+}
+
+impl SecondTof {
+    /// Set up the sensor on the bus (I2C3 on `PA8` & `PC9`) & start ranging, a failure is logged &
+    /// the sensor stays silent.
+    pub fn new(
+        i2c: GuardedI2c<I2C3>,
+        interrupt: PD2<Input>,
+        settings: Option<TofSettings>,
+        syscfg: &mut SysCfg,
+        exti: &mut EXTI,
+    ) -> Self {
+        let mut sensor = VL53L1X::new(i2c, vl53l1x_uld::DEFAULT_ADDRESS);
+        match tof::setup(&mut sensor, None, settings) {
+            Ok(()) => defmt::info!("second TOF sensor ranging"),
+            Err(e) => defmt::error!(
+                "failed to set up the second TOF sensor: {}",
+                defmt::Debug2Format(&e)
+            ),
+        }
+        let mut interrupt = interrupt;
+        interrupt.make_interrupt_source(syscfg);
+        interrupt.enable_interrupt(exti);
+        interrupt.trigger_on_edge(exti, TOF_INTERRUPT_EDGE);
+        Self {
+            sensor,
+            interrupt,
+            count: 0,
+            interleaved: false,
+        }
+    }
+
+    /// Stop the continuous ranging while interleaved, start it again afterwards.
+    pub fn set_interleaved(&mut self, interleaved: bool) -> Result<(), Error> {
+        if interleaved != self.interleaved {
+            if interleaved {
+                self.sensor.stop()?;
+            } else {
+                self.sensor.start()?;
+            }
+            self.interleaved = interleaved;
+        }
+        Ok(())
+    }
+
+    /// Start the single measurement of a window.
+    pub fn start(&mut self) -> Result<(), Error> {
+        self.sensor.start()
+    }
+
+    /// Whether the data ready line has raised the interrupt, which is also pended by the firmware.
+    pub fn is_interrupt_pending(&self) -> bool {
+        self.interrupt.check_interrupt()
+    }
+
+    /// Acknowledge the data ready interrupt & read the measurement, returns it with its sequence
+    /// number.
+    pub fn on_interrupt(&mut self) -> Result<(u32, Reading), Error> {
+        self.interrupt.clear_interrupt_pending_bit();
+        let reading = self.sensor.read()?;
+        if self.interleaved {
+            self.sensor.stop()?;
+        }
+        self.count = self.count.wrapping_add(1);
+        Ok((self.count, reading))
+    }
+}
@@ -0,0 +1,198 @@
+//! Escalation of failures of the TOF sensor, so that a failing sensor is recovered (or at least
+//! reported) instead of silently stopping the measurements.
+//!
+//! The sensor is failing while it's ranging but hasn't been read successfully for
+//! [`config::STALL_TIMEOUT_MS`]. The readings which are dropped afterwards (e.g. by the
+//! [`crate::range_gate`] or the pause) still prove that the sensor works. It's then re-initialised (with the stored
+//! calibration & settings, like at boot) up to [`config::MAX_REINITS`] times per boot, after that
+//! the board resets. The resets are counted in a backup register of the [`crate::clock`]: once
+//! there have been [`config::MAX_RESETS`] of them in a row the firmware enters the safe mode
+//! instead. The count is cleared once the sensor has been working for [`STABLE_MS`] after a boot.
+//!
+//! From the first escalation until the next successful read the sensor is lost: the outputs are driven
+//! to their failsafe values (see [`crate::outputs::Outputs::failsafe`]) & the health report flags
+//! the telemetry as stale, instead of freezing on the last valid measurement.
+
+use crate::config::sensor_supervisor as config;
+
+/// Time after which a working sensor clears the count of resets.
+pub const STABLE_MS: u32 = 10 * 60 * 1000;
+/// Interval at which the sensor is checked.
+pub const CHECK_INTERVAL_MS: u32 = 1_000;
+
+/// The next step of the escalation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Action {
+    /// Initialise the sensor again & restart the ranging.
+    Reinit,
+    /// Reset the board after counting the reset.
+    Reset,
+    /// Stop operating the sensor.
+    SafeMode,
+    /// The sensor works again, clear the count of resets.
+    Stable,
+}
+
+pub struct SensorSupervisor {
+    /// Number of re-initialisations since boot.
+    reinits: u8,
+    /// Number of resets in a row before this boot.
+    resets: u32,
+    /// Time since boot at which the sensor has last been known to work (or has been started).
+    alive_ms: u32,
+    /// Whether the sensor was ranging at the previous check.
+    was_ranging: bool,
+    /// Whether the sensor has failed & hasn't delivered a measurement since.
+    lost: bool,
+}
+
+impl SensorSupervisor {
+    /// `resets` is the number of resets caused by the escalation before this boot.
+    pub fn new(resets: u32) -> Self {
+        Self {
+            reinits: 0,
+            resets,
+            alive_ms: 0,
+            was_ranging: false,
+            lost: false,
+        }
+    }
+
+    /// Number of re-initialisations since boot, reported in the health report.
+    pub fn reinits(&self) -> u8 {
+        self.reinits
+    }
+
+    /// Whether the sensor is lost, i.e. the latest measurement is stale.
+    pub fn is_lost(&self) -> bool {
+        self.lost
+    }
+
+    /// Number of resets in a row including the next one, stored before resetting.
+    pub fn next_resets(&self) -> u32 {
+        self.resets + 1
+    }
+
+    /// Check the sensor, `last_read_ms` is the time of the latest successful read, whether the
+    /// reading has become a measurement or not. Returns the action to take if the sensor is failing
+    /// (or has become stable).
+    pub fn check(
+        &mut self,
+        now_ms: u32,
+        ranging: bool,
+        last_read_ms: Option<u32>,
+    ) -> Option<Action> {
+        // a stopped sensor doesn't deliver measurements, the time starts again with the ranging
+        if ranging && !self.was_ranging {
+            self.alive_ms = now_ms;
+        }
+        self.was_ranging = ranging;
+        if let Some(last_read_ms) = last_read_ms {
+            if last_read_ms.wrapping_sub(self.alive_ms) as i32 > 0 {
+                self.alive_ms = last_read_ms;
+                self.lost = false;
+            }
+        }
+        if !ranging {
+            self.lost = false;
+            return None;
+        }
+        if now_ms.wrapping_sub(self.alive_ms) <= config::STALL_TIMEOUT_MS {
+            if self.resets > 0 && self.reinits == 0 && now_ms >= STABLE_MS {
+                self.resets = 0;
+                return Some(Action::Stable);
+            }
+            return None;
+        }
+        // the re-initialised sensor is given the full time again
+        self.alive_ms = now_ms;
+        self.lost = true;
+        if self.reinits < config::MAX_REINITS {
+            self.reinits += 1;
+            defmt::warn!(
+                "TOF sensor failing, re-initialising it ({}/{})",
+                self.reinits,
+                config::MAX_REINITS
+            );
+            Some(Action::Reinit)
+        } else if self.resets < config::MAX_RESETS {
+            defmt::error!(
+                "TOF sensor still failing after {} re-initialisations, resetting",
+                self.reinits
+            );
+            Some(Action::Reset)
+        } else {
+            defmt::error!(
+                "TOF sensor still failing after {} resets, entering safe mode",
+                self.resets
+            );
+            Some(Action::SafeMode)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::range_gate::RangeGate;
+    use crate::range_sensor::Reading;
+    use vl53l1x_uld::RangeStatus;
+
+    /// Range for `duration_ms` with a reading every 100 ms & return the actions of the checks.
+    fn run(
+        supervisor: &mut SensorSupervisor,
+        gate: &mut RangeGate,
+        duration_ms: u32,
+        read: impl Fn(u32) -> bool,
+    ) -> Vec<Action> {
+        let mut last_read_ms = None;
+        let mut actions = Vec::new();
+        for now_ms in (0..duration_ms).step_by(100) {
+            if read(now_ms) {
+                last_read_ms = Some(now_ms);
+                let reading = Reading {
+                    distance_mm: 4_000,
+                    status: RangeStatus::Valid,
+                    ambient_kcps: 0,
+                    signal_per_spad_kcps: 0,
+                };
+                assert!(!gate.passes(&reading));
+            }
+            if now_ms % CHECK_INTERVAL_MS == 0 {
+                actions.extend(supervisor.check(now_ms, true, last_read_ms));
+            }
+        }
+        actions
+    }
+
+    #[test]
+    fn gated_readings_keep_the_sensor_alive() {
+        let mut supervisor = SensorSupervisor::new(0);
+        let mut gate = RangeGate::new();
+        gate.set_window(Some((100, 2_000)));
+        let actions = run(
+            &mut supervisor,
+            &mut gate,
+            3 * config::STALL_TIMEOUT_MS,
+            |_| true,
+        );
+        assert_eq!(actions, []);
+        assert!(!supervisor.is_lost());
+        assert_eq!(supervisor.reinits(), 0);
+    }
+
+    #[test]
+    fn a_stalled_sensor_is_reinitialised() {
+        let mut supervisor = SensorSupervisor::new(0);
+        let mut gate = RangeGate::new();
+        gate.set_window(Some((100, 2_000)));
+        let actions = run(
+            &mut supervisor,
+            &mut gate,
+            config::STALL_TIMEOUT_MS + 2 * CHECK_INTERVAL_MS,
+            |now_ms| now_ms < CHECK_INTERVAL_MS,
+        );
+        assert_eq!(actions, [Action::Reinit]);
+        assert!(supervisor.is_lost());
+    }
+}
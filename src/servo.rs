@@ -0,0 +1,59 @@
+//! A hobby servo on `PA8` (TIM1 CH1) which works as a gauge pointer for the distance.
+//!
+//! The servo is at its minimum position at or below the minimum distance, at its maximum position
+//! at or beyond the maximum distance and moves linearly in between (see
+//! [`crate::config::servo`]). It keeps its position for invalid measurements & moves to
+//! [`crate::config::servo::FAILSAFE_PULSE_US`] while the TOF sensor is lost.
+
+use crate::config::servo as config;
+use crate::telemetry::Measurement;
+use stm32f4xx_hal::pac::TIM1;
+use stm32f4xx_hal::timer::PwmChannel;
+use vl53l1x_uld::RangeStatus;
+
+/// Period of the servo signal (50 Hz).
+pub const PERIOD_US: u32 = 20_000;
+
+pub struct Servo {
+    pwm: PwmChannel<TIM1, 0>,
+}
+
+impl Servo {
+    pub fn new(pwm: PwmChannel<TIM1, 0>) -> Self {
+        let mut servo = Self { pwm };
+        servo.set_pulse_us(config::MIN_PULSE_US);
+        servo.pwm.enable();
+        servo
+    }
+
+    /// Move the servo to the position for the measurement.
+    pub fn update(&mut self, measurement: &Measurement) {
+        if measurement.status == RangeStatus::Valid {
+            self.set_pulse_us(pulse_us(measurement.distance_mm));
+        }
+    }
+
+    /// Move the servo to its failsafe position as the TOF sensor is lost.
+    pub fn failsafe(&mut self) {
+        self.set_pulse_us(config::FAILSAFE_PULSE_US);
+    }
+
+    fn set_pulse_us(&mut self, pulse_us: u32) {
+        let max_duty = self.pwm.get_max_duty() as u32;
+        self.pwm.set_duty((pulse_us * max_duty / PERIOD_US) as u16);
+    }
+}
+
+/// Pulse width for the distance.
+fn pulse_us(distance_mm: u16) -> u32 {
+    let distance = distance_mm.clamp(config::MIN_MM, config::MAX_MM) as u32;
+    let range = (config::MAX_MM - config::MIN_MM) as u32;
+    let offset = (distance - config::MIN_MM as u32)
+        * config::MAX_PULSE_US.abs_diff(config::MIN_PULSE_US)
+        / range;
+    if config::MIN_PULSE_US <= config::MAX_PULSE_US {
+        config::MIN_PULSE_US + offset
+    } else {
+        config::MIN_PULSE_US - offset
+    }
+}
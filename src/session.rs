@@ -0,0 +1,73 @@
+//! A random identifier of each boot (a session), so that a host can tell a restart of the board
+//! from a gap in the data & split the data up accordingly: it's part of the session header (see
+//! [`crate::telemetry::session_frame`]) & of the header of the log files on the SD card.
+//!
+//! Unlike the [`crate::device_id::DeviceId`] & the sequence numbers (which continue after a reset,
+//! see [`crate::clock`]) it changes with every boot. It's derived from the noise of
+//! [`NOISE_SAMPLES`] conversions of the internal channels of the ADC at boot.
+
+use crate::storage::crc32;
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Number of conversions whose noise makes up the identifier.
+pub const NOISE_SAMPLES: usize = 32;
+
+static SESSION: AtomicU32 = AtomicU32::new(0);
+
+/// Formatted as 8 hexadecimal digits, never `0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionId(pub u32);
+
+impl SessionId {
+    /// Derive the identifier from the conversions of the ADC, only their noisy lower bits
+    /// differ between the boots.
+    pub fn from_noise(samples: &[u16; NOISE_SAMPLES]) -> Self {
+        let mut bytes = [0; 2 * NOISE_SAMPLES];
+        for (chunk, sample) in bytes.chunks_exact_mut(2).zip(samples) {
+            chunk.copy_from_slice(&sample.to_le_bytes());
+        }
+        Self(crc32(&bytes).max(1))
+    }
+
+    /// Make this the identifier of the current session, once at boot.
+    pub fn set(self) {
+        SESSION.store(self.0, Ordering::Relaxed);
+    }
+
+    /// The identifier of the current session, `0` until it has been set.
+    pub fn get() -> Self {
+        Self(SESSION.load(Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+
+impl defmt::Format for SessionId {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{=u32:08x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_changes_the_identifier() {
+        let mut samples = [1_650; NOISE_SAMPLES];
+        let first = SessionId::from_noise(&samples);
+        samples[7] += 1;
+        let second = SessionId::from_noise(&samples);
+        assert_ne!(first, second);
+        assert_ne!(first.0, 0);
+        assert_eq!(SessionId::from_noise(&samples), second);
+
+        second.set();
+        assert_eq!(SessionId::get().to_string().len(), 8);
+    }
+}
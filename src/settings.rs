@@ -0,0 +1,368 @@
+//! The settings which can be changed at runtime, stored in the [`crate::eeprom`] with the `save`
+//! command so that the device comes back up with them after a reset.
+//!
+//! Each group of settings is stored under its own key, groups which haven't been saved yet keep
+//! the defaults at boot (`None`). Once all of them have been written the [`SCHEMA_VERSION`] & the
+//! CRC of all settings are stored, thus settings of another schema or a mix of two `save`s (e.g.
+//! after a reset in between) are detected at boot.
+
+use crate::eeprom::{self, Eeprom, Key};
+use crate::geofence::{ZoneConfig, Zones, MAX_ZONES};
+#[cfg(feature = "motor-pid")]
+use crate::pid::Gains;
+use crate::scaling::Scaling;
+use crate::schedule::Schedule;
+use crate::storage::crc32_continue;
+use crate::telemetry::FrameFormat;
+use core::cmp::Ordering;
+use stm32f4xx_hal::flash;
+use stm32f4xx_hal::pac::FLASH;
+use stm32f4xx_hal::watchdog::IndependentWatchdog;
+use vl53l1x_uld::DistanceMode;
+
+/// Version of the format in which the settings are stored.
+const EEPROM_VERSION: u8 = 1;
+
+/// Version of the schema of the settings:
+/// 1. stored by a firmware without the schema (there's no schema record)
+/// 2. the same values, with the schema record
+///
+/// Settings of an older schema are migrated at boot (see [`Settings::migrate`]), a change of the
+/// format of a value thus bumps the version & adds a step which converts the stored values.
+pub const SCHEMA_VERSION: u8 = 2;
+
+/// The state of the stored settings, see [`Settings::schema`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum SchemaState {
+    Current,
+    /// Stored with this older schema, they need to be migrated.
+    Outdated(u8),
+    /// Stored by a newer firmware with this schema, which can't be migrated back. The defaults are
+    /// used until the next `save`.
+    Newer(u8),
+    /// The CRC doesn't match the stored settings, e.g. because a `save` has been interrupted. The
+    /// defaults are used until the next `save`.
+    Corrupt,
+}
+
+impl SchemaState {
+    /// The state of the settings with the CRC `crc` & the schema record `stored` (`None` if there
+    /// isn't any).
+    fn of(stored: Option<[u8; 5]>, crc: u32) -> Self {
+        let Some([version, stored_crc @ ..]) = stored else {
+            return Self::Outdated(1);
+        };
+        if u32::from_le_bytes(stored_crc) != crc {
+            return Self::Corrupt;
+        }
+        match version.cmp(&SCHEMA_VERSION) {
+            Ordering::Less => Self::Outdated(version),
+            Ordering::Equal => Self::Current,
+            Ordering::Greater => Self::Newer(version),
+        }
+    }
+}
+
+/// The measurement settings of the TOF sensor.
+///
+/// This doesn't implement [`defmt::Format`] as [`DistanceMode`] doesn't, use
+/// [`defmt::Debug2Format`] to log it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TofSettings {
+    pub distance_mode: DistanceMode,
+    pub timing_budget_ms: u16,
+    pub inter_measurement_ms: u16,
+}
+
+impl TofSettings {
+    /// Layout: distance mode, timing budget & inter-measurement period.
+    pub fn encode(&self) -> [u8; 5] {
+        let mut value = [0; 5];
+        value[0] = self.distance_mode as u8;
+        value[1..3].copy_from_slice(&self.timing_budget_ms.to_le_bytes());
+        value[3..].copy_from_slice(&self.inter_measurement_ms.to_le_bytes());
+        value
+    }
+
+    pub fn decode(value: &[u8; 5]) -> Option<Self> {
+        Some(Self {
+            distance_mode: match value[0] {
+                1 => DistanceMode::Short,
+                2 => DistanceMode::Long,
+                _ => return None,
+            },
+            timing_budget_ms: u16::from_le_bytes([value[1], value[2]]),
+            inter_measurement_ms: u16::from_le_bytes([value[3], value[4]]),
+        })
+    }
+}
+
+/// The settings of the distance hold controller.
+#[cfg(feature = "motor-pid")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotorSettings {
+    pub enabled: bool,
+    pub target_mm: u16,
+    pub gains: Gains,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub tof: Option<TofSettings>,
+    pub frame_format: Option<FrameFormat>,
+    pub scaling: Option<Scaling>,
+    /// The zones of the [`crate::geofence`].
+    pub zones: Option<Zones>,
+    /// The [`crate::publish_interval`], 0 if every measurement is published.
+    pub publish_interval_ms: Option<u16>,
+    /// The delta of the [`crate::change_filter`], 0 if every measurement is published.
+    pub change_delta_mm: Option<u16>,
+    /// The distance to the target of the offset calibration, see [`crate::calibration`].
+    pub cal_target_mm: Option<u16>,
+    /// The active hours of the alarms, see [`crate::schedule`].
+    pub schedule: Option<Schedule>,
+    /// The threshold of the alarm output & of the LoRa alarms.
+    #[cfg(any(feature = "alarm-output", feature = "lora"))]
+    pub alarm_threshold_mm: Option<u16>,
+    #[cfg(feature = "buzzer")]
+    pub buzzer_muted: Option<bool>,
+    #[cfg(feature = "motor-pid")]
+    pub motor: Option<MotorSettings>,
+}
+
+impl Settings {
+    /// The stored settings, the defaults unless they have the current schema.
+    pub fn load(eeprom: &Eeprom, flash: &FLASH) -> Self {
+        if Self::schema(eeprom, flash) != SchemaState::Current {
+            return Self::default();
+        }
+        Self {
+            tof: read(eeprom, flash, eeprom::keys::TOF)
+                .and_then(|value| TofSettings::decode(&value)),
+            frame_format: read(eeprom, flash, eeprom::keys::FRAME_FORMAT).and_then(
+                |[value]: [u8; 1]| match value {
+                    0 => Some(FrameFormat::Csv),
+                    1 => Some(FrameFormat::Json),
+                    _ => None,
+                },
+            ),
+            scaling: read(eeprom, flash, eeprom::keys::SCALING)
+                .and_then(|value| Scaling::decode(&value)),
+            zones: read(eeprom, flash, eeprom::keys::ZONES).map(|[configured]: [u8; 1]| {
+                core::array::from_fn(|index| {
+                    (configured & 1 << index != 0)
+                        .then(|| read(eeprom, flash, zone_key(index)))
+                        .flatten()
+                        .map(|value| ZoneConfig::decode(&value))
+                })
+            }),
+            publish_interval_ms: read(eeprom, flash, eeprom::keys::PUBLISH_INTERVAL)
+                .map(u16::from_le_bytes),
+            change_delta_mm: read(eeprom, flash, eeprom::keys::CHANGE_DELTA)
+                .map(u16::from_le_bytes),
+            cal_target_mm: read(eeprom, flash, eeprom::keys::CAL_TARGET).map(u16::from_le_bytes),
+            schedule: read(eeprom, flash, eeprom::keys::SCHEDULE)
+                .and_then(|value| Schedule::decode(&value)),
+            #[cfg(any(feature = "alarm-output", feature = "lora"))]
+            alarm_threshold_mm: read(eeprom, flash, eeprom::keys::ALARM_THRESHOLD)
+                .map(u16::from_le_bytes),
+            #[cfg(feature = "buzzer")]
+            buzzer_muted: read(eeprom, flash, eeprom::keys::BUZZER)
+                .map(|[value]: [u8; 1]| value != 0),
+            #[cfg(feature = "motor-pid")]
+            motor: read(eeprom, flash, eeprom::keys::MOTOR).and_then(|value: [u8; 3]| {
+                let [kp, ki, kd] = [
+                    eeprom::keys::MOTOR_KP,
+                    eeprom::keys::MOTOR_KI,
+                    eeprom::keys::MOTOR_KD,
+                ]
+                .map(|key| read(eeprom, flash, key).map(f32::from_le_bytes));
+                Some(MotorSettings {
+                    enabled: value[0] != 0,
+                    target_mm: u16::from_le_bytes([value[1], value[2]]),
+                    gains: Gains {
+                        kp: kp?,
+                        ki: ki?,
+                        kd: kd?,
+                    },
+                })
+            }),
+        }
+    }
+
+    /// The state of the stored settings.
+    pub fn schema(eeprom: &Eeprom, flash: &FLASH) -> SchemaState {
+        SchemaState::of(
+            read(eeprom, flash, eeprom::keys::SETTINGS_SCHEMA),
+            stored_crc(eeprom, flash),
+        )
+    }
+
+    /// Migrate settings of an older schema to the current one, returns the state before. See
+    /// [`Eeprom::write`] for the watchdog.
+    pub fn migrate(
+        eeprom: &mut Eeprom,
+        flash: &mut FLASH,
+        watchdog: &mut IndependentWatchdog,
+    ) -> Result<SchemaState, flash::Error> {
+        let state = Self::schema(eeprom, flash);
+        if let SchemaState::Outdated(_) = state {
+            // 1 => 2: only the schema record is added, the values are the same. The steps of later
+            // versions convert the values here, one version after the other.
+            write_schema(eeprom, flash, watchdog)?;
+        }
+        Ok(state)
+    }
+
+    /// Store all settings which are set & the schema, see [`Eeprom::write`] for the watchdog.
+    pub fn store(
+        &self,
+        eeprom: &mut Eeprom,
+        flash: &mut FLASH,
+        watchdog: &mut IndependentWatchdog,
+    ) -> Result<(), flash::Error> {
+        let mut write =
+            |key: Key, value: &[u8]| eeprom.write(flash, key, EEPROM_VERSION, value, watchdog);
+        if let Some(tof) = self.tof {
+            write(eeprom::keys::TOF, &tof.encode())?;
+        }
+        if let Some(frame_format) = self.frame_format {
+            write(eeprom::keys::FRAME_FORMAT, &[frame_format as u8])?;
+        }
+        if let Some(scaling) = self.scaling {
+            write(eeprom::keys::SCALING, &scaling.encode())?;
+        }
+        if let Some(zones) = self.zones {
+            // the zones are written first as they are only used together with the mask
+            let mut configured = 0;
+            for (index, zone) in zones.iter().enumerate() {
+                if let Some(zone) = zone {
+                    write(zone_key(index), &zone.encode())?;
+                    configured |= 1 << index;
+                }
+            }
+            write(eeprom::keys::ZONES, &[configured])?;
+        }
+        if let Some(interval_ms) = self.publish_interval_ms {
+            write(eeprom::keys::PUBLISH_INTERVAL, &interval_ms.to_le_bytes())?;
+        }
+        if let Some(delta_mm) = self.change_delta_mm {
+            write(eeprom::keys::CHANGE_DELTA, &delta_mm.to_le_bytes())?;
+        }
+        if let Some(target_mm) = self.cal_target_mm {
+            write(eeprom::keys::CAL_TARGET, &target_mm.to_le_bytes())?;
+        }
+        if let Some(schedule) = self.schedule {
+            write(eeprom::keys::SCHEDULE, &schedule.encode())?;
+        }
+        #[cfg(any(feature = "alarm-output", feature = "lora"))]
+        if let Some(threshold_mm) = self.alarm_threshold_mm {
+            write(eeprom::keys::ALARM_THRESHOLD, &threshold_mm.to_le_bytes())?;
+        }
+        #[cfg(feature = "buzzer")]
+        if let Some(muted) = self.buzzer_muted {
+            write(eeprom::keys::BUZZER, &[muted as u8])?;
+        }
+        #[cfg(feature = "motor-pid")]
+        if let Some(motor) = self.motor {
+            // the gains are written first as they are only used together with the motor record
+            write(eeprom::keys::MOTOR_KP, &motor.gains.kp.to_le_bytes())?;
+            write(eeprom::keys::MOTOR_KI, &motor.gains.ki.to_le_bytes())?;
+            write(eeprom::keys::MOTOR_KD, &motor.gains.kd.to_le_bytes())?;
+            let target = motor.target_mm.to_le_bytes();
+            write(
+                eeprom::keys::MOTOR,
+                &[motor.enabled as u8, target[0], target[1]],
+            )?;
+        }
+        write_schema(eeprom, flash, watchdog)
+    }
+}
+
+/// The keys of all settings, also of the features which aren't enabled, so that the CRC doesn't
+/// depend on them.
+fn keys() -> impl Iterator<Item = Key> {
+    use eeprom::keys::*;
+    [
+        TOF,
+        FRAME_FORMAT,
+        ALARM_THRESHOLD,
+        BUZZER,
+        MOTOR,
+        MOTOR_KP,
+        MOTOR_KI,
+        MOTOR_KD,
+        SCALING,
+        ZONES,
+        PUBLISH_INTERVAL,
+        CHANGE_DELTA,
+        CAL_TARGET,
+        SCHEDULE,
+    ]
+    .into_iter()
+    .chain((0..MAX_ZONES).map(zone_key))
+}
+
+/// The CRC-32 of the keys, the lengths & the values of all stored settings.
+fn stored_crc(eeprom: &Eeprom, flash: &FLASH) -> u32 {
+    keys().fold(0, |crc, key| {
+        let mut value = [0; eeprom::MAX_VALUE_LEN];
+        match eeprom.read(flash, key, EEPROM_VERSION, &mut value) {
+            Some(len) => crc32_continue(crc32_continue(crc, &[key, len as u8]), &value[..len]),
+            None => crc,
+        }
+    })
+}
+
+/// Store the current schema with the CRC of the stored settings, once all of them have been
+/// written.
+fn write_schema(
+    eeprom: &mut Eeprom,
+    flash: &mut FLASH,
+    watchdog: &mut IndependentWatchdog,
+) -> Result<(), flash::Error> {
+    let mut value = [SCHEMA_VERSION; 5];
+    value[1..].copy_from_slice(&stored_crc(eeprom, flash).to_le_bytes());
+    eeprom.write(
+        flash,
+        eeprom::keys::SETTINGS_SCHEMA,
+        EEPROM_VERSION,
+        &value,
+        watchdog,
+    )
+}
+
+fn zone_key(index: usize) -> Key {
+    eeprom::keys::ZONE + (index % MAX_ZONES) as Key
+}
+
+fn read<const N: usize>(eeprom: &Eeprom, flash: &FLASH, key: Key) -> Option<[u8; N]> {
+    eeprom.read_array(flash, key, EEPROM_VERSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_the_schema() {
+        let record = |version: u8, crc: u32| {
+            let mut value = [version; 5];
+            value[1..].copy_from_slice(&crc.to_le_bytes());
+            Some(value)
+        };
+        assert_eq!(SchemaState::of(None, 0x1234), SchemaState::Outdated(1));
+        assert_eq!(
+            SchemaState::of(record(SCHEMA_VERSION, 0x1234), 0x1234),
+            SchemaState::Current
+        );
+        assert_eq!(
+            SchemaState::of(record(SCHEMA_VERSION + 1, 0x1234), 0x1234),
+            SchemaState::Newer(SCHEMA_VERSION + 1)
+        );
+        assert_eq!(
+            SchemaState::of(record(SCHEMA_VERSION, 0x1234), 0x4321),
+            SchemaState::Corrupt
+        );
+    }
+}
@@ -0,0 +1,68 @@
+//! Low-power halt after the `shutdown` command, so that the power can be removed safely at any
+//! time: the TOF sensor is parked with its `XSHUT` pin (`PB2`) & the microcontroller waits in the
+//! stop mode (with all clocks except the ones of the RTC & the watchdog stopped) until the board
+//! is reset.
+//!
+//! The independent watchdog can't be stopped once it's running. Its timeout is extended to the
+//! maximum instead & the wakeup timer of the RTC wakes the microcontroller to feed it.
+
+use crate::clock::Clock;
+use cortex_m::peripheral::NVIC;
+use stm32f4xx_hal::pac::{self, Interrupt};
+use stm32f4xx_hal::prelude::*;
+use stm32f4xx_hal::watchdog::IndependentWatchdog;
+
+pub use crate::board::TofShutdown;
+
+/// Timeout of the watchdog during the halt, the maximum with the 32 kHz of the LSI.
+const WATCHDOG_TIMEOUT_MS: u32 = 32_000;
+/// Interval at which the watchdog is fed during the halt.
+const WAKEUP_INTERVAL_S: u32 = 10;
+
+/// Park the sensor & halt until the next reset. The ranging & the data loggers must have been
+/// stopped before.
+#[allow(unsafe_code)]
+pub fn halt(
+    tof_shutdown: &mut TofShutdown,
+    clock: &mut Clock,
+    watchdog: &mut IndependentWatchdog,
+) -> ! {
+    defmt::info!("halting until the next reset");
+    tof_shutdown.set_low();
+    cortex_m::interrupt::disable();
+    watchdog.start(WATCHDOG_TIMEOUT_MS.millis());
+    watchdog.feed();
+
+    // the interrupts aren't taken anymore, but an enabled one which is pending ends the stop mode
+    let nvic = NVIC::PTR;
+    unsafe {
+        for i in 0..(*nvic).icer.len() {
+            (*nvic).icer[i].write(u32::MAX);
+            (*nvic).icpr[i].write(u32::MAX);
+        }
+    }
+    // SAFETY: the firmware doesn't continue, nothing else accesses the peripherals anymore
+    let device = unsafe { pac::Peripherals::steal() };
+    let mut exti = device.EXTI;
+    exti.imr.reset();
+    exti.emr.reset();
+    exti.pr.write(|w| unsafe { w.bits(u32::MAX) });
+    clock.start_wakeup(WAKEUP_INTERVAL_S, &mut exti);
+    clock.clear_wakeup();
+    unsafe { NVIC::unmask(Interrupt::RTC_WKUP) };
+
+    // the stop mode with the voltage regulator in low-power mode & the flash powered down
+    device
+        .PWR
+        .cr
+        .modify(|_, w| w.pdds().clear_bit().lpds().set_bit().fpds().set_bit());
+    let mut core = unsafe { cortex_m::Peripherals::steal() };
+    core.SCB.set_sleepdeep();
+    loop {
+        cortex_m::asm::wfi();
+        // the microcontroller continues on the HSI, which is good enough to feed the watchdog
+        clock.clear_wakeup();
+        NVIC::unpend(Interrupt::RTC_WKUP);
+        watchdog.feed();
+    }
+}
@@ -0,0 +1,296 @@
+//! Simulation of the TOF sensor with synthetic measurements (`sim` feature), so that the whole
+//! pipeline (filtering, telemetry, application modes, outputs) can be exercised on a bare Nucleo.
+//!
+//! [`SimBus`] takes the place of the I2C bus of the sensor & emulates the registers of the
+//! VL53L1X which are used by the driver, thus the setup, the settings & the calibration work like
+//! with a real sensor. The distance follows the [`config::WAVEFORM`] between [`config::MIN_MM`]
+//! & [`config::MAX_MM`] with noise & dropouts (measurements without a target). The measurements
+//! are generated by [`step`] instead of the sensor, which also replaces its data ready interrupt.
+
+use crate::config::sim as config;
+use crate::range_sensor::{ReadShortIndex, WriteShortIndex};
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+use vl53l1x_uld::comm::{Read, Write};
+use vl53l1x_uld::{Polarity, Register};
+
+/// Number of emulated registers, up to the model ID.
+const REGISTERS: usize = 0x120;
+/// The range status of a valid measurement, in the encoding of the registers.
+const STATUS_VALID: u8 = 9;
+/// The range status of a measurement without a target, in the encoding of the registers.
+const STATUS_SIGNAL_FAILURE: u8 = 4;
+/// Value of the oscillator calibration, as used for the inter-measurement period. The default
+/// configuration of the driver results in 100 ms with it.
+const OSC_CALIBRATE_VAL: u16 = 36;
+/// Interval of the measurements until the inter-measurement period has been set.
+const DEFAULT_INTERVAL_MS: u32 = 100;
+
+/// Shape of the synthetic distance over one [`config::PERIOD_MS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Waveform {
+    /// Always [`config::MIN_MM`].
+    Constant,
+    /// Oscillating around the middle, e.g. for the filters.
+    Sine,
+    /// Rising from the minimum to the maximum & falling again.
+    Triangle,
+    /// Alternating between the minimum & the maximum, e.g. for the presence detection.
+    Square,
+    /// Rising from the minimum to the maximum.
+    Sawtooth,
+}
+
+impl Waveform {
+    /// Parse the name of the waveform at compile time.
+    pub const fn parse(name: &str) -> Self {
+        match name.as_bytes() {
+            b"constant" => Self::Constant,
+            b"sine" => Self::Sine,
+            b"triangle" => Self::Triangle,
+            b"square" => Self::Square,
+            b"sawtooth" => Self::Sawtooth,
+            _ => panic!("invalid waveform"),
+        }
+    }
+
+    /// The level (0 - 1000) at the time `t` within the `period`.
+    fn level(&self, t: u32, period: u32) -> u32 {
+        let half = period / 2;
+        match self {
+            Self::Constant => 0,
+            Self::Sine => {
+                // Bhaskara's approximation of the sine of each half wave
+                let (x, p) = ((t % half) as u64, half as u64);
+                let sine = (16_000 * x * (p - x) / (5 * p * p - 4 * x * (p - x))) as u32;
+                if t < half {
+                    500 + sine / 2
+                } else {
+                    500 - sine / 2
+                }
+            }
+            Self::Triangle if t < half => 1000 * t / half,
+            Self::Triangle => 1000 * (period - t) / half,
+            Self::Square if t < half => 1000,
+            Self::Square => 0,
+            Self::Sawtooth => (1000 * t as u64 / period as u64) as u32,
+        }
+    }
+}
+
+/// The state of the simulated sensor.
+struct Sensor {
+    registers: [u8; REGISTERS],
+    ranging: bool,
+    /// Whether a measurement is available, until the interrupt is cleared.
+    data_ready: bool,
+    /// State of the xorshift generator for the noise & the dropouts.
+    random: u32,
+    /// Time of the latest [`step`].
+    now_ms: u32,
+}
+
+static SENSOR: Mutex<RefCell<Sensor>> = Mutex::new(RefCell::new(Sensor::new()));
+
+impl Sensor {
+    const fn new() -> Self {
+        let mut registers = [0; REGISTERS];
+        let model_id = crate::tof::MODEL_ID.to_be_bytes();
+        registers[Register::IDENTIFICATION__MODEL_ID as usize] = model_id[0];
+        registers[Register::IDENTIFICATION__MODEL_ID as usize + 1] = model_id[1];
+        registers[Register::FIRMWARE__SYSTEM_STATUS as usize] = 1;
+        let osc = OSC_CALIBRATE_VAL.to_be_bytes();
+        registers[Register::RESULT__OSC_CALIBRATE_VAL as usize] = osc[0];
+        registers[Register::RESULT__OSC_CALIBRATE_VAL as usize + 1] = osc[1];
+        Self {
+            registers,
+            ranging: false,
+            data_ready: false,
+            random: 0x1234_5678,
+            now_ms: 0,
+        }
+    }
+
+    fn register_u16(&self, register: Register) -> u16 {
+        let index = register as usize;
+        u16::from_be_bytes([self.registers[index], self.registers[index + 1]])
+    }
+
+    fn write(&mut self, register: u16, bytes: &[u8]) {
+        if register == Register::SYSTEM__MODE_START as u16 {
+            self.ranging = bytes.first() == Some(&0x40);
+            // the driver waits for the first measurement during the initialisation
+            if self.ranging {
+                self.measure(self.now_ms);
+            }
+        } else if register == Register::SYSTEM__INTERRUPT_CLEAR as u16 {
+            self.data_ready = false;
+        }
+        for (i, &byte) in bytes.iter().enumerate() {
+            if let Some(register) = self.registers.get_mut(register as usize + i) {
+                *register = byte;
+            }
+        }
+    }
+
+    fn read(&self, register: u16, bytes: &mut [u8]) {
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let index = register as usize + i;
+            *byte = if index == Register::GPIO__TIO_HV_STATUS as usize {
+                // the driver compares the status with the polarity to check for data
+                let polarity = self.registers[Register::GPIO_HV_MUX__CTRL as usize] & 0x10;
+                let ready = Polarity::from(polarity) as u8;
+                if self.data_ready {
+                    ready
+                } else {
+                    ready ^ 1
+                }
+            } else {
+                self.registers.get(index).copied().unwrap_or(0)
+            };
+        }
+    }
+
+    fn next_random(&mut self) -> u32 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 17;
+        self.random ^= self.random << 5;
+        self.random
+    }
+
+    /// Put a measurement at `now_ms` into the result registers.
+    fn measure(&mut self, now_ms: u32) {
+        let level = config::WAVEFORM.level(now_ms % config::PERIOD_MS, config::PERIOD_MS);
+        let range = (config::MAX_MM - config::MIN_MM) as u32;
+        let noise = match config::NOISE_MM as u32 {
+            0 => 0,
+            noise => (self.next_random() % (2 * noise + 1)) as i32 - noise as i32,
+        };
+        // the offset is stored in quarters of a millimeter
+        let offset = self.register_u16(Register::ALGO__PART_TO_PART_RANGE_OFFSET_MM) as i16 >> 2;
+        let distance =
+            (config::MIN_MM as u32 + range * level / 1000) as i32 + noise + offset as i32;
+        let dropout = self.next_random() % 100 < config::DROPOUT_PERCENT as u32;
+        let (status, distance) = if dropout {
+            (STATUS_SIGNAL_FAILURE, 0)
+        } else {
+            (STATUS_VALID, distance.clamp(0, u16::MAX as i32) as u16)
+        };
+
+        let result = &mut self.registers[Register::RESULT__RANGE_STATUS as usize..][..17];
+        result.fill(0);
+        result[0] = status;
+        // the number of SPADs, the ambient & the signal rates are those of a typical target
+        result[3] = 16;
+        result[7..9].copy_from_slice(&100u16.to_be_bytes());
+        result[13..15].copy_from_slice(&distance.to_be_bytes());
+        result[15..17].copy_from_slice(&(if dropout { 0u16 } else { 1000 }).to_be_bytes());
+        self.data_ready = true;
+    }
+
+    /// The inter-measurement period, decoded like the driver does it.
+    fn interval_ms(&self) -> u32 {
+        let index = Register::SYSTEM__INTERMEASUREMENT_PERIOD as usize;
+        let period = u32::from_be_bytes([
+            self.registers[index],
+            self.registers[index + 1],
+            self.registers[index + 2],
+            self.registers[index + 3],
+        ]);
+        match period as u64 * 1000 / (OSC_CALIBRATE_VAL as u64 * 1065) {
+            0 => DEFAULT_INTERVAL_MS,
+            interval_ms => interval_ms as u32,
+        }
+    }
+}
+
+/// Generate the next measurement if the sensor is ranging & the previous one has been read.
+/// Returns whether a measurement is available (i.e. the data ready interrupt has to be raised) &
+/// the time until the next step.
+pub fn step(now_ms: u32) -> (bool, u32) {
+    cortex_m::interrupt::free(|cs| {
+        let mut sensor = SENSOR.borrow(cs).borrow_mut();
+        sensor.now_ms = now_ms;
+        if sensor.ranging && !sensor.data_ready {
+            sensor.measure(now_ms);
+        }
+        (sensor.ranging && sensor.data_ready, sensor.interval_ms())
+    })
+}
+
+/// The bus to the simulated sensor, the I2C address is ignored.
+pub struct SimBus;
+
+/// The simulated bus never fails.
+#[derive(Debug)]
+pub enum SimError {}
+
+impl Write for SimBus {
+    type Error = SimError;
+
+    fn write_registers(
+        &mut self,
+        _address: u8,
+        register: [u8; 2],
+        bytes: &[u8],
+    ) -> Result<(), Self::Error> {
+        cortex_m::interrupt::free(|cs| {
+            let register = u16::from_be_bytes(register);
+            SENSOR.borrow(cs).borrow_mut().write(register, bytes);
+        });
+        Ok(())
+    }
+}
+
+impl Read for SimBus {
+    type Error = SimError;
+
+    fn read_registers(
+        &mut self,
+        _address: u8,
+        register: [u8; 2],
+        bytes: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        cortex_m::interrupt::free(|cs| {
+            let register = u16::from_be_bytes(register);
+            SENSOR.borrow(cs).borrow().read(register, bytes);
+        });
+        Ok(())
+    }
+}
+
+impl ReadShortIndex for SimBus {
+    type Error = SimError;
+
+    /// The simulated VL53L1X takes the single byte as the upper byte of its 16 bit index.
+    fn read_short_index(
+        &mut self,
+        _address: u8,
+        index: u8,
+        bytes: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        cortex_m::interrupt::free(|cs| {
+            let register = u16::from_be_bytes([index, 0]);
+            SENSOR.borrow(cs).borrow().read(register, bytes);
+        });
+        Ok(())
+    }
+}
+
+impl WriteShortIndex for SimBus {
+    type Error = SimError;
+
+    /// Like a read, the single byte is the upper byte of the 16 bit index.
+    fn write_short_index(
+        &mut self,
+        _address: u8,
+        index: u8,
+        bytes: &[u8],
+    ) -> Result<(), Self::Error> {
+        cortex_m::interrupt::free(|cs| {
+            let register = u16::from_be_bytes([index, 0]);
+            SENSOR.borrow(cs).borrow_mut().write(register, bytes);
+        });
+        Ok(())
+    }
+}
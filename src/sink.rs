@@ -0,0 +1,130 @@
+//! The pipeline of sinks to which the published measurements & the other telemetry frames are
+//! handed, see [`crate::links::Links`].
+//!
+//! Every link to a host is a sink, as well as the optional ones which are selected with cargo
+//! features:
+//! - `defmt-sink`: logs each published measurement with defmt, e.g. to follow them with a probe
+//!   while the UART is used otherwise.
+//! - `uart-binary`: sends each published measurement as a binary record on the TX pin of USART1
+//!   (`PA9`), see `binary_record`.
+//!
+//! A new transport implements [`Sink`] & is registered in [`crate::links::Links::for_each_sink`],
+//! the tasks which publish the measurements aren't changed. The displays aren't sinks, they show
+//! the latest measurement (published or not) & are redrawn by their own task, see
+//! [`crate::display`].
+
+#[cfg(feature = "uart-binary")]
+use crate::log_record::{Chain, Encoded, Record};
+use crate::telemetry::Measurement;
+
+#[cfg(all(feature = "uart-binary", feature = "wifi"))]
+compile_error!("the features `uart-binary` and `wifi` can't be combined as both use USART1");
+#[cfg(all(feature = "uart-binary", feature = "uart-loopback"))]
+compile_error!(
+    "the features `uart-binary` and `uart-loopback` can't be combined as both use USART1"
+);
+
+/// A destination of the telemetry. The frames are already formatted, a sink which doesn't send
+/// text (e.g. LoRa) uses the measurements themselves.
+pub trait Sink {
+    /// A published measurement with its frame.
+    fn measurement(&mut self, measurement: &Measurement, frame: &str);
+
+    /// A measurement which is published in a [`crate::batch`]. Ignored by the sinks which send
+    /// the frames of the batches instead.
+    fn batched(&mut self, measurement: &Measurement) {
+        let _ = measurement;
+    }
+
+    /// The frame of a [`crate::batch`], which is dropped like a measurement if the sink doesn't
+    /// keep up.
+    fn batch(&mut self, frame: &str) {
+        let _ = frame;
+    }
+
+    /// Any other frame (e.g. a rollup or a track), ignored by the sinks which don't send text.
+    fn frame(&mut self, frame: &str) {
+        let _ = frame;
+    }
+}
+
+/// Encode a measurement as the record of the `uart-binary` sink: the format of the
+/// [`crate::log_record`]s, each one being the first of its [`Chain`] (thus the difference to
+/// zero). A record is self-contained, the host can start to decode at any of them.
+#[cfg(feature = "uart-binary")]
+pub fn binary_record(measurement: &Measurement) -> Encoded {
+    Chain::new().encode(&Record::from(measurement))
+}
+
+/// Logs the published measurements with defmt.
+#[cfg(feature = "defmt-sink")]
+pub struct DefmtSink;
+
+#[cfg(feature = "defmt-sink")]
+impl Sink for DefmtSink {
+    fn measurement(&mut self, measurement: &Measurement, _frame: &str) {
+        defmt::info!(
+            "measurement {}: {} mm (status {})",
+            measurement.seq,
+            measurement.distance_mm,
+            measurement.status as u8
+        );
+    }
+
+    fn batched(&mut self, measurement: &Measurement) {
+        self.measurement(measurement, "");
+    }
+}
+
+/// Sends the published measurements as [`binary_record`]s on USART1, nothing is received.
+#[cfg(feature = "uart-binary")]
+pub struct BinaryUart {
+    uart: crate::uart::BufferedUart<stm32f4xx_hal::pac::USART1>,
+}
+
+#[cfg(feature = "uart-binary")]
+impl BinaryUart {
+    pub fn new(uart: crate::uart::BufferedUart<stm32f4xx_hal::pac::USART1>) -> Self {
+        Self { uart }
+    }
+
+    /// Handle the UART interrupt, i.e. send the next bytes.
+    pub fn on_interrupt(&mut self) {
+        self.uart.on_interrupt(|_, _| {});
+    }
+}
+
+#[cfg(feature = "uart-binary")]
+impl Sink for BinaryUart {
+    fn measurement(&mut self, measurement: &Measurement, _frame: &str) {
+        self.uart.write(&binary_record(measurement));
+    }
+
+    fn batched(&mut self, measurement: &Measurement) {
+        self.measurement(measurement, "");
+    }
+}
+
+#[cfg(all(test, feature = "uart-binary"))]
+mod tests {
+    use super::*;
+    use crate::log_record::Decoded;
+    use crate::telemetry::test_measurement;
+
+    #[test]
+    fn binary_records_are_self_contained() {
+        let mut measurement = Measurement {
+            seq: 1_000,
+            utc_ms: Some(1_760_000_000_000),
+            ..test_measurement(120_000, 1_234)
+        };
+        binary_record(&measurement);
+        measurement.seq += 1;
+        let record = binary_record(&measurement);
+        // decoded without the previous record
+        assert_eq!(
+            Chain::new().decode(&record),
+            Decoded::Record(Record::from(&measurement), record.len())
+        );
+    }
+}
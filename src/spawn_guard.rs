@@ -0,0 +1,90 @@
+//! Accounting of the failed spawns of the software tasks: a spawn fails if the queue of the task
+//! (its capacity) or the timer queue is full, the task then doesn't run. Instead of discarding the
+//! result, [`Spawned::or_count`] counts the failures per task & logs them.
+//!
+//! A periodic task which fails to schedule itself stops for good. The firmware resets in that case
+//! (& if it fails to start at boot) for the task which feeds the independent watchdog, as the [`crate::task_watchdog`] only
+//! notices it once the watchdog is about to expire, the others are caught by the task watchdog.
+//! The spawns which are expected to fail now & then (e.g. of a task which is already pending)
+//! still discard the result, with a comment why.
+
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+
+/// Number of tasks whose failures are counted separately, further ones only count in the total.
+pub const MAX_TASKS: usize = 16;
+
+static FAILURES: Mutex<RefCell<Failures>> = Mutex::new(RefCell::new(Failures::new()));
+
+/// The failed spawns since boot.
+pub struct Failures {
+    counts: heapless::LinearMap<&'static str, u32, MAX_TASKS>,
+    total: u32,
+}
+
+impl Failures {
+    pub const fn new() -> Self {
+        Self {
+            counts: heapless::LinearMap::new(),
+            total: 0,
+        }
+    }
+
+    /// Count a failed spawn of the task, returns the number of its failures (`None` if it isn't
+    /// counted separately as there are too many tasks).
+    pub fn record(&mut self, task: &'static str) -> Option<u32> {
+        self.total = self.total.wrapping_add(1);
+        match self.counts.get_mut(task) {
+            Some(count) => {
+                *count = count.wrapping_add(1);
+                Some(*count)
+            }
+            None => self.counts.insert(task, 1).ok().map(|_| 1),
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+}
+
+/// The result of a spawn.
+pub trait Spawned {
+    /// Count & log the failure of the spawn of the task, returns whether the spawn succeeded.
+    fn or_count(self, task: &'static str) -> bool;
+}
+
+impl<H, T> Spawned for Result<H, T> {
+    fn or_count(self, task: &'static str) -> bool {
+        if self.is_ok() {
+            return true;
+        }
+        let count = cortex_m::interrupt::free(|cs| FAILURES.borrow(cs).borrow_mut().record(task));
+        match count {
+            Some(count) => defmt::warn!("failed to spawn {} ({} times)", task, count),
+            None => defmt::warn!("failed to spawn {}", task),
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_the_failures_per_task() {
+        let mut failures = Failures::new();
+        assert_eq!(failures.record("log_event"), Some(1));
+        assert_eq!(failures.record("report_health"), Some(1));
+        assert_eq!(failures.record("log_event"), Some(2));
+        for i in 2..MAX_TASKS {
+            let task = Box::leak(format!("task_{}", i).into_boxed_str());
+            assert_eq!(failures.record(task), Some(1));
+        }
+        // only counted in the total once the map is full
+        assert_eq!(failures.record("one_too_many"), None);
+        assert_eq!(failures.record("log_event"), Some(3));
+        assert_eq!(failures.total(), MAX_TASKS as u32 + 3);
+    }
+}
@@ -0,0 +1,118 @@
+//! Shows the state of the firmware on the user LED (LD2, `PA5`) of the Nucleo board.
+//!
+//! A setting which has been changed (e.g. the application mode) can be indicated by blinking
+//! the LED a number of times, this temporarily replaces the pattern. The blink codes of the errors
+//! (see [`crate::blink_code`]) are shown the same way, but repeatedly.
+
+use crate::board::Led;
+
+/// Length of a single blink of an indication.
+const INDICATION_BLINK_MS: u32 = 400;
+/// Time the LED is on during a blink of an indication.
+const INDICATION_ON_MS: u32 = 150;
+/// Time the LED stays off before & after an indication to separate it from the pattern.
+const INDICATION_PAUSE_MS: u32 = 600;
+/// Time the LED stays off between the repetitions of a blink code.
+const CODE_PAUSE_MS: u32 = 2_000;
+
+/// Blink patterns of the LED.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Pattern {
+    /// The sensor isn't ranging.
+    Off,
+    /// Double blink once per second while ranging.
+    Heartbeat,
+    /// 5 Hz blinking while the sensor reports errors.
+    FastBlink,
+    /// 1 Hz blinking during the offset calibration.
+    SlowBlink,
+    /// Continuously on in safe mode.
+    Solid,
+    /// The given number of blinks & a pause, repeatedly: the blink code of the latest error, in
+    /// safe mode or while the sensor reports errors.
+    Code(u8),
+}
+
+impl Pattern {
+    /// Length of a single repetition of the pattern.
+    fn period_ms(&self) -> u32 {
+        match self {
+            Pattern::Off | Pattern::Solid => 1,
+            Pattern::Heartbeat => 1_000,
+            Pattern::FastBlink => 200,
+            Pattern::SlowBlink => 1_000,
+            Pattern::Code(count) => *count as u32 * INDICATION_BLINK_MS + CODE_PAUSE_MS,
+        }
+    }
+
+    /// Whether the LED is on at the given time within the period.
+    fn is_on(&self, phase_ms: u32) -> bool {
+        match self {
+            Pattern::Off => false,
+            Pattern::Solid => true,
+            Pattern::Heartbeat => phase_ms < 100 || (200..300).contains(&phase_ms),
+            Pattern::FastBlink => phase_ms < 100,
+            Pattern::SlowBlink => phase_ms < 500,
+            Pattern::Code(count) => {
+                phase_ms < *count as u32 * INDICATION_BLINK_MS
+                    && phase_ms % INDICATION_BLINK_MS < INDICATION_ON_MS
+            }
+        }
+    }
+}
+
+pub struct StatusLed {
+    led: Led,
+    pattern: Pattern,
+    /// Time at which the current pattern has been started.
+    started_ms: u32,
+    /// The number of blinks of the current indication & the time at which it has been started.
+    indication: Option<(u8, u32)>,
+}
+
+impl StatusLed {
+    pub fn new(mut led: Led) -> Self {
+        led.set_low();
+        Self {
+            led,
+            pattern: Pattern::Off,
+            started_ms: 0,
+            indication: None,
+        }
+    }
+
+    /// Blink the LED `count` times, afterwards the pattern is shown again.
+    pub fn indicate(&mut self, count: u8, now_ms: u32) {
+        defmt::debug!("status LED: indicating {}", count);
+        self.indication = Some((count, now_ms));
+    }
+
+    /// Switch to another pattern. Setting the current pattern again doesn't restart it.
+    pub fn set_pattern(&mut self, pattern: Pattern, now_ms: u32) {
+        if pattern != self.pattern {
+            defmt::debug!("status LED: {}", pattern);
+            self.pattern = pattern;
+            self.started_ms = now_ms;
+        }
+    }
+
+    /// Update the LED. Must be called periodically, the resolution of the patterns is 100 ms.
+    pub fn tick(&mut self, now_ms: u32) {
+        if let Some((count, started_ms)) = self.indication {
+            let elapsed_ms = now_ms.wrapping_sub(started_ms);
+            let Some(blinks_ms) = elapsed_ms.checked_sub(INDICATION_PAUSE_MS) else {
+                self.led.set_low();
+                return;
+            };
+            if blinks_ms < count as u32 * INDICATION_BLINK_MS + INDICATION_PAUSE_MS {
+                let blink = blinks_ms / INDICATION_BLINK_MS;
+                let on = blink < count as u32 && blinks_ms % INDICATION_BLINK_MS < INDICATION_ON_MS;
+                self.led.set_state(on.into());
+                return;
+            }
+            self.indication = None;
+        }
+        let phase_ms = now_ms.wrapping_sub(self.started_ms) % self.pattern.period_ms();
+        self.led.set_state(self.pattern.is_on(phase_ms).into());
+    }
+}
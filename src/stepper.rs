@@ -0,0 +1,183 @@
+//! Moves a mechanism with a stepper motor proportionally to the measured distance or scans until a
+//! target distance is reached.
+//!
+//! The motor is connected to `PC0` - `PC3`, either to the four inputs of a ULN2003 driver (driven
+//! with half steps) or with `PC0` as STEP and `PC1` as DIR input of a step/dir driver (e.g. A4988,
+//! see [`crate::config::stepper`]). The steps are timed by TIM9 and the speed is ramped up & down
+//! with a constant acceleration.
+//!
+//! In the follow mode (the default) the position is proportional to the distance, with the start
+//! of the travel at the minimum distance. In the scan mode the motor moves towards the end of the
+//! travel until the distance is at or below the requested one.
+
+use crate::config::stepper as config;
+use crate::telemetry::Measurement;
+use stm32f4xx_hal::gpio::{ErasedPin, Output};
+use stm32f4xx_hal::pac::TIM9;
+use stm32f4xx_hal::prelude::*;
+use stm32f4xx_hal::timer::{CounterUs, Event};
+use vl53l1x_uld::RangeStatus;
+
+/// Coil states of the half step sequence for the ULN2003 (bit 0 = `IN1`).
+const HALF_STEPS: [u8; 8] = [
+    0b0001, 0b0011, 0b0010, 0b0110, 0b0100, 0b1100, 0b1000, 0b1001,
+];
+/// The motor starts & stops at this speed, in steps per second.
+const START_SPEED: f32 = 50.0;
+/// Delay before the first step after the motor has been idle.
+const START_DELAY_US: u32 = 1_000;
+/// Length of the pulses on STEP (about 2.4 us at 84 MHz), the drivers need at least 1 - 2 us.
+const STEP_PULSE_CYCLES: u32 = 200;
+
+/// What the position of the motor is derived from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Mode {
+    /// The position is proportional to the distance.
+    Follow,
+    /// Move towards the end of the travel until the distance is at or below this value.
+    Scan(u16),
+    /// The scan has ended, either because the distance has been reached or because the end of the
+    /// travel has been reached.
+    Stopped,
+}
+
+pub struct Stepper {
+    pins: [ErasedPin<Output>; 4],
+    timer: CounterUs<TIM9>,
+    mode: Mode,
+    /// Current & target position in steps from the start of the travel.
+    position: i32,
+    target: i32,
+    /// Current speed in steps per second, 0 if the motor is idle.
+    speed: f32,
+    /// Direction of the current movement, `1` towards the end of the travel and `-1` back.
+    direction: i32,
+}
+
+impl Stepper {
+    pub fn new(mut pins: [ErasedPin<Output>; 4], mut timer: CounterUs<TIM9>) -> Self {
+        for pin in &mut pins {
+            pin.set_low();
+        }
+        timer.listen(Event::Update);
+        Self {
+            pins,
+            timer,
+            mode: Mode::Follow,
+            position: 0,
+            target: 0,
+            speed: 0.0,
+            direction: 1,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn position(&self) -> i32 {
+        self.position
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        defmt::info!("stepper mode: {}", mode);
+        self.mode = mode;
+    }
+
+    /// Update the target position with a new measurement.
+    pub fn update(&mut self, measurement: &Measurement) {
+        let valid = measurement.status == RangeStatus::Valid;
+        match self.mode {
+            Mode::Follow if valid => {
+                let distance = measurement
+                    .distance_mm
+                    .clamp(config::MIN_MM, config::MAX_MM);
+                let range = (config::MAX_MM - config::MIN_MM) as i32;
+                self.move_to((distance - config::MIN_MM) as i32 * config::STEPS / range);
+            }
+            Mode::Scan(target_mm) if valid && measurement.distance_mm <= target_mm => {
+                defmt::info!("scan reached {} mm at step {}", target_mm, self.position);
+                self.mode = Mode::Stopped;
+                self.move_to(self.position);
+            }
+            Mode::Scan(_) if self.position == config::STEPS => {
+                defmt::warn!("scan reached the end of the travel");
+                self.mode = Mode::Stopped;
+            }
+            Mode::Scan(_) => self.move_to(config::STEPS),
+            _ => {}
+        }
+    }
+
+    /// Stop at the current position as the TOF sensor is lost. The stepper follows again with the
+    /// next measurement, a scan continues.
+    pub fn failsafe(&mut self) {
+        self.move_to(self.position);
+    }
+
+    /// Execute the next step. Must be called from the interrupt of TIM9.
+    pub fn on_timer(&mut self) {
+        self.timer.wait().ok();
+
+        let to_go = self.target - self.position;
+        if to_go == 0 {
+            self.speed = 0.0;
+            self.timer.cancel().ok();
+            if !config::STEP_DIR {
+                // don't keep the coils energized while idle to save power & avoid heating
+                for pin in &mut self.pins {
+                    pin.set_low();
+                }
+            }
+            return;
+        }
+
+        let direction = to_go.signum();
+        if self.speed == 0.0 {
+            self.direction = direction;
+            self.speed = START_SPEED;
+        } else {
+            // steps needed to slow down to the start speed
+            let braking = (self.speed * self.speed - START_SPEED * START_SPEED)
+                / (2.0 * config::ACCELERATION as f32);
+            let acceleration = config::ACCELERATION as f32 / self.speed;
+            if direction != self.direction || (to_go.unsigned_abs() as f32) <= braking {
+                self.speed = (self.speed - acceleration).max(START_SPEED);
+                if self.speed == START_SPEED {
+                    self.direction = direction;
+                }
+            } else {
+                self.speed = (self.speed + acceleration).min(config::MAX_SPEED as f32);
+            }
+        }
+
+        self.step();
+        self.timer
+            .start(((1_000_000.0 / self.speed) as u32).micros())
+            .ok();
+    }
+
+    fn move_to(&mut self, target: i32) {
+        self.target = target.clamp(0, config::STEPS);
+        if self.speed == 0.0 && self.target != self.position {
+            self.timer.start(START_DELAY_US.micros()).ok();
+        }
+    }
+
+    /// Move by one step in the current direction.
+    fn step(&mut self) {
+        self.position += self.direction;
+        if config::STEP_DIR {
+            let [step, direction, ..] = &mut self.pins;
+            direction.set_state((self.direction > 0).into());
+            step.set_high();
+            cortex_m::asm::delay(STEP_PULSE_CYCLES);
+            step.set_low();
+        } else {
+            let coils = HALF_STEPS[self.position.rem_euclid(8) as usize];
+            for (i, pin) in self.pins.iter_mut().enumerate() {
+                pin.set_state((coils & (1 << i) != 0).into());
+            }
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Access to the sectors of the internal flash used by the [`crate::eeprom`] (& the slots
+//! of the [`crate::firmware_update`]).
+//!
+//! The flash is programmed in rows of [`ROW_LEN`] bytes, all records are a multiple of it. The
+//! sectors are accessed through [`Flash`], which the host tests implement with [`RamFlash`].
+
+use crate::config::watchdog as config;
+use core::sync::atomic::{AtomicU32, Ordering};
+use stm32f4xx_hal::flash::{self, FlashExt};
+use stm32f4xx_hal::hal::watchdog::WatchdogEnable;
+use stm32f4xx_hal::pac::FLASH;
+use stm32f4xx_hal::prelude::*;
+use stm32f4xx_hal::timer::fugit::MillisDurationU32;
+
+/// Length of the rows in which the flash is programmed.
+pub const ROW_LEN: usize = 16;
+/// Length of the 128 KiB sectors 5 - 7.
+pub const SECTOR_LEN: usize = 128 * 1024;
+/// Watchdog timeout while a sector is being erased, which takes up to 4 s.
+const ERASE_WATCHDOG_TIMEOUT_MS: u32 = if config::TIMEOUT_MS > 8_000 {
+    config::TIMEOUT_MS
+} else {
+    8_000
+};
+
+static ERASURES: AtomicU32 = AtomicU32::new(0);
+
+/// The flash which contains the sectors.
+pub trait Flash {
+    /// The contents of the whole flash.
+    fn contents(&self) -> &[u8];
+    /// Erase a sector.
+    fn erase(&mut self, sector: u8) -> Result<(), flash::Error>;
+    /// Program `data` at `offset` (from the start of the flash), which must be erased.
+    fn program(&mut self, offset: usize, data: &[u8]) -> Result<(), flash::Error>;
+}
+
+impl Flash for FLASH {
+    fn contents(&self) -> &[u8] {
+        self.read()
+    }
+
+    fn erase(&mut self, sector: u8) -> Result<(), flash::Error> {
+        self.unlocked().erase(sector)
+    }
+
+    fn program(&mut self, offset: usize, data: &[u8]) -> Result<(), flash::Error> {
+        self.unlocked().program(offset, data.iter())
+    }
+}
+
+/// The watchdog which is extended while a sector is erased.
+pub trait Watchdog: WatchdogEnable<Time = MillisDurationU32> {}
+
+impl<T> Watchdog for T where T: WatchdogEnable<Time = MillisDurationU32> {}
+
+/// Offset of one of the 128 KiB sectors 5 - 7 from the start of the flash.
+pub const fn sector_offset(sector: u8) -> usize {
+    (sector as usize - 4) * SECTOR_LEN
+}
+
+/// The contents of the sector.
+pub fn read_sector(flash: &impl Flash, sector: u8) -> &[u8] {
+    let offset = sector_offset(sector);
+    &flash.contents()[offset..offset + SECTOR_LEN]
+}
+
+/// Erase a sector. The watchdog is extended meanwhile as the flash (and thus the firmware) stalls
+/// during that time.
+pub fn erase_sector(
+    flash: &mut impl Flash,
+    sector: u8,
+    watchdog: &mut impl Watchdog,
+) -> Result<(), flash::Error> {
+    defmt::info!("erasing flash sector {}", sector);
+    watchdog.start(ERASE_WATCHDOG_TIMEOUT_MS.millis());
+    let result = flash.erase(sector);
+    watchdog.start(config::TIMEOUT_MS.millis());
+    ERASURES.fetch_add(1, Ordering::Relaxed);
+    result
+}
+
+/// Number of sectors which have been erased since boot, each restarts the watchdog.
+pub fn erasures() -> u32 {
+    ERASURES.load(Ordering::Relaxed)
+}
+
+/// Program `data` into the flash at `offset` (from the start of the flash), which must be erased.
+pub fn program(flash: &mut impl Flash, offset: usize, data: &[u8]) -> Result<(), flash::Error> {
+    flash.program(offset, data)
+}
+
+/// Whether the row hasn't been programmed since the sector has been erased.
+pub fn is_erased(row: &[u8]) -> bool {
+    row.iter().all(|&byte| byte == 0xFF)
+}
+
+/// The CRC-32 (as used by Ethernet & zip) of the data.
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_continue(0, data)
+}
+
+/// The CRC-32 of data which continues after data with the CRC `crc` (`0` at the start), i.e.
+/// `crc32_continue(crc32(a), b)` is the CRC-32 of `a` followed by `b`.
+pub fn crc32_continue(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// A copy of the flash up to the end of sector 7 in RAM, for the tests of the modules which store
+/// records in the flash.
+#[cfg(test)]
+pub struct RamFlash(pub Vec<u8>);
+
+#[cfg(test)]
+impl RamFlash {
+    /// An erased flash.
+    pub fn new() -> Self {
+        Self(vec![0xFF; sector_offset(7) + SECTOR_LEN])
+    }
+}
+
+#[cfg(test)]
+impl Flash for RamFlash {
+    fn contents(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn erase(&mut self, sector: u8) -> Result<(), flash::Error> {
+        let offset = sector_offset(sector);
+        self.0[offset..offset + SECTOR_LEN].fill(0xFF);
+        Ok(())
+    }
+
+    fn program(&mut self, offset: usize, data: &[u8]) -> Result<(), flash::Error> {
+        let target = &mut self.0[offset..offset + data.len()];
+        if !is_erased(target) {
+            return Err(flash::Error::ProgrammingSequence);
+        }
+        target.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+/// A watchdog which doesn't need the peripheral, for the tests of the modules which erase sectors.
+#[cfg(test)]
+pub struct NoWatchdog;
+
+#[cfg(test)]
+impl WatchdogEnable for NoWatchdog {
+    type Time = MillisDurationU32;
+
+    fn start<T: Into<Self::Time>>(&mut self, _period: T) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_of_the_check_string() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn crc32_continues_over_the_parts() {
+        let crc = crc32_continue(crc32(b"1234"), b"56789");
+        assert_eq!(crc, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn erases_only_the_sector() {
+        let mut flash = RamFlash::new();
+        program(&mut flash, sector_offset(6), &[0; ROW_LEN]).unwrap();
+        program(&mut flash, sector_offset(7), &[0; ROW_LEN]).unwrap();
+        erase_sector(&mut flash, 7, &mut NoWatchdog).unwrap();
+        assert!(is_erased(&read_sector(&flash, 7)[..ROW_LEN]));
+        assert!(!is_erased(&read_sector(&flash, 6)[..ROW_LEN]));
+    }
+
+    #[test]
+    fn programming_needs_an_erased_row() {
+        let mut flash = RamFlash::new();
+        program(&mut flash, sector_offset(6), &[0x5A; ROW_LEN]).unwrap();
+        assert!(program(&mut flash, sector_offset(6), &[0; ROW_LEN]).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! A software watchdog in front of the independent watchdog: the critical tasks check in each time
+//! they run & the IWDG is only fed as long as all of them have checked in within their interval
+//! plus [`config::MARGIN_MS`]. The IWDG alone only notices a blocked feeding task, this also
+//! catches a task which stopped running (e.g. because it couldn't be spawned again).
+//!
+//! Once a task has missed its deadline the IWDG isn't fed anymore, thus the board resets within
+//! [`crate::config::watchdog::TIMEOUT_MS`]. The task is named in the log before, as the reset
+//! itself is only reported as one of the watchdog (see [`crate::reset_log`]).
+
+use crate::config::task_watchdog as config;
+
+/// The tasks which have to check in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Task {
+    /// The supervision of the TOF sensor, which keeps the acquisition going.
+    Acquisition,
+    /// The check of the change filter for the keepalive.
+    Processing,
+    /// The health report.
+    Telemetry,
+}
+
+impl Task {
+    const ALL: [Task; 3] = [Task::Acquisition, Task::Processing, Task::Telemetry];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Task::Acquisition => "acquisition",
+            Task::Processing => "processing",
+            Task::Telemetry => "telemetry",
+        }
+    }
+
+    /// The interval at which the task runs.
+    const fn interval_ms(&self) -> u32 {
+        match self {
+            Task::Acquisition => crate::sensor_supervisor::CHECK_INTERVAL_MS,
+            Task::Processing => crate::change_filter::CHECK_INTERVAL_MS,
+            Task::Telemetry => crate::config::health::INTERVAL_S * 1_000,
+        }
+    }
+}
+
+/// The state of the tasks, see [`TaskWatchdog::check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// All tasks are alive, the IWDG may be fed.
+    Alive,
+    /// The task has just missed its deadline by this many ms.
+    Missed(Task, u32),
+    /// A task has missed its deadline before, the board is about to reset.
+    Failed,
+}
+
+pub struct TaskWatchdog {
+    /// The time by which each task has to check in again, `None` for the released ones.
+    deadlines_ms: [Option<u32>; Task::ALL.len()],
+    failed: bool,
+}
+
+impl TaskWatchdog {
+    /// All tasks have to check in for the first time within their interval after boot.
+    pub const fn new() -> Self {
+        let mut deadlines_ms = [None; Task::ALL.len()];
+        let mut i = 0;
+        while i < Task::ALL.len() {
+            deadlines_ms[i] = Some(Task::ALL[i].interval_ms() + config::MARGIN_MS);
+            i += 1;
+        }
+        Self {
+            deadlines_ms,
+            failed: false,
+        }
+    }
+
+    /// The task is alive at `now_ms`.
+    pub fn check_in(&mut self, task: Task, now_ms: u32) {
+        self.deadlines_ms[task as usize] =
+            Some(now_ms.wrapping_add(task.interval_ms() + config::MARGIN_MS));
+    }
+
+    /// Stop expecting check-ins of the task, as it has been stopped on purpose.
+    pub fn release(&mut self, task: Task) {
+        self.deadlines_ms[task as usize] = None;
+    }
+
+    /// Check the deadlines at `now_ms`. Must be called before each feed of the IWDG.
+    pub fn check(&mut self, now_ms: u32) -> Status {
+        if self.failed {
+            return Status::Failed;
+        }
+        let missed = Task::ALL.into_iter().find_map(|task| {
+            let late_ms = now_ms.wrapping_sub(self.deadlines_ms[task as usize]?);
+            // the difference is negative (i.e. huge) as long as the deadline hasn't passed
+            (late_ms > 0 && late_ms <= u32::MAX / 2).then_some((task, late_ms))
+        });
+        match missed {
+            Some((task, late_ms)) => {
+                self.failed = true;
+                Status::Missed(task, late_ms)
+            }
+            None => Status::Alive,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fails_once_a_task_misses_its_deadline() {
+        let mut watchdog = TaskWatchdog::new();
+        let deadline_ms = Task::Acquisition.interval_ms() + config::MARGIN_MS;
+        assert_eq!(watchdog.check(deadline_ms), Status::Alive);
+
+        // the released task doesn't count
+        watchdog.release(Task::Acquisition);
+        watchdog.check_in(Task::Processing, deadline_ms);
+        watchdog.check_in(Task::Telemetry, deadline_ms);
+        assert_eq!(watchdog.check(deadline_ms + 1), Status::Alive);
+
+        let late_ms = deadline_ms + Task::Processing.interval_ms() + config::MARGIN_MS + 5;
+        watchdog.check_in(Task::Telemetry, late_ms);
+        assert_eq!(watchdog.check(late_ms), Status::Missed(Task::Processing, 5));
+        // the board resets even if the task checks in again
+        watchdog.check_in(Task::Processing, late_ms);
+        assert_eq!(watchdog.check(late_ms), Status::Failed);
+    }
+}
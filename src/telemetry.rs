@@ -0,0 +1,871 @@
+//! Telemetry frames sent to a host.
+//!
+//! Every frame is a single line terminated by `\r\n`, which makes the stream easy to read in a
+//! terminal and trivial to parse on the host side. The values are either comma separated or a JSON
+//! object, see [`FrameFormat`]. Each frame contains the [`DeviceId`], so that the frames of several
+//! boards can be told apart once they're aggregated.
+
+use crate::app_mode::AppMode;
+use crate::batch::Batch;
+use crate::build_info;
+use crate::capture::{self, Limit, Outcome};
+use crate::device_id::DeviceId;
+use crate::log_backend::Level;
+use crate::session::SessionId;
+use crate::track::Track;
+use core::fmt::{self, Write};
+use vl53l1x_uld::RangeStatus;
+
+/// A single range measurement as reported by the TOF sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Measurement {
+    /// Sequence number of the measurement, increments by one for each measurement. It continues
+    /// after a reset unless the power was lost, see [`crate::clock`].
+    pub seq: u32,
+    /// Time since boot at which the measurement has been read out.
+    pub timestamp_ms: u32,
+    /// The same time as calendar time (UTC, since the Unix epoch), `None` if the clock hasn't
+    /// been set yet.
+    pub utc_ms: Option<u64>,
+    /// The measured distance.
+    pub distance_mm: u16,
+    /// The status reported by the sensor for this measurement.
+    pub status: RangeStatus,
+    /// The ambient rate measured by the sensor, see [`crate::ambient`].
+    pub ambient_kcps: u16,
+    /// The distance converted with the [`crate::scaling`], `None` if it isn't scaled.
+    pub value: Option<crate::scaling::Milli>,
+    /// Whether the measurement has been taken during the [`crate::warm_up`] of the sensor.
+    pub settling: bool,
+    /// Position of the encoder at the time of the measurement, see [`crate::encoder`].
+    #[cfg(feature = "encoder")]
+    pub position: i32,
+    /// The distance split up according to the tilt measured by the IMU, `None` if it isn't
+    /// available.
+    #[cfg(feature = "imu")]
+    pub components: Option<crate::imu::Components>,
+    /// The latest sample of the environmental sensor, `None` if it isn't available.
+    #[cfg(feature = "environment")]
+    pub environment: Option<crate::environment::Environment>,
+    /// The distance of the ultrasonic sensor, `None` if it isn't valid.
+    #[cfg(feature = "ultrasonic")]
+    pub acoustic_mm: Option<u16>,
+    /// The distance fused from both sensors, `None` if neither is valid.
+    #[cfg(feature = "ultrasonic")]
+    pub fused: Option<crate::fusion::Fused>,
+}
+
+/// A valid measurement for the tests of the modules which process them, the other fields can be
+/// set with the struct update syntax.
+#[cfg(test)]
+pub fn test_measurement(timestamp_ms: u32, distance_mm: u16) -> Measurement {
+    Measurement {
+        seq: 0,
+        timestamp_ms,
+        utc_ms: None,
+        distance_mm,
+        status: RangeStatus::Valid,
+        ambient_kcps: 0,
+        value: None,
+        settling: false,
+        #[cfg(feature = "encoder")]
+        position: 0,
+        #[cfg(feature = "imu")]
+        components: None,
+        #[cfg(feature = "environment")]
+        environment: None,
+        #[cfg(feature = "ultrasonic")]
+        acoustic_mm: None,
+        #[cfg(feature = "ultrasonic")]
+        fused: None,
+    }
+}
+
+/// A range measurement of the second TOF sensor, see [`crate::second_tof`].
+#[cfg(feature = "second-tof")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecondMeasurement {
+    /// Sequence number of the measurements of the second sensor, starts at 1 after each boot.
+    pub seq: u32,
+    /// Time since boot at which the measurement has been read out.
+    pub timestamp_ms: u32,
+    /// The same time as calendar time (UTC, since the Unix epoch), `None` if the clock hasn't
+    /// been set yet.
+    pub utc_ms: Option<u64>,
+    pub distance_mm: u16,
+    pub status: RangeStatus,
+}
+
+/// A range measurement of a TOF sensor behind the I2C multiplexer, see [`crate::tof_mux`].
+#[cfg(feature = "tof-mux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArrayMeasurement {
+    /// The channel of the multiplexer of the sensor.
+    pub channel: u8,
+    /// Sequence number of the measurements of the sensor, starts at 1 after each boot.
+    pub seq: u32,
+    /// Time since boot at which the measurement has been read out.
+    pub timestamp_ms: u32,
+    /// The same time as calendar time (UTC, since the Unix epoch), `None` if the clock hasn't
+    /// been set yet.
+    pub utc_ms: Option<u64>,
+    pub distance_mm: u16,
+    pub status: RangeStatus,
+}
+
+/// State of the firmware sent in the periodic health report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Health {
+    /// Time since boot.
+    pub timestamp_ms: u32,
+    /// Calendar time (UTC, since the Unix epoch), `None` if the clock hasn't been set yet.
+    pub utc_ms: Option<u64>,
+    pub ranging: bool,
+    /// Number of measurements, i.e. the latest sequence number.
+    pub measurements: u32,
+    pub sensor_error: bool,
+    pub safe_mode: bool,
+    /// Whether the TOF sensor is lost & the latest measurement is stale, see
+    /// [`crate::sensor_supervisor`].
+    pub stale: bool,
+    /// Number of re-initialisations of the TOF sensor since boot, see [`crate::sensor_supervisor`].
+    pub reinits: u8,
+    /// Number of data ready interrupts suppressed since boot, see [`crate::interrupt_guard`].
+    pub suppressed_interrupts: u32,
+    /// Number of measurements dropped by the full [`crate::measurement_queue`] since boot.
+    pub queue_overflows: crate::measurement_queue::Overflows,
+    /// The latest sample of the power monitor, `None` if it isn't available.
+    #[cfg(feature = "power")]
+    pub power: Option<crate::power::Power>,
+}
+
+/// Statistics of the measurements since the previous rollup, see [`crate::rollup`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rollup {
+    /// Time since boot at the end of the rollup.
+    pub timestamp_ms: u32,
+    /// Calendar time (UTC, since the Unix epoch), `None` if the clock hasn't been set yet.
+    pub utc_ms: Option<u64>,
+    /// Time covered by the rollup.
+    pub duration_s: u32,
+    pub measurements: u32,
+    /// Number of measurements without a valid distance.
+    pub errors: u32,
+    /// The statistics of the valid distances, `None` if there was none.
+    pub min_mm: Option<u16>,
+    pub max_mm: Option<u16>,
+    pub mean_mm: Option<u16>,
+    /// Share of the measurements during which a target was present.
+    pub occupancy_pct: u8,
+    /// The achieved measurement rate in mHz.
+    pub rate_mhz: u32,
+    /// Whether the rate matches the configured one, `None` if the sensor hasn't been ranging.
+    pub rate_ok: Option<bool>,
+}
+
+/// Maximum length of a single telemetry frame (including the line ending). The longest one is a
+/// JSON measurement with the optional parts of all the features.
+pub const MAX_FRAME_LEN: usize = 288;
+
+/// Format of the telemetry frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum FrameFormat {
+    /// Comma separated values, the first value is the type of the frame.
+    Csv,
+    /// A JSON object per line with short keys (see the README), the type of the frame is in the
+    /// `t` field. Values which aren't available are `null`.
+    Json,
+}
+
+/// A fully formatted telemetry frame.
+pub type Frame = heapless::String<MAX_FRAME_LEN>;
+
+/// Format a health report as a telemetry frame.
+///
+/// The CSV frame is
+/// `H,<timestamp_ms>,<ranging>,<measurements>,<sensor_error>,<safe_mode>,<utc_ms>,<device>` (the
+/// calendar time is empty if the clock hasn't been set), followed by
+/// `,<bus_mv>,<current_ua>,<power_mw>` (empty if not available) if the power monitor is enabled.
+/// The JSON frame contains the same values.
+pub fn health_frame(health: &Health, format: FrameFormat) -> Result<Frame, fmt::Error> {
+    let mut frame = Frame::new();
+    match format {
+        FrameFormat::Csv => write!(
+            frame,
+            "H,{},{},{},{},{}",
+            health.timestamp_ms,
+            health.ranging as u8,
+            health.measurements,
+            health.sensor_error as u8,
+            health.safe_mode as u8
+        )?,
+        FrameFormat::Json => write!(
+            frame,
+            "{{\"t\":\"H\",\"ts\":{},\"ranging\":{},\"count\":{},\"error\":{},\"safe\":{}",
+            health.timestamp_ms,
+            health.ranging,
+            health.measurements,
+            health.sensor_error,
+            health.safe_mode
+        )?,
+    }
+    write_field(&mut frame, format, "utc", health.utc_ms)?;
+    write_device(&mut frame, format)?;
+    #[cfg(feature = "power")]
+    {
+        let power = health.power;
+        write_field(&mut frame, format, "mv", power.map(|p| p.bus_mv))?;
+        write_field(&mut frame, format, "ua", power.map(|p| p.current_ua))?;
+        write_field(&mut frame, format, "mw", power.map(|p| p.power_mw()))?;
+    }
+    write_field(&mut frame, format, "reinits", Some(health.reinits))?;
+    write_field(
+        &mut frame,
+        format,
+        "suppressed",
+        Some(health.suppressed_interrupts),
+    )?;
+    let overflows = health.queue_overflows;
+    write_field(&mut frame, format, "dropped_oldest", Some(overflows.oldest))?;
+    write_field(&mut frame, format, "dropped_newest", Some(overflows.newest))?;
+    write_flag(&mut frame, format, "stale", Some(health.stale))?;
+    end_frame(&mut frame, format)?;
+    Ok(frame)
+}
+
+/// Format the header of a telemetry session, which identifies the firmware (see
+/// [`crate::build_info`]), the active application mode & the boot (see [`crate::session`]).
+///
+/// The CSV frame is `I,<version>,<git_hash>,<build_unix_s>,<device>,<mode>,<session>`, the JSON
+/// frame contains the same values.
+pub fn session_frame(format: FrameFormat, app_mode: AppMode) -> Result<Frame, fmt::Error> {
+    let mut frame = Frame::new();
+    match format {
+        FrameFormat::Csv => write!(
+            frame,
+            "I,{},{},{}",
+            build_info::VERSION,
+            build_info::GIT_HASH,
+            build_info::BUILD_UNIX_S
+        )?,
+        FrameFormat::Json => write!(
+            frame,
+            "{{\"t\":\"I\",\"ver\":\"{}\",\"git\":\"{}\",\"built\":{}",
+            build_info::VERSION,
+            build_info::GIT_HASH,
+            build_info::BUILD_UNIX_S
+        )?,
+    }
+    write_device(&mut frame, format)?;
+    match format {
+        FrameFormat::Csv => write!(frame, ",{},{}", app_mode.name(), SessionId::get())?,
+        FrameFormat::Json => write!(
+            frame,
+            ",\"mode\":\"{}\",\"session\":\"{}\"",
+            app_mode.name(),
+            SessionId::get()
+        )?,
+    }
+    end_frame(&mut frame, format)?;
+    Ok(frame)
+}
+
+/// Format a keepalive of the [`crate::change_filter`] as a telemetry frame.
+///
+/// The CSV frame is `K,<timestamp_ms>,<seq>,<utc_ms>,<device>` with the sequence number of the
+/// latest measurement (published or not, the calendar time is empty if the clock hasn't been set).
+/// The JSON frame contains the same values.
+pub fn keepalive_frame(
+    timestamp_ms: u32,
+    seq: u32,
+    utc_ms: Option<u64>,
+    format: FrameFormat,
+) -> Result<Frame, fmt::Error> {
+    let mut frame = Frame::new();
+    match format {
+        FrameFormat::Csv => write!(frame, "K,{},{}", timestamp_ms, seq)?,
+        FrameFormat::Json => write!(
+            frame,
+            "{{\"t\":\"K\",\"ts\":{},\"seq\":{}",
+            timestamp_ms, seq
+        )?,
+    }
+    write_field(&mut frame, format, "utc", utc_ms)?;
+    write_device(&mut frame, format)?;
+    end_frame(&mut frame, format)?;
+    Ok(frame)
+}
+
+/// Format a mark of the [`crate::marker`] input as a telemetry frame.
+///
+/// The CSV frame is `M,<timestamp_ms>,<seq>,<number>,<edge>,<utc_ms>,<device>` with the sequence
+/// number of the latest measurement & the edge `rise` or `fall`. The JSON frame contains the same
+/// values.
+#[cfg(feature = "marker-input")]
+pub fn marker_frame(
+    timestamp_ms: u32,
+    seq: u32,
+    mark: &crate::marker::Mark,
+    utc_ms: Option<u64>,
+    format: FrameFormat,
+) -> Result<Frame, fmt::Error> {
+    let mut frame = Frame::new();
+    match format {
+        FrameFormat::Csv => write!(
+            frame,
+            "M,{},{},{},{}",
+            timestamp_ms,
+            seq,
+            mark.number,
+            mark.edge()
+        )?,
+        FrameFormat::Json => write!(
+            frame,
+            "{{\"t\":\"M\",\"ts\":{},\"seq\":{},\"n\":{},\"edge\":\"{}\"",
+            timestamp_ms,
+            seq,
+            mark.number,
+            mark.edge()
+        )?,
+    }
+    write_field(&mut frame, format, "utc", utc_ms)?;
+    write_device(&mut frame, format)?;
+    end_frame(&mut frame, format)?;
+    Ok(frame)
+}
+
+/// Format the header of a [`crate::capture`] as a telemetry frame.
+///
+/// The CSV frame is `C,<timestamp_ms>,<id>,start,<samples>,<duration_s>,<rate_hz>,<device>` (the
+/// limit which isn't used is empty, the rate if it isn't changed). The JSON frame contains the same
+/// values.
+pub fn capture_start_frame(
+    timestamp_ms: u32,
+    id: u16,
+    request: &capture::Request,
+    format: FrameFormat,
+) -> Result<Frame, fmt::Error> {
+    let mut frame = start_capture_frame(timestamp_ms, id, "start", format)?;
+    let (samples, duration_s) = match request.limit {
+        Limit::Samples(samples) => (Some(samples), None),
+        Limit::Seconds(seconds) => (None, Some(seconds)),
+    };
+    write_field(&mut frame, format, "samples", samples)?;
+    write_field(&mut frame, format, "duration_s", duration_s)?;
+    write_field(&mut frame, format, "rate_hz", request.rate_hz)?;
+    write_device(&mut frame, format)?;
+    end_frame(&mut frame, format)?;
+    Ok(frame)
+}
+
+/// Format the footer of a [`crate::capture`] as a telemetry frame.
+///
+/// The CSV frame is `C,<timestamp_ms>,<id>,<outcome>,<count>,<device>` with the completion status
+/// (see [`Outcome::name`]) & the number of captured measurements. The JSON frame contains the same
+/// values.
+pub fn capture_end_frame(
+    timestamp_ms: u32,
+    id: u16,
+    outcome: Outcome,
+    count: u32,
+    format: FrameFormat,
+) -> Result<Frame, fmt::Error> {
+    let mut frame = start_capture_frame(timestamp_ms, id, outcome.name(), format)?;
+    write_field(&mut frame, format, "count", Some(count))?;
+    write_device(&mut frame, format)?;
+    end_frame(&mut frame, format)?;
+    Ok(frame)
+}
+
+fn start_capture_frame(
+    timestamp_ms: u32,
+    id: u16,
+    state: &str,
+    format: FrameFormat,
+) -> Result<Frame, fmt::Error> {
+    let mut frame = Frame::new();
+    match format {
+        FrameFormat::Csv => write!(frame, "C,{},{},{}", timestamp_ms, id, state)?,
+        FrameFormat::Json => write!(
+            frame,
+            "{{\"t\":\"C\",\"ts\":{},\"id\":{},\"state\":\"{}\"",
+            timestamp_ms, id, state
+        )?,
+    }
+    Ok(frame)
+}
+
+/// Format a track of the [`crate::track`]ing as a telemetry frame.
+///
+/// The CSV frame is
+/// `T,<timestamp_ms>,<seq>,<position_mm>,<velocity_mm_s>,<confidence_pct>,<device>` with the
+/// sequence number of the latest measurement (the velocity is positive while the target moves
+/// away). The JSON frame contains the same values.
+pub fn track_frame(track: &Track, format: FrameFormat) -> Result<Frame, fmt::Error> {
+    let mut frame = Frame::new();
+    match format {
+        FrameFormat::Csv => write!(
+            frame,
+            "T,{},{},{},{},{}",
+            track.timestamp_ms,
+            track.seq,
+            track.position_mm,
+            track.velocity_mm_s,
+            track.confidence_pct
+        )?,
+        FrameFormat::Json => write!(
+            frame,
+            "{{\"t\":\"T\",\"ts\":{},\"seq\":{},\"mm\":{},\"mm_s\":{},\"conf\":{}",
+            track.timestamp_ms,
+            track.seq,
+            track.position_mm,
+            track.velocity_mm_s,
+            track.confidence_pct
+        )?,
+    }
+    write_device(&mut frame, format)?;
+    end_frame(&mut frame, format)?;
+    Ok(frame)
+}
+
+/// Format a rollup as a telemetry frame.
+///
+/// The CSV frame is
+/// `R,<timestamp_ms>,<duration_s>,<measurements>,<errors>,<occupancy_pct>,<min_mm>,<max_mm>,<mean_mm>,<utc_ms>,<device>`
+/// (the distances are empty if there was no valid measurement, the calendar time if the clock
+/// hasn't been set). The JSON frame contains the same values.
+pub fn rollup_frame(rollup: &Rollup, format: FrameFormat) -> Result<Frame, fmt::Error> {
+    let mut frame = Frame::new();
+    match format {
+        FrameFormat::Csv => write!(
+            frame,
+            "R,{},{},{},{},{}",
+            rollup.timestamp_ms,
+            rollup.duration_s,
+            rollup.measurements,
+            rollup.errors,
+            rollup.occupancy_pct
+        )?,
+        FrameFormat::Json => write!(
+            frame,
+            "{{\"t\":\"R\",\"ts\":{},\"s\":{},\"count\":{},\"err\":{},\"occ\":{}",
+            rollup.timestamp_ms,
+            rollup.duration_s,
+            rollup.measurements,
+            rollup.errors,
+            rollup.occupancy_pct
+        )?,
+    }
+    write_field(&mut frame, format, "min", rollup.min_mm)?;
+    write_field(&mut frame, format, "max", rollup.max_mm)?;
+    write_field(&mut frame, format, "mean", rollup.mean_mm)?;
+    write_field(&mut frame, format, "utc", rollup.utc_ms)?;
+    write_device(&mut frame, format)?;
+    write_field(&mut frame, format, "rate", Some(rollup.rate_mhz))?;
+    write_flag(&mut frame, format, "rate_ok", rollup.rate_ok)?;
+    end_frame(&mut frame, format)?;
+    Ok(frame)
+}
+
+/// Format a message of the [`crate::log_backend`] as a telemetry frame.
+///
+/// The CSV frame is `L,<timestamp_ms>,<level>,<device>,<message>` (the message is last as it may
+/// contain commas), the JSON frame contains the same values (with `'` instead of `"` & `/`
+/// instead of `\` in the message). The timestamp is the time at which the message is sent.
+pub fn log_frame(
+    timestamp_ms: u32,
+    level: Level,
+    message: &str,
+    format: FrameFormat,
+) -> Result<Frame, fmt::Error> {
+    let mut frame = Frame::new();
+    match format {
+        FrameFormat::Csv => {
+            write!(frame, "L,{},{}", timestamp_ms, level.name())?;
+            write_device(&mut frame, format)?;
+            write!(frame, ",{}", message)?;
+        }
+        FrameFormat::Json => {
+            write!(
+                frame,
+                "{{\"t\":\"L\",\"ts\":{},\"lvl\":\"{}\"",
+                timestamp_ms,
+                level.name()
+            )?;
+            write_device(&mut frame, format)?;
+            frame.push_str(",\"msg\":\"").map_err(|_| fmt::Error)?;
+            for c in message.chars() {
+                let c = match c {
+                    '"' => '\'',
+                    '\\' => '/',
+                    c if c.is_control() => ' ',
+                    c => c,
+                };
+                frame.push(c).map_err(|_| fmt::Error)?;
+            }
+            frame.push('"').map_err(|_| fmt::Error)?;
+        }
+    }
+    end_frame(&mut frame, format)?;
+    Ok(frame)
+}
+
+/// Format a measurement as a telemetry frame.
+///
+/// The CSV frame is
+/// `D,<seq>,<timestamp_ms>,<distance_mm>,<status>,<utc_ms>,<device>,<ambient_kcps>,<value>,<settling>`
+/// (the calendar time is empty if the clock hasn't been set, the scaled value if the distance isn't
+/// scaled, the settling flag is `1` during the [`crate::warm_up`]), followed by `,<position>` if the encoder is enabled
+/// and `,<vertical_mm>,<horizontal_mm>` (empty if not available) if the IMU is enabled and
+/// `,<temperature>,<humidity>,<pressure_pa>` (in 0.01 °C & 0.01 %, empty if not available) if the
+/// environmental sensor is enabled and `,<acoustic_mm>,<fused_mm>,<source>` (empty if not
+/// available, the source is a [`crate::fusion::Source`]) if the ultrasonic sensor is enabled. The
+/// JSON frame contains the same values.
+pub fn measurement_frame(
+    measurement: &Measurement,
+    format: FrameFormat,
+) -> Result<Frame, fmt::Error> {
+    let mut frame = Frame::new();
+    match format {
+        FrameFormat::Csv => write!(
+            frame,
+            "D,{},{},{},{}",
+            measurement.seq,
+            measurement.timestamp_ms,
+            measurement.distance_mm,
+            measurement.status as u8
+        )?,
+        FrameFormat::Json => write!(
+            frame,
+            "{{\"t\":\"D\",\"seq\":{},\"ts\":{},\"mm\":{},\"st\":{}",
+            measurement.seq,
+            measurement.timestamp_ms,
+            measurement.distance_mm,
+            measurement.status as u8
+        )?,
+    }
+    write_field(&mut frame, format, "utc", measurement.utc_ms)?;
+    write_device(&mut frame, format)?;
+    write_field(&mut frame, format, "amb", Some(measurement.ambient_kcps))?;
+    write_field(&mut frame, format, "val", measurement.value)?;
+    write_flag(&mut frame, format, "settling", Some(measurement.settling))?;
+    #[cfg(feature = "encoder")]
+    write_field(&mut frame, format, "pos", Some(measurement.position))?;
+    #[cfg(feature = "imu")]
+    {
+        let components = measurement.components;
+        write_field(
+            &mut frame,
+            format,
+            "v_mm",
+            components.map(|c| c.vertical_mm),
+        )?;
+        write_field(
+            &mut frame,
+            format,
+            "h_mm",
+            components.map(|c| c.horizontal_mm),
+        )?;
+    }
+    #[cfg(feature = "environment")]
+    {
+        let environment = measurement.environment;
+        write_field(
+            &mut frame,
+            format,
+            "temp",
+            environment.map(|e| e.temperature),
+        )?;
+        write_field(&mut frame, format, "rh", environment.map(|e| e.humidity))?;
+        write_field(&mut frame, format, "pa", environment.map(|e| e.pressure_pa))?;
+    }
+    #[cfg(feature = "ultrasonic")]
+    {
+        let fused = measurement.fused;
+        write_field(&mut frame, format, "us_mm", measurement.acoustic_mm)?;
+        write_field(&mut frame, format, "f_mm", fused.map(|f| f.distance_mm))?;
+        write_field(&mut frame, format, "src", fused.map(|f| f.source as u8))?;
+    }
+    end_frame(&mut frame, format)?;
+    Ok(frame)
+}
+
+/// Format a [`crate::batch`] of measurements as a telemetry frame.
+///
+/// The CSV frame is `B,<seq>,<timestamp_ms>,<count>,<utc_ms>,<device>` with the values of the first
+/// measurement (the calendar time is empty if the clock hasn't been set), followed by
+/// `,<offset_ms>,<distance_mm>,<status>` for each measurement with its time since the first one.
+/// The JSON frame contains the same values, the samples as a flat array in the `s` field.
+pub fn batch_frame(batch: &Batch, format: FrameFormat) -> Result<Frame, fmt::Error> {
+    let mut frame = Frame::new();
+    let count = batch.samples.len();
+    match format {
+        FrameFormat::Csv => write!(frame, "B,{},{},{}", batch.seq, batch.timestamp_ms, count)?,
+        FrameFormat::Json => write!(
+            frame,
+            "{{\"t\":\"B\",\"seq\":{},\"ts\":{},\"n\":{}",
+            batch.seq, batch.timestamp_ms, count
+        )?,
+    }
+    write_field(&mut frame, format, "utc", batch.utc_ms)?;
+    write_device(&mut frame, format)?;
+    if format == FrameFormat::Json {
+        write!(frame, ",\"s\":[")?;
+    }
+    for (i, sample) in batch.samples.iter().enumerate() {
+        let separator = if format == FrameFormat::Json && i == 0 {
+            ""
+        } else {
+            ","
+        };
+        write!(
+            frame,
+            "{}{},{},{}",
+            separator, sample.offset_ms, sample.distance_mm, sample.status as u8
+        )?;
+    }
+    if format == FrameFormat::Json {
+        write!(frame, "]")?;
+    }
+    end_frame(&mut frame, format)?;
+    Ok(frame)
+}
+
+/// Format a measurement of the second TOF sensor as a telemetry frame.
+///
+/// The CSV frame is `S,<seq>,<timestamp_ms>,<distance_mm>,<status>,<utc_ms>,<device>` (the
+/// calendar time is empty if the clock hasn't been set), the JSON frame contains the same values.
+#[cfg(feature = "second-tof")]
+pub fn second_measurement_frame(
+    measurement: &SecondMeasurement,
+    format: FrameFormat,
+) -> Result<Frame, fmt::Error> {
+    let mut frame = Frame::new();
+    match format {
+        FrameFormat::Csv => write!(
+            frame,
+            "S,{},{},{},{}",
+            measurement.seq,
+            measurement.timestamp_ms,
+            measurement.distance_mm,
+            measurement.status as u8
+        )?,
+        FrameFormat::Json => write!(
+            frame,
+            "{{\"t\":\"S\",\"seq\":{},\"ts\":{},\"mm\":{},\"st\":{}",
+            measurement.seq,
+            measurement.timestamp_ms,
+            measurement.distance_mm,
+            measurement.status as u8
+        )?,
+    }
+    write_field(&mut frame, format, "utc", measurement.utc_ms)?;
+    write_device(&mut frame, format)?;
+    end_frame(&mut frame, format)?;
+    Ok(frame)
+}
+
+/// Format a measurement of a TOF sensor behind the I2C multiplexer as a telemetry frame.
+///
+/// The CSV frame is `A,<channel>,<seq>,<timestamp_ms>,<distance_mm>,<status>,<utc_ms>,<device>`
+/// (the calendar time is empty if the clock hasn't been set), the JSON frame contains the same
+/// values.
+#[cfg(feature = "tof-mux")]
+pub fn array_measurement_frame(
+    measurement: &ArrayMeasurement,
+    format: FrameFormat,
+) -> Result<Frame, fmt::Error> {
+    let mut frame = Frame::new();
+    match format {
+        FrameFormat::Csv => write!(
+            frame,
+            "A,{},{},{},{},{}",
+            measurement.channel,
+            measurement.seq,
+            measurement.timestamp_ms,
+            measurement.distance_mm,
+            measurement.status as u8
+        )?,
+        FrameFormat::Json => write!(
+            frame,
+            "{{\"t\":\"A\",\"ch\":{},\"seq\":{},\"ts\":{},\"mm\":{},\"st\":{}",
+            measurement.channel,
+            measurement.seq,
+            measurement.timestamp_ms,
+            measurement.distance_mm,
+            measurement.status as u8
+        )?,
+    }
+    write_field(&mut frame, format, "utc", measurement.utc_ms)?;
+    write_device(&mut frame, format)?;
+    end_frame(&mut frame, format)?;
+    Ok(frame)
+}
+
+/// Append an optional field to the frame, it's left empty (CSV) or `null` (JSON) if it's `None`.
+fn write_field(
+    frame: &mut Frame,
+    format: FrameFormat,
+    name: &str,
+    value: Option<impl fmt::Display>,
+) -> fmt::Result {
+    match (format, value) {
+        (FrameFormat::Csv, Some(value)) => write!(frame, ",{}", value),
+        (FrameFormat::Csv, None) => write!(frame, ","),
+        (FrameFormat::Json, Some(value)) => write!(frame, ",\"{}\":{}", name, value),
+        (FrameFormat::Json, None) => write!(frame, ",\"{}\":null", name),
+    }
+}
+
+/// Append a flag to the frame, `1` or `0` in the CSV format.
+fn write_flag(
+    frame: &mut Frame,
+    format: FrameFormat,
+    name: &str,
+    value: Option<bool>,
+) -> fmt::Result {
+    match format {
+        FrameFormat::Csv => write_field(frame, format, name, value.map(u8::from)),
+        FrameFormat::Json => write_field(frame, format, name, value),
+    }
+}
+
+/// Append the [`DeviceId`] of the board to the frame.
+fn write_device(frame: &mut Frame, format: FrameFormat) -> fmt::Result {
+    match format {
+        FrameFormat::Csv => write!(frame, ",{}", DeviceId::get()),
+        FrameFormat::Json => write!(frame, ",\"dev\":\"{}\"", DeviceId::get()),
+    }
+}
+
+fn end_frame(frame: &mut Frame, format: FrameFormat) -> fmt::Result {
+    match format {
+        FrameFormat::Csv => write!(frame, "\r\n"),
+        FrameFormat::Json => write!(frame, "}}\r\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scaling::Milli;
+
+    fn measurement() -> Measurement {
+        Measurement {
+            seq: 42,
+            utc_ms: Some(1_760_000_000_000),
+            status: RangeStatus::SignalFailure,
+            ambient_kcps: 80,
+            value: Some(Milli(-1_250)),
+            settling: true,
+            ..test_measurement(1_500, 1_234)
+        }
+    }
+
+    /// The fields of a CSV frame, without the line ending.
+    fn csv_fields(frame: &Frame) -> heapless::Vec<&str, 24> {
+        frame.strip_suffix("\r\n").unwrap().split(',').collect()
+    }
+
+    #[test]
+    fn csv_measurements_can_be_read_back() {
+        let measurement = measurement();
+        let frame = measurement_frame(&measurement, FrameFormat::Csv).unwrap();
+        let fields = csv_fields(&frame);
+        assert_eq!(fields[0], "D");
+        assert_eq!(fields[1].parse(), Ok(measurement.seq));
+        assert_eq!(fields[2].parse(), Ok(measurement.timestamp_ms));
+        assert_eq!(fields[3].parse(), Ok(measurement.distance_mm));
+        assert_eq!(fields[4].parse(), Ok(measurement.status as u8));
+        assert_eq!(fields[5].parse().ok(), measurement.utc_ms);
+        assert_eq!(u32::from_str_radix(fields[6], 16), Ok(DeviceId::get().0));
+        assert_eq!(fields[7].parse(), Ok(measurement.ambient_kcps));
+        assert_eq!(fields[8], "-1.250");
+        assert_eq!(fields[9], "1");
+    }
+
+    #[test]
+    fn csv_fields_which_arent_available_are_empty() {
+        let measurement = Measurement {
+            utc_ms: None,
+            value: None,
+            settling: false,
+            ..measurement()
+        };
+        let frame = measurement_frame(&measurement, FrameFormat::Csv).unwrap();
+        let fields = csv_fields(&frame);
+        assert_eq!((fields[5], fields[8], fields[9]), ("", "", "0"));
+    }
+
+    #[test]
+    fn json_measurements_have_named_fields() {
+        let frame = measurement_frame(&measurement(), FrameFormat::Json).unwrap();
+        assert!(frame.starts_with(
+            "{\"t\":\"D\",\"seq\":42,\"ts\":1500,\"mm\":1234,\"st\":2,\"utc\":1760000000000,\
+             \"dev\":\"5f3a91c2\",\"amb\":80,\"val\":-1.250,\"settling\":true"
+        ));
+        assert!(frame.ends_with("}\r\n"));
+
+        let measurement = Measurement {
+            utc_ms: None,
+            ..measurement()
+        };
+        let frame = measurement_frame(&measurement, FrameFormat::Json).unwrap();
+        assert!(frame.contains(",\"utc\":null,"));
+    }
+
+    #[test]
+    fn the_longest_measurement_fits_into_a_frame() {
+        let measurement = Measurement {
+            seq: u32::MAX,
+            timestamp_ms: u32::MAX,
+            utc_ms: Some(u64::MAX),
+            distance_mm: u16::MAX,
+            status: RangeStatus::None,
+            ambient_kcps: u16::MAX,
+            value: Some(Milli(i32::MIN)),
+            settling: false,
+            #[cfg(feature = "encoder")]
+            position: i32::MIN,
+            #[cfg(feature = "imu")]
+            components: Some(crate::imu::Components {
+                vertical_mm: u16::MAX,
+                horizontal_mm: u16::MAX,
+            }),
+            #[cfg(feature = "environment")]
+            environment: Some(crate::environment::Environment {
+                temperature: i32::MIN,
+                humidity: u32::MAX,
+                pressure_pa: u32::MAX,
+            }),
+            #[cfg(feature = "ultrasonic")]
+            acoustic_mm: Some(u16::MAX),
+            #[cfg(feature = "ultrasonic")]
+            fused: Some(crate::fusion::Fused {
+                distance_mm: u16::MAX,
+                source: crate::fusion::Source::Optical,
+            }),
+        };
+        assert!(measurement_frame(&measurement, FrameFormat::Csv).is_ok());
+        assert!(measurement_frame(&measurement, FrameFormat::Json).is_ok());
+    }
+
+    #[test]
+    fn both_formats_contain_the_same_values() {
+        let csv = measurement_frame(&measurement(), FrameFormat::Csv).unwrap();
+        let json = measurement_frame(&measurement(), FrameFormat::Json).unwrap();
+        let values = json
+            .strip_suffix("}\r\n")
+            .unwrap()
+            .split(',')
+            .map(|field| field.split_once(':').unwrap().1.trim_matches('"'));
+        let csv_fields = csv_fields(&csv);
+        for (csv, json) in csv_fields.iter().zip(values) {
+            match (*csv, json) {
+                ("1", "true") | ("0", "false") | ("", "null") => {}
+                (csv, json) => assert_eq!(csv, json),
+            }
+        }
+        assert_eq!(csv_fields.len(), json.split(',').count());
+    }
+}
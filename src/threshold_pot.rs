@@ -0,0 +1,50 @@
+//! A potentiometer on `PB1` (ADC1 IN9) which sets the alarm threshold, so that it can be tuned in
+//! the field without a host.
+//!
+//! The wiper voltage is mapped linearly to the range of [`crate::config::threshold_pot`].
+
+#[cfg(not(any(feature = "alarm-output", feature = "lora")))]
+compile_error!("the feature `threshold-pot` needs `alarm-output` or `lora` to have any effect");
+
+use crate::config::threshold_pot as config;
+use stm32f4xx_hal::adc::config::SampleTime;
+use stm32f4xx_hal::adc::Adc;
+use stm32f4xx_hal::gpio::{Analog, PB1};
+use stm32f4xx_hal::pac::ADC1;
+
+/// Maximum value of the 12 bit ADC.
+const ADC_MAX: u32 = 4095;
+/// A new threshold is only reported once it differs from the current one by at least 1 % of the
+/// range, this suppresses the noise of the ADC.
+const DEADBAND_MM: u16 = (config::MAX_MM - config::MIN_MM) / 100;
+
+pub struct ThresholdPot {
+    adc: Adc<ADC1>,
+    pin: PB1<Analog>,
+    threshold_mm: Option<u16>,
+}
+
+impl ThresholdPot {
+    pub fn new(adc: Adc<ADC1>, pin: PB1<Analog>) -> Self {
+        Self {
+            adc,
+            pin,
+            threshold_mm: None,
+        }
+    }
+
+    /// Read the potentiometer. Returns the new threshold if it has changed.
+    pub fn poll(&mut self) -> Option<u16> {
+        let sample = self.adc.convert(&self.pin, SampleTime::Cycles_480) as u32;
+        let range = (config::MAX_MM - config::MIN_MM) as u32;
+        let threshold_mm = config::MIN_MM + (sample.min(ADC_MAX) * range / ADC_MAX) as u16;
+        match self.threshold_mm {
+            Some(current) if current.abs_diff(threshold_mm) <= DEADBAND_MM => None,
+            _ => {
+                defmt::info!("alarm threshold set to {} mm", threshold_mm);
+                self.threshold_mm = Some(threshold_mm);
+                Some(threshold_mm)
+            }
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Synchronisation with the clock of the host, so that the timestamps (time since boot) of the
+//! telemetry frames can be mapped to the time of the host with millisecond accuracy.
+//!
+//! The exchange consists of two commands:
+//! 1. the host sends `sync`, the firmware responds with its current time: `OK sync=<fw_ms>`
+//! 2. the host immediately sends `sync <fw_ms> <host_ms>` with the received time & its own time,
+//!    the firmware measures the round trip & responds with `OK offset=<ms> rtt=<ms>`
+//!
+//! The time of the host is then `timestamp_ms + offset`, accurate to half of the round trip (the
+//! delays in both directions are assumed to be the same). The exchange can be repeated (e.g. to
+//! pick the one with the shortest round trip), the firmware doesn't keep any state.
+
+/// Exchanges with a longer round trip are rejected as they're too inaccurate.
+pub const MAX_ROUND_TRIP_MS: u32 = 1_000;
+
+/// The second command of the exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct SyncReply {
+    /// The time of the firmware from the response to the first command.
+    pub sent_ms: u32,
+    /// The time of the host when sending this command, in any unit of milliseconds (e.g. since the
+    /// Unix epoch).
+    pub host_ms: u64,
+}
+
+/// The result of an exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct TimeSync {
+    /// To be added to the time of the firmware to get the time of the host.
+    pub offset_ms: i64,
+    pub round_trip_ms: u32,
+}
+
+impl TimeSync {
+    /// Evaluate the exchange, `received_ms` is the time at which the reply has been received.
+    /// Returns `None` if the reply doesn't belong to a response of this boot or if the round trip
+    /// took too long.
+    pub fn new(reply: &SyncReply, received_ms: u32) -> Option<Self> {
+        let round_trip_ms = received_ms.checked_sub(reply.sent_ms)?;
+        if round_trip_ms > MAX_ROUND_TRIP_MS {
+            return None;
+        }
+        // the host's time has been taken half of the round trip before the reply was received
+        let host_ms = i64::try_from(reply.host_ms).ok()? + (round_trip_ms / 2) as i64;
+        Some(Self {
+            offset_ms: host_ms - received_ms as i64,
+            round_trip_ms,
+        })
+    }
+}
@@ -0,0 +1,668 @@
+//! Setup & operation of the VL53L1X, generic over the I2C bus (any `embedded-hal` bus) so that it
+//! can be tested on the host. The tasks use it as a [`RangeSensor`].
+//!
+//! The sensor may not answer right after a power-on (or a glitch on the bus), thus the setup is
+//! attempted up to [`SETUP_ATTEMPTS`] times before giving up.
+
+use crate::calibration_store::CalibrationData;
+use crate::range_sensor::{Optics, RangeSensor, Reading, Thresholds};
+use crate::register_dump::{self, Snapshot};
+use crate::settings::TofSettings;
+use core::fmt::Debug;
+use vl53l1x_uld::comm::{Read, Write};
+use vl53l1x_uld::threshold::{Threshold, Window};
+use vl53l1x_uld::{IOVoltage, MeasureResult, Polarity, Register, VL53L1X};
+
+/// Number of attempts to set up the sensor.
+pub const SETUP_ATTEMPTS: u8 = 3;
+/// Number of times the boot state is read before the setup attempt fails.
+pub const BOOT_POLLS: u16 = 100;
+/// The model ID of the VL53L1X.
+pub const MODEL_ID: u16 = 0xEACC;
+/// The configuration of the interrupt in which every new measurement is signalled.
+const INTERRUPT_NEW_SAMPLE: u8 = 0x20;
+/// The polarity of the interrupt, see [`crate::config::tof_interrupt`].
+const INTERRUPT_POLARITY: Polarity = if crate::config::tof_interrupt::ACTIVE_HIGH {
+    Polarity::ActiveHigh
+} else {
+    Polarity::ActiveLow
+};
+
+/// The I2C bus of the sensor.
+pub trait Bus<E: Debug>: Write<Error = E> + Read<Error = E> {}
+
+impl<T, E: Debug> Bus<E> for T where T: Write<Error = E> + Read<Error = E> {}
+
+pub type Error<E> = vl53l1x_uld::Error<E>;
+
+/// Reasons why the sensor could not be set up, it's [`SetupError::NotBooted`] if it hasn't
+/// finished booting within [`BOOT_POLLS`].
+///
+/// [`SetupError::NotBooted`]: crate::range_sensor::SetupError::NotBooted
+pub type SetupError<E> = crate::range_sensor::SetupError<Error<E>>;
+
+/// Set up the sensor, apply the calibration & settings (if any) and start ranging.
+pub fn setup<E: Debug>(
+    dev: &mut VL53L1X<impl Bus<E>>,
+    calibration: Option<CalibrationData>,
+    settings: Option<TofSettings>,
+) -> Result<(), SetupError<E>> {
+    let mut attempt = 1;
+    loop {
+        match try_setup(dev, calibration, settings) {
+            // retrying doesn't help if there's another device
+            Err(SetupError::UnknownModel(id)) => {
+                defmt::error!("unknown model {=u16:#06x} instead of a VL53L1X", id);
+                return Err(SetupError::UnknownModel(id));
+            }
+            Err(e) if attempt < SETUP_ATTEMPTS => {
+                defmt::warn!(
+                    "failed to set up the TOF sensor ({}/{}): {}",
+                    attempt,
+                    SETUP_ATTEMPTS,
+                    defmt::Debug2Format(&e)
+                );
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn try_setup<E: Debug>(
+    dev: &mut VL53L1X<impl Bus<E>>,
+    calibration: Option<CalibrationData>,
+    settings: Option<TofSettings>,
+) -> Result<(), SetupError<E>> {
+    let id = dev.get_sensor_id()?;
+    if id != MODEL_ID {
+        return Err(SetupError::UnknownModel(id));
+    }
+    wait_for_boot(dev)?;
+    dev.init(IOVoltage::Volt2_8)?;
+    dev.set_interrupt_polarity(INTERRUPT_POLARITY)?;
+    if let Some(calibration) = calibration {
+        defmt::info!("applying the stored calibration {}", calibration);
+        write_calibration(dev, &calibration)?;
+    }
+    if let Some(settings) = settings {
+        configure(dev, &settings)?;
+    }
+    dev.start_ranging()?;
+    Ok(())
+}
+
+fn wait_for_boot<E: Debug>(dev: &mut VL53L1X<impl Bus<E>>) -> Result<(), SetupError<E>> {
+    for _ in 0..BOOT_POLLS {
+        if dev.is_booted()? {
+            return Ok(());
+        }
+    }
+    Err(SetupError::NotBooted)
+}
+
+/// Apply the measurement settings, the sensor must not be ranging.
+pub fn configure<E: Debug>(
+    dev: &mut VL53L1X<impl Bus<E>>,
+    settings: &TofSettings,
+) -> Result<(), Error<E>> {
+    dev.set_distance_mode(settings.distance_mode)?;
+    dev.set_timing_budget_ms(settings.timing_budget_ms)?;
+    dev.set_inter_measurement_period_ms(settings.inter_measurement_ms)
+}
+
+/// Apply the thresholds of the measurements.
+pub fn set_thresholds<E: Debug>(
+    dev: &mut VL53L1X<impl Bus<E>>,
+    thresholds: &Thresholds,
+) -> Result<(), Error<E>> {
+    dev.set_signal_threshold(thresholds.signal_kcps)?;
+    dev.set_sigma_threshold(thresholds.sigma_mm)
+}
+
+/// Only signal the measurements closer than `below_mm` with the interrupt, all of them for `None`.
+pub fn set_interrupt_threshold<E: Debug>(
+    dev: &mut VL53L1X<impl Bus<E>>,
+    below_mm: Option<u16>,
+) -> Result<(), Error<E>> {
+    match below_mm {
+        Some(below_mm) => {
+            dev.set_distance_threshold(Threshold::new(below_mm, below_mm, Window::Below))
+        }
+        None => dev.write_bytes(
+            Register::SYSTEM__INTERRUPT_CONFIG_GPIO,
+            &[INTERRUPT_NEW_SAMPLE],
+        ),
+    }
+}
+
+/// The current measurement settings.
+pub fn read_settings<E: Debug>(dev: &mut VL53L1X<impl Bus<E>>) -> Result<TofSettings, Error<E>> {
+    Ok(TofSettings {
+        distance_mode: dev.get_distance_mode()?,
+        timing_budget_ms: dev.get_timing_budget_ms()?,
+        inter_measurement_ms: dev.get_inter_measurement_period_ms()?,
+    })
+}
+
+/// The current calibration.
+pub fn read_calibration<E: Debug>(
+    dev: &mut VL53L1X<impl Bus<E>>,
+) -> Result<CalibrationData, Error<E>> {
+    Ok(CalibrationData {
+        offset_mm: dev.get_offset()?,
+        cross_talk_cps: dev.get_cross_talk()?,
+    })
+}
+
+/// Set the crosstalk correction in counts per second. Unlike `VL53L1X::set_cross_talk` (which
+/// calculates with 16 bit & thus overflows above 127 cps) this accepts the whole range.
+pub fn set_cross_talk<E: Debug>(
+    dev: &mut VL53L1X<impl Bus<E>>,
+    cross_talk_cps: u16,
+) -> Result<(), Error<E>> {
+    dev.write_bytes(
+        Register::ALGO__CROSSTALK_COMPENSATION_X_PLANE_GRADIENT_KCPS,
+        &[0, 0],
+    )?;
+    dev.write_bytes(
+        Register::ALGO__CROSSTALK_COMPENSATION_Y_PLANE_GRADIENT_KCPS,
+        &[0, 0],
+    )?;
+    // in kcps with 9 fractional bits
+    let correction = ((cross_talk_cps as u32) << 9) / 1_000;
+    dev.write_bytes(
+        Register::ALGO__CROSSTALK_COMPENSATION_PLANE_OFFSET_KCPS,
+        &(correction as u16).to_be_bytes(),
+    )
+}
+
+/// Apply a calibration.
+pub fn write_calibration<E: Debug>(
+    dev: &mut VL53L1X<impl Bus<E>>,
+    calibration: &CalibrationData,
+) -> Result<(), Error<E>> {
+    dev.set_offset(calibration.offset_mm)?;
+    set_cross_talk(dev, calibration.cross_talk_cps)
+}
+
+/// The current optical configuration.
+pub fn read_optics<E: Debug>(dev: &mut VL53L1X<impl Bus<E>>) -> Result<Optics, Error<E>> {
+    // a bit per SPAD of the 16x16 array
+    let mut spad_map = [0; 32];
+    dev.read_bytes(Register::GLOBAL_CONFIG__SPAD_ENABLES_RTN_0, &mut spad_map)?;
+    let roi = dev.get_roi()?;
+    Ok(Optics {
+        enabled_spads: spad_map.iter().map(|byte| byte.count_ones() as u16).sum(),
+        effective_spads: dev.get_spad_count()?,
+        roi_width: roi.width as u8,
+        roi_height: roi.height as u8,
+        center_spad: dev.get_roi_center()?.spad,
+    })
+}
+
+/// Read the measurement once the sensor has signalled it with its interrupt. The interrupt is
+/// cleared even if reading fails so that the sensor continues with the next measurement.
+pub fn read_result<E: Debug>(dev: &mut VL53L1X<impl Bus<E>>) -> Result<MeasureResult, Error<E>> {
+    let result = dev.get_result();
+    dev.clear_interrupt().ok();
+    result
+}
+
+/// Calibrate the sensor for the current temperature & continue ranging.
+pub fn recalibrate_temperature<E: Debug>(dev: &mut VL53L1X<impl Bus<E>>) -> Result<(), Error<E>> {
+    // the calibration stops the ranging
+    dev.calibrate_temperature()?;
+    dev.start_ranging()
+}
+
+/// Start the offset calibration: the offset is cleared & the sensor is ranging afterwards.
+/// Returns the previous offset.
+pub fn start_offset_calibration<E: Debug>(
+    dev: &mut VL53L1X<impl Bus<E>>,
+    ranging: bool,
+) -> Result<i16, Error<E>> {
+    let previous_offset = dev.get_offset()?;
+    dev.set_offset(0)?;
+    if !ranging {
+        dev.start_ranging()?;
+    }
+    Ok(previous_offset)
+}
+
+/// Finish the offset calibration with the `offset`, the sensor is stopped again unless it
+/// `was_ranging` before.
+pub fn finish_offset_calibration<E: Debug>(
+    dev: &mut VL53L1X<impl Bus<E>>,
+    offset: i16,
+    was_ranging: bool,
+) -> Result<(), Error<E>> {
+    dev.set_offset(offset)?;
+    if !was_ranging {
+        dev.stop_ranging()?;
+    }
+    Ok(())
+}
+
+/// Start the crosstalk calibration: the crosstalk correction is cleared & the sensor is ranging
+/// afterwards.
+pub fn start_cross_talk_calibration<E: Debug>(
+    dev: &mut VL53L1X<impl Bus<E>>,
+    ranging: bool,
+) -> Result<(), Error<E>> {
+    set_cross_talk(dev, 0)?;
+    if !ranging {
+        dev.start_ranging()?;
+    }
+    Ok(())
+}
+
+/// Finish the crosstalk calibration with the `cross_talk_cps`, the sensor is stopped again unless
+/// it `was_ranging` before.
+pub fn finish_cross_talk_calibration<E: Debug>(
+    dev: &mut VL53L1X<impl Bus<E>>,
+    cross_talk_cps: u16,
+    was_ranging: bool,
+) -> Result<(), Error<E>> {
+    set_cross_talk(dev, cross_talk_cps)?;
+    if !was_ranging {
+        dev.stop_ranging()?;
+    }
+    Ok(())
+}
+
+impl<B, E> RangeSensor for VL53L1X<B>
+where
+    B: Write<Error = E> + Read<Error = E>,
+    E: Debug,
+{
+    type Error = Error<E>;
+
+    fn setup(
+        &mut self,
+        calibration: Option<CalibrationData>,
+        settings: Option<TofSettings>,
+    ) -> Result<(), SetupError<E>> {
+        setup(self, calibration, settings)
+    }
+
+    fn start(&mut self) -> Result<(), Error<E>> {
+        self.start_ranging()
+    }
+
+    fn stop(&mut self) -> Result<(), Error<E>> {
+        self.stop_ranging()
+    }
+
+    fn is_data_ready(&mut self) -> Result<bool, Error<E>> {
+        VL53L1X::is_data_ready(self)
+    }
+
+    fn read(&mut self) -> Result<Reading, Error<E>> {
+        let result = read_result(self)?;
+        Ok(Reading {
+            distance_mm: result.distance_mm,
+            status: result.status,
+            ambient_kcps: result.ambient,
+            signal_per_spad_kcps: result.sig_per_spad,
+        })
+    }
+
+    fn configure(&mut self, settings: &TofSettings) -> Result<(), Error<E>> {
+        configure(self, settings)
+    }
+
+    fn set_thresholds(&mut self, thresholds: &Thresholds) -> Result<(), Error<E>> {
+        set_thresholds(self, thresholds)
+    }
+
+    fn set_interrupt_threshold(&mut self, below_mm: Option<u16>) -> Result<(), Error<E>> {
+        set_interrupt_threshold(self, below_mm)
+    }
+
+    fn settings(&mut self) -> Result<TofSettings, Error<E>> {
+        read_settings(self)
+    }
+
+    fn calibration(&mut self) -> Result<CalibrationData, Error<E>> {
+        read_calibration(self)
+    }
+
+    fn set_calibration(&mut self, calibration: &CalibrationData) -> Result<(), Error<E>> {
+        write_calibration(self, calibration)
+    }
+
+    fn optics(&mut self) -> Result<Optics, Error<E>> {
+        read_optics(self)
+    }
+
+    fn registers(&mut self) -> Result<Snapshot, Error<E>> {
+        register_dump::read(&register_dump::REGISTERS, |index, bytes| {
+            self.read_bytes(index.to_be_bytes(), bytes)
+        })
+    }
+
+    fn recalibrate_temperature(&mut self) -> Result<(), Error<E>> {
+        recalibrate_temperature(self)
+    }
+
+    fn start_offset_calibration(&mut self, ranging: bool) -> Result<i16, Error<E>> {
+        start_offset_calibration(self, ranging)
+    }
+
+    fn finish_offset_calibration(
+        &mut self,
+        offset: i16,
+        was_ranging: bool,
+    ) -> Result<(), Error<E>> {
+        finish_offset_calibration(self, offset, was_ranging)
+    }
+
+    fn start_cross_talk_calibration(&mut self, ranging: bool) -> Result<(), Error<E>> {
+        start_cross_talk_calibration(self, ranging)
+    }
+
+    fn finish_cross_talk_calibration(
+        &mut self,
+        cross_talk_cps: u16,
+        was_ranging: bool,
+    ) -> Result<(), Error<E>> {
+        finish_cross_talk_calibration(self, cross_talk_cps, was_ranging)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::i2c::{Mock, Transaction};
+    use embedded_hal_mock::MockError;
+    use std::io::ErrorKind;
+    use vl53l1x_uld::{RangeStatus, Register, DEFAULT_ADDRESS};
+
+    fn write(register: Register, bytes: &[u8]) -> Transaction {
+        let register: [u8; 2] = register.into();
+        let mut data = register.to_vec();
+        data.extend_from_slice(bytes);
+        Transaction::write(DEFAULT_ADDRESS, data)
+    }
+
+    fn read(register: Register, response: &[u8]) -> Transaction {
+        let register: [u8; 2] = register.into();
+        Transaction::write_read(DEFAULT_ADDRESS, register.to_vec(), response.to_vec())
+    }
+
+    fn error(transaction: Transaction) -> Transaction {
+        transaction.with_error(MockError::Io(ErrorKind::Other))
+    }
+
+    fn identify() -> Vec<Transaction> {
+        vec![
+            read(Register::IDENTIFICATION__MODEL_ID, &MODEL_ID.to_be_bytes()),
+            read(Register::FIRMWARE__SYSTEM_STATUS, &[1]),
+        ]
+    }
+
+    /// The transactions of [`VL53L1X::init`] & setting the interrupt polarity.
+    fn init() -> Vec<Transaction> {
+        let mut transactions = vec![
+            write(Register::PAD_I2C_HV__CONFIG, &[0]),
+            write(Register::GPIO_HV_PAD__CTRL, &[1]),
+            write(Register::PAD_I2C_HV__EXTSUP_CONFIG, &[1]),
+        ];
+        for (byte, address) in VL53L1X::<Mock>::DEFAULT_CONFIG.iter().zip(0x30u16..0x88) {
+            let mut data = address.to_be_bytes().to_vec();
+            data.push(*byte);
+            transactions.push(Transaction::write(DEFAULT_ADDRESS, data));
+        }
+        transactions.extend([
+            start_ranging(),
+            // the first measurement is ready at once
+            read(Register::GPIO_HV_MUX__CTRL, &[0x00]),
+            read(Register::GPIO__TIO_HV_STATUS, &[0x01]),
+            write(Register::SYSTEM__INTERRUPT_CLEAR, &[0x01]),
+            stop_ranging(),
+            write(Register::VHV_CONFIG__TIMEOUT_MACROP_LOOP_BOUND, &[0x09]),
+            write(Register::VHV_CONFIG__INIT, &[0]),
+            read(Register::GPIO_HV_MUX__CTRL, &[0x01]),
+            write(Register::GPIO_HV_MUX__CTRL, &[0x11]),
+        ]);
+        transactions
+    }
+
+    fn start_ranging() -> Transaction {
+        write(Register::SYSTEM__MODE_START, &[0x40])
+    }
+
+    fn stop_ranging() -> Transaction {
+        write(Register::SYSTEM__MODE_START, &[0x00])
+    }
+
+    fn run(transactions: &[Transaction], test: impl FnOnce(&mut VL53L1X<Mock>)) {
+        let mut i2c = Mock::new(transactions);
+        let mut dev = VL53L1X::new(i2c.clone(), DEFAULT_ADDRESS);
+        test(&mut dev);
+        i2c.done();
+    }
+
+    #[test]
+    fn setup_initialises_the_sensor_and_starts_ranging() {
+        let transactions = [identify(), init(), vec![start_ranging()]].concat();
+        run(&transactions, |dev| {
+            assert!(setup(dev, None, None).is_ok());
+        });
+    }
+
+    #[test]
+    fn setup_applies_the_calibration() {
+        let calibration = CalibrationData {
+            offset_mm: -12,
+            cross_talk_cps: 100,
+        };
+        let apply = vec![
+            write(
+                Register::ALGO__PART_TO_PART_RANGE_OFFSET_MM,
+                &(-48i16).to_be_bytes(),
+            ),
+            write(Register::MM_CONFIG__INNER_OFFSET_MM, &[0, 0]),
+            write(Register::MM_CONFIG__OUTER_OFFSET_MM, &[0, 0]),
+            write(
+                Register::ALGO__CROSSTALK_COMPENSATION_X_PLANE_GRADIENT_KCPS,
+                &[0, 0],
+            ),
+            write(
+                Register::ALGO__CROSSTALK_COMPENSATION_Y_PLANE_GRADIENT_KCPS,
+                &[0, 0],
+            ),
+            write(
+                Register::ALGO__CROSSTALK_COMPENSATION_PLANE_OFFSET_KCPS,
+                &51u16.to_be_bytes(),
+            ),
+            start_ranging(),
+        ];
+        let transactions = [identify(), init(), apply].concat();
+        run(&transactions, |dev| {
+            assert!(setup(dev, Some(calibration), None).is_ok());
+        });
+    }
+
+    #[test]
+    fn large_cross_talk_doesnt_overflow() {
+        let transactions = [
+            write(
+                Register::ALGO__CROSSTALK_COMPENSATION_X_PLANE_GRADIENT_KCPS,
+                &[0, 0],
+            ),
+            write(
+                Register::ALGO__CROSSTALK_COMPENSATION_Y_PLANE_GRADIENT_KCPS,
+                &[0, 0],
+            ),
+            write(
+                Register::ALGO__CROSSTALK_COMPENSATION_PLANE_OFFSET_KCPS,
+                &512u16.to_be_bytes(),
+            ),
+        ];
+        run(&transactions, |dev| {
+            assert!(set_cross_talk(dev, 1_000).is_ok());
+        });
+    }
+
+    #[test]
+    fn setup_waits_for_the_boot() {
+        let transactions = [
+            vec![
+                read(Register::IDENTIFICATION__MODEL_ID, &MODEL_ID.to_be_bytes()),
+                read(Register::FIRMWARE__SYSTEM_STATUS, &[0]),
+                read(Register::FIRMWARE__SYSTEM_STATUS, &[0]),
+                read(Register::FIRMWARE__SYSTEM_STATUS, &[1]),
+            ],
+            init(),
+            vec![start_ranging()],
+        ]
+        .concat();
+        run(&transactions, |dev| {
+            assert!(setup(dev, None, None).is_ok());
+        });
+    }
+
+    #[test]
+    fn setup_fails_if_the_sensor_doesnt_boot() {
+        let not_booted = read(Register::FIRMWARE__SYSTEM_STATUS, &[0]);
+        let mut transactions = Vec::new();
+        for _ in 0..SETUP_ATTEMPTS {
+            transactions.push(read(
+                Register::IDENTIFICATION__MODEL_ID,
+                &MODEL_ID.to_be_bytes(),
+            ));
+            transactions.extend((0..BOOT_POLLS).map(|_| not_booted.clone()));
+        }
+        run(&transactions, |dev| {
+            assert!(matches!(setup(dev, None, None), Err(SetupError::NotBooted)));
+        });
+    }
+
+    #[test]
+    fn setup_rejects_an_unknown_model_without_retrying() {
+        let transactions = [read(Register::IDENTIFICATION__MODEL_ID, &[0x12, 0x34])];
+        run(&transactions, |dev| {
+            assert!(matches!(
+                setup(dev, None, None),
+                Err(SetupError::UnknownModel(0x1234))
+            ));
+        });
+    }
+
+    #[test]
+    fn setup_retries_after_a_communication_error() {
+        let transactions = [
+            vec![error(read(Register::IDENTIFICATION__MODEL_ID, &[0, 0]))],
+            // the init fails half-way through
+            identify(),
+            init()[..10].to_vec(),
+            vec![error(init()[10].clone())],
+            identify(),
+            init(),
+            vec![start_ranging()],
+        ]
+        .concat();
+        run(&transactions, |dev| {
+            assert!(setup(dev, None, None).is_ok());
+        });
+    }
+
+    #[test]
+    fn setup_gives_up_after_all_attempts() {
+        let transactions: Vec<_> = (0..SETUP_ATTEMPTS)
+            .map(|_| error(read(Register::IDENTIFICATION__MODEL_ID, &[0, 0])))
+            .collect();
+        run(&transactions, |dev| {
+            assert!(matches!(
+                setup(dev, None, None),
+                Err(SetupError::Sensor(Error::CommunicationError(_)))
+            ));
+        });
+    }
+
+    #[test]
+    fn reconfigure_stops_and_restarts_the_ranging() {
+        let transactions = [
+            stop_ranging(),
+            write(Register::SYSTEM__INTERRUPT_CLEAR, &[0x01]),
+            start_ranging(),
+        ];
+        run(&transactions, |dev| {
+            assert!(dev.reconfigure(true, |dev| dev.clear_interrupt()).is_ok());
+        });
+    }
+
+    #[test]
+    fn reconfigure_keeps_a_stopped_sensor_stopped() {
+        let transactions = [write(Register::SYSTEM__INTERRUPT_CLEAR, &[0x01])];
+        run(&transactions, |dev| {
+            assert!(dev.reconfigure(false, |dev| dev.clear_interrupt()).is_ok());
+        });
+    }
+
+    #[test]
+    fn reconfigure_doesnt_restart_after_an_error() {
+        let transactions = [
+            stop_ranging(),
+            error(write(Register::SYSTEM__INTERRUPT_CLEAR, &[0x01])),
+        ];
+        run(&transactions, |dev| {
+            assert!(dev.reconfigure(true, |dev| dev.clear_interrupt()).is_err());
+        });
+    }
+
+    #[test]
+    fn read_result_clears_the_interrupt() {
+        let mut result = [0; 17];
+        result[0] = 9;
+        result[13..15].copy_from_slice(&1234u16.to_be_bytes());
+        let transactions = [
+            read(Register::RESULT__RANGE_STATUS, &result),
+            write(Register::SYSTEM__INTERRUPT_CLEAR, &[0x01]),
+        ];
+        run(&transactions, |dev| {
+            let result = read_result(dev).unwrap();
+            assert_eq!(result.distance_mm, 1234);
+            assert_eq!(result.status, RangeStatus::Valid);
+        });
+    }
+
+    #[test]
+    fn read_result_clears_the_interrupt_after_an_error() {
+        let transactions = [
+            error(read(Register::RESULT__RANGE_STATUS, &[0; 17])),
+            write(Register::SYSTEM__INTERRUPT_CLEAR, &[0x01]),
+        ];
+        run(&transactions, |dev| {
+            assert!(read_result(dev).is_err());
+        });
+    }
+
+    #[test]
+    fn offset_calibration_restores_a_stopped_sensor() {
+        let transactions = [
+            // the offset is stored in quarter millimetres
+            read(
+                Register::ALGO__PART_TO_PART_RANGE_OFFSET_MM,
+                &160i16.to_be_bytes(),
+            ),
+            write(Register::ALGO__PART_TO_PART_RANGE_OFFSET_MM, &[0, 0]),
+            write(Register::MM_CONFIG__INNER_OFFSET_MM, &[0, 0]),
+            write(Register::MM_CONFIG__OUTER_OFFSET_MM, &[0, 0]),
+            start_ranging(),
+            write(
+                Register::ALGO__PART_TO_PART_RANGE_OFFSET_MM,
+                &20i16.to_be_bytes(),
+            ),
+            write(Register::MM_CONFIG__INNER_OFFSET_MM, &[0, 0]),
+            write(Register::MM_CONFIG__OUTER_OFFSET_MM, &[0, 0]),
+            stop_ranging(),
+        ];
+        run(&transactions, |dev| {
+            assert_eq!(start_offset_calibration(dev, false).unwrap(), 40);
+            assert!(finish_offset_calibration(dev, 5, false).is_ok());
+        });
+    }
+}
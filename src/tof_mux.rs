@@ -0,0 +1,179 @@
+//! A TCA9548A I2C multiplexer on the shared I2C bus (`tof-mux` feature) with up to 8 VL53L1X
+//! behind its channels, e.g. for a ring of sensors: all of them keep the default address, which
+//! would otherwise have to be changed for each sensor at every boot (with a `XSHUT` line each).
+//!
+//! The [`TofArray`] connects the channel of a sensor for each access & disconnects it again
+//! afterwards, thus the sensors never answer to the transactions of the main TOF sensor (or of the
+//! other devices) on the bus. The sensors use the same measurement settings as the main sensor
+//! but not its calibration, they range from boot on & are polled every
+//! [`config::POLL_INTERVAL_MS`] (their interrupt lines aren't connected). Their measurements are
+//! sent as separate frames (see [`crate::telemetry::array_measurement_frame`]) while the main
+//! sensor is ranging, they aren't processed by the outputs or loggers.
+
+use crate::config::tof_mux as config;
+use crate::range_sensor::{RangeSensor, Reading};
+use crate::settings::TofSettings;
+use crate::tof;
+use crate::I2cBus;
+use stm32f4xx_hal::hal::blocking::i2c::Write;
+use vl53l1x_uld::VL53L1X;
+
+/// Number of channels of the multiplexer.
+pub const CHANNELS: u8 = 8;
+
+/// The driver of the multiplexer, which only has a control register: bit `n` connects channel `n`
+/// to the bus.
+pub struct Tca9548a<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C: Write> Tca9548a<I2C> {
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    /// Disconnect all channels, e.g. after a reset during an access.
+    pub fn disable(&mut self) -> Result<(), I2C::Error> {
+        self.i2c.write(self.address, &[0])
+    }
+
+    /// Run `access` with the `channel` connected to the bus, it's disconnected afterwards (also if
+    /// `access` has failed). Fails if the multiplexer can't be switched.
+    pub fn with_channel<T>(
+        &mut self,
+        channel: u8,
+        access: impl FnOnce() -> T,
+    ) -> Result<T, I2C::Error> {
+        self.i2c.write(self.address, &[1 << (channel % CHANNELS)])?;
+        let result = access();
+        self.disable()?;
+        Ok(result)
+    }
+}
+
+/// A sensor behind a channel of the multiplexer.
+struct ArraySensor {
+    channel: u8,
+    sensor: VL53L1X<I2cBus>,
+    /// Number of measurements, the sequence number of the latest one.
+    count: u32,
+}
+
+/// A measurement of a sensor of the array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArrayReading {
+    pub channel: u8,
+    /// Sequence number of the measurements of the sensor, starts at 1 after each boot.
+    pub seq: u32,
+    pub reading: Reading,
+}
+
+/// The sensors of the channels of [`config::CHANNELS`] behind the multiplexer.
+pub struct TofArray {
+    mux: Tca9548a<I2cBus>,
+    sensors: heapless::Vec<ArraySensor, { CHANNELS as usize }>,
+}
+
+impl TofArray {
+    /// Set up the sensors & start ranging, each one gets a handle of the shared bus from `bus`.
+    /// The sensors which can't be set up are logged & left out.
+    pub fn new(
+        mut mux: Tca9548a<I2cBus>,
+        mut bus: impl FnMut() -> I2cBus,
+        settings: Option<TofSettings>,
+    ) -> Self {
+        let mut sensors = heapless::Vec::new();
+        for channel in (0..CHANNELS).filter(|channel| config::CHANNELS & 1 << channel != 0) {
+            let mut sensor = VL53L1X::new(bus(), vl53l1x_uld::DEFAULT_ADDRESS);
+            match mux.with_channel(channel, || tof::setup(&mut sensor, None, settings)) {
+                Ok(Ok(())) => {
+                    defmt::info!("TOF sensor on channel {} of the I2C mux ranging", channel);
+                    sensors
+                        .push(ArraySensor {
+                            channel,
+                            sensor,
+                            count: 0,
+                        })
+                        .ok();
+                }
+                Ok(Err(e)) => defmt::warn!(
+                    "failed to set up the TOF sensor on channel {} of the I2C mux: {}",
+                    channel,
+                    defmt::Debug2Format(&e)
+                ),
+                Err(e) => {
+                    defmt::error!("failed to switch the I2C mux: {}", defmt::Debug2Format(&e));
+                    break;
+                }
+            }
+        }
+        Self { mux, sensors }
+    }
+
+    /// Channels of the sensors which have been set up, one bit per channel.
+    pub fn channels(&self) -> u8 {
+        self.sensors
+            .iter()
+            .fold(0, |channels, sensor| channels | 1 << sensor.channel)
+    }
+
+    /// Read the measurements which are available, failures are logged.
+    pub fn poll(&mut self) -> heapless::Vec<ArrayReading, { CHANNELS as usize }> {
+        let mut readings = heapless::Vec::new();
+        for sensor in self.sensors.iter_mut() {
+            let result =
+                self.mux
+                    .with_channel(sensor.channel, || match sensor.sensor.is_data_ready()? {
+                        true => sensor.sensor.read().map(Some),
+                        false => Ok(None),
+                    });
+            match result {
+                Ok(Ok(Some(reading))) => {
+                    sensor.count = sensor.count.wrapping_add(1);
+                    readings
+                        .push(ArrayReading {
+                            channel: sensor.channel,
+                            seq: sensor.count,
+                            reading,
+                        })
+                        .ok();
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => defmt::warn!(
+                    "failed to read the TOF sensor on channel {}: {}",
+                    sensor.channel,
+                    defmt::Debug2Format(&e)
+                ),
+                Err(e) => {
+                    defmt::warn!("failed to switch the I2C mux: {}", defmt::Debug2Format(&e));
+                    break;
+                }
+            }
+        }
+        readings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::i2c::{Mock, Transaction};
+    use embedded_hal_mock::MockError;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn connects_the_channel_for_the_access() {
+        let transactions = [
+            Transaction::write(0x70, vec![0b0000_1000]),
+            Transaction::write(0x70, vec![0]),
+            Transaction::write(0x70, vec![0b1000_0000]).with_error(MockError::Io(ErrorKind::Other)),
+        ];
+        let mut mux = Tca9548a::new(Mock::new(&transactions), 0x70);
+        assert_eq!(mux.with_channel(3, || 42), Ok(42));
+        let mut accessed = false;
+        assert!(mux.with_channel(7, || accessed = true).is_err());
+        assert!(!accessed);
+        mux.i2c.done();
+    }
+}
@@ -0,0 +1,157 @@
+//! Tracking of a moving target, e.g. the nearest obstacle of a robot: each measurement updates an
+//! estimate of its position & velocity, which is published as a track record (see
+//! [`crate::telemetry::track_frame`]) if [`config::ENABLED`], so that the host gets a filtered
+//! state instead of the raw distances.
+//!
+//! The estimate is an alpha-beta filter: the position is predicted with the velocity & corrected
+//! by [`config::ALPHA_PCT`] of the difference to the measured distance, the velocity by
+//! [`config::BETA_PCT`] of it. The confidence rises with each valid measurement & falls with each
+//! invalid one, during which the track coasts with its velocity. The track is lost once the
+//! confidence reaches 0 or there hasn't been a measurement for [`MAX_GAP_MS`], the next valid
+//! measurement starts a new one.
+
+use crate::config::track as config;
+use crate::telemetry::Measurement;
+use vl53l1x_uld::RangeStatus;
+
+/// The longest interval between two measurements of the same track.
+pub const MAX_GAP_MS: u32 = 1_000;
+/// Confidence gained with each valid & lost with each invalid measurement.
+const CONFIDENCE_GAIN_PCT: u8 = 20;
+const CONFIDENCE_LOSS_PCT: u8 = 25;
+
+/// The estimated state of the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Track {
+    pub timestamp_ms: u32,
+    /// Sequence number of the latest measurement.
+    pub seq: u32,
+    pub position_mm: u16,
+    /// Positive while the target moves away.
+    pub velocity_mm_s: i32,
+    pub confidence_pct: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+    timestamp_ms: u32,
+    /// The position in µm, so that the corrections of slow movements aren't lost to rounding.
+    position_um: i32,
+    velocity_um_s: i32,
+    confidence_pct: u8,
+}
+
+pub struct Tracker {
+    state: Option<State>,
+}
+
+impl Tracker {
+    pub const fn new() -> Self {
+        Self { state: None }
+    }
+
+    /// Update the track with the measurement, `None` while there isn't any.
+    pub fn update(&mut self, measurement: &Measurement) -> Option<Track> {
+        let valid = measurement.status == RangeStatus::Valid;
+        let measured_um = i32::from(measurement.distance_mm) * 1_000;
+        let elapsed_ms = self
+            .state
+            .map(|state| measurement.timestamp_ms.wrapping_sub(state.timestamp_ms))
+            .filter(|&elapsed_ms| elapsed_ms > 0 && elapsed_ms <= MAX_GAP_MS);
+        let state = match (self.state, elapsed_ms) {
+            (Some(state), Some(elapsed_ms)) => {
+                let predicted_um = state.position_um
+                    + (i64::from(state.velocity_um_s) * i64::from(elapsed_ms) / 1_000) as i32;
+                let elapsed_ms = elapsed_ms as i32;
+                if valid {
+                    let residual_um = measured_um - predicted_um;
+                    State {
+                        timestamp_ms: measurement.timestamp_ms,
+                        position_um: predicted_um + residual_um / 100 * config::ALPHA_PCT as i32,
+                        velocity_um_s: state.velocity_um_s
+                            + residual_um / 100 * config::BETA_PCT as i32 * 1_000 / elapsed_ms,
+                        confidence_pct: (state.confidence_pct + CONFIDENCE_GAIN_PCT).min(100),
+                    }
+                } else {
+                    State {
+                        timestamp_ms: measurement.timestamp_ms,
+                        position_um: predicted_um.max(0),
+                        velocity_um_s: state.velocity_um_s,
+                        confidence_pct: state.confidence_pct.saturating_sub(CONFIDENCE_LOSS_PCT),
+                    }
+                }
+            }
+            // a new track
+            _ if valid => State {
+                timestamp_ms: measurement.timestamp_ms,
+                position_um: measured_um,
+                velocity_um_s: 0,
+                confidence_pct: CONFIDENCE_GAIN_PCT,
+            },
+            _ => {
+                self.state = None;
+                return None;
+            }
+        };
+        if state.confidence_pct == 0 {
+            self.state = None;
+            return None;
+        }
+        self.state = Some(state);
+        Some(Track {
+            timestamp_ms: state.timestamp_ms,
+            seq: measurement.seq,
+            position_mm: (state.position_um / 1_000).clamp(0, i32::from(u16::MAX)) as u16,
+            velocity_mm_s: state.velocity_um_s / 1_000,
+            confidence_pct: state.confidence_pct,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test_measurement;
+
+    #[test]
+    fn follows_an_approaching_target() {
+        let mut tracker = Tracker::new();
+        // approaching at 500 mm/s
+        let mut track = None;
+        for i in 0..50 {
+            let distance_mm = 2_000 - i as u16 * 25;
+            track = tracker.update(&test_measurement(i * 50, distance_mm));
+        }
+        let track = track.unwrap();
+        assert!(track.position_mm.abs_diff(775) <= 10, "{:?}", track);
+        assert!((track.velocity_mm_s + 500).abs() <= 25, "{:?}", track);
+        assert_eq!(track.confidence_pct, 100);
+
+        // coasts through the invalid measurements until the confidence is gone
+        let invalid = |timestamp_ms| Measurement {
+            status: RangeStatus::SignalFailure,
+            ..test_measurement(timestamp_ms, 0)
+        };
+        let coasting = tracker.update(&invalid(2_500)).unwrap();
+        assert!(coasting.position_mm < track.position_mm);
+        assert_eq!(coasting.confidence_pct, 75);
+        for i in 1..3 {
+            assert!(tracker.update(&invalid(2_500 + i * 50)).is_some());
+        }
+        assert_eq!(tracker.update(&invalid(2_650)), None);
+    }
+
+    #[test]
+    fn restarts_after_a_gap() {
+        let mut tracker = Tracker::new();
+        tracker.update(&test_measurement(0, 1_000));
+        tracker.update(&test_measurement(100, 1_100));
+        let track = tracker
+            .update(&test_measurement(100 + MAX_GAP_MS + 1, 500))
+            .unwrap();
+        assert_eq!(
+            (track.position_mm, track.velocity_mm_s, track.confidence_pct),
+            (500, 0, CONFIDENCE_GAIN_PCT)
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! The external trigger input of the `trigger-input` feature on `PC10` (EXTI15_10, shared with the
+//! user button): while the acquisition is `triggered` (see [`crate::acquisition`]) each rising
+//! edge starts exactly one measurement, so that the sensor can be synchronized to e.g. a camera
+//! shutter or the sampling clock of another sensor. The sensor is stopped again once the
+//! measurement has been read.
+//!
+//! A pulse only starts a measurement while the ranging is stopped & no triggered measurement is in
+//! progress, otherwise it's counted as missed: the pulses mustn't come faster than the timing
+//! budget.
+
+#[cfg(feature = "trigger-input")]
+use stm32f4xx_hal::gpio::{Edge, ExtiPin, Input, PC10};
+#[cfg(feature = "trigger-input")]
+use stm32f4xx_hal::pac::EXTI;
+#[cfg(feature = "trigger-input")]
+use stm32f4xx_hal::syscfg::SysCfg;
+
+#[cfg(all(feature = "trigger-input", feature = "menu"))]
+compile_error!("the features `trigger-input` and `menu` can't be combined as both use PC10");
+
+/// The state of the single measurements, which are also started by the [`crate::cadence`].
+#[derive(Debug, Default)]
+pub struct Trigger {
+    /// Whether a triggered measurement is in progress.
+    measuring: bool,
+    pulses: u32,
+    missed: u32,
+}
+
+impl Trigger {
+    /// Handle a pulse, returns whether it starts a measurement. The sensor has to be `idle` (not
+    /// ranging) for it.
+    pub fn on_pulse(&mut self, idle: bool) -> bool {
+        self.pulses = self.pulses.wrapping_add(1);
+        if self.measuring || !idle {
+            self.missed = self.missed.wrapping_add(1);
+            return false;
+        }
+        self.measuring = true;
+        true
+    }
+
+    /// The measurement couldn't be started.
+    pub fn abort(&mut self) {
+        self.measuring = false;
+    }
+
+    /// Handle a measurement which has been read, returns whether it has been triggered, the
+    /// sensor has to be stopped then.
+    pub fn on_measurement(&mut self) -> bool {
+        core::mem::take(&mut self.measuring)
+    }
+
+    /// Number of pulses since boot.
+    pub fn pulses(&self) -> u32 {
+        self.pulses
+    }
+
+    /// Number of pulses which didn't start a measurement.
+    pub fn missed(&self) -> u32 {
+        self.missed
+    }
+}
+
+/// The pin of the trigger input, pulled down.
+#[cfg(feature = "trigger-input")]
+pub struct TriggerPin {
+    pin: PC10<Input>,
+}
+
+#[cfg(feature = "trigger-input")]
+impl TriggerPin {
+    pub fn new(pin: PC10<Input>, syscfg: &mut SysCfg, exti: &mut EXTI) -> Self {
+        let mut pin = pin;
+        pin.make_interrupt_source(syscfg);
+        pin.enable_interrupt(exti);
+        pin.trigger_on_edge(exti, Edge::Rising);
+        Self { pin }
+    }
+
+    /// Whether a pulse has raised the interrupt, which is acknowledged.
+    pub fn take_pulse(&mut self) -> bool {
+        let pulse = self.pin.check_interrupt();
+        if pulse {
+            self.pin.clear_interrupt_pending_bit();
+        }
+        pulse
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_one_measurement_per_pulse() {
+        let mut trigger = Trigger::default();
+        assert!(!trigger.on_pulse(false));
+        assert!(trigger.on_pulse(true));
+        // the measurement is still in progress
+        assert!(!trigger.on_pulse(true));
+        assert!(trigger.on_measurement());
+        assert!(!trigger.on_measurement());
+        assert!(trigger.on_pulse(true));
+        trigger.abort();
+        assert!(!trigger.on_measurement());
+        assert_eq!((trigger.pulses(), trigger.missed()), (4, 2));
+    }
+}
@@ -0,0 +1,287 @@
+//! Telemetry & command link via a UART, by default the USART2 which is connected to the virtual COM
+//! port of the ST-LINK on the Nucleo board.
+//!
+//! Both directions are interrupt driven so that sending telemetry never blocks the calling task.
+//!
+//! A slow host can pause the transmission with XOFF (`0x13`) & resume it with XON (`0x11`), unless
+//! disabled with [`config::XON_XOFF`] (the virtual COM port has no handshake lines). Data which
+//! doesn't fit into the transmit buffer is dropped as a whole, so that the host never receives a
+//! partial frame. The measurement frames leave [`config::RESERVED_LEN`] bytes of the buffer free,
+//! thus they're dropped first & the responses & the other frames (e.g. the events) still fit.
+
+use crate::config::uart as config;
+
+use crate::command::{self, Command, ParseError};
+use crate::sink::Sink;
+use crate::telemetry::Measurement;
+use stm32f4xx_hal::hal::serial::{Read, Write};
+use stm32f4xx_hal::nb;
+use stm32f4xx_hal::prelude::*;
+use stm32f4xx_hal::serial::{self, Event, Serial};
+
+/// Size of the transmit buffer. Data which doesn't fit into it anymore is dropped.
+pub const TX_QUEUE_LEN: usize = crate::config::rt::UART_TX_QUEUE_LEN;
+
+/// Resumes the transmission.
+const XON: u8 = 0x11;
+/// Pauses the transmission.
+const XOFF: u8 = 0x13;
+
+/// Baud rate used for the link.
+pub const BAUD_RATE: u32 = 115_200;
+
+/// Number of bytes received during a file transfer which can wait to be handled, a firmware update
+/// receives whole blocks of 1 KiB.
+#[cfg(any(feature = "xmodem", feature = "firmware-update"))]
+const TRANSFER_RX_LEN: usize = if cfg!(feature = "firmware-update") {
+    2048
+} else {
+    16
+};
+
+/// An interrupt driven UART with a transmit buffer.
+pub struct BufferedUart<UART: serial::Instance> {
+    serial: Serial<UART>,
+    tx_queue: heapless::Deque<u8, TX_QUEUE_LEN>,
+    /// Whether the host has paused the transmission.
+    paused: bool,
+}
+
+impl<UART> BufferedUart<UART>
+where
+    UART: serial::Instance,
+    Serial<UART>: Read<u8, Error = serial::Error>
+        + Write<u8, Error = serial::Error>
+        + stm32f4xx_hal::Listen<Event = Event>,
+{
+    pub fn new(mut serial: Serial<UART>) -> Self {
+        serial.listen(Event::RxNotEmpty);
+        Self {
+            serial,
+            tx_queue: heapless::Deque::new(),
+            paused: false,
+        }
+    }
+
+    /// Handle the UART interrupt: hand all received bytes to `on_byte` and send the next bytes of
+    /// the transmit buffer.
+    pub fn on_interrupt(&mut self, mut on_byte: impl FnMut(&mut Self, u8)) {
+        loop {
+            match self.serial.read() {
+                Ok(byte) => on_byte(self, byte),
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(e)) => {
+                    defmt::warn!("UART receive error: {}", defmt::Debug2Format(&e));
+                }
+            }
+        }
+
+        while let Some(byte) = self.tx_queue.front().filter(|_| !self.paused) {
+            match self.serial.write(*byte) {
+                Ok(()) => {
+                    self.tx_queue.pop_front();
+                }
+                Err(_) => break,
+            }
+        }
+        if self.tx_queue.is_empty() || self.paused {
+            self.serial.unlisten(Event::TxEmpty);
+        }
+    }
+
+    /// Pause (or resume) the transmission, the data is still queued meanwhile.
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused != self.paused {
+            defmt::debug!(
+                "UART transmission {}",
+                if paused { "paused" } else { "resumed" }
+            );
+        }
+        self.paused = paused;
+        if !paused && !self.tx_queue.is_empty() {
+            self.serial.listen(Event::TxEmpty);
+        }
+    }
+
+    /// Number of bytes which can currently be queued without dropping any.
+    pub fn free_space(&self) -> usize {
+        self.tx_queue.capacity() - self.tx_queue.len()
+    }
+
+    /// Queue the data for sending. Data which doesn't fit into the transmit buffer is dropped as a
+    /// whole.
+    pub fn write(&mut self, bytes: &[u8]) {
+        if bytes.len() > self.free_space() {
+            defmt::trace!("UART buffer full, dropping {} bytes", bytes.len());
+            return;
+        }
+        for byte in bytes {
+            // there's enough space
+            self.tx_queue.push_back(*byte).ok();
+        }
+        if !self.tx_queue.is_empty() && !self.paused {
+            self.serial.listen(Event::TxEmpty);
+        }
+    }
+}
+
+/// Telemetry & command link to a host.
+// unused if MQTT-SN is used on the virtual COM port & no other UART link is enabled
+#[cfg_attr(feature = "mqtt-sn", allow(dead_code))]
+pub struct UartLink<UART: serial::Instance> {
+    uart: BufferedUart<UART>,
+    line_buffer: command::LineBuffer,
+    /// Number of frames which have been dropped since boot as they didn't fit into the transmit
+    /// buffer.
+    dropped_frames: u32,
+    /// The bytes received during a file transfer, `None` if none is running.
+    #[cfg(any(feature = "xmodem", feature = "firmware-update"))]
+    transfer: Option<heapless::Deque<u8, TRANSFER_RX_LEN>>,
+}
+
+#[cfg_attr(feature = "mqtt-sn", allow(dead_code))]
+impl<UART> UartLink<UART>
+where
+    UART: serial::Instance,
+    Serial<UART>: Read<u8, Error = serial::Error>
+        + Write<u8, Error = serial::Error>
+        + stm32f4xx_hal::Listen<Event = Event>,
+{
+    pub fn new(serial: Serial<UART>) -> Self {
+        Self {
+            uart: BufferedUart::new(serial),
+            line_buffer: command::LineBuffer::new(),
+            dropped_frames: 0,
+            #[cfg(any(feature = "xmodem", feature = "firmware-update"))]
+            transfer: None,
+        }
+    }
+
+    /// Handle the UART interrupt.
+    ///
+    /// Every complete line received from the host is parsed and handed to `on_command`, unless a
+    /// file transfer is running. XON & XOFF resume & pause the transmission instead.
+    pub fn on_interrupt(
+        &mut self,
+        mut on_command: impl FnMut(&mut BufferedUart<UART>, Result<Command, ParseError>),
+    ) {
+        let line_buffer = &mut self.line_buffer;
+        #[cfg(any(feature = "xmodem", feature = "firmware-update"))]
+        let transfer = &mut self.transfer;
+        self.uart.on_interrupt(|uart, byte| {
+            #[cfg(any(feature = "xmodem", feature = "firmware-update"))]
+            if let Some(received) = transfer {
+                if received.push_back(byte).is_err() {
+                    defmt::warn!("UART: dropping byte received during the transfer");
+                }
+                return;
+            }
+            if config::XON_XOFF && (byte == XON || byte == XOFF) {
+                uart.set_paused(byte == XOFF);
+                return;
+            }
+            if let Some(result) = line_buffer.push(byte) {
+                on_command(uart, result);
+            }
+        });
+    }
+
+    /// Queue the data for sending. Data which doesn't fit into the transmit buffer is dropped, as
+    /// well as all data while a file transfer is running.
+    pub fn write(&mut self, bytes: &[u8]) {
+        #[cfg(any(feature = "xmodem", feature = "firmware-update"))]
+        if self.transfer.is_some() {
+            return;
+        }
+        if bytes.len() > self.uart.free_space() {
+            self.dropped_frames = self.dropped_frames.wrapping_add(1);
+        }
+        self.uart.write(bytes);
+    }
+
+    /// Queue a measurement frame, which is dropped unless [`config::RESERVED_LEN`] bytes of the
+    /// transmit buffer stay free for the other data.
+    pub fn write_measurement(&mut self, bytes: &[u8]) {
+        if bytes.len() + config::RESERVED_LEN > self.uart.free_space() {
+            self.dropped_frames = self.dropped_frames.wrapping_add(1);
+            return;
+        }
+        self.write(bytes);
+    }
+
+    /// Number of frames which have been dropped since boot.
+    pub fn dropped_frames(&self) -> u32 {
+        self.dropped_frames
+    }
+
+    /// Number of bytes which can currently be queued without dropping any.
+    #[cfg_attr(not(feature = "nor-flash"), allow(dead_code))]
+    pub fn free_space(&self) -> usize {
+        self.uart.free_space()
+    }
+
+    /// Whether a file transfer is running.
+    #[cfg(feature = "firmware-update")]
+    pub fn is_transferring(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    /// Use the link exclusively for a file transfer, see [`crate::xmodem`]: the telemetry is paused
+    /// & no commands are handled until [`Self::stop_transfer`] is called.
+    #[cfg(any(feature = "xmodem", feature = "firmware-update"))]
+    pub fn start_transfer(&mut self) {
+        self.line_buffer = command::LineBuffer::new();
+        self.transfer = Some(heapless::Deque::new());
+    }
+
+    #[cfg(any(feature = "xmodem", feature = "firmware-update"))]
+    pub fn stop_transfer(&mut self) {
+        self.transfer = None;
+    }
+
+    /// Take the bytes which have been received during the file transfer.
+    #[cfg(any(feature = "xmodem", feature = "firmware-update"))]
+    pub fn transfer_receive(&mut self) -> heapless::Vec<u8, TRANSFER_RX_LEN> {
+        let mut bytes = heapless::Vec::new();
+        if let Some(received) = &mut self.transfer {
+            while let Some(byte) = received.pop_front() {
+                bytes.push(byte).ok();
+            }
+        }
+        bytes
+    }
+
+    /// Queue as many bytes of the file transfer as fit into the transmit buffer, returns their
+    /// number.
+    #[cfg(any(feature = "xmodem", feature = "firmware-update"))]
+    pub fn transfer_write(&mut self, bytes: &[u8]) -> usize {
+        let len = bytes.len().min(self.uart.free_space());
+        self.uart.write(&bytes[..len]);
+        len
+    }
+}
+
+impl<UART> Sink for UartLink<UART>
+where
+    UART: serial::Instance,
+    Serial<UART>: Read<u8, Error = serial::Error>
+        + Write<u8, Error = serial::Error>
+        + stm32f4xx_hal::Listen<Event = Event>,
+{
+    fn measurement(&mut self, _measurement: &Measurement, frame: &str) {
+        self.write_measurement(frame.as_bytes());
+    }
+
+    fn batch(&mut self, frame: &str) {
+        self.write_measurement(frame.as_bytes());
+    }
+
+    fn frame(&mut self, frame: &str) {
+        self.write(frame.as_bytes());
+    }
+}
+
+/// Configuration of the UART.
+pub fn config() -> serial::Config {
+    serial::Config::default().baudrate(BAUD_RATE.bps())
+}
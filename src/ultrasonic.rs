@@ -0,0 +1,90 @@
+//! HC-SR04 ultrasonic sensor (`ultrasonic` feature) whose distance is fused with the one of the
+//! TOF sensor, see [`crate::fusion`]: the trigger is on `PC8`, the echo on `PB6` (TIM4 CH1, the
+//! HC-SR04 needs 5 V but its echo output can be connected directly as `PB6` is 5 V tolerant).
+//!
+//! The width of the echo pulse is captured by TIM4 in PWM input mode. The sensor is sampled with
+//! each measurement of the TOF sensor: the echo of the previous trigger is read & the sensor is
+//! triggered again, at most every [`TRIGGER_INTERVAL_MS`] so that the echoes don't overlap.
+
+use stm32f4xx_hal::gpio::{Output, PB6, PC8};
+use stm32f4xx_hal::pac::TIM4;
+use stm32f4xx_hal::prelude::*;
+use stm32f4xx_hal::rcc::Clocks;
+use stm32f4xx_hal::timer::{Flag, PwmInput, Timer};
+use stm32f4xx_hal::{ClearFlags, ReadFlags};
+
+#[cfg(feature = "buzzer")]
+compile_error!("the features `ultrasonic` and `buzzer` can't be combined as both use TIM4 & PB6");
+#[cfg(feature = "bluetooth-hc05")]
+compile_error!("the features `ultrasonic` and `bluetooth-hc05` can't be combined as both use PC8");
+
+/// Minimum time between two triggers, as recommended in the datasheet.
+pub const TRIGGER_INTERVAL_MS: u32 = 60;
+/// Maximum age of an echo to be fused with a measurement of the TOF sensor.
+const MAX_AGE_MS: u32 = 2 * TRIGGER_INTERVAL_MS;
+/// Duration of the trigger pulse in cycles of the system clock, at least 10 µs.
+const TRIGGER_CYCLES: u32 = 1_000;
+/// Frequency with which the timer is set up, it can capture echoes of up to 1 / this.
+const CAPTURE_HZ: u32 = 10;
+/// The range of the sensor, the echo is longer than the maximum if there's none.
+const MIN_MM: u16 = 20;
+const MAX_MM: u16 = 4_000;
+
+pub struct Ultrasonic {
+    echo: PwmInput<TIM4>,
+    trigger: PC8<Output>,
+    /// Frequency with which the timer counts.
+    tick_hz: u32,
+    triggered_ms: Option<u32>,
+    /// The distance of the latest echo (`None` if it was out of range) & when it was read.
+    latest: Option<(Option<u16>, u32)>,
+}
+
+impl Ultrasonic {
+    pub fn new(tim: TIM4, echo: PB6, trigger: PC8<Output>, clocks: &Clocks) -> Self {
+        let echo = Timer::new(tim, clocks).pwm_input(CAPTURE_HZ.Hz(), echo);
+        // the same prescaler as the one chosen by `pwm_input`
+        let timer_hz = clocks.timclk1().raw();
+        let prescaler = (timer_hz / CAPTURE_HZ - 1) / (1 << 16);
+        Self {
+            echo,
+            trigger,
+            tick_hz: timer_hz / (prescaler + 1),
+            triggered_ms: None,
+            latest: None,
+        }
+    }
+
+    /// Read the echo of the previous trigger & trigger the sensor again if it's time to. Returns
+    /// the distance of the latest echo, `None` if it's out of range or too old.
+    pub fn sample(&mut self, now_ms: u32) -> Option<u16> {
+        if self.echo.flags().contains(Flag::C2) && self.echo.is_valid_capture() {
+            // reading the capture clears the flag
+            let ticks = self.echo.get_duty_cycle_clocks() as u64;
+            let echo_us = (ticks * 1_000_000 / self.tick_hz as u64) as u32;
+            self.latest = Some((distance_mm(echo_us), now_ms));
+        }
+        self.echo.clear_flags(Flag::C2 | Flag::C2Overcapture);
+        let due = self
+            .triggered_ms
+            .is_none_or(|triggered_ms| now_ms.wrapping_sub(triggered_ms) >= TRIGGER_INTERVAL_MS);
+        if due {
+            self.trigger.set_high();
+            cortex_m::asm::delay(TRIGGER_CYCLES);
+            self.trigger.set_low();
+            self.triggered_ms = Some(now_ms);
+        }
+        self.latest
+            .filter(|(_, read_ms)| now_ms.wrapping_sub(*read_ms) <= MAX_AGE_MS)
+            .and_then(|(distance_mm, _)| distance_mm)
+    }
+}
+
+/// The distance of a target whose echo took `echo_us` (there & back) at the speed of sound at
+/// 20 °C, `None` if it's out of the range of the sensor.
+fn distance_mm(echo_us: u32) -> Option<u16> {
+    let distance_mm = (echo_us as u64 * 343 / 2_000) as u32;
+    (MIN_MM as u32..=MAX_MM as u32)
+        .contains(&distance_mm)
+        .then_some(distance_mm as u16)
+}
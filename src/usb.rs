@@ -0,0 +1,85 @@
+//! Telemetry & command link via the native USB OTG FS peripheral, exposed to the host as a CDC-ACM
+//! (virtual serial port) device.
+
+use crate::command::{self, Command, ParseError};
+use crate::sink::Sink;
+use crate::telemetry::Measurement;
+use stm32f4xx_hal::otg_fs::UsbBusType;
+use usb_device::{bus::UsbBusAllocator, prelude::*};
+use usbd_serial::SerialPort;
+
+/// Size of the endpoint memory which has to be provided by the caller.
+pub const EP_MEMORY_SIZE: usize = 1024;
+
+pub struct UsbLink {
+    device: UsbDevice<'static, UsbBusType>,
+    serial: SerialPort<'static, UsbBusType>,
+    line_buffer: command::LineBuffer,
+}
+
+impl UsbLink {
+    pub fn new(usb_bus: &'static UsbBusAllocator<UsbBusType>) -> Self {
+        let serial = SerialPort::new(usb_bus);
+        let device = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x16c0, 0x27dd))
+            .device_class(usbd_serial::USB_CLASS_CDC)
+            .strings(&[StringDescriptors::default()
+                .manufacturer("rursprung")
+                .product("VL53L1X TOF sensor")
+                .serial_number("0001")])
+            .expect("")
+            .build();
+
+        Self {
+            device,
+            serial,
+            line_buffer: command::LineBuffer::new(),
+        }
+    }
+
+    /// Handle pending USB events. Must be called from the USB interrupt.
+    ///
+    /// Every complete line received from the host is parsed and handed to `on_command`.
+    pub fn poll(&mut self, mut on_command: impl FnMut(&mut Self, Result<Command, ParseError>)) {
+        if !self.device.poll(&mut [&mut self.serial]) {
+            return;
+        }
+
+        let mut buf = [0u8; 64];
+        if let Ok(count) = self.serial.read(&mut buf) {
+            for byte in &buf[..count] {
+                if let Some(result) = self.line_buffer.push(*byte) {
+                    on_command(self, result);
+                }
+            }
+        }
+    }
+
+    /// Write to the USB serial port without blocking. Data which doesn't fit into the buffer is
+    /// dropped.
+    pub fn write(&mut self, bytes: &[u8]) {
+        let mut written = 0;
+        while written < bytes.len() {
+            match self.serial.write(&bytes[written..]) {
+                Ok(len) if len > 0 => written += len,
+                _ => {
+                    defmt::trace!("USB buffer full, dropping {} bytes", bytes.len() - written);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Sink for UsbLink {
+    fn measurement(&mut self, _measurement: &Measurement, frame: &str) {
+        self.write(frame.as_bytes());
+    }
+
+    fn batch(&mut self, frame: &str) {
+        self.write(frame.as_bytes());
+    }
+
+    fn frame(&mut self, frame: &str) {
+        self.write(frame.as_bytes());
+    }
+}
@@ -0,0 +1,127 @@
+//! The blue user button (B1) of the Nucleo board on `PC13`.
+//!
+//! Every edge of the button triggers an interrupt, the state of the button is read once it has
+//! settled for [`DEBOUNCE_MS`] to suppress the bouncing of the contacts. The presses are classified
+//! by their duration once the button is released. Short presses are counted until there's no
+//! further press for [`CLICK_GAP_MS`], thus a single press is only reported after this time.
+
+use crate::board::Button;
+use stm32f4xx_hal::gpio::{Edge, ExtiPin};
+use stm32f4xx_hal::pac::EXTI;
+use stm32f4xx_hal::syscfg::SysCfg;
+
+/// Time between an edge & reading the state of the button.
+pub const DEBOUNCE_MS: u32 = 20;
+/// Minimum duration of a [`ButtonEvent::LongPress`].
+const LONG_PRESS_MS: u32 = 1_500;
+/// Minimum duration of a [`ButtonEvent::VeryLongPress`].
+const VERY_LONG_PRESS_MS: u32 = 5_000;
+/// Maximum time between the release of the button & the next press of a multi-click.
+pub const CLICK_GAP_MS: u32 = 400;
+
+/// A gesture detected on the button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ButtonEvent {
+    Press,
+    DoubleClick,
+    TripleClick,
+    QuadrupleClick,
+    /// The button has been held for at least [`LONG_PRESS_MS`].
+    LongPress,
+    /// The button has been held for at least [`VERY_LONG_PRESS_MS`].
+    VeryLongPress,
+}
+
+pub struct UserButton {
+    pin: Button,
+    pressed: bool,
+    /// Time at which the button has been pressed.
+    pressed_ms: u32,
+    /// Time at which the button has been released.
+    released_ms: u32,
+    /// Number of short presses of the current multi-click.
+    clicks: u8,
+}
+
+impl UserButton {
+    pub fn new(pin: Button, syscfg: &mut SysCfg, exti: &mut EXTI) -> Self {
+        let mut pin = pin;
+        pin.make_interrupt_source(syscfg);
+        pin.enable_interrupt(exti);
+        pin.trigger_on_edge(exti, Edge::RisingFalling);
+        Self {
+            pin,
+            pressed: false,
+            pressed_ms: 0,
+            released_ms: 0,
+            clicks: 0,
+        }
+    }
+
+    /// Whether the button is currently pressed, without debouncing.
+    pub fn is_pressed(&self) -> bool {
+        self.pin.is_low()
+    }
+
+    /// Whether an edge has raised the interrupt, which is acknowledged. The line is shared with
+    /// the [`crate::trigger`] input.
+    pub fn take_interrupt(&mut self) -> bool {
+        let edge = self.pin.check_interrupt();
+        if edge {
+            self.pin.clear_interrupt_pending_bit();
+        }
+        edge
+    }
+
+    /// Read the debounced state of the button, [`DEBOUNCE_MS`] after an edge. Short presses are
+    /// reported by [`Self::finish_clicks`].
+    pub fn update(&mut self, now_ms: u32) -> Option<ButtonEvent> {
+        // the button connects the pin to GND
+        let pressed = self.pin.is_low();
+        if pressed == self.pressed {
+            return None;
+        }
+        self.pressed = pressed;
+        if pressed {
+            self.pressed_ms = now_ms;
+            return None;
+        }
+        self.released_ms = now_ms;
+        // the time is measured between the debounced edges, the debounce time cancels out
+        let duration_ms = now_ms.wrapping_sub(self.pressed_ms);
+        if duration_ms >= LONG_PRESS_MS {
+            // a long press ends a multi-click
+            self.clicks = 0;
+            if duration_ms >= VERY_LONG_PRESS_MS {
+                Some(ButtonEvent::VeryLongPress)
+            } else {
+                Some(ButtonEvent::LongPress)
+            }
+        } else {
+            self.clicks = self.clicks.saturating_add(1);
+            None
+        }
+    }
+
+    /// Whether a multi-click is in progress, [`Self::finish_clicks`] has to be called until it's
+    /// finished.
+    pub fn clicks_pending(&self) -> bool {
+        self.clicks > 0
+    }
+
+    /// Report the short presses once the gap after the last one has passed. More than four
+    /// presses are ignored.
+    pub fn finish_clicks(&mut self, now_ms: u32) -> Option<ButtonEvent> {
+        if self.pressed || now_ms.wrapping_sub(self.released_ms) < CLICK_GAP_MS {
+            return None;
+        }
+        let clicks = core::mem::take(&mut self.clicks);
+        match clicks {
+            1 => Some(ButtonEvent::Press),
+            2 => Some(ButtonEvent::DoubleClick),
+            3 => Some(ButtonEvent::TripleClick),
+            4 => Some(ButtonEvent::QuadrupleClick),
+            _ => None,
+        }
+    }
+}
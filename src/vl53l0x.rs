@@ -0,0 +1,1074 @@
+//! Setup & operation of the VL53L0X, the predecessor of the VL53L1X with 8 bit register indices &
+//! a range of up to 2 m. The tasks use it as a [`RangeSensor`] once [`crate::range_sensor::probe`]
+//! has found one.
+//!
+//! There's no driver crate for the buses of the firmware, thus this follows the sequences of ST's
+//! API (as ported by Pololu's VL53L0X library): the tuning settings, the reference SPADs, the
+//! reference calibration & the timeouts of the sequence steps. The short distance mode uses the
+//! default VCSEL periods of ST (14 & 10 PCLKs), the long one the ones of its long range profile
+//! (18 & 14 PCLKs). The sensor itself only checks the signal rate, ST's API estimates the sigma on
+//! the host, thus only [`Thresholds::signal_kcps`] is applied.
+
+use crate::calibration_store::CalibrationData;
+use crate::range_sensor::{
+    Optics, RangeSensor, ReadShortIndex, Reading, Thresholds, WriteShortIndex,
+};
+use crate::register_dump::{self, Snapshot};
+use crate::settings::TofSettings;
+use core::fmt::Debug;
+use vl53l1x_uld::{DistanceMode, RangeStatus};
+
+/// Number of attempts to set up the sensor.
+pub const SETUP_ATTEMPTS: u8 = 3;
+/// Number of times the status is read while waiting for the sensor before giving up.
+pub const MAX_POLLS: u16 = 2_000;
+/// The model ID of the VL53L0X.
+pub const MODEL_ID: u8 = 0xEE;
+/// The shortest timing budget the sensor accepts.
+pub const MIN_TIMING_BUDGET_MS: u16 = 20;
+/// The inter-measurement period until another one is configured.
+const DEFAULT_INTER_MEASUREMENT_MS: u16 = 100;
+/// The VL53L0X has no region of interest, the centre of the whole array is reported.
+const CENTER_SPAD: u8 = 199;
+
+const SYSRANGE_START: u8 = 0x00;
+const SYSTEM_SEQUENCE_CONFIG: u8 = 0x01;
+const SYSTEM_INTERMEASUREMENT_PERIOD: u8 = 0x04;
+const SYSTEM_INTERRUPT_CONFIG_GPIO: u8 = 0x0A;
+const SYSTEM_INTERRUPT_CLEAR: u8 = 0x0B;
+const SYSTEM_THRESH_LOW: u8 = 0x0E;
+const RESULT_INTERRUPT_STATUS: u8 = 0x13;
+const RESULT_RANGE_STATUS: u8 = 0x14;
+const CROSSTALK_COMPENSATION_PEAK_RATE_MCPS: u8 = 0x20;
+const ALGO_PART_TO_PART_RANGE_OFFSET_MM: u8 = 0x28;
+/// On page 0, [`ALGO_PHASECAL_LIM`] is at the same index on page 1.
+const ALGO_PHASECAL_CONFIG_TIMEOUT: u8 = 0x30;
+const ALGO_PHASECAL_LIM: u8 = 0x30;
+const GLOBAL_CONFIG_VCSEL_WIDTH: u8 = 0x32;
+const FINAL_RANGE_CONFIG_MIN_COUNT_RATE_RTN_LIMIT: u8 = 0x44;
+const MSRC_CONFIG_TIMEOUT_MACROP: u8 = 0x46;
+const FINAL_RANGE_CONFIG_VALID_PHASE_LOW: u8 = 0x47;
+const FINAL_RANGE_CONFIG_VALID_PHASE_HIGH: u8 = 0x48;
+const DYNAMIC_SPAD_NUM_REQUESTED_REF_SPAD: u8 = 0x4E;
+const DYNAMIC_SPAD_REF_EN_START_OFFSET: u8 = 0x4F;
+const PRE_RANGE_CONFIG_VCSEL_PERIOD: u8 = 0x50;
+const PRE_RANGE_CONFIG_TIMEOUT_MACROP_HI: u8 = 0x51;
+const PRE_RANGE_CONFIG_VALID_PHASE_LOW: u8 = 0x56;
+const PRE_RANGE_CONFIG_VALID_PHASE_HIGH: u8 = 0x57;
+const MSRC_CONFIG_CONTROL: u8 = 0x60;
+const FINAL_RANGE_CONFIG_VCSEL_PERIOD: u8 = 0x70;
+const FINAL_RANGE_CONFIG_TIMEOUT_MACROP_HI: u8 = 0x71;
+const GPIO_HV_MUX_ACTIVE_HIGH: u8 = 0x84;
+const VHV_CONFIG_PAD_SCL_SDA_EXTSUP_HV: u8 = 0x89;
+const I2C_SLAVE_DEVICE_ADDRESS: u8 = 0x8A;
+const GLOBAL_CONFIG_SPAD_ENABLES_REF_0: u8 = 0xB0;
+const GLOBAL_CONFIG_REF_EN_START_SELECT: u8 = 0xB6;
+const IDENTIFICATION_MODEL_ID: u8 = 0xC0;
+const OSC_CALIBRATE_VAL: u8 = 0xF8;
+/// Selects the page of the registers.
+const PAGE: u8 = 0xFF;
+
+/// Enters the page of the internal registers, e.g. the stop variable (0x91).
+const ENTER_INTERNAL: [(u8, u8); 3] = [(0x80, 0x01), (PAGE, 0x01), (0x00, 0x00)];
+/// Leaves it again.
+const LEAVE_INTERNAL: [(u8, u8); 3] = [(0x00, 0x01), (PAGE, 0x00), (0x80, 0x00)];
+
+/// The default tuning settings of ST's API (`vl53l0x_tuning.h`).
+const TUNING_SETTINGS: [(u8, u8); 80] = [
+    (0xFF, 0x01),
+    (0x00, 0x00),
+    (0xFF, 0x00),
+    (0x09, 0x00),
+    (0x10, 0x00),
+    (0x11, 0x00),
+    (0x24, 0x01),
+    (0x25, 0xFF),
+    (0x75, 0x00),
+    (0xFF, 0x01),
+    (0x4E, 0x2C),
+    (0x48, 0x00),
+    (0x30, 0x20),
+    (0xFF, 0x00),
+    (0x30, 0x09),
+    (0x54, 0x00),
+    (0x31, 0x04),
+    (0x32, 0x03),
+    (0x40, 0x83),
+    (0x46, 0x25),
+    (0x60, 0x00),
+    (0x27, 0x00),
+    (0x50, 0x06),
+    (0x51, 0x00),
+    (0x52, 0x96),
+    (0x56, 0x08),
+    (0x57, 0x30),
+    (0x61, 0x00),
+    (0x62, 0x00),
+    (0x64, 0x00),
+    (0x65, 0x00),
+    (0x66, 0xA0),
+    (0xFF, 0x01),
+    (0x22, 0x32),
+    (0x47, 0x14),
+    (0x49, 0xFF),
+    (0x4A, 0x00),
+    (0xFF, 0x00),
+    (0x7A, 0x0A),
+    (0x7B, 0x00),
+    (0x78, 0x21),
+    (0xFF, 0x01),
+    (0x23, 0x34),
+    (0x42, 0x00),
+    (0x44, 0xFF),
+    (0x45, 0x26),
+    (0x46, 0x05),
+    (0x40, 0x40),
+    (0x0E, 0x06),
+    (0x20, 0x1A),
+    (0x43, 0x40),
+    (0xFF, 0x00),
+    (0x34, 0x03),
+    (0x35, 0x44),
+    (0xFF, 0x01),
+    (0x31, 0x04),
+    (0x4B, 0x09),
+    (0x4C, 0x05),
+    (0x4D, 0x04),
+    (0xFF, 0x00),
+    (0x44, 0x00),
+    (0x45, 0x20),
+    (0x47, 0x08),
+    (0x48, 0x28),
+    (0x67, 0x00),
+    (0x70, 0x04),
+    (0x71, 0x01),
+    (0x72, 0xFE),
+    (0x76, 0x00),
+    (0x77, 0x00),
+    (0xFF, 0x01),
+    (0x0D, 0x01),
+    (0xFF, 0x00),
+    (0x80, 0x01),
+    (0x01, 0xF8),
+    (0xFF, 0x01),
+    (0x8E, 0x01),
+    (0x00, 0x01),
+    (0xFF, 0x00),
+    (0x80, 0x00),
+];
+
+/// The sequence steps: the final range, the pre-range, the target centre check (TCC), the
+/// minimum signal rate check (MSRC) & the dynamic SPAD selection (DSS), without the latter three
+/// by default.
+const DEFAULT_SEQUENCE: u8 = 0xE8;
+
+/// Overheads of the sequence steps in the timing budget in µs.
+const START_OVERHEAD_US: u32 = 1_910;
+const END_OVERHEAD_US: u32 = 960;
+const MSRC_OVERHEAD_US: u32 = 660;
+const TCC_OVERHEAD_US: u32 = 590;
+const DSS_OVERHEAD_US: u32 = 690;
+const PRE_RANGE_OVERHEAD_US: u32 = 660;
+const FINAL_RANGE_OVERHEAD_US: u32 = 550;
+
+/// An error of the sensor.
+#[derive(Debug)]
+pub enum Error<E: Debug> {
+    /// The communication with the sensor failed.
+    CommunicationError(E),
+    /// The timing budget is shorter than [`MIN_TIMING_BUDGET_MS`] or too long for the sensor.
+    InvalidTimingBudget,
+    /// The sensor hasn't finished an operation within [`MAX_POLLS`].
+    Timeout,
+}
+
+impl<E: Debug> From<E> for Error<E> {
+    fn from(error: E) -> Self {
+        Error::CommunicationError(error)
+    }
+}
+
+/// Reasons why the sensor could not be set up.
+pub type SetupError<E> = crate::range_sensor::SetupError<Error<E>>;
+
+/// The VCSEL (laser) periods of the pre-range & the final range of the distance mode in PCLKs.
+fn vcsel_periods(distance_mode: DistanceMode) -> (u8, u8) {
+    match distance_mode {
+        DistanceMode::Short => (14, 10),
+        DistanceMode::Long => (18, 14),
+    }
+}
+
+fn decode_vcsel_period(value: u8) -> u8 {
+    (value + 1) << 1
+}
+
+fn encode_vcsel_period(period_pclks: u8) -> u8 {
+    (period_pclks >> 1) - 1
+}
+
+/// The length of a macro period in ns.
+fn macro_period_ns(vcsel_period_pclks: u8) -> u32 {
+    (2_304 * vcsel_period_pclks as u32 * 1_655 + 500) / 1_000
+}
+
+/// Decode a timeout in macro periods, it's stored as `LSB * 2^MSB + 1`.
+fn decode_timeout(value: u16) -> u32 {
+    (((value & 0xFF) as u32) << (value >> 8) as u32) + 1
+}
+
+fn encode_timeout(timeout_mclks: u32) -> u16 {
+    if timeout_mclks == 0 {
+        return 0;
+    }
+    let mut lsb = timeout_mclks - 1;
+    let mut msb = 0;
+    while lsb > 0xFF {
+        lsb >>= 1;
+        msb += 1;
+    }
+    (msb << 8) | lsb as u16
+}
+
+fn timeout_mclks_to_us(timeout_mclks: u32, vcsel_period_pclks: u8) -> u32 {
+    let macro_period_ns = macro_period_ns(vcsel_period_pclks);
+    (timeout_mclks * macro_period_ns + 500) / 1_000
+}
+
+fn timeout_us_to_mclks(timeout_us: u32, vcsel_period_pclks: u8) -> u32 {
+    let macro_period_ns = macro_period_ns(vcsel_period_pclks);
+    (timeout_us * 1_000 + macro_period_ns / 2) / macro_period_ns
+}
+
+/// The offset is stored in quarter millimetres as a 12 bit two's complement.
+fn encode_offset(offset_mm: i16) -> u16 {
+    (offset_mm.clamp(-512, 511) * 4) as u16 & 0x0FFF
+}
+
+fn decode_offset(value: u16) -> i16 {
+    let quarters = (value & 0x0FFF) as i16;
+    let quarters = if quarters >= 2_048 {
+        quarters - 4_096
+    } else {
+        quarters
+    };
+    quarters / 4
+}
+
+/// The crosstalk is stored in MCPS with 13 fractional bits.
+fn encode_cross_talk(cross_talk_cps: u16) -> u16 {
+    ((cross_talk_cps as u32 * 8_192 + 500_000) / 1_000_000) as u16
+}
+
+fn decode_cross_talk(value: u16) -> u16 {
+    (value as u32 * 1_000_000 / 8_192) as u16
+}
+
+/// Convert the status of the device to the one of the VL53L1X, like ST's API does.
+fn range_status(device_status: u8) -> RangeStatus {
+    match (device_status & 0x78) >> 3 {
+        11 => RangeStatus::Valid,
+        1..=3 => RangeStatus::HardwareFailure,
+        4 => RangeStatus::SignalFailure,
+        6 | 9 => RangeStatus::OutOfBounds,
+        8 | 10 => RangeStatus::MinRangeFail,
+        _ => RangeStatus::InvalidRange,
+    }
+}
+
+/// The enabled steps of the measurement sequence.
+struct SequenceSteps {
+    tcc: bool,
+    dss: bool,
+    msrc: bool,
+    pre_range: bool,
+    final_range: bool,
+}
+
+impl SequenceSteps {
+    fn new(config: u8) -> Self {
+        Self {
+            tcc: config & 0x10 != 0,
+            dss: config & 0x08 != 0,
+            msrc: config & 0x04 != 0,
+            pre_range: config & 0x40 != 0,
+            final_range: config & 0x80 != 0,
+        }
+    }
+}
+
+/// The timeouts of the steps of the measurement sequence.
+struct SequenceTimeouts {
+    final_range_vcsel_period_pclks: u8,
+    msrc_dss_tcc_us: u32,
+    pre_range_mclks: u32,
+    pre_range_us: u32,
+    final_range_us: u32,
+}
+
+/// The part of a measurement sequence with its own VCSEL period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Range {
+    Pre,
+    Final,
+}
+
+/// A VL53L0X on the bus `B`.
+pub struct Vl53l0x<B> {
+    bus: B,
+    address: u8,
+    /// Read during the setup, it has to be restored to start ranging.
+    stop_variable: u8,
+    timing_budget_us: u32,
+    inter_measurement_ms: u16,
+}
+
+impl<B> Vl53l0x<B> {
+    pub fn new(bus: B, address: u8) -> Self {
+        Self {
+            bus,
+            address,
+            stop_variable: 0,
+            timing_budget_us: 0,
+            inter_measurement_ms: DEFAULT_INTER_MEASUREMENT_MS,
+        }
+    }
+}
+
+impl<B, E> Vl53l0x<B>
+where
+    B: ReadShortIndex<Error = E> + WriteShortIndex<Error = E>,
+    E: Debug,
+{
+    fn read_bytes(&mut self, index: u8, bytes: &mut [u8]) -> Result<(), Error<E>> {
+        Ok(self.bus.read_short_index(self.address, index, bytes)?)
+    }
+
+    fn read_u8(&mut self, index: u8) -> Result<u8, Error<E>> {
+        let mut value = [0];
+        self.read_bytes(index, &mut value)?;
+        Ok(value[0])
+    }
+
+    fn read_u16(&mut self, index: u8) -> Result<u16, Error<E>> {
+        let mut value = [0; 2];
+        self.read_bytes(index, &mut value)?;
+        Ok(u16::from_be_bytes(value))
+    }
+
+    fn write_bytes(&mut self, index: u8, bytes: &[u8]) -> Result<(), Error<E>> {
+        Ok(self.bus.write_short_index(self.address, index, bytes)?)
+    }
+
+    fn write_u8(&mut self, index: u8, value: u8) -> Result<(), Error<E>> {
+        self.write_bytes(index, &[value])
+    }
+
+    fn write_u16(&mut self, index: u8, value: u16) -> Result<(), Error<E>> {
+        self.write_bytes(index, &value.to_be_bytes())
+    }
+
+    /// Write the registers one after the other.
+    fn write_all(&mut self, registers: &[(u8, u8)]) -> Result<(), Error<E>> {
+        for &(index, value) in registers {
+            self.write_u8(index, value)?;
+        }
+        Ok(())
+    }
+
+    /// Wait until `done` returns true for the value of the register.
+    fn poll(&mut self, index: u8, done: impl Fn(u8) -> bool) -> Result<(), Error<E>> {
+        for _ in 0..MAX_POLLS {
+            if done(self.read_u8(index)?) {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Change the I2C address of the sensor, it keeps it until it's powered off.
+    pub fn set_address(&mut self, address: u8) -> Result<(), Error<E>> {
+        self.write_u8(I2C_SLAVE_DEVICE_ADDRESS, address & 0x7F)?;
+        self.address = address;
+        Ok(())
+    }
+
+    /// The data & static initialisation of ST's API followed by the reference calibration.
+    fn init(&mut self) -> Result<(), Error<E>> {
+        // 2.8 V I/O like the VL53L1X
+        let pad = self.read_u8(VHV_CONFIG_PAD_SCL_SDA_EXTSUP_HV)?;
+        self.write_u8(VHV_CONFIG_PAD_SCL_SDA_EXTSUP_HV, pad | 0x01)?;
+        // standard I2C mode
+        self.write_u8(0x88, 0x00)?;
+        self.write_all(&ENTER_INTERNAL)?;
+        self.stop_variable = self.read_u8(0x91)?;
+        self.write_all(&LEAVE_INTERNAL)?;
+        // no limit checks of the signal rate of the MSRC & the pre-range
+        let control = self.read_u8(MSRC_CONFIG_CONTROL)?;
+        self.write_u8(MSRC_CONFIG_CONTROL, control | 0x12)?;
+        // the signal rate limit of ST's API, 0.25 MCPS with 7 fractional bits
+        self.write_u16(FINAL_RANGE_CONFIG_MIN_COUNT_RATE_RTN_LIMIT, 32)?;
+        self.write_u8(SYSTEM_SEQUENCE_CONFIG, 0xFF)?;
+
+        self.set_reference_spads()?;
+        self.write_all(&TUNING_SETTINGS)?;
+        // signal each new measurement with the interrupt
+        self.write_u8(SYSTEM_INTERRUPT_CONFIG_GPIO, 0x04)?;
+        let mux = self.read_u8(GPIO_HV_MUX_ACTIVE_HIGH)? & !0x10;
+        let polarity = if crate::config::tof_interrupt::ACTIVE_HIGH {
+            0x10
+        } else {
+            0x00
+        };
+        self.write_u8(GPIO_HV_MUX_ACTIVE_HIGH, mux | polarity)?;
+        self.write_u8(SYSTEM_INTERRUPT_CLEAR, 0x01)?;
+
+        self.timing_budget_us = self.read_timing_budget_us()?;
+        self.write_u8(SYSTEM_SEQUENCE_CONFIG, DEFAULT_SEQUENCE)?;
+        // the final range takes the time of the disabled steps
+        self.set_timing_budget_us(self.timing_budget_us)?;
+        self.calibrate_reference()
+    }
+
+    /// Enable the reference SPADs as they've been characterised in the factory.
+    fn set_reference_spads(&mut self) -> Result<(), Error<E>> {
+        self.write_all(&ENTER_INTERNAL)?;
+        self.write_u8(PAGE, 0x06)?;
+        let value = self.read_u8(0x83)?;
+        self.write_u8(0x83, value | 0x04)?;
+        self.write_all(&[
+            (PAGE, 0x07),
+            (0x81, 0x01),
+            (0x80, 0x01),
+            (0x94, 0x6B),
+            (0x83, 0x00),
+        ])?;
+        self.poll(0x83, |value| value != 0)?;
+        self.write_u8(0x83, 0x01)?;
+        let info = self.read_u8(0x92)?;
+        self.write_all(&[(0x81, 0x00), (PAGE, 0x06)])?;
+        let value = self.read_u8(0x83)?;
+        self.write_u8(0x83, value & !0x04)?;
+        self.write_all(&[(PAGE, 0x01), (0x00, 0x01), (PAGE, 0x00), (0x80, 0x00)])?;
+        let count = info & 0x7F;
+        // the first 12 SPADs aren't aperture ones
+        let first = if info & 0x80 != 0 { 12 } else { 0 };
+
+        let mut spad_map = [0; 6];
+        self.read_bytes(GLOBAL_CONFIG_SPAD_ENABLES_REF_0, &mut spad_map)?;
+        self.write_all(&[
+            (PAGE, 0x01),
+            (DYNAMIC_SPAD_REF_EN_START_OFFSET, 0x00),
+            (DYNAMIC_SPAD_NUM_REQUESTED_REF_SPAD, 0x2C),
+            (PAGE, 0x00),
+            (GLOBAL_CONFIG_REF_EN_START_SELECT, 0xB4),
+        ])?;
+        let mut enabled = 0;
+        for spad in 0..48 {
+            let bit = 1 << (spad % 8);
+            if spad < first || enabled == count {
+                spad_map[spad / 8] &= !bit;
+            } else if spad_map[spad / 8] & bit != 0 {
+                enabled += 1;
+            }
+        }
+        self.write_bytes(GLOBAL_CONFIG_SPAD_ENABLES_REF_0, &spad_map)
+    }
+
+    /// Run one step of the reference calibration, e.g. after the temperature has changed.
+    fn calibrate_once(&mut self, vhv_init: u8) -> Result<(), Error<E>> {
+        self.write_u8(SYSRANGE_START, 0x01 | vhv_init)?;
+        self.poll(RESULT_INTERRUPT_STATUS, |status| status & 0x07 != 0)?;
+        self.write_all(&[(SYSTEM_INTERRUPT_CLEAR, 0x01), (SYSRANGE_START, 0x00)])
+    }
+
+    /// The VHV (temperature) & the phase calibration, the sensor must not be ranging.
+    fn calibrate_reference(&mut self) -> Result<(), Error<E>> {
+        let sequence = self.read_u8(SYSTEM_SEQUENCE_CONFIG)?;
+        self.write_u8(SYSTEM_SEQUENCE_CONFIG, 0x01)?;
+        self.calibrate_once(0x40)?;
+        self.write_u8(SYSTEM_SEQUENCE_CONFIG, 0x02)?;
+        self.calibrate_once(0x00)?;
+        self.write_u8(SYSTEM_SEQUENCE_CONFIG, sequence)
+    }
+
+    fn sequence_steps(&mut self) -> Result<SequenceSteps, Error<E>> {
+        Ok(SequenceSteps::new(self.read_u8(SYSTEM_SEQUENCE_CONFIG)?))
+    }
+
+    fn vcsel_period(&mut self, range: Range) -> Result<u8, Error<E>> {
+        let index = match range {
+            Range::Pre => PRE_RANGE_CONFIG_VCSEL_PERIOD,
+            Range::Final => FINAL_RANGE_CONFIG_VCSEL_PERIOD,
+        };
+        Ok(decode_vcsel_period(self.read_u8(index)?))
+    }
+
+    fn sequence_timeouts(&mut self, steps: &SequenceSteps) -> Result<SequenceTimeouts, Error<E>> {
+        let pre_range_vcsel_period_pclks = self.vcsel_period(Range::Pre)?;
+        let msrc_dss_tcc_mclks = self.read_u8(MSRC_CONFIG_TIMEOUT_MACROP)? as u32 + 1;
+        let pre_range_mclks = decode_timeout(self.read_u16(PRE_RANGE_CONFIG_TIMEOUT_MACROP_HI)?);
+        let final_range_vcsel_period_pclks = self.vcsel_period(Range::Final)?;
+        let mut final_range_mclks =
+            decode_timeout(self.read_u16(FINAL_RANGE_CONFIG_TIMEOUT_MACROP_HI)?);
+        // the timeout of the final range includes the pre-range
+        if steps.pre_range {
+            final_range_mclks = final_range_mclks.saturating_sub(pre_range_mclks);
+        }
+        Ok(SequenceTimeouts {
+            final_range_vcsel_period_pclks,
+            msrc_dss_tcc_us: timeout_mclks_to_us(msrc_dss_tcc_mclks, pre_range_vcsel_period_pclks),
+            pre_range_mclks,
+            pre_range_us: timeout_mclks_to_us(pre_range_mclks, pre_range_vcsel_period_pclks),
+            final_range_us: timeout_mclks_to_us(final_range_mclks, final_range_vcsel_period_pclks),
+        })
+    }
+
+    /// The time taken by the steps before the final range.
+    fn overhead_us(steps: &SequenceSteps, timeouts: &SequenceTimeouts) -> u32 {
+        let mut overhead_us = START_OVERHEAD_US + END_OVERHEAD_US;
+        if steps.tcc {
+            overhead_us += timeouts.msrc_dss_tcc_us + TCC_OVERHEAD_US;
+        }
+        if steps.dss {
+            overhead_us += 2 * (timeouts.msrc_dss_tcc_us + DSS_OVERHEAD_US);
+        } else if steps.msrc {
+            overhead_us += timeouts.msrc_dss_tcc_us + MSRC_OVERHEAD_US;
+        }
+        if steps.pre_range {
+            overhead_us += timeouts.pre_range_us + PRE_RANGE_OVERHEAD_US;
+        }
+        overhead_us
+    }
+
+    /// The timing budget of the configured sequence.
+    fn read_timing_budget_us(&mut self) -> Result<u32, Error<E>> {
+        let steps = self.sequence_steps()?;
+        let timeouts = self.sequence_timeouts(&steps)?;
+        let mut budget_us = Self::overhead_us(&steps, &timeouts);
+        if steps.final_range {
+            budget_us += timeouts.final_range_us + FINAL_RANGE_OVERHEAD_US;
+        }
+        Ok(budget_us)
+    }
+
+    /// Apply the timing budget, the final range takes what the other steps leave of it.
+    fn set_timing_budget_us(&mut self, budget_us: u32) -> Result<(), Error<E>> {
+        let steps = self.sequence_steps()?;
+        let timeouts = self.sequence_timeouts(&steps)?;
+        if !steps.final_range {
+            return Ok(());
+        }
+        let used_us = Self::overhead_us(&steps, &timeouts) + FINAL_RANGE_OVERHEAD_US;
+        let final_range_us = budget_us
+            .checked_sub(used_us)
+            .ok_or(Error::InvalidTimingBudget)?;
+        let mut final_range_mclks =
+            timeout_us_to_mclks(final_range_us, timeouts.final_range_vcsel_period_pclks);
+        if steps.pre_range {
+            final_range_mclks += timeouts.pre_range_mclks;
+        }
+        self.write_u16(
+            FINAL_RANGE_CONFIG_TIMEOUT_MACROP_HI,
+            encode_timeout(final_range_mclks),
+        )?;
+        self.timing_budget_us = budget_us;
+        Ok(())
+    }
+
+    /// Change the VCSEL period of the range, the timeouts are kept & the phase is calibrated
+    /// again.
+    fn set_vcsel_period(&mut self, range: Range, period_pclks: u8) -> Result<(), Error<E>> {
+        let steps = self.sequence_steps()?;
+        let timeouts = self.sequence_timeouts(&steps)?;
+        let value = encode_vcsel_period(period_pclks);
+        match range {
+            Range::Pre => {
+                let phase_high = match period_pclks {
+                    14 => 0x30,
+                    _ => 0x50,
+                };
+                self.write_all(&[
+                    (PRE_RANGE_CONFIG_VALID_PHASE_HIGH, phase_high),
+                    (PRE_RANGE_CONFIG_VALID_PHASE_LOW, 0x08),
+                    (PRE_RANGE_CONFIG_VCSEL_PERIOD, value),
+                ])?;
+                let pre_range_mclks = timeout_us_to_mclks(timeouts.pre_range_us, period_pclks);
+                self.write_u16(
+                    PRE_RANGE_CONFIG_TIMEOUT_MACROP_HI,
+                    encode_timeout(pre_range_mclks),
+                )?;
+                let msrc_mclks = timeout_us_to_mclks(timeouts.msrc_dss_tcc_us, period_pclks);
+                self.write_u8(
+                    MSRC_CONFIG_TIMEOUT_MACROP,
+                    (msrc_mclks.clamp(1, 256) - 1) as u8,
+                )?;
+            }
+            Range::Final => {
+                let (phase_high, width, phasecal_timeout) = match period_pclks {
+                    10 => (0x28, 0x03, 0x09),
+                    _ => (0x48, 0x03, 0x07),
+                };
+                self.write_all(&[
+                    (FINAL_RANGE_CONFIG_VALID_PHASE_HIGH, phase_high),
+                    (FINAL_RANGE_CONFIG_VALID_PHASE_LOW, 0x08),
+                    (GLOBAL_CONFIG_VCSEL_WIDTH, width),
+                    (ALGO_PHASECAL_CONFIG_TIMEOUT, phasecal_timeout),
+                    (PAGE, 0x01),
+                    (ALGO_PHASECAL_LIM, 0x20),
+                    (PAGE, 0x00),
+                    (FINAL_RANGE_CONFIG_VCSEL_PERIOD, value),
+                ])?;
+                let mut final_range_mclks =
+                    timeout_us_to_mclks(timeouts.final_range_us, period_pclks);
+                if steps.pre_range {
+                    final_range_mclks += timeouts.pre_range_mclks;
+                }
+                self.write_u16(
+                    FINAL_RANGE_CONFIG_TIMEOUT_MACROP_HI,
+                    encode_timeout(final_range_mclks),
+                )?;
+            }
+        }
+        self.set_timing_budget_us(self.timing_budget_us)?;
+        // only the phase calibration
+        let sequence = self.read_u8(SYSTEM_SEQUENCE_CONFIG)?;
+        self.write_u8(SYSTEM_SEQUENCE_CONFIG, 0x02)?;
+        self.calibrate_once(0x00)?;
+        self.write_u8(SYSTEM_SEQUENCE_CONFIG, sequence)
+    }
+
+    fn distance_mode(&mut self) -> Result<DistanceMode, Error<E>> {
+        Ok(
+            if self.vcsel_period(Range::Final)? == vcsel_periods(DistanceMode::Long).1 {
+                DistanceMode::Long
+            } else {
+                DistanceMode::Short
+            },
+        )
+    }
+
+    fn set_cross_talk(&mut self, cross_talk_cps: u16) -> Result<(), Error<E>> {
+        self.write_u16(
+            CROSSTALK_COMPENSATION_PEAK_RATE_MCPS,
+            encode_cross_talk(cross_talk_cps),
+        )
+    }
+
+    fn set_offset(&mut self, offset_mm: i16) -> Result<(), Error<E>> {
+        self.write_u16(ALGO_PART_TO_PART_RANGE_OFFSET_MM, encode_offset(offset_mm))
+    }
+
+    fn try_setup(
+        &mut self,
+        calibration: Option<CalibrationData>,
+        settings: Option<TofSettings>,
+    ) -> Result<(), SetupError<E>> {
+        let id = self.read_u8(IDENTIFICATION_MODEL_ID)?;
+        if id != MODEL_ID {
+            return Err(SetupError::UnknownModel(id as u16));
+        }
+        self.init()?;
+        if let Some(calibration) = calibration {
+            defmt::info!("applying the stored calibration {}", calibration);
+            self.set_calibration(&calibration)?;
+        }
+        if let Some(settings) = settings {
+            self.configure(&settings)?;
+        }
+        self.start()?;
+        Ok(())
+    }
+}
+
+impl<B, E> RangeSensor for Vl53l0x<B>
+where
+    B: ReadShortIndex<Error = E> + WriteShortIndex<Error = E>,
+    E: Debug,
+{
+    type Error = Error<E>;
+
+    fn setup(
+        &mut self,
+        calibration: Option<CalibrationData>,
+        settings: Option<TofSettings>,
+    ) -> Result<(), SetupError<E>> {
+        let mut attempt = 1;
+        loop {
+            match self.try_setup(calibration, settings) {
+                // retrying doesn't help if there's another device
+                Err(SetupError::UnknownModel(id)) => {
+                    defmt::error!("unknown model {=u16:#04x} instead of a VL53L0X", id);
+                    return Err(SetupError::UnknownModel(id));
+                }
+                Err(e) if attempt < SETUP_ATTEMPTS => {
+                    defmt::warn!(
+                        "failed to set up the TOF sensor ({}/{}): {}",
+                        attempt,
+                        SETUP_ATTEMPTS,
+                        defmt::Debug2Format(&e)
+                    );
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Start ranging continuously with the inter-measurement period (at least the timing
+    /// budget).
+    fn start(&mut self) -> Result<(), Error<E>> {
+        self.write_all(&ENTER_INTERNAL)?;
+        self.write_u8(0x91, self.stop_variable)?;
+        self.write_all(&LEAVE_INTERNAL)?;
+        let budget_ms = self.timing_budget_us.div_ceil(1_000);
+        let mut period = (self.inter_measurement_ms as u32).max(budget_ms);
+        // the period is counted in cycles of the oscillator
+        let oscillator = self.read_u16(OSC_CALIBRATE_VAL)?;
+        if oscillator != 0 {
+            period *= oscillator as u32;
+        }
+        self.write_bytes(SYSTEM_INTERMEASUREMENT_PERIOD, &period.to_be_bytes())?;
+        // the timed mode
+        self.write_u8(SYSRANGE_START, 0x04)
+    }
+
+    fn stop(&mut self) -> Result<(), Error<E>> {
+        // the single shot mode stops the timed one
+        self.write_u8(SYSRANGE_START, 0x01)?;
+        self.write_all(&[
+            (PAGE, 0x01),
+            (0x00, 0x00),
+            (0x91, 0x00),
+            (0x00, 0x01),
+            (PAGE, 0x00),
+        ])
+    }
+
+    fn is_data_ready(&mut self) -> Result<bool, Error<E>> {
+        Ok(self.read_u8(RESULT_INTERRUPT_STATUS)? & 0x07 != 0)
+    }
+
+    fn read(&mut self) -> Result<Reading, Error<E>> {
+        let mut result = [0; 12];
+        let read = self.read_bytes(RESULT_RANGE_STATUS, &mut result);
+        self.write_u8(SYSTEM_INTERRUPT_CLEAR, 0x01).ok();
+        read?;
+        let value = |offset: usize| u16::from_be_bytes([result[offset], result[offset + 1]]);
+        // the rates are in MCPS with 7 fractional bits, the SPADs with 8
+        let kcps = |mcps: u16| (mcps as u32 * 1_000 / 128) as u16;
+        let spads = value(2) as u32;
+        Ok(Reading {
+            distance_mm: value(10),
+            status: range_status(result[0]),
+            ambient_kcps: kcps(value(8)),
+            signal_per_spad_kcps: match spads {
+                0 => 0,
+                _ => (kcps(value(6)) as u32 * 256 / spads) as u16,
+            },
+        })
+    }
+
+    fn configure(&mut self, settings: &TofSettings) -> Result<(), Error<E>> {
+        if settings.timing_budget_ms < MIN_TIMING_BUDGET_MS {
+            return Err(Error::InvalidTimingBudget);
+        }
+        if self.distance_mode()? != settings.distance_mode {
+            let (pre_range, final_range) = vcsel_periods(settings.distance_mode);
+            self.set_vcsel_period(Range::Pre, pre_range)?;
+            self.set_vcsel_period(Range::Final, final_range)?;
+        }
+        self.set_timing_budget_us(settings.timing_budget_ms as u32 * 1_000)?;
+        self.inter_measurement_ms = settings.inter_measurement_ms;
+        Ok(())
+    }
+
+    fn set_thresholds(&mut self, thresholds: &Thresholds) -> Result<(), Error<E>> {
+        // in MCPS with 7 fractional bits
+        let limit = (thresholds.signal_kcps as u32 * 128 / 1_000) as u16;
+        self.write_u16(FINAL_RANGE_CONFIG_MIN_COUNT_RATE_RTN_LIMIT, limit)
+    }
+
+    fn set_interrupt_threshold(&mut self, below_mm: Option<u16>) -> Result<(), Error<E>> {
+        match below_mm {
+            Some(below_mm) => {
+                // the sensor doubles the threshold
+                self.write_u16(SYSTEM_THRESH_LOW, (below_mm / 2) & 0x0FFF)?;
+                self.write_u8(SYSTEM_INTERRUPT_CONFIG_GPIO, 0x01)
+            }
+            None => self.write_u8(SYSTEM_INTERRUPT_CONFIG_GPIO, 0x04),
+        }
+    }
+
+    fn settings(&mut self) -> Result<TofSettings, Error<E>> {
+        Ok(TofSettings {
+            distance_mode: self.distance_mode()?,
+            timing_budget_ms: ((self.timing_budget_us + 500) / 1_000) as u16,
+            inter_measurement_ms: self.inter_measurement_ms,
+        })
+    }
+
+    fn calibration(&mut self) -> Result<CalibrationData, Error<E>> {
+        Ok(CalibrationData {
+            offset_mm: decode_offset(self.read_u16(ALGO_PART_TO_PART_RANGE_OFFSET_MM)?),
+            cross_talk_cps: decode_cross_talk(
+                self.read_u16(CROSSTALK_COMPENSATION_PEAK_RATE_MCPS)?,
+            ),
+        })
+    }
+
+    fn set_calibration(&mut self, calibration: &CalibrationData) -> Result<(), Error<E>> {
+        self.set_offset(calibration.offset_mm)?;
+        self.set_cross_talk(calibration.cross_talk_cps)
+    }
+
+    /// The reference SPADs as the enabled ones, the VL53L0X has no region of interest.
+    fn optics(&mut self) -> Result<Optics, Error<E>> {
+        let mut spad_map = [0; 6];
+        self.read_bytes(GLOBAL_CONFIG_SPAD_ENABLES_REF_0, &mut spad_map)?;
+        Ok(Optics {
+            enabled_spads: spad_map.iter().map(|byte| byte.count_ones() as u16).sum(),
+            // with 8 fractional bits
+            effective_spads: self.read_u16(RESULT_RANGE_STATUS + 2)? >> 8,
+            roi_width: 16,
+            roi_height: 16,
+            center_spad: CENTER_SPAD,
+        })
+    }
+
+    fn registers(&mut self) -> Result<Snapshot, Error<E>> {
+        register_dump::read(&register_dump::VL53L0X_REGISTERS, |index, bytes| {
+            self.read_bytes(index as u8, bytes)
+        })
+    }
+
+    fn recalibrate_temperature(&mut self) -> Result<(), Error<E>> {
+        self.stop()?;
+        self.calibrate_reference()?;
+        self.start()
+    }
+
+    fn start_offset_calibration(&mut self, ranging: bool) -> Result<i16, Error<E>> {
+        let previous_offset = decode_offset(self.read_u16(ALGO_PART_TO_PART_RANGE_OFFSET_MM)?);
+        self.set_offset(0)?;
+        if !ranging {
+            self.start()?;
+        }
+        Ok(previous_offset)
+    }
+
+    fn finish_offset_calibration(
+        &mut self,
+        offset: i16,
+        was_ranging: bool,
+    ) -> Result<(), Error<E>> {
+        self.set_offset(offset)?;
+        if !was_ranging {
+            self.stop()?;
+        }
+        Ok(())
+    }
+
+    fn start_cross_talk_calibration(&mut self, ranging: bool) -> Result<(), Error<E>> {
+        self.set_cross_talk(0)?;
+        if !ranging {
+            self.start()?;
+        }
+        Ok(())
+    }
+
+    fn finish_cross_talk_calibration(
+        &mut self,
+        cross_talk_cps: u16,
+        was_ranging: bool,
+    ) -> Result<(), Error<E>> {
+        self.set_cross_talk(cross_talk_cps)?;
+        if !was_ranging {
+            self.stop()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::i2c::{Mock, Transaction};
+    use embedded_hal_mock::MockError;
+    use std::io::ErrorKind;
+    use vl53l1x_uld::DEFAULT_ADDRESS;
+
+    fn write(index: u8, bytes: &[u8]) -> Transaction {
+        let mut data = vec![index];
+        data.extend_from_slice(bytes);
+        Transaction::write(DEFAULT_ADDRESS, data)
+    }
+
+    fn read(index: u8, response: &[u8]) -> Transaction {
+        Transaction::write_read(DEFAULT_ADDRESS, vec![index], response.to_vec())
+    }
+
+    fn error(transaction: Transaction) -> Transaction {
+        transaction.with_error(MockError::Io(ErrorKind::Other))
+    }
+
+    fn run(transactions: &[Transaction], test: impl FnOnce(&mut Vl53l0x<Mock>)) {
+        let mut i2c = Mock::new(transactions);
+        let mut dev = Vl53l0x::new(i2c.clone(), DEFAULT_ADDRESS);
+        test(&mut dev);
+        i2c.done();
+    }
+
+    #[test]
+    fn timeouts_are_encoded_as_mantissa_and_exponent() {
+        assert_eq!(encode_timeout(0), 0);
+        assert_eq!(decode_timeout(encode_timeout(1)), 1);
+        assert_eq!(encode_timeout(300), 0x0195);
+        // the lowest bit of the mantissa is lost
+        assert_eq!(decode_timeout(0x0195), 299);
+        assert_eq!(macro_period_ns(14), 53_384);
+        assert_eq!(timeout_us_to_mclks(timeout_mclks_to_us(600, 10), 10), 600);
+    }
+
+    #[test]
+    fn offset_is_a_twos_complement_in_quarter_millimetres() {
+        assert_eq!(encode_offset(-12), 0x0FD0);
+        assert_eq!(decode_offset(0x0FD0), -12);
+        assert_eq!(decode_offset(encode_offset(25)), 25);
+        assert_eq!(decode_offset(encode_offset(600)), 511);
+        assert_eq!(decode_offset(encode_offset(-600)), -512);
+    }
+
+    #[test]
+    fn cross_talk_is_rounded_to_the_resolution_of_the_sensor() {
+        assert_eq!(encode_cross_talk(1_000), 8);
+        assert_eq!(decode_cross_talk(8), 976);
+        assert_eq!(encode_cross_talk(0), 0);
+    }
+
+    #[test]
+    fn range_status_is_mapped_to_the_one_of_the_vl53l1x() {
+        assert_eq!(range_status(11 << 3), RangeStatus::Valid);
+        assert_eq!(range_status(4 << 3 | 0x01), RangeStatus::SignalFailure);
+        assert_eq!(range_status(2 << 3), RangeStatus::HardwareFailure);
+        assert_eq!(range_status(9 << 3), RangeStatus::OutOfBounds);
+        assert_eq!(range_status(0), RangeStatus::InvalidRange);
+    }
+
+    #[test]
+    fn setup_rejects_an_unknown_model_without_retrying() {
+        let transactions = [read(IDENTIFICATION_MODEL_ID, &[0xEA])];
+        run(&transactions, |dev| {
+            assert!(matches!(
+                dev.setup(None, None),
+                Err(SetupError::UnknownModel(0xEA))
+            ));
+        });
+    }
+
+    #[test]
+    fn read_clears_the_interrupt() {
+        let mut result = [0; 12];
+        result[0] = 11 << 3;
+        // 10 SPADs, 10 MCPS signal, 1 MCPS ambient
+        result[2..4].copy_from_slice(&0x0A00u16.to_be_bytes());
+        result[6..8].copy_from_slice(&1_280u16.to_be_bytes());
+        result[8..10].copy_from_slice(&128u16.to_be_bytes());
+        result[10..12].copy_from_slice(&1_234u16.to_be_bytes());
+        let transactions = [
+            read(RESULT_RANGE_STATUS, &result),
+            write(SYSTEM_INTERRUPT_CLEAR, &[0x01]),
+        ];
+        run(&transactions, |dev| {
+            let reading = dev.read().unwrap();
+            assert_eq!(reading.distance_mm, 1_234);
+            assert_eq!(reading.status, RangeStatus::Valid);
+            assert_eq!(reading.ambient_kcps, 1_000);
+            assert_eq!(reading.signal_per_spad_kcps, 1_000);
+        });
+    }
+
+    #[test]
+    fn read_clears_the_interrupt_after_an_error() {
+        let transactions = [
+            error(read(RESULT_RANGE_STATUS, &[0; 12])),
+            write(SYSTEM_INTERRUPT_CLEAR, &[0x01]),
+        ];
+        run(&transactions, |dev| {
+            assert!(dev.read().is_err());
+        });
+    }
+
+    #[test]
+    fn start_restores_the_stop_variable_and_counts_the_period_in_oscillator_cycles() {
+        let transactions = [
+            write(0x80, &[0x01]),
+            write(PAGE, &[0x01]),
+            write(0x00, &[0x00]),
+            write(0x91, &[0x3C]),
+            write(0x00, &[0x01]),
+            write(PAGE, &[0x00]),
+            write(0x80, &[0x00]),
+            read(OSC_CALIBRATE_VAL, &[0x0B, 0xB8]),
+            write(
+                SYSTEM_INTERMEASUREMENT_PERIOD,
+                &(100u32 * 3_000).to_be_bytes(),
+            ),
+            write(SYSRANGE_START, &[0x04]),
+        ];
+        run(&transactions, |dev| {
+            dev.stop_variable = 0x3C;
+            dev.timing_budget_us = 33_000;
+            assert!(dev.start().is_ok());
+        });
+    }
+
+    #[test]
+    fn period_is_at_least_the_timing_budget() {
+        let transactions = [
+            write(0x80, &[0x01]),
+            write(PAGE, &[0x01]),
+            write(0x00, &[0x00]),
+            write(0x91, &[0x00]),
+            write(0x00, &[0x01]),
+            write(PAGE, &[0x00]),
+            write(0x80, &[0x00]),
+            read(OSC_CALIBRATE_VAL, &[0, 0]),
+            write(SYSTEM_INTERMEASUREMENT_PERIOD, &34u32.to_be_bytes()),
+            write(SYSRANGE_START, &[0x04]),
+        ];
+        run(&transactions, |dev| {
+            dev.timing_budget_us = 33_200;
+            dev.inter_measurement_ms = 20;
+            assert!(dev.start().is_ok());
+        });
+    }
+
+    #[test]
+    fn configure_rejects_a_short_timing_budget() {
+        run(&[], |dev| {
+            let settings = TofSettings {
+                distance_mode: DistanceMode::Short,
+                timing_budget_ms: 15,
+                inter_measurement_ms: 20,
+            };
+            assert!(matches!(
+                dev.configure(&settings),
+                Err(Error::InvalidTimingBudget)
+            ));
+        });
+    }
+
+    #[test]
+    fn interrupt_threshold_is_in_units_of_2_mm() {
+        let transactions = [
+            write(SYSTEM_THRESH_LOW, &250u16.to_be_bytes()),
+            write(SYSTEM_INTERRUPT_CONFIG_GPIO, &[0x01]),
+            write(SYSTEM_INTERRUPT_CONFIG_GPIO, &[0x04]),
+        ];
+        run(&transactions, |dev| {
+            assert!(dev.set_interrupt_threshold(Some(500)).is_ok());
+            assert!(dev.set_interrupt_threshold(None).is_ok());
+        });
+    }
+}
@@ -0,0 +1,156 @@
+//! Driver for a Winbond W25Qxx SPI NOR flash (or a compatible one) connected to SPI3 (`PB3` = SCK,
+//! `PB4` = MISO, `PB5` = MOSI, `PB7` = CS).
+//!
+//! Only the 3 byte addresses are supported, i.e. up to 16 MiB (W25Q128) can be used. The flash is
+//! erased in sectors of [`SECTOR_LEN`] bytes, an erased byte reads as `0xFF` and programming can
+//! only clear bits.
+
+use stm32f4xx_hal::gpio::{Output, PB7};
+use stm32f4xx_hal::pac::SPI3;
+use stm32f4xx_hal::spi::Spi;
+
+#[cfg(feature = "sd-card")]
+compile_error!("the features `nor-flash` and `sd-card` can't be combined as both use SPI3");
+#[cfg(feature = "encoder")]
+compile_error!("the features `nor-flash` and `encoder` can't be combined as both use PB4 & PB5");
+
+/// Length of the smallest unit which can be erased.
+pub const SECTOR_LEN: u32 = 4096;
+/// Length of a page, a single program operation must not cross the end of a page.
+const PAGE_LEN: u32 = 256;
+
+mod cmd {
+    pub const PAGE_PROGRAM: u8 = 0x02;
+    pub const READ_DATA: u8 = 0x03;
+    pub const READ_STATUS_1: u8 = 0x05;
+    pub const WRITE_ENABLE: u8 = 0x06;
+    pub const SECTOR_ERASE: u8 = 0x20;
+    pub const JEDEC_ID: u8 = 0x9F;
+    pub const RELEASE_POWER_DOWN: u8 = 0xAB;
+}
+
+/// A program or erase operation is in progress.
+const STATUS_BUSY: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    Spi,
+    /// The JEDEC id (manufacturer, memory type & capacity) doesn't belong to a supported flash,
+    /// it's probably not connected.
+    UnknownDevice([u8; 3]),
+}
+
+pub struct W25q {
+    spi: Spi<SPI3>,
+    cs: PB7<Output>,
+    /// Size of the flash in bytes.
+    capacity: u32,
+}
+
+impl W25q {
+    /// Wake up the flash (it might have been powered down) & identify it.
+    pub fn new(spi: Spi<SPI3>, cs: PB7<Output>, sysclk_hz: u32) -> Result<Self, Error> {
+        let mut flash = Self {
+            spi,
+            cs,
+            capacity: 0,
+        };
+        flash.cs.set_high();
+        flash.command(&[cmd::RELEASE_POWER_DOWN], &mut [])?;
+        // this runs during init where no timers are available yet, waking up takes 3 us
+        cortex_m::asm::delay(sysclk_hz / 100_000);
+
+        let mut id = [0; 3];
+        flash.command(&[cmd::JEDEC_ID], &mut id)?;
+        // the capacity is encoded as a power of 2, at most 16 MiB can be addressed with 3 bytes
+        flash.capacity = match id[2] {
+            capacity @ 0x10..=0x18 if id[0] != 0x00 && id[0] != 0xFF => 1 << capacity,
+            _ => return Err(Error::UnknownDevice(id)),
+        };
+        defmt::info!(
+            "NOR flash {=[u8]:02x} with {} KiB",
+            id,
+            flash.capacity / 1024
+        );
+        Ok(flash)
+    }
+
+    /// Size of the flash in bytes.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Read from `address`, waits for a program or erase operation to finish first.
+    pub fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error> {
+        self.wait_idle()?;
+        self.command(&with_address(cmd::READ_DATA, address), buffer)
+    }
+
+    /// Program `data` at `address`, which must have been erased. Waits for the previous program
+    /// operation of each page to finish.
+    pub fn write(&mut self, mut address: u32, mut data: &[u8]) -> Result<(), Error> {
+        while !data.is_empty() {
+            let len = data.len().min((PAGE_LEN - address % PAGE_LEN) as usize);
+            self.wait_idle()?;
+            self.command(&[cmd::WRITE_ENABLE], &mut [])?;
+            self.cs.set_low();
+            let result = self
+                .spi
+                .write(&with_address(cmd::PAGE_PROGRAM, address))
+                .and_then(|_| self.spi.write(&data[..len]));
+            self.cs.set_high();
+            result.map_err(|_| Error::Spi)?;
+            address += len as u32;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// Start erasing the sector which contains `address`. This takes up to 400 ms, use
+    /// [`Self::is_busy`] to find out when it's done.
+    pub fn start_erase(&mut self, address: u32) -> Result<(), Error> {
+        self.wait_idle()?;
+        self.command(&[cmd::WRITE_ENABLE], &mut [])?;
+        self.command(
+            &with_address(cmd::SECTOR_ERASE, address - address % SECTOR_LEN),
+            &mut [],
+        )
+    }
+
+    /// Erase the sector which contains `address` & wait for it to finish.
+    #[cfg(feature = "littlefs")]
+    pub fn erase(&mut self, address: u32) -> Result<(), Error> {
+        self.start_erase(address)?;
+        self.wait_idle()
+    }
+
+    /// Whether a program or erase operation is still in progress.
+    pub fn is_busy(&mut self) -> Result<bool, Error> {
+        let mut status = [0];
+        self.command(&[cmd::READ_STATUS_1], &mut status)?;
+        Ok(status[0] & STATUS_BUSY != 0)
+    }
+
+    fn wait_idle(&mut self) -> Result<(), Error> {
+        while self.is_busy()? {}
+        Ok(())
+    }
+
+    /// Send the command & read the response into `response`.
+    fn command(&mut self, command: &[u8], response: &mut [u8]) -> Result<(), Error> {
+        self.cs.set_low();
+        response.fill(0);
+        let result = self
+            .spi
+            .write(command)
+            .and_then(|_| self.spi.transfer_in_place(response));
+        self.cs.set_high();
+        result.map_err(|_| Error::Spi)
+    }
+}
+
+/// The command followed by the address (big endian).
+fn with_address(command: u8, address: u32) -> [u8; 4] {
+    let [_, a2, a1, a0] = address.to_be_bytes();
+    [command, a2, a1, a0]
+}
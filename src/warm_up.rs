@@ -0,0 +1,90 @@
+//! The warm-up of the TOF sensor after the ranging has been started: its first measurements are
+//! systematically off (e.g. while its VCSEL warms up), thus those within [`config::DURATION_MS`]
+//! of the start are flagged as settling (see [`crate::telemetry::Measurement::settling`]). They
+//! are still published & logged, but they don't reach the filters, the outputs & the statistics.
+//!
+//! The warm-up restarts whenever the ranging is started (at boot, with `start` & the user button),
+//! not for each measurement of a single-shot acquisition.
+
+use crate::config::warm_up as config;
+
+pub struct WarmUp {
+    /// The time at which the warm-up ends, `None` once it has ended.
+    until_ms: Option<u32>,
+}
+
+impl WarmUp {
+    pub const fn new() -> Self {
+        Self { until_ms: None }
+    }
+
+    /// Restart the warm-up as the ranging has been started at `now_ms`.
+    pub fn restart(&mut self, now_ms: u32) {
+        self.until_ms =
+            (config::DURATION_MS > 0).then_some(now_ms.wrapping_add(config::DURATION_MS));
+    }
+
+    /// Whether a measurement at `timestamp_ms` is still settling.
+    pub fn is_settling(&mut self, timestamp_ms: u32) -> bool {
+        let Some(until_ms) = self.until_ms else {
+            return false;
+        };
+        // the difference is negative (i.e. huge) as long as the end hasn't been reached
+        let settling = timestamp_ms.wrapping_sub(until_ms) > u32::MAX / 2;
+        if !settling {
+            self.until_ms = None;
+        }
+        settling
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the warm-up is enabled by the configuration.
+    const ENABLED: bool = config::DURATION_MS > 0;
+
+    #[test]
+    fn doesnt_settle_before_the_start() {
+        let mut warm_up = WarmUp::new();
+        assert!(!warm_up.is_settling(0));
+        assert!(!warm_up.is_settling(config::DURATION_MS / 2));
+    }
+
+    #[test]
+    fn settles_until_the_end_of_the_warm_up() {
+        let mut warm_up = WarmUp::new();
+        warm_up.restart(1_000);
+        assert_eq!(warm_up.is_settling(1_000), ENABLED);
+        assert_eq!(
+            warm_up.is_settling(1_000 + config::DURATION_MS / 2),
+            ENABLED
+        );
+        assert!(!warm_up.is_settling(1_000 + config::DURATION_MS));
+    }
+
+    #[test]
+    fn settles_across_the_wrap_around_of_the_time() {
+        let mut warm_up = WarmUp::new();
+        let start_ms = u32::MAX - config::DURATION_MS / 4;
+        warm_up.restart(start_ms);
+        assert_eq!(
+            warm_up.is_settling(start_ms.wrapping_add(config::DURATION_MS / 2)),
+            ENABLED
+        );
+        assert!(!warm_up.is_settling(start_ms.wrapping_add(config::DURATION_MS)));
+    }
+
+    #[test]
+    fn stays_ended_until_the_next_start() {
+        let mut warm_up = WarmUp::new();
+        warm_up.restart(1_000);
+        assert!(!warm_up.is_settling(1_000 + config::DURATION_MS));
+        // e.g. a measurement which has been read out before the end
+        assert!(!warm_up.is_settling(1_000));
+
+        warm_up.restart(5_000);
+        assert_eq!(warm_up.is_settling(5_000), ENABLED);
+    }
+}
@@ -0,0 +1,84 @@
+//! Auditing of the margin of the watchdog feeds: each feed is checked against the time left until
+//! the watchdog would have reset the board (its timeout minus the time since the previous feed). A
+//! warning is logged & counted whenever it's less than
+//! [`crate::config::watchdog::MIN_MARGIN_MS`], thus tasks which block the feeding (e.g. a long
+//! calculation at a higher priority) are noticed before they lead to a reset.
+//!
+//! The watchdog is also restarted while a flash sector is erased (see
+//! [`crate::storage::erase_sector`]), the intervals across an erasure aren't checked. The time
+//! while the microcontroller is stopped (see [`crate::low_power`]) isn't counted by the monotonic
+//! timer.
+
+pub struct WatchdogMargin {
+    timeout_ms: u32,
+    min_margin_ms: u32,
+    /// Time since boot of the previous feed & the number of erasures then.
+    fed: Option<(u32, u32)>,
+    lowest_ms: Option<u32>,
+    low: u32,
+}
+
+impl WatchdogMargin {
+    pub const fn new(timeout_ms: u32, min_margin_ms: u32) -> Self {
+        Self {
+            timeout_ms,
+            min_margin_ms,
+            fed: None,
+            lowest_ms: None,
+            low: 0,
+        }
+    }
+
+    /// The watchdog has been started again with `timeout_ms`, which is a feed as well.
+    pub fn restart(&mut self, now_ms: u32, erasures: u32, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+        self.fed = Some((now_ms, erasures));
+    }
+
+    /// Record a feed at `now_ms` with the number of erasures of flash sectors since boot, returns
+    /// the margin if it's below the minimum.
+    pub fn feed(&mut self, now_ms: u32, erasures: u32) -> Option<u32> {
+        let previous = self.fed.replace((now_ms, erasures));
+        let (fed_ms, _) = previous.filter(|&(_, previous)| previous == erasures)?;
+        let margin_ms = self.timeout_ms.saturating_sub(now_ms.wrapping_sub(fed_ms));
+        self.lowest_ms = Some(
+            self.lowest_ms
+                .map_or(margin_ms, |lowest| lowest.min(margin_ms)),
+        );
+        if margin_ms >= self.min_margin_ms {
+            return None;
+        }
+        self.low = self.low.wrapping_add(1);
+        Some(margin_ms)
+    }
+
+    /// The lowest margin since boot, `None` before the second feed.
+    pub fn lowest_ms(&self) -> Option<u32> {
+        self.lowest_ms
+    }
+
+    /// Number of feeds with a margin below the minimum since boot.
+    pub fn low(&self) -> u32 {
+        self.low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_the_late_feeds() {
+        let mut margin = WatchdogMargin::new(1_000, 500);
+        assert_eq!(margin.feed(0, 0), None);
+        assert_eq!(margin.lowest_ms(), None);
+        assert_eq!(margin.feed(200, 0), None);
+        assert_eq!(margin.feed(900, 0), Some(300));
+        assert_eq!(margin.feed(2_100, 0), Some(0));
+        // the watchdog has been restarted by an erasure in between
+        assert_eq!(margin.feed(5_000, 1), None);
+        margin.restart(5_100, 1, 10_000);
+        assert_eq!(margin.feed(8_000, 1), None);
+        assert_eq!((margin.lowest_ms(), margin.low()), (Some(0), 2));
+    }
+}
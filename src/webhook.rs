@@ -0,0 +1,100 @@
+//! The presence & zone events as ready-to-POST JSON payloads for webhooks, so that a gateway can
+//! forward them (e.g. to a chat or an automation service) without transforming them first. They're
+//! enabled with [`crate::config::webhook::ENABLED`] & sent on all text based links in addition to
+//! the telemetry frames, the WiFi uplink posts them with the content type `application/json`.
+//!
+//! The payload is e.g.
+//! `{"device":"5f3a91c2","event":"zone_entered","zone":1,"value":312,"timestamp_ms":100,"utc_ms":null}`,
+//! `value` is the distance in mm of the measurement which caused the event.
+
+use crate::device_id::DeviceId;
+use crate::event_log::Event;
+use crate::telemetry::Measurement;
+use core::fmt::{self, Write};
+
+/// Maximum length of a payload.
+pub const MAX_PAYLOAD_LEN: usize = 160;
+
+pub type Payload = heapless::String<MAX_PAYLOAD_LEN>;
+
+/// The type of the event in the payload, `None` for the events which aren't sent.
+fn event_type(event: &Event) -> Option<&'static str> {
+    match event {
+        Event::Presence(true) => Some("target_present"),
+        Event::Presence(false) => Some("target_absent"),
+        Event::Zone { entered: true, .. } => Some("zone_entered"),
+        Event::Zone { entered: false, .. } => Some("zone_left"),
+        _ => None,
+    }
+}
+
+/// Format the payload of an event caused by the measurement, `None` if the event isn't sent.
+pub fn payload(
+    event: &Event,
+    measurement: &Measurement,
+    device: DeviceId,
+) -> Option<Result<Payload, fmt::Error>> {
+    let event_type = event_type(event)?;
+    let mut payload = Payload::new();
+    Some(write_payload(&mut payload, event_type, event, measurement, device).map(|()| payload))
+}
+
+fn write_payload(
+    payload: &mut Payload,
+    event_type: &str,
+    event: &Event,
+    measurement: &Measurement,
+    device: DeviceId,
+) -> fmt::Result {
+    write!(
+        payload,
+        "{{\"device\":\"{}\",\"event\":\"{}\"",
+        device, event_type
+    )?;
+    if let Event::Zone { zone, .. } = event {
+        write!(payload, ",\"zone\":{}", zone)?;
+    }
+    write!(
+        payload,
+        ",\"value\":{},\"timestamp_ms\":{},\"utc_ms\":",
+        measurement.distance_mm, measurement.timestamp_ms
+    )?;
+    match measurement.utc_ms {
+        Some(utc_ms) => write!(payload, "{}}}", utc_ms),
+        None => write!(payload, "null}}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test_measurement;
+
+    #[test]
+    fn formats_the_events() {
+        let measurement = Measurement {
+            seq: 1,
+            ..test_measurement(100, 312)
+        };
+        let device = DeviceId(0x5f3a91c2);
+        let zone = Event::Zone {
+            zone: 1,
+            entered: true,
+        };
+        assert_eq!(
+            payload(&zone, &measurement, device).unwrap().unwrap(),
+            r#"{"device":"5f3a91c2","event":"zone_entered","zone":1,"value":312,"timestamp_ms":100,"utc_ms":null}"#
+        );
+        let measurement = Measurement {
+            utc_ms: Some(1_760_000_000_000),
+            ..measurement
+        };
+        assert_eq!(
+            payload(&Event::Presence(false), &measurement, device)
+                .unwrap()
+                .unwrap(),
+            r#"{"device":"5f3a91c2","event":"target_absent","value":312,"timestamp_ms":100,"utc_ms":1760000000000}"#
+        );
+        assert_eq!(payload(&Event::Lens(true), &measurement, device), None);
+    }
+}
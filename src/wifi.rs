@@ -0,0 +1,426 @@
+//! WiFi uplink using an ESP8266/ESP32 module running the default AT command firmware.
+//!
+//! The module is connected to USART1 (`PA9` = TX, `PA10` = RX) and is driven by a non-blocking
+//! state machine: incoming response lines are handled in the UART interrupt and timeouts are
+//! handled by the periodic [`WifiUplink::tick`].
+//!
+//! Once connected, the latest measurement is sent to the configured endpoint (see
+//! [`crate::config::wifi`]) whenever the module is idle. Measurements arriving while a transfer is
+//! still in progress replace the pending one, thus a slow connection never backs up the
+//! application. The [`crate::webhook`] payloads aren't replaced, they're sent before the next
+//! measurement.
+//!
+//! The payload is frozen once its length has been announced with `AT+CIPSEND`, the module expects
+//! exactly that many bytes. Payloads arriving meanwhile are held in a second slot which only takes
+//! over after the transfer has succeeded or failed.
+
+use crate::config::wifi as config;
+use crate::sink::Sink;
+use crate::telemetry::Measurement;
+use crate::uart::BufferedUart;
+use core::fmt::Write;
+use stm32f4xx_hal::pac::USART1;
+
+/// The UART connected to the module.
+pub trait Port {
+    /// Queue the data for sending.
+    fn write(&mut self, bytes: &[u8]);
+    /// Handle the UART interrupt: hand all received bytes to `on_byte`.
+    fn on_interrupt(&mut self, on_byte: impl FnMut(u8));
+}
+
+impl Port for BufferedUart<USART1> {
+    fn write(&mut self, bytes: &[u8]) {
+        BufferedUart::write(self, bytes);
+    }
+
+    fn on_interrupt(&mut self, mut on_byte: impl FnMut(u8)) {
+        BufferedUart::on_interrupt(self, |_, byte| on_byte(byte));
+    }
+}
+
+/// Maximum length of a single response line of the module.
+const MAX_LINE_LEN: usize = 64;
+/// Maximum size of a single payload sent to the endpoint, a telemetry frame with the headers of
+/// the HTTP request.
+const MAX_PAYLOAD_LEN: usize = crate::telemetry::MAX_FRAME_LEN + 128;
+
+/// Time to wait for the module to respond to a normal command.
+const COMMAND_TIMEOUT_MS: u32 = 2_000;
+/// Time to wait for the module to respond to a command which needs network access.
+const NETWORK_TIMEOUT_MS: u32 = 20_000;
+/// Time to wait after a failure before the module is reset.
+const RETRY_DELAY_MS: u32 = 5_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum State {
+    /// Waiting before (re-)starting the module.
+    Backoff,
+    /// The module is rebooting.
+    Resetting,
+    DisablingEcho,
+    SettingStationMode,
+    JoiningNetwork,
+    Connecting,
+    /// Connected to the endpoint, ready to send.
+    Idle,
+    /// Waiting for the `>` prompt after announcing the payload length.
+    AwaitingPrompt,
+    /// Waiting for the module to confirm that the payload has been sent.
+    Sending,
+}
+
+impl State {
+    /// Whether the length of the payload has been announced, thus it mustn't be changed anymore.
+    fn is_frozen(self) -> bool {
+        matches!(self, State::AwaitingPrompt | State::Sending)
+    }
+}
+
+/// A payload waiting to be sent.
+#[derive(Default)]
+struct Slot {
+    data: heapless::String<MAX_PAYLOAD_LEN>,
+    /// Whether `data` hasn't been sent yet.
+    pending: bool,
+    /// Whether `data` is a webhook payload, which isn't replaced by a frame.
+    is_event: bool,
+}
+
+impl Slot {
+    /// Whether the slot holds a webhook payload which hasn't been sent yet.
+    fn holds_event(&self) -> bool {
+        self.pending && self.is_event
+    }
+
+    fn set(&mut self, body: &str, content_type: &str, line_ending: &str, is_event: bool) {
+        self.data.clear();
+        let result = if config::HTTP_PATH.is_empty() {
+            write!(self.data, "{}{}", body, line_ending)
+        } else {
+            write!(
+                self.data,
+                "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+                config::HTTP_PATH,
+                config::HOST,
+                content_type,
+                body.len(),
+                body
+            )
+        };
+        self.pending = result.is_ok();
+        self.is_event = is_event;
+    }
+}
+
+pub struct WifiUplink<P: Port = BufferedUart<USART1>> {
+    uart: P,
+    state: State,
+    /// Time at which the current state has been entered.
+    state_since_ms: u32,
+    /// Time of the last call to [`Self::tick`].
+    now_ms: u32,
+    line: heapless::Vec<u8, MAX_LINE_LEN>,
+    /// The payload which is sent next, frozen while its transfer is in progress.
+    payload: Slot,
+    /// The payload arriving while `payload` is frozen, it replaces `payload` afterwards.
+    next: Slot,
+}
+
+impl<P: Port> WifiUplink<P> {
+    pub fn new(uart: P) -> Self {
+        Self {
+            uart,
+            state: State::Backoff,
+            state_since_ms: 0,
+            now_ms: 0,
+            line: heapless::Vec::new(),
+            payload: Slot::default(),
+            next: Slot::default(),
+        }
+    }
+
+    /// Hand a telemetry frame to the uplink, replacing any frame which hasn't been sent yet.
+    pub fn publish(&mut self, frame: &str) {
+        let slot = self.open_slot();
+        if slot.holds_event() {
+            return;
+        }
+        slot.set(frame, "text/plain", "", false);
+        self.send_if_idle();
+    }
+
+    /// Hand a [`crate::webhook`] payload to the uplink, it replaces any pending frame (but not
+    /// another pending webhook payload).
+    pub fn post_event(&mut self, payload: &str) {
+        let slot = self.open_slot();
+        if slot.holds_event() {
+            defmt::warn!("WiFi: dropping a webhook payload, the previous one is still pending");
+            return;
+        }
+        slot.set(payload, "application/json", "\r\n", true);
+        self.send_if_idle();
+    }
+
+    /// The slot which new payloads are written to.
+    fn open_slot(&mut self) -> &mut Slot {
+        if self.state.is_frozen() {
+            &mut self.next
+        } else {
+            &mut self.payload
+        }
+    }
+
+    fn send_if_idle(&mut self) {
+        if self.state == State::Idle && self.payload.pending {
+            self.send_payload();
+        }
+    }
+
+    /// Handle timeouts. Must be called periodically.
+    pub fn tick(&mut self, now_ms: u32) {
+        self.now_ms = now_ms;
+        let elapsed = now_ms.wrapping_sub(self.state_since_ms);
+        let timeout = match self.state {
+            State::Backoff => {
+                if elapsed >= RETRY_DELAY_MS {
+                    defmt::debug!("WiFi: resetting module");
+                    self.command(State::Resetting, format_args!("AT+RST"));
+                }
+                return;
+            }
+            State::Idle => return,
+            State::Resetting | State::JoiningNetwork | State::Connecting | State::Sending => {
+                NETWORK_TIMEOUT_MS
+            }
+            _ => COMMAND_TIMEOUT_MS,
+        };
+        if elapsed >= timeout {
+            defmt::warn!("WiFi: timeout in state {}", self.state);
+            self.enter(State::Backoff);
+        }
+    }
+
+    /// Handle the UART interrupt.
+    pub fn on_interrupt(&mut self) {
+        let mut line = core::mem::take(&mut self.line);
+        let mut lines = heapless::Vec::<heapless::Vec<u8, MAX_LINE_LEN>, 4>::new();
+        self.uart.on_interrupt(|byte| match byte {
+            b'\r' => {}
+            b'\n' => {
+                if !line.is_empty() {
+                    lines.push(core::mem::take(&mut line)).ok();
+                }
+            }
+            // the prompt for the payload isn't terminated by a line ending
+            b'>' if line.is_empty() => {
+                lines.push(heapless::Vec::from_slice(b">").unwrap()).ok();
+            }
+            byte => {
+                line.push(byte).ok();
+            }
+        });
+        self.line = line;
+
+        for line in lines {
+            if let Ok(line) = core::str::from_utf8(&line) {
+                self.on_line(line);
+            }
+        }
+    }
+
+    fn on_line(&mut self, line: &str) {
+        defmt::trace!("WiFi: received '{}' in state {}", line, self.state);
+
+        if line == "CLOSED"
+            && matches!(
+                self.state,
+                State::Idle | State::AwaitingPrompt | State::Sending
+            )
+        {
+            defmt::warn!("WiFi: connection closed by endpoint, reconnecting");
+            self.connect();
+            return;
+        }
+        if line == "ERROR" || line == "FAIL" || line == "SEND FAIL" {
+            defmt::warn!("WiFi: command failed in state {}", self.state);
+            self.enter(State::Backoff);
+            return;
+        }
+
+        match (self.state, line) {
+            (State::Resetting, "ready") => self.command(State::DisablingEcho, format_args!("ATE0")),
+            (State::DisablingEcho, "OK") => {
+                self.command(State::SettingStationMode, format_args!("AT+CWMODE=1"))
+            }
+            (State::SettingStationMode, "OK") => self.command(
+                State::JoiningNetwork,
+                format_args!("AT+CWJAP=\"{}\",\"{}\"", config::SSID, config::PASSWORD),
+            ),
+            (State::JoiningNetwork, "OK") => {
+                defmt::info!("WiFi: joined network {}", config::SSID);
+                self.connect();
+            }
+            (State::Connecting, "OK" | "ALREADY CONNECTED") => {
+                defmt::info!("WiFi: connected to {}:{}", config::HOST, config::PORT);
+                self.enter(State::Idle);
+                self.send_if_idle();
+            }
+            (State::AwaitingPrompt, ">") => {
+                self.uart.write(self.payload.data.as_bytes());
+                self.payload.pending = false;
+                self.enter(State::Sending);
+            }
+            (State::Sending, "SEND OK") => {
+                self.enter(State::Idle);
+                self.send_if_idle();
+            }
+            // everything else (status messages, received data, ...) is of no interest
+            _ => {}
+        }
+    }
+
+    fn connect(&mut self) {
+        self.command(
+            State::Connecting,
+            format_args!("AT+CIPSTART=\"TCP\",\"{}\",{}", config::HOST, config::PORT),
+        );
+    }
+
+    fn send_payload(&mut self) {
+        let len = self.payload.data.len();
+        self.command(State::AwaitingPrompt, format_args!("AT+CIPSEND={}", len));
+    }
+
+    /// Send a command to the module and enter the state in which its response is awaited.
+    fn command(&mut self, next: State, command: core::fmt::Arguments) {
+        let mut buffer = heapless::String::<MAX_PAYLOAD_LEN>::new();
+        if write!(buffer, "{}\r\n", command).is_err() {
+            defmt::error!("WiFi: command too long");
+            self.enter(State::Backoff);
+            return;
+        }
+        self.uart.write(buffer.as_bytes());
+        self.enter(next);
+    }
+
+    fn enter(&mut self, state: State) {
+        self.state = state;
+        self.state_since_ms = self.now_ms;
+        if !state.is_frozen() {
+            self.take_next();
+        }
+    }
+
+    /// Let the payload which arrived during the transfer replace the current one, unless that's a
+    /// webhook payload which still has to be sent.
+    fn take_next(&mut self) {
+        if self.next.pending && !self.payload.holds_event() {
+            core::mem::swap(&mut self.payload, &mut self.next);
+            self.next.pending = false;
+        }
+    }
+}
+
+impl<P: Port> Sink for WifiUplink<P> {
+    fn measurement(&mut self, _measurement: &Measurement, frame: &str) {
+        self.publish(frame);
+    }
+
+    fn batch(&mut self, frame: &str) {
+        self.publish(frame);
+    }
+
+    fn frame(&mut self, frame: &str) {
+        self.publish(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records all data written to the module.
+    #[derive(Default)]
+    struct Recorder(Vec<u8>);
+
+    impl Port for Recorder {
+        fn write(&mut self, bytes: &[u8]) {
+            self.0.extend_from_slice(bytes);
+        }
+
+        fn on_interrupt(&mut self, _on_byte: impl FnMut(u8)) {}
+    }
+
+    fn connected() -> WifiUplink<Recorder> {
+        let mut uplink = WifiUplink::new(Recorder::default());
+        uplink.enter(State::Connecting);
+        uplink.on_line("OK");
+        assert_eq!(uplink.state, State::Idle);
+        uplink.uart.0.clear();
+        uplink
+    }
+
+    /// The length announced by the last `AT+CIPSEND` & the data written after it.
+    fn announced_and_written(uplink: &WifiUplink<Recorder>) -> (usize, Vec<u8>) {
+        let written = &uplink.uart.0;
+        let command = b"AT+CIPSEND=";
+        let start = written
+            .windows(command.len())
+            .rposition(|window| window == command)
+            .expect("no payload announced")
+            + command.len();
+        let end = start + written[start..].iter().position(|&b| b == b'\r').unwrap();
+        let len = core::str::from_utf8(&written[start..end])
+            .unwrap()
+            .parse()
+            .unwrap();
+        (len, written[end + 2..].to_vec())
+    }
+
+    #[test]
+    fn payload_is_frozen_once_announced() {
+        let mut uplink = connected();
+        uplink.publish("1234 mm");
+        assert_eq!(uplink.state, State::AwaitingPrompt);
+
+        uplink.publish("a considerably longer frame");
+        uplink.post_event("{\"event\":\"alarm\"}");
+        uplink.on_line(">");
+        let (len, written) = announced_and_written(&uplink);
+        assert_eq!(written.len(), len);
+        assert_eq!(written, b"1234 mm");
+
+        // the webhook payload which arrived meanwhile is sent next
+        uplink.on_line("SEND OK");
+        assert_eq!(uplink.state, State::AwaitingPrompt);
+        uplink.on_line(">");
+        let (len, written) = announced_and_written(&uplink);
+        assert_eq!(written.len(), len);
+        assert_eq!(written, b"{\"event\":\"alarm\"}\r\n");
+
+        uplink.on_line("SEND OK");
+        assert_eq!(uplink.state, State::Idle);
+    }
+
+    #[test]
+    fn next_payload_takes_over_after_failure() {
+        let mut uplink = connected();
+        uplink.post_event("{\"event\":\"alarm\"}");
+        uplink.publish("1234 mm");
+        uplink.on_line(">");
+        uplink.on_line("SEND FAIL");
+        assert_eq!(uplink.state, State::Backoff);
+        assert!(uplink.payload.pending);
+        assert_eq!(uplink.payload.data, "1234 mm");
+
+        // a pending webhook payload isn't replaced by a frame after a timeout either
+        let mut uplink = connected();
+        uplink.post_event("{\"event\":\"alarm\"}");
+        uplink.publish("1234 mm");
+        uplink.tick(COMMAND_TIMEOUT_MS);
+        assert_eq!(uplink.state, State::Backoff);
+        assert!(uplink.payload.holds_event());
+        assert!(uplink.next.pending);
+    }
+}
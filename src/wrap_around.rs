@@ -0,0 +1,109 @@
+//! Handling of the wrap-around ambiguity of the TOF sensor: a target beyond the maximum range
+//! which reflects enough light can be reported at a bogus short distance, as the phase of the
+//! returned signal has wrapped around.
+//!
+//! A measurement is considered wrapped if the sensor reports it as such
+//! ([`RangeStatus::Wraparound`]) or if it's valid but closer than the previous valid measurement
+//! by more than [`config::MAX_JUMP_MM`]. Such a jump is only accepted as a real target once it
+//! has been confirmed by [`CONFIRM_SAMPLES`] measurements in a row at about the same distance.
+//! Wrapped measurements are reported with the status [`RangeStatus::Wraparound`] (so that the
+//! outputs ignore them) or dropped if [`config::SUPPRESS`] is enabled.
+
+use crate::config::wrap_around as config;
+use crate::range_sensor::Reading;
+use vl53l1x_uld::RangeStatus;
+
+/// Number of measurements in a row which confirm a jump.
+pub const CONFIRM_SAMPLES: u8 = 3;
+/// The maximum difference of the measurements which confirm a jump.
+const TOLERANCE_MM: u16 = 100;
+
+pub struct WrapCheck {
+    /// The latest plausible valid distance.
+    reference_mm: Option<u16>,
+    /// The distance after the jump & the number of measurements which confirmed it.
+    suspect_mm: u16,
+    count: u8,
+}
+
+impl WrapCheck {
+    pub fn new() -> Self {
+        Self {
+            reference_mm: None,
+            suspect_mm: 0,
+            count: 0,
+        }
+    }
+
+    /// Check a measurement, returns whether it's (probably) wrapped.
+    pub fn is_wrapped(&mut self, reading: &Reading) -> bool {
+        match reading.status {
+            RangeStatus::Wraparound => return true,
+            RangeStatus::Valid => {}
+            _ => return false,
+        }
+        let distance_mm = reading.distance_mm;
+        let jumped = self.reference_mm.is_some_and(|reference_mm| {
+            reference_mm.saturating_sub(distance_mm) > config::MAX_JUMP_MM
+        });
+        if jumped {
+            if self.count > 0 && self.suspect_mm.abs_diff(distance_mm) <= TOLERANCE_MM {
+                self.count += 1;
+            } else {
+                self.suspect_mm = distance_mm;
+                self.count = 1;
+            }
+            if self.count < CONFIRM_SAMPLES {
+                return true;
+            }
+        }
+        // a target which has really appeared in front of the sensor after the confirmation
+        self.reference_mm = Some(distance_mm);
+        self.count = 0;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(distance_mm: u16, status: RangeStatus) -> Reading {
+        Reading {
+            distance_mm,
+            status,
+            ambient_kcps: 0,
+            signal_per_spad_kcps: 0,
+        }
+    }
+
+    #[test]
+    fn status_is_checked() {
+        let mut check = WrapCheck::new();
+        assert!(check.is_wrapped(&reading(120, RangeStatus::Wraparound)));
+        assert!(!check.is_wrapped(&reading(120, RangeStatus::SignalFailure)));
+        assert!(!check.is_wrapped(&reading(120, RangeStatus::Valid)));
+    }
+
+    #[test]
+    fn implausible_jump_is_wrapped() {
+        let mut check = WrapCheck::new();
+        assert!(!check.is_wrapped(&reading(3_500, RangeStatus::Valid)));
+        assert!(check.is_wrapped(&reading(200, RangeStatus::Valid)));
+        // gradual changes & jumps away from the sensor are plausible
+        assert!(!check.is_wrapped(&reading(3_000, RangeStatus::Valid)));
+        assert!(!check.is_wrapped(&reading(1_600, RangeStatus::Valid)));
+        assert!(!check.is_wrapped(&reading(3_900, RangeStatus::Valid)));
+    }
+
+    #[test]
+    fn confirmed_jump_is_accepted() {
+        let mut check = WrapCheck::new();
+        check.is_wrapped(&reading(3_500, RangeStatus::Valid));
+        let wrapped: Vec<_> = [300, 320, 310, 305]
+            .iter()
+            .map(|distance_mm| check.is_wrapped(&reading(*distance_mm, RangeStatus::Valid)))
+            .collect();
+        assert_eq!(wrapped, [true, true, false, false]);
+    }
+}
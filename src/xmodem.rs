@@ -0,0 +1,875 @@
+//! XMODEM & YMODEM sender, so that the stored measurements can be downloaded over the virtual COM
+//! port with any terminal program (e.g. Tera Term, minicom with `rb`/`rx` of lrzsz), & a YMODEM
+//! receiver for the [`crate::firmware_update`].
+//!
+//! The downloaded file contains the lines of the `dump` command. XMODEM uses blocks of 128 bytes
+//! with a CRC-16 (if the receiver starts with `C`) or the original checksum (if it starts with
+//! NAK), YMODEM uses blocks of 1 KiB with a CRC-16 & announces the file name. The size isn't known
+//! in advance as the log keeps growing, thus the last block is padded with [`PAD`].
+//!
+//! An interrupted download can be resumed by starting a new one at the number of bytes which have
+//! been received (without the padding), the part of the log before it is skipped. This only works
+//! as long as the oldest measurements haven't been deleted in the meantime.
+//!
+//! The receiver only accepts a single file per batch & relies on the size announced in the header
+//! to strip the padding of the last block.
+
+#[cfg(feature = "mqtt-sn")]
+compile_error!(
+    "the features `xmodem`/`firmware-update` and `mqtt-sn` can't be combined as they use the \
+     virtual COM port"
+);
+
+/// Start of a block of 128 bytes.
+const SOH: u8 = 0x01;
+/// Start of a block of 1 KiB.
+const STX: u8 = 0x02;
+/// End of transmission.
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+/// Cancels the transfer if it's received twice in a row.
+const CAN: u8 = 0x18;
+/// Fills the rest of the last block.
+#[cfg(feature = "xmodem")]
+pub const PAD: u8 = 0x1A;
+
+/// Name of the file announced by YMODEM.
+#[cfg(feature = "xmodem")]
+const FILE_NAME: &[u8] = b"log.csv";
+/// Time to wait for the receiver to be started.
+const START_TIMEOUT_MS: u32 = 60_000;
+/// Time to wait for the response to a block before it's sent again.
+const ACK_TIMEOUT_MS: u32 = 10_000;
+/// Number of times a block is sent again before the transfer is aborted.
+const MAX_RETRIES: u8 = 10;
+/// Length of the longest packet: header, block of 1 KiB & CRC.
+const MAX_PACKET_LEN: usize = 3 + 1024 + 2;
+/// Interval in which the receiver requests the file until the sender has been started.
+#[cfg(feature = "firmware-update")]
+const REQUEST_INTERVAL_MS: u32 = 3_000;
+
+#[cfg(feature = "xmodem")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Protocol {
+    Xmodem,
+    Ymodem,
+}
+
+#[cfg(feature = "xmodem")]
+impl Protocol {
+    fn block_len(&self) -> usize {
+        match self {
+            Protocol::Xmodem => 128,
+            Protocol::Ymodem => 1024,
+        }
+    }
+}
+
+/// A download requested by the host.
+#[cfg(feature = "xmodem")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Request {
+    pub protocol: Protocol,
+    /// Number of bytes of the file which are skipped, to resume an interrupted download.
+    pub offset: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    /// The other side hasn't been started or stopped responding.
+    Timeout,
+    /// The other side cancelled the transfer.
+    Cancelled,
+    /// A block has been rejected too often.
+    TooManyRetries,
+    /// The announced file doesn't fit into the storage.
+    #[cfg(feature = "firmware-update")]
+    TooLarge,
+    /// The received data couldn't be stored.
+    #[cfg(feature = "firmware-update")]
+    Write,
+}
+
+impl Error {
+    /// Human readable description, used in the `ERR` response.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::Timeout => "transfer timed out",
+            Error::Cancelled => "transfer cancelled",
+            Error::TooManyRetries => "transfer failed",
+            #[cfg(feature = "firmware-update")]
+            Error::TooLarge => "file too large",
+            #[cfg(feature = "firmware-update")]
+            Error::Write => "storage failed",
+        }
+    }
+}
+
+#[cfg(feature = "xmodem")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for the receiver to request the file (YMODEM: the header).
+    Start,
+    /// The header (YMODEM block 0) has been sent.
+    Header,
+    /// Waiting for the receiver to request the data after the header.
+    DataStart,
+    /// A data block has been sent.
+    Data,
+    /// The end of the file has been sent.
+    Eot,
+    /// Waiting for the receiver to request the next file of the batch (YMODEM).
+    FinalStart,
+    /// The empty header which ends the batch has been sent (YMODEM).
+    Final,
+    Done(Result<(), Error>),
+}
+
+#[cfg(feature = "xmodem")]
+pub struct Sender {
+    protocol: Protocol,
+    state: State,
+    /// Whether the receiver asked for a CRC-16 instead of the checksum.
+    crc: bool,
+    /// Number of the last data block, wraps around.
+    block: u8,
+    /// The packet which is being sent, kept to send it again.
+    packet: heapless::Vec<u8, MAX_PACKET_LEN>,
+    /// Number of bytes of the packet which have been handed to the link.
+    sent: usize,
+    /// Number of data bytes in the current block, without the padding.
+    data_len: usize,
+    retries: u8,
+    /// Number of CAN which have been received in a row.
+    cancels: u8,
+    /// Time at which the current packet has been sent or the waiting has been started, `None` until
+    /// the first poll.
+    since_ms: Option<u32>,
+    /// Number of data bytes which have been acknowledged.
+    bytes: u32,
+}
+
+#[cfg(feature = "xmodem")]
+impl Sender {
+    pub fn new(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            state: State::Start,
+            crc: true,
+            block: 0,
+            packet: heapless::Vec::new(),
+            sent: 0,
+            data_len: 0,
+            retries: 0,
+            cancels: 0,
+            since_ms: None,
+            bytes: 0,
+        }
+    }
+
+    /// The result once the transfer has finished & everything has been sent.
+    pub fn result(&self) -> Option<Result<(), Error>> {
+        match self.state {
+            State::Done(result) if self.pending().is_empty() => Some(result),
+            _ => None,
+        }
+    }
+
+    /// Number of data bytes which have been acknowledged by the receiver.
+    pub fn bytes(&self) -> u32 {
+        self.bytes
+    }
+
+    /// Handle the bytes received from the receiver & the timeouts. `source` fills the buffer with
+    /// the next data of the file & returns its length, `0` at the end of the file.
+    pub fn poll(
+        &mut self,
+        now_ms: u32,
+        received: &[u8],
+        source: &mut impl FnMut(&mut [u8]) -> usize,
+    ) {
+        for &byte in received {
+            if matches!(self.state, State::Done(_)) {
+                return;
+            }
+            self.on_byte(byte, now_ms, source);
+        }
+        let elapsed_ms = now_ms.wrapping_sub(*self.since_ms.get_or_insert(now_ms));
+        match self.state {
+            State::Start | State::DataStart | State::FinalStart
+                if elapsed_ms > START_TIMEOUT_MS =>
+            {
+                self.fail(Error::Timeout)
+            }
+            State::Header | State::Data | State::Eot | State::Final
+                if elapsed_ms > ACK_TIMEOUT_MS =>
+            {
+                self.retry(now_ms)
+            }
+            _ => {}
+        }
+    }
+
+    /// The bytes of the current packet which still have to be sent.
+    pub fn pending(&self) -> &[u8] {
+        &self.packet[self.sent..]
+    }
+
+    /// Mark `len` bytes of [`Self::pending`] as sent.
+    pub fn advance(&mut self, len: usize) {
+        self.sent = (self.sent + len).min(self.packet.len());
+    }
+
+    fn on_byte(&mut self, byte: u8, now_ms: u32, source: &mut impl FnMut(&mut [u8]) -> usize) {
+        if byte == CAN {
+            self.cancels += 1;
+            if self.cancels >= 2 {
+                defmt::info!("xmodem: cancelled by the receiver");
+                self.state = State::Done(Err(Error::Cancelled));
+                self.packet.clear();
+                self.sent = 0;
+            }
+            return;
+        }
+        self.cancels = 0;
+        match (self.state, byte) {
+            (State::Start, b'C') => self.begin(now_ms, source),
+            (State::Start, NAK) if self.protocol == Protocol::Xmodem => {
+                self.crc = false;
+                self.begin(now_ms, source);
+            }
+            (State::Header, ACK) => {
+                self.state = State::DataStart;
+                self.since_ms = Some(now_ms);
+            }
+            (State::DataStart, b'C') => self.send_data(now_ms, source),
+            (State::Data, ACK) => {
+                self.bytes += self.data_len as u32;
+                self.send_data(now_ms, source);
+            }
+            (State::Eot, ACK) if self.protocol == Protocol::Ymodem => {
+                self.state = State::FinalStart;
+                self.since_ms = Some(now_ms);
+            }
+            (State::Eot, ACK) | (State::Final, ACK) => {
+                defmt::info!("xmodem: sent {} bytes", self.bytes);
+                self.state = State::Done(Ok(()));
+            }
+            (State::FinalStart, b'C') => {
+                // a header without a file name ends the batch
+                self.send_packet(0, &[0; 128], now_ms);
+                self.state = State::Final;
+            }
+            (State::Header | State::Data | State::Eot | State::Final, NAK) => self.retry(now_ms),
+            // e.g. repeated requests of the receiver before it got the first block
+            _ => {}
+        }
+    }
+
+    fn begin(&mut self, now_ms: u32, source: &mut impl FnMut(&mut [u8]) -> usize) {
+        defmt::info!(
+            "xmodem: receiver started ({}, crc={})",
+            self.protocol,
+            self.crc
+        );
+        match self.protocol {
+            Protocol::Xmodem => self.send_data(now_ms, source),
+            Protocol::Ymodem => {
+                // the file name followed by the (unknown) size
+                let mut header = [0; 128];
+                header[..FILE_NAME.len()].copy_from_slice(FILE_NAME);
+                self.send_packet(0, &header, now_ms);
+                self.state = State::Header;
+            }
+        }
+    }
+
+    fn send_data(&mut self, now_ms: u32, source: &mut impl FnMut(&mut [u8]) -> usize) {
+        let mut data = [PAD; 1024];
+        let data = &mut data[..self.protocol.block_len()];
+        self.data_len = source(data);
+        if self.data_len == 0 {
+            self.packet.clear();
+            self.packet.push(EOT).ok();
+            self.sent = 0;
+            self.retries = 0;
+            self.since_ms = Some(now_ms);
+            self.state = State::Eot;
+            return;
+        }
+        self.block = self.block.wrapping_add(1);
+        self.send_packet(self.block, data, now_ms);
+        self.state = State::Data;
+    }
+
+    fn send_packet(&mut self, block: u8, data: &[u8], now_ms: u32) {
+        self.packet.clear();
+        self.packet
+            .push(if data.len() == 128 { SOH } else { STX })
+            .ok();
+        self.packet.extend_from_slice(&[block, !block]).ok();
+        self.packet.extend_from_slice(data).ok();
+        if self.crc {
+            self.packet
+                .extend_from_slice(&crc16(data).to_be_bytes())
+                .ok();
+        } else {
+            let checksum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            self.packet.push(checksum).ok();
+        }
+        self.sent = 0;
+        self.retries = 0;
+        self.since_ms = Some(now_ms);
+    }
+
+    /// Send the current packet again.
+    fn retry(&mut self, now_ms: u32) {
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            self.fail(Error::TooManyRetries);
+            return;
+        }
+        defmt::debug!("xmodem: sending block {} again", self.block);
+        self.sent = 0;
+        self.since_ms = Some(now_ms);
+    }
+
+    /// Abort the transfer, the receiver is told so as well.
+    fn fail(&mut self, error: Error) {
+        defmt::warn!("xmodem: aborting: {}", error);
+        self.packet.clear();
+        self.packet.extend_from_slice(&[CAN, CAN]).ok();
+        self.sent = 0;
+        self.state = State::Done(Err(error));
+    }
+}
+
+#[cfg(feature = "firmware-update")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReceiveState {
+    /// Requesting the header (block 0) until the sender has been started.
+    Start,
+    /// Receiving the data blocks until the end of the file.
+    Data,
+    /// Requesting the next header, which must be the empty one ending the batch.
+    Final,
+    /// The result & the number of received bytes.
+    Done(Result<u32, Error>),
+}
+
+/// YMODEM receiver of a single file, see [`crate::firmware_update`].
+#[cfg(feature = "firmware-update")]
+pub struct Receiver {
+    state: ReceiveState,
+    /// Maximum size of the file.
+    max_size: u32,
+    /// The size announced in the header.
+    size: u32,
+    /// Number of the next data block, wraps around.
+    block: u8,
+    /// The packet which is being received.
+    packet: heapless::Vec<u8, MAX_PACKET_LEN>,
+    /// The response which is being sent.
+    response: heapless::Vec<u8, 2>,
+    /// Number of bytes of the response which have been handed to the link.
+    sent: usize,
+    retries: u8,
+    /// Number of CAN which have been received in a row.
+    cancels: u8,
+    /// Time at which the last response has been sent, `None` until the first poll.
+    since_ms: Option<u32>,
+    /// Number of data bytes which have been stored.
+    bytes: u32,
+}
+
+#[cfg(feature = "firmware-update")]
+impl Receiver {
+    pub fn new(max_size: u32) -> Self {
+        Self {
+            state: ReceiveState::Start,
+            max_size,
+            size: 0,
+            block: 0,
+            packet: heapless::Vec::new(),
+            response: heapless::Vec::new(),
+            sent: 0,
+            retries: 0,
+            cancels: 0,
+            since_ms: None,
+            bytes: 0,
+        }
+    }
+
+    /// The number of received bytes once the transfer has finished & everything has been sent.
+    pub fn result(&self) -> Option<Result<u32, Error>> {
+        match self.state {
+            ReceiveState::Done(result) if self.pending().is_empty() => Some(result),
+            _ => None,
+        }
+    }
+
+    /// Handle the bytes received from the sender & the timeouts. `sink` stores the data at the
+    /// offset in the file & returns whether that succeeded.
+    pub fn poll(
+        &mut self,
+        now_ms: u32,
+        received: &[u8],
+        sink: &mut impl FnMut(u32, &[u8]) -> bool,
+    ) {
+        if self.since_ms.is_none() {
+            self.respond(b"C", now_ms);
+        }
+        for &byte in received {
+            if matches!(self.state, ReceiveState::Done(_)) {
+                return;
+            }
+            self.on_byte(byte, now_ms, sink);
+        }
+        let elapsed_ms = now_ms.wrapping_sub(self.since_ms.unwrap_or(now_ms));
+        match self.state {
+            ReceiveState::Start | ReceiveState::Final if elapsed_ms > REQUEST_INTERVAL_MS => {
+                self.retry(b'C', now_ms)
+            }
+            ReceiveState::Data if elapsed_ms > ACK_TIMEOUT_MS => self.retry(NAK, now_ms),
+            _ => {}
+        }
+    }
+
+    /// The bytes of the current response which still have to be sent.
+    pub fn pending(&self) -> &[u8] {
+        &self.response[self.sent..]
+    }
+
+    /// Mark `len` bytes of [`Self::pending`] as sent.
+    pub fn advance(&mut self, len: usize) {
+        self.sent = (self.sent + len).min(self.response.len());
+    }
+
+    fn on_byte(&mut self, byte: u8, now_ms: u32, sink: &mut impl FnMut(u32, &[u8]) -> bool) {
+        if self.packet.is_empty() {
+            match byte {
+                CAN => {
+                    self.cancels += 1;
+                    if self.cancels >= 2 {
+                        defmt::info!("ymodem: cancelled by the sender");
+                        self.state = ReceiveState::Done(Err(Error::Cancelled));
+                        self.response.clear();
+                        self.sent = 0;
+                    }
+                    return;
+                }
+                EOT if matches!(self.state, ReceiveState::Data | ReceiveState::Final) => {
+                    // the next header ends the batch
+                    self.respond(&[ACK, b'C'], now_ms);
+                    self.state = ReceiveState::Final;
+                    return;
+                }
+                SOH | STX => {}
+                // e.g. the rest of a corrupted packet
+                _ => return,
+            }
+        }
+        self.cancels = 0;
+        self.packet.push(byte).ok();
+        let block_len = if self.packet[0] == SOH { 128 } else { 1024 };
+        if self.packet.len() == 3 + block_len + 2 {
+            self.on_packet(block_len, now_ms, sink);
+            self.packet.clear();
+        }
+    }
+
+    fn on_packet(
+        &mut self,
+        block_len: usize,
+        now_ms: u32,
+        sink: &mut impl FnMut(u32, &[u8]) -> bool,
+    ) {
+        let (block, data, crc) = (
+            self.packet[1],
+            &self.packet[3..3 + block_len],
+            &self.packet[3 + block_len..],
+        );
+        if self.packet[2] != !block || crc16(data).to_be_bytes() != crc {
+            defmt::debug!("ymodem: corrupt block {}", block);
+            self.retry(NAK, now_ms);
+            return;
+        }
+        match self.state {
+            ReceiveState::Start if block == 0 => {
+                // the file name & the size (followed by optional fields)
+                let mut fields = data.split(|byte| *byte == 0);
+                let name = fields.next().unwrap_or_default();
+                let size = fields
+                    .next()
+                    .and_then(|field| field.split(|byte| *byte == b' ').next())
+                    .and_then(|size| core::str::from_utf8(size).ok())
+                    .and_then(|size| size.parse().ok());
+                match size {
+                    _ if name.is_empty() => self.fail(Error::Cancelled),
+                    Some(size) if size <= self.max_size => {
+                        defmt::info!("ymodem: receiving {} bytes", size);
+                        self.size = size;
+                        self.block = 1;
+                        self.respond(&[ACK, b'C'], now_ms);
+                        self.state = ReceiveState::Data;
+                    }
+                    _ => self.fail(Error::TooLarge),
+                }
+            }
+            ReceiveState::Data if block == self.block => {
+                let len = data.len().min((self.size - self.bytes) as usize);
+                if !sink(self.bytes, &data[..len]) {
+                    self.fail(Error::Write);
+                    return;
+                }
+                self.bytes += len as u32;
+                self.block = self.block.wrapping_add(1);
+                self.respond(&[ACK], now_ms);
+            }
+            // the sender didn't get the acknowledgement
+            ReceiveState::Data if block == 0 && self.block == 1 => {
+                self.respond(&[ACK, b'C'], now_ms)
+            }
+            ReceiveState::Data if block == self.block.wrapping_sub(1) => {
+                self.respond(&[ACK], now_ms)
+            }
+            ReceiveState::Final if block == 0 => {
+                defmt::info!("ymodem: received {} bytes", self.bytes);
+                self.respond(&[ACK], now_ms);
+                self.state = ReceiveState::Done(Ok(self.bytes));
+            }
+            _ => self.retry(NAK, now_ms),
+        }
+    }
+
+    fn respond(&mut self, response: &[u8], now_ms: u32) {
+        self.response.clear();
+        self.response.extend_from_slice(response).ok();
+        self.sent = 0;
+        self.retries = 0;
+        self.since_ms = Some(now_ms);
+    }
+
+    /// Send the request again or reject the current packet.
+    fn retry(&mut self, response: u8, now_ms: u32) {
+        let max_retries = if self.state == ReceiveState::Start {
+            (START_TIMEOUT_MS / REQUEST_INTERVAL_MS) as u8
+        } else {
+            MAX_RETRIES
+        };
+        let retries = self.retries + 1;
+        if retries > max_retries && self.state == ReceiveState::Final {
+            // the file is complete, only the end of the batch is missing
+            self.state = ReceiveState::Done(Ok(self.bytes));
+            return;
+        }
+        if retries > max_retries {
+            self.fail(if self.state == ReceiveState::Start {
+                Error::Timeout
+            } else {
+                Error::TooManyRetries
+            });
+            return;
+        }
+        self.packet.clear();
+        self.respond(&[response], now_ms);
+        self.retries = retries;
+    }
+
+    /// Abort the transfer, the sender is told so as well.
+    fn fail(&mut self, error: Error) {
+        defmt::warn!("ymodem: aborting: {}", error);
+        self.respond(&[CAN, CAN], 0);
+        self.state = ReceiveState::Done(Err(error));
+    }
+}
+
+/// CRC-16/XMODEM (polynomial `0x1021`, initial value `0`).
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ (*byte as u16) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_the_crc16() {
+        assert_eq!(crc16(b""), 0);
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+
+    /// Poll the sender with the response of the receiver, the file is read from `data`. Returns
+    /// the bytes sent by the sender.
+    #[cfg(feature = "xmodem")]
+    fn send(sender: &mut Sender, now_ms: u32, received: &[u8], data: &mut &[u8]) -> Vec<u8> {
+        sender.poll(now_ms, received, &mut |buffer| {
+            let len = buffer.len().min(data.len());
+            buffer[..len].copy_from_slice(&data[..len]);
+            *data = &data[len..];
+            len
+        });
+        let sent = sender.pending().to_vec();
+        sender.advance(sent.len());
+        sent
+    }
+
+    #[cfg(feature = "xmodem")]
+    #[test]
+    fn sends_xmodem_blocks_with_a_crc() {
+        let file = [b'a'; 200];
+        let mut data = &file[..];
+        let mut sender = Sender::new(Protocol::Xmodem);
+        assert_eq!(send(&mut sender, 0, b"", &mut data), b"");
+
+        let block = send(&mut sender, 10, b"C", &mut data);
+        assert_eq!(block.len(), 3 + 128 + 2);
+        assert_eq!(block[..3], [SOH, 1, 0xFE]);
+        assert_eq!(block[3..131], file[..128]);
+        assert_eq!(block[131..], crc16(&file[..128]).to_be_bytes());
+
+        let block = send(&mut sender, 20, &[ACK], &mut data);
+        assert_eq!(block[..3], [SOH, 2, 0xFD]);
+        assert_eq!(block[3..75], file[128..]);
+        assert!(block[75..131].iter().all(|&byte| byte == PAD));
+
+        assert_eq!(send(&mut sender, 30, &[ACK], &mut data), [EOT]);
+        assert_eq!(sender.result(), None);
+        assert_eq!(send(&mut sender, 40, &[ACK], &mut data), b"");
+        assert_eq!(sender.result(), Some(Ok(())));
+        assert_eq!(sender.bytes(), 200);
+    }
+
+    #[cfg(feature = "xmodem")]
+    #[test]
+    fn sends_the_checksum_when_started_with_a_nak() {
+        let mut data = &b"abc"[..];
+        let mut sender = Sender::new(Protocol::Xmodem);
+        let block = send(&mut sender, 0, &[NAK], &mut data);
+        assert_eq!(block.len(), 3 + 128 + 1);
+        let checksum = (b'a' as u32 + b'b' as u32 + b'c' as u32 + 125 * PAD as u32) as u8;
+        assert_eq!(block[131], checksum);
+    }
+
+    #[cfg(feature = "xmodem")]
+    #[test]
+    fn sends_a_ymodem_batch_with_the_file_name() {
+        let file = [b'a'; 1000];
+        let mut data = &file[..];
+        let mut sender = Sender::new(Protocol::Ymodem);
+        // the checksum isn't supported by YMODEM
+        assert_eq!(send(&mut sender, 0, &[NAK], &mut data), b"");
+
+        let header = send(&mut sender, 10, b"C", &mut data);
+        assert_eq!(header.len(), 3 + 128 + 2);
+        assert_eq!(header[..3], [SOH, 0, 0xFF]);
+        assert_eq!(header[3..11], *b"log.csv\0");
+        assert_eq!(send(&mut sender, 20, &[ACK], &mut data), b"");
+
+        let block = send(&mut sender, 30, b"C", &mut data);
+        assert_eq!(block.len(), 3 + 1024 + 2);
+        assert_eq!(block[..3], [STX, 1, 0xFE]);
+        assert_eq!(block[3..1003], file);
+        assert_eq!(block[1003], PAD);
+
+        assert_eq!(send(&mut sender, 40, &[ACK], &mut data), [EOT]);
+        assert_eq!(send(&mut sender, 50, &[ACK], &mut data), b"");
+        let header = send(&mut sender, 60, b"C", &mut data);
+        assert_eq!(header[..3], [SOH, 0, 0xFF]);
+        assert!(header[3..131].iter().all(|&byte| byte == 0));
+        assert_eq!(sender.result(), None);
+        send(&mut sender, 70, &[ACK], &mut data);
+        assert_eq!(sender.result(), Some(Ok(())));
+        assert_eq!(sender.bytes(), 1000);
+    }
+
+    #[cfg(feature = "xmodem")]
+    #[test]
+    fn sends_a_block_again_until_it_has_been_rejected_too_often() {
+        let mut data = &b"abc"[..];
+        let mut sender = Sender::new(Protocol::Xmodem);
+        let block = send(&mut sender, 0, b"C", &mut data);
+        assert_eq!(send(&mut sender, 10, &[NAK], &mut data), block);
+        assert_eq!(send(&mut sender, ACK_TIMEOUT_MS, b"", &mut data), b"");
+        let timeout_ms = 10 + ACK_TIMEOUT_MS + 1;
+        assert_eq!(send(&mut sender, timeout_ms, b"", &mut data), block);
+        for _ in 2..MAX_RETRIES {
+            assert_eq!(send(&mut sender, timeout_ms, &[NAK], &mut data), block);
+        }
+        assert_eq!(send(&mut sender, timeout_ms, &[NAK], &mut data), [CAN, CAN]);
+        assert_eq!(sender.result(), Some(Err(Error::TooManyRetries)));
+        assert_eq!(sender.bytes(), 0);
+    }
+
+    #[cfg(feature = "xmodem")]
+    #[test]
+    fn is_cancelled_by_two_cans_of_the_receiver() {
+        let mut data = &b"abc"[..];
+        let mut sender = Sender::new(Protocol::Xmodem);
+        send(&mut sender, 0, b"C", &mut data);
+        // a single one may be noise
+        assert_eq!(send(&mut sender, 10, &[CAN, NAK], &mut data).len(), 133);
+        assert_eq!(sender.result(), None);
+        assert_eq!(send(&mut sender, 20, &[CAN, CAN], &mut data), b"");
+        assert_eq!(sender.result(), Some(Err(Error::Cancelled)));
+    }
+
+    #[cfg(feature = "xmodem")]
+    #[test]
+    fn times_out_without_a_receiver() {
+        let mut data = &b"abc"[..];
+        let mut sender = Sender::new(Protocol::Ymodem);
+        send(&mut sender, 1_000, b"", &mut data);
+        assert_eq!(
+            send(&mut sender, 1_000 + START_TIMEOUT_MS, b"", &mut data),
+            b""
+        );
+        let sent = send(&mut sender, 1_001 + START_TIMEOUT_MS, b"", &mut data);
+        assert_eq!(sent, [CAN, CAN]);
+        assert_eq!(sender.result(), Some(Err(Error::Timeout)));
+    }
+
+    /// A packet with a CRC-16 as sent by the sender, blocks of 128 bytes are sent with SOH.
+    #[cfg(feature = "firmware-update")]
+    fn packet(block: u8, data: &[u8]) -> Vec<u8> {
+        let start = if data.len() == 128 { SOH } else { STX };
+        let mut packet = vec![start, block, !block];
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(&crc16(data).to_be_bytes());
+        packet
+    }
+
+    /// The YMODEM header of a file.
+    #[cfg(feature = "firmware-update")]
+    fn header(name_and_size: &[u8]) -> Vec<u8> {
+        let mut data = [0; 128];
+        data[..name_and_size.len()].copy_from_slice(name_and_size);
+        packet(0, &data)
+    }
+
+    /// Poll the receiver with the bytes of the sender, the data is stored in `file`. Returns the
+    /// response of the receiver.
+    #[cfg(feature = "firmware-update")]
+    fn receive(
+        receiver: &mut Receiver,
+        now_ms: u32,
+        received: &[u8],
+        file: &mut Vec<u8>,
+    ) -> Vec<u8> {
+        receiver.poll(now_ms, received, &mut |offset, data| {
+            assert_eq!(offset as usize, file.len());
+            file.extend_from_slice(data);
+            true
+        });
+        let response = receiver.pending().to_vec();
+        receiver.advance(response.len());
+        response
+    }
+
+    #[cfg(feature = "firmware-update")]
+    #[test]
+    fn receives_a_file_without_the_padding() {
+        let mut file = Vec::new();
+        let mut receiver = Receiver::new(1_000);
+        assert_eq!(receive(&mut receiver, 0, b"", &mut file), b"C");
+        let start = header(b"fw.bin\x00200 14607106 100644");
+        assert_eq!(receive(&mut receiver, 10, &start, &mut file), [ACK, b'C']);
+
+        let block = packet(1, &[b'a'; 128]);
+        assert_eq!(receive(&mut receiver, 20, &block, &mut file), [ACK]);
+        let mut data = [0x1A; 128];
+        data[..72].fill(b'b');
+        // split into several polls
+        let block = packet(2, &data);
+        let (first, second) = block.split_at(50);
+        assert_eq!(receive(&mut receiver, 30, first, &mut file), b"");
+        assert_eq!(receive(&mut receiver, 31, second, &mut file), [ACK]);
+
+        assert_eq!(receive(&mut receiver, 40, &[EOT], &mut file), [ACK, b'C']);
+        assert_eq!(receiver.result(), None);
+        let end = header(b"");
+        assert_eq!(receive(&mut receiver, 50, &end, &mut file), [ACK]);
+        assert_eq!(receiver.result(), Some(Ok(200)));
+        assert_eq!(file[..128], [b'a'; 128]);
+        assert_eq!(file[128..], [b'b'; 72]);
+    }
+
+    #[cfg(feature = "firmware-update")]
+    #[test]
+    fn rejects_a_file_which_is_too_large() {
+        let mut file = Vec::new();
+        let mut receiver = Receiver::new(1_000);
+        receive(&mut receiver, 0, b"", &mut file);
+        let header = header(b"fw.bin\x001001");
+        assert_eq!(receive(&mut receiver, 10, &header, &mut file), [CAN, CAN]);
+        assert_eq!(receiver.result(), Some(Err(Error::TooLarge)));
+    }
+
+    #[cfg(feature = "firmware-update")]
+    #[test]
+    fn rejects_a_file_without_a_size() {
+        let mut file = Vec::new();
+        let mut receiver = Receiver::new(1_000);
+        receive(&mut receiver, 0, b"", &mut file);
+        let header = header(b"fw.bin");
+        assert_eq!(receive(&mut receiver, 10, &header, &mut file), [CAN, CAN]);
+        assert_eq!(receiver.result(), Some(Err(Error::TooLarge)));
+    }
+
+    #[cfg(feature = "firmware-update")]
+    #[test]
+    fn rejects_a_corrupt_block_and_stores_a_repeated_one_once() {
+        let mut file = Vec::new();
+        let mut receiver = Receiver::new(1_000);
+        receive(&mut receiver, 0, b"", &mut file);
+        receive(&mut receiver, 10, &header(b"fw.bin\x00256"), &mut file);
+
+        let block = packet(1, &[b'a'; 128]);
+        let mut corrupt = block.clone();
+        corrupt[10] = b'b';
+        assert_eq!(receive(&mut receiver, 20, &corrupt, &mut file), [NAK]);
+        assert_eq!(receive(&mut receiver, 30, &block, &mut file), [ACK]);
+        // the sender didn't get the acknowledgement
+        assert_eq!(receive(&mut receiver, 40, &block, &mut file), [ACK]);
+        assert_eq!(file.len(), 128);
+        // a block out of order
+        let block = packet(3, &[b'c'; 128]);
+        assert_eq!(receive(&mut receiver, 50, &block, &mut file), [NAK]);
+        assert_eq!(file.len(), 128);
+    }
+
+    #[cfg(feature = "firmware-update")]
+    #[test]
+    fn requests_the_file_until_it_times_out() {
+        let mut file = Vec::new();
+        let mut receiver = Receiver::new(1_000);
+        assert_eq!(receive(&mut receiver, 0, b"", &mut file), b"C");
+        let mut now_ms = 0;
+        for _ in 0..START_TIMEOUT_MS / REQUEST_INTERVAL_MS {
+            now_ms += REQUEST_INTERVAL_MS + 1;
+            assert_eq!(receive(&mut receiver, now_ms, b"", &mut file), b"C");
+        }
+        now_ms += REQUEST_INTERVAL_MS + 1;
+        assert_eq!(receive(&mut receiver, now_ms, b"", &mut file), [CAN, CAN]);
+        assert_eq!(receiver.result(), Some(Err(Error::Timeout)));
+    }
+
+    #[cfg(feature = "firmware-update")]
+    #[test]
+    fn is_cancelled_by_two_cans_of_the_sender() {
+        let mut file = Vec::new();
+        let mut receiver = Receiver::new(1_000);
+        receive(&mut receiver, 0, b"", &mut file);
+        assert_eq!(receive(&mut receiver, 10, &[CAN, CAN], &mut file), b"");
+        assert_eq!(receiver.result(), Some(Err(Error::Cancelled)));
+    }
+}
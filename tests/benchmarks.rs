@@ -0,0 +1,154 @@
+//! Micro-benchmarks of the processing of the measurements on the board, run with
+//! `cargo test --test benchmarks` (add the features of the firmware to include their processing).
+//! They report the cycles per sample of each filter & encoder, counted by the DWT, so that the
+//! processing pipeline can be budgeted before enabling features. At 84 MHz 84 cycles are 1 µs.
+//!
+//! The sensor isn't needed, the benchmarks run on synthetic measurements.
+
+#![no_std]
+#![no_main]
+
+use core::hint::black_box;
+use cortex_m::peripheral::DWT;
+use defmt_rtt as _;
+use nucleo_f401re_rtic_vl53l1x_uld::telemetry::Measurement;
+use panic_probe as _;
+use vl53l1x_uld::RangeStatus;
+
+/// Number of measurements each benchmark processes.
+const SAMPLES: u32 = 1_000;
+/// Every n-th measurement is invalid, like dropouts of the sensor.
+const INVALID_EVERY: u32 = 10;
+
+/// The `i`-th synthetic measurement: a pseudo-random distance within the range of the sensor.
+fn measurement(i: u32) -> Measurement {
+    let random = i.wrapping_mul(2_654_435_761);
+    Measurement {
+        seq: i,
+        timestamp_ms: i * 100,
+        utc_ms: Some(1_700_000_000_000 + i as u64 * 100),
+        distance_mm: (random >> 16) as u16 % 4_000,
+        status: if i.is_multiple_of(INVALID_EVERY) {
+            RangeStatus::SignalFailure
+        } else {
+            RangeStatus::Valid
+        },
+        ambient_kcps: 0,
+        value: None,
+        settling: false,
+        #[cfg(feature = "encoder")]
+        position: random as i32,
+        #[cfg(feature = "imu")]
+        components: None,
+        #[cfg(feature = "environment")]
+        environment: None,
+        #[cfg(feature = "ultrasonic")]
+        acoustic_mm: None,
+        #[cfg(feature = "ultrasonic")]
+        fused: None,
+    }
+}
+
+/// Run `process` for [`SAMPLES`] measurements & report the average cycles per sample. Only
+/// `process` itself is counted, not the generation of the measurements.
+fn benchmark(name: &str, mut process: impl FnMut(&Measurement)) {
+    let mut cycles = 0u32;
+    for i in 0..SAMPLES {
+        let measurement = black_box(measurement(i));
+        let start = DWT::cycle_count();
+        process(&measurement);
+        cycles = cycles.wrapping_add(DWT::cycle_count().wrapping_sub(start));
+    }
+    defmt::println!("{}: {} cycles per sample", name, cycles / SAMPLES);
+}
+
+#[defmt_test::tests]
+mod benchmarks {
+    use super::{benchmark, measurement};
+    use core::hint::black_box;
+    use nucleo_f401re_rtic_vl53l1x_uld::app_mode::PresenceDetector;
+    use nucleo_f401re_rtic_vl53l1x_uld::filter::Median3;
+    use nucleo_f401re_rtic_vl53l1x_uld::geofence::{self, Geofence};
+    use nucleo_f401re_rtic_vl53l1x_uld::rollup::RollupAccumulator;
+    use nucleo_f401re_rtic_vl53l1x_uld::storage;
+    use nucleo_f401re_rtic_vl53l1x_uld::telemetry::{self, FrameFormat};
+    use stm32f4xx_hal::pac;
+    use stm32f4xx_hal::prelude::*;
+
+    #[init]
+    fn init() {
+        let mut core = cortex_m::Peripherals::take().unwrap();
+        let device = pac::Peripherals::take().unwrap();
+        // the clock of the firmware, the flash wait states depend on it
+        let _clocks = device.RCC.constrain().cfgr.sysclk(84.MHz()).freeze();
+        core.DCB.enable_trace();
+        core.DWT.enable_cycle_counter();
+    }
+
+    #[test]
+    fn median_filter() {
+        let mut filter = Median3::new(u16::MAX);
+        benchmark("median filter", |measurement| {
+            black_box(filter.update(measurement.distance_mm));
+        });
+    }
+
+    #[test]
+    fn presence_detector() {
+        let mut detector = PresenceDetector::new();
+        benchmark("presence detector", |measurement| {
+            black_box(detector.update(measurement));
+        });
+    }
+
+    #[test]
+    fn geofence() {
+        // all zones configured for the worst case
+        let mut geofence = Geofence::new([geofence::DEFAULT_ZONES[0]; geofence::MAX_ZONES]);
+        benchmark("geofence", |measurement| {
+            black_box(geofence.update(measurement));
+        });
+    }
+
+    #[test]
+    fn rollup() {
+        let mut accumulator = RollupAccumulator::new(0);
+        benchmark("rollup", |measurement| {
+            accumulator.add(measurement, false);
+        });
+        black_box(accumulator.take(0, None, None));
+    }
+
+    #[test]
+    fn csv_frame() {
+        benchmark("CSV frame", |measurement| {
+            black_box(telemetry::measurement_frame(measurement, FrameFormat::Csv).unwrap());
+        });
+    }
+
+    #[test]
+    fn json_frame() {
+        benchmark("JSON frame", |measurement| {
+            black_box(telemetry::measurement_frame(measurement, FrameFormat::Json).unwrap());
+        });
+    }
+
+    #[test]
+    fn frame_crc() {
+        let frame = telemetry::measurement_frame(&measurement(1), FrameFormat::Json).unwrap();
+        benchmark("CRC-32 of a JSON frame", |_| {
+            black_box(storage::crc32(frame.as_bytes()));
+        });
+    }
+
+    #[cfg(feature = "nor-flash")]
+    #[test]
+    fn log_record() {
+        use nucleo_f401re_rtic_vl53l1x_uld::log_record::{Chain, Record};
+
+        let mut chain = Chain::new();
+        benchmark("log record", |measurement| {
+            black_box(chain.encode(&Record::from(measurement)));
+        });
+    }
+}
@@ -0,0 +1,138 @@
+//! Tests on the board (with the TOF sensor connected like for the firmware), run with
+//! `cargo test --test on_target`. They use the hardware directly instead of the RTIC application.
+//!
+//! The calibration test writes to the EEPROM sectors of the flash, the stored calibration is
+//! restored afterwards.
+
+#![no_std]
+#![no_main]
+
+use defmt_rtt as _;
+use panic_probe as _;
+
+#[defmt_test::tests]
+mod tests {
+    use cortex_m::delay::Delay;
+    use nucleo_f401re_rtic_vl53l1x_uld::calibration_store::{self, CalibrationData};
+    use nucleo_f401re_rtic_vl53l1x_uld::config::watchdog as config;
+    use nucleo_f401re_rtic_vl53l1x_uld::eeprom::Eeprom;
+    use nucleo_f401re_rtic_vl53l1x_uld::{board_pins, tof};
+    use stm32f4xx_hal::i2c::{I2c, I2c1};
+    use stm32f4xx_hal::pac::{self, FLASH};
+    use stm32f4xx_hal::prelude::*;
+    use stm32f4xx_hal::watchdog::IndependentWatchdog;
+    use vl53l1x_uld::{RangeStatus, VL53L1X};
+
+    /// Number of measurements of the ranging burst.
+    const BURST_LEN: usize = 10;
+    /// The longest distance the sensor measures (in the long distance mode).
+    const MAX_DISTANCE_MM: u16 = 4_000;
+    /// Number of times the data ready flag is polled (with a delay of 1 ms) per measurement.
+    const DATA_READY_POLLS: u32 = 1_000;
+    /// Duration of the watchdog test, several timeouts.
+    const WATCHDOG_TEST_MS: u32 = 5 * config::TIMEOUT_MS;
+
+    struct State {
+        tof_sensor: VL53L1X<I2c1>,
+        flash: FLASH,
+        watchdog: IndependentWatchdog,
+        delay: Delay,
+    }
+
+    #[init]
+    fn init() -> State {
+        let core = cortex_m::Peripherals::take().unwrap();
+        let device = pac::Peripherals::take().unwrap();
+        let clocks = device.RCC.constrain().cfgr.sysclk(84.MHz()).freeze();
+        let gpioa = device.GPIOA.split();
+        let gpiob = device.GPIOB.split();
+        let gpioc = device.GPIOC.split();
+        // the sensor is shut down while XSHUT is low, it's set high
+        let pins = board_pins!(gpioa, gpiob, gpioc);
+        let i2c = I2c::new(device.I2C1, pins.i2c, 400.kHz(), &clocks);
+        let mut watchdog = IndependentWatchdog::new(device.IWDG);
+        watchdog.start(config::TIMEOUT_MS.millis());
+        State {
+            tof_sensor: VL53L1X::new(i2c, vl53l1x_uld::DEFAULT_ADDRESS),
+            flash: device.FLASH,
+            watchdog,
+            delay: Delay::new(core.SYST, clocks.hclk().raw()),
+        }
+    }
+
+    #[test]
+    fn sensor_is_detected(state: &mut State) {
+        assert_eq!(state.tof_sensor.get_sensor_id().unwrap(), tof::MODEL_ID);
+    }
+
+    #[test]
+    fn ranging_burst_is_plausible(state: &mut State) {
+        tof::setup(&mut state.tof_sensor, None, None).unwrap();
+        let mut valid = 0;
+        for _ in 0..BURST_LEN {
+            let mut polls = 0;
+            while !state.tof_sensor.is_data_ready().unwrap() {
+                polls += 1;
+                assert!(polls < DATA_READY_POLLS, "no measurement");
+                state.delay.delay_ms(1);
+                state.watchdog.feed();
+            }
+            let result = tof::read_result(&mut state.tof_sensor).unwrap();
+            defmt::debug!("{} mm ({})", result.distance_mm, result.status);
+            if result.status == RangeStatus::Valid {
+                assert!(result.distance_mm <= MAX_DISTANCE_MM);
+                valid += 1;
+            }
+        }
+        state.tof_sensor.stop_ranging().unwrap();
+        // a few measurements may fail (e.g. due to ambient light), not most of them
+        assert!(valid * 2 >= BURST_LEN, "only {} valid measurements", valid);
+    }
+
+    #[test]
+    fn calibration_round_trips_through_flash(state: &mut State) {
+        let mut eeprom = Eeprom::open(&mut state.flash, &mut state.watchdog).unwrap();
+        let stored = calibration_store::load(&eeprom, &state.flash);
+        let calibration = CalibrationData {
+            offset_mm: -42,
+            cross_talk_cps: 1234,
+        };
+        calibration_store::save(
+            &mut eeprom,
+            &mut state.flash,
+            &calibration,
+            &mut state.watchdog,
+        )
+        .unwrap();
+        assert_eq!(
+            calibration_store::load(&eeprom, &state.flash),
+            Some(calibration)
+        );
+        // the sectors are read again, like at the next boot
+        let eeprom = Eeprom::open(&mut state.flash, &mut state.watchdog).unwrap();
+        assert_eq!(
+            calibration_store::load(&eeprom, &state.flash),
+            Some(calibration)
+        );
+
+        if let Some(stored) = stored {
+            let mut eeprom = eeprom;
+            calibration_store::save(&mut eeprom, &mut state.flash, &stored, &mut state.watchdog)
+                .unwrap();
+        }
+    }
+
+    /// Feeds the watchdog at the configured interval for several timeouts. The timeout depends
+    /// on the LSI, which is quite inaccurate: if it's too fast for the interval the board resets &
+    /// the test run fails.
+    #[test]
+    fn watchdog_is_fed_on_time(state: &mut State) {
+        state.watchdog.start(config::TIMEOUT_MS.millis());
+        let mut elapsed_ms = 0;
+        while elapsed_ms < WATCHDOG_TEST_MS {
+            state.delay.delay_ms(config::FEED_INTERVAL_MS);
+            state.watchdog.feed();
+            elapsed_ms += config::FEED_INTERVAL_MS;
+        }
+    }
+}